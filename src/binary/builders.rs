@@ -10,7 +10,10 @@ use std::{
 
 use crate::{
     pure_values::{Float, Integer},
-    util::imm_string::ImmString,
+    util::{
+        imm_string::{ImmBytes, ImmString},
+        intern::{InternStats, SharedInternSet},
+    },
 };
 
 use self::{
@@ -19,8 +22,9 @@ use self::{
 };
 
 use super::{
-    const_table::{ConstIndex, ConstValue},
+    const_table::{ConstIndex, ConstRecord, ConstValue},
     error::{BuilderError, Result},
+    indexes::{GlobalIndex, ImportIndex, ModuleConstIndex},
     modules::{ConstModule, ImportSource, ModuleId, ModuleMemberId},
 };
 
@@ -29,12 +33,12 @@ pub use func_builder::FunctionBuilder;
 // The final index of a value in the module. This can be either one of the const indexes,
 // or a global
 #[derive(Clone, Debug)]
-enum ValueIndex {
+pub(crate) enum ValueIndex {
     Const(ConstIndex),
-    Global(u32),
+    Global(GlobalIndex),
 }
 impl ValueIndex {
-    pub fn as_module_const(&self) -> Result<u32> {
+    pub fn as_module_const(&self) -> Result<ModuleConstIndex> {
         match self {
             ValueIndex::Const(ConstIndex::ModuleConst(index)) => Ok(*index),
             _ => Err(BuilderError::ExpectedModuleConst),
@@ -72,19 +76,29 @@ struct BuilderInner {
     imports: Vec<ImportSource>,
     values: ValueResolver<RefResolver, ConstValue, BuilderError>,
     exports: HashMap<ModuleMemberId, RefIndex>,
+    export_docs: HashMap<ModuleMemberId, String>,
     initializer: Option<RefIndex>,
+    /// Unit tests by name, in the order they were added.
+    tests: Vec<(String, RefIndex)>,
     num_globals: u32,
+    /// Set once a table has grown past its largest index. Building then
+    /// fails, rather than handing out a truncated index.
+    index_overflow: bool,
+    /// Branch target names, shared by all functions of the module.
+    label_names: SharedInternSet<ImmString>,
 }
 
 impl BuilderInner {
     pub fn new_global(&mut self) -> RefIndex {
-        let global_index = self.num_globals;
-        self.num_globals += 1;
+        let global_index = GlobalIndex::from_usize(self.num_globals as usize)
+            .unwrap_or_else(|| self.overflowed(GlobalIndex::new(u32::MAX)));
+        self.num_globals = self.num_globals.saturating_add(1);
         self.new_ref(ValueIndex::Global(global_index))
     }
 
     pub fn new_import(&mut self, source: ImportSource) -> RefIndex {
-        let import_index = u32::try_from(self.imports.len()).unwrap();
+        let import_index = ImportIndex::from_usize(self.imports.len())
+            .unwrap_or_else(|| self.overflowed(ImportIndex::new(u32::MAX)));
         self.imports.push(source);
         self.new_ref(ValueIndex::Const(ConstIndex::ModuleImport(import_index)))
     }
@@ -93,10 +107,18 @@ impl BuilderInner {
     where
         F: FnOnce(&RefResolver) -> Result<ConstValue> + 'static,
     {
-        let resolve_ref = self.values.resolve_ref(value_fn) as u32;
+        let resolve_ref = ModuleConstIndex::from_usize(self.values.resolve_ref(value_fn))
+            .unwrap_or_else(|| self.overflowed(ModuleConstIndex::new(u32::MAX)));
         self.new_ref(ValueIndex::Const(ConstIndex::ModuleConst(resolve_ref)))
     }
 
+    /// Records that a table overflowed, and returns `placeholder` to stand
+    /// in for the index until building fails.
+    fn overflowed<T>(&mut self, placeholder: T) -> T {
+        self.index_overflow = true;
+        placeholder
+    }
+
     pub fn new_ref(&mut self, value: ValueIndex) -> RefIndex {
         let index = self.ref_indexes.borrow_mut().make_deferred_set();
         self.ref_indexes
@@ -114,7 +136,8 @@ impl BuilderInner {
     where
         F: FnOnce(&RefResolver) -> Result<ConstValue> + 'static,
     {
-        let resolve_ref = self.values.resolve_ref(value_fn) as u32;
+        let resolve_ref = ModuleConstIndex::from_usize(self.values.resolve_ref(value_fn))
+            .ok_or(BuilderError::IndexOverflow)?;
         self.resolve_ref(
             index,
             ValueIndex::Const(ConstIndex::ModuleConst(resolve_ref)),
@@ -131,6 +154,18 @@ impl BuilderInner {
     }
 }
 
+fn resolve_record(
+    resolver: &RefResolver,
+    fields: Vec<(ImmString, RefIndex)>,
+) -> Result<ConstValue> {
+    Ok(ConstValue::Record(ConstRecord::new(
+        fields
+            .into_iter()
+            .map(|(name, value)| Ok((name, resolver.resolve_to_const_index(value)?)))
+            .collect::<Result<Vec<_>>>()?,
+    )))
+}
+
 #[derive(Clone)]
 struct InnerRc(Rc<RefCell<BuilderInner>>);
 
@@ -142,8 +177,12 @@ impl InnerRc {
             ref_indexes: Rc::new(RefCell::new(DisjointSet::new())),
             values: ValueResolver::new(),
             exports: HashMap::new(),
+            export_docs: HashMap::new(),
             initializer: None,
+            tests: Vec::new(),
             num_globals: 0,
+            index_overflow: false,
+            label_names: SharedInternSet::new(),
         })))
     }
 
@@ -151,6 +190,29 @@ impl InnerRc {
         std::ptr::eq(self.0.as_ptr(), other.0.as_ptr())
     }
 
+    pub fn label_names(&self) -> SharedInternSet<ImmString> {
+        self.0.borrow().label_names.clone()
+    }
+
+    pub fn set_export_doc(&self, name: ModuleMemberId, doc: String) -> Result<()> {
+        let mut inner = self.0.borrow_mut();
+        if !inner.exports.contains_key(&name) {
+            return Err(BuilderError::UnknownExport(name.as_str().to_string()));
+        }
+        inner.export_docs.insert(name, doc);
+        Ok(())
+    }
+
+    pub fn add_test(&self, name: String, value_ref: &ValueRef) -> Result<()> {
+        let index = self.find_ref_index(value_ref)?;
+        let mut inner = self.0.borrow_mut();
+        if inner.tests.iter().any(|(existing, _)| *existing == name) {
+            return Err(BuilderError::DuplicateTest(name));
+        }
+        inner.tests.push((name, index));
+        Ok(())
+    }
+
     pub fn add_import(&self, source: ImportSource) -> ValueRef {
         let mut inner = self.0.borrow_mut();
         ValueRef {
@@ -167,6 +229,14 @@ impl InnerRc {
         }
     }
 
+    pub fn new_ref(&self, index: ValueIndex) -> ValueRef {
+        let mut inner = self.0.borrow_mut();
+        ValueRef {
+            builder_inner: self.clone(),
+            const_index: inner.new_ref(index),
+        }
+    }
+
     fn new_ref_with_resolver<F>(&self, resolver: F) -> ValueRef
     where
         F: FnOnce(&RefResolver) -> Result<ConstValue> + 'static,
@@ -200,10 +270,22 @@ impl InnerRc {
         self.new_const_cell(ConstValue::Float(float_value.into()))
     }
 
+    pub fn new_null(&self) -> ValueRef {
+        self.new_const_cell(ConstValue::Null)
+    }
+
     pub fn new_bool(&self, bool_value: bool) -> ValueRef {
         self.new_const_cell(ConstValue::Bool(bool_value))
     }
 
+    pub fn new_bytes(&self, bytes_value: impl Into<ImmBytes>) -> ValueRef {
+        self.new_const_cell(ConstValue::Bytes(bytes_value.into()))
+    }
+
+    pub fn new_string(&self, string_value: impl Into<ImmString>) -> ValueRef {
+        self.new_const_cell(ConstValue::String(string_value.into()))
+    }
+
     pub fn new_list(&self, iter: impl IntoIterator<Item = ValueRef>) -> ValueRef {
         let indexes = iter.into_iter().map(|v| v.const_index).collect::<Vec<_>>();
         self.new_ref_with_resolver(move |resolver| {
@@ -216,6 +298,32 @@ impl InnerRc {
         })
     }
 
+    pub fn new_record(
+        &self,
+        fields: impl IntoIterator<Item = (ImmString, ValueRef)>,
+    ) -> Result<ValueRef> {
+        let fields = self.record_fields(fields)?;
+        Ok(self.new_ref_with_resolver(move |resolver| resolve_record(resolver, fields)))
+    }
+
+    /// Checks the fields of a record, and finds the references of their
+    /// values.
+    fn record_fields(
+        &self,
+        fields: impl IntoIterator<Item = (ImmString, ValueRef)>,
+    ) -> Result<Vec<(ImmString, RefIndex)>> {
+        let mut result: Vec<(ImmString, RefIndex)> = Vec::new();
+        for (name, value) in fields {
+            if result.iter().any(|(other, _)| *other == name) {
+                return Err(BuilderError::DuplicateRecordField(
+                    name.as_str().to_string(),
+                ));
+            }
+            result.push((name, self.find_ref_index(&value)?));
+        }
+        Ok(result)
+    }
+
     pub fn new_function(&self) -> (ValueRef, FunctionBuilder) {
         let (value_ref, deferred) = self.new_deferred();
         let builder = FunctionBuilder::new(self.clone(), deferred);
@@ -230,6 +338,7 @@ impl InnerRc {
             return Err(BuilderError::AlreadyExists);
         }
         inner.initializer = Some(value_ref.const_index);
+        drop(inner);
 
         Ok(FunctionBuilder::new(self.clone(), deferred))
     }
@@ -243,6 +352,9 @@ impl InnerRc {
 
     pub fn to_const_module(&self) -> Result<ConstModule> {
         let mut inner = self.0.borrow_mut();
+        if inner.index_overflow {
+            return Err(BuilderError::IndexOverflow);
+        }
         let exports = inner
             .exports
             .iter()
@@ -254,8 +366,7 @@ impl InnerRc {
                         .borrow()
                         .find(v.0)
                         .ok_or(BuilderError::UnresolvedReference)?
-                        .as_module_const()
-                        .expect("Expected module const."),
+                        .as_module_const()?,
                 ))
             })
             .collect::<Result<HashMap<_, _>>>()?;
@@ -263,17 +374,29 @@ impl InnerRc {
             .initializer
             .as_ref()
             .map(|i| {
-                Ok::<_, BuilderError>(
+                inner
+                    .ref_indexes
+                    .borrow()
+                    .find(i.0)
+                    .ok_or(BuilderError::UnresolvedReference)?
+                    .as_module_const()
+            })
+            .transpose()?;
+        let tests = inner
+            .tests
+            .iter()
+            .map(|(name, index)| {
+                Ok((
+                    name.clone(),
                     inner
                         .ref_indexes
                         .borrow()
-                        .find(i.0)
+                        .find(index.0)
                         .ok_or(BuilderError::UnresolvedReference)?
-                        .as_module_const()
-                        .expect("Expected module const."),
-                )
+                        .as_module_const()?,
+                ))
             })
-            .transpose()?;
+            .collect::<Result<Vec<_>>>()?;
         let result = std::mem::take(&mut inner.values).into_values(&RefResolver {
             index_layer: inner.ref_indexes.clone(),
         })?;
@@ -284,7 +407,9 @@ impl InnerRc {
             exports,
             initializer_index,
             inner.num_globals,
-        )?)
+        )?
+        .with_export_docs(inner.export_docs.clone())
+        .with_tests(tests)?)
     }
 }
 
@@ -295,6 +420,13 @@ impl ModuleBuilder {
         ModuleBuilder(InnerRc::new(id))
     }
 
+    /// Returns statistics for the branch target names interned by the
+    /// functions of this module.
+    #[must_use]
+    pub fn intern_stats(&self) -> InternStats {
+        self.0.label_names().stats()
+    }
+
     pub fn add_import(&self, source: ImportSource) -> ValueRef {
         self.0.add_import(source)
     }
@@ -315,14 +447,50 @@ impl ModuleBuilder {
         self.0.new_float(float_value)
     }
 
+    pub fn new_null(&self) -> ValueRef {
+        self.0.new_null()
+    }
+
     pub fn new_bool(&self, bool_value: bool) -> ValueRef {
         self.0.new_bool(bool_value)
     }
 
+    pub fn new_bytes(&self, bytes_value: impl Into<ImmBytes>) -> ValueRef {
+        self.0.new_bytes(bytes_value)
+    }
+
+    pub fn new_string(&self, string_value: impl Into<ImmString>) -> ValueRef {
+        self.0.new_string(string_value)
+    }
+
     pub fn new_list(&self, iter: impl IntoIterator<Item = ValueRef>) -> ValueRef {
         self.0.new_list(iter)
     }
 
+    /// Creates a record with the given fields, in order. Each field may only
+    /// be given once.
+    pub fn new_record<S: Into<ImmString>>(
+        &self,
+        fields: impl IntoIterator<Item = (S, ValueRef)>,
+    ) -> Result<ValueRef> {
+        self.0
+            .new_record(fields.into_iter().map(|(name, value)| (name.into(), value)))
+    }
+
+    /// Creates a list whose items may refer to the list itself.
+    ///
+    /// `items_fn` receives a reference to the new list, and returns the items
+    /// of the list. Cycles between constants are only allowed when they pass
+    /// through a list or a function; see [`DeferredValue::resolve_other`].
+    pub fn new_recursive_list<F>(&self, items_fn: F) -> Result<ValueRef>
+    where
+        F: FnOnce(&ValueRef) -> Result<Vec<ValueRef>>,
+    {
+        let (list, deferred) = self.new_deferred();
+        deferred.resolve_list(items_fn(&list)?)?;
+        Ok(list)
+    }
+
     pub fn new_function(&self) -> (ValueRef, FunctionBuilder) {
         self.0.new_function()
     }
@@ -331,9 +499,43 @@ impl ModuleBuilder {
         self.0.new_initializer()
     }
 
+    /// Attaches a documentation string to an existing export.
+    pub fn set_export_doc(&self, name: ModuleMemberId, doc: impl Into<String>) -> Result<()> {
+        self.0.set_export_doc(name, doc.into())
+    }
+
+    /// Adds a unit test named `name`, which calls the function `value` with
+    /// no arguments. Tests are run in the order they are added.
+    pub fn add_test(&self, name: impl Into<String>, value: &ValueRef) -> Result<()> {
+        self.0.add_test(name.into(), value)
+    }
+
     pub fn into_const_module(&self) -> Result<ConstModule> {
         self.0.to_const_module()
     }
+
+    /// Creates a builder whose tables start out as those of `module`, so
+    /// that values added to it can refer to the module's constants, imports
+    /// and globals by their indexes. The exports, initializer and tests of
+    /// `module` are not carried over.
+    pub(crate) fn extending(module: &ConstModule) -> Self {
+        let builder = InnerRc::new(module.id().clone());
+        {
+            let mut inner = builder.0.borrow_mut();
+            inner.imports = module.imports().to_vec();
+            inner.num_globals = module.global_table_size();
+            for value in module.const_table() {
+                let value = value.clone();
+                inner.values.resolve_ref(move |_| Ok(value));
+            }
+        }
+        ModuleBuilder(builder)
+    }
+
+    /// Returns a reference to the value at `index`.
+    pub(crate) fn new_ref(&self, index: ValueIndex) -> ValueRef {
+        self.0.new_ref(index)
+    }
 }
 
 #[derive(Clone)]
@@ -359,15 +561,32 @@ impl ValueRef {
             .ref_indexes
             .borrow_mut()
             .resolve_to_other_set(self.const_index.0, other.const_index.0)
-            .map_err(BuilderError::new_other)?;
+            .map_err(|e| match e {
+                disjoint_sets::Error::Cycle => BuilderError::CyclicDefinition,
+                e => BuilderError::new_other(e),
+            })?;
         Ok(())
     }
 
+    /// Returns where the value was placed in the module, once it is
+    /// resolved.
+    pub(crate) fn resolved_index(&self) -> Option<ValueIndex> {
+        self.builder_inner
+            .0
+            .borrow()
+            .ref_indexes
+            .borrow()
+            .find(self.const_index.0)
+            .cloned()
+    }
+
     pub fn export(&self, name: ModuleMemberId) -> Result<()> {
         let mut inner = self.builder_inner.0.borrow_mut();
         match inner.exports.entry(name) {
-            hash_map::Entry::Occupied(_) => {
-                return Err(BuilderError::AlreadyExists);
+            hash_map::Entry::Occupied(occ) => {
+                return Err(BuilderError::DuplicateExport(
+                    occ.key().as_str().to_string(),
+                ));
             }
             hash_map::Entry::Vacant(vac) => {
                 vac.insert(self.const_index);
@@ -406,6 +625,10 @@ impl DeferredValue {
         self.resolve(ConstValue::Float(value.into()))
     }
 
+    pub fn resolve_null(self) -> Result<()> {
+        self.resolve(ConstValue::Null)
+    }
+
     pub fn resolve_bool(self, value: bool) -> Result<()> {
         self.resolve(ConstValue::Bool(value))
     }
@@ -414,6 +637,10 @@ impl DeferredValue {
         self.resolve(ConstValue::String(value.into()))
     }
 
+    pub fn resolve_bytes(self, value: impl Into<ImmBytes>) -> Result<()> {
+        self.resolve(ConstValue::Bytes(value.into()))
+    }
+
     pub fn resolve_list(self, iter: impl IntoIterator<Item = ValueRef>) -> Result<()> {
         let values = iter
             .into_iter()
@@ -429,6 +656,23 @@ impl DeferredValue {
         })
     }
 
+    /// Resolves this value to a record with the given fields, in order. Each
+    /// field may only be given once.
+    pub fn resolve_record<S: Into<ImmString>>(
+        self,
+        fields: impl IntoIterator<Item = (S, ValueRef)>,
+    ) -> Result<()> {
+        let fields = self
+            .0
+            .builder_inner
+            .record_fields(fields.into_iter().map(|(name, value)| (name.into(), value)))?;
+        self.resolve_fn(move |resolver| resolve_record(resolver, fields))
+    }
+
+    /// Resolves this value to be the same as `value`.
+    ///
+    /// Returns [`BuilderError::CyclicDefinition`] if `value` is this value,
+    /// or is itself an alias that leads back to this value.
     pub fn resolve_other(self, value: &ValueRef) -> Result<()> {
         self.0.resolve_other(value)
     }
@@ -441,6 +685,7 @@ impl DeferredValue {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::binary::instructions::Instruction;
 
     #[test]
     fn test_build_atomic_values() -> anyhow::Result<()> {
@@ -470,4 +715,146 @@ mod tests {
         let _const_table = value_set.into_const_module()?;
         Ok(())
     }
+
+    #[test]
+    fn functions_share_label_names() -> anyhow::Result<()> {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        for _ in 0..2 {
+            let (_, mut builder) = value_set.new_function();
+            builder.branch("end").define_branch_target("end").return_(0);
+            builder.build()?;
+        }
+        let stats = value_set.intern_stats();
+        assert_eq!(stats.unique, 1);
+        assert_eq!(stats.lookups, 4);
+        assert_eq!(stats.hits, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn self_referential_list() -> anyhow::Result<()> {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        let one = value_set.new_int(1);
+        let list = value_set.new_recursive_list(|this| Ok(vec![one, this.clone()]))?;
+        list.export(ModuleMemberId::new("list"))?;
+        let module = value_set.into_const_module()?;
+        let index = module.exports()[&ModuleMemberId::new("list")];
+        let Some(ConstValue::List(items)) = index.get(module.const_table()) else {
+            panic!("Expected a list.");
+        };
+        assert_eq!(items[1].as_module_const(), Some(index));
+        Ok(())
+    }
+
+    #[test]
+    fn only_constants_can_be_exported() -> anyhow::Result<()> {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        let import = value_set.add_import(ImportSource::new(["bar"], "baz"));
+        import.export(ModuleMemberId::new("baz"))?;
+        assert!(matches!(
+            value_set.into_const_module(),
+            Err(BuilderError::ExpectedModuleConst)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn popped_values_are_written_to_globals() -> anyhow::Result<()> {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        let global = value_set.new_global();
+        let (f, mut builder) = value_set.new_function();
+        builder
+            .push_int(1)
+            .pop_value(&global)?
+            .push_value(&global)?
+            .return_(1);
+        builder.build()?;
+        f.export(ModuleMemberId::new("f"))?;
+        let module = value_set.into_const_module()?;
+        let index = module.exports()[&ModuleMemberId::new("f")];
+        let Some(ConstValue::Function(function)) = index.get(module.const_table()) else {
+            panic!("Expected a function.");
+        };
+        assert_eq!(
+            &function.instructions().instructions()[1..3],
+            &[
+                Instruction::PopGlobal(GlobalIndex::new(0)),
+                Instruction::PushGlobal(GlobalIndex::new(0)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mutually_recursive_lists() -> anyhow::Result<()> {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        let (a, a_deferred) = value_set.new_deferred();
+        let b = value_set.new_list(vec![a.clone()]);
+        a_deferred.resolve_list(vec![b.clone()])?;
+        value_set.into_const_module()?;
+        Ok(())
+    }
+
+    #[test]
+    fn recursive_function() -> anyhow::Result<()> {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        let (f, mut builder) = value_set.new_function();
+        builder.push_value(&f)?.return_(1);
+        builder.build()?;
+        value_set.into_const_module()?;
+        Ok(())
+    }
+
+    #[test]
+    fn alias_of_itself_is_rejected() {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        let (a, a_deferred) = value_set.new_deferred();
+        assert!(matches!(
+            a_deferred.resolve_other(&a),
+            Err(BuilderError::CyclicDefinition)
+        ));
+    }
+
+    #[test]
+    fn alias_cycle_is_rejected() {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        let (a, a_deferred) = value_set.new_deferred();
+        let (b, b_deferred) = value_set.new_deferred();
+        let (c, c_deferred) = value_set.new_deferred();
+        a_deferred.resolve_other(&b).unwrap();
+        b_deferred.resolve_other(&c).unwrap();
+        assert!(matches!(
+            c_deferred.resolve_other(&a),
+            Err(BuilderError::CyclicDefinition)
+        ));
+    }
+
+    #[test]
+    fn duplicate_export_reports_name() -> anyhow::Result<()> {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        let value = value_set.new_int(1);
+        value.export(ModuleMemberId::new("value"))?;
+        let error = value.export(ModuleMemberId::new("value")).unwrap_err();
+        assert_eq!(error.member_name(), Some("value"));
+        Ok(())
+    }
+
+    #[test]
+    fn export_docs_are_kept() -> anyhow::Result<()> {
+        let value_set = ModuleBuilder::new(ModuleId::new(["foo"]));
+        let value = value_set.new_int(1);
+        value.export(ModuleMemberId::new("value"))?;
+        value_set.set_export_doc(ModuleMemberId::new("value"), "The value.")?;
+        let error = value_set
+            .set_export_doc(ModuleMemberId::new("other"), "Missing.")
+            .unwrap_err();
+        assert_eq!(error.member_name(), Some("other"));
+
+        let module = value_set.into_const_module()?;
+        assert_eq!(
+            module.export_doc(&ModuleMemberId::new("value")),
+            Some("The value.")
+        );
+        Ok(())
+    }
 }
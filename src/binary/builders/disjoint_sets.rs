@@ -2,6 +2,9 @@
 pub enum Error {
     #[error("Resolved entry multiple times")]
     MultiplyResolved,
+
+    #[error("Resolving entry to other set would form a cycle")]
+    Cycle,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -36,10 +39,24 @@ impl<T> DisjointSet<T> {
         Ok(())
     }
 
+    /// Resolves the set at `index` to whatever `other` resolves to.
+    ///
+    /// Fails if `other` already resolves, directly or transitively, to
+    /// `index`, as the set would then never resolve to a value.
     pub fn resolve_to_other_set(&mut self, index: SetIndex, other: SetIndex) -> Result<(), Error> {
         if self.0[index.0].is_some() {
             return Err(Error::MultiplyResolved);
         }
+        let mut current = other;
+        loop {
+            if current.0 == index.0 {
+                return Err(Error::Cycle);
+            }
+            match self.0[current.0] {
+                Some(Entry::Parent(next)) => current = next,
+                _ => break,
+            }
+        }
         self.0[index.0] = Some(Entry::Parent(other));
         Ok(())
     }
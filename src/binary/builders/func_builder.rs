@@ -1,21 +1,34 @@
 use crate::{
     binary::{
         error::{BuilderError, Result},
-        instructions::{CallInstruction, CompareOp, InstructionListBuilder, StackIndex},
+        indexes::LocalConstIndex,
+        instructions::{
+            CallInstruction, CompareOp, Instruction, InstructionListBuilder, NumericKind,
+            StackIndex, Truthiness,
+        },
         ConstFunction, ConstValue,
     },
     pure_values::Integer,
+    util::imm_string::ImmString,
 };
 
 use super::{DeferredValue, InnerRc, RefIndex, ValueIndex, ValueRef};
 
+/// Makes an instruction from the local index of its constant operand.
+type MakeConstInst = fn(LocalConstIndex) -> Instruction;
+
 pub struct FunctionBuilder {
     builder_inner: InnerRc,
     /// The value reference for the deferred function being built.
     deferred: DeferredValue,
     value_pushes: Vec<(u32, RefIndex)>,
     value_pops: Vec<(u32, RefIndex)>,
+    /// Instructions whose operand is a function constant, such as the field
+    /// name of a `RecordGet`, with the constant and how to make the
+    /// instruction from its local index.
+    const_operands: Vec<(u32, RefIndex, MakeConstInst)>,
     insts: InstructionListBuilder,
+    arity: Option<u32>,
 }
 
 macro_rules! def_build_inst_method {
@@ -29,20 +42,38 @@ macro_rules! def_build_inst_method {
 
 impl FunctionBuilder {
     pub(super) fn new(builder_inner: InnerRc, deferred: DeferredValue) -> Self {
+        let insts = InstructionListBuilder::with_interner(builder_inner.label_names());
         FunctionBuilder {
             builder_inner,
             deferred,
             value_pushes: Vec::new(),
             value_pops: Vec::new(),
-            insts: InstructionListBuilder::new(),
+            const_operands: Vec::new(),
+            insts,
+            arity: None,
         }
     }
+
+    /// Declares the number of arguments the function takes, so that binding
+    /// more values to it than that is reported when the module is built or
+    /// when the closure is made.
+    pub fn set_arity(&mut self, arity: u32) -> &mut Self {
+        self.arity = Some(arity);
+        self
+    }
+
     pub fn push_int(&mut self, value: impl Into<Integer>) -> &mut Self {
         let value_ref = self.builder_inner.new_int(value);
         self.push_value(&value_ref)
             .expect("Value should be resolved.")
     }
 
+    pub fn push_null(&mut self) -> &mut Self {
+        let value_ref = self.builder_inner.new_null();
+        self.push_value(&value_ref)
+            .expect("Value should be resolved.")
+    }
+
     pub fn push_value(&mut self, value: &ValueRef) -> Result<&mut Self> {
         let ref_index = self.builder_inner.find_ref_index(value)?;
         let inst_index = self.insts.add_deferred_inst();
@@ -57,7 +88,51 @@ impl FunctionBuilder {
         Ok(self)
     }
 
+    fn push_const_operand_inst(&mut self, value: &ValueRef, make_inst: MakeConstInst) -> &mut Self {
+        let ref_index = self
+            .builder_inner
+            .find_ref_index(value)
+            .expect("Value should be from this builder.");
+        let inst_index = self.insts.add_deferred_inst();
+        self.const_operands.push((inst_index, ref_index, make_inst));
+        self
+    }
+
+    /// Adds a `RecordNew` that makes a record with `fields`, in order.
+    pub fn record_new<S: Into<ImmString>>(
+        &mut self,
+        fields: impl IntoIterator<Item = S>,
+    ) -> &mut Self {
+        let names = fields
+            .into_iter()
+            .map(|name| self.builder_inner.new_string(name))
+            .collect::<Vec<_>>();
+        let value_ref = self.builder_inner.new_list(names);
+        self.push_const_operand_inst(&value_ref, Instruction::RecordNew)
+    }
+
+    /// Adds a `RecordGet` that reads the field named `field`.
+    pub fn record_get(&mut self, field: impl Into<ImmString>) -> &mut Self {
+        let value_ref = self.builder_inner.new_string(field);
+        self.push_const_operand_inst(&value_ref, Instruction::RecordGet)
+    }
+
+    /// Adds a `RecordSet` that writes the field named `field`.
+    pub fn record_set(&mut self, field: impl Into<ImmString>) -> &mut Self {
+        let value_ref = self.builder_inner.new_string(field);
+        self.push_const_operand_inst(&value_ref, Instruction::RecordSet)
+    }
+
+    /// Adds a `TagNew` that makes a tag named `name`.
+    pub fn tag_new(&mut self, name: impl Into<ImmString>) -> &mut Self {
+        let value_ref = self.builder_inner.new_string(name);
+        self.push_const_operand_inst(&value_ref, Instruction::TagNew)
+    }
+
     def_build_inst_method!(add());
+    def_build_inst_method!(sub());
+    def_build_inst_method!(mul());
+    def_build_inst_method!(div());
     def_build_inst_method!(push_copy(s: StackIndex));
     def_build_inst_method!(pop(n: u32));
     def_build_inst_method!(write_stack(s: StackIndex));
@@ -69,46 +144,85 @@ impl FunctionBuilder {
     def_build_inst_method!(call(call: CallInstruction));
     def_build_inst_method!(tail_call(num_args: u32));
     def_build_inst_method!(call_dynamic());
+    def_build_inst_method!(apply());
     def_build_inst_method!(return_(n: u32));
     def_build_inst_method!(return_dynamic());
     def_build_inst_method!(branch_if(target: &str));
+    def_build_inst_method!(branch_if_truthy(target: &str, truthiness: Truthiness));
     def_build_inst_method!(branch(target: &str));
+    def_build_inst_method!(cmp_branch(op: CompareOp, target: &str));
+    def_build_inst_method!(call_protected(call: CallInstruction, handler: &str));
     def_build_inst_method!(define_branch_target(target: &str));
     def_build_inst_method!(bind_front(num_args: u32));
+    def_build_inst_method!(list_new());
+    def_build_inst_method!(list_append());
+    def_build_inst_method!(list_len());
+    def_build_inst_method!(list_get());
+    def_build_inst_method!(list_get_or_null());
+    def_build_inst_method!(list_set());
+    def_build_inst_method!(list_get_rel());
+    def_build_inst_method!(list_set_rel());
+    def_build_inst_method!(list_slice());
+    def_build_inst_method!(cell_new());
+    def_build_inst_method!(cell_get());
+    def_build_inst_method!(cell_set());
+    def_build_inst_method!(weak_new());
+    def_build_inst_method!(weak_get());
+    def_build_inst_method!(tag_wrap());
+    def_build_inst_method!(tag_of());
+    def_build_inst_method!(untag());
+    def_build_inst_method!(raise());
+    def_build_inst_method!(str_len_bytes());
+    def_build_inst_method!(str_len_chars());
+    def_build_inst_method!(str_slice_bytes());
+    def_build_inst_method!(str_slice_chars());
+    def_build_inst_method!(is_null());
+    def_build_inst_method!(identity_hash());
+    def_build_inst_method!(to_bool(truthiness: Truthiness));
+    def_build_inst_method!(to_number(kind: NumericKind));
 
     pub fn build(self) -> Result<()> {
         let mut instructions = self.insts;
         let value_pushes = self.value_pushes;
         let value_pops = self.value_pops;
+        let const_operands = self.const_operands;
+        let arity = self.arity;
 
-        self.deferred.resolve_fn(|resolver| {
+        self.deferred.resolve_fn(move |resolver| {
             let mut const_indexes = Vec::new();
             for (inst_index, ref_index) in value_pushes {
                 match resolver.resolve_ref(ref_index)? {
                     ValueIndex::Const(const_index) => {
-                        let local_index = const_indexes.len();
+                        let local_index = LocalConstIndex::from_usize(const_indexes.len())
+                            .ok_or(BuilderError::IndexOverflow)?;
                         const_indexes.push(const_index);
-                        instructions.resolve_push_const(inst_index, local_index as u32)?;
+                        instructions.resolve_push_const(inst_index, local_index)?;
                     }
-                    super::ValueIndex::Global(global_index) => {
+                    ValueIndex::Global(global_index) => {
                         instructions.resolve_push_global(inst_index, global_index)?;
                     }
                 }
             }
+            for (inst_index, ref_index, make_inst) in const_operands {
+                let const_index = resolver.resolve_to_const_index(ref_index)?;
+                let local_index = LocalConstIndex::from_usize(const_indexes.len())
+                    .ok_or(BuilderError::IndexOverflow)?;
+                const_indexes.push(const_index);
+                instructions.resolve_deferred_inst(inst_index, make_inst(local_index))?;
+            }
             for (inst_index, ref_index) in value_pops {
                 match resolver.resolve_ref(ref_index)? {
                     ValueIndex::Const(_) => {
                         return Err(BuilderError::ExpectedGlobal);
                     }
-                    super::ValueIndex::Global(global_index) => {
+                    ValueIndex::Global(global_index) => {
                         instructions.resolve_pop_global(inst_index, global_index)?;
                     }
                 }
             }
-            Ok(ConstValue::Function(ConstFunction::new(
-                const_indexes,
-                instructions.build()?,
-            )))
+            Ok(ConstValue::Function(
+                ConstFunction::new(const_indexes, instructions.build()?).with_arity(arity),
+            ))
         })?;
         Ok(())
     }
@@ -0,0 +1,302 @@
+//! Static references between the functions of a module set.
+//!
+//! A function refers to another when it pushes a constant that is, or
+//! contains, that function. References through imports are followed into
+//! the exporting module when it is part of the set, and are otherwise kept as
+//! imports. Values that only flow through globals or arguments are not seen,
+//! so the graph is a lower bound on what a function may call.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    const_table::{ConstIndex, ConstValue},
+    function_id::FunctionId,
+    indexes::ModuleConstIndex,
+    instructions::Instruction,
+    module_set::ModuleSet,
+    modules::{ImportSource, ModuleId},
+};
+
+/// Something a function refers to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FunctionReference {
+    /// A managed function defined in the module set.
+    Function(FunctionId),
+    /// An import from a module outside of the set, or an export the set does
+    /// not define.
+    Import(ImportSource),
+}
+
+/// The functions of a module set, and what each of them refers to.
+#[derive(Clone, Debug)]
+pub struct CallGraph {
+    references: HashMap<FunctionId, Vec<FunctionReference>>,
+    entry_points: Vec<FunctionId>,
+}
+
+impl CallGraph {
+    pub(super) fn new(module_set: &ModuleSet) -> Self {
+        let resolver = Resolver { module_set };
+        let mut references = HashMap::new();
+        let mut entry_points = Vec::new();
+        for module in module_set.modules() {
+            for (index, value) in module.const_table().iter().enumerate() {
+                let ConstValue::Function(function) = value else {
+                    continue;
+                };
+                let mut function_refs = Vec::new();
+                let mut visited = HashSet::new();
+                let mut pushed = HashSet::new();
+                for inst in function.instructions().instructions() {
+                    let Instruction::PushConst(local) = inst else {
+                        continue;
+                    };
+                    if !pushed.insert(*local) {
+                        continue;
+                    }
+                    if let Some(const_index) = local.get(function.module_constants()) {
+                        resolver.resolve(
+                            module.id(),
+                            const_index,
+                            &mut visited,
+                            &mut function_refs,
+                        );
+                    }
+                }
+                let index =
+                    ModuleConstIndex::from_usize(index).expect("Const tables are indexed by u32.");
+                references.insert(function_id(module.id(), index), function_refs);
+            }
+
+            let mut entry_refs = Vec::new();
+            let mut visited = HashSet::new();
+            for index in module
+                .exports()
+                .values()
+                .chain(module.initializer().as_ref())
+            {
+                resolver.resolve(
+                    module.id(),
+                    &ConstIndex::ModuleConst(*index),
+                    &mut visited,
+                    &mut entry_refs,
+                );
+            }
+            entry_points.extend(entry_refs.into_iter().filter_map(|r| match r {
+                FunctionReference::Function(id) => Some(id),
+                FunctionReference::Import(_) => None,
+            }));
+        }
+        CallGraph {
+            references,
+            entry_points,
+        }
+    }
+
+    /// Returns every function in the module set, in no particular order.
+    pub fn functions(&self) -> impl Iterator<Item = &FunctionId> {
+        self.references.keys()
+    }
+
+    /// Returns what `function` refers to, in the order the references first
+    /// appear in its instructions. Returns an empty slice for functions that
+    /// are not in the graph.
+    #[must_use]
+    pub fn references(&self, function: &FunctionId) -> &[FunctionReference] {
+        self.references.get(function).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the functions that refer to `function`.
+    pub fn referrers<'a>(
+        &'a self,
+        function: &'a FunctionId,
+    ) -> impl Iterator<Item = &'a FunctionId> {
+        self.references.iter().filter_map(move |(referrer, refs)| {
+            refs.iter()
+                .any(|r| matches!(r, FunctionReference::Function(id) if id == function))
+                .then_some(referrer)
+        })
+    }
+
+    /// Returns the functions that are exported, or are module initializers,
+    /// along with those contained in exported lists.
+    #[must_use]
+    pub fn entry_points(&self) -> &[FunctionId] {
+        &self.entry_points
+    }
+
+    /// Returns the functions that can be reached from `roots` by following
+    /// references, including the roots themselves.
+    pub fn reachable_from<'a>(
+        &self,
+        roots: impl IntoIterator<Item = &'a FunctionId>,
+    ) -> HashSet<FunctionId> {
+        let mut reached = HashSet::new();
+        let mut pending = roots.into_iter().cloned().collect::<Vec<_>>();
+        while let Some(function) = pending.pop() {
+            if !reached.insert(function.clone()) {
+                continue;
+            }
+            pending.extend(self.references(&function).iter().filter_map(|r| match r {
+                FunctionReference::Function(id) if !reached.contains(id) => Some(id.clone()),
+                _ => None,
+            }));
+        }
+        reached
+    }
+
+    /// Returns the functions that cannot be reached from any entry point.
+    #[must_use]
+    pub fn unreachable(&self) -> HashSet<FunctionId> {
+        let reached = self.reachable_from(&self.entry_points);
+        self.functions()
+            .filter(|function| !reached.contains(*function))
+            .cloned()
+            .collect()
+    }
+}
+
+fn function_id(module_id: &ModuleId, const_index: ModuleConstIndex) -> FunctionId {
+    FunctionId::Managed {
+        module_id: Some(module_id.clone()),
+        const_index,
+    }
+}
+
+struct Resolver<'a> {
+    module_set: &'a ModuleSet,
+}
+
+impl Resolver<'_> {
+    /// Adds the functions that the constant at `index` of `module_id` is or
+    /// contains to `out`. `visited` holds the constants already resolved, so
+    /// lists that contain themselves terminate.
+    fn resolve(
+        &self,
+        module_id: &ModuleId,
+        index: &ConstIndex,
+        visited: &mut HashSet<(ModuleId, ModuleConstIndex)>,
+        out: &mut Vec<FunctionReference>,
+    ) {
+        let Some(module) = self.module_set.module(module_id) else {
+            return;
+        };
+        match index {
+            ConstIndex::ModuleConst(i) => {
+                if !visited.insert((module_id.clone(), *i)) {
+                    return;
+                }
+                match i.get(module.const_table()) {
+                    Some(ConstValue::Function(_)) => {
+                        let reference = FunctionReference::Function(function_id(module_id, *i));
+                        if !out.contains(&reference) {
+                            out.push(reference);
+                        }
+                    }
+                    Some(ConstValue::List(items)) => {
+                        for item in items {
+                            self.resolve(module_id, item, visited, out);
+                        }
+                    }
+                    Some(ConstValue::Record(record)) => {
+                        for (_, value) in record.fields() {
+                            self.resolve(module_id, value, visited, out);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            ConstIndex::ModuleImport(i) => {
+                let Some(import) = i.get(module.imports()) else {
+                    return;
+                };
+                let export = self
+                    .module_set
+                    .module(import.module_id())
+                    .and_then(|source| source.exports().get(import.import_name()).copied());
+                match export {
+                    Some(export) => self.resolve(
+                        import.module_id(),
+                        &ConstIndex::ModuleConst(export),
+                        visited,
+                        out,
+                    ),
+                    None => {
+                        let reference = FunctionReference::Import(import.clone());
+                        if !out.contains(&reference) {
+                            out.push(reference);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::modules::ModuleMemberId;
+
+    #[test]
+    fn references_follow_lists_and_imports() -> anyhow::Result<()> {
+        let module_set = crate::lat::from_str(
+            r#"
+                (module-set
+                    ("lib"
+                        (const helper (fn (return 0)))
+                        (const unused (fn (push helper) (return 1)))
+                        (export helper))
+                    ("app"
+                        (import helper "lib" helper)
+                        (import print "std.io" print)
+                        (const handlers (list helper main))
+                        (const main
+                            (fn
+                                (push handlers)
+                                (push print)
+                                (return 2)))
+                        (export main)))
+            "#,
+        )?;
+        let export_id = |module: &str, name: &str| {
+            let module_id = ModuleId::new([module]);
+            let index =
+                module_set.module(&module_id).unwrap().exports()[&ModuleMemberId::new(name)];
+            function_id(&module_id, index)
+        };
+        let main = export_id("app", "main");
+        let helper = export_id("lib", "helper");
+        let graph = module_set.call_graph();
+        assert_eq!(graph.functions().count(), 3);
+        let unused = graph
+            .functions()
+            .find(|f| **f != main && **f != helper)
+            .unwrap()
+            .clone();
+
+        assert_eq!(
+            graph.references(&main),
+            &[
+                FunctionReference::Function(helper.clone()),
+                FunctionReference::Function(main.clone()),
+                FunctionReference::Import(ImportSource::new(ModuleId::new(["std", "io"]), "print")),
+            ]
+        );
+        assert!(graph.references(&helper).is_empty());
+        assert_eq!(
+            graph.references(&unused),
+            &[FunctionReference::Function(helper.clone())]
+        );
+
+        let referrers = graph.referrers(&helper).cloned().collect::<HashSet<_>>();
+        assert_eq!(referrers, HashSet::from([main.clone(), unused.clone()]));
+
+        assert_eq!(graph.unreachable(), HashSet::from([unused]));
+        assert_eq!(
+            graph.reachable_from([&main]),
+            HashSet::from([main.clone(), helper])
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,424 @@
+//! A load-time pass that evaluates the side-effect-free prefix of a module
+//! initializer, and bakes the values it writes to globals into the constant
+//! table.
+//!
+//! Only a small, obviously pure subset of instructions is interpreted. The
+//! prefix stops at the first instruction outside of that subset, at the first
+//! branch target, or at any point where evaluation would fail, so that errors
+//! are still reported by the runtime as before. Whether an instruction may be
+//! evaluated at all is decided by its [`Effects`].
+//!
+//! The bottom of an initializer's frame holds any arguments passed by the
+//! embedder, which are not known ahead of time, so the prefix also stops at
+//! the first stack access relative to the bottom of the frame.
+
+use std::collections::BTreeMap;
+
+use super::{
+    const_table::{ConstFunction, ConstIndex, ConstValue},
+    effects::Effects,
+    indexes::{GlobalIndex, LocalConstIndex, ModuleConstIndex},
+    instructions::{BranchTarget, Instruction, InstructionList, StackIndex},
+    modules::ConstModule,
+};
+
+/// A value on the abstract stack.
+#[derive(Clone)]
+enum AbstractValue {
+    /// A value loaded from one of the function's constants.
+    Const(ConstIndex),
+
+    /// A value computed by the pass.
+    Computed(ConstValue),
+}
+
+struct PrefixEval<'a> {
+    const_table: &'a [ConstValue],
+    function: &'a ConstFunction,
+    stack: Vec<AbstractValue>,
+    global_writes: BTreeMap<GlobalIndex, AbstractValue>,
+}
+
+impl<'a> PrefixEval<'a> {
+    fn new(const_table: &'a [ConstValue], function: &'a ConstFunction) -> Self {
+        PrefixEval {
+            const_table,
+            function,
+            stack: Vec::new(),
+            global_writes: BTreeMap::new(),
+        }
+    }
+
+    fn scalar(&self, value: &AbstractValue) -> Option<ConstValue> {
+        let value = match value {
+            AbstractValue::Const(ConstIndex::ModuleConst(index)) => index.get(self.const_table)?,
+            AbstractValue::Const(ConstIndex::ModuleImport(_)) => return None,
+            AbstractValue::Computed(value) => value,
+        };
+        match value {
+            ConstValue::Null
+            | ConstValue::Bool(_)
+            | ConstValue::Integer(_)
+            | ConstValue::Float(_) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn peek_scalars(&self, count: usize) -> Option<Vec<ConstValue>> {
+        let start = self.stack.len().checked_sub(count)?;
+        self.stack[start..]
+            .iter()
+            .map(|value| self.scalar(value))
+            .collect()
+    }
+
+    fn stack_index(&self, index: StackIndex) -> Option<usize> {
+        match index {
+            StackIndex::FromTop(i) => self.stack.len().checked_sub(i as usize + 1),
+            // The abstract stack does not include the initializer's arguments.
+            StackIndex::FromBottom(_) => None,
+        }
+    }
+
+    /// Evaluates a single instruction. Returns `None` without modifying any
+    /// state if the instruction cannot be evaluated at load time.
+    fn step(&mut self, inst: &Instruction) -> Option<()> {
+        // Failures are left for the runtime to report, and global writes are
+        // recorded, but nothing else can be done ahead of time.
+        if !(Effects::WRITES_GLOBALS | Effects::MAY_FAIL).contains(inst.effects()) {
+            return None;
+        }
+        match inst {
+            Instruction::PushConst(index) => {
+                let index = index.get(self.function.module_constants())?;
+                self.stack.push(AbstractValue::Const(index.clone()));
+            }
+            Instruction::PushCopy(index) => {
+                let index = self.stack_index(*index)?;
+                self.stack.push(self.stack[index].clone());
+            }
+            Instruction::Pop(count) => {
+                let new_len = self.stack.len().checked_sub(*count as usize)?;
+                self.stack.truncate(new_len);
+            }
+            Instruction::PopGlobal(index) => {
+                let value = self.stack.pop()?;
+                self.global_writes.insert(*index, value);
+            }
+            Instruction::Add => {
+                let operands = self.peek_scalars(2)?;
+                let result = match (&operands[1], &operands[0]) {
+                    (ConstValue::Integer(a), ConstValue::Integer(b)) => {
+                        ConstValue::Integer(a.clone().add_owned(b.clone()))
+                    }
+                    (ConstValue::Float(a), ConstValue::Float(b)) => {
+                        ConstValue::Float(a.clone().add_owned(b.clone()))
+                    }
+                    _ => return None,
+                };
+                self.push_computed(2, result);
+            }
+            Instruction::BoolAnd | Instruction::BoolOr | Instruction::BoolXor => {
+                let operands = self.peek_scalars(2)?;
+                let (ConstValue::Bool(a), ConstValue::Bool(b)) = (&operands[1], &operands[0])
+                else {
+                    return None;
+                };
+                let result = match inst {
+                    Instruction::BoolAnd => *a && *b,
+                    Instruction::BoolOr => *a || *b,
+                    _ => *a ^ *b,
+                };
+                self.push_computed(2, ConstValue::Bool(result));
+            }
+            Instruction::BoolNot => {
+                let ConstValue::Bool(b) = self.scalar(self.stack.last()?)? else {
+                    return None;
+                };
+                self.push_computed(1, ConstValue::Bool(!b));
+            }
+            _ => return None,
+        }
+        Some(())
+    }
+
+    fn push_computed(&mut self, num_operands: usize, value: ConstValue) {
+        self.stack.truncate(self.stack.len() - num_operands);
+        self.stack.push(AbstractValue::Computed(value));
+    }
+}
+
+/// Returns the index of the first instruction that may be reached other than
+/// by falling through from the previous instruction.
+fn first_branch_target(instructions: &[Instruction]) -> usize {
+    instructions
+        .iter()
+        .filter_map(|inst| Some(inst.branch_target()?.target_index() as usize))
+        .min()
+        .unwrap_or(instructions.len())
+}
+
+fn shift_branch(inst: &Instruction, old_start: usize, new_start: usize) -> Instruction {
+    let mut inst = inst.clone();
+    if let Some(target) = inst.branch_target_mut() {
+        *target =
+            BranchTarget::new((target.target_index() as usize - old_start + new_start) as u32);
+    }
+    inst
+}
+
+impl ConstModule {
+    /// Returns a copy of this module where the side-effect-free prefix of the
+    /// initializer has been evaluated ahead of time.
+    ///
+    /// Values the prefix writes to globals are added to the constant table,
+    /// and the prefix is replaced by instructions that copy those constants
+    /// into the globals. Returns `None` if no part of the initializer can be
+    /// evaluated.
+    #[must_use]
+    pub fn const_eval_initializer(&self) -> Option<ConstModule> {
+        let init_index = self.initializer()?;
+        let ConstValue::Function(function) = init_index.get(self.const_table())? else {
+            return None;
+        };
+        let instructions = function.instructions().instructions();
+        let limit = first_branch_target(instructions);
+
+        // The prefix may only end where the abstract stack is empty, so the
+        // remaining instructions see the same stack as before.
+        let mut eval = PrefixEval::new(self.const_table(), function);
+        let mut prefix_end = 0;
+        let mut writes = BTreeMap::new();
+        for (pc, inst) in instructions[..limit].iter().enumerate() {
+            if eval.step(inst).is_none() {
+                break;
+            }
+            if eval.stack.is_empty() {
+                prefix_end = pc + 1;
+                writes.clone_from(&eval.global_writes);
+            }
+        }
+        if prefix_end == 0 {
+            return None;
+        }
+
+        let mut const_table = self.const_table().to_vec();
+        let mut module_constants = function.module_constants().to_vec();
+        let mut new_instructions = Vec::new();
+        for (global, value) in writes {
+            let const_index = match value {
+                AbstractValue::Const(const_index) => const_index,
+                AbstractValue::Computed(value) => {
+                    const_table.push(value);
+                    ConstIndex::ModuleConst(ModuleConstIndex::from_usize(const_table.len() - 1)?)
+                }
+            };
+            new_instructions.push(Instruction::PushConst(LocalConstIndex::from_usize(
+                module_constants.len(),
+            )?));
+            new_instructions.push(Instruction::PopGlobal(global));
+            module_constants.push(const_index);
+        }
+        let new_start = new_instructions.len();
+        new_instructions.extend(
+            instructions[prefix_end..]
+                .iter()
+                .map(|inst| shift_branch(inst, prefix_end, new_start)),
+        );
+        let new_instructions = InstructionList::new(new_instructions);
+        const_table[init_index.as_usize()] = ConstValue::Function(ConstFunction::new(
+            module_constants,
+            new_instructions
+                .eliminate_dead_pushes()
+                .unwrap_or(new_instructions),
+        ));
+        Some(self.with_const_table(const_table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::binary::{instructions::CallInstruction, modules::ModuleId};
+
+    fn module_with_init(
+        mut const_table: Vec<ConstValue>,
+        instructions: Vec<Instruction>,
+    ) -> anyhow::Result<ConstModule> {
+        let module_constants = (0..const_table.len() as u32)
+            .map(|index| ConstIndex::ModuleConst(ModuleConstIndex::new(index)))
+            .collect();
+        const_table.push(ConstValue::Function(ConstFunction::new(
+            module_constants,
+            InstructionList::new(instructions),
+        )));
+        let init = ModuleConstIndex::from_usize(const_table.len() - 1).unwrap();
+        Ok(ConstModule::new(
+            ModuleId::new(["test"]),
+            const_table,
+            vec![],
+            HashMap::new(),
+            Some(init),
+            2,
+        )?)
+    }
+
+    fn init_function(module: &ConstModule) -> &ConstFunction {
+        match &module.const_table()[module.initializer().unwrap().as_usize()] {
+            ConstValue::Function(function) => function,
+            _ => panic!("Initializer is not a function."),
+        }
+    }
+
+    #[test]
+    fn pure_prefix_is_folded() -> anyhow::Result<()> {
+        let module = module_with_init(
+            vec![
+                ConstValue::Integer(1.into()),
+                ConstValue::Integer(2.into()),
+                ConstValue::Bool(true),
+            ],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PushConst(LocalConstIndex::new(1)),
+                Instruction::Add,
+                Instruction::PopGlobal(GlobalIndex::new(0)),
+                Instruction::PushConst(LocalConstIndex::new(2)),
+                Instruction::BoolNot,
+                Instruction::PopGlobal(GlobalIndex::new(1)),
+                Instruction::Return(0),
+            ],
+        )?;
+        let folded = module.const_eval_initializer().unwrap();
+        let function = init_function(&folded);
+        let insts = function.instructions().instructions();
+        assert_eq!(insts.len(), 5);
+        let Instruction::PushConst(local) = insts[0] else {
+            panic!("Expected PushConst.");
+        };
+        let ConstIndex::ModuleConst(index) = function.module_constants()[local.as_usize()] else {
+            panic!("Expected a module const.");
+        };
+        let ConstValue::Integer(sum) = &folded.const_table()[index.as_usize()] else {
+            panic!("Expected an integer.");
+        };
+        assert_eq!(sum.to_compact_integer(), Some(3));
+        assert_eq!(insts[1], Instruction::PopGlobal(GlobalIndex::new(0)));
+        assert_eq!(insts[3], Instruction::PopGlobal(GlobalIndex::new(1)));
+        assert!(matches!(insts[4], Instruction::Return(0)));
+        Ok(())
+    }
+
+    #[test]
+    fn prefix_stops_at_impure_instruction() -> anyhow::Result<()> {
+        let module = module_with_init(
+            vec![ConstValue::Integer(1.into())],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PopGlobal(GlobalIndex::new(0)),
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::Call(CallInstruction {
+                    num_args: 0,
+                    num_returns: 1,
+                }),
+                Instruction::PopGlobal(GlobalIndex::new(1)),
+                Instruction::Return(0),
+            ],
+        )?;
+        let folded = module.const_eval_initializer().unwrap();
+        let insts = init_function(&folded).instructions().instructions();
+        // Only the first write is folded, and it passes the constant through.
+        assert_eq!(folded.const_table().len(), module.const_table().len());
+        assert_eq!(insts.len(), 6);
+        assert!(matches!(insts[3], Instruction::Call(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn dead_pushes_after_the_prefix_are_removed() -> anyhow::Result<()> {
+        let module = module_with_init(
+            vec![ConstValue::Integer(1.into())],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PopGlobal(GlobalIndex::new(0)),
+                Instruction::PushGlobal(GlobalIndex::new(0)),
+                Instruction::Pop(1),
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::Pop(1),
+                Instruction::Return(0),
+            ],
+        )?;
+        let folded = module.const_eval_initializer().unwrap();
+        let insts = init_function(&folded).instructions().instructions();
+        // The global read may fail, so only the constant push is removed.
+        assert_eq!(
+            &insts[2..],
+            &[
+                Instruction::PushGlobal(GlobalIndex::new(0)),
+                Instruction::Pop(1),
+                Instruction::Return(0),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bottom_relative_reads_are_left_for_runtime() -> anyhow::Result<()> {
+        let module = module_with_init(
+            vec![ConstValue::Integer(1.into())],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PushCopy(StackIndex::FromBottom(0)),
+                Instruction::PopGlobal(GlobalIndex::new(0)),
+                Instruction::Pop(1),
+                Instruction::Return(0),
+            ],
+        )?;
+        assert!(module.const_eval_initializer().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn ill_typed_prefix_is_left_for_runtime() -> anyhow::Result<()> {
+        let module = module_with_init(
+            vec![ConstValue::Integer(1.into()), ConstValue::Bool(true)],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PushConst(LocalConstIndex::new(1)),
+                Instruction::Add,
+                Instruction::PopGlobal(GlobalIndex::new(0)),
+                Instruction::Return(0),
+            ],
+        )?;
+        assert!(module.const_eval_initializer().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn branch_targets_are_shifted() -> anyhow::Result<()> {
+        let module = module_with_init(
+            vec![ConstValue::Integer(1.into()), ConstValue::Bool(false)],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::Add,
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::Add,
+                Instruction::PopGlobal(GlobalIndex::new(0)),
+                Instruction::PushConst(LocalConstIndex::new(1)),
+                Instruction::BranchIf(BranchTarget::new(6)),
+                Instruction::Return(0),
+            ],
+        )?;
+        let folded = module.const_eval_initializer().unwrap();
+        let insts = init_function(&folded).instructions().instructions();
+        assert_eq!(insts.len(), 5);
+        let Instruction::BranchIf(target) = insts[3] else {
+            panic!("Expected BranchIf.");
+        };
+        assert_eq!(target.target_index(), 2);
+        Ok(())
+    }
+}
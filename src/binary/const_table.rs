@@ -1,21 +1,25 @@
 use crate::{
     pure_values::{Float, Integer},
-    util::imm_string::ImmString,
+    util::imm_string::{ImmBytes, ImmString},
 };
 
-use super::instructions::InstructionList;
+use super::{
+    indexes::{ImportIndex, ModuleConstIndex},
+    instructions::InstructionList,
+};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ConstIndex {
-    /// An index into the stack of constant tables.
-    ModuleConst(u32),
+    /// An index into the module's constant table.
+    ModuleConst(ModuleConstIndex),
 
-    /// An index to be resolved globally by name.
-    ModuleImport(u32),
+    /// An index into the module's imports, which are resolved by name when
+    /// the module is loaded.
+    ModuleImport(ImportIndex),
 }
 
 impl ConstIndex {
-    pub fn as_module_const(&self) -> Option<u32> {
+    pub fn as_module_const(&self) -> Option<ModuleConstIndex> {
         match self {
             ConstIndex::ModuleConst(index) => Some(*index),
             _ => None,
@@ -23,11 +27,14 @@ impl ConstIndex {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ConstFunction {
-    /// Definitions of constants local to the function.
+    /// Definitions of constants local to the function, indexed by the
+    /// operands of its `PushConst` instructions.
     module_constants: Vec<ConstIndex>,
     instructions: InstructionList,
+    /// The number of arguments the function declares, if known.
+    arity: Option<u32>,
 }
 
 impl ConstFunction {
@@ -35,9 +42,18 @@ impl ConstFunction {
         ConstFunction {
             module_constants,
             instructions,
+            arity: None,
         }
     }
 
+    /// Declares the number of arguments the function takes. `bind_front`
+    /// checks the values it captures against it.
+    #[must_use]
+    pub fn with_arity(mut self, arity: Option<u32>) -> Self {
+        self.arity = arity;
+        self
+    }
+
     pub fn module_constants(&self) -> &[ConstIndex] {
         &self.module_constants[..]
     }
@@ -45,14 +61,57 @@ impl ConstFunction {
     pub fn instructions(&self) -> &InstructionList {
         &self.instructions
     }
+
+    pub fn arity(&self) -> Option<u32> {
+        self.arity
+    }
+}
+
+/// A record constant, with the names and values of its fields in the order
+/// they were declared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConstRecord {
+    fields: Vec<(ImmString, ConstIndex)>,
+}
+
+impl ConstRecord {
+    pub fn new(fields: Vec<(ImmString, ConstIndex)>) -> Self {
+        ConstRecord { fields }
+    }
+
+    pub fn fields(&self) -> &[(ImmString, ConstIndex)] {
+        &self.fields[..]
+    }
+
+    /// Returns the index of the value of the `n`th field, if there is one.
+    pub fn value(&self, n: usize) -> Option<&ConstIndex> {
+        self.fields.get(n).map(|(_, value)| value)
+    }
+
+    /// Returns the name of the first field that is declared more than once,
+    /// if any.
+    pub fn duplicate_field(&self) -> Option<&ImmString> {
+        self.fields
+            .iter()
+            .enumerate()
+            .find(|(i, (name, _))| self.fields[..*i].iter().any(|(other, _)| other == name))
+            .map(|(_, (name, _))| name)
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum ConstValue {
+    /// The null value, which marks the absence of a value.
+    Null,
     Bool(bool),
     Integer(Integer),
+    /// A float constant. The exact bit pattern is preserved, including
+    /// subnormals, signed zeros, infinities and NaN payloads.
     Float(Float),
     String(ImmString),
+    /// An immutable byte string.
+    Bytes(ImmBytes),
     List(Vec<ConstIndex>),
+    Record(ConstRecord),
     Function(ConstFunction),
 }
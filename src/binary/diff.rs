@@ -0,0 +1,559 @@
+//! Structural differences between two versions of a module.
+//!
+//! Compilers that produce modules can use a [`ModuleDiff`] to show what
+//! changed between builds: which exports were added, removed or changed,
+//! which constants changed, and an instruction level diff of each changed
+//! function.
+
+use std::collections::BTreeSet;
+
+use super::{
+    const_table::{ConstFunction, ConstIndex, ConstValue},
+    indexes::ModuleConstIndex,
+    instructions::Instruction,
+    modules::{ConstModule, ModuleMemberId},
+};
+
+/// A change to a single instruction in a function body.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InstructionDiff {
+    /// The instruction is in both versions.
+    Same(Instruction),
+    /// The instruction is only in the old version.
+    Removed(Instruction),
+    /// The instruction is only in the new version.
+    Added(Instruction),
+}
+
+/// The differences between two versions of a function at the same index of
+/// the constant table.
+#[derive(Clone, Debug)]
+pub struct FunctionDiff {
+    index: ModuleConstIndex,
+    old_constants: Vec<ConstIndex>,
+    new_constants: Vec<ConstIndex>,
+    instructions: Vec<InstructionDiff>,
+}
+
+impl FunctionDiff {
+    pub fn index(&self) -> ModuleConstIndex {
+        self.index
+    }
+
+    pub fn constants_changed(&self) -> bool {
+        self.old_constants != self.new_constants
+    }
+
+    pub fn old_constants(&self) -> &[ConstIndex] {
+        &self.old_constants
+    }
+
+    pub fn new_constants(&self) -> &[ConstIndex] {
+        &self.new_constants
+    }
+
+    /// The edit script from the old instructions to the new ones, including
+    /// the instructions both versions have in common.
+    pub fn instructions(&self) -> &[InstructionDiff] {
+        &self.instructions
+    }
+}
+
+/// A change to a single entry of the constant table.
+#[derive(Clone, Debug)]
+pub enum ConstChange {
+    /// A constant only in the new version.
+    Added(ModuleConstIndex, ConstValue),
+    /// A constant only in the old version.
+    Removed(ModuleConstIndex, ConstValue),
+    /// A constant with a different value in each version, where at least one
+    /// of them is not a function.
+    Replaced {
+        index: ModuleConstIndex,
+        old: ConstValue,
+        new: ConstValue,
+    },
+    /// A function with a different body in each version.
+    Function(FunctionDiff),
+}
+
+impl ConstChange {
+    pub fn index(&self) -> ModuleConstIndex {
+        match self {
+            ConstChange::Added(index, _) | ConstChange::Removed(index, _) => *index,
+            ConstChange::Replaced { index, .. } => *index,
+            ConstChange::Function(diff) => diff.index(),
+        }
+    }
+}
+
+/// The differences between two modules, as returned by
+/// [`ConstModule::diff`].
+///
+/// Constants are compared by their position in the constant table. The
+/// `Display` implementation renders the differences in a line based format
+/// meant for reading in logs and code review.
+#[derive(Clone, Debug, Default)]
+pub struct ModuleDiff {
+    added_exports: Vec<ModuleMemberId>,
+    removed_exports: Vec<ModuleMemberId>,
+    changed_exports: Vec<ModuleMemberId>,
+    const_changes: Vec<ConstChange>,
+}
+
+impl ModuleDiff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.added_exports.is_empty()
+            && self.removed_exports.is_empty()
+            && self.changed_exports.is_empty()
+            && self.const_changes.is_empty()
+    }
+
+    pub fn added_exports(&self) -> &[ModuleMemberId] {
+        &self.added_exports
+    }
+
+    pub fn removed_exports(&self) -> &[ModuleMemberId] {
+        &self.removed_exports
+    }
+
+    /// Exports in both versions that refer to a different constant, or to a
+    /// constant whose value changed.
+    pub fn changed_exports(&self) -> &[ModuleMemberId] {
+        &self.changed_exports
+    }
+
+    pub fn const_changes(&self) -> &[ConstChange] {
+        &self.const_changes
+    }
+}
+
+impl std::fmt::Display for ModuleDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for name in &self.added_exports {
+            writeln!(f, "+ export {}", name.as_str())?;
+        }
+        for name in &self.removed_exports {
+            writeln!(f, "- export {}", name.as_str())?;
+        }
+        for name in &self.changed_exports {
+            writeln!(f, "~ export {}", name.as_str())?;
+        }
+        for change in &self.const_changes {
+            match change {
+                ConstChange::Added(index, value) => writeln!(f, "+ const {index}: {value:?}")?,
+                ConstChange::Removed(index, value) => writeln!(f, "- const {index}: {value:?}")?,
+                ConstChange::Replaced { index, old, new } => {
+                    writeln!(f, "- const {index}: {old:?}")?;
+                    writeln!(f, "+ const {index}: {new:?}")?;
+                }
+                ConstChange::Function(diff) => {
+                    writeln!(f, "~ const {}: function", diff.index)?;
+                    if diff.constants_changed() {
+                        writeln!(f, "  - constants: {:?}", diff.old_constants)?;
+                        writeln!(f, "  + constants: {:?}", diff.new_constants)?;
+                    }
+                    for inst in &diff.instructions {
+                        match inst {
+                            InstructionDiff::Same(inst) => writeln!(f, "    {inst:?}")?,
+                            InstructionDiff::Removed(inst) => writeln!(f, "  - {inst:?}")?,
+                            InstructionDiff::Added(inst) => writeln!(f, "  + {inst:?}")?,
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compares two constants. Floats are compared by their bit patterns, so a
+/// NaN constant is equal to itself.
+fn const_value_eq(a: &ConstValue, b: &ConstValue) -> bool {
+    match (a, b) {
+        (ConstValue::Null, ConstValue::Null) => true,
+        (ConstValue::Bool(a), ConstValue::Bool(b)) => a == b,
+        (ConstValue::Integer(a), ConstValue::Integer(b)) => a == b,
+        (ConstValue::Float(a), ConstValue::Float(b)) => a.to_bits() == b.to_bits(),
+        (ConstValue::String(a), ConstValue::String(b)) => a == b,
+        (ConstValue::Bytes(a), ConstValue::Bytes(b)) => a == b,
+        (ConstValue::List(a), ConstValue::List(b)) => a == b,
+        (ConstValue::Record(a), ConstValue::Record(b)) => a == b,
+        (ConstValue::Function(a), ConstValue::Function(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The most edits [`shortest_edit_script`] looks for. Its time and memory
+/// grow with the square of the number of edits, so past this the changed
+/// part of a function is reported as wholly replaced.
+const MAX_EDIT_DISTANCE: usize = 1024;
+
+/// Returns the edit script between two instruction lists. The instructions
+/// both lists start and end with are kept, and the rest is diffed with
+/// [`shortest_edit_script`].
+fn diff_instructions(old: &[Instruction], new: &[Instruction]) -> Vec<InstructionDiff> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let mut result = Vec::with_capacity(old.len().max(new.len()));
+    result.extend(old[..prefix].iter().cloned().map(InstructionDiff::Same));
+    match shortest_edit_script(old_middle, new_middle) {
+        Some(edits) => result.extend(edits),
+        None => {
+            result.extend(old_middle.iter().cloned().map(InstructionDiff::Removed));
+            result.extend(new_middle.iter().cloned().map(InstructionDiff::Added));
+        }
+    }
+    result.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .cloned()
+            .map(InstructionDiff::Same),
+    );
+    result
+}
+
+/// Returns a shortest edit script between two instruction lists, found with
+/// Myers' algorithm, or `None` if it takes more than [`MAX_EDIT_DISTANCE`]
+/// edits.
+fn shortest_edit_script(old: &[Instruction], new: &[Instruction]) -> Option<Vec<InstructionDiff>> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max_d = (n + m).min(MAX_EDIT_DISTANCE as isize);
+    let offset = max_d + 1;
+    // v[k + offset] is the furthest x reached so far on diagonal k = x - y,
+    // where x counts old instructions and y new ones.
+    let mut v = vec![0; 2 * offset as usize + 1];
+    // trace[d] holds v for the diagonals -d..=d as it was before round d.
+    let mut trace = Vec::new();
+    for d in 0..=max_d {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                return Some(backtrack(old, new, &trace));
+            }
+        }
+    }
+    None
+}
+
+/// Follows the rounds recorded by [`shortest_edit_script`] back from the end
+/// of both lists, returning the edit script in order.
+fn backtrack(
+    old: &[Instruction],
+    new: &[Instruction],
+    trace: &[Vec<isize>],
+) -> Vec<InstructionDiff> {
+    let (mut x, mut y) = (old.len() as isize, new.len() as isize);
+    let mut edits = Vec::new();
+    for (d, v) in trace.iter().enumerate().skip(1).rev() {
+        let d = d as isize;
+        let at = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = at(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(InstructionDiff::Same(old[x as usize].clone()));
+        }
+        if x == prev_x {
+            edits.push(InstructionDiff::Added(new[prev_y as usize].clone()));
+        } else {
+            edits.push(InstructionDiff::Removed(old[prev_x as usize].clone()));
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    // The first round only follows the diagonal from the start.
+    while x > 0 {
+        x -= 1;
+        edits.push(InstructionDiff::Same(old[x as usize].clone()));
+    }
+    edits.reverse();
+    edits
+}
+
+fn diff_function(
+    index: ModuleConstIndex,
+    old: &ConstFunction,
+    new: &ConstFunction,
+) -> FunctionDiff {
+    FunctionDiff {
+        index,
+        old_constants: old.module_constants().to_vec(),
+        new_constants: new.module_constants().to_vec(),
+        instructions: diff_instructions(
+            old.instructions().instructions(),
+            new.instructions().instructions(),
+        ),
+    }
+}
+
+impl ConstModule {
+    /// Returns the differences between this module and `other`, treating this
+    /// module as the old version.
+    #[must_use]
+    pub fn diff(&self, other: &ConstModule) -> ModuleDiff {
+        let old_table = self.const_table();
+        let new_table = other.const_table();
+        let mut const_changes = Vec::new();
+        for index in 0..old_table.len().max(new_table.len()) {
+            let const_index =
+                ModuleConstIndex::from_usize(index).expect("Const tables are indexed by u32.");
+            match (old_table.get(index), new_table.get(index)) {
+                (Some(old), Some(new)) if const_value_eq(old, new) => {}
+                (Some(ConstValue::Function(old)), Some(ConstValue::Function(new))) => {
+                    const_changes.push(ConstChange::Function(diff_function(const_index, old, new)));
+                }
+                (Some(old), Some(new)) => const_changes.push(ConstChange::Replaced {
+                    index: const_index,
+                    old: old.clone(),
+                    new: new.clone(),
+                }),
+                (Some(old), None) => {
+                    const_changes.push(ConstChange::Removed(const_index, old.clone()))
+                }
+                (None, Some(new)) => {
+                    const_changes.push(ConstChange::Added(const_index, new.clone()))
+                }
+                (None, None) => unreachable!("Index is within one of the tables."),
+            }
+        }
+        let changed_consts = const_changes
+            .iter()
+            .map(ConstChange::index)
+            .collect::<BTreeSet<_>>();
+
+        let names = self
+            .exports()
+            .keys()
+            .chain(other.exports().keys())
+            .collect::<BTreeSet<_>>();
+        let mut diff = ModuleDiff {
+            const_changes,
+            ..ModuleDiff::default()
+        };
+        for name in names {
+            match (self.exports().get(name), other.exports().get(name)) {
+                (Some(old), Some(new)) => {
+                    if old != new || changed_consts.contains(new) {
+                        diff.changed_exports.push(name.clone());
+                    }
+                }
+                (Some(_), None) => diff.removed_exports.push(name.clone()),
+                (None, Some(_)) => diff.added_exports.push(name.clone()),
+                (None, None) => unreachable!("Name is exported by one of the modules."),
+            }
+        }
+        diff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{
+        binary::{indexes::LocalConstIndex, instructions::InstructionList, modules::ModuleId},
+        pure_values::Float,
+    };
+
+    fn function(instructions: Vec<Instruction>) -> ConstValue {
+        ConstValue::Function(ConstFunction::new(
+            vec![ConstIndex::ModuleConst(ModuleConstIndex::new(0))],
+            InstructionList::new(instructions),
+        ))
+    }
+
+    fn module(
+        const_table: Vec<ConstValue>,
+        exports: &[(&str, u32)],
+    ) -> anyhow::Result<ConstModule> {
+        let exports = exports
+            .iter()
+            .map(|(name, index)| (ModuleMemberId::new(*name), ModuleConstIndex::new(*index)))
+            .collect::<HashMap<_, _>>();
+        Ok(ConstModule::new(
+            ModuleId::new(["test"]),
+            const_table,
+            vec![],
+            exports,
+            None,
+            0,
+        )?)
+    }
+
+    #[test]
+    fn identical_modules_have_no_diff() -> anyhow::Result<()> {
+        let table = vec![
+            ConstValue::Float(Float::new(f64::NAN)),
+            function(vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::Return(1),
+            ]),
+        ];
+        let module = module(table, &[("f", 1)])?;
+        let diff = module.diff(&module);
+        assert!(diff.is_empty());
+        assert_eq!(diff.to_string(), "");
+        Ok(())
+    }
+
+    #[test]
+    fn export_changes_are_reported() -> anyhow::Result<()> {
+        let old = module(
+            vec![ConstValue::Integer(1.into()), ConstValue::Bool(true)],
+            &[("a", 0), ("b", 1), ("c", 1)],
+        )?;
+        let new = module(
+            vec![ConstValue::Integer(2.into()), ConstValue::Bool(true)],
+            &[("a", 0), ("c", 1), ("d", 0)],
+        )?;
+        let diff = old.diff(&new);
+        assert_eq!(diff.added_exports(), [ModuleMemberId::new("d")]);
+        assert_eq!(diff.removed_exports(), [ModuleMemberId::new("b")]);
+        assert_eq!(diff.changed_exports(), [ModuleMemberId::new("a")]);
+        assert!(matches!(
+            diff.const_changes(),
+            [ConstChange::Replaced { index, .. }] if *index == ModuleConstIndex::new(0)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn changed_functions_get_instruction_diffs() -> anyhow::Result<()> {
+        let old = module(
+            vec![
+                ConstValue::Integer(1.into()),
+                function(vec![
+                    Instruction::PushConst(LocalConstIndex::new(0)),
+                    Instruction::PushConst(LocalConstIndex::new(0)),
+                    Instruction::Add,
+                    Instruction::Return(1),
+                ]),
+            ],
+            &[("f", 1)],
+        )?;
+        let new = module(
+            vec![
+                ConstValue::Integer(1.into()),
+                function(vec![
+                    Instruction::PushConst(LocalConstIndex::new(0)),
+                    Instruction::PushConst(LocalConstIndex::new(0)),
+                    Instruction::BoolAnd,
+                    Instruction::Return(1),
+                ]),
+                ConstValue::Bool(false),
+            ],
+            &[("f", 1)],
+        )?;
+        let diff = old.diff(&new);
+        assert_eq!(diff.changed_exports(), [ModuleMemberId::new("f")]);
+        let [ConstChange::Function(function), ConstChange::Added(added, _)] = diff.const_changes()
+        else {
+            panic!("Unexpected changes: {:?}", diff.const_changes());
+        };
+        assert_eq!(*added, ModuleConstIndex::new(2));
+        assert!(!function.constants_changed());
+        assert_eq!(
+            function.instructions(),
+            [
+                InstructionDiff::Same(Instruction::PushConst(LocalConstIndex::new(0))),
+                InstructionDiff::Same(Instruction::PushConst(LocalConstIndex::new(0))),
+                InstructionDiff::Removed(Instruction::Add),
+                InstructionDiff::Added(Instruction::BoolAnd),
+                InstructionDiff::Same(Instruction::Return(1)),
+            ]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "~ export f\n\
+             ~ const 1: function\n    \
+             PushConst(0)\n    \
+             PushConst(0)\n  \
+             - Add\n  \
+             + BoolAnd\n    \
+             Return(1)\n\
+             + const 2: Bool(false)\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn instruction_diffs_are_minimal() {
+        let pop = Instruction::Pop;
+        let old = [pop(1), pop(2), pop(3), pop(4), pop(5), pop(6)];
+        let new = [pop(1), pop(3), pop(7), pop(4), pop(6), pop(8)];
+        let edits = diff_instructions(&old, &new);
+        let same = edits
+            .iter()
+            .filter(|edit| matches!(edit, InstructionDiff::Same(_)))
+            .count();
+        assert_eq!(same, 4);
+        assert_eq!(edits.len(), 8);
+        let replay = |keep: fn(&InstructionDiff) -> Option<&Instruction>| {
+            edits.iter().filter_map(keep).cloned().collect::<Vec<_>>()
+        };
+        assert_eq!(
+            replay(|edit| match edit {
+                InstructionDiff::Same(inst) | InstructionDiff::Removed(inst) => Some(inst),
+                InstructionDiff::Added(_) => None,
+            }),
+            old
+        );
+        assert_eq!(
+            replay(|edit| match edit {
+                InstructionDiff::Same(inst) | InstructionDiff::Added(inst) => Some(inst),
+                InstructionDiff::Removed(_) => None,
+            }),
+            new
+        );
+    }
+
+    #[test]
+    fn large_rewrites_are_diffed_coarsely() {
+        let len = MAX_EDIT_DISTANCE as u32;
+        let mut old = (0..len).map(Instruction::Pop).collect::<Vec<_>>();
+        let mut new = (len..2 * len).map(Instruction::Pop).collect::<Vec<_>>();
+        old.push(Instruction::Return(0));
+        new.push(Instruction::Return(0));
+        let edits = diff_instructions(&old, &new);
+        assert_eq!(edits.len(), 2 * len as usize + 1);
+        assert!(edits[..len as usize]
+            .iter()
+            .all(|edit| matches!(edit, InstructionDiff::Removed(_))));
+        assert!(edits[len as usize..2 * len as usize]
+            .iter()
+            .all(|edit| matches!(edit, InstructionDiff::Added(_))));
+        assert_eq!(
+            edits.last(),
+            Some(&InstructionDiff::Same(Instruction::Return(0)))
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! The effects each instruction may have beyond its use of the local stack.
+//!
+//! Passes that rewrite instructions use these to decide what they may remove
+//! or reorder. Errors from a malformed stack are not counted as effects, since
+//! every instruction that uses the stack can raise them.
+
+use std::ops::BitOr;
+
+use super::instructions::Instruction;
+
+/// A set of effects an instruction may have.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Default)]
+pub struct Effects(u8);
+
+impl Effects {
+    /// No effects. The instruction only computes values from the stack.
+    pub const NONE: Effects = Effects(0);
+    /// Reads a module global.
+    pub const READS_GLOBALS: Effects = Effects(1 << 0);
+    /// Writes a module global.
+    pub const WRITES_GLOBALS: Effects = Effects(1 << 1);
+    /// Reads the contents of a list or cell, which other code may change.
+    pub const READS_HEAP: Effects = Effects(1 << 2);
+    /// Changes the contents of a list or cell.
+    pub const WRITES_HEAP: Effects = Effects(1 << 3);
+    /// Calls a function, which may have any other effect.
+    pub const CALLS: Effects = Effects(1 << 4);
+    /// Allocates a new object on the heap.
+    pub const ALLOCATES: Effects = Effects(1 << 5);
+    /// May fail, such as on operands of the wrong type.
+    pub const MAY_FAIL: Effects = Effects(1 << 6);
+    /// Transfers control somewhere other than the next instruction.
+    pub const CONTROL: Effects = Effects(1 << 7);
+
+    #[must_use]
+    pub fn contains(self, other: Effects) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    #[must_use]
+    pub fn intersects(self, other: Effects) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    /// Returns true if the instruction has no effects at all.
+    #[must_use]
+    pub fn is_pure(self) -> bool {
+        self == Effects::NONE
+    }
+
+    /// Returns true if the instruction can be removed when the values it
+    /// pushes are never used. Reads and fresh allocations cannot be observed
+    /// once their results are dropped.
+    #[must_use]
+    pub fn is_eliminable(self) -> bool {
+        !self.intersects(
+            Effects::WRITES_GLOBALS
+                | Effects::WRITES_HEAP
+                | Effects::CALLS
+                | Effects::MAY_FAIL
+                | Effects::CONTROL,
+        )
+    }
+
+    /// Returns true if two instructions with these effects can be swapped,
+    /// provided their stack operands allow it.
+    #[must_use]
+    pub fn commutes_with(self, other: Effects) -> bool {
+        let fixed = Effects::CALLS | Effects::MAY_FAIL | Effects::CONTROL;
+        let conflicts = |a: Effects, b: Effects| {
+            (a.contains(Effects::WRITES_GLOBALS)
+                && b.intersects(Effects::READS_GLOBALS | Effects::WRITES_GLOBALS))
+                || (a.contains(Effects::WRITES_HEAP)
+                    && b.intersects(Effects::READS_HEAP | Effects::WRITES_HEAP))
+        };
+        !self.intersects(fixed)
+            && !other.intersects(fixed)
+            && !conflicts(self, other)
+            && !conflicts(other, self)
+    }
+}
+
+impl BitOr for Effects {
+    type Output = Effects;
+
+    fn bitor(self, rhs: Effects) -> Effects {
+        Effects(self.0 | rhs.0)
+    }
+}
+
+impl Instruction {
+    /// Returns the effects this instruction may have.
+    #[must_use]
+    pub fn effects(&self) -> Effects {
+        match self {
+            Instruction::PushConst(_)
+            | Instruction::PushCopy(_)
+            | Instruction::WriteStack(_)
+            | Instruction::Pop(_)
+            | Instruction::IsNull
+            | Instruction::IdentityHash => Effects::NONE,
+            Instruction::PushGlobal(_) => Effects::READS_GLOBALS | Effects::MAY_FAIL,
+            Instruction::PopGlobal(_) => Effects::WRITES_GLOBALS,
+            Instruction::Add
+            | Instruction::Sub
+            | Instruction::Mul
+            | Instruction::Div
+            | Instruction::ToNumber(_)
+            | Instruction::BoolAnd
+            | Instruction::BoolOr
+            | Instruction::BoolXor
+            | Instruction::BoolNot
+            | Instruction::Compare(_)
+            | Instruction::ToBool(_) => Effects::MAY_FAIL,
+            Instruction::Branch(_) => Effects::CONTROL,
+            Instruction::BranchIf(_)
+            | Instruction::BranchIfTruthy(_, _)
+            | Instruction::CmpBranch(_, _) => Effects::CONTROL | Effects::MAY_FAIL,
+            Instruction::Call(_)
+            | Instruction::TailCall(_)
+            | Instruction::CallDynamic
+            | Instruction::Apply => Effects::CALLS,
+            Instruction::CallProtected(_, _) => Effects::CALLS | Effects::CONTROL,
+            Instruction::Raise => Effects::CONTROL | Effects::MAY_FAIL,
+            Instruction::Return(_) | Instruction::ReturnDynamic => Effects::CONTROL,
+            Instruction::ListNew | Instruction::CellNew => Effects::ALLOCATES,
+            Instruction::WeakNew
+            | Instruction::RecordNew(_)
+            | Instruction::TagNew(_)
+            | Instruction::TagWrap => Effects::ALLOCATES | Effects::MAY_FAIL,
+            Instruction::TagOf => Effects::READS_HEAP,
+            Instruction::Untag => Effects::READS_HEAP | Effects::MAY_FAIL,
+            Instruction::ListLen
+            | Instruction::ListGet
+            | Instruction::ListGetOrNull
+            | Instruction::ListGetRel
+            | Instruction::CellGet
+            | Instruction::WeakGet
+            | Instruction::RecordGet(_) => Effects::READS_HEAP | Effects::MAY_FAIL,
+            Instruction::ListAppend
+            | Instruction::ListSet
+            | Instruction::ListSetRel
+            | Instruction::CellSet
+            | Instruction::RecordSet(_) => Effects::WRITES_HEAP | Effects::MAY_FAIL,
+            Instruction::ListSlice => Effects::READS_HEAP | Effects::ALLOCATES | Effects::MAY_FAIL,
+            Instruction::StrLenBytes | Instruction::StrLenChars => Effects::MAY_FAIL,
+            Instruction::StrSliceBytes | Instruction::StrSliceChars => {
+                Effects::ALLOCATES | Effects::MAY_FAIL
+            }
+            Instruction::BindFront(_) => Effects::ALLOCATES | Effects::MAY_FAIL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{
+        indexes::{GlobalIndex, LocalConstIndex},
+        instructions::CallInstruction,
+    };
+
+    #[test]
+    fn effects_decide_elimination_and_reordering() {
+        assert!(Instruction::PushConst(LocalConstIndex::new(0))
+            .effects()
+            .is_pure());
+        assert!(Instruction::ListNew.effects().is_eliminable());
+        assert!(Instruction::CellGet.effects().contains(Effects::READS_HEAP));
+        assert!(!Instruction::PushGlobal(GlobalIndex::new(0))
+            .effects()
+            .is_eliminable());
+        assert!(!Instruction::Add.effects().is_eliminable());
+        let call = Instruction::Call(CallInstruction {
+            num_args: 0,
+            num_returns: 0,
+        });
+        assert!(!call.effects().is_eliminable());
+
+        let read = Effects::READS_HEAP | Effects::READS_GLOBALS;
+        assert!(read.commutes_with(Effects::ALLOCATES));
+        assert!(!read.commutes_with(Effects::WRITES_HEAP));
+        assert!(!Effects::WRITES_GLOBALS.commutes_with(Effects::READS_GLOBALS));
+        assert!(Effects::WRITES_GLOBALS.commutes_with(Effects::WRITES_HEAP));
+        assert!(!Effects::NONE.commutes_with(call.effects()));
+    }
+}
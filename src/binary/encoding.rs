@@ -0,0 +1,549 @@
+//! A compact byte encoding for instruction streams.
+//!
+//! Each instruction is written as a one-byte opcode followed by its operands.
+//! Operands are unsigned LEB128 varints, so the small indexes that make up
+//! most generated code take a single byte each.
+//!
+//! Operands are read as varints of up to 64 bits, though this version only
+//! supports `u32` operands, so that wider operands can be introduced without
+//! changing the encoding. Until then, they fail to decode with
+//! [`DecodeError::IndexOutOfRange`], and longer varints are malformed.
+
+use super::{
+    error::DecodeError,
+    indexes::{GlobalIndex, LocalConstIndex},
+    instructions::{
+        BranchTarget, CallInstruction, CompareOp, Instruction, InstructionList, NumericKind,
+        StackIndex, Truthiness,
+    },
+};
+
+type Result<T> = std::result::Result<T, DecodeError>;
+
+mod opcodes {
+    pub const PUSH_CONST: u8 = 0x00;
+    pub const PUSH_COPY: u8 = 0x01;
+    pub const PUSH_GLOBAL: u8 = 0x02;
+    pub const POP_GLOBAL: u8 = 0x03;
+    pub const WRITE_STACK: u8 = 0x04;
+    pub const POP: u8 = 0x05;
+    pub const ADD: u8 = 0x10;
+    pub const SUB: u8 = 0x11;
+    pub const MUL: u8 = 0x12;
+    pub const DIV: u8 = 0x13;
+    pub const BOOL_AND: u8 = 0x18;
+    pub const BOOL_OR: u8 = 0x19;
+    pub const BOOL_XOR: u8 = 0x1a;
+    pub const BOOL_NOT: u8 = 0x1b;
+    pub const LIST_NEW: u8 = 0x20;
+    pub const LIST_APPEND: u8 = 0x21;
+    pub const LIST_LEN: u8 = 0x22;
+    pub const LIST_GET: u8 = 0x23;
+    pub const LIST_SET: u8 = 0x24;
+    pub const LIST_GET_REL: u8 = 0x25;
+    pub const LIST_SET_REL: u8 = 0x26;
+    pub const LIST_SLICE: u8 = 0x27;
+    pub const CELL_NEW: u8 = 0x28;
+    pub const CELL_GET: u8 = 0x29;
+    pub const CELL_SET: u8 = 0x2a;
+    pub const LIST_GET_OR_NULL: u8 = 0x2b;
+    pub const STR_LEN_BYTES: u8 = 0x2c;
+    pub const STR_LEN_CHARS: u8 = 0x2d;
+    pub const STR_SLICE_BYTES: u8 = 0x2e;
+    pub const STR_SLICE_CHARS: u8 = 0x2f;
+    pub const COMPARE: u8 = 0x30;
+    pub const IS_NULL: u8 = 0x31;
+    pub const IDENTITY_HASH: u8 = 0x32;
+    pub const TO_BOOL: u8 = 0x33;
+    pub const TO_NUMBER: u8 = 0x34;
+    pub const BRANCH: u8 = 0x38;
+    pub const BRANCH_IF: u8 = 0x39;
+    pub const BRANCH_IF_TRUTHY: u8 = 0x3a;
+    pub const CMP_BRANCH: u8 = 0x3b;
+    pub const CALL: u8 = 0x40;
+    pub const CALL_DYNAMIC: u8 = 0x41;
+    pub const RETURN: u8 = 0x42;
+    pub const RETURN_DYNAMIC: u8 = 0x43;
+    pub const TAIL_CALL: u8 = 0x44;
+    pub const BIND_FRONT: u8 = 0x45;
+    pub const APPLY: u8 = 0x46;
+    pub const CALL_PROTECTED: u8 = 0x4a;
+    pub const RAISE: u8 = 0x4b;
+    pub const WEAK_NEW: u8 = 0x48;
+    pub const WEAK_GET: u8 = 0x49;
+    pub const RECORD_NEW: u8 = 0x50;
+    pub const RECORD_GET: u8 = 0x51;
+    pub const RECORD_SET: u8 = 0x52;
+    pub const TAG_NEW: u8 = 0x54;
+    pub const TAG_WRAP: u8 = 0x55;
+    pub const TAG_OF: u8 = 0x56;
+    pub const UNTAG: u8 = 0x57;
+}
+
+pub(super) fn write_varint(out: &mut Vec<u8>, value: u32) {
+    write_varint_u64(out, u64::from(value));
+}
+
+pub(super) fn write_varint_u64(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Stack indexes are written as a single varint, with the end of the stack
+/// they count from in the low bit.
+fn write_stack_index(out: &mut Vec<u8>, index: StackIndex) {
+    let (index, from_bottom) = match index {
+        StackIndex::FromTop(i) => (u64::from(i), 0),
+        StackIndex::FromBottom(i) => (u64::from(i), 1),
+    };
+    write_varint_u64(out, index << 1 | from_bottom);
+}
+
+fn compare_op_code(op: CompareOp) -> u8 {
+    match op {
+        CompareOp::RefEq => 0,
+        CompareOp::Eq => 1,
+        CompareOp::Ne => 2,
+        CompareOp::Lt => 3,
+        CompareOp::Le => 4,
+        CompareOp::Gt => 5,
+        CompareOp::Ge => 6,
+    }
+}
+
+fn numeric_kind_code(kind: NumericKind) -> u8 {
+    match kind {
+        NumericKind::Integer => 0,
+        NumericKind::Rational => 1,
+        NumericKind::Float => 2,
+    }
+}
+
+fn truthiness_code(truthiness: Truthiness) -> u8 {
+    match truthiness {
+        Truthiness::Strict => 0,
+        Truthiness::NullAndFalse => 1,
+        Truthiness::Empty => 2,
+    }
+}
+
+pub(super) struct Reader<'a> {
+    pub(super) bytes: &'a [u8],
+    pub(super) pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(super) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, pos: 0 }
+    }
+
+    pub(super) fn read_byte(&mut self) -> Result<u8> {
+        let byte = *self.bytes.get(self.pos).ok_or(DecodeError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    pub(super) fn read_varint_u64(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::VarintOverflow(self.pos))
+    }
+
+    pub(super) fn read_varint(&mut self) -> Result<u32> {
+        let pos = self.pos;
+        let value = self.read_varint_u64()?;
+        u32::try_from(value).map_err(|_| DecodeError::IndexOutOfRange(value, pos))
+    }
+
+    fn read_stack_index(&mut self) -> Result<StackIndex> {
+        let pos = self.pos;
+        let value = self.read_varint_u64()?;
+        let index =
+            u32::try_from(value >> 1).map_err(|_| DecodeError::IndexOutOfRange(value >> 1, pos))?;
+        Ok(if value & 1 == 0 {
+            StackIndex::FromTop(index)
+        } else {
+            StackIndex::FromBottom(index)
+        })
+    }
+
+    fn read_compare_op(&mut self) -> Result<CompareOp> {
+        let pos = self.pos;
+        Ok(match self.read_byte()? {
+            0 => CompareOp::RefEq,
+            1 => CompareOp::Eq,
+            2 => CompareOp::Ne,
+            3 => CompareOp::Lt,
+            4 => CompareOp::Le,
+            5 => CompareOp::Gt,
+            6 => CompareOp::Ge,
+            code => return Err(DecodeError::UnknownCompareOp(code, pos)),
+        })
+    }
+
+    fn read_truthiness(&mut self) -> Result<Truthiness> {
+        let pos = self.pos;
+        Ok(match self.read_byte()? {
+            0 => Truthiness::Strict,
+            1 => Truthiness::NullAndFalse,
+            2 => Truthiness::Empty,
+            code => return Err(DecodeError::UnknownTruthiness(code, pos)),
+        })
+    }
+
+    fn read_numeric_kind(&mut self) -> Result<NumericKind> {
+        let pos = self.pos;
+        Ok(match self.read_byte()? {
+            0 => NumericKind::Integer,
+            1 => NumericKind::Rational,
+            2 => NumericKind::Float,
+            code => return Err(DecodeError::UnknownNumericKind(code, pos)),
+        })
+    }
+
+    fn read_instruction(&mut self) -> Result<Instruction> {
+        use opcodes::*;
+        let pos = self.pos;
+        Ok(match self.read_byte()? {
+            PUSH_CONST => Instruction::PushConst(LocalConstIndex::new(self.read_varint()?)),
+            PUSH_COPY => Instruction::PushCopy(self.read_stack_index()?),
+            PUSH_GLOBAL => Instruction::PushGlobal(GlobalIndex::new(self.read_varint()?)),
+            POP_GLOBAL => Instruction::PopGlobal(GlobalIndex::new(self.read_varint()?)),
+            WRITE_STACK => Instruction::WriteStack(self.read_stack_index()?),
+            POP => Instruction::Pop(self.read_varint()?),
+            ADD => Instruction::Add,
+            SUB => Instruction::Sub,
+            MUL => Instruction::Mul,
+            DIV => Instruction::Div,
+            BOOL_AND => Instruction::BoolAnd,
+            BOOL_OR => Instruction::BoolOr,
+            BOOL_XOR => Instruction::BoolXor,
+            BOOL_NOT => Instruction::BoolNot,
+            LIST_NEW => Instruction::ListNew,
+            LIST_APPEND => Instruction::ListAppend,
+            LIST_LEN => Instruction::ListLen,
+            LIST_GET => Instruction::ListGet,
+            LIST_GET_OR_NULL => Instruction::ListGetOrNull,
+            LIST_SET => Instruction::ListSet,
+            LIST_GET_REL => Instruction::ListGetRel,
+            LIST_SET_REL => Instruction::ListSetRel,
+            LIST_SLICE => Instruction::ListSlice,
+            CELL_NEW => Instruction::CellNew,
+            CELL_GET => Instruction::CellGet,
+            CELL_SET => Instruction::CellSet,
+            WEAK_NEW => Instruction::WeakNew,
+            WEAK_GET => Instruction::WeakGet,
+            RECORD_NEW => Instruction::RecordNew(LocalConstIndex::new(self.read_varint()?)),
+            RECORD_GET => Instruction::RecordGet(LocalConstIndex::new(self.read_varint()?)),
+            RECORD_SET => Instruction::RecordSet(LocalConstIndex::new(self.read_varint()?)),
+            TAG_NEW => Instruction::TagNew(LocalConstIndex::new(self.read_varint()?)),
+            TAG_WRAP => Instruction::TagWrap,
+            TAG_OF => Instruction::TagOf,
+            UNTAG => Instruction::Untag,
+            STR_LEN_BYTES => Instruction::StrLenBytes,
+            STR_LEN_CHARS => Instruction::StrLenChars,
+            STR_SLICE_BYTES => Instruction::StrSliceBytes,
+            STR_SLICE_CHARS => Instruction::StrSliceChars,
+            COMPARE => Instruction::Compare(self.read_compare_op()?),
+            IS_NULL => Instruction::IsNull,
+            IDENTITY_HASH => Instruction::IdentityHash,
+            TO_BOOL => Instruction::ToBool(self.read_truthiness()?),
+            TO_NUMBER => Instruction::ToNumber(self.read_numeric_kind()?),
+            BRANCH => Instruction::Branch(BranchTarget::new(self.read_varint()?)),
+            BRANCH_IF => Instruction::BranchIf(BranchTarget::new(self.read_varint()?)),
+            BRANCH_IF_TRUTHY => Instruction::BranchIfTruthy(
+                BranchTarget::new(self.read_varint()?),
+                self.read_truthiness()?,
+            ),
+            CMP_BRANCH => {
+                let target = BranchTarget::new(self.read_varint()?);
+                Instruction::CmpBranch(self.read_compare_op()?, target)
+            }
+            CALL => Instruction::Call(CallInstruction {
+                num_args: self.read_varint()?,
+                num_returns: self.read_varint()?,
+            }),
+            CALL_DYNAMIC => Instruction::CallDynamic,
+            APPLY => Instruction::Apply,
+            CALL_PROTECTED => Instruction::CallProtected(
+                CallInstruction {
+                    num_args: self.read_varint()?,
+                    num_returns: self.read_varint()?,
+                },
+                BranchTarget::new(self.read_varint()?),
+            ),
+            RAISE => Instruction::Raise,
+            RETURN => Instruction::Return(self.read_varint()?),
+            RETURN_DYNAMIC => Instruction::ReturnDynamic,
+            TAIL_CALL => Instruction::TailCall(self.read_varint()?),
+            BIND_FRONT => Instruction::BindFront(self.read_varint()?),
+            opcode => return Err(DecodeError::UnknownOpcode(opcode, pos)),
+        })
+    }
+}
+
+impl InstructionList {
+    /// Encodes the instructions into the compact byte format.
+    #[must_use]
+    pub fn encode(&self) -> Vec<u8> {
+        use opcodes::*;
+        let mut out = Vec::new();
+        for inst in self.instructions() {
+            match inst {
+                Instruction::PushConst(i) => {
+                    out.push(PUSH_CONST);
+                    write_varint(&mut out, i.index());
+                }
+                Instruction::PushCopy(i) => {
+                    out.push(PUSH_COPY);
+                    write_stack_index(&mut out, *i);
+                }
+                Instruction::PushGlobal(i) => {
+                    out.push(PUSH_GLOBAL);
+                    write_varint(&mut out, i.index());
+                }
+                Instruction::PopGlobal(i) => {
+                    out.push(POP_GLOBAL);
+                    write_varint(&mut out, i.index());
+                }
+                Instruction::WriteStack(i) => {
+                    out.push(WRITE_STACK);
+                    write_stack_index(&mut out, *i);
+                }
+                Instruction::Pop(n) => {
+                    out.push(POP);
+                    write_varint(&mut out, *n);
+                }
+                Instruction::Add => out.push(ADD),
+                Instruction::Sub => out.push(SUB),
+                Instruction::Mul => out.push(MUL),
+                Instruction::Div => out.push(DIV),
+                Instruction::BoolAnd => out.push(BOOL_AND),
+                Instruction::BoolOr => out.push(BOOL_OR),
+                Instruction::BoolXor => out.push(BOOL_XOR),
+                Instruction::BoolNot => out.push(BOOL_NOT),
+                Instruction::ListNew => out.push(LIST_NEW),
+                Instruction::ListAppend => out.push(LIST_APPEND),
+                Instruction::ListLen => out.push(LIST_LEN),
+                Instruction::ListGet => out.push(LIST_GET),
+                Instruction::ListGetOrNull => out.push(LIST_GET_OR_NULL),
+                Instruction::ListSet => out.push(LIST_SET),
+                Instruction::ListGetRel => out.push(LIST_GET_REL),
+                Instruction::ListSetRel => out.push(LIST_SET_REL),
+                Instruction::ListSlice => out.push(LIST_SLICE),
+                Instruction::CellNew => out.push(CELL_NEW),
+                Instruction::CellGet => out.push(CELL_GET),
+                Instruction::CellSet => out.push(CELL_SET),
+                Instruction::WeakNew => out.push(WEAK_NEW),
+                Instruction::WeakGet => out.push(WEAK_GET),
+                Instruction::RecordNew(i) => {
+                    out.push(RECORD_NEW);
+                    write_varint(&mut out, i.index());
+                }
+                Instruction::RecordGet(i) => {
+                    out.push(RECORD_GET);
+                    write_varint(&mut out, i.index());
+                }
+                Instruction::RecordSet(i) => {
+                    out.push(RECORD_SET);
+                    write_varint(&mut out, i.index());
+                }
+                Instruction::TagNew(i) => {
+                    out.push(TAG_NEW);
+                    write_varint(&mut out, i.index());
+                }
+                Instruction::TagWrap => out.push(TAG_WRAP),
+                Instruction::TagOf => out.push(TAG_OF),
+                Instruction::Untag => out.push(UNTAG),
+                Instruction::StrLenBytes => out.push(STR_LEN_BYTES),
+                Instruction::StrLenChars => out.push(STR_LEN_CHARS),
+                Instruction::StrSliceBytes => out.push(STR_SLICE_BYTES),
+                Instruction::StrSliceChars => out.push(STR_SLICE_CHARS),
+                Instruction::Compare(op) => {
+                    out.push(COMPARE);
+                    out.push(compare_op_code(*op));
+                }
+                Instruction::IsNull => out.push(IS_NULL),
+                Instruction::IdentityHash => out.push(IDENTITY_HASH),
+                Instruction::ToBool(truthiness) => {
+                    out.push(TO_BOOL);
+                    out.push(truthiness_code(*truthiness));
+                }
+                Instruction::ToNumber(kind) => {
+                    out.push(TO_NUMBER);
+                    out.push(numeric_kind_code(*kind));
+                }
+                Instruction::Branch(target) => {
+                    out.push(BRANCH);
+                    write_varint(&mut out, target.target_index());
+                }
+                Instruction::BranchIf(target) => {
+                    out.push(BRANCH_IF);
+                    write_varint(&mut out, target.target_index());
+                }
+                Instruction::BranchIfTruthy(target, truthiness) => {
+                    out.push(BRANCH_IF_TRUTHY);
+                    write_varint(&mut out, target.target_index());
+                    out.push(truthiness_code(*truthiness));
+                }
+                Instruction::CmpBranch(op, target) => {
+                    out.push(CMP_BRANCH);
+                    write_varint(&mut out, target.target_index());
+                    out.push(compare_op_code(*op));
+                }
+                Instruction::Call(call) => {
+                    out.push(CALL);
+                    write_varint(&mut out, call.num_args);
+                    write_varint(&mut out, call.num_returns);
+                }
+                Instruction::CallDynamic => out.push(CALL_DYNAMIC),
+                Instruction::Apply => out.push(APPLY),
+                Instruction::CallProtected(call, target) => {
+                    out.push(CALL_PROTECTED);
+                    write_varint(&mut out, call.num_args);
+                    write_varint(&mut out, call.num_returns);
+                    write_varint(&mut out, target.target_index());
+                }
+                Instruction::Raise => out.push(RAISE),
+                Instruction::Return(n) => {
+                    out.push(RETURN);
+                    write_varint(&mut out, *n);
+                }
+                Instruction::ReturnDynamic => out.push(RETURN_DYNAMIC),
+                Instruction::TailCall(n) => {
+                    out.push(TAIL_CALL);
+                    write_varint(&mut out, *n);
+                }
+                Instruction::BindFront(n) => {
+                    out.push(BIND_FRONT);
+                    write_varint(&mut out, *n);
+                }
+            }
+        }
+        out
+    }
+
+    /// Decodes instructions written by [`InstructionList::encode`].
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+        let mut instructions = Vec::new();
+        while reader.pos < bytes.len() {
+            instructions.push(reader.read_instruction()?);
+        }
+        Ok(InstructionList::new(instructions))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> InstructionList {
+        InstructionList::new(vec![
+            Instruction::PushConst(LocalConstIndex::new(0)),
+            Instruction::PushCopy(StackIndex::FromBottom(3)),
+            Instruction::PopGlobal(GlobalIndex::new(130)),
+            Instruction::PushCopy(StackIndex::FromTop(u32::MAX)),
+            Instruction::Add,
+            Instruction::Compare(CompareOp::Ge),
+            Instruction::BranchIf(BranchTarget::new(300)),
+            Instruction::BranchIfTruthy(BranchTarget::new(2), Truthiness::NullAndFalse),
+            Instruction::CmpBranch(CompareOp::Lt, BranchTarget::new(1)),
+            Instruction::ToBool(Truthiness::Empty),
+            Instruction::Div,
+            Instruction::ToNumber(NumericKind::Rational),
+            Instruction::Call(CallInstruction {
+                num_args: 2,
+                num_returns: 1,
+            }),
+            Instruction::CellNew,
+            Instruction::WeakNew,
+            Instruction::WeakGet,
+            Instruction::RecordNew(LocalConstIndex::new(1)),
+            Instruction::RecordGet(LocalConstIndex::new(2)),
+            Instruction::RecordSet(LocalConstIndex::new(200)),
+            Instruction::TagNew(LocalConstIndex::new(3)),
+            Instruction::TagWrap,
+            Instruction::TagOf,
+            Instruction::Untag,
+            Instruction::CallProtected(
+                CallInstruction {
+                    num_args: 1,
+                    num_returns: 1,
+                },
+                BranchTarget::new(2),
+            ),
+            Instruction::Raise,
+            Instruction::ListGetOrNull,
+            Instruction::StrSliceChars,
+            Instruction::Return(1),
+        ])
+    }
+
+    #[test]
+    fn instructions_round_trip() -> anyhow::Result<()> {
+        let list = sample();
+        let decoded = InstructionList::decode(&list.encode())?;
+        assert_eq!(
+            format!("{:?}", decoded.instructions()),
+            format!("{:?}", list.instructions())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn small_operands_take_one_byte() {
+        let list = InstructionList::new(vec![
+            Instruction::PushConst(LocalConstIndex::new(5)),
+            Instruction::PushCopy(StackIndex::FromTop(1)),
+            Instruction::Return(1),
+        ]);
+        assert_eq!(list.encode().len(), 6);
+    }
+
+    #[test]
+    fn malformed_input_is_rejected() {
+        let encoded = sample().encode();
+        assert!(matches!(
+            InstructionList::decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            InstructionList::decode(&[0xff]),
+            Err(DecodeError::UnknownOpcode(0xff, 0))
+        ));
+        assert!(matches!(
+            InstructionList::decode(&[opcodes::POP, 0xff, 0xff, 0xff, 0xff, 0x7f]),
+            Err(DecodeError::IndexOutOfRange(0x7_ffff_ffff, 1))
+        ));
+        assert!(matches!(
+            InstructionList::decode(&[
+                opcodes::POP,
+                0xff,
+                0xff,
+                0xff,
+                0xff,
+                0xff,
+                0xff,
+                0xff,
+                0xff,
+                0xff,
+                0xff
+            ]),
+            Err(DecodeError::VarintOverflow(11))
+        ));
+    }
+}
@@ -1,19 +1,52 @@
+//! Errors produced while building and validating modules.
+
+use super::{
+    const_table::ConstIndex,
+    indexes::{ModuleConstIndex, MAX_TABLE_LEN},
+    inst_policy::InstructionFamily,
+};
+
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
 pub enum BuilderError {
     #[error("Value already exists.")]
     AlreadyExists,
 
+    /// The module already has an export with the given name.
+    #[error("Export {0:?} already exists.")]
+    DuplicateExport(String),
+
+    /// The module already has a test with the given name.
+    #[error("Test {0:?} already exists.")]
+    DuplicateTest(String),
+
+    /// A record was given the same field more than once.
+    #[error("Record field {0:?} is given more than once.")]
+    DuplicateRecordField(String),
+
+    /// Documentation was attached to a name the module does not export.
+    #[error("Export {0:?} does not exist.")]
+    UnknownExport(String),
+
     #[error("Expected a module const.")]
     ExpectedModuleConst,
 
     #[error("Mismatched builder.")]
     MismatchedBuilder,
 
+    /// Two fragments of different modules were merged.
+    #[error("Cannot merge module {other} into module {module}.")]
+    MismatchedModule { module: String, other: String },
+
+    /// Two merged fragments have initializers that take different numbers of
+    /// arguments. An initializer without a declared arity takes none.
+    #[error("Cannot merge initializers taking {first} and {second} arguments.")]
+    MismatchedInitializers { first: u32, second: u32 },
+
     #[error("Deferred value not resolved.")]
     DeferredNotResolved,
 
-    #[error(transparent)]
+    #[error("Module failed validation.")]
     Validation(#[from] ValidationError),
 
     #[error("Reference was unresolved.")]
@@ -25,6 +58,34 @@ pub enum BuilderError {
     #[error("Expected a global value.")]
     ExpectedGlobal,
 
+    /// A value was defined as an alias of itself, directly or through other
+    /// aliases. Only lists and functions may refer back to themselves.
+    #[error("Value is defined in terms of itself.")]
+    CyclicDefinition,
+
+    /// An [`IrFunction`](super::IrFunction) used a register it did not
+    /// create.
+    #[error("Register {0} does not exist.")]
+    UnknownRegister(u32),
+
+    /// An [`IrFunction`](super::IrFunction) jumped to a block it did not
+    /// create.
+    #[error("Block {0} does not exist.")]
+    UnknownBlock(u32),
+
+    /// A block of an [`IrFunction`](super::IrFunction) has no jump, branch,
+    /// return or tail call at its end.
+    #[error("Block {0} is not terminated.")]
+    UnterminatedBlock(u32),
+
+    /// An IR operation was given the wrong number of operands.
+    #[error("Operation takes {expected} operands, but {actual} were given.")]
+    IrOperandCount { expected: usize, actual: usize },
+
+    /// A table grew past the largest index that can be encoded.
+    #[error("Too many entries for a table index.")]
+    IndexOverflow,
+
     #[error(transparent)]
     Other(Box<dyn std::error::Error + Send + Sync>),
 }
@@ -36,12 +97,242 @@ impl BuilderError {
     {
         BuilderError::Other(Box::new(error))
     }
+
+    /// Returns the name of the export that caused this error, if any.
+    #[must_use]
+    pub fn member_name(&self) -> Option<&str> {
+        match self {
+            BuilderError::DuplicateExport(name) | BuilderError::UnknownExport(name) => {
+                Some(name.as_str())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the validation failure that caused this error, if any.
+    #[must_use]
+    pub fn validation_error(&self) -> Option<&ValidationError> {
+        match self {
+            BuilderError::Validation(error) => Some(error),
+            _ => None,
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
 pub enum ValidationError {
-    #[error("Found an invalid constant index")]
-    LocalIndexResolutionError,
+    /// The constant at `table_index` refers to a constant or import that does
+    /// not exist.
+    #[error("Constant {table_index} refers to invalid index {index:?}.")]
+    LocalIndexResolutionError {
+        table_index: ModuleConstIndex,
+        index: ConstIndex,
+    },
+
+    /// The instruction at `pc` in the function at `table_index` refers to a
+    /// function constant or module global that does not exist.
+    #[error("Function at constant {table_index} has an invalid operand at pc {pc}.")]
+    InvalidOperand {
+        table_index: ModuleConstIndex,
+        pc: u32,
+    },
+
+    /// The branch at `pc` in the function at `table_index` jumps to
+    /// `target`, which is past its last instruction.
+    #[error("Function at constant {table_index} branches to {target} at pc {pc}, past its end.")]
+    InvalidBranchTarget {
+        table_index: ModuleConstIndex,
+        pc: u32,
+        target: u32,
+    },
+
+    /// The named export refers to a constant that does not exist.
+    #[error("Export {export_name:?} refers to invalid constant {index}.")]
+    InvalidExport {
+        export_name: String,
+        index: ModuleConstIndex,
+    },
+
+    /// The initializer refers to a constant that does not exist.
+    #[error("Initializer refers to invalid constant {0}.")]
+    InvalidInitializer(ModuleConstIndex),
+
+    /// The named test refers to a constant that does not exist.
+    #[error("Test {test_name:?} refers to invalid constant {index}.")]
+    InvalidTest {
+        test_name: String,
+        index: ModuleConstIndex,
+    },
+
+    /// The function at `table_index` contains an instruction that the
+    /// module's [`InstructionPolicy`](super::InstructionPolicy) denies. `pc`
+    /// is the index of the instruction in the function.
+    #[error(
+        "Function {} uses a denied {family:?} instruction at pc {pc}.",
+        function_name(*.table_index, .export_name.as_deref())
+    )]
+    DeniedInstruction {
+        table_index: ModuleConstIndex,
+        /// The name the function is exported under, if it is exported.
+        export_name: Option<String>,
+        pc: u32,
+        family: InstructionFamily,
+    },
+
+    /// The `BindFront` at `pc` in the function at `table_index` binds more
+    /// values than the function at `target_index` declares arguments.
+    #[error(
+        "Function at constant {table_index} binds {bound} values at pc {pc} to the function at \
+         constant {target_index}, which takes {arity} arguments."
+    )]
+    TooManyBoundArguments {
+        table_index: ModuleConstIndex,
+        pc: u32,
+        target_index: ModuleConstIndex,
+        arity: u32,
+        bound: u32,
+    },
+
+    /// The record at `table_index` declares the field `field` more than once.
+    #[error("Record at constant {table_index} declares field {field:?} more than once.")]
+    DuplicateRecordField {
+        table_index: ModuleConstIndex,
+        field: String,
+    },
+
+    /// A table has more than [`MAX_TABLE_LEN`] entries. `table` names it,
+    /// e.g. `"constant"`, and `table_index` is the function it belongs to, if
+    /// it is a function's constants or instructions.
+    #[error("The {table} table has {len} entries, more than the limit of {MAX_TABLE_LEN}.")]
+    TableTooLarge {
+        table: &'static str,
+        table_index: Option<ModuleConstIndex>,
+        len: usize,
+    },
+}
+
+fn function_name(table_index: ModuleConstIndex, export_name: Option<&str>) -> String {
+    match export_name {
+        Some(name) => format!("{name:?} (constant {table_index})"),
+        None => format!("at constant {table_index}"),
+    }
+}
+
+impl ValidationError {
+    /// Returns the index of the constant table entry that failed validation.
+    #[must_use]
+    pub fn table_index(&self) -> Option<ModuleConstIndex> {
+        match self {
+            ValidationError::LocalIndexResolutionError { table_index, .. }
+            | ValidationError::InvalidOperand { table_index, .. }
+            | ValidationError::InvalidBranchTarget { table_index, .. }
+            | ValidationError::DeniedInstruction { table_index, .. }
+            | ValidationError::TooManyBoundArguments { table_index, .. }
+            | ValidationError::DuplicateRecordField { table_index, .. } => Some(*table_index),
+            ValidationError::TableTooLarge { table_index, .. } => *table_index,
+            ValidationError::InvalidExport { .. }
+            | ValidationError::InvalidInitializer(_)
+            | ValidationError::InvalidTest { .. } => None,
+        }
+    }
+
+    /// Returns the invalid index that the constant refers to.
+    #[must_use]
+    pub fn const_index(&self) -> Option<&ConstIndex> {
+        match self {
+            ValidationError::LocalIndexResolutionError { index, .. } => Some(index),
+            _ => None,
+        }
+    }
+}
+
+/// An error assembling a [`Program`](super::Program) from a module set and
+/// an entry point.
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum ProgramError {
+    #[error("Entry module {0} is not in the program's module set.")]
+    EntryModuleMissing(String),
+
+    #[error("Entry point {name:?} is not exported by module {module}.")]
+    EntryNotExported { module: String, name: String },
 }
 
 pub type Result<T> = std::result::Result<T, BuilderError>;
+
+/// An error decoding an encoded instruction stream or module. Positions are
+/// byte offsets into the input.
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum DecodeError {
+    #[error("Unexpected end of input.")]
+    UnexpectedEnd,
+
+    #[error("Unknown opcode {0:#04x} at offset {1}.")]
+    UnknownOpcode(u8, usize),
+
+    #[error("Unknown comparison {0} at offset {1}.")]
+    UnknownCompareOp(u8, usize),
+
+    #[error("Unknown truthiness rule {0} at offset {1}.")]
+    UnknownTruthiness(u8, usize),
+
+    #[error("Unknown numeric kind {0} at offset {1}.")]
+    UnknownNumericKind(u8, usize),
+
+    #[error("Integer at offset {0} is too large.")]
+    VarintOverflow(usize),
+
+    /// A well-formed varint, such as an index or count, exceeds the `u32`
+    /// range this version supports. Inputs written with wider indexes decode
+    /// to this error rather than a malformed one.
+    #[error("Index {0} at offset {1} is beyond the supported limit.")]
+    IndexOutOfRange(u64, usize),
+
+    #[error("Input is not an encoded module.")]
+    BadMagic,
+
+    #[error("Unsupported module format version {0}.")]
+    UnsupportedVersion(u32),
+
+    #[error("Unknown constant kind {0} at offset {1}.")]
+    UnknownConstKind(u8, usize),
+
+    #[error("Unknown instruction stream codec {0} at offset {1}.")]
+    UnknownCodec(u8, usize),
+
+    /// The stream is compressed, and the crate was built without the
+    /// `compression` feature.
+    #[error("Compressed instruction stream at offset {0} is not supported.")]
+    UnsupportedCompression(usize),
+
+    #[error("Corrupt compressed instruction stream at offset {0}.")]
+    CorruptCompressedData(usize),
+
+    #[error("Invalid UTF-8 in string at offset {0}.")]
+    InvalidUtf8(usize),
+
+    #[error("Unexpected data after the end of the module at offset {0}.")]
+    TrailingData(usize),
+
+    #[error("Export {0:?} is listed more than once.")]
+    DuplicateExport(String),
+
+    #[error("Test {0:?} is listed more than once.")]
+    DuplicateTest(String),
+
+    #[error("Module {0} is listed more than once.")]
+    DuplicateModule(String),
+
+    #[error("The program's modules have cyclic dependencies.")]
+    CyclicDependencies,
+
+    /// The module was decoded, but is not valid.
+    #[error(transparent)]
+    Validation(#[from] ValidationError),
+
+    /// The program's modules were decoded, but do not form a valid program.
+    #[error(transparent)]
+    Program(#[from] ProgramError),
+}
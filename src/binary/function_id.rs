@@ -0,0 +1,68 @@
+//! Stable identifiers for functions.
+//!
+//! A [`FunctionId`] names a function by where it was defined rather than by
+//! its address, so it stays the same across runs that load the same modules
+//! and register the same host functions in the same order. Profiles, caller
+//! information and errors all report functions by their id.
+
+use super::{
+    indexes::ModuleConstIndex,
+    modules::{ModuleId, ModuleMemberId},
+};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FunctionId {
+    /// A managed function, identified by its index in the constant table of
+    /// the module that defines it.
+    Managed {
+        module_id: Option<ModuleId>,
+        const_index: ModuleConstIndex,
+    },
+
+    /// A function exported from a native module.
+    Native {
+        module_id: ModuleId,
+        name: ModuleMemberId,
+    },
+
+    /// A native function created directly by the host or the runtime,
+    /// numbered in order of creation within the runtime.
+    Host(u64),
+}
+
+impl std::fmt::Display for FunctionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FunctionId::Managed {
+                module_id: Some(module_id),
+                const_index,
+            } => write!(f, "{module_id}#{const_index}"),
+            FunctionId::Managed {
+                module_id: None,
+                const_index,
+            } => write!(f, "<unknown>#{const_index}"),
+            FunctionId::Native { module_id, name } => write!(f, "{module_id}:{}", name.as_str()),
+            FunctionId::Host(id) => write!(f, "<host>#{id}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_display_their_origin() {
+        let managed = FunctionId::Managed {
+            module_id: Some(ModuleId::new(["app", "main"])),
+            const_index: ModuleConstIndex::new(3),
+        };
+        let native = FunctionId::Native {
+            module_id: ModuleId::new(["std", "fn"]),
+            name: ModuleMemberId::new("memoize"),
+        };
+        assert_eq!(managed.to_string(), "app.main#3");
+        assert_eq!(native.to_string(), "std.fn:memoize");
+        assert_eq!(FunctionId::Host(7).to_string(), "<host>#7");
+    }
+}
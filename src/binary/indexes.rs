@@ -0,0 +1,118 @@
+//! Indexes into the tables of a module.
+//!
+//! Each table has its own index type, so that an index into one cannot be
+//! used for another by mistake. Indexes are created with `new`, or from a
+//! table position with `from_usize`, which fails if the position does not fit.
+//!
+//! Every table holds at most [`MAX_TABLE_LEN`] entries, so that both its
+//! length and its indexes fit in a `u32`. Builders fail with
+//! [`BuilderError::IndexOverflow`](super::BuilderError::IndexOverflow) rather
+//! than truncate a larger position, and validation rejects larger tables.
+
+use std::fmt;
+
+/// The most entries any table of a module may have: its constants, imports
+/// and globals, and the constants and instructions of each function.
+pub const MAX_TABLE_LEN: usize = u32::MAX as usize;
+
+macro_rules! index_type {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(u32);
+
+        impl $name {
+            #[must_use]
+            pub const fn new(index: u32) -> Self {
+                $name(index)
+            }
+
+            /// Returns the index of the entry at `position`, or `None` if it
+            /// is past the end of the largest table, [`MAX_TABLE_LEN`].
+            #[must_use]
+            pub fn from_usize(position: usize) -> Option<Self> {
+                if position < MAX_TABLE_LEN {
+                    u32::try_from(position).ok().map($name)
+                } else {
+                    None
+                }
+            }
+
+            #[must_use]
+            pub const fn index(self) -> u32 {
+                self.0
+            }
+
+            #[must_use]
+            pub fn as_usize(self) -> usize {
+                self.0 as usize
+            }
+
+            /// Returns true if this indexes a table with `len` entries.
+            #[must_use]
+            pub fn is_within(self, len: usize) -> bool {
+                self.as_usize() < len
+            }
+
+            /// Returns the entry of `table` at this index.
+            #[must_use]
+            pub fn get<T>(self, table: &[T]) -> Option<&T> {
+                table.get(self.as_usize())
+            }
+        }
+
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Debug::fmt(&self.0, f)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)
+            }
+        }
+    };
+}
+
+index_type!(
+    /// An index into the constants of a function, which are the operands of
+    /// its `PushConst` instructions.
+    LocalConstIndex
+);
+
+index_type!(
+    /// An index into the constant table of a module.
+    ModuleConstIndex
+);
+
+index_type!(
+    /// An index into the imports of a module.
+    ImportIndex
+);
+
+index_type!(
+    /// An index into the globals of a module.
+    GlobalIndex
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_are_checked() {
+        assert_eq!(GlobalIndex::from_usize(3), Some(GlobalIndex::new(3)));
+        assert_eq!(ModuleConstIndex::from_usize(u32::MAX as usize + 1), None);
+        // The last position of the largest table is the largest index.
+        assert_eq!(
+            ModuleConstIndex::from_usize(MAX_TABLE_LEN - 1),
+            Some(ModuleConstIndex::new(u32::MAX - 1))
+        );
+        assert_eq!(ImportIndex::from_usize(MAX_TABLE_LEN), None);
+        let table = ["a", "b"];
+        assert_eq!(ImportIndex::new(1).get(&table), Some(&"b"));
+        assert!(!LocalConstIndex::new(2).is_within(table.len()));
+        assert_eq!(format!("{:?}", LocalConstIndex::new(7)), "7");
+    }
+}
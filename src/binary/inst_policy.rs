@@ -0,0 +1,220 @@
+//! Restrictions on which instructions a module's functions may contain.
+//!
+//! Embedders that load untrusted modules can deny whole families of
+//! instructions, such as writes to globals or dynamic calls. Modules that use
+//! a denied instruction fail validation instead of loading.
+
+use super::instructions::Instruction;
+
+/// A group of related instructions that can be allowed or denied together.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum InstructionFamily {
+    /// `PushConst`, `PushCopy`, `WriteStack` and `Pop`.
+    Stack,
+    /// `PushGlobal`.
+    GlobalRead,
+    /// `PopGlobal`.
+    GlobalWrite,
+    /// `Add`, `Sub`, `Mul`, `Div` and `ToNumber`.
+    Arithmetic,
+    /// The boolean operations, `Compare`, `IsNull`, `IdentityHash` and
+    /// `ToBool`.
+    Logic,
+    /// `Branch`, `BranchIf`, `BranchIfTruthy` and `CmpBranch`.
+    Branch,
+    /// `Call` and `TailCall`, whose argument counts are fixed.
+    Call,
+    /// `CallDynamic` and `Apply`, whose argument counts are only known at run
+    /// time.
+    CallDynamic,
+    /// `Return` and `ReturnDynamic`.
+    Return,
+    /// The `List*` instructions.
+    List,
+    /// The `Cell*` instructions.
+    Cell,
+    /// The `Weak*` instructions.
+    Weak,
+    /// The `Record*` instructions.
+    Record,
+    /// The `Tag*` instructions and `Untag`.
+    Tag,
+    /// `CallProtected` and `Raise`, which catch and raise errors.
+    Error,
+    /// `BindFront`.
+    BindFront,
+    /// The `Str*` instructions.
+    String,
+}
+
+impl InstructionFamily {
+    /// Every family, in declaration order.
+    pub const ALL: [InstructionFamily; 17] = [
+        InstructionFamily::Stack,
+        InstructionFamily::GlobalRead,
+        InstructionFamily::GlobalWrite,
+        InstructionFamily::Arithmetic,
+        InstructionFamily::Logic,
+        InstructionFamily::Branch,
+        InstructionFamily::Call,
+        InstructionFamily::CallDynamic,
+        InstructionFamily::Return,
+        InstructionFamily::List,
+        InstructionFamily::Cell,
+        InstructionFamily::Weak,
+        InstructionFamily::Record,
+        InstructionFamily::Tag,
+        InstructionFamily::Error,
+        InstructionFamily::BindFront,
+        InstructionFamily::String,
+    ];
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+impl Instruction {
+    /// Returns the family this instruction belongs to.
+    #[must_use]
+    pub fn family(&self) -> InstructionFamily {
+        match self {
+            Instruction::PushConst(_)
+            | Instruction::PushCopy(_)
+            | Instruction::WriteStack(_)
+            | Instruction::Pop(_) => InstructionFamily::Stack,
+            Instruction::PushGlobal(_) => InstructionFamily::GlobalRead,
+            Instruction::PopGlobal(_) => InstructionFamily::GlobalWrite,
+            Instruction::Add
+            | Instruction::Sub
+            | Instruction::Mul
+            | Instruction::Div
+            | Instruction::ToNumber(_) => InstructionFamily::Arithmetic,
+            Instruction::BoolAnd
+            | Instruction::BoolOr
+            | Instruction::BoolXor
+            | Instruction::BoolNot
+            | Instruction::Compare(_)
+            | Instruction::IsNull
+            | Instruction::IdentityHash
+            | Instruction::ToBool(_) => InstructionFamily::Logic,
+            Instruction::Branch(_)
+            | Instruction::BranchIf(_)
+            | Instruction::BranchIfTruthy(_, _)
+            | Instruction::CmpBranch(_, _) => InstructionFamily::Branch,
+            Instruction::Call(_) | Instruction::TailCall(_) => InstructionFamily::Call,
+            Instruction::CallDynamic | Instruction::Apply => InstructionFamily::CallDynamic,
+            Instruction::Return(_) | Instruction::ReturnDynamic => InstructionFamily::Return,
+            Instruction::ListNew
+            | Instruction::ListAppend
+            | Instruction::ListLen
+            | Instruction::ListGet
+            | Instruction::ListGetOrNull
+            | Instruction::ListSet
+            | Instruction::ListGetRel
+            | Instruction::ListSetRel
+            | Instruction::ListSlice => InstructionFamily::List,
+            Instruction::CellNew | Instruction::CellGet | Instruction::CellSet => {
+                InstructionFamily::Cell
+            }
+            Instruction::WeakNew | Instruction::WeakGet => InstructionFamily::Weak,
+            Instruction::RecordNew(_) | Instruction::RecordGet(_) | Instruction::RecordSet(_) => {
+                InstructionFamily::Record
+            }
+            Instruction::TagNew(_)
+            | Instruction::TagWrap
+            | Instruction::TagOf
+            | Instruction::Untag => InstructionFamily::Tag,
+            Instruction::CallProtected(_, _) | Instruction::Raise => InstructionFamily::Error,
+            Instruction::BindFront(_) => InstructionFamily::BindFront,
+            Instruction::StrLenBytes
+            | Instruction::StrLenChars
+            | Instruction::StrSliceBytes
+            | Instruction::StrSliceChars => InstructionFamily::String,
+        }
+    }
+}
+
+/// The set of instruction families a module may use.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InstructionPolicy {
+    allowed: u32,
+}
+
+impl InstructionPolicy {
+    /// A policy that allows every instruction.
+    #[must_use]
+    pub fn allow_all() -> Self {
+        InstructionFamily::ALL
+            .into_iter()
+            .fold(Self::deny_all(), Self::allow)
+    }
+
+    /// A policy that allows no instructions. Families are added with
+    /// [`InstructionPolicy::allow`].
+    #[must_use]
+    pub fn deny_all() -> Self {
+        InstructionPolicy { allowed: 0 }
+    }
+
+    #[must_use]
+    pub fn allow(self, family: InstructionFamily) -> Self {
+        InstructionPolicy {
+            allowed: self.allowed | family.bit(),
+        }
+    }
+
+    #[must_use]
+    pub fn deny(self, family: InstructionFamily) -> Self {
+        InstructionPolicy {
+            allowed: self.allowed & !family.bit(),
+        }
+    }
+
+    #[must_use]
+    pub fn allows_family(&self, family: InstructionFamily) -> bool {
+        self.allowed & family.bit() != 0
+    }
+
+    #[must_use]
+    pub fn allows(&self, instruction: &Instruction) -> bool {
+        self.allows_family(instruction.family())
+    }
+}
+
+impl Default for InstructionPolicy {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{indexes::GlobalIndex, instructions::CallInstruction};
+
+    #[test]
+    fn families_are_allowed_and_denied_independently() {
+        let policy = InstructionPolicy::allow_all()
+            .deny(InstructionFamily::GlobalWrite)
+            .deny(InstructionFamily::CallDynamic);
+        assert!(policy.allows(&Instruction::PushGlobal(GlobalIndex::new(0))));
+        assert!(!policy.allows(&Instruction::PopGlobal(GlobalIndex::new(0))));
+        assert!(!policy.allows(&Instruction::Apply));
+        assert!(policy.allows(&Instruction::Call(CallInstruction {
+            num_args: 0,
+            num_returns: 0,
+        })));
+
+        let policy = InstructionPolicy::deny_all().allow(InstructionFamily::Return);
+        assert!(policy.allows(&Instruction::Return(0)));
+        assert!(!policy.allows(&Instruction::Add));
+        assert_eq!(
+            InstructionFamily::ALL
+                .into_iter()
+                .filter(|family| InstructionPolicy::allow_all().allows_family(*family))
+                .count(),
+            InstructionFamily::ALL.len()
+        );
+    }
+}
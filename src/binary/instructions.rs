@@ -2,10 +2,13 @@ use std::{collections::HashMap, rc::Rc};
 
 use crate::{
     binary::error::BuilderError,
-    util::{imm_string::ImmString, intern::InternSet},
+    util::{imm_string::ImmString, intern::SharedInternSet},
 };
 
-use super::error::Result;
+use super::{
+    error::Result,
+    indexes::{GlobalIndex, LocalConstIndex, MAX_TABLE_LEN},
+};
 
 /// An opcode for an instruction.
 ///
@@ -15,28 +18,48 @@ use super::error::Result;
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Opcode(ImmString);
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum StackIndex {
     FromTop(u32),
     FromBottom(u32),
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct BranchTarget(u32);
 
 impl BranchTarget {
+    pub(crate) fn new(index: u32) -> Self {
+        BranchTarget(index)
+    }
+
     pub fn target_index(&self) -> u32 {
         self.0
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// A comparison between two values.
+///
+/// The value comparisons treat integers and floats as a single numeric type:
+///
+/// | left     | right   | rule                                              |
+/// |----------|---------|---------------------------------------------------|
+/// | integer  | integer | exact, for both compact and big integers          |
+/// | float    | float   | IEEE 754, so `-0.0 == 0.0`                        |
+/// | integer  | float   | exact, without rounding the integer to a float    |
+/// | rational | number  | exact, without rounding either value              |
+///
+/// Comparing an integer with a float never loses precision, so `2^53 + 1` is
+/// greater than `2^53` as a float even though it rounds to it. NaN is unequal
+/// to every value including itself, and every ordering comparison with it
+/// is false. Strings and byte strings are ordered by their bytes. Ordering
+/// any other values is a type error.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum CompareOp {
     // Referential equality.
     RefEq,
 
-    // Value equality. May call a method on the value.
-    // NOTE: Do we want to do this? This might block our method call semantics.
+    // Value equality. Numbers compare by value as above, and other values as
+    // with `RefEq`.
     Eq,
     Ne,
     Lt,
@@ -45,25 +68,50 @@ pub enum CompareOp {
     Ge,
 }
 
-#[derive(Copy, Clone, Debug)]
+/// How the truthiness instructions convert a value to a boolean, so that
+/// each source language can pick its own rules.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Truthiness {
+    /// Only booleans are accepted. Any other value is a type error.
+    Strict,
+    /// `null` and `false` are false. Every other value is true.
+    NullAndFalse,
+    /// `null`, `false`, zero, NaN, and empty strings, byte strings, lists and
+    /// maps are false. Every other value is true.
+    Empty,
+}
+
+/// The kinds of numbers, in the order of the numeric tower.
+///
+/// Arithmetic on two numbers of different kinds first converts the narrower
+/// one to the wider kind: integers become rationals exactly, and integers and
+/// rationals become the nearest float.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NumericKind {
+    Integer,
+    Rational,
+    Float,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct CallInstruction {
     pub num_args: u32,
     pub num_returns: u32,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Instruction {
     /// Push a local constant onto the stack.
-    PushConst(u32),
+    PushConst(LocalConstIndex),
 
     /// Push a copy of the given stack index onto the stack.
     PushCopy(StackIndex),
 
     /// Pushes the value of a global from the module.
-    PushGlobal(u32),
+    PushGlobal(GlobalIndex),
 
     /// Pops the top value off of the stack and writes it to the global.
-    PopGlobal(u32),
+    PopGlobal(GlobalIndex),
 
     /// Pops the top value off of the stack, writing it to the given location
     /// in the local stack. The stack top is counted after the top value has
@@ -75,6 +123,14 @@ pub enum Instruction {
 
     /// Add the top two values on the stack. Push the result.
     Add,
+    /// Pop a value, then another. Push the second minus the first.
+    Sub,
+    /// Multiply the top two values on the stack. Push the result.
+    Mul,
+    /// Pop a value, then another. Push the second divided by the first.
+    /// Dividing two integers gives a rational, and dividing an integer or
+    /// rational by zero is an error.
+    Div,
 
     // Boolean Operations
     /// Boolean AND the top two values on the stack. Push the result.
@@ -87,11 +143,109 @@ pub enum Instruction {
     ListAppend,
     ListLen,
     ListGet,
+    /// Pop a list, then an index. Push the item at the index, or null if the
+    /// index is out of range.
+    ListGetOrNull,
     ListSet,
 
+    // Relative list operations. A negative index counts from the end of the
+    // list, so -1 is the last item. After adjustment the index must be in
+    // range, or the instruction fails.
+    /// Pop a list, then an index. Push the item at the relative index.
+    ListGetRel,
+    /// Pop a list, an index, then a value. Set the item at the relative index
+    /// to the value.
+    ListSetRel,
+    /// Pop a list, a start index, then an end index. Push a new list with the
+    /// items from start up to, but not including, end. Negative indexes count
+    /// from the end, and out of range indexes are clamped to the list, so the
+    /// result is empty rather than an error when the range is empty.
+    ListSlice,
+
+    // Cell operations. A cell is a mutable box holding a single value.
+    /// Pop a value. Push a new cell holding the value.
+    CellNew,
+    /// Pop a cell. Push the value it holds.
+    CellGet,
+    /// Pop a cell, then a value. Store the value in the cell.
+    CellSet,
+
+    // Weak reference operations. A weak reference does not keep its target
+    // alive, so hosts and scripts can build caches that do not leak.
+    /// Pop a list, function, map, cell, weak reference, record, tag or tagged
+    /// value. Push a new weak reference to it.
+    WeakNew,
+    /// Pop a weak reference. Push its target, or null if the target has been
+    /// collected.
+    WeakGet,
+
+    // Record operations. A record has a fixed set of named fields. Fields
+    // are named by string constants, and the field names of a record are
+    // shared with every other record that has the same fields.
+    /// Pop one value for each field named by the list of strings at the
+    /// local constant, with the value of the last field on top. Push a new
+    /// record with those fields.
+    RecordNew(LocalConstIndex),
+    /// Pop a record. Push the value of the field named by the string at the
+    /// local constant.
+    RecordGet(LocalConstIndex),
+    /// Pop a record, then a value. Store the value in the field named by the
+    /// string at the local constant.
+    RecordSet(LocalConstIndex),
+
+    // Tag operations. A tag is a unique value with a name, which marks the
+    // values wrapped with it as belonging to a user-defined type, so that
+    // frontends can tell apart types with the same representation.
+    /// Push a new tag, named by the string at the local constant. Each tag is
+    /// distinct from every other tag, even one with the same name.
+    TagNew(LocalConstIndex),
+    /// Pop a tag, then a value. Push a new tagged value wrapping the value.
+    TagWrap,
+    /// Pop a value. Push its tag if it is a tagged value, or null otherwise.
+    TagOf,
+    /// Pop a tag, then a tagged value. Push the value it wraps, failing if it
+    /// is not tagged with the tag.
+    Untag,
+
+    // String operations. Strings are indexed either by the bytes of their
+    // UTF-8 encoding or by their chars (Unicode scalar values), so that
+    // frontends can compile the indexing model of their language directly.
+    // Slice indexes that are negative count from the end of the string.
+    // After adjustment they must be within the string and in order, or the
+    // instruction fails.
+    /// Pop a string. Push its length in bytes.
+    StrLenBytes,
+    /// Pop a string. Push its length in chars.
+    StrLenChars,
+    /// Pop a string, a start index, then an end index, counted in bytes.
+    /// Push the string from start up to, but not including, end. Both
+    /// indexes must fall on char boundaries.
+    StrSliceBytes,
+    /// Pop a string, a start index, then an end index, counted in chars.
+    /// Push the string from start up to, but not including, end.
+    StrSliceChars,
+
     /// Compare the top two values on the stack, applying the given comparison.
     Compare(CompareOp),
 
+    /// Pop a value. Push true if it is null, such as an optional import whose
+    /// module was not loaded.
+    IsNull,
+
+    /// Pop a value. Push an integer hash of it. Values that compare equal
+    /// with `RefEq` have the same hash. Reference values are hashed by
+    /// identity, so the hash stays the same for as long as the object lives.
+    IdentityHash,
+
+    /// Pop a value. Push it converted to a boolean with the given rules.
+    ToBool(Truthiness),
+
+    /// Pop a number. Push it converted to the given kind. Conversions to
+    /// integers round toward zero, and conversions to floats round to
+    /// nearest. Infinities and NaN cannot be converted to integers or
+    /// rationals.
+    ToNumber(NumericKind),
+
     /// Unconditionally branch to the given target.
     Branch(BranchTarget),
 
@@ -99,6 +253,15 @@ pub enum Instruction {
     /// at the top of the stack must be a boolean.
     BranchIf(BranchTarget),
 
+    /// Pop the top value off of the stack and branch if it converts to true
+    /// with the given rules.
+    BranchIfTruthy(BranchTarget, Truthiness),
+
+    /// Compare the top two values on the stack like `Compare`, popping both,
+    /// and branch if the comparison holds. Equivalent to a `Compare` followed
+    /// by a `BranchIf`, which the peephole pass fuses into this.
+    CmpBranch(CompareOp, BranchTarget),
+
     /// Calls a function. The number of arguments and return values are given
     /// as enum parameters. If the function does not return the specified number
     /// of values, an error will occur.
@@ -109,6 +272,10 @@ pub enum Instruction {
     /// the arguments. The value is the index of the instruction to return to.
     CallDynamic,
 
+    /// Call a function with the items of a list as its arguments. The top of
+    /// the stack must be the list, followed by the function.
+    Apply,
+
     /// Returns from a function. The parameter gives the number of return values
     /// that will be popped off of the stack.
     Return(u32),
@@ -124,12 +291,53 @@ pub enum Instruction {
     /// Binds N arguments to a function, returning a new function that takes
     /// the remaining arguments.
     BindFront(u32),
+
+    // Error handling. Errors unwind the call stack to the nearest frame
+    // waiting on a protected call. Only user errors can be caught, so
+    // resource limits and cancellation always reach the embedder.
+    /// Calls a function like `Call`. If the call fails with an error that can
+    /// be caught, the frames it added are dropped, an error value describing
+    /// the error is pushed, and execution continues at the target.
+    CallProtected(CallInstruction, BranchTarget),
+    /// Pop an error value or a string, and fail with it. A string is raised
+    /// as the message of an error of kind `error`.
+    Raise,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct InstructionList(Rc<Vec<Instruction>>);
 
+impl Instruction {
+    /// Returns the target of a branching instruction.
+    #[must_use]
+    pub fn branch_target(&self) -> Option<BranchTarget> {
+        match self {
+            Instruction::Branch(target)
+            | Instruction::BranchIf(target)
+            | Instruction::BranchIfTruthy(target, _)
+            | Instruction::CmpBranch(_, target)
+            | Instruction::CallProtected(_, target) => Some(*target),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn branch_target_mut(&mut self) -> Option<&mut BranchTarget> {
+        match self {
+            Instruction::Branch(target)
+            | Instruction::BranchIf(target)
+            | Instruction::BranchIfTruthy(target, _)
+            | Instruction::CmpBranch(_, target)
+            | Instruction::CallProtected(_, target) => Some(target),
+            _ => None,
+        }
+    }
+}
+
 impl InstructionList {
+    pub fn new(instructions: Vec<Instruction>) -> Self {
+        InstructionList(Rc::new(instructions))
+    }
+
     pub fn instructions(&self) -> &[Instruction] {
         &self.0[..]
     }
@@ -137,18 +345,22 @@ impl InstructionList {
 
 enum BranchType {
     Conditional,
+    Truthy(Truthiness),
+    Compare(CompareOp),
     Unconditional,
+    CallProtected(CallInstruction),
 }
 
 pub struct InstructionListBuilder {
-    branch_target_names: InternSet<ImmString>,
+    branch_target_names: SharedInternSet<ImmString>,
     branch_targets: HashMap<ImmString, BranchTarget>,
     branch_resolutions: Vec<(BranchType, u32, ImmString)>,
     instructions: Vec<Option<Instruction>>,
 }
 
 macro_rules! inst_builder {
-    ($name:ident, $opcode:ident $(($($arg_name:ident : $arg_type:ty)*))?) => {
+    ($(#[$attr:meta])* $name:ident, $opcode:ident $(($($arg_name:ident : $arg_type:ty)*))?) => {
+        $(#[$attr])*
         pub fn $name(&mut self, $($($arg_name: $arg_type),*)*) -> &mut Self {
             self.instructions.push(Some(Instruction::$opcode$(($($arg_name),*))*));
             self
@@ -158,8 +370,14 @@ macro_rules! inst_builder {
 
 impl InstructionListBuilder {
     pub fn new() -> Self {
+        Self::with_interner(SharedInternSet::new())
+    }
+
+    /// Creates a builder that interns branch target names in `interner`, so
+    /// that names repeated across functions are only stored once.
+    pub fn with_interner(interner: SharedInternSet<ImmString>) -> Self {
         InstructionListBuilder {
-            branch_target_names: InternSet::new(),
+            branch_target_names: interner,
             branch_targets: HashMap::new(),
             branch_resolutions: Vec::new(),
             instructions: Vec::new(),
@@ -172,7 +390,11 @@ impl InstructionListBuilder {
         index
     }
 
-    pub fn resolve_push_const(&mut self, inst_index: u32, const_index: u32) -> Result<()> {
+    pub fn resolve_push_const(
+        &mut self,
+        inst_index: u32,
+        const_index: LocalConstIndex,
+    ) -> Result<()> {
         let target_inst = &mut self.instructions[inst_index as usize];
         if target_inst.is_some() {
             return Err(BuilderError::AlreadyExists);
@@ -181,16 +403,21 @@ impl InstructionListBuilder {
         Ok(())
     }
 
-    pub fn resolve_push_global(&mut self, inst_index: u32, global_index: u32) -> Result<()> {
+    /// Fills in an instruction added with [`Self::add_deferred_inst`].
+    pub fn resolve_deferred_inst(&mut self, inst_index: u32, inst: Instruction) -> Result<()> {
         let target_inst = &mut self.instructions[inst_index as usize];
         if target_inst.is_some() {
             return Err(BuilderError::AlreadyExists);
         }
-        *target_inst = Some(Instruction::PushGlobal(global_index));
+        *target_inst = Some(inst);
         Ok(())
     }
 
-    pub fn resolve_pop_global(&mut self, inst_index: u32, global_index: u32) -> Result<()> {
+    pub fn resolve_push_global(
+        &mut self,
+        inst_index: u32,
+        global_index: GlobalIndex,
+    ) -> Result<()> {
         let target_inst = &mut self.instructions[inst_index as usize];
         if target_inst.is_some() {
             return Err(BuilderError::AlreadyExists);
@@ -199,26 +426,75 @@ impl InstructionListBuilder {
         Ok(())
     }
 
+    pub fn resolve_pop_global(&mut self, inst_index: u32, global_index: GlobalIndex) -> Result<()> {
+        let target_inst = &mut self.instructions[inst_index as usize];
+        if target_inst.is_some() {
+            return Err(BuilderError::AlreadyExists);
+        }
+        *target_inst = Some(Instruction::PopGlobal(global_index));
+        Ok(())
+    }
+
     inst_builder!(push_copy, PushCopy(s: StackIndex));
     inst_builder!(pop, Pop(n: u32));
     inst_builder!(write_stack, WriteStack(s: StackIndex));
     inst_builder!(add, Add);
+    inst_builder!(sub, Sub);
+    inst_builder!(mul, Mul);
+    inst_builder!(div, Div);
     inst_builder!(bool_and, BoolAnd);
     inst_builder!(bool_or, BoolOr);
     inst_builder!(bool_xor, BoolXor);
     inst_builder!(bool_not, BoolNot);
     inst_builder!(compare, Compare(op: CompareOp));
+    inst_builder!(is_null, IsNull);
+    inst_builder!(identity_hash, IdentityHash);
+    // Builder methods are named after their instructions, so these are not
+    // conversions of the builder.
+    inst_builder!(
+        #[allow(clippy::wrong_self_convention)]
+        to_bool,
+        ToBool(truthiness: Truthiness)
+    );
+    inst_builder!(
+        #[allow(clippy::wrong_self_convention)]
+        to_number,
+        ToNumber(kind: NumericKind)
+    );
     inst_builder!(call, Call(call: CallInstruction));
     inst_builder!(call_dynamic, CallDynamic);
+    inst_builder!(apply, Apply);
     inst_builder!(tail_call, TailCall(num_args: u32));
     inst_builder!(return_, Return(n: u32));
     inst_builder!(return_dynamic, ReturnDynamic);
     inst_builder!(bind_front, BindFront(n: u32));
+    inst_builder!(list_new, ListNew);
+    inst_builder!(list_append, ListAppend);
+    inst_builder!(list_len, ListLen);
+    inst_builder!(list_get, ListGet);
+    inst_builder!(list_get_or_null, ListGetOrNull);
+    inst_builder!(list_set, ListSet);
+    inst_builder!(list_get_rel, ListGetRel);
+    inst_builder!(list_set_rel, ListSetRel);
+    inst_builder!(list_slice, ListSlice);
+    inst_builder!(cell_new, CellNew);
+    inst_builder!(cell_get, CellGet);
+    inst_builder!(cell_set, CellSet);
+    inst_builder!(weak_new, WeakNew);
+    inst_builder!(weak_get, WeakGet);
+    inst_builder!(tag_wrap, TagWrap);
+    inst_builder!(tag_of, TagOf);
+    inst_builder!(untag, Untag);
+    inst_builder!(raise, Raise);
+    inst_builder!(str_len_bytes, StrLenBytes);
+    inst_builder!(str_len_chars, StrLenChars);
+    inst_builder!(str_slice_bytes, StrSliceBytes);
+    inst_builder!(str_slice_chars, StrSliceChars);
 
     // These are only used in testing, as the top-level builder delays the
     // resolution of push/pop instructions until the end.
     #[cfg(test)]
-    inst_builder!(push_const, PushConst(c: u32));
+    inst_builder!(push_const, PushConst(c: LocalConstIndex));
 
     pub fn branch(&mut self, target: &str) -> &mut Self {
         let target = self.branch_target_names.intern(target);
@@ -242,6 +518,40 @@ impl InstructionListBuilder {
         self
     }
 
+    pub fn branch_if_truthy(&mut self, target: &str, truthiness: Truthiness) -> &mut Self {
+        let target = self.branch_target_names.intern(target);
+        self.branch_resolutions.push((
+            BranchType::Truthy(truthiness),
+            self.instructions.len() as u32,
+            target,
+        ));
+        self.instructions.push(None);
+        self
+    }
+
+    pub fn cmp_branch(&mut self, op: CompareOp, target: &str) -> &mut Self {
+        let target = self.branch_target_names.intern(target);
+        self.branch_resolutions.push((
+            BranchType::Compare(op),
+            self.instructions.len() as u32,
+            target,
+        ));
+        self.instructions.push(None);
+        self
+    }
+
+    /// Adds a `CallProtected` that continues at `handler` if the call fails.
+    pub fn call_protected(&mut self, call: CallInstruction, handler: &str) -> &mut Self {
+        let handler = self.branch_target_names.intern(handler);
+        self.branch_resolutions.push((
+            BranchType::CallProtected(call),
+            self.instructions.len() as u32,
+            handler,
+        ));
+        self.instructions.push(None);
+        self
+    }
+
     pub fn define_branch_target(&mut self, target: &str) -> &mut Self {
         let target = self.branch_target_names.intern(target);
         let curr_branch_target = BranchTarget(self.instructions.len() as u32);
@@ -251,6 +561,11 @@ impl InstructionListBuilder {
     }
 
     pub fn build(mut self) -> Result<InstructionList> {
+        // Instruction positions, including branch targets, are recorded as
+        // `u32`s, which is only exact if the function is within the limit.
+        if self.instructions.len() > MAX_TABLE_LEN {
+            return Err(BuilderError::IndexOverflow);
+        }
         // Resolve branch targets.
         for (branch_type, index, target) in self.branch_resolutions {
             let target = self
@@ -261,7 +576,10 @@ impl InstructionListBuilder {
             assert!(inst.is_none(), "Should never be able to double resolve.");
             *inst = Some(match branch_type {
                 BranchType::Conditional => Instruction::BranchIf(*target),
+                BranchType::Truthy(truthiness) => Instruction::BranchIfTruthy(*target, truthiness),
+                BranchType::Compare(op) => Instruction::CmpBranch(op, *target),
                 BranchType::Unconditional => Instruction::Branch(*target),
+                BranchType::CallProtected(call) => Instruction::CallProtected(call, *target),
             });
         }
         let result = self
@@ -294,8 +612,8 @@ mod tests {
         builder
             .pop(1)
             // Push the constant 0 and 1 onto the stack.
-            .push_const(0)
-            .push_const(1)
+            .push_const(LocalConstIndex::new(0))
+            .push_const(LocalConstIndex::new(1))
             .add()
             .return_(1);
 
@@ -308,9 +626,30 @@ mod tests {
         let mut builder = InstructionListBuilder::new();
         builder
             .define_branch_target("loop_start")
-            .push_const(0)
+            .push_const(LocalConstIndex::new(0))
             .branch_if("loop_start");
         builder.build()?;
         Ok(())
     }
+
+    #[test]
+    fn deferred_global_accesses_resolve_to_their_instructions() -> anyhow::Result<()> {
+        let mut builder = InstructionListBuilder::new();
+        let push = builder.add_deferred_inst();
+        let pop = builder.add_deferred_inst();
+        builder.resolve_push_global(push, GlobalIndex::new(1))?;
+        builder.resolve_pop_global(pop, GlobalIndex::new(2))?;
+        assert!(matches!(
+            builder.resolve_pop_global(pop, GlobalIndex::new(2)),
+            Err(BuilderError::AlreadyExists)
+        ));
+        assert_eq!(
+            builder.build()?.instructions(),
+            &[
+                Instruction::PushGlobal(GlobalIndex::new(1)),
+                Instruction::PopGlobal(GlobalIndex::new(2)),
+            ]
+        );
+        Ok(())
+    }
 }
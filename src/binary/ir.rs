@@ -0,0 +1,635 @@
+//! A mid-level IR of virtual registers and basic blocks, lowered to stack
+//! instructions.
+//!
+//! Code generators that target the stack machine directly have to track
+//! where each value sits on the stack. With [`IrFunction`], they instead
+//! compute into registers, split the body into blocks that each end in a
+//! jump, branch, return or tail call, and let [`IrFunction::lower`] handle
+//! stack allocation and branch wiring.
+//!
+//! Registers are mutable, so a value that differs between the predecessors
+//! of a block is assigned to the same register in each of them, in place of
+//! a phi node. Each register gets a fixed slot at the bottom of the frame's
+//! stack, above the parameters, which are the first registers. Blocks start
+//! and end with only those slots on the stack, so any block can jump to any
+//! other.
+
+use crate::{pure_values::Integer, util::imm_string::ImmString};
+
+use super::{
+    builders::{FunctionBuilder, ValueRef},
+    error::{BuilderError, Result},
+    instructions::{CallInstruction, CompareOp, NumericKind, StackIndex, Truthiness},
+};
+
+/// A virtual register of an [`IrFunction`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Reg(u32);
+
+impl Reg {
+    #[must_use]
+    pub const fn index(self) -> u32 {
+        self.0
+    }
+
+    fn slot(self) -> StackIndex {
+        StackIndex::FromBottom(self.0)
+    }
+}
+
+/// A basic block of an [`IrFunction`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Block(u32);
+
+impl Block {
+    #[must_use]
+    pub const fn index(self) -> u32 {
+        self.0
+    }
+
+    fn label(self) -> String {
+        format!("block{}", self.0)
+    }
+}
+
+/// An operation that computes one value from its operands. Operands are
+/// given in the order the stack instruction expects them to be pushed.
+#[derive(Clone, Debug, PartialEq)]
+pub enum IrOp {
+    /// `[lhs, rhs]`
+    Add,
+    /// `[lhs, rhs]`
+    Sub,
+    /// `[lhs, rhs]`
+    Mul,
+    /// `[lhs, rhs]`
+    Div,
+    /// `[lhs, rhs]`
+    Compare(CompareOp),
+    /// `[lhs, rhs]`
+    BoolAnd,
+    /// `[lhs, rhs]`
+    BoolOr,
+    /// `[lhs, rhs]`
+    BoolXor,
+    /// `[value]`
+    BoolNot,
+    /// `[value]`
+    IsNull,
+    /// `[value]`
+    ToBool(Truthiness),
+    /// `[value]`
+    ToNumber(NumericKind),
+    /// `[]`
+    ListNew,
+    /// `[list]`
+    ListLen,
+    /// `[index, list]`
+    ListGet,
+    /// `[value]`
+    CellNew,
+    /// `[cell]`
+    CellGet,
+    /// `[record]`
+    RecordGet(ImmString),
+}
+
+impl IrOp {
+    fn num_operands(&self) -> usize {
+        match self {
+            IrOp::Add
+            | IrOp::Sub
+            | IrOp::Mul
+            | IrOp::Div
+            | IrOp::Compare(_)
+            | IrOp::BoolAnd
+            | IrOp::BoolOr
+            | IrOp::BoolXor
+            | IrOp::ListGet => 2,
+            IrOp::BoolNot
+            | IrOp::IsNull
+            | IrOp::ToBool(_)
+            | IrOp::ToNumber(_)
+            | IrOp::ListLen
+            | IrOp::CellNew
+            | IrOp::CellGet
+            | IrOp::RecordGet(_) => 1,
+            IrOp::ListNew => 0,
+        }
+    }
+
+    fn emit(&self, fn_builder: &mut FunctionBuilder) {
+        match self {
+            IrOp::Add => fn_builder.add(),
+            IrOp::Sub => fn_builder.sub(),
+            IrOp::Mul => fn_builder.mul(),
+            IrOp::Div => fn_builder.div(),
+            IrOp::Compare(op) => fn_builder.compare(*op),
+            IrOp::BoolAnd => fn_builder.bool_and(),
+            IrOp::BoolOr => fn_builder.bool_or(),
+            IrOp::BoolXor => fn_builder.bool_xor(),
+            IrOp::BoolNot => fn_builder.bool_not(),
+            IrOp::IsNull => fn_builder.is_null(),
+            IrOp::ToBool(truthiness) => fn_builder.to_bool(*truthiness),
+            IrOp::ToNumber(kind) => fn_builder.to_number(*kind),
+            IrOp::ListNew => fn_builder.list_new(),
+            IrOp::ListLen => fn_builder.list_len(),
+            IrOp::ListGet => fn_builder.list_get(),
+            IrOp::CellNew => fn_builder.cell_new(),
+            IrOp::CellGet => fn_builder.cell_get(),
+            IrOp::RecordGet(field) => fn_builder.record_get(field.clone()),
+        };
+    }
+}
+
+/// An operation that changes a value without computing one. Operands are
+/// ordered as for [`IrOp`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum IrEffect {
+    /// `[value, list]`
+    ListAppend,
+    /// `[value, index, list]`
+    ListSet,
+    /// `[value, cell]`
+    CellSet,
+    /// `[value, record]`
+    RecordSet(ImmString),
+}
+
+impl IrEffect {
+    fn num_operands(&self) -> usize {
+        match self {
+            IrEffect::ListAppend | IrEffect::CellSet | IrEffect::RecordSet(_) => 2,
+            IrEffect::ListSet => 3,
+        }
+    }
+
+    fn emit(&self, fn_builder: &mut FunctionBuilder) {
+        match self {
+            IrEffect::ListAppend => fn_builder.list_append(),
+            IrEffect::ListSet => fn_builder.list_set(),
+            IrEffect::CellSet => fn_builder.cell_set(),
+            IrEffect::RecordSet(field) => fn_builder.record_set(field.clone()),
+        };
+    }
+}
+
+enum IrInst {
+    /// Reads a constant or a global.
+    Value(Reg, ValueRef),
+    Int(Reg, Integer),
+    Null(Reg),
+    Copy(Reg, Reg),
+    Op(Reg, IrOp, Vec<Reg>),
+    Effect(IrEffect, Vec<Reg>),
+    Call {
+        function: Reg,
+        args: Vec<Reg>,
+        results: Vec<Reg>,
+    },
+    SetGlobal(ValueRef, Reg),
+}
+
+enum Terminator {
+    Jump(Block),
+    Branch {
+        cond: Reg,
+        then_block: Block,
+        else_block: Block,
+    },
+    Return(Vec<Reg>),
+    TailCall {
+        function: Reg,
+        args: Vec<Reg>,
+    },
+}
+
+#[derive(Default)]
+struct BlockData {
+    insts: Vec<IrInst>,
+    terminator: Option<Terminator>,
+}
+
+/// A function written as registers and blocks, to be lowered to stack
+/// instructions with [`Self::lower`].
+///
+/// Instructions are added to a block with the methods of this type, which
+/// return the registers they assign. The entry block is [`Self::entry`].
+pub struct IrFunction {
+    num_params: u32,
+    num_regs: u32,
+    blocks: Vec<BlockData>,
+    /// The first block that was added to without existing, if any.
+    unknown_block: Option<u32>,
+}
+
+impl IrFunction {
+    /// Creates a function taking `num_params` arguments, which are held in
+    /// the registers returned by [`Self::param`].
+    #[must_use]
+    pub fn new(num_params: u32) -> Self {
+        IrFunction {
+            num_params,
+            num_regs: num_params,
+            blocks: vec![BlockData::default()],
+            unknown_block: None,
+        }
+    }
+
+    /// Returns the register holding the argument at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the function takes no argument at `index`.
+    #[must_use]
+    pub fn param(&self, index: u32) -> Reg {
+        assert!(index < self.num_params, "Parameter {index} does not exist.");
+        Reg(index)
+    }
+
+    /// Returns the block that runs first.
+    #[must_use]
+    pub fn entry(&self) -> Block {
+        Block(0)
+    }
+
+    /// Returns a new register, which holds null until it is assigned.
+    pub fn new_reg(&mut self) -> Reg {
+        let reg = Reg(self.num_regs);
+        self.num_regs += 1;
+        reg
+    }
+
+    /// Returns a new, empty block.
+    pub fn new_block(&mut self) -> Block {
+        let block = Block(u32::try_from(self.blocks.len()).expect("Too many blocks."));
+        self.blocks.push(BlockData::default());
+        block
+    }
+
+    fn block_mut(&mut self, block: Block) -> Option<&mut BlockData> {
+        let data = self.blocks.get_mut(block.0 as usize);
+        if data.is_none() {
+            self.unknown_block.get_or_insert(block.0);
+        }
+        data
+    }
+
+    fn push(&mut self, block: Block, inst: IrInst) {
+        if let Some(data) = self.block_mut(block) {
+            data.insts.push(inst);
+        }
+    }
+
+    fn assign(&mut self, block: Block, make_inst: impl FnOnce(Reg) -> IrInst) -> Reg {
+        let dst = self.new_reg();
+        self.push(block, make_inst(dst));
+        dst
+    }
+
+    /// Reads a constant or a global into a new register.
+    pub fn value(&mut self, block: Block, value: &ValueRef) -> Reg {
+        self.assign(block, |dst| IrInst::Value(dst, value.clone()))
+    }
+
+    pub fn int(&mut self, block: Block, value: impl Into<Integer>) -> Reg {
+        let value = value.into();
+        self.assign(block, |dst| IrInst::Int(dst, value))
+    }
+
+    pub fn null(&mut self, block: Block) -> Reg {
+        self.assign(block, IrInst::Null)
+    }
+
+    /// Computes `op` into a new register.
+    pub fn op(&mut self, block: Block, op: IrOp, operands: &[Reg]) -> Reg {
+        let operands = operands.to_vec();
+        self.assign(block, |dst| IrInst::Op(dst, op, operands))
+    }
+
+    /// Assigns the value of `src` to `dst`, which may already be assigned,
+    /// e.g. to merge values from several blocks.
+    pub fn copy(&mut self, block: Block, dst: Reg, src: Reg) {
+        self.push(block, IrInst::Copy(dst, src));
+    }
+
+    pub fn effect(&mut self, block: Block, effect: IrEffect, operands: &[Reg]) {
+        self.push(block, IrInst::Effect(effect, operands.to_vec()));
+    }
+
+    /// Calls the function in `function` with `args`, returning a new register
+    /// for each of its `num_returns` return values.
+    pub fn call(
+        &mut self,
+        block: Block,
+        function: Reg,
+        args: &[Reg],
+        num_returns: u32,
+    ) -> Vec<Reg> {
+        let results = (0..num_returns).map(|_| self.new_reg()).collect::<Vec<_>>();
+        self.push(
+            block,
+            IrInst::Call {
+                function,
+                args: args.to_vec(),
+                results: results.clone(),
+            },
+        );
+        results
+    }
+
+    /// Writes `src` to the global `global`.
+    pub fn set_global(&mut self, block: Block, global: &ValueRef, src: Reg) {
+        self.push(block, IrInst::SetGlobal(global.clone(), src));
+    }
+
+    fn terminate(&mut self, block: Block, terminator: Terminator) {
+        if let Some(data) = self.block_mut(block) {
+            data.terminator = Some(terminator);
+        }
+    }
+
+    /// Ends `block` with a jump to `target`.
+    pub fn jump(&mut self, block: Block, target: Block) {
+        self.terminate(block, Terminator::Jump(target));
+    }
+
+    /// Ends `block` with a jump to `then_block` if `cond` holds true, and to
+    /// `else_block` if it holds false. Other values fail at run time.
+    pub fn branch(&mut self, block: Block, cond: Reg, then_block: Block, else_block: Block) {
+        self.terminate(
+            block,
+            Terminator::Branch {
+                cond,
+                then_block,
+                else_block,
+            },
+        );
+    }
+
+    /// Ends `block` by returning the values of `values`, in order.
+    pub fn ret(&mut self, block: Block, values: &[Reg]) {
+        self.terminate(block, Terminator::Return(values.to_vec()));
+    }
+
+    /// Ends `block` with a tail call of the function in `function`.
+    pub fn tail_call(&mut self, block: Block, function: Reg, args: &[Reg]) {
+        self.terminate(
+            block,
+            Terminator::TailCall {
+                function,
+                args: args.to_vec(),
+            },
+        );
+    }
+
+    fn check_reg(&self, reg: Reg) -> Result<()> {
+        if reg.0 >= self.num_regs {
+            return Err(BuilderError::UnknownRegister(reg.0));
+        }
+        Ok(())
+    }
+
+    fn check_regs(&self, regs: &[Reg]) -> Result<()> {
+        regs.iter().try_for_each(|reg| self.check_reg(*reg))
+    }
+
+    fn check_block(&self, block: Block) -> Result<()> {
+        if block.0 as usize >= self.blocks.len() {
+            return Err(BuilderError::UnknownBlock(block.0));
+        }
+        Ok(())
+    }
+
+    fn check_operands(expected: usize, operands: &[Reg]) -> Result<()> {
+        if operands.len() != expected {
+            return Err(BuilderError::IrOperandCount {
+                expected,
+                actual: operands.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks that every register and block is defined, that operations have
+    /// as many operands as they take, and that every block is terminated.
+    fn validate(&self) -> Result<()> {
+        if let Some(block) = self.unknown_block {
+            return Err(BuilderError::UnknownBlock(block));
+        }
+        for (index, data) in self.blocks.iter().enumerate() {
+            for inst in &data.insts {
+                match inst {
+                    IrInst::Value(dst, _) | IrInst::Int(dst, _) | IrInst::Null(dst) => {
+                        self.check_reg(*dst)?;
+                    }
+                    IrInst::Copy(dst, src) => {
+                        self.check_reg(*dst)?;
+                        self.check_reg(*src)?;
+                    }
+                    IrInst::Op(dst, op, operands) => {
+                        self.check_reg(*dst)?;
+                        self.check_regs(operands)?;
+                        Self::check_operands(op.num_operands(), operands)?;
+                    }
+                    IrInst::Effect(effect, operands) => {
+                        self.check_regs(operands)?;
+                        Self::check_operands(effect.num_operands(), operands)?;
+                    }
+                    IrInst::Call {
+                        function,
+                        args,
+                        results,
+                    } => {
+                        self.check_reg(*function)?;
+                        self.check_regs(args)?;
+                        self.check_regs(results)?;
+                    }
+                    IrInst::SetGlobal(_, src) => self.check_reg(*src)?,
+                }
+            }
+            match &data.terminator {
+                None => {
+                    let index = u32::try_from(index).expect("Blocks are indexed by u32.");
+                    return Err(BuilderError::UnterminatedBlock(index));
+                }
+                Some(Terminator::Jump(target)) => self.check_block(*target)?,
+                Some(Terminator::Branch {
+                    cond,
+                    then_block,
+                    else_block,
+                }) => {
+                    self.check_reg(*cond)?;
+                    self.check_block(*then_block)?;
+                    self.check_block(*else_block)?;
+                }
+                Some(Terminator::Return(values)) => self.check_regs(values)?,
+                Some(Terminator::TailCall { function, args }) => {
+                    self.check_reg(*function)?;
+                    self.check_regs(args)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Lowers the function into `fn_builder`, and builds it. The function
+    /// is declared to take its parameters as arguments.
+    pub fn lower(&self, mut fn_builder: FunctionBuilder) -> Result<()> {
+        self.validate()?;
+        fn_builder.set_arity(self.num_params);
+        for _ in self.num_params..self.num_regs {
+            fn_builder.push_null();
+        }
+        let push_regs = |fn_builder: &mut FunctionBuilder, regs: &[Reg]| {
+            for reg in regs {
+                fn_builder.push_copy(reg.slot());
+            }
+        };
+        for (index, data) in self.blocks.iter().enumerate() {
+            let block = Block(u32::try_from(index).expect("Blocks are indexed by u32."));
+            fn_builder.define_branch_target(&block.label());
+            for inst in &data.insts {
+                match inst {
+                    IrInst::Value(dst, value) => {
+                        fn_builder.push_value(value)?.write_stack(dst.slot());
+                    }
+                    IrInst::Int(dst, value) => {
+                        fn_builder.push_int(value.clone()).write_stack(dst.slot());
+                    }
+                    IrInst::Null(dst) => {
+                        fn_builder.push_null().write_stack(dst.slot());
+                    }
+                    IrInst::Copy(dst, src) => {
+                        fn_builder.push_copy(src.slot()).write_stack(dst.slot());
+                    }
+                    IrInst::Op(dst, op, operands) => {
+                        push_regs(&mut fn_builder, operands);
+                        op.emit(&mut fn_builder);
+                        fn_builder.write_stack(dst.slot());
+                    }
+                    IrInst::Effect(effect, operands) => {
+                        push_regs(&mut fn_builder, operands);
+                        effect.emit(&mut fn_builder);
+                    }
+                    IrInst::Call {
+                        function,
+                        args,
+                        results,
+                    } => {
+                        push_regs(&mut fn_builder, &[*function]);
+                        push_regs(&mut fn_builder, args);
+                        fn_builder.call(CallInstruction {
+                            num_args: reg_count(args)?,
+                            num_returns: reg_count(results)?,
+                        });
+                        // The last return value is on top.
+                        for result in results.iter().rev() {
+                            fn_builder.write_stack(result.slot());
+                        }
+                    }
+                    IrInst::SetGlobal(global, src) => {
+                        fn_builder.push_copy(src.slot()).pop_value(global)?;
+                    }
+                }
+            }
+            match data.terminator.as_ref().expect("Blocks are validated.") {
+                Terminator::Jump(target) => {
+                    fn_builder.branch(&target.label());
+                }
+                Terminator::Branch {
+                    cond,
+                    then_block,
+                    else_block,
+                } => {
+                    fn_builder
+                        .push_copy(cond.slot())
+                        .branch_if(&then_block.label())
+                        .branch(&else_block.label());
+                }
+                Terminator::Return(values) => {
+                    push_regs(&mut fn_builder, values);
+                    fn_builder.return_(reg_count(values)?);
+                }
+                Terminator::TailCall { function, args } => {
+                    push_regs(&mut fn_builder, &[*function]);
+                    push_regs(&mut fn_builder, args);
+                    fn_builder.tail_call(reg_count(args)?);
+                }
+            }
+        }
+        fn_builder.build()
+    }
+}
+
+fn reg_count(regs: &[Reg]) -> Result<u32> {
+    u32::try_from(regs.len()).map_err(|_| BuilderError::IndexOverflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{
+        instructions::Instruction,
+        modules::{ModuleId, ModuleMemberId},
+        ConstValue, LocalConstIndex, ModuleBuilder,
+    };
+
+    fn lowered(function: &IrFunction) -> Result<Vec<Instruction>> {
+        let builder = ModuleBuilder::new(ModuleId::new(["test"]));
+        let (value, fn_builder) = builder.new_function();
+        function.lower(fn_builder)?;
+        value.export("f".into())?;
+        let module = builder.into_const_module()?;
+        let index = module.exports()[&ModuleMemberId::new("f")];
+        let ConstValue::Function(function) = &module.const_table()[index.as_usize()] else {
+            panic!("Export is not a function.");
+        };
+        Ok(function.instructions().instructions().to_vec())
+    }
+
+    #[test]
+    fn registers_are_stack_slots_above_the_parameters() -> Result<()> {
+        let mut function = IrFunction::new(2);
+        let entry = function.entry();
+        let (a, b) = (function.param(0), function.param(1));
+        let sum = function.op(entry, IrOp::Add, &[a, b]);
+        function.ret(entry, &[sum]);
+        assert_eq!(
+            lowered(&function)?,
+            [
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PushCopy(StackIndex::FromBottom(0)),
+                Instruction::PushCopy(StackIndex::FromBottom(1)),
+                Instruction::Add,
+                Instruction::WriteStack(StackIndex::FromBottom(2)),
+                Instruction::PushCopy(StackIndex::FromBottom(2)),
+                Instruction::Return(1),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_functions_are_rejected() {
+        let mut function = IrFunction::new(1);
+        let entry = function.entry();
+        let next = function.new_block();
+        function.jump(entry, next);
+        assert!(matches!(
+            lowered(&function),
+            Err(BuilderError::UnterminatedBlock(1))
+        ));
+
+        let param = function.param(0);
+        function.op(next, IrOp::Add, &[param]);
+        function.ret(next, &[]);
+        assert!(matches!(
+            lowered(&function),
+            Err(BuilderError::IrOperandCount {
+                expected: 2,
+                actual: 1
+            })
+        ));
+    }
+}
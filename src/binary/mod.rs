@@ -1,10 +1,43 @@
+//! The bytecode layer: module and function builders, validation, and the
+//! instruction encoding. It does not depend on the runtime, and is all that is
+//! built when the `runtime` feature is disabled.
+
 pub(crate) mod builders;
+pub(crate) mod call_graph;
+pub(crate) mod const_eval;
 pub(crate) mod const_table;
-pub(crate) mod error;
+pub(crate) mod diff;
+pub(crate) mod effects;
+mod encoding;
+pub mod error;
+mod function_id;
+pub(crate) mod indexes;
+pub(crate) mod inst_policy;
 pub(crate) mod instructions;
+pub(crate) mod ir;
+mod module_encoding;
 pub(crate) mod module_set;
 pub(crate) mod modules;
+mod peephole;
+mod program;
 
 pub use builders::{DeferredValue, FunctionBuilder, ModuleBuilder, ValueRef};
-pub use const_table::{ConstFunction, ConstIndex, ConstValue};
-pub use modules::ConstModule;
+pub use call_graph::{CallGraph, FunctionReference};
+pub use const_table::{ConstFunction, ConstIndex, ConstRecord, ConstValue};
+pub use diff::{ConstChange, FunctionDiff, InstructionDiff, ModuleDiff};
+pub use effects::Effects;
+pub use error::{BuilderError, DecodeError, ProgramError, ValidationError};
+pub use function_id::FunctionId;
+pub use indexes::{GlobalIndex, ImportIndex, LocalConstIndex, ModuleConstIndex, MAX_TABLE_LEN};
+pub use inst_policy::{InstructionFamily, InstructionPolicy};
+pub use instructions::{
+    BranchTarget, CallInstruction, CompareOp, Instruction, InstructionList, NumericKind,
+    StackIndex, Truthiness,
+};
+pub use ir::{Block, IrEffect, IrFunction, IrOp, Reg};
+pub use module_encoding::Compression;
+pub use module_set::ModuleSet;
+pub use modules::{ConstModule, ImportSource, ModuleId, ModuleMemberId};
+pub use program::Program;
+
+pub use crate::util::intern::InternStats;
@@ -0,0 +1,693 @@
+//! A versioned byte format for whole modules, so that compiled modules can
+//! be stored and loaded without rebuilding them from text.
+//!
+//! The input starts with the magic bytes `LOON` and a format version. The
+//! id, global table size, initializer, imports, constants, exports, export
+//! docs and tests follow, in that order. Counts, lengths and indexes are
+//! varints, as in the instruction encoding, so a later version can widen
+//! them past `u32` without changing the layout. Exports and docs are written
+//! sorted by name, so equal modules encode to equal bytes, and tests in the
+//! order they were declared. Decoded modules are validated as
+//! [`ConstModule::new`] validates built ones.
+//!
+//! Each function's instruction stream is preceded by a codec byte. Streams
+//! are stored as they are encoded by default. With the `compression` feature,
+//! [`ConstModule::to_bytes_with`] can compress them with LZ4, which mostly
+//! pays off for large generated functions. A stream is only stored
+//! compressed if that makes it smaller, and decoding a compressed stream
+//! without the feature fails.
+//!
+//! Programs start with the magic bytes `LPRG` and their own format version,
+//! followed by the entry point and each module's encoding as a byte string,
+//! in initialization order.
+
+use std::collections::HashMap;
+
+use super::{
+    const_table::{ConstFunction, ConstIndex, ConstRecord, ConstValue},
+    encoding::{write_varint, Reader},
+    error::DecodeError,
+    indexes::{ImportIndex, ModuleConstIndex},
+    instructions::InstructionList,
+    module_set::ModuleSet,
+    modules::{ConstModule, ImportSource, ModuleId, ModuleMemberId},
+    program::Program,
+};
+use crate::{
+    pure_values::{Float, Integer},
+    util::imm_string::ImmBytes,
+};
+
+type Result<T> = std::result::Result<T, DecodeError>;
+
+const MAGIC: &[u8; 4] = b"LOON";
+
+/// The version written by [`ConstModule::to_bytes`]. Decoding rejects any
+/// other.
+const FORMAT_VERSION: u32 = 4;
+
+const PROGRAM_MAGIC: &[u8; 4] = b"LPRG";
+
+/// The version written by [`Program::to_bytes`]. Decoding rejects any other.
+const PROGRAM_FORMAT_VERSION: u32 = 1;
+
+/// How [`ConstModule::to_bytes_with`] stores function instruction streams.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    /// Streams are stored as encoded.
+    #[default]
+    None,
+
+    /// Streams are compressed with LZ4 where that makes them smaller.
+    #[cfg(feature = "compression")]
+    Lz4,
+}
+
+mod codecs {
+    pub const RAW: u8 = 0;
+    pub const LZ4: u8 = 1;
+}
+
+/// The largest ratio LZ4 can compress data by. Streams that claim to expand
+/// by more than this are rejected before anything is allocated for them.
+#[cfg(feature = "compression")]
+const MAX_LZ4_RATIO: usize = 255;
+
+mod const_kinds {
+    pub const BOOL: u8 = 0;
+    pub const INTEGER: u8 = 1;
+    pub const FLOAT: u8 = 2;
+    pub const STRING: u8 = 3;
+    pub const BYTES: u8 = 4;
+    pub const LIST: u8 = 5;
+    pub const FUNCTION: u8 = 6;
+    pub const NULL: u8 = 7;
+    pub const RECORD: u8 = 8;
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    write_varint(
+        out,
+        u32::try_from(len).expect("Module tables are indexed by u32."),
+    );
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_len(out, bytes.len());
+    out.extend_from_slice(bytes);
+}
+
+fn write_module_id(out: &mut Vec<u8>, id: &ModuleId) {
+    write_len(out, id.path().len());
+    for component in id.path() {
+        write_bytes(out, component.as_str().as_bytes());
+    }
+}
+
+/// Const indexes are written as a single varint, with whether they refer to
+/// an import in the low bit.
+fn write_const_index(out: &mut Vec<u8>, index: &ConstIndex) {
+    let (index, is_import) = match index {
+        ConstIndex::ModuleConst(i) => (u64::from(i.index()), 0),
+        ConstIndex::ModuleImport(i) => (u64::from(i.index()), 1),
+    };
+    super::encoding::write_varint_u64(out, index << 1 | is_import);
+}
+
+fn write_const_indexes(out: &mut Vec<u8>, indexes: &[ConstIndex]) {
+    write_len(out, indexes.len());
+    for index in indexes {
+        write_const_index(out, index);
+    }
+}
+
+fn write_instructions(out: &mut Vec<u8>, instructions: &InstructionList, compression: Compression) {
+    let encoded = instructions.encode();
+    match compression {
+        Compression::None => {}
+        #[cfg(feature = "compression")]
+        Compression::Lz4 => {
+            let compressed = lz4_flex::block::compress_prepend_size(&encoded);
+            if compressed.len() < encoded.len() {
+                out.push(codecs::LZ4);
+                write_bytes(out, &compressed);
+                return;
+            }
+        }
+    }
+    out.push(codecs::RAW);
+    write_bytes(out, &encoded);
+}
+
+fn write_const(out: &mut Vec<u8>, value: &ConstValue, compression: Compression) {
+    use const_kinds::*;
+    match value {
+        ConstValue::Null => out.push(NULL),
+        ConstValue::Bool(b) => {
+            out.push(BOOL);
+            out.push(u8::from(*b));
+        }
+        ConstValue::Integer(i) => {
+            out.push(INTEGER);
+            write_bytes(out, &i.to_signed_bytes_le());
+        }
+        ConstValue::Float(f) => {
+            out.push(FLOAT);
+            out.extend_from_slice(&f.to_bits().to_le_bytes());
+        }
+        ConstValue::String(s) => {
+            out.push(STRING);
+            write_bytes(out, s.as_str().as_bytes());
+        }
+        ConstValue::Bytes(b) => {
+            out.push(BYTES);
+            write_bytes(out, b.as_bytes());
+        }
+        ConstValue::List(items) => {
+            out.push(LIST);
+            write_const_indexes(out, items);
+        }
+        ConstValue::Record(record) => {
+            out.push(RECORD);
+            write_len(out, record.fields().len());
+            for (name, value) in record.fields() {
+                write_bytes(out, name.as_str().as_bytes());
+                write_const_index(out, value);
+            }
+        }
+        ConstValue::Function(function) => {
+            out.push(FUNCTION);
+            write_const_indexes(out, function.module_constants());
+            write_instructions(out, function.instructions(), compression);
+            write_varint(out, function.arity().map_or(0, |arity| arity + 1));
+        }
+    }
+}
+
+impl<'a> Reader<'a> {
+    fn read_len(&mut self) -> Result<usize> {
+        Ok(self.read_varint()? as usize)
+    }
+
+    fn read_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.read_len()?;
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_str(&mut self) -> Result<&'a str> {
+        let pos = self.pos;
+        std::str::from_utf8(self.read_bytes()?).map_err(|_| DecodeError::InvalidUtf8(pos))
+    }
+
+    fn read_module_id(&mut self) -> Result<ModuleId> {
+        let len = self.read_len()?;
+        let path = (0..len)
+            .map(|_| self.read_str())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ModuleId::new(path))
+    }
+
+    fn read_instructions(&mut self) -> Result<InstructionList> {
+        let pos = self.pos;
+        match self.read_byte()? {
+            codecs::RAW => InstructionList::decode(self.read_bytes()?),
+            #[cfg(feature = "compression")]
+            codecs::LZ4 => {
+                let pos = self.pos;
+                let corrupt = |_| DecodeError::CorruptCompressedData(pos);
+                let (len, compressed) =
+                    lz4_flex::block::uncompressed_size(self.read_bytes()?).map_err(corrupt)?;
+                if len > compressed.len().saturating_mul(MAX_LZ4_RATIO) {
+                    return Err(DecodeError::CorruptCompressedData(pos));
+                }
+                let mut encoded = vec![0; len];
+                let written =
+                    lz4_flex::block::decompress_into(compressed, &mut encoded).map_err(corrupt)?;
+                if written != len {
+                    return Err(DecodeError::CorruptCompressedData(pos));
+                }
+                InstructionList::decode(&encoded)
+            }
+            #[cfg(not(feature = "compression"))]
+            codecs::LZ4 => Err(DecodeError::UnsupportedCompression(pos)),
+            codec => Err(DecodeError::UnknownCodec(codec, pos)),
+        }
+    }
+
+    fn read_const_index(&mut self) -> Result<ConstIndex> {
+        let pos = self.pos;
+        let value = self.read_varint_u64()?;
+        let index =
+            u32::try_from(value >> 1).map_err(|_| DecodeError::IndexOutOfRange(value >> 1, pos))?;
+        Ok(if value & 1 == 0 {
+            ConstIndex::ModuleConst(ModuleConstIndex::new(index))
+        } else {
+            ConstIndex::ModuleImport(ImportIndex::new(index))
+        })
+    }
+
+    fn read_const_indexes(&mut self) -> Result<Vec<ConstIndex>> {
+        let len = self.read_len()?;
+        (0..len).map(|_| self.read_const_index()).collect()
+    }
+
+    fn read_const(&mut self) -> Result<ConstValue> {
+        use const_kinds::*;
+        let pos = self.pos;
+        Ok(match self.read_byte()? {
+            NULL => ConstValue::Null,
+            BOOL => ConstValue::Bool(self.read_byte()? != 0),
+            INTEGER => ConstValue::Integer(Integer::from_signed_bytes_le(self.read_bytes()?)),
+            FLOAT => {
+                let mut bits = [0; 8];
+                for byte in &mut bits {
+                    *byte = self.read_byte()?;
+                }
+                ConstValue::Float(Float::from_bits(u64::from_le_bytes(bits)))
+            }
+            STRING => ConstValue::String(self.read_str()?.into()),
+            BYTES => ConstValue::Bytes(ImmBytes::from(self.read_bytes()?)),
+            LIST => ConstValue::List(self.read_const_indexes()?),
+            RECORD => {
+                let len = self.read_len()?;
+                let fields = (0..len)
+                    .map(|_| Ok((self.read_str()?.into(), self.read_const_index()?)))
+                    .collect::<Result<Vec<_>>>()?;
+                ConstValue::Record(ConstRecord::new(fields))
+            }
+            FUNCTION => {
+                let module_constants = self.read_const_indexes()?;
+                let instructions = self.read_instructions()?;
+                let arity = self.read_varint()?.checked_sub(1);
+                ConstValue::Function(
+                    ConstFunction::new(module_constants, instructions).with_arity(arity),
+                )
+            }
+            kind => return Err(DecodeError::UnknownConstKind(kind, pos)),
+        })
+    }
+}
+
+/// Returns the entries of `map` sorted by name, for a stable encoding.
+fn sorted_by_name<V>(map: &HashMap<ModuleMemberId, V>) -> Vec<(&ModuleMemberId, &V)> {
+    let mut entries = map.iter().collect::<Vec<_>>();
+    entries.sort_by_key(|(name, _)| *name);
+    entries
+}
+
+impl ConstModule {
+    /// Encodes the module into the versioned byte format described in this
+    /// module's documentation.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with(Compression::None)
+    }
+
+    /// Encodes the module like [`ConstModule::to_bytes`], storing function
+    /// instruction streams as `compression` asks.
+    #[must_use]
+    pub fn to_bytes_with(&self, compression: Compression) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        write_varint(&mut out, FORMAT_VERSION);
+        write_module_id(&mut out, self.id());
+        write_varint(&mut out, self.global_table_size());
+        write_varint(&mut out, self.initializer().map_or(0, |i| i.index() + 1));
+
+        write_len(&mut out, self.imports().len());
+        for import in self.imports() {
+            write_module_id(&mut out, import.module_id());
+            write_bytes(&mut out, import.import_name().as_str().as_bytes());
+            out.push(u8::from(import.is_optional()));
+        }
+
+        write_len(&mut out, self.const_table().len());
+        for value in self.const_table() {
+            write_const(&mut out, value, compression);
+        }
+
+        let exports = sorted_by_name(self.exports());
+        write_len(&mut out, exports.len());
+        for (name, index) in exports {
+            write_bytes(&mut out, name.as_str().as_bytes());
+            write_varint(&mut out, index.index());
+        }
+
+        let docs = sorted_by_name(self.export_docs());
+        write_len(&mut out, docs.len());
+        for (name, doc) in docs {
+            write_bytes(&mut out, name.as_str().as_bytes());
+            write_bytes(&mut out, doc.as_bytes());
+        }
+
+        write_len(&mut out, self.tests().len());
+        for (name, index) in self.tests() {
+            write_bytes(&mut out, name.as_bytes());
+            write_varint(&mut out, index.index());
+        }
+        out
+    }
+
+    /// Decodes and validates a module written by [`ConstModule::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(DecodeError::BadMagic);
+        }
+        let mut reader = Reader::new(bytes);
+        reader.pos = MAGIC.len();
+        let version = reader.read_varint()?;
+        if version != FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let id = reader.read_module_id()?;
+        let global_table_size = reader.read_varint()?;
+        let initializer = reader
+            .read_varint()?
+            .checked_sub(1)
+            .map(ModuleConstIndex::new);
+
+        let num_imports = reader.read_len()?;
+        let imports = (0..num_imports)
+            .map(|_| {
+                let module_id = reader.read_module_id()?;
+                let name = reader.read_str()?;
+                Ok(if reader.read_byte()? != 0 {
+                    ImportSource::new_optional(module_id, name)
+                } else {
+                    ImportSource::new(module_id, name)
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let num_consts = reader.read_len()?;
+        let const_table = (0..num_consts)
+            .map(|_| reader.read_const())
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut exports = HashMap::new();
+        for _ in 0..reader.read_len()? {
+            let name = reader.read_str()?;
+            let index = ModuleConstIndex::new(reader.read_varint()?);
+            if exports.insert(ModuleMemberId::new(name), index).is_some() {
+                return Err(DecodeError::DuplicateExport(name.to_string()));
+            }
+        }
+
+        let mut export_docs = HashMap::new();
+        for _ in 0..reader.read_len()? {
+            let name = reader.read_str()?;
+            let doc = reader.read_str()?;
+            export_docs.insert(ModuleMemberId::new(name), doc.to_string());
+        }
+
+        let mut tests: Vec<(String, ModuleConstIndex)> = Vec::new();
+        for _ in 0..reader.read_len()? {
+            let name = reader.read_str()?;
+            let index = ModuleConstIndex::new(reader.read_varint()?);
+            if tests.iter().any(|(existing, _)| existing == name) {
+                return Err(DecodeError::DuplicateTest(name.to_string()));
+            }
+            tests.push((name.to_string(), index));
+        }
+
+        if reader.pos != bytes.len() {
+            return Err(DecodeError::TrailingData(reader.pos));
+        }
+        let module = ConstModule::new(
+            id,
+            const_table,
+            imports,
+            exports,
+            initializer,
+            global_table_size,
+        )?;
+        Ok(module.with_export_docs(export_docs).with_tests(tests)?)
+    }
+}
+
+impl Program {
+    /// Encodes the program into the versioned byte format described in this
+    /// module's documentation.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with(Compression::None)
+    }
+
+    /// Encodes the program like [`Program::to_bytes`], storing function
+    /// instruction streams as `compression` asks.
+    #[must_use]
+    pub fn to_bytes_with(&self, compression: Compression) -> Vec<u8> {
+        let mut out = PROGRAM_MAGIC.to_vec();
+        write_varint(&mut out, PROGRAM_FORMAT_VERSION);
+        write_module_id(&mut out, self.entry().module_id());
+        write_bytes(&mut out, self.entry().import_name().as_str().as_bytes());
+        write_len(&mut out, self.init_order().len());
+        for id in self.init_order() {
+            let module = self
+                .modules()
+                .module(id)
+                .expect("The initialization order lists the set's modules.");
+            write_bytes(&mut out, &module.to_bytes_with(compression));
+        }
+        out
+    }
+
+    /// Decodes and validates a program written by [`Program::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(PROGRAM_MAGIC) {
+            return Err(DecodeError::BadMagic);
+        }
+        let mut reader = Reader::new(bytes);
+        reader.pos = PROGRAM_MAGIC.len();
+        let version = reader.read_varint()?;
+        if version != PROGRAM_FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+        let entry_module = reader.read_module_id()?;
+        let entry = ImportSource::new(entry_module, reader.read_str()?);
+
+        let mut modules: Vec<ConstModule> = Vec::new();
+        for _ in 0..reader.read_len()? {
+            let module = ConstModule::from_bytes(reader.read_bytes()?)?;
+            if modules.iter().any(|existing| existing.id() == module.id()) {
+                return Err(DecodeError::DuplicateModule(module.id().to_string()));
+            }
+            modules.push(module);
+        }
+
+        if reader.pos != bytes.len() {
+            return Err(DecodeError::TrailingData(reader.pos));
+        }
+        let modules = ModuleSet::try_new(modules).ok_or(DecodeError::CyclicDependencies)?;
+        Ok(Program::new(modules, entry)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MODULE_SET: &str = r#"
+        (module-set
+            ("app.main"
+                (import print "std.io" print)
+                (import log "std.log" log #:optional)
+                (global counter)
+                (const big 123456789012345678901234567890)
+                (const negative -5)
+                (const half 0.5)
+                (const name "café")
+                (const blob (bytes #u8(0 1 255)))
+                (const items (list big half name print))
+                (const point (record (x big) (y half)))
+                (const run
+                    (fn
+                        (params)
+                        (push items)
+                        (push print)
+                        (pop 2)
+                        (return 0))
+                    (doc "Runs the app."))
+                (init
+                    (push counter)
+                    (pop 1)
+                    (return 0))
+                (export run)
+                (export big)
+                (export negative)
+                (test "run succeeds" run)))
+    "#;
+
+    fn sample() -> anyhow::Result<ConstModule> {
+        let module_set = crate::lat::from_str(MODULE_SET)?;
+        let module = module_set.modules().next().unwrap();
+        Ok(ConstModule::from_bytes(&module.to_bytes())?)
+    }
+
+    #[test]
+    fn modules_round_trip() -> anyhow::Result<()> {
+        let module_set = crate::lat::from_str(MODULE_SET)?;
+        let module = module_set.modules().next().unwrap();
+        let bytes = module.to_bytes();
+        let decoded = ConstModule::from_bytes(&bytes)?;
+        assert!(module.diff(&decoded).is_empty());
+        assert_eq!(decoded.id(), module.id());
+        assert_eq!(decoded.imports(), module.imports());
+        assert_eq!(decoded.exports(), module.exports());
+        assert_eq!(decoded.export_docs(), module.export_docs());
+        assert_eq!(decoded.tests(), module.tests());
+        assert_eq!(decoded.initializer(), module.initializer());
+        assert_eq!(decoded.global_table_size(), module.global_table_size());
+        // Encoding does not depend on the order of hash maps.
+        assert_eq!(decoded.to_bytes(), bytes);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_modules_are_rejected() -> anyhow::Result<()> {
+        let bytes = sample()?.to_bytes();
+        assert!(matches!(
+            ConstModule::from_bytes(b"NOPE"),
+            Err(DecodeError::BadMagic)
+        ));
+        let mut future = MAGIC.to_vec();
+        future.push(FORMAT_VERSION as u8 + 1);
+        assert!(matches!(
+            ConstModule::from_bytes(&future),
+            Err(DecodeError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1
+        ));
+        assert!(matches!(
+            ConstModule::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        ));
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            ConstModule::from_bytes(&trailing),
+            Err(DecodeError::TrailingData(pos)) if pos == bytes.len()
+        ));
+
+        // A module whose export points past the end of its const table.
+        let module = ConstModule::new(
+            ModuleId::new(["m"]),
+            vec![ConstValue::Bool(true)],
+            Vec::new(),
+            HashMap::from([(ModuleMemberId::new("x"), ModuleConstIndex::new(0))]),
+            None,
+            0,
+        )?;
+        let mut bytes = module.to_bytes();
+        // The export's index is followed by the empty doc and test counts.
+        let export_index = bytes.len() - 3;
+        assert_eq!(bytes[export_index], 0);
+        bytes[export_index] = 1;
+        assert!(matches!(
+            ConstModule::from_bytes(&bytes),
+            Err(DecodeError::Validation(_))
+        ));
+        Ok(())
+    }
+
+    /// Returns a module of similar functions, as a code generator might
+    /// produce, each repeating the same computation `repeats` times.
+    fn generated_module(functions: usize, repeats: usize) -> anyhow::Result<ConstModule> {
+        let consts = (0..functions)
+            .map(|i| {
+                let body = "(push a) (push b) (add) (push 3) (mul) (pop 1)".repeat(repeats);
+                format!("(const f{i} (fn (params a b) {body} (push a) (return 1)))")
+            })
+            .collect::<String>();
+        let module_set =
+            crate::lat::from_str(&format!(r#"(module-set ("gen" {consts} (export f0)))"#))?;
+        let module = module_set.modules().next().unwrap().clone();
+        Ok(module)
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_modules_round_trip() -> anyhow::Result<()> {
+        let module = generated_module(4, 100)?;
+        let raw = module.to_bytes();
+        let compressed = module.to_bytes_with(Compression::Lz4);
+        assert!(compressed.len() < raw.len());
+        let decoded = ConstModule::from_bytes(&compressed)?;
+        assert!(module.diff(&decoded).is_empty());
+        assert_eq!(decoded.to_bytes(), raw);
+
+        // Streams too small to benefit are left uncompressed.
+        let sample = sample()?;
+        assert_eq!(sample.to_bytes_with(Compression::Lz4), sample.to_bytes());
+        Ok(())
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn corrupt_compressed_streams_are_rejected() -> anyhow::Result<()> {
+        let module = generated_module(1, 100)?;
+        let raw = module.to_bytes();
+        let compressed = module.to_bytes_with(Compression::Lz4);
+        // The encodings first differ at the function's codec byte.
+        let codec_pos = raw
+            .iter()
+            .zip(&compressed)
+            .position(|(a, b)| a != b)
+            .unwrap();
+
+        let mut bytes = compressed.clone();
+        bytes[codec_pos] = 9;
+        assert!(matches!(
+            ConstModule::from_bytes(&bytes),
+            Err(DecodeError::UnknownCodec(9, pos)) if pos == codec_pos
+        ));
+
+        // A stream that claims to expand by more than LZ4 can.
+        let mut reader = Reader::new(&compressed);
+        reader.pos = codec_pos + 1;
+        reader.read_len()?;
+        let mut bytes = compressed.clone();
+        bytes[reader.pos..reader.pos + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            ConstModule::from_bytes(&bytes),
+            Err(DecodeError::CorruptCompressedData(_))
+        ));
+        Ok(())
+    }
+
+    /// Prints the encoded size of generated modules with each compression,
+    /// as in:
+    ///
+    /// ```text
+    /// cargo test --features compression module_size_report -- --ignored --nocapture
+    /// ```
+    #[test]
+    #[ignore]
+    fn module_size_report() -> anyhow::Result<()> {
+        #[cfg_attr(not(feature = "compression"), allow(unused_mut))]
+        let mut compressions = vec![Compression::None];
+        #[cfg(feature = "compression")]
+        compressions.push(Compression::Lz4);
+        println!(
+            "{:>10} {:>8} {:>12} {:>10}",
+            "functions", "repeats", "compression", "bytes"
+        );
+        for (functions, repeats) in [(10, 1), (10, 50), (100, 10), (1000, 5)] {
+            let module = generated_module(functions, repeats)?;
+            for &compression in &compressions {
+                let bytes = module.to_bytes_with(compression);
+                assert!(ConstModule::from_bytes(&bytes).is_ok());
+                println!(
+                    "{functions:>10} {repeats:>8} {:>12} {:>10}",
+                    format!("{compression:?}"),
+                    bytes.len()
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use super::{modules::ModuleId, ConstModule};
+use super::{call_graph::CallGraph, modules::ModuleId, ConstModule};
 
 fn detect_cycles<T>(edges: HashMap<T, Vec<T>>) -> bool
 where
@@ -40,6 +40,12 @@ pub struct ModuleSet {
 
 impl ModuleSet {
     pub fn new(modules: impl IntoIterator<Item = ConstModule>) -> Self {
+        Self::try_new(modules).expect("Cyclic module dependencies detected.")
+    }
+
+    /// Creates a set like [`Self::new`], returning `None` instead of
+    /// panicking if the modules' dependencies form a cycle.
+    pub(crate) fn try_new(modules: impl IntoIterator<Item = ConstModule>) -> Option<Self> {
         let modules: HashMap<ModuleId, ConstModule> = modules
             .into_iter()
             .map(|module| (module.id().clone(), module))
@@ -51,20 +57,65 @@ impl ModuleSet {
             .collect();
 
         if detect_cycles(dependency_edges) {
-            panic!("Cyclic module dependencies detected.");
+            return None;
         }
 
-        Self { modules }
+        Some(Self { modules })
     }
 
     pub fn external_dependencies(&self) -> impl Iterator<Item = &ModuleId> {
         self.modules
             .values()
-            .flat_map(|module| module.dependencies())
+            .flat_map(|module| module.required_dependencies())
             .filter(|id| !self.modules.contains_key(id))
     }
 
     pub fn modules(&self) -> impl Iterator<Item = &ConstModule> {
         self.modules.values()
     }
+
+    pub fn module(&self, id: &ModuleId) -> Option<&ConstModule> {
+        self.modules.get(id)
+    }
+
+    /// Orders the modules of the set so that each comes after the modules in
+    /// the set that it imports from. Modules are visited in id order, so the
+    /// result does not depend on how the set stores them.
+    #[must_use]
+    pub fn dependency_order(&self) -> Vec<&ConstModule> {
+        fn visit<'a>(
+            module_set: &'a ModuleSet,
+            module: &'a ConstModule,
+            visited: &mut HashSet<&'a ModuleId>,
+            order: &mut Vec<&'a ConstModule>,
+        ) {
+            if !visited.insert(module.id()) {
+                return;
+            }
+            let mut dependencies: Vec<_> = module
+                .dependencies()
+                .filter_map(|dependency| module_set.module(dependency))
+                .collect();
+            dependencies.sort_by_key(|dependency| dependency.id());
+            for dependency in dependencies {
+                visit(module_set, dependency, visited, order);
+            }
+            order.push(module);
+        }
+
+        let mut modules: Vec<_> = self.modules().collect();
+        modules.sort_by_key(|module| module.id());
+        let mut visited = HashSet::new();
+        let mut order = Vec::new();
+        for module in modules {
+            visit(self, module, &mut visited, &mut order);
+        }
+        order
+    }
+
+    /// Returns the static references between the functions of this set.
+    #[must_use]
+    pub fn call_graph(&self) -> CallGraph {
+        CallGraph::new(self)
+    }
 }
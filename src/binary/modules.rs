@@ -1,14 +1,33 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
 
 use crate::util::imm_string::ImmString;
 
 use super::{
-    const_table::{ConstIndex, ConstValue},
-    error::ValidationError,
+    const_table::{ConstFunction, ConstIndex, ConstRecord, ConstValue},
+    error::{BuilderError, ValidationError},
+    indexes::{GlobalIndex, ImportIndex, LocalConstIndex, ModuleConstIndex, MAX_TABLE_LEN},
+    inst_policy::InstructionPolicy,
+    instructions::{CallInstruction, Instruction, InstructionList, StackIndex},
+    peephole::pushes_one,
 };
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
-pub struct ModuleId(Rc<Vec<ImmString>>);
+struct ModuleIdInner {
+    /// A hash of `path`, computed once when the id is created.
+    hash: u64,
+    path: Vec<ImmString>,
+}
+
+/// The path that identifies a module, such as `std.fn`.
+///
+/// Ids are cheap to clone, and carry a precomputed hash. Ids that share the
+/// same allocation (e.g. after being interned by the runtime) compare equal
+/// without comparing their paths.
+#[derive(Clone)]
+pub struct ModuleId(Rc<ModuleIdInner>);
 
 impl ModuleId {
     pub fn new<I>(path: I) -> Self
@@ -16,7 +35,73 @@ impl ModuleId {
         I: IntoIterator,
         I::Item: Into<ImmString>,
     {
-        ModuleId(Rc::new(path.into_iter().map(Into::into).collect()))
+        let path: Vec<ImmString> = path.into_iter().map(Into::into).collect();
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        path.hash(&mut hasher);
+        ModuleId(Rc::new(ModuleIdInner {
+            hash: hasher.finish(),
+            path,
+        }))
+    }
+
+    /// Returns the components of the module path.
+    pub fn path(&self) -> &[ImmString] {
+        &self.0.path
+    }
+
+    /// Returns true if both ids share the same allocation.
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Returns true if no other clone of this id exists.
+    pub fn is_unique(&self) -> bool {
+        Rc::strong_count(&self.0) == 1
+    }
+}
+
+impl PartialEq for ModuleId {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other) || (self.0.hash == other.0.hash && self.0.path == other.0.path)
+    }
+}
+
+impl Eq for ModuleId {}
+
+impl PartialOrd for ModuleId {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ModuleId {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.path.cmp(&other.0.path)
+    }
+}
+
+impl Hash for ModuleId {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash);
+    }
+}
+
+impl std::fmt::Display for ModuleId {
+    /// Writes the path with `.` separators, e.g. `std.fn`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, part) in self.0.path.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            f.write_str(part.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for ModuleId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("ModuleId").field(&self.0.path).finish()
     }
 }
 
@@ -40,6 +125,15 @@ impl ModuleMemberId {
     {
         ModuleMemberId(name.into())
     }
+
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+
+    /// Returns true if no other clone of this name exists.
+    pub fn is_unique(&self) -> bool {
+        self.0.is_unique()
+    }
 }
 
 impl<T> From<T> for ModuleMemberId
@@ -55,6 +149,7 @@ where
 pub struct ImportSource {
     module_id: ModuleId,
     import_name: ModuleMemberId,
+    optional: bool,
 }
 
 impl ImportSource {
@@ -62,9 +157,26 @@ impl ImportSource {
         ImportSource {
             module_id: module_id.into(),
             import_name: import_name.into(),
+            optional: false,
+        }
+    }
+
+    /// Creates an import that resolves to null if the source module is not
+    /// loaded, instead of failing to load the importing module.
+    pub fn new_optional(
+        module_id: impl Into<ModuleId>,
+        import_name: impl Into<ModuleMemberId>,
+    ) -> Self {
+        ImportSource {
+            optional: true,
+            ..ImportSource::new(module_id, import_name)
         }
     }
 
+    pub fn is_optional(&self) -> bool {
+        self.optional
+    }
+
     pub fn module_id(&self) -> &ModuleId {
         &self.module_id
     }
@@ -76,38 +188,226 @@ impl ImportSource {
 
 /// Check that the constant values are valid, and return the set of constraints
 /// the table has to meet.
+///
+/// Every index a constant holds must be in range: list items, record fields
+/// and function constants must name an existing constant or import, and the
+/// operands of `PushConst`, the `Record*` instructions, `TagNew`, `PushGlobal`
+/// and `PopGlobal` must name an existing function constant or global. Records
+/// may not declare a field twice.
 pub fn validate_module(
     table_elements: &[ConstValue],
-    _globals_size: u32,
+    globals_size: u32,
     imports_size: u32,
 ) -> Result<(), ValidationError> {
-    let check_index = |index: &ConstIndex| {
-        match index {
-            ConstIndex::ModuleConst(i) => {
-                if *i >= table_elements.len() as u32 {
-                    return Err(ValidationError::LocalIndexResolutionError);
-                }
-            }
-            ConstIndex::ModuleImport(i) => {
-                if *i >= imports_size {
-                    return Err(ValidationError::LocalIndexResolutionError);
-                }
-            }
+    check_table_len("constant", None, table_elements.len())?;
+    let check_index = |table_index: ModuleConstIndex, index: &ConstIndex| {
+        let valid = match index {
+            ConstIndex::ModuleConst(i) => i.is_within(table_elements.len()),
+            ConstIndex::ModuleImport(i) => i.index() < imports_size,
+        };
+        if !valid {
+            return Err(ValidationError::LocalIndexResolutionError {
+                table_index,
+                index: index.clone(),
+            });
         }
         Ok(())
     };
 
-    for value in table_elements {
+    for (table_index, value) in table_elements.iter().enumerate() {
+        let table_index =
+            ModuleConstIndex::from_usize(table_index).expect("Const tables are indexed by u32.");
         match value {
             ConstValue::List(list) => {
                 for index in list {
-                    check_index(index)?;
+                    check_index(table_index, index)?;
+                }
+            }
+            ConstValue::Record(record) => {
+                for (_, index) in record.fields() {
+                    check_index(table_index, index)?;
+                }
+                if let Some(field) = record.duplicate_field() {
+                    return Err(ValidationError::DuplicateRecordField {
+                        table_index,
+                        field: field.as_str().to_string(),
+                    });
+                }
+            }
+            ConstValue::Function(function) => {
+                check_table_len(
+                    "function constant",
+                    Some(table_index),
+                    function.module_constants().len(),
+                )?;
+                check_table_len(
+                    "instruction",
+                    Some(table_index),
+                    function.instructions().instructions().len(),
+                )?;
+                for index in function.module_constants() {
+                    check_index(table_index, index)?;
                 }
+                check_operands(
+                    table_index,
+                    function.instructions(),
+                    function.module_constants().len(),
+                    globals_size,
+                )?;
+                check_bound_arities(table_elements, table_index, function)?;
             }
-            ConstValue::Function(_) => {
-                // FIXME: Const tables should preserve the enviroment they
-                // expect, to allow for validation outside of the context of
-                // building the const table.
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Checks that the operands of `instructions`, those of the function at
+/// `table_index`, name one of its `num_constants` constants or one of the
+/// module's `globals_size` globals, and that its branches stay within it.
+fn check_operands(
+    table_index: ModuleConstIndex,
+    instructions: &InstructionList,
+    num_constants: usize,
+    globals_size: u32,
+) -> Result<(), ValidationError> {
+    let invalid = instructions
+        .instructions()
+        .iter()
+        .position(|inst| match inst {
+            Instruction::PushConst(i)
+            | Instruction::RecordNew(i)
+            | Instruction::RecordGet(i)
+            | Instruction::RecordSet(i)
+            | Instruction::TagNew(i) => !i.is_within(num_constants),
+            Instruction::PushGlobal(i) | Instruction::PopGlobal(i) => i.index() >= globals_size,
+            _ => false,
+        });
+    if let Some(pc) = invalid {
+        return Err(ValidationError::InvalidOperand {
+            table_index,
+            pc: pc as u32,
+        });
+    }
+    let len = instructions.instructions().len();
+    let invalid_branch = instructions
+        .instructions()
+        .iter()
+        .enumerate()
+        .find_map(|(pc, inst)| {
+            let target = inst.branch_target()?.target_index();
+            (target as usize >= len).then_some((pc, target))
+        });
+    if let Some((pc, target)) = invalid_branch {
+        return Err(ValidationError::InvalidBranchTarget {
+            table_index,
+            pc: pc as u32,
+            target,
+        });
+    }
+    Ok(())
+}
+
+/// Checks instructions that are to replace those of the function at
+/// `table_index`, such as an optimized variant. As when the module was
+/// validated, their operands must be valid for the function's
+/// `num_constants` constants and the module's `globals_size` globals, and
+/// `policy`, if the module has one, must allow each of them.
+pub(crate) fn validate_replacement_instructions(
+    table_index: ModuleConstIndex,
+    instructions: &InstructionList,
+    num_constants: usize,
+    globals_size: u32,
+    policy: Option<&InstructionPolicy>,
+) -> Result<(), ValidationError> {
+    check_table_len(
+        "instruction",
+        Some(table_index),
+        instructions.instructions().len(),
+    )?;
+    check_operands(table_index, instructions, num_constants, globals_size)?;
+    let denied = policy.and_then(|policy| {
+        instructions
+            .instructions()
+            .iter()
+            .position(|inst| !policy.allows(inst))
+    });
+    if let Some(pc) = denied {
+        return Err(ValidationError::DeniedInstruction {
+            table_index,
+            export_name: None,
+            pc: pc as u32,
+            family: instructions.instructions()[pc].family(),
+        });
+    }
+    Ok(())
+}
+
+/// Fails if a table has more entries than can be indexed.
+fn check_table_len(
+    table: &'static str,
+    table_index: Option<ModuleConstIndex>,
+    len: usize,
+) -> Result<(), ValidationError> {
+    if len > MAX_TABLE_LEN {
+        return Err(ValidationError::TableTooLarge {
+            table,
+            table_index,
+            len,
+        });
+    }
+    Ok(())
+}
+
+/// Checks the `BindFront` instructions of `function` whose target is a
+/// function constant with a declared arity. Only targets pushed directly
+/// before the bound values, with no branch landing between them, are known
+/// statically; the rest are checked when the closure is made.
+fn check_bound_arities(
+    table_elements: &[ConstValue],
+    table_index: ModuleConstIndex,
+    function: &ConstFunction,
+) -> Result<(), ValidationError> {
+    let instructions = function.instructions().instructions();
+    let branch_targets = instructions
+        .iter()
+        .filter_map(Instruction::branch_target)
+        .map(|target| target.target_index() as usize)
+        .collect::<HashSet<_>>();
+    for (pc, inst) in instructions.iter().enumerate() {
+        let Instruction::BindFront(num_bound) = inst else {
+            continue;
+        };
+        let Some(target_pc) = pc.checked_sub(*num_bound as usize + 1) else {
+            continue;
+        };
+        let bound_values = &instructions[target_pc + 1..pc];
+        if !bound_values.iter().all(pushes_one)
+            || (target_pc + 1..=pc).any(|i| branch_targets.contains(&i))
+        {
+            continue;
+        }
+        let Instruction::PushConst(local_index) = &instructions[target_pc] else {
+            continue;
+        };
+        let target_index = local_index
+            .get(function.module_constants())
+            .and_then(ConstIndex::as_module_const);
+        let Some(target_index) = target_index else {
+            continue;
+        };
+        let Some(ConstValue::Function(target)) = target_index.get(table_elements) else {
+            continue;
+        };
+        match target.arity() {
+            Some(arity) if *num_bound > arity => {
+                return Err(ValidationError::TooManyBoundArguments {
+                    table_index,
+                    pc: pc as u32,
+                    target_index,
+                    arity,
+                    bound: *num_bound,
+                });
             }
             _ => {}
         }
@@ -115,6 +415,68 @@ pub fn validate_module(
     Ok(())
 }
 
+/// Check that the exports and the initializer refer to existing constants.
+fn validate_members(
+    table_elements: &[ConstValue],
+    exports: &HashMap<ModuleMemberId, ModuleConstIndex>,
+    initializer: Option<ModuleConstIndex>,
+) -> Result<(), ValidationError> {
+    let mut invalid_exports = exports
+        .iter()
+        .filter(|(_, index)| !index.is_within(table_elements.len()))
+        .collect::<Vec<_>>();
+    invalid_exports.sort_unstable();
+    if let Some((name, index)) = invalid_exports.first() {
+        return Err(ValidationError::InvalidExport {
+            export_name: name.as_str().to_string(),
+            index: **index,
+        });
+    }
+    match initializer {
+        Some(index) if !index.is_within(table_elements.len()) => {
+            Err(ValidationError::InvalidInitializer(index))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that every function in the constant table only uses instructions
+/// allowed by `policy`. `exports` is used to name the offending function.
+pub fn validate_instructions(
+    table_elements: &[ConstValue],
+    exports: &HashMap<ModuleMemberId, ModuleConstIndex>,
+    policy: &InstructionPolicy,
+) -> Result<(), ValidationError> {
+    for (table_index, value) in table_elements.iter().enumerate() {
+        let ConstValue::Function(function) = value else {
+            continue;
+        };
+        let denied = function
+            .instructions()
+            .instructions()
+            .iter()
+            .position(|inst| !policy.allows(inst));
+        if let Some(pc) = denied {
+            let table_index = ModuleConstIndex::from_usize(table_index)
+                .expect("Const tables are indexed by u32.");
+            let mut export_names = exports
+                .iter()
+                .filter(|(_, index)| **index == table_index)
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>();
+            export_names.sort_unstable();
+            return Err(ValidationError::DeniedInstruction {
+                table_index,
+                export_name: export_names.first().map(|name| name.to_string()),
+                pc: pc as u32,
+                family: function.instructions().instructions()[pc].family(),
+            });
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone)]
 pub struct ConstModule {
     /// The unique identifier for this module.
     id: ModuleId,
@@ -129,12 +491,23 @@ pub struct ConstModule {
     imports: Vec<ImportSource>,
 
     /// Exports from this module. Values are indexes into the const table.
-    exports: HashMap<ModuleMemberId, u32>,
+    exports: HashMap<ModuleMemberId, ModuleConstIndex>,
+
+    /// Documentation strings for exports. This is metadata only, and has no
+    /// effect on how the module is loaded or run. Loaded modules expose them
+    /// through `Runtime::export_doc`. There is no
+    /// disassembler yet to print them.
+    export_docs: HashMap<ModuleMemberId, String>,
 
     /// The initializer for this module, if it has one.
     ///
     /// The value is an index into the const table.
-    initializer: Option<u32>,
+    initializer: Option<ModuleConstIndex>,
+
+    /// Unit tests shipped with the module, by name, in the order they were
+    /// declared. Values are indexes into the const table. Tests are not
+    /// exported, and only run when the module's tests are run.
+    tests: Vec<(String, ModuleConstIndex)>,
 
     /// The size of the module global table. At runtime, all globals will start
     /// empty, and will cause an error if read in this state. The initializer
@@ -147,20 +520,53 @@ impl ConstModule {
         id: ModuleId,
         const_table: Vec<ConstValue>,
         imports: Vec<ImportSource>,
-        exports: HashMap<ModuleMemberId, u32>,
-        initializer: Option<u32>,
+        exports: HashMap<ModuleMemberId, ModuleConstIndex>,
+        initializer: Option<ModuleConstIndex>,
         global_table_size: u32,
     ) -> Result<Self, ValidationError> {
-        validate_module(&const_table, global_table_size, imports.len() as u32)?;
+        check_table_len("import", None, imports.len())?;
+        let num_imports = u32::try_from(imports.len()).expect("Import tables are indexed by u32.");
+        validate_module(&const_table, global_table_size, num_imports)?;
+        validate_members(&const_table, &exports, initializer)?;
         Ok(ConstModule {
             id,
             const_table,
             imports,
             exports,
+            export_docs: HashMap::new(),
             initializer,
+            tests: Vec::new(),
             global_table_size,
         })
     }
+
+    /// Returns this module with the given documentation strings attached to
+    /// its exports, replacing any previous ones.
+    #[must_use]
+    pub fn with_export_docs(mut self, export_docs: HashMap<ModuleMemberId, String>) -> Self {
+        self.export_docs = export_docs;
+        self
+    }
+
+    /// Returns this module with the given unit tests, replacing any previous
+    /// ones. Fails if a test refers to a constant that does not exist.
+    pub fn with_tests(
+        mut self,
+        tests: Vec<(String, ModuleConstIndex)>,
+    ) -> Result<Self, ValidationError> {
+        if let Some((name, index)) = tests
+            .iter()
+            .find(|(_, index)| !index.is_within(self.const_table.len()))
+        {
+            return Err(ValidationError::InvalidTest {
+                test_name: name.clone(),
+                index: *index,
+            });
+        }
+        self.tests = tests;
+        Ok(self)
+    }
+
     pub fn id(&self) -> &ModuleId {
         &self.id
     }
@@ -170,16 +576,522 @@ impl ConstModule {
     pub fn imports(&self) -> &[ImportSource] {
         &self.imports
     }
-    pub fn exports(&self) -> &HashMap<ModuleMemberId, u32> {
+    pub fn exports(&self) -> &HashMap<ModuleMemberId, ModuleConstIndex> {
         &self.exports
     }
+    /// Returns the documentation string of the named export, if it has one.
+    pub fn export_doc(&self, name: &ModuleMemberId) -> Option<&str> {
+        self.export_docs.get(name).map(String::as_str)
+    }
+    pub fn export_docs(&self) -> &HashMap<ModuleMemberId, String> {
+        &self.export_docs
+    }
     pub fn global_table_size(&self) -> u32 {
         self.global_table_size
     }
-    pub fn initializer(&self) -> Option<u32> {
+    pub fn initializer(&self) -> Option<ModuleConstIndex> {
         self.initializer
     }
+    /// Returns the module's unit tests, in the order they were declared.
+    pub fn tests(&self) -> &[(String, ModuleConstIndex)] {
+        &self.tests
+    }
     pub fn dependencies(&self) -> impl Iterator<Item = &ModuleId> {
         self.imports.iter().map(|import| import.module_id())
     }
+
+    /// Returns the modules that must be loaded before this one, which
+    /// excludes those only imported optionally.
+    pub fn required_dependencies(&self) -> impl Iterator<Item = &ModuleId> {
+        self.imports
+            .iter()
+            .filter(|import| !import.is_optional())
+            .map(|import| import.module_id())
+    }
+
+    /// Checks that the module's functions only use instructions allowed by
+    /// `policy`.
+    pub fn validate_instructions(&self, policy: &InstructionPolicy) -> Result<(), ValidationError> {
+        validate_instructions(&self.const_table, &self.exports, policy)
+    }
+
+    /// Combines this module with `other`, another fragment of the same module
+    /// that was built separately, e.g. from a different source file.
+    ///
+    /// The constants and globals of `other` are placed after those of this
+    /// module, and imports of the same source are shared. If both fragments
+    /// have an initializer, the merged module runs this module's and then
+    /// `other`'s, passing each the arguments the module was initialized with.
+    ///
+    /// Fails with [`BuilderError::MismatchedModule`] if the modules have
+    /// different ids, with [`BuilderError::DuplicateExport`] if both export
+    /// the same name, with [`BuilderError::DuplicateTest`] if both have a
+    /// test of the same name, and with
+    /// [`BuilderError::MismatchedInitializers`] if both have an initializer
+    /// but they take different numbers of arguments.
+    pub fn merge(self, other: ConstModule) -> Result<ConstModule, BuilderError> {
+        if self.id != other.id {
+            return Err(BuilderError::MismatchedModule {
+                module: self.id.to_string(),
+                other: other.id.to_string(),
+            });
+        }
+        if let Some(name) = other
+            .exports
+            .keys()
+            .filter(|name| self.exports.contains_key(*name))
+            .min()
+        {
+            return Err(BuilderError::DuplicateExport(name.as_str().to_string()));
+        }
+
+        let mut imports = self.imports;
+        let mut import_map = Vec::with_capacity(other.imports.len());
+        for import in other.imports {
+            let position = match imports.iter().position(|existing| *existing == import) {
+                Some(position) => position,
+                None => {
+                    imports.push(import);
+                    imports.len() - 1
+                }
+            };
+            import_map.push(ImportIndex::from_usize(position).ok_or(BuilderError::IndexOverflow)?);
+        }
+        let remap = FragmentRemap {
+            const_offset: self.const_table.len(),
+            global_offset: self.global_table_size,
+            import_map,
+        };
+
+        let mut const_table = self.const_table;
+        for value in other.const_table {
+            const_table.push(remap.remap_value(value)?);
+        }
+        let mut exports = self.exports;
+        for (name, index) in other.exports {
+            exports.insert(name, remap.remap_const(index)?);
+        }
+        let mut export_docs = self.export_docs;
+        export_docs.extend(other.export_docs);
+        let mut tests = self.tests;
+        for (name, index) in other.tests {
+            if tests.iter().any(|(existing, _)| *existing == name) {
+                return Err(BuilderError::DuplicateTest(name));
+            }
+            tests.push((name, remap.remap_const(index)?));
+        }
+
+        let other_initializer = other
+            .initializer
+            .map(|index| remap.remap_const(index))
+            .transpose()?;
+        let initializer = match (self.initializer, other_initializer) {
+            (Some(first), Some(second)) => {
+                let first_arity = initializer_arity(&const_table, first);
+                let second_arity = initializer_arity(&const_table, second);
+                if first_arity != second_arity {
+                    return Err(BuilderError::MismatchedInitializers {
+                        first: first_arity,
+                        second: second_arity,
+                    });
+                }
+                let call = CallInstruction {
+                    num_args: first_arity,
+                    num_returns: 0,
+                };
+                // Each initializer is called with copies of the arguments,
+                // which sit at the bottom of the chained initializer's frame.
+                let mut instructions = Vec::new();
+                for local_index in 0..2 {
+                    instructions.push(Instruction::PushConst(LocalConstIndex::new(local_index)));
+                    instructions.extend(
+                        (0..first_arity)
+                            .map(|arg| Instruction::PushCopy(StackIndex::FromBottom(arg))),
+                    );
+                    instructions.push(Instruction::Call(call));
+                }
+                instructions.push(Instruction::Return(0));
+                let chained = ConstFunction::new(
+                    vec![
+                        ConstIndex::ModuleConst(first),
+                        ConstIndex::ModuleConst(second),
+                    ],
+                    InstructionList::new(instructions),
+                )
+                .with_arity(Some(first_arity));
+                let index = ModuleConstIndex::from_usize(const_table.len())
+                    .ok_or(BuilderError::IndexOverflow)?;
+                const_table.push(ConstValue::Function(chained));
+                Some(index)
+            }
+            (first, second) => first.or(second),
+        };
+        let global_table_size = self
+            .global_table_size
+            .checked_add(other.global_table_size)
+            .ok_or(BuilderError::IndexOverflow)?;
+
+        Ok(ConstModule::new(
+            self.id,
+            const_table,
+            imports,
+            exports,
+            initializer,
+            global_table_size,
+        )?
+        .with_export_docs(export_docs)
+        .with_tests(tests)?)
+    }
+
+    /// Returns a copy of this module with its constant table replaced. The
+    /// new table must be valid for the module's globals and imports.
+    pub(crate) fn with_const_table(&self, const_table: Vec<ConstValue>) -> ConstModule {
+        ConstModule {
+            id: self.id.clone(),
+            const_table,
+            imports: self.imports.clone(),
+            exports: self.exports.clone(),
+            export_docs: self.export_docs.clone(),
+            initializer: self.initializer,
+            tests: self.tests.clone(),
+            global_table_size: self.global_table_size,
+        }
+    }
+}
+
+/// Returns the number of arguments the initializer at `index` takes, which is
+/// none unless it declares an arity.
+fn initializer_arity(const_table: &[ConstValue], index: ModuleConstIndex) -> u32 {
+    match index.get(const_table) {
+        Some(ConstValue::Function(function)) => function.arity().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Moves the indexes of a module fragment past those of the module it is
+/// merged into.
+struct FragmentRemap {
+    const_offset: usize,
+    global_offset: u32,
+    /// The merged index of each of the fragment's imports.
+    import_map: Vec<ImportIndex>,
+}
+
+impl FragmentRemap {
+    fn remap_const(&self, index: ModuleConstIndex) -> Result<ModuleConstIndex, BuilderError> {
+        ModuleConstIndex::from_usize(index.as_usize() + self.const_offset)
+            .ok_or(BuilderError::IndexOverflow)
+    }
+
+    fn remap_index(&self, index: &ConstIndex) -> Result<ConstIndex, BuilderError> {
+        Ok(match index {
+            ConstIndex::ModuleConst(index) => ConstIndex::ModuleConst(self.remap_const(*index)?),
+            // The fragment was validated, so its imports are in range.
+            ConstIndex::ModuleImport(index) => {
+                ConstIndex::ModuleImport(self.import_map[index.as_usize()])
+            }
+        })
+    }
+
+    fn remap_global(&self, index: GlobalIndex) -> Result<GlobalIndex, BuilderError> {
+        index
+            .index()
+            .checked_add(self.global_offset)
+            .map(GlobalIndex::new)
+            .ok_or(BuilderError::IndexOverflow)
+    }
+
+    fn remap_value(&self, value: ConstValue) -> Result<ConstValue, BuilderError> {
+        Ok(match value {
+            ConstValue::List(items) => ConstValue::List(
+                items
+                    .iter()
+                    .map(|item| self.remap_index(item))
+                    .collect::<Result<_, _>>()?,
+            ),
+            ConstValue::Record(record) => ConstValue::Record(ConstRecord::new(
+                record
+                    .fields()
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), self.remap_index(value)?)))
+                    .collect::<Result<_, BuilderError>>()?,
+            )),
+            ConstValue::Function(function) => ConstValue::Function(self.remap_function(&function)?),
+            value => value,
+        })
+    }
+
+    fn remap_function(&self, function: &ConstFunction) -> Result<ConstFunction, BuilderError> {
+        let module_constants = function
+            .module_constants()
+            .iter()
+            .map(|index| self.remap_index(index))
+            .collect::<Result<_, _>>()?;
+        let instructions = if self.global_offset == 0 {
+            function.instructions().clone()
+        } else {
+            InstructionList::new(
+                function
+                    .instructions()
+                    .instructions()
+                    .iter()
+                    .map(|inst| {
+                        Ok(match inst {
+                            Instruction::PushGlobal(index) => {
+                                Instruction::PushGlobal(self.remap_global(*index)?)
+                            }
+                            Instruction::PopGlobal(index) => {
+                                Instruction::PopGlobal(self.remap_global(*index)?)
+                            }
+                            inst => inst.clone(),
+                        })
+                    })
+                    .collect::<Result<_, BuilderError>>()?,
+            )
+        };
+        Ok(ConstFunction::new(module_constants, instructions).with_arity(function.arity()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::*;
+    use crate::binary::{
+        error::BuilderError,
+        indexes::{GlobalIndex, ImportIndex, LocalConstIndex},
+        instructions::{BranchTarget, InstructionList},
+    };
+
+    #[test]
+    fn module_ids_compare_by_path() {
+        let a = ModuleId::new(["std", "fn"]);
+        let b = ModuleId::new(["std", "fn"]);
+        assert!(!a.ptr_eq(&b));
+        assert_eq!(a, b);
+        assert_ne!(a, ModuleId::new(["std", "string"]));
+
+        let mut map = HashMap::new();
+        map.insert(a, 1);
+        assert_eq!(map.get(&b), Some(&1));
+    }
+
+    #[test]
+    fn invalid_index_is_reported() {
+        let error = ConstModule::new(
+            ModuleId::new(["test"]),
+            vec![
+                ConstValue::Integer(1.into()),
+                ConstValue::List(vec![
+                    ConstIndex::ModuleConst(ModuleConstIndex::new(0)),
+                    ConstIndex::ModuleImport(ImportIndex::new(3)),
+                ]),
+            ],
+            vec![],
+            HashMap::new(),
+            None,
+            0,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.table_index(), Some(ModuleConstIndex::new(1)));
+        assert_eq!(
+            error.const_index(),
+            Some(&ConstIndex::ModuleImport(ImportIndex::new(3)))
+        );
+    }
+
+    #[test]
+    fn invalid_function_constant_is_reported() {
+        let error = ConstModule::new(
+            ModuleId::new(["test"]),
+            vec![ConstValue::Function(ConstFunction::new(
+                vec![
+                    ConstIndex::ModuleConst(ModuleConstIndex::new(0)),
+                    ConstIndex::ModuleConst(ModuleConstIndex::new(1)),
+                ],
+                InstructionList::new(vec![]),
+            ))],
+            vec![],
+            HashMap::new(),
+            None,
+            0,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(error.table_index(), Some(ModuleConstIndex::new(0)));
+        assert_eq!(
+            error.const_index(),
+            Some(&ConstIndex::ModuleConst(ModuleConstIndex::new(1)))
+        );
+    }
+
+    #[test]
+    fn out_of_range_operands_are_reported() {
+        let function = |instructions| {
+            vec![ConstValue::Function(ConstFunction::new(
+                vec![ConstIndex::ModuleConst(ModuleConstIndex::new(0))],
+                InstructionList::new(instructions),
+            ))]
+        };
+        let validate = |instructions| {
+            ConstModule::new(
+                ModuleId::new(["test"]),
+                function(instructions),
+                vec![],
+                HashMap::new(),
+                None,
+                1,
+            )
+        };
+        assert!(validate(vec![
+            Instruction::PushConst(LocalConstIndex::new(0)),
+            Instruction::PopGlobal(GlobalIndex::new(0)),
+        ])
+        .is_ok());
+        for (instructions, bad_pc) in [
+            (vec![Instruction::PushConst(LocalConstIndex::new(1))], 0),
+            (
+                vec![
+                    Instruction::PushConst(LocalConstIndex::new(0)),
+                    Instruction::PopGlobal(GlobalIndex::new(1)),
+                ],
+                1,
+            ),
+            (vec![Instruction::PushGlobal(GlobalIndex::new(1))], 0),
+        ] {
+            let error = validate(instructions).err().unwrap();
+            assert!(matches!(
+                error,
+                ValidationError::InvalidOperand { table_index, pc }
+                    if table_index == ModuleConstIndex::new(0) && pc == bad_pc
+            ));
+        }
+    }
+
+    #[test]
+    fn branches_past_the_end_are_reported() {
+        let instructions = |target| {
+            InstructionList::new(vec![
+                Instruction::Branch(BranchTarget::new(target)),
+                Instruction::Return(0),
+            ])
+        };
+        let validate = |target| {
+            ConstModule::new(
+                ModuleId::new(["test"]),
+                vec![ConstValue::Function(ConstFunction::new(
+                    vec![],
+                    instructions(target),
+                ))],
+                vec![],
+                HashMap::new(),
+                None,
+                0,
+            )
+        };
+        assert!(validate(1).is_ok());
+        let error = validate(2).err().unwrap();
+        assert!(matches!(
+            error,
+            ValidationError::InvalidBranchTarget { table_index, pc: 0, target: 2 }
+                if table_index == ModuleConstIndex::new(0)
+        ));
+
+        let table_index = ModuleConstIndex::new(0);
+        assert!(
+            validate_replacement_instructions(table_index, &instructions(1), 0, 0, None).is_ok()
+        );
+        assert!(matches!(
+            validate_replacement_instructions(table_index, &instructions(2), 0, 0, None),
+            Err(ValidationError::InvalidBranchTarget {
+                pc: 0,
+                target: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn binding_past_a_declared_arity_is_reported() {
+        let validate = |num_bound: u32, branch_between: bool| {
+            let target =
+                ConstFunction::new(vec![], InstructionList::new(vec![Instruction::Return(0)]))
+                    .with_arity(Some(2));
+            let mut instructions = vec![Instruction::PushConst(LocalConstIndex::new(0))];
+            for _ in 0..num_bound {
+                instructions.push(Instruction::PushConst(LocalConstIndex::new(1)));
+            }
+            if branch_between {
+                instructions.insert(0, Instruction::Branch(BranchTarget::new(2)));
+            }
+            instructions.push(Instruction::BindFront(num_bound));
+            let binder = ConstFunction::new(
+                vec![
+                    ConstIndex::ModuleConst(ModuleConstIndex::new(0)),
+                    ConstIndex::ModuleConst(ModuleConstIndex::new(2)),
+                ],
+                InstructionList::new(instructions),
+            );
+            ConstModule::new(
+                ModuleId::new(["test"]),
+                vec![
+                    ConstValue::Function(target),
+                    ConstValue::Function(binder),
+                    ConstValue::Bool(true),
+                ],
+                vec![],
+                HashMap::new(),
+                None,
+                0,
+            )
+        };
+        assert!(validate(2, false).is_ok());
+        assert!(matches!(
+            validate(3, false),
+            Err(ValidationError::TooManyBoundArguments { table_index, pc: 4, target_index, arity: 2, bound: 3 })
+                if table_index == ModuleConstIndex::new(1) && target_index == ModuleConstIndex::new(0)
+        ));
+        // A branch into the bound values leaves the target unknown until run.
+        assert!(validate(3, true).is_ok());
+    }
+
+    #[test]
+    fn exports_and_initializer_must_name_constants() {
+        let new_module = |exports: Vec<(&str, u32)>, initializer| {
+            ConstModule::new(
+                ModuleId::new(["test"]),
+                vec![ConstValue::Integer(1.into())],
+                vec![],
+                exports
+                    .into_iter()
+                    .map(|(name, index)| (name.into(), ModuleConstIndex::new(index)))
+                    .collect(),
+                initializer,
+                0,
+            )
+        };
+        assert!(new_module(vec![("a", 0)], None).is_ok());
+        assert!(matches!(
+            new_module(vec![("a", 0), ("b", 1)], None),
+            Err(ValidationError::InvalidExport { export_name, .. }) if export_name == "b"
+        ));
+        assert!(matches!(
+            new_module(vec![], Some(ModuleConstIndex::new(1))),
+            Err(ValidationError::InvalidInitializer(_))
+        ));
+    }
+
+    #[test]
+    fn validation_error_is_builder_error_source() {
+        let error = BuilderError::from(ValidationError::LocalIndexResolutionError {
+            table_index: ModuleConstIndex::new(0),
+            index: ConstIndex::ModuleConst(ModuleConstIndex::new(1)),
+        });
+        assert!(error.validation_error().is_some());
+        assert!(error
+            .source()
+            .is_some_and(|source| source.is::<ValidationError>()));
+    }
 }
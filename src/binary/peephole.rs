@@ -0,0 +1,286 @@
+//! Local rewrites of instruction lists that keep their behavior.
+
+use super::{
+    const_table::{ConstFunction, ConstValue},
+    instructions::{BranchTarget, Instruction, InstructionList},
+    modules::ConstModule,
+};
+
+/// Returns true for instructions that push one value without popping any.
+pub(super) fn pushes_one(inst: &Instruction) -> bool {
+    matches!(
+        inst,
+        Instruction::PushConst(_)
+            | Instruction::PushCopy(_)
+            | Instruction::PushGlobal(_)
+            | Instruction::ListNew
+    )
+}
+
+/// Returns whether each index of `instructions`, including the one past the
+/// end, is the target of a branch. Returns `None` if a branch points past
+/// the end.
+fn branch_targets(instructions: &[Instruction]) -> Option<Vec<bool>> {
+    let mut is_target = vec![false; instructions.len() + 1];
+    for target in instructions.iter().filter_map(Instruction::branch_target) {
+        *is_target.get_mut(target.target_index() as usize)? = true;
+    }
+    Some(is_target)
+}
+
+/// Points the branches of a rewritten list at the new positions of their
+/// targets, where `new_index[pc]` is the new position of the instruction
+/// that was at `pc`. Every target must be in range, as checked by
+/// `branch_targets`.
+fn remap_targets(output: &mut [Instruction], new_index: &[usize]) {
+    for target in output.iter_mut().filter_map(Instruction::branch_target_mut) {
+        *target = BranchTarget::new(new_index[target.target_index() as usize] as u32);
+    }
+}
+
+impl InstructionList {
+    /// Returns a copy of this list with values that are pushed only to be
+    /// popped again removed, along with the pops. Only instructions whose
+    /// effects are eliminable are removed, and pushes and pops that are
+    /// branch targets are kept. Returns `None` if nothing could be removed,
+    /// or if a branch points past the end of the list.
+    #[must_use]
+    pub fn eliminate_dead_pushes(&self) -> Option<InstructionList> {
+        let instructions = self.instructions();
+        let is_target = branch_targets(instructions)?;
+
+        // `new_index[pc]` is where the instruction at `pc`, or whatever
+        // replaced it, starts in the new list.
+        let mut new_index = Vec::with_capacity(instructions.len() + 1);
+        let mut output: Vec<Instruction> = Vec::with_capacity(instructions.len());
+        // Whether each instruction in `output` was a branch target. A branch
+        // to a removed push would skip the pushes before it but still run
+        // the shortened pop, so those are kept.
+        let mut output_is_target = Vec::with_capacity(instructions.len());
+        for (pc, inst) in instructions.iter().enumerate() {
+            new_index.push(output.len());
+            let Instruction::Pop(mut count) = inst else {
+                output.push(inst.clone());
+                output_is_target.push(is_target[pc]);
+                continue;
+            };
+            if !is_target[pc] {
+                while count > 0 {
+                    match output.last() {
+                        Some(last)
+                            if pushes_one(last)
+                                && last.effects().is_eliminable()
+                                && !output_is_target.last().copied().unwrap_or(false) =>
+                        {
+                            output.pop();
+                            output_is_target.pop();
+                            count -= 1;
+                        }
+                        _ => break,
+                    }
+                }
+                // Instructions that were removed now start where the next
+                // one will be placed.
+                for index in new_index.iter_mut().rev() {
+                    if *index <= output.len() {
+                        break;
+                    }
+                    *index = output.len();
+                }
+            }
+            if count > 0 {
+                output.push(Instruction::Pop(count));
+                output_is_target.push(is_target[pc]);
+            }
+        }
+        new_index.push(output.len());
+        if output.len() == instructions.len() {
+            return None;
+        }
+
+        remap_targets(&mut output, &new_index);
+        Some(InstructionList::new(output))
+    }
+
+    /// Returns a copy of this list with each `Compare` that is directly
+    /// followed by a `BranchIf` replaced by a single `CmpBranch`. Pairs whose
+    /// `BranchIf` is a branch target are kept. Returns `None` if nothing could
+    /// be fused, or if a branch points past the end of the list.
+    #[must_use]
+    pub fn fuse_compare_branches(&self) -> Option<InstructionList> {
+        let instructions = self.instructions();
+        let is_target = branch_targets(instructions)?;
+        let mut new_index = Vec::with_capacity(instructions.len() + 1);
+        let mut output = Vec::with_capacity(instructions.len());
+        for (pc, inst) in instructions.iter().enumerate() {
+            new_index.push(output.len());
+            if let (Instruction::BranchIf(target), Some(Instruction::Compare(op))) =
+                (inst, output.last())
+            {
+                if !is_target[pc] {
+                    *output.last_mut().unwrap() = Instruction::CmpBranch(*op, *target);
+                    continue;
+                }
+            }
+            output.push(inst.clone());
+        }
+        new_index.push(output.len());
+        if output.len() == instructions.len() {
+            return None;
+        }
+        remap_targets(&mut output, &new_index);
+        Some(InstructionList::new(output))
+    }
+}
+
+impl ConstModule {
+    /// Returns a copy of this module with the peephole rewrites applied to
+    /// each of its functions: dead pushes are removed, then compares are
+    /// fused with the branches that follow them. Returns `None` if no
+    /// function changed.
+    #[must_use]
+    pub fn peephole_optimize(&self) -> Option<ConstModule> {
+        let mut changed = false;
+        let const_table = self
+            .const_table()
+            .iter()
+            .map(|value| {
+                let ConstValue::Function(function) = value else {
+                    return value.clone();
+                };
+                let list = function.instructions();
+                let pruned = list.eliminate_dead_pushes();
+                let pruned_list = pruned.as_ref().unwrap_or(list);
+                match pruned_list.fuse_compare_branches().or(pruned) {
+                    Some(optimized) => {
+                        changed = true;
+                        ConstValue::Function(
+                            ConstFunction::new(function.module_constants().to_vec(), optimized)
+                                .with_arity(function.arity()),
+                        )
+                    }
+                    None => value.clone(),
+                }
+            })
+            .collect();
+        changed.then(|| self.with_const_table(const_table))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{
+        indexes::{GlobalIndex, LocalConstIndex},
+        instructions::{CompareOp, StackIndex},
+    };
+
+    #[test]
+    fn dead_pushes_are_removed_and_targets_remapped() {
+        let list = InstructionList::new(vec![
+            Instruction::PushConst(LocalConstIndex::new(0)),
+            Instruction::ListNew,
+            Instruction::PushGlobal(GlobalIndex::new(0)),
+            Instruction::Pop(3),
+            Instruction::PushCopy(StackIndex::FromTop(0)),
+            Instruction::Pop(1),
+            Instruction::Branch(BranchTarget::new(8)),
+            Instruction::PushConst(LocalConstIndex::new(1)),
+            Instruction::Pop(1),
+            Instruction::Return(0),
+        ]);
+        let optimized = list.eliminate_dead_pushes().unwrap();
+        assert_eq!(
+            optimized.instructions(),
+            &[
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::ListNew,
+                Instruction::PushGlobal(GlobalIndex::new(0)),
+                Instruction::Pop(3),
+                Instruction::Branch(BranchTarget::new(6)),
+                Instruction::PushConst(LocalConstIndex::new(1)),
+                Instruction::Pop(1),
+                Instruction::Return(0),
+            ]
+        );
+        assert!(optimized.eliminate_dead_pushes().is_none());
+    }
+
+    #[test]
+    fn pushes_that_are_branch_targets_are_kept() {
+        // Removing the second push would leave the branch running a pop of
+        // one value that it never pushed.
+        let list = InstructionList::new(vec![
+            Instruction::PushConst(LocalConstIndex::new(0)),
+            Instruction::PushConst(LocalConstIndex::new(1)),
+            Instruction::Pop(2),
+            Instruction::Branch(BranchTarget::new(1)),
+        ]);
+        assert!(list.eliminate_dead_pushes().is_none());
+
+        // Pushes after the target can still go.
+        let list = InstructionList::new(vec![
+            Instruction::PushConst(LocalConstIndex::new(0)),
+            Instruction::PushConst(LocalConstIndex::new(1)),
+            Instruction::Pop(2),
+            Instruction::Branch(BranchTarget::new(0)),
+        ]);
+        let optimized = list.eliminate_dead_pushes().unwrap();
+        assert_eq!(
+            optimized.instructions(),
+            &[
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::Pop(1),
+                Instruction::Branch(BranchTarget::new(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn compares_are_fused_with_following_branches() {
+        let list = InstructionList::new(vec![
+            Instruction::PushCopy(StackIndex::FromBottom(0)),
+            Instruction::PushConst(LocalConstIndex::new(0)),
+            Instruction::Compare(CompareOp::Lt),
+            Instruction::BranchIf(BranchTarget::new(7)),
+            Instruction::PushConst(LocalConstIndex::new(1)),
+            Instruction::Compare(CompareOp::Eq),
+            Instruction::BranchIf(BranchTarget::new(0)),
+            Instruction::Compare(CompareOp::Ge),
+            Instruction::Return(0),
+        ]);
+        let fused = list.fuse_compare_branches().unwrap();
+        assert_eq!(
+            fused.instructions(),
+            &[
+                Instruction::PushCopy(StackIndex::FromBottom(0)),
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::CmpBranch(CompareOp::Lt, BranchTarget::new(5)),
+                Instruction::PushConst(LocalConstIndex::new(1)),
+                Instruction::CmpBranch(CompareOp::Eq, BranchTarget::new(0)),
+                Instruction::Compare(CompareOp::Ge),
+                Instruction::Return(0),
+            ]
+        );
+        assert!(fused.fuse_compare_branches().is_none());
+
+        // A branch into the middle of the pair keeps it apart.
+        let list = InstructionList::new(vec![
+            Instruction::Compare(CompareOp::Lt),
+            Instruction::BranchIf(BranchTarget::new(1)),
+        ]);
+        assert!(list.fuse_compare_branches().is_none());
+    }
+
+    #[test]
+    fn lists_with_out_of_range_branches_are_left_alone() {
+        let list = InstructionList::new(vec![
+            Instruction::PushConst(LocalConstIndex::new(0)),
+            Instruction::Pop(1),
+            Instruction::Compare(CompareOp::Lt),
+            Instruction::BranchIf(BranchTarget::new(5)),
+        ]);
+        assert!(list.eliminate_dead_pushes().is_none());
+        assert!(list.fuse_compare_branches().is_none());
+    }
+}
@@ -0,0 +1,61 @@
+use super::{
+    error::ProgramError,
+    module_set::ModuleSet,
+    modules::{ImportSource, ModuleId},
+};
+
+/// A module set together with the export that starts it: the unit an
+/// application built on Loon is deployed as.
+///
+/// A program is checked when it is created: the entry point must be exported
+/// by a module of the set. Modules the set imports from but does not contain,
+/// such as native modules, must be loaded before the program is. Programs are
+/// stored with [`Program::to_bytes`] and loaded back with
+/// [`Program::from_bytes`].
+pub struct Program {
+    modules: ModuleSet,
+    entry: ImportSource,
+    init_order: Vec<ModuleId>,
+}
+
+impl Program {
+    pub fn new(modules: ModuleSet, entry: ImportSource) -> Result<Self, ProgramError> {
+        let entry_module = modules
+            .module(entry.module_id())
+            .ok_or_else(|| ProgramError::EntryModuleMissing(entry.module_id().to_string()))?;
+        if !entry_module.exports().contains_key(entry.import_name()) {
+            return Err(ProgramError::EntryNotExported {
+                module: entry.module_id().to_string(),
+                name: entry.import_name().as_str().to_string(),
+            });
+        }
+        let init_order = modules
+            .dependency_order()
+            .into_iter()
+            .map(|module| module.id().clone())
+            .collect();
+        Ok(Program {
+            modules,
+            entry: ImportSource::new(entry.module_id().clone(), entry.import_name().clone()),
+            init_order,
+        })
+    }
+
+    #[must_use]
+    pub fn modules(&self) -> &ModuleSet {
+        &self.modules
+    }
+
+    /// Returns the export that is called to run the program.
+    #[must_use]
+    pub fn entry(&self) -> &ImportSource {
+        &self.entry
+    }
+
+    /// Returns the ids of the program's modules in the order they are
+    /// initialized, each after the modules it imports from.
+    #[must_use]
+    pub fn init_order(&self) -> &[ModuleId] {
+        &self.init_order
+    }
+}
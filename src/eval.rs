@@ -0,0 +1,126 @@
+//! Evaluation of standalone expressions, for embedders that want a small,
+//! sandboxed expression language without building modules themselves.
+
+use crate::{
+    binary::{
+        instructions::StackIndex,
+        modules::{ImportSource, ModuleId, ModuleMemberId},
+        BuilderError, ModuleBuilder,
+    },
+    lat,
+    pure_values::LoonValue,
+    runtime::{Runtime, RuntimeError},
+};
+
+/// The number of instructions an expression may run before it fails with
+/// [`RuntimeError::OutOfFuel`].
+pub const EXPRESSION_FUEL: u64 = 1_000_000;
+
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum EvalError {
+    #[error(transparent)]
+    Parse(#[from] lat::Error),
+
+    #[error(transparent)]
+    Builder(#[from] BuilderError),
+
+    #[error(transparent)]
+    Runtime(#[from] RuntimeError),
+
+    /// The expression returned some number of values other than one.
+    #[error("Expression returned {0} values instead of 1.")]
+    WrongReturnCount(u32),
+}
+
+/// Evaluates `text`, a sequence of lat instructions such as
+/// `(push x) (push 1) (add)`, and returns the value it leaves on top of the
+/// stack.
+///
+/// Each binding is available to the expression by name through
+/// `(push <name>)`. The expression runs in a fresh runtime without access to
+/// any modules, and fails with [`RuntimeError::OutOfFuel`] if it runs more
+/// than [`EXPRESSION_FUEL`] instructions.
+pub fn eval_expression(text: &str, bindings: &[(&str, LoonValue)]) -> Result<LoonValue, EvalError> {
+    let module_id = ModuleId::new(["eval"]);
+    let builder = ModuleBuilder::new(module_id.clone());
+    let (expression, mut fn_builder) = builder.new_function();
+    let names = bindings.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    lat::apply_instructions_from_str(&builder, &mut fn_builder, &names, text)?;
+    fn_builder.return_(1);
+    fn_builder.build()?;
+    expression.export(ModuleMemberId::new("expression"))?;
+
+    let runtime = Runtime::new();
+    runtime.load_module(&builder.into_const_module()?)?;
+    runtime.set_fuel(Some(EXPRESSION_FUEL));
+    let top_level = runtime.make_top_level();
+    {
+        let mut stack = top_level.stack();
+        for (_, value) in bindings {
+            stack.push_loon_value(value)?;
+        }
+        stack.push_import(&ImportSource::new(module_id, "expression"))?;
+    }
+    let num_returns = top_level.call_function(
+        u32::try_from(bindings.len())
+            .map_err(|_| RuntimeError::new_operation_precondition_error("Too many bindings."))?,
+    )?;
+    if num_returns != 1 {
+        return Err(EvalError::WrongReturnCount(num_returns));
+    }
+    let result = top_level.stack().get_loon_value(StackIndex::FromTop(0))?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pure_values::Integer;
+
+    #[test]
+    fn bindings_are_available_by_name() -> anyhow::Result<()> {
+        let result = eval_expression(
+            "(push x) (push y) (add)",
+            &[("x", 40.into()), ("y", 2.into())],
+        )?;
+        assert_eq!(result, LoonValue::Integer(Integer::from(42)));
+        Ok(())
+    }
+
+    #[test]
+    fn values_round_trip() -> anyhow::Result<()> {
+        let value = LoonValue::List(vec![
+            LoonValue::Null,
+            "text".into(),
+            LoonValue::Bytes(vec![1, 2]),
+            LoonValue::List(vec![1.5.into(), true.into()]),
+        ]);
+        assert_eq!(eval_expression("(push v)", &[("v", value.clone())])?, value);
+        assert_eq!(
+            eval_expression(r#"(push "a") (push "b") (cmp lt)"#, &[])?,
+            LoonValue::Bool(true)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn failures_are_reported() {
+        assert!(matches!(
+            eval_expression("(push unknown)", &[]),
+            Err(EvalError::Parse(_))
+        ));
+        assert!(matches!(
+            eval_expression("(push 1) (push 2) (return 2)", &[]),
+            Err(EvalError::WrongReturnCount(2))
+        ));
+        assert!(matches!(
+            eval_expression(r#"(push "a") (push 1) (add)"#, &[]),
+            Err(EvalError::Runtime(RuntimeError::Type(_)))
+        ));
+        assert!(matches!(
+            eval_expression("#:loop (branch #:loop)", &[]),
+            Err(EvalError::Runtime(RuntimeError::OutOfFuel))
+        ));
+    }
+}
@@ -1,6 +1,8 @@
 use std::{
     cell::{Cell, RefCell},
     collections::{HashMap, HashSet, VecDeque},
+    io,
+    time::{Duration, Instant},
 };
 
 use std::rc::{Rc, Weak};
@@ -32,7 +34,7 @@ impl<T> InnerType<T> {
     }
 }
 
-#[derive(Copy, Clone, Hash, Eq, PartialEq)]
+#[derive(Copy, Clone, Hash, Eq, PartialEq, PartialOrd, Ord)]
 struct PtrKey(*const ());
 
 impl PtrKey {
@@ -43,6 +45,11 @@ impl PtrKey {
 
 trait ObjectInfo {
     fn is_pinned(&self) -> bool;
+    /// Returns true if any `GcRef` or `PinnedGcRef` refers to the object. An
+    /// object that is not referenced cannot be reached.
+    fn is_referenced(&self) -> bool;
+    fn pin_count(&self) -> usize;
+    fn type_name(&self) -> &'static str;
     fn trace(&self, control_ptr: &ControlPtr, ptr_visitor: &mut dyn FnMut(PtrKey));
 }
 
@@ -85,6 +92,18 @@ where
         self.0.pin_count.is_nonzero()
     }
 
+    fn is_referenced(&self) -> bool {
+        self.0.ref_count.is_nonzero() || self.0.pin_count.is_nonzero()
+    }
+
+    fn pin_count(&self) -> usize {
+        self.0.pin_count.get()
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<T>()
+    }
+
     fn trace(&self, control_ptr: &ControlPtr, ptr_visitor: &mut dyn FnMut(PtrKey)) {
         (*self.0).as_ref().trace(&mut PtrVisitor {
             control_ptr,
@@ -93,11 +112,134 @@ where
     }
 }
 
+/// The stage of garbage collection an environment is in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum CollectPhase {
+    Idle,
+    /// Live objects are being traced. The live-object map is borrowed, so
+    /// objects may not be allocated.
+    Tracing,
+    /// Unreachable objects are being dropped. Their destructors may allocate,
+    /// but may not start another collection.
+    Sweeping,
+}
+
+/// Sets the collection phase of an environment, and returns it to
+/// [`CollectPhase::Idle`] when dropped, even if tracing panics.
+struct PhaseGuard<'a>(&'a ControlData);
+
+impl<'a> PhaseGuard<'a> {
+    fn enter(control: &'a ControlData) -> Self {
+        match control.phase.get() {
+            CollectPhase::Idle => {}
+            phase => panic!(
+                "A garbage collection was started during another collection ({phase:?}). \
+                 GcTraceable::trace implementations and destructors must not collect."
+            ),
+        }
+        control.phase.set(CollectPhase::Tracing);
+        PhaseGuard(control)
+    }
+
+    fn set(&self, phase: CollectPhase) {
+        self.0.phase.set(phase);
+    }
+}
+
+impl Drop for PhaseGuard<'_> {
+    fn drop(&mut self) {
+        self.0.phase.set(CollectPhase::Idle);
+    }
+}
+
+/// When a [`GcEnv`] starts a collection.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GcTrigger {
+    /// Collect once this many objects have been allocated since the last
+    /// collection.
+    Fixed(usize),
+    /// After each collection, collect again once the heap holds
+    /// `growth_factor` times as many objects as survived it, but no fewer
+    /// than `min_objects`. The growth factor should be greater than 1.
+    Adaptive {
+        growth_factor: f64,
+        min_objects: usize,
+    },
+    /// Never collect on allocation. Collections only run when requested with
+    /// [`GcEnv::collect`].
+    Manual,
+    /// Run a minor collection once `nursery_size` objects have been allocated
+    /// since the last collection, and a full collection in place of every
+    /// minor collection after the first `minor_per_major`.
+    ///
+    /// A minor collection only visits the objects allocated since the last
+    /// collection, and frees those that no reference points to, along with
+    /// any objects that only they referred to. Objects that survive it are
+    /// tenured, and are only freed by a full collection. Cycles are also only
+    /// freed by a full collection.
+    Generational {
+        nursery_size: usize,
+        minor_per_major: u32,
+    },
+}
+
+impl GcTrigger {
+    /// Returns the number of allocations allowed before the next collection,
+    /// given the number of objects that survived the last one.
+    fn alloc_limit(self, live_objects: usize) -> usize {
+        match self {
+            GcTrigger::Fixed(limit) => limit,
+            GcTrigger::Adaptive {
+                growth_factor,
+                min_objects,
+            } => {
+                let target = (live_objects as f64 * growth_factor).ceil() as usize;
+                target.max(min_objects).saturating_sub(live_objects).max(1)
+            }
+            GcTrigger::Manual => usize::MAX,
+            GcTrigger::Generational { nursery_size, .. } => nursery_size.max(1),
+        }
+    }
+}
+
+/// Statistics on the collections of a [`GcEnv`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    /// The number of full collections run.
+    pub collections: u64,
+    /// The number of minor collections run by a [`GcTrigger::Generational`]
+    /// trigger.
+    pub minor_collections: u64,
+    /// The number of objects that survived the last collection, full or
+    /// minor.
+    pub live_objects: usize,
+    /// The number of objects the heap may hold before the next collection is
+    /// triggered, as chosen by the [`GcTrigger`]. `usize::MAX` if collections
+    /// are manual.
+    pub next_trigger: usize,
+    /// The number of objects allocated over the heap's lifetime, including
+    /// those collected since.
+    pub total_allocations: u64,
+    /// The wall-clock time spent in collections, including running the
+    /// destructors of collected objects.
+    pub collection_time: Duration,
+}
+
 struct ControlData {
     live_objects: RefCell<HashMap<PtrKey, Box<dyn ObjectInfo>>>,
     collect_guard_count: Counter,
     alloc_count: Cell<usize>,
-    alloc_count_limit: usize,
+    /// The number of objects allocated over the environment's lifetime.
+    total_allocations: Cell<u64>,
+    alloc_count_limit: Cell<usize>,
+    trigger: Cell<GcTrigger>,
+    stats: Cell<GcStats>,
+    phase: Cell<CollectPhase>,
+    /// The objects allocated since the last collection, if the trigger is
+    /// generational.
+    nursery: RefCell<Vec<PtrKey>>,
+    /// The number of minor collections since the last full collection.
+    minor_since_major: Cell<u32>,
 }
 
 #[derive(Clone)]
@@ -107,24 +249,62 @@ struct ControlPtr {
 
 impl ControlPtr {
     /// Creates a new empty `GcContext`.
-    pub fn new(alloc_limit: usize) -> Self {
+    pub fn new(trigger: GcTrigger) -> Self {
+        let alloc_limit = trigger.alloc_limit(0);
         Self {
             control: Rc::new(ControlData {
                 live_objects: RefCell::new(HashMap::new()),
                 collect_guard_count: Counter::new(),
                 alloc_count: Cell::new(0),
-                alloc_count_limit: alloc_limit,
+                total_allocations: Cell::new(0),
+                alloc_count_limit: Cell::new(alloc_limit),
+                trigger: Cell::new(trigger),
+                stats: Cell::new(GcStats {
+                    next_trigger: alloc_limit,
+                    ..GcStats::default()
+                }),
+                phase: Cell::new(CollectPhase::Idle),
+                nursery: RefCell::new(Vec::new()),
+                minor_since_major: Cell::new(0),
             }),
         }
     }
 
+    /// Sets the trigger, choosing the next collection point as if the last
+    /// collection had just finished.
+    pub fn set_trigger(&self, trigger: GcTrigger) {
+        self.control.trigger.set(trigger);
+        // Objects allocated before a generational trigger is set are treated
+        // as tenured.
+        self.control.nursery.borrow_mut().clear();
+        self.update_alloc_limit();
+    }
+
+    /// Chooses the next collection point from the trigger and the objects
+    /// that survived the last collection.
+    fn update_alloc_limit(&self) {
+        let mut stats = self.control.stats.get();
+        let alloc_limit = self.control.trigger.get().alloc_limit(stats.live_objects);
+        self.control.alloc_count_limit.set(alloc_limit);
+        stats.next_trigger = stats.live_objects.saturating_add(alloc_limit);
+        self.control.stats.set(stats);
+    }
+
     pub fn accept_rc<T>(&self, obj: Rc<InnerType<T>>)
     where
         T: GcTraceable + 'static,
     {
+        assert!(
+            self.control.phase.get() != CollectPhase::Tracing,
+            "An object was allocated while tracing for garbage collection. \
+             GcTraceable::trace implementations must not allocate."
+        );
         self.control
             .alloc_count
             .set(self.control.alloc_count.get() + 1);
+        self.control
+            .total_allocations
+            .set(self.control.total_allocations.get() + 1);
         self.attempt_garbage_collect();
 
         // We use the pointer as a key to the object in the HashMap.
@@ -135,6 +315,9 @@ impl ControlPtr {
             let mut live_objects = self.control.live_objects.borrow_mut();
             live_objects.insert(ptr_id, Box::new(obj_info));
         }
+        if matches!(self.control.trigger.get(), GcTrigger::Generational { .. }) {
+            self.control.nursery.borrow_mut().push(ptr_id);
+        }
     }
 
     /// Creates a new reference that is managed by the RefContext that contains
@@ -151,15 +334,91 @@ impl ControlPtr {
     }
 
     pub fn attempt_garbage_collect(&self) {
-        if self.control.collect_guard_count.is_zero()
-            && self.control.alloc_count.get() >= self.control.alloc_count_limit
+        // Destructors run while sweeping may allocate, which must not start a
+        // nested collection.
+        if self.control.phase.get() == CollectPhase::Idle
+            && self.control.collect_guard_count.is_zero()
+            && self.control.alloc_count.get() >= self.control.alloc_count_limit.get()
         {
-            self.garbage_collect();
-            self.control.alloc_count.set(0);
+            match self.control.trigger.get() {
+                GcTrigger::Generational {
+                    minor_per_major, ..
+                } if self.control.minor_since_major.get() < minor_per_major => {
+                    self.minor_garbage_collect();
+                }
+                _ => self.garbage_collect(),
+            }
+        }
+    }
+
+    /// Frees the objects allocated since the last collection that are not
+    /// referenced, and the objects that only they referenced, then tenures
+    /// the rest.
+    ///
+    /// Only objects with no references are freed, so unlike a full
+    /// collection this needs no marking, and does not visit older objects.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during another collection.
+    pub fn minor_garbage_collect(&self) {
+        let started = Instant::now();
+        let phase = PhaseGuard::enter(&self.control);
+        let mut candidates = self.control.nursery.take();
+        while !candidates.is_empty() {
+            phase.set(CollectPhase::Tracing);
+            let mut live_objects = self.control.live_objects.borrow_mut();
+            let unreferenced = candidates
+                .drain(..)
+                .filter_map(|key| {
+                    let is_unreferenced = live_objects
+                        .get(&key)
+                        .is_some_and(|info| !info.is_referenced());
+                    if is_unreferenced {
+                        live_objects.remove(&key)
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            // Freeing an object drops its references, which may leave the
+            // objects it refers to unreferenced in turn.
+            for info in &unreferenced {
+                info.trace(self, &mut |key| candidates.push(key));
+            }
+            drop(live_objects);
+            phase.set(CollectPhase::Sweeping);
+            drop(unreferenced);
         }
+
+        let mut stats = self.control.stats.get();
+        stats.minor_collections += 1;
+        stats.live_objects = self.control.live_objects.borrow().len();
+        self.control.stats.set(stats);
+        self.update_alloc_limit();
+        self.control.alloc_count.set(0);
+        self.control
+            .minor_since_major
+            .set(self.control.minor_since_major.get() + 1);
+        let mut stats = self.control.stats.get();
+        stats.collection_time += started.elapsed();
+        self.control.stats.set(stats);
     }
 
+    /// Marks objects reachable from pinned roots and drops the rest, then
+    /// chooses when the next collection is triggered.
+    ///
+    /// Marking uses an explicit worklist, so deeply nested object graphs do
+    /// not recurse on the native stack.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during another collection, such as from a
+    /// `GcTraceable::trace` implementation or a destructor of a collected
+    /// object.
     pub fn garbage_collect(&self) {
+        let started = Instant::now();
+        let phase = PhaseGuard::enter(&self.control);
         let mut live_objects = self.control.live_objects.borrow_mut();
         let mut reachable = HashSet::new();
         let mut worklist: VecDeque<_> = live_objects
@@ -179,8 +438,114 @@ impl ControlPtr {
             }
         }
 
-        live_objects.retain(|key, _| reachable.contains(key));
+        // Unreachable objects are dropped after the live-object map is
+        // released, so their destructors may allocate.
+        let unreachable = live_objects
+            .keys()
+            .filter(|key| !reachable.contains(key))
+            .copied()
+            .collect::<Vec<_>>();
+        let unreachable = unreachable
+            .into_iter()
+            .filter_map(|key| live_objects.remove(&key))
+            .collect::<Vec<_>>();
+        let mut stats = self.control.stats.get();
+        stats.collections += 1;
+        stats.live_objects = live_objects.len();
+        self.control.stats.set(stats);
+        self.update_alloc_limit();
+        // Objects allocated by destructors while sweeping count towards the
+        // next collection.
+        self.control.alloc_count.set(0);
+        self.control.nursery.borrow_mut().clear();
+        self.control.minor_since_major.set(0);
+        drop(live_objects);
+        phase.set(CollectPhase::Sweeping);
+        drop(unreachable);
+        let mut stats = self.control.stats.get();
+        stats.collection_time += started.elapsed();
+        self.control.stats.set(stats);
+    }
+
+    /// Runs a collection unless one is already running or collection guards
+    /// are active, returning true if it ran.
+    pub fn try_garbage_collect(&self) -> bool {
+        if self.control.phase.get() != CollectPhase::Idle
+            || !self.control.collect_guard_count.is_zero()
+        {
+            return false;
+        }
+        self.garbage_collect();
+        true
+    }
+
+    /// Writes the graph of live objects in Graphviz DOT format. Each object
+    /// is labeled with its type, and pinned objects are filled and labeled
+    /// with their pin count.
+    ///
+    /// Objects are ordered by address, so the output of a single run is
+    /// stable while the heap does not change.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called during a collection.
+    pub fn write_graph_dot(&self, w: &mut dyn io::Write) -> io::Result<()> {
+        let _phase = PhaseGuard::enter(&self.control);
+        let live_objects = self.control.live_objects.borrow();
+        let mut keys = live_objects.keys().copied().collect::<Vec<_>>();
+        keys.sort();
+        let ids = keys
+            .iter()
+            .enumerate()
+            .map(|(id, key)| (*key, id))
+            .collect::<HashMap<_, _>>();
+
+        writeln!(w, "digraph gc {{")?;
+        writeln!(w, "    node [shape=box];")?;
+        for (id, key) in keys.iter().enumerate() {
+            let info = &live_objects[key];
+            let label = short_type_name(info.type_name());
+            if info.is_pinned() {
+                writeln!(
+                    w,
+                    "    o{id} [label=\"{label}\\npinned {}\", style=filled, fillcolor=lightblue];",
+                    info.pin_count()
+                )?;
+            } else {
+                writeln!(w, "    o{id} [label=\"{label}\"];")?;
+            }
+        }
+        for (id, key) in keys.iter().enumerate() {
+            let mut targets: Vec<usize> = Vec::new();
+            live_objects[key].trace(self, &mut |target| targets.extend(ids.get(&target)));
+            for target in targets {
+                writeln!(w, "    o{id} -> o{target};")?;
+            }
+        }
+        writeln!(w, "}}")
+    }
+}
+
+/// Removes the module paths from a type name, e.g. turning
+/// `alloc::vec::Vec<loon::Value>` into `Vec<Value>`. Quotes are escaped, so
+/// the result can be used in a DOT string.
+fn short_type_name(name: &str) -> String {
+    let mut result = String::with_capacity(name.len());
+    let mut segment = String::new();
+    for c in name.chars() {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            segment.push(c);
+            continue;
+        }
+        result.push_str(segment.rsplit("::").next().unwrap_or_default());
+        segment.clear();
+        if c == '"' {
+            result.push('\\');
+        }
+        result.push(c);
     }
+    result.push_str(segment.rsplit("::").next().unwrap_or_default());
+    result
 }
 
 /// The main context object that manages a set of garbage collected objects.
@@ -192,8 +557,33 @@ impl ControlPtr {
 pub struct GcEnv(ControlPtr);
 
 impl GcEnv {
+    /// Creates an environment that collects after every `alloc_limit`
+    /// allocations.
     pub fn new(alloc_limit: usize) -> Self {
-        Self(ControlPtr::new(alloc_limit))
+        Self(ControlPtr::new(GcTrigger::Fixed(alloc_limit)))
+    }
+
+    /// Changes when collections are triggered. The next collection point is
+    /// chosen from the objects that survived the last collection.
+    pub fn set_trigger(&self, trigger: GcTrigger) {
+        self.0.set_trigger(trigger);
+    }
+
+    pub fn trigger(&self) -> GcTrigger {
+        self.0.control.trigger.get()
+    }
+
+    pub fn stats(&self) -> GcStats {
+        GcStats {
+            total_allocations: self.total_allocations(),
+            ..self.0.control.stats.get()
+        }
+    }
+
+    /// Returns the number of objects allocated since the environment was
+    /// created, whether or not they have been collected since.
+    pub fn total_allocations(&self) -> u64 {
+        self.0.control.total_allocations.get()
     }
 
     pub fn with_lock<F, R>(&self, body: F) -> R
@@ -212,10 +602,35 @@ impl GcEnv {
         guard.create_ref(value).pin()
     }
 
+    /// Runs a garbage collection if enough allocations have happened since the
+    /// last one, and no collection guards are active.
+    pub fn collect_if_pending(&self) {
+        self.0.attempt_garbage_collect();
+    }
+
+    /// Runs a garbage collection now, whatever the trigger, unless one is
+    /// already running or collection guards are active. Returns true if it
+    /// ran.
+    pub fn collect(&self) -> bool {
+        self.0.try_garbage_collect()
+    }
+
     #[cfg(test)]
     pub fn force_collect(&self) {
         self.0.garbage_collect();
     }
+
+    #[cfg(test)]
+    pub fn force_minor_collect(&self) {
+        self.0.minor_garbage_collect();
+    }
+
+    /// Writes the graph of live objects in Graphviz DOT format, for finding
+    /// what keeps objects alive. Pinned objects, the roots of collection, are
+    /// highlighted.
+    pub fn dump_graph_dot(&self, mut w: impl io::Write) -> io::Result<()> {
+        self.0.write_graph_dot(&mut w)
+    }
 }
 
 /// A guard on a [`GcEnv`] that ensures that no garbage collections happen
@@ -386,6 +801,13 @@ where
         Rc::ptr_eq(&self.obj, &other.obj)
     }
 
+    /// Returns an identifier for the referenced object. Two live objects never
+    /// share an identifier, but an identifier may be reused after an object
+    /// is collected.
+    pub fn identity(&self) -> usize {
+        Rc::as_ptr(&self.obj) as *const () as usize
+    }
+
     /// Creates a GcRef from this PinnedGcRef. This does not require a
     /// collect lock, as the object remains pinned.
     pub fn to_ref(&self) -> GcRef<T> {
@@ -399,6 +821,63 @@ where
     pub fn into_ref(self, _env_lock: &CollectGuard) -> GcRef<T> {
         self.to_ref()
     }
+
+    /// Creates a weak reference to the object.
+    pub fn downgrade(&self) -> WeakGcRef<T> {
+        WeakGcRef {
+            obj: Rc::downgrade(&self.obj),
+        }
+    }
+}
+
+/// A reference to a garbage collected object that does not keep it alive.
+///
+/// Weak references are not traced, so an object reachable only through weak
+/// references is collected by the next collection that sees it, after which
+/// [`WeakGcRef::upgrade`] returns `None`.
+pub struct WeakGcRef<T>
+where
+    T: ?Sized + 'static,
+{
+    obj: Weak<InnerType<T>>,
+}
+
+impl<T> WeakGcRef<T>
+where
+    T: ?Sized + 'static,
+{
+    /// Pins the object, if it has not been collected.
+    pub fn upgrade(&self) -> Option<PinnedGcRef<T>> {
+        self.obj.upgrade().map(PinnedGcRef::from_rc)
+    }
+
+    /// Returns true if the object has not been collected.
+    pub fn is_alive(&self) -> bool {
+        self.obj.strong_count() > 0
+    }
+}
+
+impl<T> Clone for WeakGcRef<T>
+where
+    T: ?Sized + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            obj: self.obj.clone(),
+        }
+    }
+}
+
+impl<T> GcTraceable for WeakGcRef<T>
+where
+    T: ?Sized + 'static,
+{
+    fn trace<V>(&self, _visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        // Weak references do not keep their object alive.
+    }
 }
 
 impl<T> std::ops::Deref for PinnedGcRef<T> {
@@ -17,6 +17,10 @@ impl Counter {
         self.0.set(value.checked_sub(1).expect("Counter underflow"));
     }
 
+    pub fn get(&self) -> usize {
+        self.0.get()
+    }
+
     pub fn is_nonzero(&self) -> bool {
         self.0.get() != 0
     }
@@ -7,7 +7,10 @@
 mod core;
 mod counter;
 
-pub use core::{CollectGuard, GcEnv, GcRef, GcRefVisitor, GcTraceable, PinnedGcRef};
+pub use core::{
+    CollectGuard, GcEnv, GcRef, GcRefVisitor, GcStats, GcTraceable, GcTrigger, PinnedGcRef,
+    WeakGcRef,
+};
 
 #[cfg(test)]
 mod tests {
@@ -107,4 +110,273 @@ mod tests {
         assert!(drop1());
         assert!(drop2());
     }
+
+    #[test]
+    fn graph_dump_shows_pins_and_edges() -> anyhow::Result<()> {
+        let env = GcEnv::new(usize::MAX);
+        let (parent, _) = Node::new();
+        let (child, _) = Node::new();
+        let parent = env.create_pinned_ref(parent);
+        let child = env.create_pinned_ref(child);
+        parent.add_child(child.to_ref());
+        drop(child);
+
+        let mut out = Vec::new();
+        env.dump_graph_dot(&mut out)?;
+        let dot = String::from_utf8(out)?;
+        assert!(dot.starts_with("digraph gc {"));
+        assert_eq!(dot.matches("label=\"Node").count(), 2);
+        assert_eq!(dot.matches("pinned 1").count(), 1);
+        assert_eq!(dot.matches(" -> ").count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn deep_chain_is_traced_without_recursion() {
+        let env = GcEnv::new(usize::MAX);
+        let (root, root_dropped) = Node::new();
+        let root = env.create_pinned_ref(root);
+        let mut tail = root.clone();
+        for _ in 0..100_000 {
+            let (node, _) = Node::new();
+            let node = env.create_pinned_ref(node);
+            tail.add_child(node.to_ref());
+            tail = node;
+        }
+        drop(tail);
+        env.force_collect();
+        assert!(!root_dropped());
+
+        drop(root);
+        env.force_collect();
+        assert!(root_dropped());
+    }
+
+    #[test]
+    fn adaptive_trigger_scales_with_live_objects() {
+        let env = GcEnv::new(usize::MAX);
+        let kept: Vec<_> = (0..10).map(|i| env.create_pinned_ref(i)).collect();
+        env.set_trigger(GcTrigger::Adaptive {
+            growth_factor: 2.0,
+            min_objects: 4,
+        });
+        assert_eq!(env.stats().next_trigger, 4);
+        env.force_collect();
+        let stats = env.stats();
+        assert_eq!(
+            (stats.collections, stats.live_objects, stats.next_trigger),
+            (1, 10, 20)
+        );
+
+        // The tenth allocation reaches the trigger. It is still pinned while
+        // the collection runs, so it survives it.
+        for i in 0..10 {
+            drop(env.create_pinned_ref(i));
+            assert_eq!(env.stats().collections, if i < 9 { 1 } else { 2 });
+        }
+        assert_eq!(env.stats().live_objects, 11);
+        assert_eq!(env.stats().next_trigger, 22);
+
+        env.set_trigger(GcTrigger::Fixed(5));
+        assert_eq!(env.stats().next_trigger, 16);
+        drop(kept);
+    }
+
+    #[test]
+    fn manual_trigger_only_collects_on_request() {
+        let env = GcEnv::new(1);
+        env.set_trigger(GcTrigger::Manual);
+        let dropped = (0..10)
+            .map(|_| {
+                let (node, dropped) = Node::new();
+                drop(env.create_pinned_ref(node));
+                dropped
+            })
+            .collect::<Vec<_>>();
+        let stats = env.stats();
+        assert_eq!(stats.collections, 0);
+        assert_eq!(stats.total_allocations, 10);
+        assert_eq!(stats.next_trigger, usize::MAX);
+        assert!(dropped.iter().all(|dropped| !dropped()));
+
+        assert!(env.collect());
+        assert!(dropped.iter().all(|dropped| dropped()));
+        let stats = env.stats();
+        assert_eq!(stats.collections, 1);
+        assert_eq!(stats.live_objects, 0);
+        assert_eq!(stats.total_allocations, 10);
+
+        // Collections do not run while the heap is locked.
+        env.with_lock(|_| assert!(!env.collect()));
+        assert_eq!(env.stats().collections, 1);
+    }
+
+    #[test]
+    fn minor_collections_free_unreferenced_young_objects() {
+        let env = GcEnv::new(usize::MAX);
+        env.set_trigger(GcTrigger::Generational {
+            nursery_size: 1000,
+            minor_per_major: 1,
+        });
+        let (kept, kept_dropped) = Node::new();
+        let kept = env.create_pinned_ref(kept);
+
+        let (a, a_dropped) = Node::new();
+        let (b, b_dropped) = Node::new();
+        let a = env.create_pinned_ref(a);
+        let b = env.create_pinned_ref(b);
+        a.add_child(b.to_ref());
+        b.add_child(a.to_ref());
+        drop((a, b));
+
+        let (parent, parent_dropped) = Node::new();
+        let (child, child_dropped) = Node::new();
+        let parent = env.create_pinned_ref(parent);
+        parent.add_child(env.create_pinned_ref(child).to_ref());
+        drop(parent);
+
+        // The chain is freed, and the cycle is tenured.
+        env.force_minor_collect();
+        assert!(parent_dropped() && child_dropped());
+        assert!(!a_dropped() && !b_dropped() && !kept_dropped());
+        let stats = env.stats();
+        assert_eq!((stats.minor_collections, stats.collections), (1, 0));
+        assert_eq!(stats.live_objects, 3);
+
+        env.force_collect();
+        assert!(a_dropped() && b_dropped());
+        assert!(!kept_dropped());
+        drop(kept);
+    }
+
+    #[test]
+    fn generational_trigger_alternates_minor_and_full_collections() {
+        let env = GcEnv::new(usize::MAX);
+        env.set_trigger(GcTrigger::Generational {
+            nursery_size: 2,
+            minor_per_major: 1,
+        });
+        for i in 0..6 {
+            drop(env.create_pinned_ref(i));
+        }
+        let stats = env.stats();
+        assert_eq!((stats.minor_collections, stats.collections), (2, 1));
+    }
+
+    #[test]
+    fn weak_refs_do_not_keep_objects_alive() {
+        let env = GcEnv::new(usize::MAX);
+        let (parent, _) = Node::new();
+        let (child, child_dropped) = Node::new();
+        let parent = env.create_pinned_ref(parent);
+        let child = env.create_pinned_ref(child);
+        parent.add_child(child.to_ref());
+        let weak = child.downgrade();
+        drop(child);
+
+        env.force_collect();
+        assert!(weak.is_alive());
+        let pinned = weak.upgrade().expect("child is reachable");
+        assert!(pinned.children.borrow().is_empty());
+        drop(pinned);
+
+        parent.children.borrow_mut().clear();
+        env.force_collect();
+        assert!(child_dropped());
+        assert!(!weak.is_alive());
+        assert!(weak.upgrade().is_none());
+    }
+
+    /// An object that misuses its environment while being traced.
+    struct Reentrant {
+        env: GcEnv,
+        collect: bool,
+    }
+
+    impl GcTraceable for Reentrant {
+        fn trace<V>(&self, _visitor: &mut V)
+        where
+            V: GcRefVisitor,
+        {
+            if self.collect {
+                self.env.force_collect();
+            } else {
+                self.env.create_pinned_ref(0);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "must not allocate")]
+    fn allocation_during_trace_fails_fast() {
+        let env = GcEnv::new(100);
+        let _obj = env.create_pinned_ref(Reentrant {
+            env: env.clone(),
+            collect: false,
+        });
+        env.force_collect();
+    }
+
+    #[test]
+    #[should_panic(expected = "must not collect")]
+    fn collection_during_trace_fails_fast() {
+        let env = GcEnv::new(100);
+        let _obj = env.create_pinned_ref(Reentrant {
+            env: env.clone(),
+            collect: true,
+        });
+        env.force_collect();
+    }
+
+    #[test]
+    fn env_recovers_after_failed_collection() {
+        let env = GcEnv::new(100);
+        let obj = env.create_pinned_ref(Reentrant {
+            env: env.clone(),
+            collect: false,
+        });
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| env.force_collect()));
+        assert!(result.is_err());
+
+        drop(obj);
+        let i_ref = env.create_pinned_ref(4).to_ref();
+        env.force_collect();
+        assert!(i_ref.try_borrow().is_none());
+    }
+
+    #[test]
+    fn destructors_may_allocate_while_sweeping() {
+        struct AllocOnDrop {
+            env: GcEnv,
+            allocated: Rc<RefCell<Option<GcRef<i32>>>>,
+        }
+
+        impl GcTraceable for AllocOnDrop {
+            fn trace<V>(&self, _visitor: &mut V)
+            where
+                V: GcRefVisitor,
+            {
+            }
+        }
+
+        impl Drop for AllocOnDrop {
+            fn drop(&mut self) {
+                let obj = self.env.create_pinned_ref(7).to_ref();
+                *self.allocated.borrow_mut() = Some(obj);
+            }
+        }
+
+        // A limit of 1 would collect on every allocation, if allowed.
+        let env = GcEnv::new(1);
+        let allocated = Rc::new(RefCell::new(None));
+        drop(env.create_pinned_ref(AllocOnDrop {
+            env: env.clone(),
+            allocated: allocated.clone(),
+        }));
+        env.force_collect();
+        let obj = allocated.borrow_mut().take().expect("destructor ran");
+        // The new object is unpinned, so the next collection drops it.
+        env.force_collect();
+        assert!(obj.try_borrow().is_none());
+    }
 }
@@ -0,0 +1,172 @@
+//! Decoding of the text encodings accepted for byte string constants.
+
+use std::borrow::Cow;
+
+use super::{Error, Result};
+
+/// Decodes a string of hex digit pairs. Whitespace between digits is ignored,
+/// so long blobs can be split across lines.
+pub(super) fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    let digits = text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16))
+        .collect::<Option<Vec<_>>>()?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(
+        digits
+            .chunks_exact(2)
+            .map(|pair| u8::try_from(pair[0] << 4 | pair[1]).unwrap())
+            .collect(),
+    )
+}
+
+fn base64_value(c: u8) -> Option<u32> {
+    Some(u32::from(match c {
+        b'A'..=b'Z' => c - b'A',
+        b'a'..=b'z' => c - b'a' + 26,
+        b'0'..=b'9' => c - b'0' + 52,
+        b'+' => 62,
+        b'/' => 63,
+        _ => return None,
+    }))
+}
+
+/// Decodes standard (RFC 4648) base64. Padding is optional, and whitespace is
+/// ignored.
+pub(super) fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut chars = text
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect::<Vec<_>>();
+    if chars.len() % 4 == 0 {
+        let padding = chars.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 {
+            return None;
+        }
+        chars.truncate(chars.len() - padding);
+    }
+    if chars.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut result = Vec::with_capacity(chars.len() * 3 / 4);
+    for chunk in chars.chunks(4) {
+        let mut acc = 0u32;
+        for c in chunk {
+            acc = acc << 6 | base64_value(*c)?;
+        }
+        // A chunk of n characters holds n * 6 bits, of which the leading
+        // (n - 1) bytes are data. Any remaining bits must be zero.
+        let data_bytes = chunk.len() - 1;
+        let extra_bits = chunk.len() * 6 - data_bytes * 8;
+        if acc & ((1 << extra_bits) - 1) != 0 {
+            return None;
+        }
+        let acc = acc >> extra_bits;
+        for i in (0..data_bytes).rev() {
+            result.push(u8::try_from((acc >> (i * 8)) & 0xff).unwrap());
+        }
+    }
+    Some(result)
+}
+
+/// Rewrites each hex byte string literal, `#x"DEAD"`, as the byte vector
+/// `#u8(222 173)`, which the s-expression reader understands. Strings,
+/// characters and comments are left as they are.
+pub(super) fn expand_hex_literals(text: &str) -> Result<Cow<'_, str>> {
+    if !text.contains("#x\"") {
+        return Ok(Cow::Borrowed(text));
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (token, after) = if let Some(literal) = rest.strip_prefix("#x\"") {
+            let end = literal
+                .find('"')
+                .ok_or_else(|| Error::InvalidBytes("hex", literal.to_string()))?;
+            let digits = &literal[..end];
+            let bytes =
+                decode_hex(digits).ok_or_else(|| Error::InvalidBytes("hex", digits.to_string()))?;
+            let bytes = bytes.iter().map(u8::to_string).collect::<Vec<_>>();
+            result.push_str("#u8(");
+            result.push_str(&bytes.join(" "));
+            result.push(')');
+            rest = &literal[end + 1..];
+            continue;
+        } else if c == '"' {
+            let mut escaped = false;
+            let end = rest[1..]
+                .find(|c| {
+                    let closes = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    closes
+                })
+                .map_or(rest.len(), |end| end + 2);
+            rest.split_at(end)
+        } else if c == ';' {
+            rest.split_at(rest.find('\n').unwrap_or(rest.len()))
+        } else if let Some(character) = rest.strip_prefix("#\\") {
+            let len = character.chars().next().map_or(0, char::len_utf8);
+            rest.split_at(2 + len)
+        } else {
+            rest.split_at(c.len_utf8())
+        };
+        result.push_str(token);
+        rest = after;
+    }
+    Ok(Cow::Owned(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_decodes() {
+        assert_eq!(decode_hex("DEADbeef"), Some(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(
+            decode_hex("de ad\n be ef"),
+            Some(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn hex_literals_expand_to_byte_vectors() -> anyhow::Result<()> {
+        assert_eq!(
+            expand_hex_literals(r#"(bytes #x"DEAD beef")"#)?,
+            "(bytes #u8(222 173 190 239))"
+        );
+        assert_eq!(
+            expand_hex_literals(r##"("#x\"00\"" #\" #x"" ; #x"zz""##)?,
+            r##"("#x\"00\"" #\" #u8() ; #x"zz""##
+        );
+        assert!(matches!(
+            expand_hex_literals(r#"#x"abc""#),
+            Err(Error::InvalidBytes("hex", digits)) if digits == "abc"
+        ));
+        assert!(expand_hex_literals(r#"#x"ab"#).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn base64_decodes() {
+        assert_eq!(
+            decode_base64("3q2+7w=="),
+            Some(vec![0xde, 0xad, 0xbe, 0xef])
+        );
+        assert_eq!(decode_base64("3q2+7w"), Some(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(decode_base64("TWFu"), Some(b"Man".to_vec()));
+        assert_eq!(decode_base64("TWE="), Some(b"Ma".to_vec()));
+        assert_eq!(decode_base64("TQ=="), Some(b"M".to_vec()));
+        assert_eq!(decode_base64(""), Some(vec![]));
+        assert_eq!(decode_base64("T"), None);
+        assert_eq!(decode_base64("TR=="), None);
+        assert_eq!(decode_base64("T$=="), None);
+    }
+}
@@ -0,0 +1,562 @@
+//! Compilation of module sets with reuse of unchanged items.
+//!
+//! Watch-mode tooling recompiles the same source after every edit, where
+//! usually only a few items have changed. A [`Compiler`] with a
+//! [`CompileCache`] hashes each module item, and reuses the module built by
+//! an earlier compilation if none of its items or the shared constants have
+//! changed. If only the bodies of some of its functions or of its
+//! initializer changed, those are rebuilt against the cached module and
+//! take the place of their previous versions, so the constants of the other
+//! items keep their indexes. Any other change rebuilds the module as a whole.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use super::{
+    build_module_with_names, parse_documented_item, parse_list_with_head,
+    parse_list_with_initial_symbol, parse_module_items, parse_symbol, read_expr, resolve_fn_expr,
+    split_module_set, Error, ReferenceSet, Result,
+};
+use crate::binary::{
+    builders::ValueIndex, error::BuilderError, module_set::ModuleSet, modules::ModuleId,
+    ConstModule, ModuleBuilder,
+};
+
+/// Hashes the source of an item, ignoring how it was laid out.
+fn hash_expr(expr: &lexpr::Value) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    expr.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A module item that defines a function: a constant written as
+/// `(fn ...)`, or the initializer.
+struct FunctionItem<'a> {
+    /// The name of the constant, or `None` for the initializer.
+    name: Option<&'a str>,
+    doc: Option<&'a str>,
+    body: &'a lexpr::Value,
+}
+
+impl<'a> FunctionItem<'a> {
+    fn parse(expr: &'a lexpr::Value) -> Option<Self> {
+        let (head, rest) = parse_list_with_initial_symbol(expr).ok()?;
+        match head {
+            "init" => Some(FunctionItem {
+                name: None,
+                doc: None,
+                body: rest,
+            }),
+            "const" => {
+                let ([name, value], doc) = parse_documented_item::<2>(rest).ok()?;
+                Some(FunctionItem {
+                    name: Some(parse_symbol(name).ok()?),
+                    doc,
+                    body: parse_list_with_head("fn", value).ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What an item contributes to the layout of its module's constants. The
+/// body of a function item is left out, as changing it does not move the
+/// constants of other items.
+#[derive(PartialEq, Eq)]
+enum ItemLayout {
+    Function {
+        name: Option<String>,
+        doc: Option<String>,
+    },
+    Other(u64),
+}
+
+impl ItemLayout {
+    fn of(expr: &lexpr::Value) -> Self {
+        match FunctionItem::parse(expr) {
+            Some(function) => ItemLayout::Function {
+                name: function.name.map(str::to_string),
+                doc: function.doc.map(str::to_string),
+            },
+            None => ItemLayout::Other(hash_expr(expr)),
+        }
+    }
+}
+
+struct CachedModule {
+    /// The hashes of the module's items, followed by those of the shared
+    /// constants, in order.
+    item_hashes: Vec<u64>,
+    /// The layouts of the module's items, followed by those of the shared
+    /// constants, in order.
+    layout: Vec<ItemLayout>,
+    module: ConstModule,
+    /// Where each name in the module's scope was placed.
+    names: HashMap<String, ValueIndex>,
+    /// The size of the constant table when the module was last built as a
+    /// whole. Rebuilt functions are added to the table, and leave the
+    /// constants only their previous versions used behind.
+    built_len: usize,
+}
+
+impl CachedModule {
+    /// Rebuilds the functions whose items changed, where no other items
+    /// did, and puts them in place of their previous versions.
+    ///
+    /// Returns `None` if the constant table would grow past twice its size
+    /// when the module was last built as a whole, so that the unused
+    /// constants are dropped by building it again.
+    fn rebuild_functions(
+        &self,
+        item_exprs: &[&lexpr::Value],
+        item_hashes: &[u64],
+    ) -> Result<Option<ConstModule>> {
+        let module = &self.module;
+        let builder = ModuleBuilder::extending(module);
+        let references = ReferenceSet {
+            names: self
+                .names
+                .iter()
+                .map(|(name, index)| (name.clone(), builder.new_ref(index.clone())))
+                .collect(),
+            forward: None,
+        };
+
+        let mut rebuilt = Vec::new();
+        for ((item_expr, hash), cached_hash) in
+            item_exprs.iter().zip(item_hashes).zip(&self.item_hashes)
+        {
+            if hash == cached_hash {
+                continue;
+            }
+            let function = FunctionItem::parse(item_expr)
+                .expect("Only function items can change without changing the layout");
+            let target = match function.name {
+                Some(name) => self
+                    .names
+                    .get(name)
+                    .ok_or_else(|| Error::UnknownReference(name.to_string()))?
+                    .as_module_const()?,
+                None => module
+                    .initializer()
+                    .expect("An init item builds an initializer"),
+            };
+            let (value, deferred) = builder.new_deferred();
+            resolve_fn_expr(
+                &builder,
+                &references,
+                deferred.into_function_builder(),
+                function.body,
+            )?;
+            rebuilt.push((target, value));
+        }
+
+        let mut const_table = builder.into_const_module()?.const_table().to_vec();
+        if const_table.len() > 2 * self.built_len {
+            return Ok(None);
+        }
+        for (target, value) in rebuilt {
+            let index = value
+                .resolved_index()
+                .ok_or(BuilderError::UnresolvedReference)?
+                .as_module_const()?;
+            const_table[target.as_usize()] = const_table[index.as_usize()].clone();
+        }
+        let patched = ConstModule::new(
+            module.id().clone(),
+            const_table,
+            module.imports().to_vec(),
+            module.exports().clone(),
+            module.initializer(),
+            module.global_table_size(),
+        )
+        .and_then(|patched| {
+            patched
+                .with_export_docs(module.export_docs().clone())
+                .with_tests(module.tests().to_vec())
+        })
+        .map_err(BuilderError::from)?;
+        Ok(Some(patched))
+    }
+}
+
+/// The modules built by a [`Compiler`], kept for reuse by later
+/// compilations.
+#[derive(Default)]
+pub struct CompileCache {
+    modules: HashMap<ModuleId, CachedModule>,
+}
+
+impl CompileCache {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of modules in the cache.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.modules.clear();
+    }
+}
+
+/// What the last compilation of a [`Compiler`] reused.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompileStats {
+    /// The number of modules taken from the cache.
+    pub modules_reused: usize,
+    /// The number of modules built as a whole from their source.
+    pub modules_built: usize,
+    /// The number of cached modules that had only their changed functions
+    /// rebuilt.
+    pub modules_patched: usize,
+    /// The number of functions rebuilt in patched modules.
+    pub functions_rebuilt: usize,
+    /// The number of items of built or patched modules that were not in the
+    /// cached version of their module. Items of modules not in the cache are
+    /// not counted.
+    pub items_changed: usize,
+}
+
+/// Compiles lat module sets, optionally reusing modules built by earlier
+/// compilations.
+#[derive(Default)]
+pub struct Compiler {
+    features: Vec<String>,
+    cache: Option<CompileCache>,
+    stats: CompileStats,
+}
+
+impl Compiler {
+    /// Creates a compiler that builds every module on each compilation.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a compiler that reuses the unchanged modules in `cache`, and
+    /// keeps the modules it builds there.
+    #[must_use]
+    pub fn with_cache(cache: CompileCache) -> Self {
+        Compiler {
+            cache: Some(cache),
+            ..Self::default()
+        }
+    }
+
+    /// Enables the named features, as
+    /// [`from_str_with_features`](super::from_str_with_features) does.
+    #[must_use]
+    pub fn with_features<'a>(mut self, features: impl IntoIterator<Item = &'a str>) -> Self {
+        self.features = features.into_iter().map(str::to_string).collect();
+        self
+    }
+
+    /// Parses a module set, as [`from_str`](super::from_str) does.
+    ///
+    /// Modules whose items have not changed since they were last compiled
+    /// are taken from the cache, and modules where only functions changed
+    /// have just those rebuilt. Modules that are no longer in the source are
+    /// dropped from the cache.
+    pub fn compile_str(&mut self, text: &str) -> Result<ModuleSet> {
+        let expr = read_expr(text)?;
+        let features = self.features.iter().map(String::as_str).collect::<Vec<_>>();
+        let (shared_consts, module_exprs) = split_module_set(&expr)?;
+        let shared_hashes = shared_consts
+            .iter()
+            .map(|expr| hash_expr(expr))
+            .collect::<Vec<_>>();
+
+        self.stats = CompileStats::default();
+        let mut modules = Vec::new();
+        for module_expr in module_exprs {
+            let (module_id, item_exprs) = parse_module_items(module_expr, &features)?;
+            let mut item_hashes = item_exprs
+                .iter()
+                .map(|expr| hash_expr(expr))
+                .collect::<Vec<_>>();
+            item_hashes.extend(&shared_hashes);
+            let mut layout = item_exprs
+                .iter()
+                .map(|expr| ItemLayout::of(expr))
+                .collect::<Vec<_>>();
+            layout.extend(shared_hashes.iter().copied().map(ItemLayout::Other));
+
+            let cached = self
+                .cache
+                .as_ref()
+                .and_then(|cache| cache.modules.get(&module_id));
+            if let Some(cached) = cached.filter(|cached| cached.item_hashes == item_hashes) {
+                self.stats.modules_reused += 1;
+                modules.push(cached.module.clone());
+                continue;
+            }
+            if let Some(cached) = cached {
+                let old_hashes = cached.item_hashes.iter().collect::<HashSet<_>>();
+                self.stats.items_changed += item_hashes
+                    .iter()
+                    .filter(|hash| !old_hashes.contains(hash))
+                    .count();
+            }
+
+            let mut patched = None;
+            if let Some(cached) = cached.filter(|cached| cached.layout == layout) {
+                if let Some(module) = cached.rebuild_functions(&item_exprs, &item_hashes)? {
+                    self.stats.modules_patched += 1;
+                    self.stats.functions_rebuilt += item_hashes
+                        .iter()
+                        .zip(&cached.item_hashes)
+                        .filter(|(hash, cached_hash)| hash != cached_hash)
+                        .count();
+                    patched = Some((module, cached.names.clone(), cached.built_len));
+                }
+            }
+            let (module, names, built_len) = match patched {
+                Some(patched) => patched,
+                None => {
+                    let (module, names) =
+                        build_module_with_names(module_id.clone(), &item_exprs, &shared_consts)?;
+                    self.stats.modules_built += 1;
+                    let built_len = module.const_table().len();
+                    (module, names, built_len)
+                }
+            };
+            if let Some(cache) = &mut self.cache {
+                cache.modules.insert(
+                    module_id,
+                    CachedModule {
+                        item_hashes,
+                        layout,
+                        module: module.clone(),
+                        names,
+                        built_len,
+                    },
+                );
+            }
+            modules.push(module);
+        }
+
+        if let Some(cache) = &mut self.cache {
+            let compiled = modules.iter().map(ConstModule::id).collect::<HashSet<_>>();
+            cache.modules.retain(|id, _| compiled.contains(id));
+        }
+        ModuleSet::try_new(modules).ok_or(Error::CyclicDependencies)
+    }
+
+    /// Returns what the last compilation reused.
+    #[must_use]
+    pub fn last_stats(&self) -> CompileStats {
+        self.stats
+    }
+
+    #[must_use]
+    pub fn cache(&self) -> Option<&CompileCache> {
+        self.cache.as_ref()
+    }
+
+    /// Returns the cache, e.g. to keep it while the compiler's settings
+    /// change.
+    #[must_use]
+    pub fn into_cache(self) -> Option<CompileCache> {
+        self.cache
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        binary::{modules::ModuleMemberId, ConstIndex, ConstValue, ModuleConstIndex},
+        pure_values::Integer,
+    };
+
+    fn source(value: i64) -> String {
+        format!(
+            r#"
+                (module-set
+                    ("a"
+                        (const value {value})
+                        (export value))
+                    ("b"
+                        (const f (fn (push 1) (return 1)))
+                        (export f)))
+            "#
+        )
+    }
+
+    #[test]
+    fn unchanged_modules_are_reused() -> anyhow::Result<()> {
+        let mut compiler = Compiler::with_cache(CompileCache::new());
+        compiler.compile_str(&source(1))?;
+        assert_eq!(
+            compiler.last_stats(),
+            CompileStats {
+                modules_reused: 0,
+                modules_built: 2,
+                modules_patched: 0,
+                functions_rebuilt: 0,
+                items_changed: 0,
+            }
+        );
+
+        // Only layout changed, so both modules are reused.
+        compiler.compile_str(&source(1).replace("  ", " "))?;
+        assert_eq!(compiler.last_stats().modules_reused, 2);
+
+        let module_set = compiler.compile_str(&source(2))?;
+        assert_eq!(
+            compiler.last_stats(),
+            CompileStats {
+                modules_reused: 1,
+                modules_built: 1,
+                modules_patched: 0,
+                functions_rebuilt: 0,
+                items_changed: 1,
+            }
+        );
+        let a = module_set
+            .modules()
+            .find(|module| *module.id() == ModuleId::new(["a"]))
+            .unwrap();
+        assert!(a.exports().contains_key(&ModuleMemberId::new("value")));
+        assert_eq!(compiler.cache().map(CompileCache::len), Some(2));
+
+        compiler.compile_str(r#"(module-set ("a" (const value 2) (export value)))"#)?;
+        assert_eq!(compiler.cache().map(CompileCache::len), Some(1));
+        Ok(())
+    }
+
+    fn functions_source(value: i64, extra: &str) -> String {
+        format!(
+            r#"
+                (module-set
+                    ("a"
+                        (const helper (fn (push {value}) (return 1)))
+                        (const main (fn (push helper) (call 0 1) (return 1)))
+                        (init (push {value}) (pop 1) (return 0))
+                        {extra}
+                        (export helper)
+                        (export main)))
+            "#
+        )
+    }
+
+    /// Returns the integer pushed first by the function at `index`.
+    fn pushed_int(module: &ConstModule, index: ModuleConstIndex) -> Option<Integer> {
+        let ConstValue::Function(function) = index.get(module.const_table())? else {
+            return None;
+        };
+        let ConstIndex::ModuleConst(value) = function.module_constants().first()? else {
+            return None;
+        };
+        match value.get(module.const_table())? {
+            ConstValue::Integer(value) => Some(value.clone()),
+            _ => None,
+        }
+    }
+
+    fn only_module(module_set: &ModuleSet) -> &ConstModule {
+        module_set.modules().next().unwrap()
+    }
+
+    #[test]
+    fn changed_functions_are_rebuilt_in_place() -> anyhow::Result<()> {
+        let mut compiler = Compiler::with_cache(CompileCache::new());
+        let first = compiler.compile_str(&functions_source(1, ""))?;
+        let module_set = compiler.compile_str(&functions_source(2, ""))?;
+        assert_eq!(
+            compiler.last_stats(),
+            CompileStats {
+                modules_reused: 0,
+                modules_built: 0,
+                modules_patched: 1,
+                functions_rebuilt: 2,
+                items_changed: 2,
+            }
+        );
+
+        // The functions keep their indexes, and the other items their
+        // constants.
+        let (first, module) = (only_module(&first), only_module(&module_set));
+        assert_eq!(first.exports(), module.exports());
+        assert_eq!(first.initializer(), module.initializer());
+        let helper = module.exports()[&ModuleMemberId::new("helper")];
+        assert_eq!(pushed_int(module, helper), Some(Integer::from(2)));
+        assert_eq!(
+            pushed_int(module, module.initializer().unwrap()),
+            Some(Integer::from(2))
+        );
+        let main = module.exports()[&ModuleMemberId::new("main")];
+        assert!(matches!(
+            (main.get(first.const_table()), main.get(module.const_table())),
+            (Some(ConstValue::Function(before)), Some(ConstValue::Function(after)))
+                if before == after
+        ));
+
+        // Adding an item moves the constants after it, so the module is
+        // built as a whole.
+        compiler.compile_str(&functions_source(2, "(const extra 5)"))?;
+        assert_eq!(compiler.last_stats().modules_built, 1);
+        assert_eq!(compiler.last_stats().modules_patched, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn patched_modules_are_rebuilt_before_their_tables_double() -> anyhow::Result<()> {
+        let mut compiler = Compiler::with_cache(CompileCache::new());
+        let built_len = only_module(&compiler.compile_str(&functions_source(0, ""))?)
+            .const_table()
+            .len();
+        let mut modules_built = 0;
+        for value in 1..20 {
+            let module_set = compiler.compile_str(&functions_source(value, ""))?;
+            modules_built += compiler.last_stats().modules_built;
+            let module = only_module(&module_set);
+            assert!(module.const_table().len() <= 2 * built_len);
+            let helper = module.exports()[&ModuleMemberId::new("helper")];
+            assert_eq!(pushed_int(module, helper), Some(Integer::from(value)));
+        }
+        assert!(modules_built > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn cyclic_dependencies_are_reported() {
+        let source = r#"
+            (module-set
+                ("a" (import y "b" y) (const x 1) (export x))
+                ("b" (import x "a" x) (const y 2) (export y)))
+        "#;
+        let mut compiler = Compiler::with_cache(CompileCache::new());
+        assert!(matches!(
+            compiler.compile_str(source),
+            Err(Error::CyclicDependencies)
+        ));
+        assert!(matches!(
+            crate::lat::from_str(source),
+            Err(Error::CyclicDependencies)
+        ));
+    }
+
+    #[test]
+    fn compiler_without_cache_builds_every_module() -> anyhow::Result<()> {
+        let mut compiler = Compiler::new();
+        compiler.compile_str(&source(1))?;
+        compiler.compile_str(&source(1))?;
+        assert_eq!(compiler.last_stats().modules_built, 2);
+        assert!(compiler.into_cache().is_none());
+        Ok(())
+    }
+}
@@ -1,17 +1,26 @@
 //! A description of a text format to describe the contents of a Loon VM program.
 
+mod blob;
+mod compiler;
+mod stream;
+
 use std::{
-    cell::Cell,
+    cell::{Cell, RefCell},
     collections::{HashMap, HashSet},
 };
 
 use crate::binary::{
+    builders::ValueIndex,
     error::BuilderError,
-    instructions::{CallInstruction, CompareOp, StackIndex},
+    instructions::{CallInstruction, CompareOp, NumericKind, StackIndex, Truthiness},
     module_set::ModuleSet,
     modules::{ImportSource, ModuleId, ModuleMemberId},
     ConstModule, DeferredValue, FunctionBuilder, ModuleBuilder, ValueRef,
 };
+use crate::pure_values::Float;
+
+pub use compiler::{CompileCache, CompileStats, Compiler};
+pub use stream::from_reader;
 
 #[non_exhaustive]
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -64,6 +73,35 @@ pub enum Error {
 
     #[error("Unknown reference: {0}")]
     UnknownReference(String),
+
+    /// An integer literal does not fit the operand it was written for, such
+    /// as a negative stack index. `role` names the operand.
+    #[error("{role} out of range: {value}")]
+    IntOutOfRange { role: &'static str, value: i64 },
+
+    #[error("Invalid float bit pattern: {0:?}")]
+    InvalidFloatBits(String),
+
+    #[error("Invalid {0} byte string: {1:?}")]
+    InvalidBytes(&'static str, String),
+
+    #[error("Name defined more than once: {0}")]
+    DuplicateName(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("Unexpected end of input")]
+    UnexpectedEnd,
+
+    #[error("Unexpected character {0:?}")]
+    UnexpectedChar(char),
+
+    #[error("Shared constants must come before the first module when streaming")]
+    SharedConstsAfterModule,
+
+    #[error("The modules' dependencies form a cycle")]
+    CyclicDependencies,
 }
 
 impl Error {
@@ -135,6 +173,29 @@ fn parse_int(expr: &lexpr::Value) -> Result<i64> {
         .ok_or_else(|| Error::new_unexpected_value_type([SExprType::Number], expr))
 }
 
+/// Parses an integer operand that must fit a `u32`, such as a count or an
+/// index. `role` names the operand in the error if it does not.
+fn parse_u32(expr: &lexpr::Value, role: &'static str) -> Result<u32> {
+    let value = parse_int(expr)?;
+    u32::try_from(value).map_err(|_| Error::IntOutOfRange { role, value })
+}
+
+/// Converts the position of a parameter or the number of parameters.
+fn param_count(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| BuilderError::IndexOverflow.into())
+}
+
+/// Parses the `top`/`bot` and offset operands of a stack instruction.
+fn parse_stack_index(stack_end: &lexpr::Value, index: &lexpr::Value) -> Result<StackIndex> {
+    let index = parse_u32(index, "stack index")?;
+    let stack_end = parse_symbol(stack_end)?;
+    Ok(match stack_end {
+        "top" => StackIndex::FromTop(index),
+        "bot" => StackIndex::FromBottom(index),
+        _ => return Err(Error::UnexpectedSymbol(stack_end.to_string())),
+    })
+}
+
 fn parse_const_len_list<const L: usize>(list: &lexpr::Value) -> Result<[&lexpr::Value; L]> {
     let iter = parse_list(list)?;
     iter.collect::<Vec<_>>()
@@ -162,20 +223,67 @@ fn parse_module_id(name: &str) -> Result<ModuleId> {
     Ok(ModuleId::new(items))
 }
 
+/// Reads the s-expression in `text`, accepting hex byte string literals,
+/// `#x"DEAD"`, in addition to the syntax of the s-expression reader.
+fn read_expr(text: &str) -> Result<lexpr::Value> {
+    Ok(lexpr::from_str(&blob::expand_hex_literals(text)?)?)
+}
+
 pub fn from_str(text: &str) -> Result<ModuleSet> {
-    let expr = lexpr::from_str(text)?;
+    from_str_with_features(text, &[])
+}
+
+/// Parses a module set as [`from_str`] does, with the named features
+/// enabled. Module items written as `(when-feature "name" item...)` are
+/// included only if `name` is among `features`, so that one source can
+/// describe several variants of a module set.
+pub fn from_str_with_features(text: &str, features: &[&str]) -> Result<ModuleSet> {
+    let expr = read_expr(text)?;
 
-    parse_module_set(&expr)
+    parse_module_set_with_features(&expr, features)
 }
 
+#[cfg(test)]
 fn parse_module_set(expr: &lexpr::Value) -> Result<ModuleSet> {
-    let modules = parse_list_with_head("module-set", expr)?;
+    parse_module_set_with_features(expr, &[])
+}
+
+fn parse_module_set_with_features(expr: &lexpr::Value, features: &[&str]) -> Result<ModuleSet> {
+    let (shared_consts, module_exprs) = split_module_set(expr)?;
     let mut module_list = Vec::new();
-    for module_expr in parse_list(modules)? {
-        let module = parse_module(module_expr)?;
+    for module_expr in module_exprs {
+        let module = parse_module(module_expr, &shared_consts, features)?;
         module_list.push(module);
     }
-    Ok(ModuleSet::new(module_list))
+    ModuleSet::try_new(module_list).ok_or(Error::CyclicDependencies)
+}
+
+/// Splits a module set into the bodies of its shared constants and its
+/// module expressions.
+fn split_module_set(expr: &lexpr::Value) -> Result<(Vec<&lexpr::Value>, Vec<&lexpr::Value>)> {
+    let modules = parse_list_with_head("module-set", expr)?;
+    // Shared constants apply to every module in the set, wherever the
+    // section appears.
+    let mut shared_consts = Vec::new();
+    let mut module_exprs = Vec::new();
+    for item_expr in parse_list(modules)? {
+        let head = item_expr.as_cons().and_then(|cons| cons.car().as_symbol());
+        if head == Some("shared-consts") {
+            let entries = parse_list_with_head("shared-consts", item_expr)?;
+            shared_consts.extend(parse_list(entries)?);
+        } else {
+            module_exprs.push(item_expr);
+        }
+    }
+    let mut shared_names = HashSet::new();
+    for entry in &shared_consts {
+        let ([local_name, _], _) = parse_documented_item::<2>(entry)?;
+        let local_name = parse_symbol(local_name)?;
+        if !shared_names.insert(local_name) {
+            return Err(Error::DuplicateName(local_name.to_string()));
+        }
+    }
+    Ok((shared_consts, module_exprs))
 }
 
 struct ImportItem<'a> {
@@ -185,6 +293,7 @@ struct ImportItem<'a> {
 
 struct ExportItem<'a> {
     local_name: &'a str,
+    doc: Option<&'a str>,
 }
 
 struct ConstantItem<'a> {
@@ -192,6 +301,7 @@ struct ConstantItem<'a> {
     value: ValueRef,
     deferred_value: Cell<Option<DeferredValue>>,
     expr: &'a lexpr::Value,
+    doc: Option<&'a str>,
 }
 
 impl ConstantItem<'_> {
@@ -216,77 +326,250 @@ struct InitItem<'a> {
     body: &'a lexpr::Value,
 }
 
+struct TestItem<'a> {
+    name: &'a str,
+    expr: &'a lexpr::Value,
+}
+
 enum ModuleItem<'a> {
     Import(ImportItem<'a>),
     Export(ExportItem<'a>),
     Const(ConstantItem<'a>),
     Global(GlobalItem<'a>),
     Init(InitItem<'a>),
+    Test(TestItem<'a>),
+}
+
+impl<'a> ModuleItem<'a> {
+    /// Returns the name this item defines in the module scope, if any.
+    fn local_name(&self) -> Option<&'a str> {
+        match self {
+            ModuleItem::Import(import) => Some(import.local_name),
+            ModuleItem::Const(constant) => Some(constant.local_name),
+            ModuleItem::Global(global) => Some(global.local_name),
+            ModuleItem::Export(_) | ModuleItem::Init(_) | ModuleItem::Test(_) => None,
+        }
+    }
+}
+
+/// Appends the module items of `exprs` to `out`. Each conditional item
+/// `(when-feature "name" item...)` is replaced by its items if `name` is one
+/// of `features`, and dropped otherwise. Conditional items may nest.
+fn expand_conditional_items<'a>(
+    exprs: impl Iterator<Item = &'a lexpr::Value>,
+    features: &[&str],
+    out: &mut Vec<&'a lexpr::Value>,
+) -> Result<()> {
+    for expr in exprs {
+        let head = expr.as_cons().and_then(|cons| cons.car().as_symbol());
+        if head != Some("when-feature") {
+            out.push(expr);
+            continue;
+        }
+        let (feature, items) = parse_cons(parse_list_with_head("when-feature", expr)?)?;
+        if features.contains(&parse_str(feature)?) {
+            expand_conditional_items(parse_list(items)?, features, out)?;
+        }
+    }
+    Ok(())
 }
 
-fn parse_module(expr: &lexpr::Value) -> Result<ConstModule> {
+/// Parses a module, with the shared constants of its module set.
+fn parse_module(
+    expr: &lexpr::Value,
+    shared_consts: &[&lexpr::Value],
+    features: &[&str],
+) -> Result<ConstModule> {
+    let (module_id, item_exprs) = parse_module_items(expr, features)?;
+    build_module(module_id, &item_exprs, shared_consts)
+}
+
+/// Returns the id of a module, and its items with conditional items
+/// expanded.
+fn parse_module_items<'a>(
+    expr: &'a lexpr::Value,
+    features: &[&str],
+) -> Result<(ModuleId, Vec<&'a lexpr::Value>)> {
     let (module_str_value, module_contents) = parse_cons(expr)?;
     let module_id = parse_module_id(parse_str(module_str_value)?)?;
-    let builder = ModuleBuilder::new(module_id.clone());
+    let mut item_exprs = Vec::new();
+    expand_conditional_items(parse_list(module_contents)?, features, &mut item_exprs)?;
+    Ok((module_id, item_exprs))
+}
+
+/// Builds a module from its items. Each of `shared_consts` is the body of a
+/// constant item that is added to the module, unless the module defines the
+/// same name.
+fn build_module(
+    module_id: ModuleId,
+    item_exprs: &[&lexpr::Value],
+    shared_consts: &[&lexpr::Value],
+) -> Result<ConstModule> {
+    let (module, _) = build_module_with_names(module_id, item_exprs, shared_consts)?;
+    Ok(module)
+}
+
+/// Builds a module as [`build_module`] does, and returns where each name in
+/// the module's scope was placed.
+fn build_module_with_names(
+    module_id: ModuleId,
+    item_exprs: &[&lexpr::Value],
+    shared_consts: &[&lexpr::Value],
+) -> Result<(ConstModule, HashMap<String, ValueIndex>)> {
+    let builder = ModuleBuilder::new(module_id);
     let mut items = Vec::new();
-    for module_item_expr in parse_list(module_contents)? {
+    for module_item_expr in item_exprs {
         items.push(parse_module_item(&builder, module_item_expr)?)
     }
+    let local_names = items
+        .iter()
+        .filter_map(ModuleItem::local_name)
+        .collect::<HashSet<_>>();
+    for shared_const in shared_consts {
+        let constant = parse_constant_item(&builder, shared_const)?;
+        if !local_names.contains(constant.local_name) {
+            items.push(ModuleItem::Const(constant));
+        }
+    }
 
-    resolve_items(&builder, &items)?;
+    let references = resolve_items(&builder, &items)?;
 
     let module = builder.into_const_module()?;
-    Ok(module)
+    let names = references
+        .names
+        .into_iter()
+        .map(|(name, value)| {
+            let index = value
+                .resolved_index()
+                .ok_or(BuilderError::UnresolvedReference)?;
+            Ok((name, index))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+    Ok((module, names))
 }
 
-struct ReferenceSet<'a>(HashMap<&'a str, ValueRef>);
+/// The names defined in a module's scope.
+#[derive(Default)]
+struct ReferenceSet {
+    names: HashMap<String, ValueRef>,
+    /// Set when names may be used before they are defined. Such uses get a
+    /// placeholder, which is resolved when the definition is found.
+    forward: Option<RefCell<HashMap<String, (ValueRef, DeferredValue)>>>,
+}
+
+impl ReferenceSet {
+    fn with_forward_references() -> Self {
+        ReferenceSet {
+            names: HashMap::new(),
+            forward: Some(RefCell::new(HashMap::new())),
+        }
+    }
+
+    fn get(&self, builder: &ModuleBuilder, name: &str) -> Result<ValueRef> {
+        if let Some(value) = self.names.get(name) {
+            return Ok(value.clone());
+        }
+        let Some(forward) = &self.forward else {
+            return Err(Error::UnknownReference(name.to_string()));
+        };
+        let mut forward = forward.borrow_mut();
+        let (placeholder, _) = forward
+            .entry(name.to_string())
+            .or_insert_with(|| builder.new_deferred());
+        Ok(placeholder.clone())
+    }
+
+    /// Adds a definition of `name`, resolving any placeholder that was given
+    /// out for it.
+    fn define(&mut self, name: &str, value: ValueRef) -> Result<()> {
+        if self.names.contains_key(name) {
+            return Err(Error::DuplicateName(name.to_string()));
+        }
+        let placeholder = self
+            .forward
+            .as_mut()
+            .and_then(|forward| forward.get_mut().remove(name));
+        if let Some((_, deferred)) = placeholder {
+            deferred.resolve_other(&value)?;
+        }
+        self.names.insert(name.to_string(), value);
+        Ok(())
+    }
 
-impl ReferenceSet<'_> {
-    fn get(&self, name: &str) -> Result<&ValueRef> {
-        self.0
-            .get(name)
-            .ok_or_else(|| Error::UnknownReference(name.to_string()))
+    /// Stops giving out placeholders. Fails if a placeholder was given out
+    /// for a name that was never defined.
+    fn close_forward_references(&mut self) -> Result<()> {
+        let Some(forward) = self.forward.take() else {
+            return Ok(());
+        };
+        match forward.into_inner().into_keys().min() {
+            Some(name) => Err(Error::UnknownReference(name)),
+            None => Ok(()),
+        }
     }
 }
 
-fn gather_item_references<'a>(items: &[ModuleItem<'a>]) -> Result<ReferenceSet<'a>> {
+fn gather_item_references(items: &[ModuleItem]) -> Result<ReferenceSet> {
     let mut references = HashMap::new();
     for item in items {
         match item {
             ModuleItem::Const(constant) => {
-                references.insert(constant.local_name, constant.value.clone());
+                references.insert(constant.local_name.to_string(), constant.value.clone());
             }
             ModuleItem::Import(import) => {
-                references.insert(import.local_name, import.value_ref.clone());
+                references.insert(import.local_name.to_string(), import.value_ref.clone());
             }
             ModuleItem::Global(global) => {
-                references.insert(global.local_name, global.value.clone());
+                references.insert(global.local_name.to_string(), global.value.clone());
             }
-            ModuleItem::Init(_) | ModuleItem::Export(_) => {}
+            ModuleItem::Init(_) | ModuleItem::Export(_) | ModuleItem::Test(_) => {}
         }
     }
-    Ok(ReferenceSet(references))
+    Ok(ReferenceSet {
+        names: references,
+        forward: None,
+    })
 }
 
-fn resolve_items(builder: &ModuleBuilder, items: &[ModuleItem]) -> Result<()> {
+/// Resolves the items of a module, and returns the names they define.
+fn resolve_items(builder: &ModuleBuilder, items: &[ModuleItem]) -> Result<ReferenceSet> {
     let references = gather_item_references(items)?;
+    // Docs on a const apply when it is exported under the same name.
+    let const_docs: HashMap<&str, &str> = items
+        .iter()
+        .filter_map(|item| match item {
+            ModuleItem::Const(constant) => Some((constant.local_name, constant.doc?)),
+            _ => None,
+        })
+        .collect();
     for item in items {
         match item {
             ModuleItem::Const(constant) => {
                 constant.resolve(builder, &references)?;
             }
             ModuleItem::Export(export) => {
+                let member_id = ModuleMemberId::new(export.local_name);
                 references
-                    .get(export.local_name)?
-                    .export(ModuleMemberId::new(export.local_name))?;
+                    .get(builder, export.local_name)?
+                    .export(member_id.clone())?;
+                let doc = export
+                    .doc
+                    .or_else(|| const_docs.get(export.local_name).copied());
+                if let Some(doc) = doc {
+                    builder.set_export_doc(member_id, doc)?;
+                }
             }
             ModuleItem::Init(init) => {
                 resolve_fn_expr(builder, &references, builder.new_initializer()?, init.body)?;
             }
+            ModuleItem::Test(test) => {
+                let value = parse_constant_expr(builder, &references, test.expr)?;
+                builder.add_test(test.name, &value)?;
+            }
             ModuleItem::Global(_) | ModuleItem::Import(_) => {}
         }
     }
-    Ok(())
+    Ok(references)
 }
 
 fn parse_module_item<'a>(
@@ -300,6 +583,7 @@ fn parse_module_item<'a>(
         "const" => ModuleItem::Const(parse_constant_item(builder, rest)?),
         "global" => ModuleItem::Global(parse_global_item(builder, rest)?),
         "init" => ModuleItem::Init(InitItem { body: rest }),
+        "test" => ModuleItem::Test(parse_test_item(rest)?),
         unknown_symbol => return Err(Error::UnexpectedSymbol(unknown_symbol.to_string())),
     };
     Ok(item)
@@ -309,11 +593,27 @@ fn parse_import_item<'a>(
     builder: &ModuleBuilder,
     body: &'a lexpr::Value,
 ) -> Result<ImportItem<'a>> {
-    // Has the form (import <name-sym> <module-id-str> <module-item-symbol>)
-    let [local_name, module_id_str, member_symbol] = parse_const_len_list(body)?;
+    // Has the form
+    // (import <name-sym> <module-id-str> <module-item-symbol> [#:optional])
+    let mut items = parse_list(body)?.collect::<Vec<_>>();
+    let mut optional = false;
+    if items.len() == 4 {
+        let flag = parse_keyword(items.pop().expect("List is not empty"))?;
+        if flag != "optional" {
+            return Err(Error::UnexpectedSymbol(flag.to_string()));
+        }
+        optional = true;
+    }
+    let [local_name, module_id_str, member_symbol] = items
+        .try_into()
+        .map_err(|v: Vec<_>| Error::WrongParamSize(3, v.len()))?;
     let module_id = parse_module_id(parse_str(module_id_str)?)?;
     let member_id = ModuleMemberId::new(parse_symbol(member_symbol)?);
-    let import_source = ImportSource::new(module_id, member_id);
+    let import_source = if optional {
+        ImportSource::new_optional(module_id, member_id)
+    } else {
+        ImportSource::new(module_id, member_id)
+    };
     let value_ref = builder.add_import(import_source);
     Ok(ImportItem {
         local_name: parse_symbol(local_name)?,
@@ -321,10 +621,30 @@ fn parse_import_item<'a>(
     })
 }
 
+/// Parses the items of a module item with `L` required parameters and an
+/// optional trailing `(doc "...")` annotation.
+fn parse_documented_item<const L: usize>(
+    body: &lexpr::Value,
+) -> Result<([&lexpr::Value; L], Option<&str>)> {
+    let mut items = parse_list(body)?.collect::<Vec<_>>();
+    let mut doc = None;
+    if items.len() == L + 1 {
+        let doc_body = parse_list_with_head("doc", items.pop().expect("List is not empty"))?;
+        let [doc_str] = parse_const_len_list(doc_body)?;
+        doc = Some(parse_str(doc_str)?);
+    }
+    let items = items
+        .try_into()
+        .map_err(|v: Vec<_>| Error::WrongParamSize(L, v.len()))?;
+    Ok((items, doc))
+}
+
 fn parse_export_item(body: &lexpr::Value) -> Result<ExportItem> {
-    let [local_name] = parse_const_len_list(body)?;
+    // Has the form (export <name-sym> [(doc <doc-str>)])
+    let ([local_name], doc) = parse_documented_item(body)?;
     Ok(ExportItem {
         local_name: parse_symbol(local_name)?,
+        doc,
     })
 }
 
@@ -332,14 +652,24 @@ fn parse_constant_item<'a>(
     builder: &ModuleBuilder,
     body: &'a lexpr::Value,
 ) -> Result<ConstantItem<'a>> {
-    // Has the form (const <local-name-sym> <const-value>)
-    let [local_name, expr] = parse_const_len_list(body)?;
+    // Has the form (const <local-name-sym> <const-value> [(doc <doc-str>)])
+    let ([local_name, expr], doc) = parse_documented_item(body)?;
     let (value, deferred_value) = builder.new_deferred();
     Ok(ConstantItem {
         local_name: parse_symbol(local_name)?,
         value,
         deferred_value: Cell::new(Some(deferred_value)),
         expr,
+        doc,
+    })
+}
+
+fn parse_test_item(body: &lexpr::Value) -> Result<TestItem<'_>> {
+    // Has the form (test <name-str> <const-value>)
+    let [name, expr] = parse_const_len_list(body)?;
+    Ok(TestItem {
+        name: parse_str(name)?,
+        expr,
     })
 }
 
@@ -380,7 +710,12 @@ fn resolve_constant_expr(
     } else if let Some(s) = expr.as_str() {
         deferred.resolve_string(s)?;
     } else if let Some(name) = expr.as_symbol() {
-        deferred.resolve_other(references.get(name)?)?;
+        // Hex float literals such as `0x1.8p3` are read as symbols.
+        if let Some(f) = Float::from_hex_literal(name) {
+            deferred.resolve_float(f)?;
+        } else {
+            deferred.resolve_other(&references.get(builder, name)?)?;
+        }
     } else if let Some(cons) = expr.as_cons() {
         resolve_constant_compound_expr(builder, references, deferred, cons)?;
     } else {
@@ -406,12 +741,60 @@ fn resolve_constant_compound_expr(
     let body = expr.cdr();
     match parse_symbol(expr.car())? {
         "list" => resolve_list_expr(builder, references, deferred, body)?,
+        "record" => resolve_record_expr(builder, references, deferred, body)?,
         "fn" => resolve_fn_expr(builder, references, deferred.into_function_builder(), body)?,
+        "float-bits" => deferred.resolve_float(parse_float_bits(body)?)?,
+        "bytes" => deferred.resolve_bytes(parse_bytes(body)?)?,
+        "null" => {
+            let [] = parse_const_len_list(body)?;
+            deferred.resolve_null()?;
+        }
         unknown_symbol => return Err(Error::UnexpectedSymbol(unknown_symbol.to_string())),
     }
     Ok(())
 }
 
+/// Parses the body of a `(float-bits "7ff0000000000000")` expression, which
+/// gives the exact IEEE 754 bit pattern of a float, including infinities and
+/// NaN payloads.
+fn parse_float_bits(body: &lexpr::Value) -> Result<Float> {
+    let [bits] = parse_const_len_list(body)?;
+    let bits = parse_str(bits)?;
+    let digits = bits
+        .strip_prefix("0x")
+        .or_else(|| bits.strip_prefix("0X"))
+        .unwrap_or(bits);
+    if digits.is_empty() || digits.len() > 16 {
+        return Err(Error::InvalidFloatBits(bits.to_string()));
+    }
+    u64::from_str_radix(digits, 16)
+        .map(Float::from_bits)
+        .map_err(|_| Error::InvalidFloatBits(bits.to_string()))
+}
+
+/// Parses the body of a byte string expression. The contents can be given as
+/// a byte vector, `(bytes #u8(222 173))` or `(bytes #x"DEAD")`, or as encoded
+/// text, `(bytes hex "DEAD")` or `(bytes base64 "3q0=")`.
+fn parse_bytes(body: &lexpr::Value) -> Result<Vec<u8>> {
+    let mut items = parse_list(body)?;
+    let (Some(first), second, None) = (items.next(), items.next(), items.next()) else {
+        return Err(Error::WrongParamSize(2, parse_list(body)?.count()));
+    };
+    let Some(text) = second else {
+        return first
+            .as_bytes()
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| Error::new_unexpected_value_type([SExprType::Unsupported], first));
+    };
+    let text = parse_str(text)?;
+    let (encoding, decoded) = match parse_symbol(first)? {
+        "hex" => ("hex", blob::decode_hex(text)),
+        "base64" => ("base64", blob::decode_base64(text)),
+        unknown_symbol => return Err(Error::UnexpectedSymbol(unknown_symbol.to_string())),
+    };
+    decoded.ok_or_else(|| Error::InvalidBytes(encoding, text.to_string()))
+}
+
 fn resolve_list_expr(
     builder: &ModuleBuilder,
     references: &ReferenceSet,
@@ -428,19 +811,192 @@ fn resolve_list_expr(
     Ok(())
 }
 
+/// Parses the body of a `(record (<field-sym> <const-value>)...)` expression.
+fn resolve_record_expr(
+    builder: &ModuleBuilder,
+    references: &ReferenceSet,
+    deferred: DeferredValue,
+    expr: &lexpr::Value,
+) -> Result<()> {
+    let mut fields = Vec::new();
+    for field_expr in parse_list(expr)? {
+        let [name, value_expr] = parse_const_len_list(field_expr)?;
+        let (value, value_deferred) = builder.new_deferred();
+        resolve_constant_expr(builder, references, value_deferred, value_expr)?;
+        fields.push((parse_symbol(name)?, value));
+    }
+    deferred.resolve_record(fields)?;
+    Ok(())
+}
+
+/// Parses an optional `(params <name>...)` header, mapping each parameter
+/// name to its index from the bottom of the stack. The header also declares
+/// the function's arity.
+fn parse_params_header(inst_expr: &lexpr::Value) -> Result<Option<HashMap<&str, u32>>> {
+    let Some(cons) = inst_expr.as_cons() else {
+        return Ok(None);
+    };
+    if cons.car().as_symbol() != Some("params") {
+        return Ok(None);
+    }
+    let mut params = HashMap::new();
+    for (index, name) in parse_list(cons.cdr())?.enumerate() {
+        params.insert(parse_symbol(name)?, param_count(index)?);
+    }
+    Ok(Some(params))
+}
+
 fn resolve_fn_expr(
+    builder: &ModuleBuilder,
+    references: &ReferenceSet,
+    fn_builder: FunctionBuilder,
+    body: &lexpr::Value,
+) -> Result<()> {
+    resolve_fn_body(builder, references, fn_builder, &[], body)
+}
+
+/// Resolves the instructions of a function whose first parameters are
+/// `captures`, followed by those named in its `(params ...)` header.
+fn resolve_fn_body(
     builder: &ModuleBuilder,
     references: &ReferenceSet,
     mut fn_builder: FunctionBuilder,
+    captures: &[&str],
     body: &lexpr::Value,
 ) -> Result<()> {
-    for inst_expr in parse_list(body)? {
-        apply_fn_inst(builder, &mut fn_builder, references, inst_expr)?;
+    let mut params = captures
+        .iter()
+        .enumerate()
+        .map(|(index, name)| Ok((*name, param_count(index)?)))
+        .collect::<Result<HashMap<_, _>>>()?;
+    let num_captures = param_count(captures.len())?;
+    for (i, inst_expr) in parse_list(body)?.enumerate() {
+        if i == 0 {
+            if let Some(header) = parse_params_header(inst_expr)? {
+                let arity = num_captures
+                    .checked_add(param_count(header.len())?)
+                    .ok_or(BuilderError::IndexOverflow)?;
+                fn_builder.set_arity(arity);
+                params.extend(
+                    header
+                        .into_iter()
+                        .map(|(name, index)| (name, index + num_captures)),
+                );
+                continue;
+            }
+        }
+        apply_fn_inst(builder, &mut fn_builder, references, &params, inst_expr)?;
     }
     fn_builder.build()?;
     Ok(())
 }
 
+/// Pushes a closure, written `(closure (<capture>...) <inst>...)`. Captures
+/// name parameters of the enclosing function. The body becomes a function
+/// that takes the captured values before its own parameters, and is bound to
+/// their current values with `bind_front`.
+///
+/// Closures may be nested, capturing the captures or parameters of the
+/// closure around them.
+fn apply_closure(
+    builder: &ModuleBuilder,
+    fn_builder: &mut FunctionBuilder,
+    references: &ReferenceSet,
+    params: &HashMap<&str, u32>,
+    body: &lexpr::Value,
+) -> Result<()> {
+    let (captures, closure_body) = parse_cons(body)?;
+    let captures = parse_list(captures)?
+        .map(parse_symbol)
+        .collect::<Result<Vec<_>>>()?;
+    let capture_indexes = captures
+        .iter()
+        .map(|name| {
+            params
+                .get(name)
+                .copied()
+                .ok_or_else(|| Error::UnknownReference(name.to_string()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (function, deferred) = builder.new_deferred();
+    resolve_fn_body(
+        builder,
+        references,
+        deferred.into_function_builder(),
+        &captures,
+        closure_body,
+    )?;
+    fn_builder.push_value(&function)?;
+    if !capture_indexes.is_empty() {
+        for index in &capture_indexes {
+            fn_builder.push_copy(StackIndex::FromBottom(*index));
+        }
+        fn_builder.bind_front(param_count(capture_indexes.len())?);
+    }
+    Ok(())
+}
+
+/// Parses `text` as a sequence of instructions, and adds them to
+/// `fn_builder`. `params` name the function's parameters, in order, so that
+/// `(push <name>)` reads them.
+///
+/// Constants in the instructions may only be literals, as there are no other
+/// module items to refer to.
+#[cfg(feature = "runtime")]
+pub(crate) fn apply_instructions_from_str(
+    builder: &ModuleBuilder,
+    fn_builder: &mut FunctionBuilder,
+    params: &[&str],
+    text: &str,
+) -> Result<()> {
+    let params = params
+        .iter()
+        .enumerate()
+        .map(|(index, name)| Ok((*name, param_count(index)?)))
+        .collect::<Result<HashMap<_, _>>>()?;
+    let references = ReferenceSet::default();
+    let mut parser = lexpr::parse::Parser::from_str(text);
+    while let Some(inst_expr) = parser.next_value()? {
+        apply_fn_inst(builder, fn_builder, &references, &params, &inst_expr)?;
+    }
+    Ok(())
+}
+
+fn parse_compare_op(expr: &lexpr::Value) -> Result<CompareOp> {
+    let name = parse_symbol(expr)?;
+    Ok(match name {
+        "ref_eq" => CompareOp::RefEq,
+        "eq" => CompareOp::Eq,
+        "ne" => CompareOp::Ne,
+        "lt" => CompareOp::Lt,
+        "le" => CompareOp::Le,
+        "gt" => CompareOp::Gt,
+        "ge" => CompareOp::Ge,
+        _ => return Err(Error::UnexpectedSymbol(name.to_string())),
+    })
+}
+
+fn parse_truthiness(expr: &lexpr::Value) -> Result<Truthiness> {
+    let name = parse_symbol(expr)?;
+    Ok(match name {
+        "strict" => Truthiness::Strict,
+        "null_and_false" => Truthiness::NullAndFalse,
+        "empty" => Truthiness::Empty,
+        _ => return Err(Error::UnexpectedSymbol(name.to_string())),
+    })
+}
+
+fn parse_numeric_kind(expr: &lexpr::Value) -> Result<NumericKind> {
+    let name = parse_symbol(expr)?;
+    Ok(match name {
+        "integer" => NumericKind::Integer,
+        "rational" => NumericKind::Rational,
+        "float" => NumericKind::Float,
+        _ => return Err(Error::UnexpectedSymbol(name.to_string())),
+    })
+}
+
 macro_rules! op_parse {
     ($cons:expr => $(($name:literal $(, $arg:ident)* $(,)?) => $body:block)*) => {
         match parse_symbol($cons.car())? {
@@ -457,40 +1013,47 @@ fn apply_fn_inst(
     builder: &ModuleBuilder,
     fn_builder: &mut FunctionBuilder,
     references: &ReferenceSet,
+    params: &HashMap<&str, u32>,
     body: &lexpr::Value,
 ) -> Result<()> {
     match body {
         lexpr::Value::Keyword(kw) => {
             fn_builder.define_branch_target(kw);
         }
+        lexpr::Value::Cons(cons) if cons.car().as_symbol() == Some("closure") => {
+            apply_closure(builder, fn_builder, references, params, cons.cdr())?;
+        }
         lexpr::Value::Cons(cons) => {
             op_parse! { cons =>
                 ("push", value_expr) => {
-                    let value = parse_constant_expr(builder, references, value_expr)?;
-                    fn_builder.push_value(&value)?;
+                    // Parameters are read from their stack slot.
+                    if let Some(&index) = value_expr.as_symbol().and_then(|name| params.get(name)) {
+                        fn_builder.push_copy(StackIndex::FromBottom(index));
+                    } else {
+                        let value = parse_constant_expr(builder, references, value_expr)?;
+                        fn_builder.push_value(&value)?;
+                    }
                 }
                 ("pop", n_pop) => {
-                    fn_builder.pop(parse_int(n_pop)? as u32);
+                    fn_builder.pop(parse_u32(n_pop, "pop count")?);
                 }
                 ("write_stack", stack_end, index) => {
-                    let index = parse_int(index)? as u32;
-                    let stack_end = parse_symbol(stack_end)?;
-                    let stack_index = match stack_end {
-                        "top" => {
-                            StackIndex::FromTop(index)
-                        }
-                        "bot" => {
-                            StackIndex::FromBottom(index)
-                        }
-                        _ => return Err(Error::UnexpectedSymbol(stack_end.to_string())),
-                    };
-                    fn_builder.write_stack(stack_index);
+                    fn_builder.write_stack(parse_stack_index(stack_end, index)?);
                 }
                 ("add") => {
                     fn_builder.add();
                 }
+                ("sub") => {
+                    fn_builder.sub();
+                }
+                ("mul") => {
+                    fn_builder.mul();
+                }
+                ("div") => {
+                    fn_builder.div();
+                }
                 ("return", num_args) => {
-                    fn_builder.return_(parse_int(num_args)? as u32);
+                    fn_builder.return_(parse_u32(num_args, "return count")?);
                 }
                 ("return_dynamic") => {
                     fn_builder.return_dynamic();
@@ -501,40 +1064,94 @@ fn apply_fn_inst(
                 ("branch_if", target) => {
                     fn_builder.branch_if(parse_keyword(target)?);
                 }
+                ("branch_if_truthy", target, truthiness) => {
+                    fn_builder.branch_if_truthy(parse_keyword(target)?, parse_truthiness(truthiness)?);
+                }
                 ("push_copy", stack_end, index) => {
-                    let index = parse_int(index)? as u32;
-                    let stack_end = parse_symbol(stack_end)?;
-                    let stack_index = match stack_end {
-                        "top" => {
-                            StackIndex::FromTop(index)
-                        }
-                        "bot" => {
-                            StackIndex::FromBottom(index)
-                        }
-                        _ => return Err(Error::UnexpectedSymbol(stack_end.to_string())),
-                    };
-                    fn_builder.push_copy(stack_index);
+                    fn_builder.push_copy(parse_stack_index(stack_end, index)?);
                 }
                 ("call", num_args, num_returns) => {
-                    let num_args = parse_int(num_args)? as u32;
-                    let num_returns = parse_int(num_returns)? as u32;
+                    let num_args = parse_u32(num_args, "argument count")?;
+                    let num_returns = parse_u32(num_returns, "return count")?;
                     fn_builder.call(CallInstruction { num_args, num_returns });
                 }
+                ("call_protected", num_args, num_returns, handler) => {
+                    let num_args = parse_u32(num_args, "argument count")?;
+                    let num_returns = parse_u32(num_returns, "return count")?;
+                    fn_builder.call_protected(
+                        CallInstruction { num_args, num_returns },
+                        parse_keyword(handler)?,
+                    );
+                }
+                ("raise") => {
+                    fn_builder.raise();
+                }
                 ("tail_call", num_args) => {
-                    let num_args = parse_int(num_args)? as u32;
+                    let num_args = parse_u32(num_args, "argument count")?;
                     fn_builder.tail_call(num_args);
                 }
                 ("cmp", op) => {
-                    let op = parse_symbol(op)?;
-                    match op {
-                        "ref_eq" => {
-                            fn_builder.compare(CompareOp::RefEq);
-                        }
-                        _ => return Err(Error::UnexpectedSymbol(op.to_string())),
-                    }
+                    fn_builder.compare(parse_compare_op(op)?);
+                }
+                ("cmp_branch", op, target) => {
+                    fn_builder.cmp_branch(parse_compare_op(op)?, parse_keyword(target)?);
+                }
+                ("apply") => {
+                    fn_builder.apply();
+                }
+                ("push_null") => {
+                    fn_builder.push_null();
+                }
+                ("record_new", fields) => {
+                    let fields = parse_list(fields)?
+                        .map(parse_symbol)
+                        .collect::<Result<Vec<_>>>()?;
+                    fn_builder.record_new(fields);
+                }
+                ("record_get", field) => {
+                    fn_builder.record_get(parse_symbol(field)?);
+                }
+                ("record_set", field) => {
+                    fn_builder.record_set(parse_symbol(field)?);
+                }
+                ("tag_new", name) => {
+                    fn_builder.tag_new(parse_symbol(name)?);
+                }
+                ("tag_wrap") => {
+                    fn_builder.tag_wrap();
+                }
+                ("tag_of") => {
+                    fn_builder.tag_of();
+                }
+                ("untag") => {
+                    fn_builder.untag();
+                }
+                ("is_null") => {
+                    fn_builder.is_null();
+                }
+                ("identity_hash") => {
+                    fn_builder.identity_hash();
+                }
+                ("str_len_bytes") => {
+                    fn_builder.str_len_bytes();
+                }
+                ("str_len_chars") => {
+                    fn_builder.str_len_chars();
+                }
+                ("str_slice_bytes") => {
+                    fn_builder.str_slice_bytes();
+                }
+                ("str_slice_chars") => {
+                    fn_builder.str_slice_chars();
+                }
+                ("to_bool", truthiness) => {
+                    fn_builder.to_bool(parse_truthiness(truthiness)?);
+                }
+                ("to_number", kind) => {
+                    fn_builder.to_number(parse_numeric_kind(kind)?);
                 }
                 ("bind_front", num_args) => {
-                    let num_args = parse_int(num_args)? as u32;
+                    let num_args = parse_u32(num_args, "bound argument count")?;
                     fn_builder.bind_front(num_args);
                 }
             }
@@ -552,6 +1169,7 @@ fn apply_fn_inst(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::binary::{instructions::Instruction, ConstValue};
 
     #[test]
     fn parse_import_module_item_works() -> anyhow::Result<()> {
@@ -619,6 +1237,307 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn shared_consts_are_copied_into_each_module() -> anyhow::Result<()> {
+        let expr = lexpr::from_str(
+            r#"
+                (module-set
+                    (shared-consts
+                        (pi 3.5)
+                        (pair (list pi pi)))
+                    ("a"
+                        (export pi))
+                    ("b"
+                        (const pi 3)
+                        (export pi)
+                        (export pair)))
+            "#,
+        )?;
+        let module_set = parse_module_set(&expr)?;
+        let export = |module: &str, name: &str| {
+            let module = module_set
+                .modules()
+                .find(|m| *m.id() == ModuleId::new([module]))
+                .unwrap();
+            let index = module.exports()[&ModuleMemberId::new(name)];
+            module.const_table()[index.as_usize()].clone()
+        };
+        assert!(matches!(export("a", "pi"), ConstValue::Float(f) if f.value() == 3.5));
+        // A module's own definition takes precedence, including in shared
+        // constants that refer to it.
+        assert!(matches!(export("b", "pi"), ConstValue::Integer(_)));
+        let ConstValue::List(items) = export("b", "pair") else {
+            panic!("Expected a list.");
+        };
+        assert_eq!(items.len(), 2);
+
+        let expr =
+            lexpr::from_str(r#"(module-set (shared-consts (x 1)) (shared-consts (x 2)) ("a"))"#)?;
+        assert!(matches!(
+            parse_module_set(&expr),
+            Err(Error::DuplicateName(name)) if name == "x"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_init_params() -> anyhow::Result<()> {
+        let expr = lexpr::from_str(
+            r#"
+                (module-set
+                    ("my.module"
+                        (init
+                            (params cfg other)
+                            (push other)
+                            (push cfg)
+                            (pop 2)
+                            (return 0))))
+            "#,
+        )?;
+        let module_set = parse_module_set(&expr)?;
+        let module = module_set.modules().next().unwrap();
+        let ConstValue::Function(init) =
+            &module.const_table()[module.initializer().unwrap().as_usize()]
+        else {
+            panic!("Expected the initializer to be a function.");
+        };
+        let insts = init.instructions().instructions();
+        assert!(matches!(
+            insts[0],
+            Instruction::PushCopy(StackIndex::FromBottom(1))
+        ));
+        assert!(matches!(
+            insts[1],
+            Instruction::PushCopy(StackIndex::FromBottom(0))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_closures() -> anyhow::Result<()> {
+        let module_set = from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const make
+                            (fn
+                                (params x y)
+                                (closure (y)
+                                    (params z)
+                                    (push y)
+                                    (push z)
+                                    (add)
+                                    (return 1))
+                                (return 1)))))
+            "#,
+        )?;
+        let module = module_set.modules().next().unwrap();
+        let functions: Vec<_> = module
+            .const_table()
+            .iter()
+            .filter_map(|value| match value {
+                ConstValue::Function(function) => Some(function),
+                _ => None,
+            })
+            .collect();
+        let [make, closure] = functions[..] else {
+            panic!("Expected two functions, got {}.", functions.len());
+        };
+        let (make, closure) = if make.arity() == Some(2) && closure.arity() == Some(2) {
+            match make.instructions().instructions()[0] {
+                Instruction::PushConst(_) => (make, closure),
+                _ => (closure, make),
+            }
+        } else {
+            panic!("Expected both functions to take two parameters.");
+        };
+        let insts = make.instructions().instructions();
+        assert!(matches!(insts[0], Instruction::PushConst(_)));
+        assert!(matches!(
+            insts[1],
+            Instruction::PushCopy(StackIndex::FromBottom(1))
+        ));
+        assert!(matches!(insts[2], Instruction::BindFront(1)));
+
+        // The captured value comes before the closure's own parameters.
+        let insts = closure.instructions().instructions();
+        assert!(matches!(
+            insts[0],
+            Instruction::PushCopy(StackIndex::FromBottom(0))
+        ));
+        assert!(matches!(
+            insts[1],
+            Instruction::PushCopy(StackIndex::FromBottom(1))
+        ));
+
+        let unknown = from_str(
+            r#"(module-set ("test" (const f (fn (params x) (closure (y) (return 0)) (return 1)))))"#,
+        );
+        assert!(matches!(unknown, Err(Error::UnknownReference(name)) if name == "y"));
+        Ok(())
+    }
+
+    #[test]
+    fn feature_conditional_items() -> anyhow::Result<()> {
+        let text = r#"
+            (module-set
+                ("app"
+                    (when-feature "debug"
+                        (const log_level 3)
+                        (when-feature "trace"
+                            (const trace #t)
+                            (export trace)))
+                    (when-feature "release"
+                        (const log_level 0))
+                    (export log_level)))
+        "#;
+        let log_level = |features: &[&str]| -> anyhow::Result<Option<ConstValue>> {
+            let module_set = from_str_with_features(text, features)?;
+            let module = module_set.modules().next().unwrap();
+            let value = module
+                .exports()
+                .get(&ModuleMemberId::new("log_level"))
+                .map(|index| module.const_table()[index.as_usize()].clone());
+            Ok(value)
+        };
+        assert!(matches!(
+            log_level(&["debug"])?,
+            Some(ConstValue::Integer(i)) if i == 3.into()
+        ));
+        assert!(matches!(
+            log_level(&["release"])?,
+            Some(ConstValue::Integer(i)) if i == 0.into()
+        ));
+        // Without either feature, the export has nothing to refer to.
+        assert!(matches!(
+            from_str(text),
+            Err(Error::UnknownReference(name)) if name == "log_level"
+        ));
+
+        let module_set = from_str_with_features(text, &["debug", "trace"])?;
+        let module = module_set.modules().next().unwrap();
+        assert!(module.exports().contains_key(&ModuleMemberId::new("trace")));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_stack_instructions() -> anyhow::Result<()> {
+        let module_set = from_str(
+            r#"
+                (module-set
+                    ("my.module"
+                        (init
+                            (push 1)
+                            (push 2)
+                            (push_copy top 1)
+                            (write_stack bot 0)
+                            (pop 1)
+                            (return 0))))
+            "#,
+        )?;
+        let module = module_set.modules().next().unwrap();
+        let ConstValue::Function(init) =
+            &module.const_table()[module.initializer().unwrap().as_usize()]
+        else {
+            panic!("Expected the initializer to be a function.");
+        };
+        let insts = init.instructions().instructions();
+        assert!(matches!(
+            insts[2],
+            Instruction::PushCopy(StackIndex::FromTop(1))
+        ));
+        assert!(matches!(
+            insts[3],
+            Instruction::WriteStack(StackIndex::FromBottom(0))
+        ));
+        assert!(matches!(
+            from_str(r#"(module-set ("m" (init (write_stack middle 0))))"#),
+            Err(Error::UnexpectedSymbol(name)) if name == "middle"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn out_of_range_operands_name_their_role() {
+        let parse =
+            |inst: &str| from_str(&format!(r#"(module-set ("m" (const f (fn {inst}))))"#)).err();
+        for (inst, expected_role, expected_value) in [
+            ("(push_copy top -1)", "stack index", -1),
+            ("(pop 4294967296)", "pop count", 4_294_967_296),
+            ("(call -2 1)", "argument count", -2),
+            ("(call 0 -1)", "return count", -1),
+            ("(bind_front -3)", "bound argument count", -3),
+        ] {
+            let result = parse(inst);
+            assert!(
+                matches!(
+                    &result,
+                    Some(Error::IntOutOfRange { role, value })
+                        if *role == expected_role && *value == expected_value
+                ),
+                "{inst}: {result:?}"
+            );
+        }
+        let error = parse("(return -1)").unwrap();
+        assert_eq!(error.to_string(), "return count out of range: -1");
+    }
+
+    #[test]
+    fn parse_export_docs() -> anyhow::Result<()> {
+        let module_set = from_str(
+            r#"
+                (module-set
+                    ("my.module"
+                        (const one 1 (doc "The number one."))
+                        (const two 2)
+                        (const three 3 (doc "Not exported."))
+                        (export one)
+                        (export two (doc "The number two."))))
+            "#,
+        )?;
+        let module = module_set.modules().next().unwrap();
+        assert_eq!(
+            module.export_doc(&ModuleMemberId::new("one")),
+            Some("The number one.")
+        );
+        assert_eq!(
+            module.export_doc(&ModuleMemberId::new("two")),
+            Some("The number two.")
+        );
+        assert_eq!(module.export_docs().len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_test_items() -> anyhow::Result<()> {
+        let module_set = from_str(
+            r#"
+                (module-set
+                    ("my.module"
+                        (const check (fn (push #t) (return 1)))
+                        (test "by reference" check)
+                        (test "inline" (fn (return 0)))))
+            "#,
+        )?;
+        let module = module_set.modules().next().unwrap();
+        let names = module
+            .tests()
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["by reference", "inline"]);
+        assert!(module.exports().is_empty());
+
+        let result = from_str(
+            r#"(module-set ("m" (test "twice" (fn (return 0))) (test "twice" (fn (return 0)))))"#,
+        );
+        assert!(matches!(
+            result,
+            Err(Error::Builder(BuilderError::DuplicateTest(name))) if name == "twice"
+        ));
+        Ok(())
+    }
+
     #[test]
     fn parse_infinite_loop() -> anyhow::Result<()> {
         let expr = lexpr::from_str(
@@ -662,4 +1581,226 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn parse_cyclic_alias_fails() -> anyhow::Result<()> {
+        let expr = lexpr::from_str(
+            r#"
+                (module-set
+                    ("my.module"
+                        (const foo bar)
+                        (const bar foo)
+                    )
+                )
+            "#,
+        )?;
+        let result = parse_module_set(&expr);
+        assert!(
+            matches!(result, Err(Error::Builder(BuilderError::CyclicDefinition))),
+            "found error {:?}",
+            result.err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_self_referential_list() -> anyhow::Result<()> {
+        let expr = lexpr::from_str(
+            r#"
+                (module-set
+                    ("my.module"
+                        (const foo (list 1 foo))
+                    )
+                )
+            "#,
+        )?;
+        parse_module_set(&expr)?;
+        Ok(())
+    }
+
+    fn float_consts(text: &str) -> anyhow::Result<Vec<u64>> {
+        let module_set = parse_module_set(&lexpr::from_str(text)?)?;
+        let module = module_set.modules().next().unwrap();
+        Ok(module
+            .const_table()
+            .iter()
+            .filter_map(|value| match value {
+                ConstValue::Float(f) => Some(f.to_bits()),
+                _ => None,
+            })
+            .collect())
+    }
+
+    #[test]
+    fn parse_exact_float_literals() -> anyhow::Result<()> {
+        let bits = float_consts(
+            r#"
+                (module-set
+                    ("my.module"
+                        (const hex 0x1.8p3)
+                        (const min_subnormal 0x1p-1074)
+                        (const max_subnormal -0x0.fffffffffffffp-1022)
+                        (const decimal_subnormal 4.9e-324)
+                        (const inf (float-bits "7ff0000000000000"))
+                        (const neg_inf (float-bits "0xfff0000000000000"))
+                        (const nan (float-bits "7ff8000000000001"))
+                    )
+                )
+            "#,
+        )?;
+        let mut bits = bits;
+        bits.sort_unstable();
+        let mut expected = vec![
+            12.0f64.to_bits(),
+            1,
+            (-f64::from_bits((1 << 52) - 1)).to_bits(),
+            1,
+            f64::INFINITY.to_bits(),
+            f64::NEG_INFINITY.to_bits(),
+            0x7ff8_0000_0000_0001,
+        ];
+        expected.sort_unstable();
+        assert_eq!(bits, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn float_literals_round_trip_through_text() -> anyhow::Result<()> {
+        let values = [
+            f64::from_bits(1),
+            f64::from_bits(0x000f_ffff_ffff_ffff),
+            f64::MIN_POSITIVE,
+            f64::MAX,
+            -0.1,
+        ];
+        let consts = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                format!(
+                    "(const c{i} {})",
+                    Float::new(*value).to_hex_literal().unwrap()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut bits = float_consts(&format!(r#"(module-set ("my.module" {consts}))"#))?;
+        bits.sort_unstable();
+        let mut expected = values.iter().map(|v| v.to_bits()).collect::<Vec<_>>();
+        expected.sort_unstable();
+        assert_eq!(bits, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_float_bits_fails() -> anyhow::Result<()> {
+        let expr = lexpr::from_str(
+            r#"(module-set ("my.module" (const x (float-bits "1234567890abcdef0"))))"#,
+        )?;
+        let result = parse_module_set(&expr);
+        assert!(
+            matches!(result, Err(Error::InvalidFloatBits(_))),
+            "found error {:?}",
+            result.err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_bytes_constants() -> anyhow::Result<()> {
+        let module_set = from_str(
+            r#"
+                (module-set
+                    ("my.module"
+                        (const hex (bytes hex "DEAD beef"))
+                        (const base64 (bytes base64 "3q2+7w=="))
+                        (const vector (bytes #u8(222 173 190 239)))
+                        (const literal (bytes #x"DEADBEEF"))
+                    )
+                )
+            "#,
+        )?;
+        let module = module_set.modules().next().unwrap();
+        let blobs = module
+            .const_table()
+            .iter()
+            .filter_map(|value| match value {
+                ConstValue::Bytes(b) => Some(b.to_vec()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(blobs, vec![vec![0xde, 0xad, 0xbe, 0xef]; 4]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_null_constants() -> anyhow::Result<()> {
+        let expr = lexpr::from_str(
+            r#"
+                (module-set
+                    ("my.module"
+                        (const nothing (null))
+                        (const f (fn (push_null) (push (null)) (return 2)))
+                    )
+                )
+            "#,
+        )?;
+        let module_set = parse_module_set(&expr)?;
+        let module = module_set.modules().next().unwrap();
+        let nulls = module
+            .const_table()
+            .iter()
+            .filter(|value| matches!(value, ConstValue::Null))
+            .count();
+        assert_eq!(nulls, 3);
+
+        let expr = lexpr::from_str(r#"(module-set ("my.module" (const x (null 1))))"#)?;
+        assert!(parse_module_set(&expr).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_record_constants() -> anyhow::Result<()> {
+        let expr = lexpr::from_str(
+            r#"
+                (module-set
+                    ("my.module"
+                        (const origin (record (x 0) (y 0) (label "origin")))
+                        (export origin)
+                    )
+                )
+            "#,
+        )?;
+        let module_set = parse_module_set(&expr)?;
+        let module = module_set.modules().next().unwrap();
+        let index = module.exports()[&ModuleMemberId::new("origin")];
+        let Some(ConstValue::Record(record)) = index.get(module.const_table()) else {
+            anyhow::bail!("Expected a record.");
+        };
+        let names = record
+            .fields()
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["x", "y", "label"]);
+
+        let expr = lexpr::from_str(r#"(module-set ("my.module" (const r (record (x 0) (x 1)))))"#)?;
+        assert!(matches!(
+            parse_module_set(&expr),
+            Err(Error::Builder(BuilderError::DuplicateRecordField(_)))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn parse_invalid_bytes_fails() -> anyhow::Result<()> {
+        let expr = lexpr::from_str(r#"(module-set ("my.module" (const x (bytes hex "ABC"))))"#)?;
+        let result = parse_module_set(&expr);
+        assert!(
+            matches!(result, Err(Error::InvalidBytes("hex", _))),
+            "found error {:?}",
+            result.err()
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,384 @@
+//! Parsing of module sets from a reader, one module item at a time.
+//!
+//! Only the text of the item being parsed is kept in memory, so the size of
+//! a module set is not limited by how large a single expression tree can be.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, BufRead, BufReader, Read},
+};
+
+use super::{
+    expand_conditional_items, parse_constant_expr, parse_constant_item, parse_documented_item,
+    parse_module_id, parse_module_item, parse_symbol, read_expr, resolve_fn_expr, Error,
+    ModuleItem, ReferenceSet, Result,
+};
+use crate::binary::{module_set::ModuleSet, modules::ModuleMemberId, ConstModule, ModuleBuilder};
+
+/// Splits text into the source of each top level expression, without
+/// parsing their contents.
+struct Scanner<R> {
+    reader: R,
+}
+
+impl<R: BufRead> Scanner<R> {
+    fn peek(&mut self) -> io::Result<Option<u8>> {
+        Ok(self.reader.fill_buf()?.first().copied())
+    }
+
+    fn bump(&mut self) {
+        self.reader.consume(1);
+    }
+
+    /// Returns the next byte, which must exist.
+    fn next_byte(&mut self) -> Result<u8> {
+        let byte = self.peek()?.ok_or(Error::UnexpectedEnd)?;
+        self.bump();
+        Ok(byte)
+    }
+
+    fn skip_line(&mut self) -> Result<()> {
+        while let Some(byte) = self.peek()? {
+            self.bump();
+            if byte == b'\n' {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips whitespace and comments, returning the byte that follows them.
+    fn skip_blank(&mut self) -> Result<Option<u8>> {
+        loop {
+            match self.peek()? {
+                Some(b';') => self.skip_line()?,
+                Some(byte) if byte.is_ascii_whitespace() => self.bump(),
+                next => return Ok(next),
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: u8) -> Result<()> {
+        match self.skip_blank()? {
+            Some(byte) if byte == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(byte) => Err(Error::UnexpectedChar(byte as char)),
+            None => Err(Error::UnexpectedEnd),
+        }
+    }
+
+    /// Reads the source of the next expression. Lists are matched by their
+    /// parentheses, skipping those in strings, characters and comments.
+    fn next_expr_text(&mut self) -> Result<String> {
+        self.skip_blank()?;
+        let mut text = Vec::new();
+        let mut depth = 0usize;
+        loop {
+            let Some(byte) = self.peek()? else {
+                if depth == 0 && !text.is_empty() {
+                    break;
+                }
+                return Err(Error::UnexpectedEnd);
+            };
+            let is_delimiter = byte.is_ascii_whitespace() || b"()\";".contains(&byte);
+            if depth == 0 && !text.is_empty() && is_delimiter {
+                break;
+            }
+            self.bump();
+            match byte {
+                b'(' => {
+                    depth += 1;
+                    text.push(byte);
+                }
+                b')' => {
+                    if depth == 0 {
+                        return Err(Error::UnexpectedChar(')'));
+                    }
+                    depth -= 1;
+                    text.push(byte);
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                b'"' => {
+                    text.push(byte);
+                    loop {
+                        let byte = self.next_byte()?;
+                        text.push(byte);
+                        match byte {
+                            b'\\' => text.push(self.next_byte()?),
+                            b'"' => break,
+                            _ => {}
+                        }
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                b';' => {
+                    self.skip_line()?;
+                    text.push(b'\n');
+                }
+                b'#' => {
+                    text.push(byte);
+                    // A character such as `#\(` is taken as is.
+                    if self.peek()? == Some(b'\\') {
+                        self.bump();
+                        text.push(b'\\');
+                        text.push(self.next_byte()?);
+                    }
+                }
+                _ => text.push(byte),
+            }
+        }
+        String::from_utf8(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e).into())
+    }
+
+    fn next_expr(&mut self) -> Result<lexpr::Value> {
+        read_expr(&self.next_expr_text()?)
+    }
+
+    /// Returns true if the list being read has ended, consuming its closing
+    /// parenthesis.
+    fn at_list_end(&mut self) -> Result<bool> {
+        match self.skip_blank()? {
+            Some(b')') => {
+                self.bump();
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None => Err(Error::UnexpectedEnd),
+        }
+    }
+}
+
+/// A module whose items are resolved as they are read. Names may be used
+/// before they are defined, and exports are added once every item is read.
+struct StreamedModule {
+    builder: ModuleBuilder,
+    references: ReferenceSet,
+    const_docs: HashMap<String, String>,
+    exports: Vec<(String, Option<String>)>,
+}
+
+impl StreamedModule {
+    fn new(builder: ModuleBuilder) -> Self {
+        StreamedModule {
+            builder,
+            references: ReferenceSet::with_forward_references(),
+            const_docs: HashMap::new(),
+            exports: Vec::new(),
+        }
+    }
+
+    fn add_item(&mut self, item: ModuleItem) -> Result<()> {
+        match item {
+            ModuleItem::Import(import) => {
+                self.references
+                    .define(import.local_name, import.value_ref)?;
+            }
+            ModuleItem::Global(global) => {
+                self.references.define(global.local_name, global.value)?;
+            }
+            ModuleItem::Const(constant) => {
+                self.references
+                    .define(constant.local_name, constant.value.clone())?;
+                if let Some(doc) = constant.doc {
+                    self.const_docs
+                        .insert(constant.local_name.to_string(), doc.to_string());
+                }
+                constant.resolve(&self.builder, &self.references)?;
+            }
+            ModuleItem::Export(export) => {
+                self.exports.push((
+                    export.local_name.to_string(),
+                    export.doc.map(str::to_string),
+                ));
+            }
+            ModuleItem::Init(init) => {
+                resolve_fn_expr(
+                    &self.builder,
+                    &self.references,
+                    self.builder.new_initializer()?,
+                    init.body,
+                )?;
+            }
+            ModuleItem::Test(test) => {
+                let value = parse_constant_expr(&self.builder, &self.references, test.expr)?;
+                self.builder.add_test(test.name, &value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self, shared_consts: &[lexpr::Value]) -> Result<ConstModule> {
+        for shared_const in shared_consts {
+            let constant = parse_constant_item(&self.builder, shared_const)?;
+            if !self.references.names.contains_key(constant.local_name) {
+                self.add_item(ModuleItem::Const(constant))?;
+            }
+        }
+        self.references.close_forward_references()?;
+        for (name, doc) in &self.exports {
+            let member_id = ModuleMemberId::new(name.as_str());
+            self.references
+                .get(&self.builder, name)?
+                .export(member_id.clone())?;
+            if let Some(doc) = doc.as_ref().or_else(|| self.const_docs.get(name)) {
+                self.builder.set_export_doc(member_id, doc)?;
+            }
+        }
+        Ok(self.builder.into_const_module()?)
+    }
+}
+
+/// Parses a module set from `reader`, in the same format as
+/// [`from_str`](super::from_str).
+///
+/// Each module item is parsed and added to its module as soon as it is read,
+/// so memory use is bounded by the largest item rather than the whole text.
+/// Unlike with `from_str`, `shared-consts` sections must come before the
+/// first module, and a name may only be defined once per module. No features
+/// are enabled, so `when-feature` items are skipped.
+pub fn from_reader(reader: impl Read) -> Result<ModuleSet> {
+    let mut scanner = Scanner {
+        reader: BufReader::new(reader),
+    };
+    scanner.expect(b'(')?;
+    let head = scanner.next_expr()?;
+    if parse_symbol(&head)? != "module-set" {
+        return Err(Error::UnexpectedSymbol(parse_symbol(&head)?.to_string()));
+    }
+    let mut shared_consts = Vec::new();
+    let mut shared_names = HashSet::new();
+    let mut modules = Vec::new();
+    while !scanner.at_list_end()? {
+        scanner.expect(b'(')?;
+        let section_head = scanner.next_expr()?;
+        if let Some(name) = section_head.as_str() {
+            let mut module = StreamedModule::new(ModuleBuilder::new(parse_module_id(name)?));
+            while !scanner.at_list_end()? {
+                let item_expr = scanner.next_expr()?;
+                let mut item_exprs = Vec::new();
+                expand_conditional_items(std::iter::once(&item_expr), &[], &mut item_exprs)?;
+                for item_expr in item_exprs {
+                    let item = parse_module_item(&module.builder, item_expr)?;
+                    module.add_item(item)?;
+                }
+            }
+            modules.push(module.finish(&shared_consts)?);
+            continue;
+        }
+        match parse_symbol(&section_head)? {
+            "shared-consts" => {
+                if !modules.is_empty() {
+                    return Err(Error::SharedConstsAfterModule);
+                }
+                while !scanner.at_list_end()? {
+                    let entry = scanner.next_expr()?;
+                    let ([local_name, _], _) = parse_documented_item::<2>(&entry)?;
+                    let local_name = parse_symbol(local_name)?.to_string();
+                    if !shared_names.insert(local_name.clone()) {
+                        return Err(Error::DuplicateName(local_name));
+                    }
+                    shared_consts.push(entry);
+                }
+            }
+            unknown_symbol => return Err(Error::UnexpectedSymbol(unknown_symbol.to_string())),
+        }
+    }
+    if let Some(byte) = scanner.skip_blank()? {
+        return Err(Error::UnexpectedChar(byte as char));
+    }
+    ModuleSet::try_new(modules).ok_or(Error::CyclicDependencies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::modules::ModuleId;
+
+    const MODULE_SET: &str = r#"
+        (module-set
+            ; Shared by both modules.
+            (shared-consts
+                (greeting "hello; (world)"))
+            ("a"
+                (export add)
+                (export one (doc "The number \"one\"."))
+                (const add
+                    (fn
+                        (push one)
+                        (push two)
+                        (add)
+                        (return 1)))
+                (const one 1)
+                (import two "b" two)
+                (global counter)
+                (when-feature "debug"
+                    (const log_level 3))
+                (init
+                    (push one)
+                    (pop 1)
+                    (return 0)))
+            ("b"
+                (const two 2 (doc "Two."))
+                (const blob (bytes #u8(1 2 3)))
+                (export two)
+                (export greeting)))
+    "#;
+
+    #[test]
+    fn streamed_modules_match_parsed_modules() -> anyhow::Result<()> {
+        let parsed = super::super::from_str(MODULE_SET)?;
+        let streamed = from_reader(MODULE_SET.as_bytes())?;
+        assert_eq!(parsed.modules().count(), 2);
+        for module in parsed.modules() {
+            let streamed_module = streamed.module(module.id()).unwrap();
+            assert!(module.diff(streamed_module).is_empty());
+            assert_eq!(module.export_docs(), streamed_module.export_docs());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn streaming_reports_malformed_input() {
+        let parse = |text: &str| from_reader(text.as_bytes());
+        assert!(matches!(
+            parse(r#"(module-set ("a" (const x 1))"#),
+            Err(Error::UnexpectedEnd)
+        ));
+        assert!(matches!(
+            parse(r#"(module-set ("a" (const x y)))"#),
+            Err(Error::UnknownReference(name)) if name == "y"
+        ));
+        assert!(matches!(
+            parse(r#"(module-set ("a" (const x 1) (global x)))"#),
+            Err(Error::DuplicateName(name)) if name == "x"
+        ));
+        assert!(matches!(
+            parse(r#"(module-set ("a") (shared-consts (x 1)))"#),
+            Err(Error::SharedConstsAfterModule)
+        ));
+        assert!(matches!(
+            parse(r#"(module-set ("a" (const x y) (const y x)))"#),
+            Err(Error::Builder(_))
+        ));
+        assert!(matches!(
+            parse(r#"(module-set) )"#),
+            Err(Error::UnexpectedChar(')'))
+        ));
+        assert!(matches!(
+            parse(
+                r#"(module-set
+                    ("a" (import y "b" y) (const x 1) (export x))
+                    ("b" (import x "a" x) (const y 2) (export y)))"#
+            ),
+            Err(Error::CyclicDependencies)
+        ));
+        let module_set = parse(r#"(module-set ("a.b" (const x 1) (export x)))"#).unwrap();
+        assert!(module_set.module(&ModuleId::new(["a", "b"])).is_some());
+    }
+}
@@ -1,16 +1,45 @@
+#![cfg_attr(feature = "forbid-unsafe", forbid(unsafe_code))]
+
 pub mod binary;
+#[cfg(feature = "runtime")]
+mod eval;
+#[cfg(feature = "runtime")]
 mod gc;
 pub mod lat;
+#[cfg(all(test, feature = "runtime"))]
+mod opcode_bench;
 pub mod pure_values;
+#[cfg(feature = "runtime")]
 pub mod runtime;
 mod util;
+pub mod wat;
+
+#[cfg(feature = "runtime")]
+pub use eval::{eval_expression, EvalError, EXPRESSION_FUEL};
+pub use pure_values::LoonValue;
 
-#[cfg(test)]
+#[cfg(all(test, feature = "runtime"))]
 mod tests {
     use crate::{
-        binary::{instructions::StackIndex, modules::ImportSource},
-        pure_values::Integer,
-        runtime::Runtime,
+        binary::{
+            instructions::{
+                BranchTarget, CallInstruction, CompareOp, Instruction, InstructionList,
+                NumericKind, StackIndex, Truthiness,
+            },
+            modules::{ImportSource, ModuleId, ModuleMemberId},
+            BuilderError, ConstFunction, ConstIndex, ConstModule, ConstValue, DecodeError,
+            GlobalIndex, ImportIndex, InstructionFamily, InstructionPolicy, IrFunction, IrOp,
+            LocalConstIndex, ModuleBuilder, ModuleConstIndex, Program, ProgramError,
+            ValidationError,
+        },
+        eval_expression,
+        pure_values::{Integer, LoonValue, Rational},
+        runtime::{
+            Breakpoint, CapabilitySet, DebugFrame, DebugHandler, ErrorKind, FunctionId,
+            FunctionOptimizer, FunctionProfile, MemoryIoBackend, NativeModule, Runtime,
+            RuntimeError, StepOutcome, TestOutcome, ValueKind,
+        },
+        EvalError,
     };
 
     #[test]
@@ -33,8 +62,8 @@ mod tests {
         let top_level = runtime.make_top_level();
         {
             let mut stack = top_level.stack();
-            stack.push_int(1);
-            stack.push_int(2);
+            stack.push_int(1)?;
+            stack.push_int(2)?;
             stack.push_import(&ImportSource::new(["test"], "test_func"))?;
         }
         let num_args = top_level.call_function(2)?;
@@ -46,24 +75,500 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn slots_keep_values_across_calls() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (export add)
+                        (const add
+                            (fn
+                                (add)
+                                (return 1)))))
+            "#,
+        )?;
+
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        {
+            let mut stack = top_level.stack();
+            stack.push_int(1)?;
+            stack.make_list(1)?;
+        }
+        top_level.set_slot("config", StackIndex::FromTop(0))?;
+        top_level.stack().push_int(2)?;
+        top_level.set_slot("count", StackIndex::FromTop(0))?;
+        top_level.stack().pop_n(2)?;
+        assert!(top_level.stack().is_empty());
+
+        top_level.get_slot("count")?;
+        top_level.get_slot("count")?;
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "add"))?;
+        top_level.call_function(2)?;
+        assert_eq!(
+            Integer::from(4),
+            top_level.stack().get_int(StackIndex::FromTop(0))?
+        );
+
+        top_level.get_slot("config")?;
+        assert_eq!(
+            top_level.stack().get_loon_value(StackIndex::FromTop(0))?,
+            LoonValue::List(vec![LoonValue::Integer(1.into())])
+        );
+        assert_eq!(top_level.slot_names(), ["config", "count"]);
+        assert!(top_level.remove_slot("count"));
+        assert!(!top_level.has_slot("count"));
+        assert!(top_level.get_slot("count").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn native_functions_move_values_between_stack_positions() -> anyhow::Result<()> {
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("rotate", |mut ctxt| {
+            {
+                let mut stack = ctxt.stack();
+                let mut args = stack.drain_args(stack.len())?;
+                args.rotate_left(1);
+                stack.push_all(args)?;
+            }
+            Ok(ctxt.return_with(3))
+        });
+        host.add_function("echo", |mut ctxt| {
+            {
+                let mut stack = ctxt.stack();
+                let args = stack.peek_n(stack.len())?;
+                stack.push_all(args)?;
+            }
+            Ok(ctxt.return_with(4))
+        });
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        let top_level = runtime.make_top_level();
+        let export = |name: &str| ImportSource::new(["host"], name);
+
+        assert_eq!(
+            top_level.call::<_, (i64, i64, i64)>(&export("rotate"), (1_i64, 2_i64, 3_i64))?,
+            (2, 3, 1)
+        );
+        assert_eq!(
+            top_level.call::<_, (i64, i64, i64, i64)>(&export("echo"), (1_i64, 2_i64))?,
+            (1, 2, 1, 2)
+        );
+        assert!(top_level.stack().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn values_keep_push_order_across_calls_and_lists() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import listify "host" listify)
+                        (const sub
+                            (fn
+                                (sub)
+                                (return 1)))
+                        (const bound
+                            (fn
+                                (push sub)
+                                (push 10)
+                                (bind_front 1)
+                                (push 3)
+                                (call 1 1)
+                                (return 1)))
+                        (const applied
+                            (fn
+                                (push sub)
+                                (push_copy bot 0)
+                                (apply)
+                                (return 1)))
+                        (const native_args
+                            (fn
+                                (push listify)
+                                (push 1)
+                                (push 2)
+                                (push 3)
+                                (call 3 1)
+                                (return 1)))
+                        (const returns
+                            (fn
+                                (push 1)
+                                (push 2)
+                                (return 2)))
+                        (export sub)
+                        (export bound)
+                        (export applied)
+                        (export native_args)
+                        (export returns)))
+            "#,
+        )?;
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("listify", |mut ctxt| {
+            let len = ctxt.stack().len();
+            ctxt.stack().make_list(len)?;
+            Ok(ctxt.return_with(1))
+        });
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        let export = |name: &str| ImportSource::new(["test"], name);
+
+        // Arguments arrive in push order, after any bound values.
+        assert_eq!(
+            top_level.call::<_, i64>(&export("sub"), (10_i64, 3_i64))?,
+            7
+        );
+        assert_eq!(top_level.call::<_, i64>(&export("bound"), ())?, 7);
+
+        // `apply` passes list elements in list order, and lists made from the
+        // stack are in push order.
+        {
+            let mut stack = top_level.stack();
+            stack.push_int(10)?;
+            stack.push_int(3)?;
+            stack.make_list(2)?;
+            stack.push_import(&export("applied"))?;
+        }
+        assert_eq!(top_level.call_function(1)?, 1);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(7)
+        );
+        top_level.stack().pop_n(1)?;
+        top_level.stack().push_int_slice(&[10, 3])?;
+        top_level.stack().push_import(&export("applied"))?;
+        top_level.call_function(1)?;
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(7)
+        );
+        top_level.stack().pop_n(1)?;
+
+        // Native functions see their arguments in push order.
+        assert_eq!(
+            top_level.call::<_, LoonValue>(&export("native_args"), ())?,
+            LoonValue::List(vec![1.into(), 2.into(), 3.into()])
+        );
+
+        // Return values are left in the order they were pushed.
+        assert_eq!(
+            top_level.call::<_, (i64, i64)>(&export("returns"), ())?,
+            (1, 2)
+        );
+        assert!(top_level.stack().is_empty());
+
+        // A list needs as many values as it holds, and takes none otherwise.
+        top_level.stack().push_int(1)?;
+        assert!(top_level.stack().make_list(2).is_err());
+        assert_eq!(top_level.stack().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn numeric_slices_are_pushed_as_lists() -> anyhow::Result<()> {
+        let runtime = Runtime::new();
+        let top_level = runtime.make_top_level();
+        let mut stack = top_level.stack();
+        stack.push_int_slice(&[1, -2, i64::MAX])?;
+        stack.push_float_slice(&[0.5, -1.25])?;
+        stack.push_int_slice(&[])?;
+        assert_eq!(stack.len(), 3);
+        assert_eq!(
+            stack.get_loon_value(StackIndex::FromTop(2))?,
+            LoonValue::List(vec![1.into(), (-2).into(), i64::MAX.into()])
+        );
+        assert_eq!(
+            stack.get_loon_value(StackIndex::FromTop(1))?,
+            LoonValue::List(vec![0.5.into(), (-1.25).into()])
+        );
+        assert_eq!(
+            stack.get_loon_value(StackIndex::FromTop(0))?,
+            LoonValue::List(vec![])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn thunks_call_exports_with_rust_types() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (export add)
+                        (export swap)
+                        (export value)
+                        (const add
+                            (fn
+                                (add)
+                                (return 1)))
+                        (const swap
+                            (fn
+                                (push_copy top 1)
+                                (return 1)))
+                        (const value 5)))
+            "#,
+        )?;
+
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        let add = top_level.thunk::<(i64, i64), i64>(&ImportSource::new(["test"], "add"))?;
+        assert_eq!(add((1, 2))?, 3);
+        assert_eq!(add((40, 2))?, 42);
+        let add_floats = top_level.thunk::<(f64, f64), f64>(&ImportSource::new(["test"], "add"))?;
+        assert_eq!(add_floats((0.5, 0.25))?, 0.75);
+
+        let pair =
+            top_level.thunk::<(&str, bool), (bool, bool)>(&ImportSource::new(["test"], "swap"))?;
+        let error = pair(("a", true)).unwrap_err();
+        assert!(error.to_string().contains("Expected 2 return values"));
+        let wrong_type =
+            top_level.thunk::<(i64, i64), String>(&ImportSource::new(["test"], "add"))?;
+        assert!(wrong_type((1, 2)).is_err());
+        assert!(top_level.stack().is_empty());
+
+        assert!(top_level
+            .thunk::<(), i64>(&ImportSource::new(["test"], "value"))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn typed_calls_name_mistyped_return_values() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (export both)
+                        (const both
+                            (fn
+                                (return 2)))))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        let both = ImportSource::new(["test"], "both");
+
+        let (number, text) = top_level.call::<_, (i64, String)>(&both, (7_i64, "seven"))?;
+        assert_eq!((number, text.as_str()), (7, "seven"));
+
+        let error = top_level
+            .call::<_, (i64, i64)>(&both, (7_i64, "seven"))
+            .unwrap_err();
+        assert!(matches!(error, RuntimeError::Type(_)));
+        assert!(
+            error.to_string().contains("Return value 2 of 2"),
+            "unexpected error: {error}"
+        );
+        let error = top_level
+            .call::<_, bool>(&both, (1_i64, 2_i64))
+            .unwrap_err();
+        assert!(error.to_string().contains("Expected 1 return values"));
+        assert!(top_level.stack().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn missing_exports_list_alternatives() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("math"
+                        (export length)
+                        (export add)
+                        (export sub)
+                        (const length 1)
+                        (const add 2)
+                        (const sub 3)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        let error = top_level
+            .stack()
+            .push_import(&ImportSource::new(["math"], "lenght"))
+            .unwrap_err();
+        assert!(matches!(
+            &error,
+            RuntimeError::ExportNotFound { suggestion: Some(s), .. } if s == "length"
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Module math has no export \"lenght\". Did you mean \"length\"? \
+             Available exports: add, length, sub."
+        );
+
+        let error = top_level
+            .stack()
+            .push_import(&ImportSource::new(["math"], "multiply"))
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            RuntimeError::ExportNotFound {
+                suggestion: None,
+                ..
+            }
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn wat_modules_run_on_the_runtime() -> anyhow::Result<()> {
+        let host = {
+            let builder = ModuleBuilder::new(ModuleId::new(["host"]));
+            let (double, mut fn_builder) = builder.new_function();
+            fn_builder
+                .push_copy(StackIndex::FromBottom(0))
+                .push_copy(StackIndex::FromBottom(0))
+                .add()
+                .return_(1);
+            fn_builder.build()?;
+            double.export(crate::binary::modules::ModuleMemberId::new("double"))?;
+            builder.into_const_module()?
+        };
+        let module = super::wat::module_from_str(
+            ModuleId::new(["wat"]),
+            r#"
+                (module
+                    (import "host" "double" (func $double (param i64) (result i64)))
+                    (func $add_one (param $x i64) (result i64)
+                        (i64.add (local.get $x) (i64.const 1)))
+                    (func $f (param $x i64) (result i64)
+                        (call $double (call $add_one (local.get $x))))
+                    (func $mean (param f64 f64) (result f64)
+                        (f64.div (f64.add (local.get 0) (local.get 1)) (f64.const 2)))
+                    (func $sub (param i64 i64) (result i64)
+                        local.get 0
+                        local.get 1
+                        i64.sub)
+                    (func (export "sub_flat") (param i64 i64 i64) (result i64)
+                        local.get 2
+                        local.get 0
+                        local.get 1
+                        call $sub
+                        call $sub)
+                    (export "f" (func $f))
+                    (export "mean" (func 3)))
+            "#,
+        )?;
+
+        let runtime = Runtime::new();
+        runtime.load_module(&host)?;
+        runtime.load_module(&module)?;
+        let top_level = runtime.make_top_level();
+        let f = top_level.thunk::<(i64,), i64>(&ImportSource::new(["wat"], "f"))?;
+        assert_eq!(f((4,))?, 10);
+        let mean = top_level.thunk::<(f64, f64), f64>(&ImportSource::new(["wat"], "mean"))?;
+        assert_eq!(mean((1.0, 2.0))?, 1.5);
+        let sub_flat =
+            top_level.thunk::<(i64, i64, i64), i64>(&ImportSource::new(["wat"], "sub_flat"))?;
+        assert_eq!(sub_flat((10, 3, 100))?, 93);
+        Ok(())
+    }
+
+    #[test]
+    fn ir_functions_lower_to_stack_code() -> anyhow::Result<()> {
+        let builder = ModuleBuilder::new(ModuleId::new(["ir"]));
+
+        // Sums the integers below its argument, with a loop whose counters
+        // are reassigned in the loop body.
+        let mut sum_below = IrFunction::new(1);
+        let entry = sum_below.entry();
+        let (head, body, exit) = (
+            sum_below.new_block(),
+            sum_below.new_block(),
+            sum_below.new_block(),
+        );
+        let n = sum_below.param(0);
+        let i = sum_below.int(entry, 0);
+        let total = sum_below.int(entry, 0);
+        sum_below.jump(entry, head);
+        let done = sum_below.op(head, IrOp::Compare(CompareOp::Ge), &[i, n]);
+        sum_below.branch(head, done, exit, body);
+        let next_total = sum_below.op(body, IrOp::Add, &[total, i]);
+        sum_below.copy(body, total, next_total);
+        let one = sum_below.int(body, 1);
+        let next_i = sum_below.op(body, IrOp::Add, &[i, one]);
+        sum_below.copy(body, i, next_i);
+        sum_below.jump(body, head);
+        sum_below.ret(exit, &[total]);
+        let (sum_below_value, fn_builder) = builder.new_function();
+        sum_below.lower(fn_builder)?;
+
+        // Calls `sum_below` and doubles its result.
+        let mut twice = IrFunction::new(1);
+        let entry = twice.entry();
+        let x = twice.param(0);
+        let function = twice.value(entry, &sum_below_value);
+        let [sum] = twice.call(entry, function, &[x], 1)[..] else {
+            unreachable!();
+        };
+        let doubled = twice.op(entry, IrOp::Add, &[sum, sum]);
+        twice.ret(entry, &[doubled]);
+        let (twice_value, fn_builder) = builder.new_function();
+        twice.lower(fn_builder)?;
+
+        sum_below_value.export("sum_below".into())?;
+        twice_value.export("twice".into())?;
+        let runtime = Runtime::new();
+        runtime.load_module(&builder.into_const_module()?)?;
+        let top_level = runtime.make_top_level();
+        let sum_below = top_level.thunk::<(i64,), i64>(&ImportSource::new(["ir"], "sum_below"))?;
+        assert_eq!(sum_below((10,))?, 45);
+        assert_eq!(sum_below((0,))?, 0);
+        let twice = top_level.thunk::<(i64,), i64>(&ImportSource::new(["ir"], "twice"))?;
+        assert_eq!(twice((10,))?, 90);
+        Ok(())
+    }
+
+    #[test]
+    fn heap_graph_dump_shows_stack_values() -> anyhow::Result<()> {
+        let runtime = Runtime::new();
+        let top_level = runtime.make_top_level();
+        {
+            let mut stack = top_level.stack();
+            stack.push_int(1)?;
+            stack.make_list(1)?;
+        }
+        let mut out = Vec::new();
+        runtime.dump_graph_dot(&mut out)?;
+        let dot = String::from_utf8(out)?;
+        assert!(dot.starts_with("digraph gc {"));
+        assert!(dot.contains("label=\"List"));
+        assert!(dot.contains("pinned"));
+        Ok(())
+    }
+
     #[test]
     fn simple_native_function_test() -> anyhow::Result<()> {
         let runtime = Runtime::new();
         let top_level = runtime.make_top_level();
         {
             let mut stack = top_level.stack();
-            stack.push_int(1);
-            stack.push_int(2);
+            stack.push_int(1)?;
+            stack.push_int(2)?;
             stack.push_native_function(|mut ctxt| {
                 {
                     let mut stack = ctxt.stack();
                     let i1 = stack.get_int(StackIndex::FromTop(0))?;
                     let i2 = stack.get_int(StackIndex::FromTop(1))?;
                     stack.pop_n(2)?;
-                    stack.push_int(i1.add_owned(i2));
+                    stack.push_int(i1.add_owned(i2))?;
                 }
                 Ok(ctxt.return_with(1))
-            });
+            })?;
         }
         let num_args = top_level.call_function(2)?;
         assert_eq!(num_args, 1);
@@ -74,6 +579,52 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn native_panics_and_overruns_fail_their_call() -> anyhow::Result<()> {
+        let runtime = Runtime::new();
+        let top_level = runtime.make_top_level();
+        let call_native = |body: fn() -> i64| {
+            top_level.stack().push_native_function(move |mut ctxt| {
+                ctxt.stack().push_int(body())?;
+                Ok(ctxt.return_with(1))
+            })?;
+            top_level.call_function(0)
+        };
+
+        let result = call_native(|| panic!("native bug"));
+        let Err(RuntimeError::NativePanic(message)) = result else {
+            panic!("expected a native panic, got {result:?}");
+        };
+        assert_eq!(message, "native bug");
+
+        // The runtime is still usable after the panic.
+        assert_eq!(call_native(|| 7)?, 1);
+        assert_eq!(
+            Integer::from(7),
+            top_level.stack().get_int(StackIndex::FromTop(0))?
+        );
+        top_level.stack().pop_n(1)?;
+
+        runtime.set_max_native_call_time(Some(std::time::Duration::from_millis(1)));
+        let result = call_native(|| {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            0
+        });
+        let Err(error) = result else {
+            panic!("expected the native call to time out");
+        };
+        assert!(matches!(error, RuntimeError::NativeCallTimeout(_)));
+        assert_eq!(error.kind(), ErrorKind::ResourceLimit);
+
+        runtime.set_max_native_call_time(None);
+        runtime.set_catch_native_panics(false);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            call_native(|| panic!("uncaught"))
+        }));
+        assert!(result.is_err());
+        Ok(())
+    }
+
     #[test]
     // #[ignore = "Not all opcodes implemented"]
     fn simple_recursive_function_test() -> anyhow::Result<()> {
@@ -122,7 +673,7 @@ mod tests {
         let top_level = runtime.make_top_level();
         {
             let mut stack = top_level.stack();
-            stack.push_int(9);
+            stack.push_int(9)?;
             stack.push_import(&ImportSource::new(["test"], "fib"))?;
         }
         top_level.call_function(1)?;
@@ -132,4 +683,3675 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn infinite_loop_runs_out_of_fuel() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const spin
+                            (fn
+                                #:loop
+                                (branch #:loop)))
+                        (export spin)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        runtime.set_fuel(Some(1000));
+
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "spin"))?;
+        let result = top_level.call_function(0);
+        assert!(
+            matches!(result, Err(RuntimeError::OutOfFuel)),
+            "unexpected result: {result:?}"
+        );
+        assert_eq!(runtime.fuel(), Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn cancel_stops_loop_at_back_edge() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const spin
+                            (fn
+                                #:loop
+                                (branch #:loop)))
+                        (export spin)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        runtime.cancel_handle().cancel();
+
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "spin"))?;
+        let result = top_level.call_function(0);
+        assert!(
+            matches!(result, Err(RuntimeError::Cancelled)),
+            "unexpected result: {result:?}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn deadline_stops_infinite_loop() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const spin
+                            (fn
+                                #:loop
+                                (branch #:loop)))
+                        (export spin)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "spin"))?;
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(20);
+        let result = top_level.call_function_with_deadline(0, deadline);
+        assert!(
+            matches!(result, Err(RuntimeError::Timeout)),
+            "unexpected result: {result:?}"
+        );
+        assert!(std::time::Instant::now() >= deadline);
+        Ok(())
+    }
+
+    #[test]
+    fn errors_carry_managed_stack_traces() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const inner
+                            (fn
+                                (add)
+                                (return 1)))
+                        (const outer
+                            (fn
+                                (push inner)
+                                (call 0 1)
+                                (return 1)))
+                        (export outer)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        let call_outer = || {
+            top_level
+                .stack()
+                .push_import(&ImportSource::new(["test"], "outer"))?;
+            top_level.call_function(0)
+        };
+
+        let untraced = call_outer().unwrap_err();
+        assert!(untraced.stack_trace().is_empty());
+        assert!(!matches!(untraced, RuntimeError::WithStackTrace { .. }));
+
+        runtime.set_capture_stack_traces(true);
+        let traced = call_outer().unwrap_err();
+        assert_eq!(traced.kind(), untraced.kind());
+        let trace = traced.stack_trace();
+        assert_eq!(trace.len(), 2, "unexpected trace: {trace:?}");
+        let module_prefix = format!("{}#", ModuleId::new(["test"]));
+        for frame in trace {
+            assert!(frame.function().starts_with(&module_prefix));
+        }
+        assert_ne!(trace[0].function(), trace[1].function());
+        // The outer function waits on its call; the inner one failed at its
+        // first instruction.
+        assert_eq!(trace[0].pc(), 1);
+        assert_eq!(trace[1].pc(), 0);
+        assert_eq!(
+            traced.without_stack_trace().to_string(),
+            untraced.to_string()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn debug_handler_sees_instructions_and_breakpoints() -> anyhow::Result<()> {
+        use std::{
+            cell::{Cell, RefCell},
+            rc::Rc,
+        };
+
+        #[derive(Default)]
+        struct Recorder {
+            pcs: RefCell<Vec<usize>>,
+            hits: RefCell<Vec<LoonValue>>,
+            stop_at_breakpoint: Cell<bool>,
+        }
+
+        impl DebugHandler for Rc<Recorder> {
+            fn before_instruction(&self, frame: &DebugFrame<'_>) -> Result<(), RuntimeError> {
+                self.pcs.borrow_mut().push(frame.pc());
+                Ok(())
+            }
+
+            fn on_breakpoint(
+                &self,
+                frame: &DebugFrame<'_>,
+                _breakpoint: &Breakpoint,
+            ) -> Result<(), RuntimeError> {
+                self.hits
+                    .borrow_mut()
+                    .push(frame.get_loon_value(StackIndex::FromTop(0))?);
+                if self.stop_at_breakpoint.get() {
+                    return Err(RuntimeError::Cancelled);
+                }
+                Ok(())
+            }
+        }
+
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (export test_func)
+                        (const test_func
+                            (fn
+                                (add)
+                                (return 1)))))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        let call_with = |a: i64, b: i64| {
+            let mut stack = top_level.stack();
+            stack.push_int(a)?;
+            stack.push_int(b)?;
+            stack.push_import(&ImportSource::new(["test"], "test_func"))?;
+            drop(stack);
+            top_level.call_function(2)
+        };
+        let function_id = {
+            let mut stack = top_level.stack();
+            stack.push_import(&ImportSource::new(["test"], "test_func"))?;
+            let id = stack.get_function_id(StackIndex::FromTop(0))?;
+            stack.pop_n(1)?;
+            id
+        };
+        let FunctionId::Managed { const_index, .. } = function_id else {
+            panic!("test_func is not a managed function");
+        };
+
+        let recorder = Rc::new(Recorder::default());
+        runtime.set_debug_handler(recorder.clone());
+        let breakpoint = Breakpoint::new(ModuleId::new(["test"]), const_index, 1);
+        assert!(runtime.add_breakpoint(breakpoint.clone()));
+        assert!(!runtime.add_breakpoint(breakpoint.clone()));
+
+        call_with(1, 2)?;
+        assert_eq!(*recorder.pcs.borrow(), [0, 1]);
+        assert_eq!(*recorder.hits.borrow(), [LoonValue::Integer(3.into())]);
+        top_level.stack().pop_n(1)?;
+
+        recorder.stop_at_breakpoint.set(true);
+        assert!(matches!(call_with(3, 4), Err(RuntimeError::Cancelled)));
+        assert_eq!(recorder.hits.borrow().len(), 2);
+
+        assert!(runtime.remove_breakpoint(&breakpoint));
+        assert!(runtime.breakpoints().is_empty());
+        call_with(5, 6)?;
+        assert_eq!(recorder.hits.borrow().len(), 2);
+
+        runtime.clear_debug_handler();
+        recorder.pcs.borrow_mut().clear();
+        call_with(7, 8)?;
+        assert!(recorder.pcs.borrow().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn allocation_quotas_apply_per_top_level() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const noop
+                            (fn
+                                (return 0)))
+                        (const spin
+                            (fn
+                                #:loop
+                                (push noop)
+                                (call 0 0)
+                                (branch #:loop)))
+                        (const once
+                            (fn
+                                (push noop)
+                                (call 0 0)
+                                (return 0)))
+                        (export spin)
+                        (export once)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let tenant = runtime.make_top_level();
+        let other = runtime.make_top_level();
+
+        tenant.set_allocation_quota(Some(100));
+        tenant
+            .stack()
+            .push_import(&ImportSource::new(["test"], "spin"))?;
+        let result = tenant.call_function(0);
+        assert!(
+            matches!(
+                result,
+                Err(RuntimeError::TenantQuotaExceeded { quota: 100, allocated })
+                    if allocated > 100
+            ),
+            "unexpected result: {result:?}"
+        );
+        let tenant_allocations = tenant.allocations();
+        assert!(tenant_allocations > 100);
+        assert_eq!(other.allocations(), 0);
+
+        other
+            .stack()
+            .push_import(&ImportSource::new(["test"], "once"))?;
+        other.call_function(0)?;
+        assert!(other.allocations() > 0);
+        assert_eq!(tenant.allocations(), tenant_allocations);
+
+        // Calls fail at their first safe point until the quota is raised or
+        // the count is reset.
+        tenant.reset_allocations();
+        assert_eq!(tenant.allocations(), 0);
+        tenant
+            .stack()
+            .push_import(&ImportSource::new(["test"], "once"))?;
+        tenant.call_function(0)?;
+        assert!(tenant.allocations() > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn interrupted_initializers_name_their_module() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (init
+                            #:loop
+                            (branch #:loop))))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        let module_id = ModuleId::new(["test"]);
+
+        runtime.set_fuel(Some(1000));
+        let result = top_level.init_module(&module_id);
+        let Err(RuntimeError::InitializerInterrupted { module, error }) = result else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert_eq!(module, module_id.to_string());
+        assert!(matches!(*error, RuntimeError::OutOfFuel));
+
+        runtime.set_fuel(None);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(20);
+        let error = top_level
+            .init_module_with_deadline(&module_id, 0, deadline)
+            .unwrap_err();
+        assert!(error.is_retriable());
+        assert_eq!(error.kind(), ErrorKind::ResourceLimit);
+        assert!(matches!(
+            error,
+            RuntimeError::InitializerInterrupted { error, .. } if matches!(*error, RuntimeError::Timeout)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn fused_compare_branches_keep_loop_results() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const count
+                            (fn
+                                (params n)
+                                (push 0)
+                                #:loop
+                                (push_copy bot 1)
+                                (push n)
+                                (cmp ge)
+                                (branch_if #:end)
+                                (push_copy bot 1)
+                                (push 1)
+                                (add)
+                                (write_stack bot 1)
+                                (branch #:loop)
+                                #:end
+                                (push_copy bot 1)
+                                (return 1)))
+                        (export count)))
+            "#,
+        )?;
+        let optimized = module_set
+            .module(&ModuleId::new(["test"]))
+            .unwrap()
+            .peephole_optimize()
+            .unwrap();
+        assert!(optimized.const_table().iter().any(|value| matches!(
+            value,
+            ConstValue::Function(f) if f.instructions().instructions().iter().any(
+                |inst| matches!(inst, Instruction::CmpBranch(CompareOp::Ge, _))
+            )
+        )));
+
+        for enabled in [false, true] {
+            let runtime = Runtime::new();
+            runtime.set_peephole_optimize(enabled);
+            runtime.load_module_set(&module_set)?;
+            let top_level = runtime.make_top_level();
+            {
+                let mut stack = top_level.stack();
+                stack.push_int(5)?;
+                stack.push_import(&ImportSource::new(["test"], "count"))?;
+            }
+            top_level.call_function(1)?;
+            assert_eq!(
+                Integer::from(5),
+                top_level.stack().get_int(StackIndex::FromTop(0))?
+            );
+            // Floats take the general comparison path.
+            {
+                let mut stack = top_level.stack();
+                stack.push_float(2.5)?;
+                stack.push_import(&ImportSource::new(["test"], "count"))?;
+            }
+            top_level.call_function(1)?;
+            assert_eq!(
+                Integer::from(3),
+                top_level.stack().get_int(StackIndex::FromTop(0))?
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn run_steps_pauses_and_resumes() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const count_down
+                            (fn
+                                #:loop
+                                (push_copy bot 0)
+                                (push 0)
+                                (cmp ref_eq)
+                                (branch_if #:end)
+                                (push_copy bot 0)
+                                (push -1)
+                                (add)
+                                (write_stack bot 0)
+                                (branch #:loop)
+                                #:end
+                                (push 42)
+                                (return 1)))
+                        (export count_down)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        {
+            let mut stack = top_level.stack();
+            stack.push_int(100)?;
+            stack.push_import(&ImportSource::new(["test"], "count_down"))?;
+        }
+        top_level.start_call(1)?;
+        let mut slices = 0;
+        let num_returns = loop {
+            slices += 1;
+            match top_level.run_steps(50) {
+                StepOutcome::Paused => assert!(top_level.has_pending_call()),
+                StepOutcome::Completed(num_returns) => break num_returns,
+                outcome => anyhow::bail!("unexpected outcome: {outcome:?}"),
+            }
+        };
+        // Each iteration of the loop runs 9 instructions.
+        assert_eq!(slices, 100 * 9 / 50 + 1);
+        assert_eq!(num_returns, 1);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(42)
+        );
+        assert!(!top_level.has_pending_call());
+        assert!(matches!(
+            top_level.run_steps(50),
+            StepOutcome::Error(RuntimeError::OperationPrecondition(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn scheduled_callbacks_run_when_due() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import schedule "std.timer" schedule)
+                        (const run
+                            (fn
+                                ; Stack: [n, f]
+                                (push schedule)
+                                (push 1)
+                                (push_copy bot 1)
+                                (call 2 1)
+                                (pop 1)
+                                #:loop
+                                (push_copy bot 0)
+                                (push 0)
+                                (cmp ref_eq)
+                                (branch_if #:end)
+                                (push_copy bot 0)
+                                (push -1)
+                                (add)
+                                (write_stack bot 0)
+                                (branch #:loop)
+                                #:end
+                                (push 42)
+                                (return 1)))
+                        (export run)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_std_modules()?;
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        let ran = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let push_recorder = |name: &'static str| {
+            let ran = ran.clone();
+            top_level.stack().push_native_function(move |ctxt| {
+                ran.borrow_mut().push(name);
+                Ok(ctxt.return_with(0))
+            })
+        };
+
+        push_recorder("late")?;
+        top_level.schedule_callback(2)?;
+        push_recorder("early")?;
+        top_level.schedule_callback(1)?;
+        push_recorder("cancelled")?;
+        let cancelled = top_level.schedule_callback(1)?;
+        assert!(top_level.cancel_callback(cancelled));
+        assert!(!top_level.cancel_callback(cancelled));
+        assert_eq!(top_level.pending_callbacks(), 2);
+
+        assert_eq!(top_level.run_due_callbacks()?, 0);
+        top_level.advance_ticks(1);
+        assert_eq!(top_level.run_due_callbacks()?, 1);
+        top_level.advance_ticks(5);
+        assert_eq!(top_level.run_due_callbacks()?, 1);
+        assert_eq!(*ran.borrow(), ["early", "late"]);
+        assert_eq!(top_level.current_tick(), 6);
+        assert_eq!(top_level.stack().len(), 0);
+
+        // Managed code schedules the callback during the first slice, and it
+        // runs before the second.
+        ran.borrow_mut().clear();
+        top_level.stack().push_int(100)?;
+        push_recorder("managed")?;
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        top_level.start_call(2)?;
+        assert!(matches!(top_level.run_steps(50), StepOutcome::Paused));
+        assert!(ran.borrow().is_empty());
+        assert_eq!(top_level.current_tick(), 56);
+        assert!(matches!(top_level.run_steps(50), StepOutcome::Paused));
+        assert_eq!(*ran.borrow(), ["managed"]);
+        let num_returns = loop {
+            match top_level.run_steps(50) {
+                StepOutcome::Paused => {}
+                StepOutcome::Completed(num_returns) => break num_returns,
+                outcome => anyhow::bail!("unexpected outcome: {outcome:?}"),
+            }
+        };
+        assert_eq!(num_returns, 1);
+        assert_eq!(ran.borrow().len(), 1);
+        assert_eq!(top_level.pending_callbacks(), 0);
+
+        // The clock counts instructions, so a callback that becomes due in
+        // the middle of a slice runs there.
+        ran.borrow_mut().clear();
+        top_level.stack().pop_n(1)?;
+        push_recorder("host")?;
+        top_level.schedule_callback(10)?;
+        let start = top_level.current_tick();
+        top_level.stack().push_int(100)?;
+        push_recorder("managed")?;
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        top_level.start_call(2)?;
+        assert!(matches!(top_level.run_steps(50), StepOutcome::Paused));
+        assert_eq!(*ran.borrow(), ["managed", "host"]);
+        assert_eq!(top_level.current_tick(), start + 50);
+        Ok(())
+    }
+
+    #[test]
+    fn native_functions_yield_to_host() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import wait_frame "host" wait_frame)
+                        (const run
+                            (fn
+                                (push wait_frame)
+                                (push 1)
+                                (call 1 1)
+                                (push 2)
+                                (add)
+                                (return 1)))
+                        (export run)))
+            "#,
+        )?;
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("wait_frame", |ctxt| {
+            Ok(ctxt.yield_to_host(|mut ctxt| {
+                ctxt.stack().push_int(10)?;
+                Ok(ctxt.return_with(1))
+            }))
+        });
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        top_level.start_call(0)?;
+        assert!(matches!(top_level.run_steps(100), StepOutcome::Yielded));
+        assert!(matches!(
+            top_level.run_steps(100),
+            StepOutcome::Completed(1)
+        ));
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(12)
+        );
+
+        // Calls that are not run step by step return a continuation, followed
+        // by the values left on the yielding function's stack.
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        assert_eq!(top_level.call_function(0)?, 2);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(1)
+        );
+        top_level.stack().pop_n(1)?;
+        assert_eq!(top_level.call_function(0)?, 1);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(12)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn continuations_resume_yields_across_managed_frames() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import next "host" next)
+                        (import delimit "host" delimit)
+                        (const produce
+                            (fn
+                                (push next)
+                                (push 1)
+                                (call 1 1)
+                                (push next)
+                                (push 2)
+                                (call 1 1)
+                                (add)
+                                (return 1)))
+                        (const middle
+                            (fn
+                                (push produce)
+                                (call 0 1)
+                                (push 100)
+                                (add)
+                                (return 1)))
+                        (const consume
+                            (fn
+                                (push delimit)
+                                (push middle)
+                                (call 1 2)
+                                (pop 1)
+                                (push delimit)
+                                (push 10)
+                                (push_copy top 2)
+                                (call 2 2)
+                                (pop 1)
+                                (push delimit)
+                                (push 20)
+                                (push_copy top 2)
+                                (call 2 1)
+                                (return 1)))
+                        (export middle)
+                        (export consume)))
+            "#,
+        )?;
+        // Yields its argument, and returns the values it is resumed with.
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("next", |ctxt| {
+            Ok(ctxt.yield_to_host(|mut ctxt| {
+                let num_values = ctxt.stack().len() as u32;
+                Ok(ctxt.return_with(num_values))
+            }))
+        });
+        // Calls the function on top of its stack with the values below it,
+        // so that yields stop at this call and return a continuation.
+        host.add_function("delimit", |mut ctxt| {
+            let num_args = ctxt.stack().len() as u32 - 1;
+            let num_returns = ctxt.call(num_args)?;
+            Ok(ctxt.return_with(num_returns))
+        });
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        // Managed code resumes the continuations it receives.
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "consume"))?;
+        assert_eq!(top_level.call_function(0)?, 1);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(130)
+        );
+        top_level.stack().pop_n(1)?;
+
+        // The host resumes them the same way.
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "middle"))?;
+        assert_eq!(top_level.call_function(0)?, 2);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(1)
+        );
+        top_level.set_slot("resume", StackIndex::FromTop(1))?;
+        top_level.stack().pop_n(2)?;
+        top_level.stack().push_int(5)?;
+        top_level.get_slot("resume")?;
+        assert_eq!(top_level.call_function(1)?, 2);
+        top_level.set_slot("resume_again", StackIndex::FromTop(1))?;
+        top_level.stack().pop_n(2)?;
+        top_level.stack().push_int(6)?;
+        top_level.get_slot("resume_again")?;
+        assert_eq!(top_level.call_function(1)?, 1);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(111)
+        );
+
+        // Each continuation resumes its frames only once.
+        top_level.stack().pop_n(1)?;
+        top_level.get_slot("resume")?;
+        assert!(matches!(
+            top_level.call_function(0),
+            Err(RuntimeError::OperationPrecondition(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn continuations_survive_resumes_that_overflow_the_stack() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import next "host" next)
+                        (const produce
+                            (fn
+                                (push next)
+                                (push 1)
+                                (call 1 1)
+                                (return 1)))
+                        (const middle
+                            (fn
+                                (push produce)
+                                (call 0 1)
+                                (return 1)))
+                        (export middle)))
+            "#,
+        )?;
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("next", |ctxt| {
+            Ok(ctxt.yield_to_host(|mut ctxt| {
+                let num_values = ctxt.stack().len() as u32;
+                Ok(ctxt.return_with(num_values))
+            }))
+        });
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "middle"))?;
+        assert_eq!(top_level.call_function(0)?, 2);
+        top_level.set_slot("resume", StackIndex::FromTop(1))?;
+        top_level.stack().pop_n(2)?;
+
+        // Resuming needs more frames than the limit allows, so it fails
+        // without giving up the suspended frames.
+        runtime.set_max_call_depth(Some(1));
+        top_level.stack().push_int(7)?;
+        top_level.get_slot("resume")?;
+        assert!(matches!(
+            top_level.call_function(1),
+            Err(RuntimeError::StackOverflow { limit: 1 })
+        ));
+
+        runtime.set_max_call_depth(None);
+        top_level.stack().pop_n(top_level.stack().len())?;
+        top_level.stack().push_int(7)?;
+        top_level.get_slot("resume")?;
+        assert_eq!(top_level.call_function(1)?, 1);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(7)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn native_functions_call_with_continuation() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import double_result "host" double_result)
+                        (import apply "host" apply)
+                        (const inc
+                            (fn
+                                (params n)
+                                (push n)
+                                (push 1)
+                                (add)
+                                (return 1)))
+                        (const run
+                            (fn
+                                (push double_result)
+                                (push 5)
+                                (push inc)
+                                (call 2 1)
+                                (push apply)
+                                (push 20)
+                                (push inc)
+                                (call 2 1)
+                                (add)
+                                (return 1)))
+                        (export run)))
+            "#,
+        )?;
+        // Both take an argument and a function, and call the function with
+        // the argument without nesting the call.
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("double_result", |ctxt| {
+            ctxt.call_with_continuation(1, |mut ctxt| {
+                let mut stack = ctxt.stack();
+                let result = stack.get_int(StackIndex::FromTop(0))?;
+                stack.pop_n(1)?;
+                stack.push_int(result.clone().add_owned(result))?;
+                Ok(ctxt.return_with(1))
+            })
+        });
+        host.add_function("apply", |ctxt| ctxt.tail_call(1));
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        assert_eq!(top_level.call_function(0)?, 1);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(12 + 21)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn frame_stack_limit_stops_unbounded_push() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const grow
+                            (fn
+                                #:loop
+                                (push 1)
+                                (branch #:loop)))
+                        (export grow)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        runtime.set_max_frame_stack_size(Some(100));
+
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "grow"))?;
+        let result = top_level.call_function(0);
+        let Err(RuntimeError::StackLimitExceeded { function, limit }) = result else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert!(function.starts_with("test#"), "unexpected name: {function}");
+        assert_eq!(limit, 100);
+        Ok(())
+    }
+
+    #[test]
+    fn frame_stack_limit_applies_to_bulk_pushes() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import count "host" count)
+                        (const spread
+                            (fn
+                                (params xs)
+                                (push count)
+                                (push xs)
+                                (apply)
+                                (return 1)))
+                        (export spread)))
+            "#,
+        )?;
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("count", |mut ctxt| {
+            let len = ctxt.stack().len();
+            ctxt.stack().push_int(len as i64)?;
+            Ok(ctxt.return_with(1))
+        });
+        host.add_function("fill", |mut ctxt| {
+            ctxt.stack().push_all(
+                (0..1000).map(|i: i64| crate::runtime::PinnedValue::new_integer(i.into())),
+            )?;
+            Ok(ctxt.return_with(0))
+        });
+        host.add_function("grow", |mut ctxt| {
+            for i in 0..1000 {
+                ctxt.stack().push_int(i)?;
+            }
+            Ok(ctxt.return_with(0))
+        });
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+        runtime.set_max_frame_stack_size(Some(100));
+        let top_level = runtime.make_top_level();
+        let spread = ImportSource::new(["test"], "spread");
+
+        let list = |len| LoonValue::List(vec![1.into(); len]);
+        assert_eq!(top_level.call::<_, i64>(&spread, (list(50),))?, 50);
+
+        // Spreading a list that does not fit fails before it is pushed.
+        let result = top_level.call::<_, i64>(&spread, (list(1000),));
+        let Err(RuntimeError::StackLimitExceeded { function, limit }) = result else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert!(function.starts_with("test#"), "unexpected name: {function}");
+        assert_eq!(limit, 100);
+
+        // Native frames are limited too.
+        let result = top_level.call::<_, ()>(&ImportSource::new(["host"], "fill"), ());
+        let Err(RuntimeError::StackLimitExceeded { function, .. }) = result else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert_eq!(function, "<native>");
+
+        // So are their pushes of single values.
+        let result = top_level.call::<_, ()>(&ImportSource::new(["host"], "grow"), ());
+        let Err(RuntimeError::StackLimitExceeded { function, limit }) = result else {
+            panic!("unexpected result: {result:?}");
+        };
+        assert_eq!(function, "<native>");
+        assert_eq!(limit, 100);
+        Ok(())
+    }
+
+    #[test]
+    fn call_depth_limit_stops_unbounded_recursion() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const depth
+                            (fn
+                                (push_copy bot 0)
+                                (push 0)
+                                (cmp ref_eq)
+                                (branch_if #:end)
+                                (push depth)
+                                (push_copy bot 0)
+                                (push -1)
+                                (add)
+                                (call 1 1)
+                                (push 1)
+                                (add)
+                                (return 1)
+                                #:end
+                                (push 0)
+                                (return 1)))
+                        (const count_down
+                            (fn
+                                (push_copy bot 0)
+                                (push 0)
+                                (cmp ref_eq)
+                                (branch_if #:end)
+                                (push count_down)
+                                (push_copy bot 0)
+                                (push -1)
+                                (add)
+                                (tail_call 1)
+                                #:end
+                                (push 42)
+                                (return 1)))
+                        (export depth)
+                        (export count_down)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        assert_eq!(runtime.max_call_depth(), Some(4096));
+        let top_level = runtime.make_top_level();
+        let call = |name: &str, n: i64| {
+            top_level.stack().push_int(n)?;
+            top_level
+                .stack()
+                .push_import(&ImportSource::new(["test"], name))?;
+            top_level.call_function(1)?;
+            let result = top_level.stack().get_int(StackIndex::FromTop(0))?;
+            top_level.stack().pop_n(1)?;
+            Ok::<_, RuntimeError>(result)
+        };
+
+        assert_eq!(call("depth", 1000)?, Integer::from(1000));
+
+        runtime.set_max_call_depth(Some(100));
+        runtime.set_capture_stack_traces(true);
+        assert_eq!(call("depth", 99)?, Integer::from(99));
+        let error = call("depth", 1000).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ResourceLimit);
+        assert_eq!(error.stack_trace().len(), 100);
+        assert!(matches!(
+            error.without_stack_trace(),
+            RuntimeError::StackOverflow { limit: 100 }
+        ));
+        top_level.stack().pop_n(top_level.stack().len())?;
+
+        // Tail calls replace their frame, so they run in constant depth.
+        assert_eq!(call("count_down", 10_000)?, Integer::from(42));
+        Ok(())
+    }
+
+    #[test]
+    fn memoize_calls_function_once_per_key() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import memoize "std.fn" memoize)
+                        (const run
+                            (fn
+                                ; Stack: [f]
+                                (push memoize)
+                                (push_copy bot 0)
+                                (call 1 1)
+                                ; Stack: [f, memo_f]
+                                (push_copy top 0)
+                                (push 5)
+                                (call 1 1)
+                                (push_copy top 1)
+                                (push 5)
+                                (call 1 1)
+                                (add)
+                                (return 1)))
+                        (export run)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_std_modules()?;
+        runtime.load_module_set(&module_set)?;
+
+        let call_count = std::rc::Rc::new(std::cell::Cell::new(0));
+        let top_level = runtime.make_top_level();
+        {
+            let call_count = call_count.clone();
+            let mut stack = top_level.stack();
+            stack.push_native_function(move |mut ctxt| {
+                call_count.set(call_count.get() + 1);
+                {
+                    let mut stack = ctxt.stack();
+                    let i = stack.get_int(StackIndex::FromTop(0))?;
+                    stack.pop_n(1)?;
+                    stack.push_int(i.add_owned(Integer::from(1)))?;
+                }
+                Ok(ctxt.return_with(1))
+            })?;
+            stack.push_import(&ImportSource::new(["test"], "run"))?;
+        }
+        top_level.call_function(1)?;
+        assert_eq!(
+            Integer::from(12),
+            top_level.stack().get_int(StackIndex::FromTop(0))?
+        );
+        assert_eq!(call_count.get(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn std_io_streams_through_the_embedder_backend() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("app"
+                        (import open "std.io" open)
+                        (import read "std.io" read)
+                        (import write "std.io" write)
+                        (import close "std.io" close)
+                        (const copy
+                            (fn
+                                (params src dst)
+                                (push open)
+                                (push src)
+                                (push "r")
+                                (call 2 1)
+                                (push open)
+                                (push dst)
+                                (push "w")
+                                (call 2 1)
+                                ; Stack: [src, dst, in, out]
+                                (push write)
+                                (push_copy top 1)
+                                (push read)
+                                (push_copy top 4)
+                                (push 100)
+                                (call 2 1)
+                                (call 2 1)
+                                (pop 1)
+                                (push close)
+                                (push_copy top 2)
+                                (call 1 0)
+                                (push close)
+                                (push_copy top 1)
+                                (call 1 0)
+                                (return 0)))
+                        (export copy)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_std_modules()?;
+        let app = ModuleId::new(["app"]);
+        assert!(matches!(
+            runtime.load_module_set(&module_set),
+            Err(RuntimeError::CapabilityNotGranted { .. })
+        ));
+        runtime.grant_capabilities(&app, CapabilitySet::new().with("io"));
+        runtime.load_module_set(&module_set)?;
+
+        let call_copy = |src: &str, dst: &str| {
+            let top_level = runtime.make_top_level();
+            let mut stack = top_level.stack();
+            stack.push_string(src)?;
+            stack.push_string(dst)?;
+            stack.push_import(&ImportSource::new(["app"], "copy"))?;
+            drop(stack);
+            top_level.call_function(2)
+        };
+        assert!(call_copy("in", "out").is_err());
+
+        let backend = MemoryIoBackend::new();
+        backend.insert_file("in", "some bytes");
+        runtime.set_io_backend(backend.clone());
+        call_copy("in", "out")?;
+        assert_eq!(backend.file("out").unwrap(), b"some bytes");
+        assert_eq!(backend.num_open(), 0);
+
+        let err = call_copy("missing", "out").unwrap_err();
+        assert!(matches!(err, RuntimeError::Io(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn failed_module_set_load_is_rolled_back() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("a"
+                        (const value 1)
+                        (export value))
+                    ("b"
+                        (import missing "a" missing)
+                        (const value (list missing))
+                        (export value)))
+            "#,
+        )?;
+
+        let runtime = Runtime::new();
+        assert!(runtime.load_module_set(&module_set).is_err());
+        assert!(!runtime.is_module_loaded(&ModuleId::new(["a"])));
+        assert!(!runtime.is_module_loaded(&ModuleId::new(["b"])));
+        Ok(())
+    }
+
+    #[test]
+    fn std_io_rejects_handles_and_lengths_beyond_integers() -> anyhow::Result<()> {
+        use crate::runtime::{IoBackend, OpenMode};
+
+        // Hands out the largest handle for "huge", and claims to write more
+        // bytes than an integer holds.
+        struct HugeBackend;
+        impl IoBackend for HugeBackend {
+            fn open(&self, path: &str, _mode: OpenMode) -> std::io::Result<u64> {
+                Ok(if path == "huge" { u64::MAX } else { 0 })
+            }
+            fn read(&self, _handle: u64, _max_len: usize) -> std::io::Result<Vec<u8>> {
+                Ok(Vec::new())
+            }
+            fn write(&self, _handle: u64, _data: &[u8]) -> std::io::Result<usize> {
+                Ok(usize::MAX)
+            }
+            fn close(&self, _handle: u64) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let runtime = Runtime::new();
+        runtime.load_std_modules()?;
+        runtime.set_io_backend(HugeBackend);
+        let top_level = runtime.make_top_level();
+
+        let mut stack = top_level.stack();
+        stack.push_string("huge")?;
+        stack.push_string("r")?;
+        stack.push_import(&ImportSource::new(["std", "io"], "open"))?;
+        drop(stack);
+        assert!(matches!(
+            top_level.call_function(2),
+            Err(RuntimeError::Conversion(_))
+        ));
+
+        let mut stack = top_level.stack();
+        let len = stack.len();
+        stack.pop_n(len)?;
+        stack.push_int(0)?;
+        stack.push_string("data")?;
+        stack.push_import(&ImportSource::new(["std", "io"], "write"))?;
+        drop(stack);
+        assert!(matches!(
+            top_level.call_function(2),
+            Err(RuntimeError::Conversion(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn native_function_sees_caller() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import probe "host" probe)
+                        (const run
+                            (fn
+                                (push probe)
+                                (call 0 0)
+                                (return 0)))
+                        (export run)))
+            "#,
+        )?;
+        let calls = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut host = NativeModule::new(["host"]);
+        {
+            let calls = calls.clone();
+            host.add_function("probe", move |ctxt| {
+                calls
+                    .borrow_mut()
+                    .push((ctxt.caller().cloned(), ctxt.call_depth()));
+                Ok(ctxt.return_with(0))
+            });
+        }
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        top_level.call_function(0)?;
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["host"], "probe"))?;
+        top_level.call_function(0)?;
+
+        let calls = calls.borrow();
+        assert_eq!(calls.len(), 2);
+        let (caller, depth) = &calls[0];
+        let caller = caller.as_ref().expect("probe should have a managed caller");
+        assert_eq!(caller.module_id(), Some(&ModuleId::new(["test"])));
+        assert_eq!(caller.pc(), 1);
+        assert_eq!(*depth, 1);
+        assert_eq!(calls[1], (None, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn hot_function_is_profiled_and_optimized() -> anyhow::Result<()> {
+        struct DropAdd(std::rc::Rc<std::cell::Cell<u32>>);
+
+        impl FunctionOptimizer for DropAdd {
+            fn optimize(
+                &self,
+                profile: &FunctionProfile,
+                _instructions: &InstructionList,
+            ) -> Option<InstructionList> {
+                assert_eq!(profile.call_count(), 2);
+                self.0.set(self.0.get() + 1);
+                // Return the argument unchanged.
+                Some(InstructionList::new(vec![Instruction::Return(1)]))
+            }
+        }
+
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const one 1)
+                        (const inc
+                            (fn
+                                (push one)
+                                (add)
+                                (return 1)))
+                        (export inc)))
+            "#,
+        )?;
+        let optimize_count = std::rc::Rc::new(std::cell::Cell::new(0));
+        let runtime = Runtime::new();
+        runtime.set_function_optimizer(2, DropAdd(optimize_count.clone()));
+        runtime.load_module_set(&module_set)?;
+
+        let top_level = runtime.make_top_level();
+        let mut results = Vec::new();
+        for _ in 0..3 {
+            {
+                let mut stack = top_level.stack();
+                stack.push_int(10)?;
+                stack.push_import(&ImportSource::new(["test"], "inc"))?;
+            }
+            top_level.call_function(1)?;
+            results.push(top_level.stack().get_int(StackIndex::FromTop(0))?);
+        }
+        assert_eq!(
+            results,
+            vec![Integer::from(11), Integer::from(10), Integer::from(10)]
+        );
+        assert_eq!(optimize_count.get(), 1);
+
+        let hot = runtime.hot_functions(3);
+        assert_eq!(hot.len(), 1);
+        assert_eq!(hot[0].module_id(), Some(&ModuleId::new(["test"])));
+        assert_eq!(hot[0].call_count(), 3);
+        assert!(runtime.hot_functions(4).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn loaded_modules_expose_export_docs() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const one 1 (doc "The number one."))
+                        (const two 2)
+                        (export one)
+                        (export two)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        assert_eq!(
+            runtime.export_doc(&ImportSource::new(["test"], "one"))?,
+            Some("The number one.".to_string())
+        );
+        assert_eq!(
+            runtime.export_doc(&ImportSource::new(["test"], "two"))?,
+            None
+        );
+        assert!(runtime
+            .export_doc(&ImportSource::new(["missing"], "one"))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_optimized_instructions_are_ignored() -> anyhow::Result<()> {
+        struct OutOfRange;
+
+        impl FunctionOptimizer for OutOfRange {
+            fn optimize(
+                &self,
+                _profile: &FunctionProfile,
+                _instructions: &InstructionList,
+            ) -> Option<InstructionList> {
+                Some(InstructionList::new(vec![
+                    Instruction::PushConst(LocalConstIndex::new(100)),
+                    Instruction::Return(1),
+                ]))
+            }
+        }
+
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const one 1)
+                        (const inc
+                            (fn
+                                (push one)
+                                (add)
+                                (return 1)))
+                        (export inc)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.set_function_optimizer(1, OutOfRange);
+        runtime.load_module_set(&module_set)?;
+
+        let top_level = runtime.make_top_level();
+        let mut results = Vec::new();
+        for _ in 0..3 {
+            {
+                let mut stack = top_level.stack();
+                stack.push_int(10)?;
+                stack.push_import(&ImportSource::new(["test"], "inc"))?;
+            }
+            top_level.call_function(1)?;
+            results.push(top_level.stack().get_int(StackIndex::FromTop(0))?);
+        }
+        assert_eq!(results, vec![Integer::from(11); 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn stack_samples_are_collapsed_for_flamegraphs() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const spin
+                            (fn
+                                (params n)
+                                #:loop
+                                (push n)
+                                (push 0)
+                                (cmp le)
+                                (branch_if #:end)
+                                (push n)
+                                (push 1)
+                                (sub)
+                                (write_stack bot 0)
+                                (branch #:loop)
+                                #:end
+                                (return 0)))
+                        (const outer
+                            (fn
+                                (push spin)
+                                (push 500)
+                                (call 1 0)
+                                (return 0)))
+                        (export spin)
+                        (export outer)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        let module = module_set.module(&ModuleId::new(["test"])).unwrap();
+        let id = |name: &str| {
+            let index = module.exports()[&ModuleMemberId::new(name)];
+            format!("test#{index}")
+        };
+
+        let run_outer = || {
+            top_level
+                .stack()
+                .push_import(&ImportSource::new(["test"], "outer"))?;
+            top_level.call_function(0)
+        };
+        run_outer()?;
+        assert_eq!(runtime.profiler_collapsed_stacks(), "");
+
+        runtime.start_stack_sampling(10);
+        run_outer()?;
+        let collapsed = runtime.profiler_collapsed_stacks();
+        let mut total = 0;
+        for line in collapsed.lines() {
+            let (stack, count) = line.rsplit_once(' ').unwrap();
+            assert!(stack == id("outer") || stack == format!("{};{}", id("outer"), id("spin")));
+            total += count.parse::<u64>()?;
+        }
+        // The loop runs nine instructions for each of its 500 iterations.
+        assert!((450..=460).contains(&total), "{collapsed}");
+        assert!(collapsed.contains(&format!("{};{} ", id("outer"), id("spin"))));
+
+        runtime.stop_stack_sampling();
+        assert_eq!(runtime.profiler_collapsed_stacks(), "");
+        Ok(())
+    }
+
+    #[test]
+    fn loading_a_loaded_module_id_fails_unless_allowed() -> anyhow::Result<()> {
+        let version = |value: i64| {
+            super::lat::from_str(&format!(
+                r#"(module-set ("app" (const version {value}) (export version)))"#
+            ))
+        };
+        let read_version = |runtime: &Runtime| -> anyhow::Result<Integer> {
+            let top_level = runtime.make_top_level();
+            let mut stack = top_level.stack();
+            stack.push_import(&ImportSource::new(["app"], "version"))?;
+            Ok(stack.get_int(StackIndex::FromTop(0))?)
+        };
+        let runtime = Runtime::new();
+        runtime.load_module_set(&version(1)?)?;
+        assert!(matches!(
+            runtime.load_module_set(&version(2)?),
+            Err(RuntimeError::ModuleAlreadyLoaded { module }) if module == "app"
+        ));
+        assert_eq!(read_version(&runtime)?, Integer::from(1));
+
+        runtime.load_std_modules()?;
+        assert!(matches!(
+            runtime.load_std_modules(),
+            Err(RuntimeError::ModuleAlreadyLoaded { .. })
+        ));
+
+        runtime.set_allow_module_replacement(true);
+        runtime.load_module_set(&version(2)?)?;
+        assert_eq!(read_version(&runtime)?, Integer::from(2));
+        Ok(())
+    }
+
+    #[test]
+    fn binding_past_a_declared_arity_fails() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const add
+                            (fn
+                                (params a b)
+                                (push a)
+                                (push b)
+                                (add)
+                                (return 1)))
+                        (const bind_two
+                            (fn
+                                (params f)
+                                (push f)
+                                (push 1)
+                                (push 2)
+                                (bind_front 2)
+                                (return 1)))
+                        (export add)
+                        (export bind_two)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+
+        let top_level = runtime.make_top_level();
+        let bind_two = ImportSource::new(["test"], "bind_two");
+        {
+            let mut stack = top_level.stack();
+            stack.push_import(&ImportSource::new(["test"], "add"))?;
+            stack.push_import(&bind_two)?;
+        }
+        top_level.call_function(1)?;
+        top_level.call_function(0)?;
+        assert_eq!(
+            Integer::from(3),
+            top_level.stack().get_int(StackIndex::FromTop(0))?
+        );
+
+        // Closures count the values they already captured.
+        {
+            let mut stack = top_level.stack();
+            stack.push_import(&ImportSource::new(["test"], "add"))?;
+            stack.push_import(&bind_two)?;
+        }
+        top_level.call_function(1)?;
+        top_level.stack().push_import(&bind_two)?;
+        let result = top_level.call_function(1);
+        assert!(
+            matches!(
+                &result,
+                Err(RuntimeError::TooManyBoundArguments { function, arity: 0, bound: 2 })
+                    if function.starts_with("test#")
+            ),
+            "{result:?}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn lat_closures_capture_parameters() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (export run)
+                        (const curry
+                            (fn
+                                (params a)
+                                (closure (a)
+                                    (params b)
+                                    (closure (a b)
+                                        (params c)
+                                        (push a)
+                                        (push b)
+                                        (sub)
+                                        (push c)
+                                        (mul)
+                                        (return 1))
+                                    (return 1))
+                                (return 1)))
+                        (const run
+                            (fn
+                                (params a b c)
+                                (push curry)
+                                (push a)
+                                (call 1 1)
+                                (push b)
+                                (call 1 1)
+                                (push c)
+                                (call 1 1)
+                                (return 1)))))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        let run = top_level.thunk::<(i64, i64, i64), i64>(&ImportSource::new(["test"], "run"))?;
+        assert_eq!(run((10, 4, 3))?, 18);
+        assert_eq!(run((1, 2, 5))?, -5);
+        Ok(())
+    }
+
+    #[test]
+    fn const_eval_initializer_sets_globals() -> anyhow::Result<()> {
+        let init = ConstFunction::new(
+            vec![
+                ConstIndex::ModuleConst(ModuleConstIndex::new(0)),
+                ConstIndex::ModuleConst(ModuleConstIndex::new(1)),
+            ],
+            InstructionList::new(vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PushConst(LocalConstIndex::new(1)),
+                Instruction::Add,
+                Instruction::PopGlobal(GlobalIndex::new(0)),
+                Instruction::Return(0),
+            ]),
+        );
+        let getter = ConstFunction::new(
+            vec![],
+            InstructionList::new(vec![
+                Instruction::PushGlobal(GlobalIndex::new(0)),
+                Instruction::Return(1),
+            ]),
+        );
+        let module = ConstModule::new(
+            ModuleId::new(["test"]),
+            vec![
+                ConstValue::Integer(40.into()),
+                ConstValue::Integer(2.into()),
+                ConstValue::Function(init),
+                ConstValue::Function(getter),
+            ],
+            vec![],
+            [("get".into(), ModuleConstIndex::new(3))]
+                .into_iter()
+                .collect(),
+            Some(ModuleConstIndex::new(2)),
+            1,
+        )?;
+
+        let runtime = Runtime::new();
+        runtime.set_const_eval_initializers(true);
+        runtime.load_module(&module)?;
+
+        let top_level = runtime.make_top_level();
+        top_level.init_module(&ModuleId::new(["test"]))?;
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "get"))?;
+        assert_eq!(top_level.call_function(0)?, 1);
+        assert_eq!(
+            Integer::from(42),
+            top_level.stack().get_int(StackIndex::FromTop(0))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn const_eval_keeps_initializer_arguments() -> anyhow::Result<()> {
+        let init = ConstFunction::new(
+            vec![ConstIndex::ModuleConst(ModuleConstIndex::new(0))],
+            InstructionList::new(vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PushCopy(StackIndex::FromBottom(0)),
+                Instruction::PopGlobal(GlobalIndex::new(0)),
+                Instruction::Pop(1),
+                Instruction::Return(0),
+            ]),
+        );
+        let getter = ConstFunction::new(
+            vec![],
+            InstructionList::new(vec![
+                Instruction::PushGlobal(GlobalIndex::new(0)),
+                Instruction::Return(1),
+            ]),
+        );
+        let module = ConstModule::new(
+            ModuleId::new(["test"]),
+            vec![
+                ConstValue::Integer(5.into()),
+                ConstValue::Function(init),
+                ConstValue::Function(getter),
+            ],
+            vec![],
+            [("get".into(), ModuleConstIndex::new(2))]
+                .into_iter()
+                .collect(),
+            Some(ModuleConstIndex::new(1)),
+            1,
+        )?;
+
+        let runtime = Runtime::new();
+        runtime.set_const_eval_initializers(true);
+        runtime.load_module(&module)?;
+
+        // The global is set from the argument, not from the constant pushed
+        // above it.
+        let top_level = runtime.make_top_level();
+        top_level.stack().push_int(7)?;
+        top_level.init_module_with_args(&ModuleId::new(["test"]), 1)?;
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "get"))?;
+        assert_eq!(top_level.call_function(0)?, 1);
+        assert_eq!(
+            Integer::from(7),
+            top_level.stack().get_int(StackIndex::FromTop(0))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn string_format_builds_message() -> anyhow::Result<()> {
+        let runtime = Runtime::new();
+        runtime.load_std_modules()?;
+
+        let top_level = runtime.make_top_level();
+        {
+            let mut stack = top_level.stack();
+            stack.push_string("{1} + {1} = {0}")?;
+            stack.push_int(4)?;
+            stack.push_int(2)?;
+            stack.make_list(2)?;
+            stack.push_import(&ImportSource::new(["std", "string"], "format"))?;
+        }
+        assert_eq!(top_level.call_function(2)?, 1);
+        let message = top_level
+            .stack()
+            .get_string(StackIndex::FromTop(0), |s| Ok(s.to_string()))?;
+        assert_eq!(message, "2 + 2 = 4");
+        Ok(())
+    }
+
+    #[test]
+    fn list_index_out_of_range_is_user_error() -> anyhow::Result<()> {
+        let get = ConstFunction::new(
+            vec![
+                ConstIndex::ModuleConst(ModuleConstIndex::new(0)),
+                ConstIndex::ModuleConst(ModuleConstIndex::new(1)),
+            ],
+            InstructionList::new(vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PushConst(LocalConstIndex::new(1)),
+                Instruction::ListGet,
+                Instruction::Return(1),
+            ]),
+        );
+        let module = ConstModule::new(
+            ModuleId::new(["test"]),
+            vec![
+                ConstValue::Integer(5.into()),
+                ConstValue::List(vec![ConstIndex::ModuleConst(ModuleConstIndex::new(0))]),
+                ConstValue::Function(get),
+            ],
+            vec![],
+            [("get".into(), ModuleConstIndex::new(2))]
+                .into_iter()
+                .collect(),
+            None,
+            0,
+        )?;
+
+        let runtime = Runtime::new();
+        runtime.load_module(&module)?;
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "get"))?;
+        let error = top_level.call_function(0).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UserError);
+        Ok(())
+    }
+
+    #[test]
+    fn relative_list_indexes_count_from_end() -> anyhow::Result<()> {
+        let builder = ModuleBuilder::new(ModuleId::new(["test"]));
+        let items = [10, 20, 30].map(|i| builder.new_int(i));
+        let list = builder.new_list(items);
+        let (last, mut fn_builder) = builder.new_function();
+        fn_builder
+            .push_int(-1)
+            .push_value(&list)?
+            .list_get_rel()
+            .return_(1);
+        fn_builder.build()?;
+        last.export("last".into())?;
+        let (middle_len, mut fn_builder) = builder.new_function();
+        fn_builder
+            .push_int(-1)
+            .push_int(1)
+            .push_value(&list)?
+            .list_slice()
+            .list_len()
+            .return_(1);
+        fn_builder.build()?;
+        middle_len.export("middle_len".into())?;
+
+        let runtime = Runtime::new();
+        runtime.load_module(&builder.into_const_module()?)?;
+        let top_level = runtime.make_top_level();
+        for (name, expected) in [("last", 30), ("middle_len", 1)] {
+            top_level
+                .stack()
+                .push_import(&ImportSource::new(["test"], name))?;
+            top_level.call_function(0)?;
+            assert_eq!(
+                Integer::from(expected),
+                top_level.stack().get_int(StackIndex::FromTop(0))?
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn merged_module_fragments_keep_their_globals() -> anyhow::Result<()> {
+        // Each fragment sets its own global in its initializer, and exports a
+        // function that adds it to a constant.
+        let fragment = |id: &str, name: &str, value: i64| -> anyhow::Result<ConstModule> {
+            let builder = ModuleBuilder::new(ModuleId::new([id]));
+            let global = builder.new_global();
+            let offset = builder.new_int(value * 10);
+            let mut init = builder.new_initializer()?;
+            init.push_int(value).pop_value(&global)?.return_(0);
+            init.build()?;
+            let (get, mut fn_builder) = builder.new_function();
+            fn_builder
+                .push_value(&global)?
+                .push_value(&offset)?
+                .add()
+                .return_(1);
+            fn_builder.build()?;
+            get.export(name.into())?;
+            Ok(builder.into_const_module()?)
+        };
+        let merged = fragment("app", "get_a", 1)?.merge(fragment("app", "get_b", 2)?)?;
+        assert_eq!(merged.global_table_size(), 2);
+
+        let runtime = Runtime::new();
+        runtime.load_module(&merged)?;
+        let top_level = runtime.make_top_level();
+        top_level.init_module(&ModuleId::new(["app"]))?;
+        for (name, expected) in [("get_a", 11), ("get_b", 22)] {
+            top_level
+                .stack()
+                .push_import(&ImportSource::new(["app"], name))?;
+            top_level.call_function(0)?;
+            assert_eq!(
+                Integer::from(expected),
+                top_level.stack().get_int(StackIndex::FromTop(0))?
+            );
+        }
+
+        assert!(matches!(
+            fragment("app", "get", 1)?.merge(fragment("app", "get", 2)?),
+            Err(BuilderError::DuplicateExport(name)) if name == "get"
+        ));
+        assert!(matches!(
+            fragment("app", "get_a", 1)?.merge(fragment("lib", "get_b", 2)?),
+            Err(BuilderError::MismatchedModule { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn merged_initializers_receive_the_module_arguments() -> anyhow::Result<()> {
+        // Each fragment adds its argument to its own offset and stores the
+        // result in a global it exports a getter for.
+        let fragment = |name: &str, offset: i64, arity: u32| -> anyhow::Result<ConstModule> {
+            let builder = ModuleBuilder::new(ModuleId::new(["app"]));
+            let global = builder.new_global();
+            let mut init = builder.new_initializer()?;
+            init.set_arity(arity)
+                .push_copy(StackIndex::FromBottom(0))
+                .push_int(offset)
+                .add()
+                .pop_value(&global)?
+                .return_(0);
+            init.build()?;
+            let (get, mut fn_builder) = builder.new_function();
+            fn_builder.push_value(&global)?.return_(1);
+            fn_builder.build()?;
+            get.export(name.into())?;
+            Ok(builder.into_const_module()?)
+        };
+        let merged = fragment("get_a", 10, 1)?.merge(fragment("get_b", 20, 1)?)?;
+
+        let runtime = Runtime::new();
+        runtime.load_module(&merged)?;
+        let top_level = runtime.make_top_level();
+        top_level.stack().push_int(5)?;
+        top_level.init_module_with_args(&ModuleId::new(["app"]), 1)?;
+        for (name, expected) in [("get_a", 15), ("get_b", 25)] {
+            top_level
+                .stack()
+                .push_import(&ImportSource::new(["app"], name))?;
+            top_level.call_function(0)?;
+            assert_eq!(
+                Integer::from(expected),
+                top_level.stack().get_int(StackIndex::FromTop(0))?
+            );
+        }
+
+        assert!(matches!(
+            fragment("get_a", 10, 1)?.merge(fragment("get_b", 20, 2)?),
+            Err(BuilderError::MismatchedInitializers {
+                first: 1,
+                second: 2
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn strings_index_by_bytes_or_chars() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const len_bytes (fn (params s) (push s) (str_len_bytes) (return 1)))
+                        (const len_chars (fn (params s) (push s) (str_len_chars) (return 1)))
+                        (const slice_bytes
+                            (fn
+                                (params s start end)
+                                (push end)
+                                (push start)
+                                (push s)
+                                (str_slice_bytes)
+                                (return 1)))
+                        (const slice_chars
+                            (fn
+                                (params s start end)
+                                (push end)
+                                (push start)
+                                (push s)
+                                (str_slice_chars)
+                                (return 1)))
+                        (export len_bytes)
+                        (export len_chars)
+                        (export slice_bytes)
+                        (export slice_chars)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        let source = |name| ImportSource::new(["test"], name);
+        let len_bytes = top_level.thunk::<(&str,), i64>(&source("len_bytes"))?;
+        let len_chars = top_level.thunk::<(&str,), i64>(&source("len_chars"))?;
+        let slice_bytes = top_level.thunk::<(&str, i64, i64), String>(&source("slice_bytes"))?;
+        let slice_chars = top_level.thunk::<(&str, i64, i64), String>(&source("slice_chars"))?;
+
+        let word = "na\u{ef}ve";
+        assert_eq!(len_bytes((word,))?, 6);
+        assert_eq!(len_chars((word,))?, 5);
+        assert_eq!(slice_chars((word, 1, 3))?, "a\u{ef}");
+        assert_eq!(slice_chars((word, -2, 5))?, "ve");
+        assert_eq!(slice_bytes((word, 1, 4))?, "a\u{ef}");
+
+        let Err(error) = slice_bytes((word, 1, 3)) else {
+            panic!("a slice inside a char should fail");
+        };
+        assert_eq!(error.kind(), ErrorKind::UserError);
+        assert!(error.to_string().contains("Byte index 3"), "{error}");
+        assert!(
+            error.to_string().contains("boundaries are 2 and 4"),
+            "{error}"
+        );
+        let Err(error) = slice_chars((word, 0, 6)) else {
+            panic!("a slice past the end should fail");
+        };
+        assert!(
+            error.to_string().contains("Index 6 is out of range"),
+            "{error}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn cell_copies_share_value() -> anyhow::Result<()> {
+        let builder = ModuleBuilder::new(ModuleId::new(["test"]));
+        let (run, mut fn_builder) = builder.new_function();
+        fn_builder
+            // Stack: [cell(1)]
+            .push_int(1)
+            .cell_new()
+            // Set the cell through a copy of it.
+            .push_int(5)
+            .push_copy(StackIndex::FromBottom(0))
+            .cell_set()
+            .cell_get()
+            .return_(1);
+        fn_builder.build()?;
+        run.export("run".into())?;
+
+        let runtime = Runtime::new();
+        runtime.load_module(&builder.into_const_module()?)?;
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        top_level.call_function(0)?;
+        assert_eq!(
+            Integer::from(5),
+            top_level.stack().get_int(StackIndex::FromTop(0))?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn missing_list_items_read_as_null() -> anyhow::Result<()> {
+        let builder = ModuleBuilder::new(ModuleId::new(["test"]));
+        let items = builder.new_list([builder.new_int(7)]);
+        let (run, mut fn_builder) = builder.new_function();
+        fn_builder
+            .push_int(0)
+            .push_value(&items)?
+            .list_get_or_null()
+            .push_int(1)
+            .push_value(&items)?
+            .list_get_or_null()
+            .is_null()
+            .push_null()
+            .is_null()
+            .return_(3);
+        fn_builder.build()?;
+        run.export("run".into())?;
+
+        let runtime = Runtime::new();
+        runtime.load_module(&builder.into_const_module()?)?;
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        assert_eq!(top_level.call_function(0)?, 3);
+        let stack = top_level.stack();
+        assert_eq!(stack.get_int(StackIndex::FromTop(2))?, Integer::from(7));
+        assert!(stack.get_bool(StackIndex::FromTop(1))?);
+        assert!(stack.get_bool(StackIndex::FromTop(0))?);
+        Ok(())
+    }
+
+    #[test]
+    fn records_read_and_write_named_fields() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const origin (record (x 1) (y 2)))
+                        (const run
+                            (fn
+                                (push 3)
+                                (push 4)
+                                (record_new (x y))
+                                ; Stack: [p]
+                                (push 10)
+                                (push_copy top 1)
+                                (record_set x)
+                                (push_copy top 0)
+                                (record_get x)
+                                (push_copy top 1)
+                                (record_get y)
+                                (push origin)
+                                (record_get y)
+                                (return 3)))
+                        (const missing
+                            (fn
+                                (push origin)
+                                (record_get z)
+                                (return 1)))
+                        (export run)
+                        (export missing)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        assert_eq!(top_level.call_function(0)?, 3);
+        {
+            let stack = top_level.stack();
+            assert_eq!(stack.get_int(StackIndex::FromTop(2))?, Integer::from(10));
+            assert_eq!(stack.get_int(StackIndex::FromTop(1))?, Integer::from(4));
+            assert_eq!(stack.get_int(StackIndex::FromTop(0))?, Integer::from(2));
+        }
+        top_level.stack().pop_n(3)?;
+
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "missing"))?;
+        assert!(matches!(
+            top_level.call_function(0),
+            Err(RuntimeError::OperationPrecondition(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn natives_convert_lists_of_records_to_structs() -> anyhow::Result<()> {
+        #[derive(Debug, PartialEq)]
+        struct Row {
+            name: String,
+            score: i64,
+            note: Option<String>,
+        }
+        crate::loon_record!(Row { name, score, note });
+
+        struct Name {
+            name: String,
+            score: String,
+        }
+        crate::loon_record!(Name { name, score });
+
+        let runtime = Runtime::new();
+        let top_level = runtime.make_top_level();
+        {
+            let mut stack = top_level.stack();
+            stack.push_records([
+                Row {
+                    name: "a".to_string(),
+                    score: 1,
+                    note: None,
+                },
+                Row {
+                    name: "b".to_string(),
+                    score: 2,
+                    note: Some("late".to_string()),
+                },
+            ])?;
+            stack.push_native_function(|mut ctxt| {
+                {
+                    let mut stack = ctxt.stack();
+                    let rows = stack.get_records::<Row>(StackIndex::FromTop(0))?;
+                    stack.pop_n(1)?;
+                    stack.push_records(rows.into_iter().map(|row| Row {
+                        score: row.score * 10,
+                        ..row
+                    }))?;
+                }
+                Ok(ctxt.return_with(1))
+            })?;
+        }
+        assert_eq!(top_level.call_function(1)?, 1);
+        assert_eq!(
+            top_level
+                .stack()
+                .get_records::<Row>(StackIndex::FromTop(0))?,
+            [
+                Row {
+                    name: "a".to_string(),
+                    score: 10,
+                    note: None,
+                },
+                Row {
+                    name: "b".to_string(),
+                    score: 20,
+                    note: Some("late".to_string()),
+                },
+            ]
+        );
+        top_level.stack().pop_n(1)?;
+
+        // Errors name the item and field that failed to convert.
+        top_level.stack().push_records([Name {
+            name: "c".to_string(),
+            score: "high".to_string(),
+        }])?;
+        let Err(RuntimeError::Type(error)) =
+            top_level.stack().get_records::<Row>(StackIndex::FromTop(0))
+        else {
+            anyhow::bail!("expected a type error");
+        };
+        assert!(error.to_string().contains("Item 0: Field \"score\""));
+        Ok(())
+    }
+
+    #[test]
+    fn tagged_values_only_untag_with_their_tag() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const run
+                            (fn
+                                (tag_new Point)
+                                (push 3)
+                                (push_copy top 1)
+                                (tag_wrap)
+                                ; Stack: [tag, tagged]
+                                (push_copy top 0)
+                                (tag_of)
+                                (push_copy top 2)
+                                (cmp ref_eq)
+                                (push_copy top 1)
+                                (push_copy top 3)
+                                (untag)
+                                (push 4)
+                                (tag_of)
+                                (return 3)))
+                        (const mismatch
+                            (fn
+                                (push 3)
+                                (tag_new Point)
+                                (tag_wrap)
+                                (tag_new Point)
+                                (untag)
+                                (return 1)))
+                        (export run)
+                        (export mismatch)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        assert_eq!(top_level.call_function(0)?, 3);
+        {
+            let stack = top_level.stack();
+            assert!(stack.get_bool(StackIndex::FromTop(2))?);
+            assert_eq!(stack.get_int(StackIndex::FromTop(1))?, Integer::from(3));
+            assert_eq!(
+                stack.get_loon_value(StackIndex::FromTop(0))?,
+                LoonValue::Null
+            );
+        }
+        top_level.stack().pop_n(3)?;
+
+        // A tag with the same name is still a different tag.
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "mismatch"))?;
+        assert!(matches!(
+            top_level.call_function(0),
+            Err(RuntimeError::Type(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn protected_calls_catch_errors_as_values() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import kind "std.error" kind)
+                        (import message "std.error" message)
+                        (const failing (fn (push 1) (record_get x) (return 1)))
+                        (const middle (fn (push failing) (call 0 1) (return 1)))
+                        (const raising (fn (push "boom") (raise) (return 0)))
+                        (const run
+                            (fn
+                                (push middle)
+                                (call_protected 0 1 #:caught)
+                                (return 1)
+                                #:caught
+                                ; Stack: [error]
+                                (push kind)
+                                (push_copy top 1)
+                                (call 1 1)
+                                (push raising)
+                                (call_protected 0 0 #:raised)
+                                (return 0)
+                                #:raised
+                                ; Stack: [error, kind, raised]
+                                (push message)
+                                (push_copy top 1)
+                                (call 1 1)
+                                ; Stack: [error, kind, raised, message]
+                                (push_copy top 2)
+                                (push_copy top 1)
+                                (return 2)))
+                        (export run)
+                        (export middle)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_std_modules()?;
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "run"))?;
+        assert_eq!(top_level.call_function(0)?, 2);
+        {
+            let stack = top_level.stack();
+            stack.get_string(StackIndex::FromTop(1), |kind| {
+                assert_eq!(kind, "type");
+                Ok(())
+            })?;
+            stack.get_string(StackIndex::FromTop(0), |message| {
+                assert_eq!(message, "boom");
+                Ok(())
+            })?;
+        }
+        top_level.stack().pop_n(2)?;
+
+        // Without a protected call, the error still reaches the host.
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "middle"))?;
+        assert!(matches!(
+            top_level.call_function(0),
+            Err(RuntimeError::Type(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn weak_ref_reads_null_once_target_is_collected() -> anyhow::Result<()> {
+        let builder = ModuleBuilder::new(ModuleId::new(["test"]));
+        let (make, mut fn_builder) = builder.new_function();
+        fn_builder
+            // Stack: [list, weak(list)]
+            .list_new()
+            .push_copy(StackIndex::FromTop(0))
+            .weak_new()
+            .return_(2);
+        fn_builder.build()?;
+        make.export("make".into())?;
+        let (get, mut fn_builder) = builder.new_function();
+        fn_builder
+            .push_copy(StackIndex::FromBottom(0))
+            .weak_get()
+            .return_(1);
+        fn_builder.build()?;
+        get.export("get".into())?;
+
+        let runtime = Runtime::new();
+        runtime.load_module(&builder.into_const_module()?)?;
+        let top_level = runtime.make_top_level();
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "make"))?;
+        assert_eq!(top_level.call_function(0)?, 2);
+        let list = top_level.stack().get_host_value(StackIndex::FromTop(1))?;
+        let weak = top_level.stack().get_host_value(StackIndex::FromTop(0))?;
+        top_level.stack().pop_n(2)?;
+        assert_eq!(weak.kind(), ValueKind::WeakRef);
+
+        let read_target = || -> anyhow::Result<ValueKind> {
+            let mut stack = top_level.stack();
+            stack.push_host_value(&weak)?;
+            stack.push_import(&ImportSource::new(["test"], "get"))?;
+            drop(stack);
+            top_level.call_function(1)?;
+            let target = top_level.stack().get_host_value(StackIndex::FromTop(0))?;
+            top_level.stack().pop_n(1)?;
+            Ok(target.kind())
+        };
+        runtime.collect_garbage();
+        assert_eq!(read_target()?, ValueKind::List);
+
+        drop(list);
+        runtime.collect_garbage();
+        assert_eq!(read_target()?, ValueKind::Null);
+        Ok(())
+    }
+
+    #[test]
+    fn native_imports_require_granted_capabilities() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("app"
+                        (import now "host.clock" now)
+                        (const clocks (list now))
+                        (export clocks)))
+            "#,
+        )?;
+        let mut clock = NativeModule::new(["host", "clock"]);
+        clock
+            .require_capability("time")
+            .add_function("now", |mut ctxt| {
+                ctxt.stack().push_int(0)?;
+                Ok(ctxt.return_with(1))
+            });
+
+        let runtime = Runtime::new();
+        runtime.load_native_module(&clock)?;
+        let app = ModuleId::new(["app"]);
+        runtime.grant_capabilities(&app, CapabilitySet::new().with("fs"));
+        assert!(matches!(
+            runtime.load_module_set(&module_set),
+            Err(RuntimeError::CapabilityNotGranted { .. })
+        ));
+
+        runtime.grant_capabilities(&app, CapabilitySet::new().with("time"));
+        runtime.load_module_set(&module_set)?;
+        assert!(runtime.is_module_loaded(&app));
+        Ok(())
+    }
+
+    #[test]
+    fn module_sets_load_and_initialize_in_dependency_order() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("app"
+                        (import record "host" record)
+                        (import base "base" value)
+                        (init
+                            (push record)
+                            (push base)
+                            (call 1 0)
+                            (return 0)))
+                    ("base"
+                        (import record "host" record)
+                        (export value)
+                        (const value 1)
+                        (init
+                            (push record)
+                            (push 0)
+                            (call 1 0)
+                            (return 0))))
+            "#,
+        )?;
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut host = NativeModule::new(["host"]);
+        {
+            let seen = seen.clone();
+            host.add_function("record", move |mut ctxt| {
+                let value = ctxt.stack().get_int(StackIndex::FromTop(0))?;
+                seen.borrow_mut().push(value.to_compact_integer());
+                Ok(ctxt.return_with(0))
+            });
+        }
+
+        let runtime = Runtime::new();
+        assert!(matches!(
+            runtime.load_module_set(&module_set),
+            Err(RuntimeError::DependencyNotLoaded { module, dependency })
+                if module == "base" && dependency == "host"
+        ));
+        assert!(!runtime.is_module_loaded(&ModuleId::new(["base"])));
+
+        runtime.load_native_module(&host)?;
+        runtime.set_init_modules_on_load(true);
+        runtime.load_module_set(&module_set)?;
+        assert_eq!(*seen.borrow(), [Some(0), Some(1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn programs_round_trip_and_run_from_their_entry_point() -> anyhow::Result<()> {
+        let module_set = || {
+            super::lat::from_str(
+                r#"
+                    (module-set
+                        ("app"
+                            (import record "host" record)
+                            (import offset "base" offset)
+                            (export main)
+                            (const main
+                                (fn
+                                    (params x)
+                                    (push x)
+                                    (push offset)
+                                    (add)
+                                    (return 1)))
+                            (init
+                                (push record)
+                                (push 1)
+                                (call 1 0)
+                                (return 0)))
+                        ("base"
+                            (import record "host" record)
+                            (export offset)
+                            (const offset 10)
+                            (init
+                                (push record)
+                                (push 0)
+                                (call 1 0)
+                                (return 0))))
+                "#,
+            )
+        };
+        assert!(matches!(
+            Program::new(module_set()?, ImportSource::new(["app"], "missing")),
+            Err(ProgramError::EntryNotExported { module, name })
+                if module == "app" && name == "missing"
+        ));
+        assert!(matches!(
+            Program::new(module_set()?, ImportSource::new(["host"], "record")),
+            Err(ProgramError::EntryModuleMissing(module)) if module == "host"
+        ));
+
+        let program = Program::new(module_set()?, ImportSource::new(["app"], "main"))?;
+        assert_eq!(
+            program.init_order(),
+            [ModuleId::new(["base"]), ModuleId::new(["app"])]
+        );
+        let bytes = program.to_bytes();
+        let program = Program::from_bytes(&bytes)?;
+        assert_eq!(program.to_bytes(), bytes);
+        assert!(matches!(
+            Program::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        ));
+
+        let seen = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut host = NativeModule::new(["host"]);
+        {
+            let seen = seen.clone();
+            host.add_function("record", move |mut ctxt| {
+                let value = ctxt.stack().get_int(StackIndex::FromTop(0))?;
+                seen.borrow_mut().push(value.to_compact_integer());
+                Ok(ctxt.return_with(0))
+            });
+        }
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_program(&program)?;
+
+        let top_level = runtime.make_top_level();
+        top_level.stack().push_int(5)?;
+        assert_eq!(top_level.run_program(&program, 1)?, 1);
+        assert_eq!(
+            top_level.stack().get_int(StackIndex::FromTop(0))?,
+            Integer::from(15)
+        );
+        // Modules are only initialized the first time the program runs.
+        top_level.stack().push_int(1)?;
+        top_level.run_program(&program, 1)?;
+        assert_eq!(*seen.borrow(), [Some(0), Some(1)]);
+        Ok(())
+    }
+
+    #[test]
+    fn init_module_receives_arguments() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import configure "host" configure)
+                        (init
+                            (params cfg)
+                            (push configure)
+                            (push cfg)
+                            (call 1 0)
+                            (return 0))))
+            "#,
+        )?;
+        let seen = std::rc::Rc::new(std::cell::Cell::new(None));
+        let mut host = NativeModule::new(["host"]);
+        {
+            let seen = seen.clone();
+            host.add_function("configure", move |mut ctxt| {
+                let value = ctxt.stack().get_int(StackIndex::FromTop(0))?;
+                seen.set(value.to_compact_integer());
+                Ok(ctxt.return_with(0))
+            });
+        }
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+
+        let top_level = runtime.make_top_level();
+        top_level.stack().push_int(7)?;
+        top_level.init_module_with_args(&ModuleId::new(["test"]), 1)?;
+        assert_eq!(seen.get(), Some(7));
+
+        // Modules without an initializer cannot receive arguments.
+        top_level.stack().push_int(7)?;
+        assert!(matches!(
+            top_level.init_module_with_args(&ModuleId::new(["host"]), 1),
+            Err(RuntimeError::OperationPrecondition(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn module_tests_report_each_outcome() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const sums_match
+                            (fn
+                                (push 1)
+                                (push 2)
+                                (add)
+                                (push 3)
+                                (cmp eq)
+                                (return 1)))
+                        (test "sums match" sums_match)
+                        (test "returns nothing" (fn (return 0)))
+                        (test "returns false" (fn (push #f) (return 1)))
+                        (test "adds a string"
+                            (fn
+                                (push 1)
+                                (push "one")
+                                (add)
+                                (return 1)))))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let module_id = ModuleId::new(["test"]);
+        let results = runtime.run_module_tests(&module_id)?;
+        let summary = results
+            .iter()
+            .map(|result| (result.name(), result.passed()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                ("sums match", true),
+                ("returns nothing", true),
+                ("returns false", false),
+                ("adds a string", false),
+            ]
+        );
+        assert!(matches!(results[2].outcome(), TestOutcome::ReturnedFalsy));
+        let TestOutcome::Failed(error) = results[3].outcome() else {
+            panic!("expected the test to fail with an error");
+        };
+        assert_eq!(error.kind(), ErrorKind::UserError);
+        assert_eq!(results[0].to_string(), "sums match: ok");
+
+        // Tests are not exported, and leave the stack as it was.
+        let top_level = runtime.make_top_level();
+        assert!(top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "sums match"))
+            .is_err());
+        assert_eq!(top_level.run_module_tests(&module_id)?.len(), 4);
+        assert!(top_level.stack().is_empty());
+
+        assert!(runtime
+            .run_module_tests(&ModuleId::new(["missing"]))
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn optional_import_of_missing_module_is_null() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("app"
+                        (import bar "maybe.module" bar #:optional)
+                        (const missing_bar
+                            (fn
+                                (push bar)
+                                (is_null)
+                                (return 1)))
+                        (export missing_bar)))
+            "#,
+        )?;
+        let mut maybe = NativeModule::new(["maybe", "module"]);
+        maybe.add_function("bar", |ctxt| Ok(ctxt.return_with(0)));
+
+        for load_optional in [false, true] {
+            let runtime = Runtime::new();
+            if load_optional {
+                runtime.load_native_module(&maybe)?;
+            }
+            runtime.load_module_set(&module_set)?;
+            let top_level = runtime.make_top_level();
+            top_level
+                .stack()
+                .push_import(&ImportSource::new(["app"], "missing_bar"))?;
+            top_level.call_function(0)?;
+            assert_eq!(
+                top_level.stack().get_bool(StackIndex::FromTop(0))?,
+                !load_optional
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn identity_hash_is_consistent_with_ref_eq() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const items (list 1 2))
+                        (const same_hash
+                            (fn
+                                (identity_hash)
+                                (push_copy bot 0)
+                                (identity_hash)
+                                (cmp ref_eq)
+                                (return 1)))
+                        (const list_hash_is_stable
+                            (fn
+                                (push items)
+                                (identity_hash)
+                                (push items)
+                                (identity_hash)
+                                (cmp ref_eq)
+                                (return 1)))
+                        (export same_hash)
+                        (export list_hash_is_stable)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        top_level
+            .stack()
+            .push_import(&ImportSource::new(["test"], "list_hash_is_stable"))?;
+        top_level.call_function(0)?;
+        assert!(top_level.stack().get_bool(StackIndex::FromTop(0))?);
+
+        for (a, b, expected) in [(0.0, -0.0, true), (1.5, 1.5, true), (1.0, 2.0, false)] {
+            {
+                let mut stack = top_level.stack();
+                stack.push_float(a)?;
+                stack.push_float(b)?;
+                stack.push_import(&ImportSource::new(["test"], "same_hash"))?;
+            }
+            top_level.call_function(2)?;
+            assert_eq!(
+                top_level.stack().get_bool(StackIndex::FromTop(0))?,
+                expected
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn truthiness_rules_are_selectable() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const empty_is_false
+                            (fn
+                                (to_bool empty)
+                                (return 1)))
+                        (const null_or_false_is_false
+                            (fn
+                                (branch_if_truthy #:truthy null_and_false)
+                                (push #f)
+                                (return 1)
+                                #:truthy
+                                (push #t)
+                                (return 1)))
+                        (const strict
+                            (fn
+                                (to_bool strict)
+                                (return 1)))
+                        (export empty_is_false)
+                        (export null_or_false_is_false)
+                        (export strict)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        enum Arg {
+            Int(i64),
+            Float(f64),
+            Bool(bool),
+            Str(&'static str),
+        }
+        let call = |name: &str, arg: Arg| -> crate::runtime::Result<bool> {
+            {
+                let mut stack = top_level.stack();
+                match arg {
+                    Arg::Int(i) => stack.push_int(i),
+                    Arg::Float(f) => stack.push_float(f),
+                    Arg::Bool(b) => stack.push_bool(b),
+                    Arg::Str(s) => stack.push_string(s),
+                }?;
+                stack.push_import(&ImportSource::new(["test"], name))?;
+            }
+            top_level.call_function(1)?;
+            let result = top_level.stack().get_bool(StackIndex::FromTop(0));
+            result
+        };
+
+        assert!(!call("empty_is_false", Arg::Int(0))?);
+        assert!(call("empty_is_false", Arg::Int(5))?);
+        assert!(!call("empty_is_false", Arg::Float(-0.0))?);
+        assert!(!call("empty_is_false", Arg::Float(f64::NAN))?);
+        assert!(!call("empty_is_false", Arg::Str(""))?);
+        assert!(call("empty_is_false", Arg::Str("x"))?);
+        assert!(call("null_or_false_is_false", Arg::Int(0))?);
+        assert!(call("null_or_false_is_false", Arg::Str(""))?);
+        assert!(!call("null_or_false_is_false", Arg::Bool(false))?);
+        assert!(call("strict", Arg::Bool(true))?);
+        assert!(matches!(
+            call("strict", Arg::Int(1)),
+            Err(RuntimeError::Type(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn instruction_policy_is_enforced_at_load() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const helper
+                            (fn
+                                (push 1)
+                                (push 2)
+                                (add)
+                                (return 1)))
+                        (const run
+                            (fn
+                                (push helper)
+                                (call 0 1)
+                                (return 1)))
+                        (export run)))
+            "#,
+        )?;
+        let module_id = ModuleId::new(["test"]);
+
+        let runtime = Runtime::new();
+        runtime.set_instruction_policy(
+            &module_id,
+            InstructionPolicy::allow_all().deny(InstructionFamily::CallDynamic),
+        );
+        runtime.load_module_set(&module_set)?;
+
+        let runtime = Runtime::new();
+        runtime.set_instruction_policy(
+            &module_id,
+            InstructionPolicy::allow_all().deny(InstructionFamily::Arithmetic),
+        );
+        let error = runtime.load_module_set(&module_set).unwrap_err();
+        let RuntimeError::Validation {
+            module,
+            error:
+                ValidationError::DeniedInstruction {
+                    export_name,
+                    pc,
+                    family,
+                    ..
+                },
+        } = &error
+        else {
+            panic!("Unexpected error: {error}");
+        };
+        assert_eq!(module, "test");
+        assert_eq!(*export_name, None);
+        assert_eq!(*pc, 2);
+        assert_eq!(*family, InstructionFamily::Arithmetic);
+        assert_eq!(error.kind(), ErrorKind::UserError);
+        assert!(!runtime.is_module_loaded(&module_id));
+
+        let runtime = Runtime::new();
+        runtime.set_instruction_policy(
+            &module_id,
+            InstructionPolicy::allow_all().deny(InstructionFamily::Call),
+        );
+        let error = runtime.load_module_set(&module_set).unwrap_err();
+        assert!(error.to_string().contains(r#"Function "run""#), "{error}");
+        Ok(())
+    }
+
+    #[test]
+    fn retained_values_survive_between_calls() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const make
+                            (fn
+                                (push host_list)
+                                (push 1)
+                                (push 2)
+                                (call 2 1)
+                                (return 1)))
+                        (import host_list "host" list)
+                        (export make)))
+            "#,
+        )?;
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("list", |mut ctxt| {
+            let mut stack = ctxt.stack();
+            let len = stack.len();
+            stack.make_list(len)?;
+            Ok(ctxt.return_with(1))
+        });
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+        let make = || -> anyhow::Result<()> {
+            top_level
+                .stack()
+                .push_import(&ImportSource::new(["test"], "make"))?;
+            top_level.call_function(0)?;
+            Ok(())
+        };
+
+        make()?;
+        let handle = top_level.stack().retain(StackIndex::FromTop(0))?;
+        top_level.stack().pop_n(1)?;
+        assert_eq!(runtime.num_retained(), 1);
+        // Allocate enough to trigger collections while the list is only
+        // reachable through the handle.
+        for _ in 0..100 {
+            make()?;
+            top_level.stack().pop_n(1)?;
+        }
+        top_level.stack().push_handle(&handle)?;
+        assert_eq!(
+            top_level.stack().get_loon_value(StackIndex::FromTop(0))?,
+            LoonValue::List(vec![1.into(), 2.into()])
+        );
+
+        let other = Runtime::new();
+        assert!(other.make_top_level().stack().push_handle(&handle).is_err());
+        assert!(runtime.release(handle));
+        assert_eq!(runtime.num_retained(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn host_values_stay_alive_and_can_be_inspected() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const noop
+                            (fn
+                                (return 0)))
+                        (export noop)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        let list = {
+            let mut stack = top_level.stack();
+            stack.push_int(1)?;
+            stack.push_string("two")?;
+            stack.make_list(2)?;
+            let list = stack.get_host_value(StackIndex::FromTop(0))?;
+            stack.pop_n(1)?;
+            list
+        };
+        // Calls allocate frames, which collects garbage while the list is
+        // only held by the host.
+        for _ in 0..10 {
+            top_level.call::<_, ()>(&ImportSource::new(["test"], "noop"), ())?;
+        }
+
+        assert_eq!(list.kind(), ValueKind::List);
+        assert_eq!(list.list_len()?, 2);
+        let first = list.list_get(0)?.expect("list has a first element");
+        assert_eq!(first.kind(), ValueKind::Integer);
+        assert_eq!(first.as_int()?, Integer::from(1));
+        let second = list.list_get(1)?.expect("list has a second element");
+        assert_eq!(second.as_str()?, "two");
+        assert!(matches!(second.as_int(), Err(RuntimeError::Type(_))));
+        assert!(list.list_get(2)?.is_none());
+        assert!(first.list_len().is_err());
+
+        top_level.stack().push_host_value(&list)?;
+        assert_eq!(
+            top_level.stack().get_loon_value(StackIndex::FromTop(0))?,
+            LoonValue::List(vec![1.into(), "two".into()])
+        );
+
+        let other = Runtime::new();
+        assert!(other
+            .make_top_level()
+            .stack()
+            .push_host_value(&list)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn native_functions_read_host_data() -> anyhow::Result<()> {
+        struct Config {
+            scale: i64,
+        }
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("scale", |mut ctxt| {
+            let scale = ctxt.host_data::<Config>().map_or(1, |config| config.scale);
+            let mut stack = ctxt.stack();
+            let value = stack.pop_value()?.as_compact_integer()?;
+            stack.push_int(value * scale)?;
+            Ok(ctxt.return_with(1))
+        });
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        let top_level = runtime.make_top_level();
+        let scale = |value| -> anyhow::Result<Integer> {
+            {
+                let mut stack = top_level.stack();
+                stack.push_int(value)?;
+                stack.push_import(&ImportSource::new(["host"], "scale"))?;
+            }
+            top_level.call_function(1)?;
+            Ok(top_level.stack().get_int(StackIndex::FromTop(0))?)
+        };
+
+        assert_eq!(scale(3)?, Integer::from(3));
+        assert!(runtime.set_host_data(Config { scale: 10 }).is_none());
+        assert_eq!(scale(3)?, Integer::from(30));
+        let previous = runtime.set_host_data(Config { scale: 2 });
+        assert_eq!(previous.map(|config| config.scale), Some(10));
+        assert_eq!(scale(3)?, Integer::from(6));
+        // Values of other types are stored separately.
+        runtime.set_host_data(5_i64);
+        assert_eq!(runtime.host_data::<i64>().as_deref(), Some(&5));
+        assert!(runtime.remove_host_data::<Config>().is_some());
+        assert_eq!(scale(3)?, Integer::from(3));
+        Ok(())
+    }
+
+    #[test]
+    fn value_comparisons_follow_numeric_rules() -> anyhow::Result<()> {
+        use std::cmp::Ordering::{Equal, Greater, Less};
+
+        let ops = ["eq", "ne", "lt", "le", "gt", "ge"];
+        let consts = ops
+            .iter()
+            .map(|op| format!("(const {op} (fn (cmp {op}) (return 1))) (export {op})"))
+            .collect::<String>();
+        let module_set = super::lat::from_str(&format!(r#"(module-set ("test" {consts}))"#))?;
+        let runtime = Runtime::new();
+        runtime.load_module_set(&module_set)?;
+        let top_level = runtime.make_top_level();
+
+        #[derive(Clone, Copy)]
+        enum Arg {
+            Int(i64),
+            Float(f64),
+            Str(&'static str),
+            Bool(bool),
+        }
+        let compare = |op: &str, left: Arg, right: Arg| -> crate::runtime::Result<bool> {
+            {
+                let mut stack = top_level.stack();
+                for arg in [left, right] {
+                    match arg {
+                        Arg::Int(i) => stack.push_int(i),
+                        Arg::Float(f) => stack.push_float(f),
+                        Arg::Str(s) => stack.push_string(s),
+                        Arg::Bool(b) => stack.push_bool(b),
+                    }?;
+                }
+                stack.push_import(&ImportSource::new(["test"], op))?;
+            }
+            top_level.call_function(2)?;
+            let result = top_level.stack().get_bool(StackIndex::FromTop(0));
+            result
+        };
+        // Checks all six comparisons against the expected ordering, where
+        // `None` means the values are unordered.
+        let check = |left: Arg, right: Arg, ordering: Option<std::cmp::Ordering>| {
+            let expected = [
+                ordering == Some(Equal),
+                ordering != Some(Equal),
+                ordering == Some(Less),
+                matches!(ordering, Some(Less | Equal)),
+                ordering == Some(Greater),
+                matches!(ordering, Some(Greater | Equal)),
+            ];
+            for (op, expected) in ops.iter().zip(expected) {
+                assert_eq!(compare(op, left, right).unwrap(), expected, "{op}");
+            }
+        };
+
+        check(Arg::Int(1), Arg::Int(2), Some(Less));
+        check(Arg::Int(i64::MIN), Arg::Int(i64::MAX), Some(Less));
+        check(Arg::Int(1), Arg::Float(1.0), Some(Equal));
+        check(Arg::Float(1.5), Arg::Int(1), Some(Greater));
+        check(Arg::Int(0), Arg::Float(-0.0), Some(Equal));
+        check(Arg::Float(-0.0), Arg::Float(0.0), Some(Equal));
+        check(Arg::Int(i64::MAX), Arg::Float(f64::INFINITY), Some(Less));
+        check(
+            Arg::Float(f64::NEG_INFINITY),
+            Arg::Int(i64::MIN),
+            Some(Less),
+        );
+        // Neither of these integers is exactly representable as a float.
+        check(
+            Arg::Int((1 << 53) + 1),
+            Arg::Float((1u64 << 53) as f64),
+            Some(Greater),
+        );
+        check(Arg::Int(i64::MAX), Arg::Float(2f64.powi(63)), Some(Less));
+        check(Arg::Int(1), Arg::Float(f64::NAN), None);
+        check(Arg::Float(f64::NAN), Arg::Float(f64::NAN), None);
+        check(Arg::Str("abc"), Arg::Str("abd"), Some(Less));
+        check(Arg::Str("b"), Arg::Str("abc"), Some(Greater));
+
+        // Equality of other values falls back to referential equality, but
+        // they cannot be ordered.
+        assert!(compare("eq", Arg::Bool(true), Arg::Bool(true))?);
+        assert!(compare("ne", Arg::Int(1), Arg::Str("1"))?);
+        assert!(matches!(
+            compare("lt", Arg::Bool(false), Arg::Bool(true)),
+            Err(RuntimeError::Type(_))
+        ));
+        assert!(matches!(
+            compare("lt", Arg::Int(1), Arg::Str("1")),
+            Err(RuntimeError::Type(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn apply_spreads_list_into_arguments() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (import sum3 "host" sum3)
+                        (const args (list 1 2 3))
+                        (const add
+                            (fn
+                                (add)
+                                (return 1)))
+                        (const run
+                            (fn
+                                (push add)
+                                (push_copy bot 0)
+                                (apply)
+                                (push sum3)
+                                (push args)
+                                (apply)
+                                (return 2)))
+                        (export run)))
+            "#,
+        )?;
+        let mut host = NativeModule::new(["host"]);
+        host.add_function("sum3", |mut ctxt| {
+            let mut stack = ctxt.stack();
+            let mut sum = Integer::from(0);
+            for _ in 0..3 {
+                sum = sum.add_owned(stack.get_int(StackIndex::FromTop(0))?);
+                stack.pop_n(1)?;
+            }
+            stack.push_int(sum)?;
+            Ok(ctxt.return_with(1))
+        });
+        let runtime = Runtime::new();
+        runtime.load_native_module(&host)?;
+        runtime.load_module_set(&module_set)?;
+
+        let top_level = runtime.make_top_level();
+        {
+            let mut stack = top_level.stack();
+            stack.push_int(4)?;
+            stack.push_int(5)?;
+            stack.make_list(2)?;
+            stack.push_import(&ImportSource::new(["test"], "run"))?;
+        }
+        assert_eq!(top_level.call_function(1)?, 2);
+        let stack = top_level.stack();
+        assert_eq!(Integer::from(6), stack.get_int(StackIndex::FromTop(0))?);
+        assert_eq!(Integer::from(9), stack.get_int(StackIndex::FromTop(1))?);
+        Ok(())
+    }
+
+    #[test]
+    fn functions_report_stable_ids() -> anyhow::Result<()> {
+        let module_set = super::lat::from_str(
+            r#"
+                (module-set
+                    ("test"
+                        (const run
+                            (fn
+                                (return 0)))
+                        (export run)))
+            "#,
+        )?;
+        let runtime = Runtime::new();
+        runtime.load_std_modules()?;
+        runtime.load_module_set(&module_set)?;
+
+        let top_level = runtime.make_top_level();
+        let mut stack = top_level.stack();
+        stack.push_import(&ImportSource::new(["test"], "run"))?;
+        stack.push_import(&ImportSource::new(["std", "fn"], "memoize"))?;
+        stack.push_native_function(|ctxt| Ok(ctxt.return_with(0)))?;
+        let run_id = stack.get_function_id(StackIndex::FromTop(2))?;
+        assert_eq!(
+            run_id,
+            FunctionId::Managed {
+                module_id: Some(ModuleId::new(["test"])),
+                const_index: ModuleConstIndex::new(0),
+            }
+        );
+        assert_eq!(
+            stack.get_function_id(StackIndex::FromTop(1))?.to_string(),
+            "std.fn:memoize"
+        );
+        assert!(matches!(
+            stack.get_function_id(StackIndex::FromTop(0))?,
+            FunctionId::Host(_)
+        ));
+        stack.pop_n(2)?;
+        drop(stack);
+
+        top_level.call_function(0)?;
+        let profiles = runtime.function_profiles();
+        assert!(profiles.iter().any(|p| p.function_id() == run_id));
+        Ok(())
+    }
+
+    #[test]
+    fn arithmetic_follows_the_numeric_tower() -> anyhow::Result<()> {
+        let eval =
+            |text: &str, a: LoonValue, b: LoonValue| eval_expression(text, &[("a", a), ("b", b)]);
+        let ratio = |numer: i64, denom: i64| {
+            LoonValue::Rational(Rational::new(numer.into(), denom.into()).unwrap())
+        };
+        let div = "(push a) (push b) (div)";
+        assert_eq!(eval(div, 1.into(), 3.into())?, ratio(1, 3));
+        assert_eq!(eval(div, 6.into(), 3.into())?, ratio(2, 1));
+        assert_eq!(eval(div, 1.into(), 4.0.into())?, 0.25.into());
+        assert!(matches!(
+            eval(div, 1.into(), 0.into()),
+            Err(EvalError::Runtime(RuntimeError::OperationPrecondition(_)))
+        ));
+        assert_eq!(
+            eval("(push a) (push b) (sub)", ratio(1, 2), 1.into())?,
+            ratio(-1, 2)
+        );
+        assert_eq!(
+            eval("(push a) (push b) (mul)", ratio(2, 3), 3.into())?,
+            ratio(2, 1)
+        );
+        assert_eq!(
+            eval("(push a) (push b) (add)", ratio(1, 2), 0.25.into())?,
+            0.75.into()
+        );
+        assert_eq!(
+            eval("(push a) (push b) (add)", 1.into(), 0.5.into())?,
+            1.5.into()
+        );
+        assert!(matches!(
+            eval("(push a) (push b) (mul)", ratio(1, 2), "x".into()),
+            Err(EvalError::Runtime(RuntimeError::Type(_)))
+        ));
+
+        let convert = |kind: &str, value: LoonValue| {
+            eval_expression(&format!("(push v) (to_number {kind})"), &[("v", value)])
+        };
+        assert_eq!(convert("integer", ratio(-7, 2))?, (-3).into());
+        assert_eq!(convert("integer", (-3.9).into())?, (-3).into());
+        assert_eq!(convert("rational", 0.5.into())?, ratio(1, 2));
+        assert_eq!(convert("rational", 2.into())?, ratio(2, 1));
+        assert_eq!(convert("float", ratio(1, 8))?, 0.125.into());
+        assert!(matches!(
+            convert("integer", f64::NAN.into()),
+            Err(EvalError::Runtime(RuntimeError::Conversion(_)))
+        ));
+
+        assert_eq!(
+            eval("(push a) (push b) (cmp eq)", ratio(1, 2), 0.5.into())?,
+            true.into()
+        );
+        assert_eq!(
+            eval("(push a) (push b) (cmp lt)", ratio(1, 3), 0.25.into())?,
+            false.into()
+        );
+        assert_eq!(
+            eval("(push a) (push b) (cmp gt)", 1.into(), ratio(2, 3))?,
+            true.into()
+        );
+        Ok(())
+    }
+
+    /// Loads a module whose only export runs `instructions`, and calls it with
+    /// `num_args` integer arguments. Returns false if anything panicked.
+    fn runs_without_panic(instructions: Vec<Instruction>, num_args: u32) -> bool {
+        std::panic::catch_unwind(|| {
+            let const_table = vec![
+                ConstValue::Integer(1.into()),
+                ConstValue::Bool(true),
+                ConstValue::List(vec![ConstIndex::ModuleConst(ModuleConstIndex::new(0))]),
+                ConstValue::Function(ConstFunction::new(
+                    (0..4)
+                        .map(|i| ConstIndex::ModuleConst(ModuleConstIndex::new(i)))
+                        .collect(),
+                    InstructionList::new(instructions),
+                )),
+            ];
+            let Ok(module) = ConstModule::new(
+                ModuleId::new(["fuzz"]),
+                const_table,
+                vec![],
+                [("run".into(), ModuleConstIndex::new(3))]
+                    .into_iter()
+                    .collect(),
+                None,
+                2,
+            ) else {
+                return;
+            };
+            let runtime = Runtime::new();
+            runtime.set_fuel(Some(10_000));
+            runtime.set_max_frame_stack_size(Some(1_000));
+            if runtime.load_module(&module).is_err() {
+                return;
+            }
+            let top_level = runtime.make_top_level();
+            {
+                let mut stack = top_level.stack();
+                for i in 0..num_args {
+                    stack.push_int(i64::from(i)).unwrap();
+                }
+                if stack
+                    .push_import(&ImportSource::new(["fuzz"], "run"))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            let _ = top_level.call_function(num_args);
+        })
+        .is_ok()
+    }
+
+    #[test]
+    fn adversarial_modules_do_not_panic() {
+        let corpus = vec![
+            vec![],
+            vec![Instruction::Pop(5), Instruction::Return(0)],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(100)),
+                Instruction::Return(1),
+            ],
+            vec![Instruction::PushCopy(StackIndex::FromTop(9))],
+            vec![
+                Instruction::PushGlobal(GlobalIndex::new(0)),
+                Instruction::Return(1),
+            ],
+            vec![
+                Instruction::PushGlobal(GlobalIndex::new(7)),
+                Instruction::Return(1),
+            ],
+            vec![Instruction::Branch(BranchTarget::new(50))],
+            vec![Instruction::Return(3)],
+            vec![Instruction::Add],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::Compare(CompareOp::Lt),
+                Instruction::Return(1),
+            ],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::Call(CallInstruction {
+                    num_args: 0,
+                    num_returns: 0,
+                }),
+            ],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(3)),
+                Instruction::TailCall(0),
+            ],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::CallDynamic,
+            ],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::ReturnDynamic,
+            ],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(2)),
+                Instruction::PushConst(LocalConstIndex::new(2)),
+                Instruction::ListGet,
+            ],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(1)),
+                Instruction::BindFront(4),
+            ],
+            vec![
+                Instruction::PushConst(LocalConstIndex::new(0)),
+                Instruction::Apply,
+            ],
+            vec![Instruction::CellGet],
+        ];
+        for instructions in corpus {
+            let description = format!("{instructions:?}");
+            assert!(
+                runs_without_panic(instructions, 0),
+                "panicked on {description}"
+            );
+        }
+    }
+
+    /// A fixed xorshift generator, so every run checks the same inputs.
+    struct Xorshift(u64);
+
+    impl Xorshift {
+        fn new() -> Self {
+            Xorshift(0x2545_f491_4f6c_dd1d)
+        }
+
+        fn next(&mut self, bound: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % bound
+        }
+    }
+
+    fn random_instruction(rng: &mut Xorshift) -> Instruction {
+        let operand = rng.next(5) as u32;
+        match rng.next(51) {
+            0 => Instruction::PushConst(LocalConstIndex::new(operand)),
+            1 => Instruction::PushCopy(StackIndex::FromTop(operand)),
+            2 => Instruction::PushCopy(StackIndex::FromBottom(operand)),
+            3 => Instruction::PushGlobal(GlobalIndex::new(operand)),
+            4 => Instruction::PopGlobal(GlobalIndex::new(operand)),
+            5 => Instruction::WriteStack(StackIndex::FromTop(operand)),
+            6 => Instruction::Pop(operand),
+            7 => Instruction::Add,
+            8 => Instruction::BoolAnd,
+            9 => Instruction::BoolNot,
+            10 => Instruction::ListNew,
+            11 => Instruction::ListAppend,
+            12 => Instruction::ListLen,
+            13 => Instruction::ListGet,
+            14 => Instruction::ListSet,
+            15 => Instruction::ListGetRel,
+            16 => Instruction::ListSlice,
+            17 => Instruction::CellNew,
+            18 => Instruction::CellSet,
+            19 => Instruction::Compare(
+                [
+                    CompareOp::RefEq,
+                    CompareOp::Eq,
+                    CompareOp::Lt,
+                    CompareOp::Ge,
+                ][operand as usize % 4],
+            ),
+            20 => Instruction::IsNull,
+            21 => Instruction::Branch(BranchTarget::new(operand * 3)),
+            22 => Instruction::BranchIf(BranchTarget::new(operand * 3)),
+            23 => Instruction::Call(CallInstruction {
+                num_args: operand,
+                num_returns: operand,
+            }),
+            24 => Instruction::CallDynamic,
+            25 => Instruction::Apply,
+            26 => Instruction::Return(operand),
+            27 => Instruction::ReturnDynamic,
+            28 => Instruction::TailCall(operand),
+            29 => Instruction::BindFront(operand),
+            30 => Instruction::IdentityHash,
+            31 => Instruction::ToBool(Truthiness::Empty),
+            32 => Instruction::BranchIfTruthy(
+                BranchTarget::new(operand * 3),
+                Truthiness::NullAndFalse,
+            ),
+            33 => Instruction::Sub,
+            34 => Instruction::Mul,
+            35 => Instruction::Div,
+            36 => Instruction::ToNumber(
+                [
+                    NumericKind::Integer,
+                    NumericKind::Rational,
+                    NumericKind::Float,
+                ][operand as usize % 3],
+            ),
+            37 => Instruction::CmpBranch(CompareOp::Lt, BranchTarget::new(operand * 3)),
+            38 => Instruction::StrLenChars,
+            39 => Instruction::StrSliceBytes,
+            40 => Instruction::WeakNew,
+            41 => Instruction::WeakGet,
+            42 => Instruction::RecordNew(LocalConstIndex::new(operand)),
+            43 => Instruction::RecordGet(LocalConstIndex::new(operand)),
+            44 => Instruction::TagNew(LocalConstIndex::new(operand)),
+            45 => Instruction::TagWrap,
+            46 => Instruction::TagOf,
+            47 => Instruction::Untag,
+            48 => Instruction::CallProtected(
+                CallInstruction {
+                    num_args: operand,
+                    num_returns: 1,
+                },
+                BranchTarget::new(operand * 3),
+            ),
+            49 => Instruction::Raise,
+            _ => Instruction::CellGet,
+        }
+    }
+
+    fn random_instructions(rng: &mut Xorshift) -> Vec<Instruction> {
+        let len = rng.next(12);
+        (0..len).map(|_| random_instruction(rng)).collect()
+    }
+
+    #[test]
+    fn random_functions_do_not_panic() {
+        let mut rng = Xorshift::new();
+        for _ in 0..500 {
+            let instructions = random_instructions(&mut rng);
+            let description = format!("{instructions:?}");
+            let num_args = rng.next(3) as u32;
+            assert!(
+                runs_without_panic(instructions, num_args),
+                "panicked on {description} with {num_args} arguments"
+            );
+        }
+    }
+
+    /// Returns a reference into a table of `table_len` constants with one
+    /// import, which is occasionally out of range.
+    fn random_const_index(rng: &mut Xorshift, table_len: u32) -> ConstIndex {
+        if rng.next(4) == 0 {
+            ConstIndex::ModuleImport(ImportIndex::new(rng.next(2) as u32))
+        } else {
+            ConstIndex::ModuleConst(ModuleConstIndex::new(
+                rng.next(u64::from(table_len) + 1) as u32
+            ))
+        }
+    }
+
+    fn random_const_table(rng: &mut Xorshift) -> Vec<ConstValue> {
+        let table_len = rng.next(5) as u32 + 1;
+        (0..table_len)
+            .map(|_| match rng.next(4) {
+                0 => ConstValue::Integer((rng.next(3) as i64).into()),
+                1 => ConstValue::Bool(rng.next(2) == 0),
+                2 => ConstValue::List(
+                    (0..rng.next(3))
+                        .map(|_| random_const_index(rng, table_len))
+                        .collect(),
+                ),
+                _ => ConstValue::Function(ConstFunction::new(
+                    (0..rng.next(4))
+                        .map(|_| random_const_index(rng, table_len))
+                        .collect(),
+                    InstructionList::new(random_instructions(rng)),
+                )),
+            })
+            .collect()
+    }
+
+    /// Whether every reference in `table` names an existing constant, import
+    /// or global, independently of the validator.
+    fn references_are_in_range(table: &[ConstValue], num_imports: u32, num_globals: u32) -> bool {
+        let in_range = |index: &ConstIndex| match index {
+            ConstIndex::ModuleConst(i) => (i.index() as usize) < table.len(),
+            ConstIndex::ModuleImport(i) => i.index() < num_imports,
+        };
+        let operand_in_range = |function: &ConstFunction, inst: &Instruction| match inst {
+            Instruction::PushConst(i)
+            | Instruction::RecordNew(i)
+            | Instruction::RecordGet(i)
+            | Instruction::RecordSet(i)
+            | Instruction::TagNew(i) => (i.index() as usize) < function.module_constants().len(),
+            Instruction::PushGlobal(i) | Instruction::PopGlobal(i) => i.index() < num_globals,
+            _ => inst.branch_target().map_or(true, |target| {
+                (target.target_index() as usize) < function.instructions().instructions().len()
+            }),
+        };
+        table.iter().all(|value| match value {
+            ConstValue::List(items) => items.iter().all(in_range),
+            ConstValue::Function(function) => {
+                function.module_constants().iter().all(in_range)
+                    && function
+                        .instructions()
+                        .instructions()
+                        .iter()
+                        .all(|inst| operand_in_range(function, inst))
+            }
+            _ => true,
+        })
+    }
+
+    #[test]
+    fn validator_accepts_only_executable_modules() {
+        let mut rng = Xorshift::new();
+        for _ in 0..500 {
+            let const_table = random_const_table(&mut rng);
+            let description = format!("{const_table:?}");
+            let expect_valid = references_are_in_range(&const_table, 1, 2);
+            let run_index = ModuleConstIndex::new(rng.next(const_table.len() as u64) as u32);
+            let policy = InstructionFamily::ALL
+                .into_iter()
+                .filter(|_| rng.next(8) == 0)
+                .fold(InstructionPolicy::allow_all(), InstructionPolicy::deny);
+            let num_args = rng.next(3) as u32;
+
+            let result = ConstModule::new(
+                ModuleId::new(["fuzz"]),
+                const_table,
+                vec![ImportSource::new(["std", "fn"], "memoize")],
+                [("run".into(), run_index)].into_iter().collect(),
+                None,
+                2,
+            );
+            let module = match result {
+                Ok(module) => module,
+                Err(error) => {
+                    assert!(!expect_valid, "rejected valid table {description}: {error}");
+                    continue;
+                }
+            };
+            assert!(expect_valid, "accepted invalid table {description}");
+
+            let denied = module.validate_instructions(&policy).is_err();
+            let ran = std::panic::catch_unwind(|| {
+                let runtime = Runtime::new();
+                runtime.load_std_modules().unwrap();
+                runtime.set_instruction_policy(module.id(), policy);
+                runtime.set_fuel(Some(10_000));
+                runtime.set_max_frame_stack_size(Some(1_000));
+                let loaded = runtime.load_module(&module).is_ok();
+                assert!(!(denied && loaded), "loaded a module its policy denies");
+                let top_level = runtime.make_top_level();
+                let mut stack = top_level.stack();
+                for i in 0..num_args {
+                    stack.push_int(i64::from(i)).unwrap();
+                }
+                let found = stack
+                    .push_import(&ImportSource::new(["fuzz"], "run"))
+                    .is_ok();
+                drop(stack);
+                assert!(loaded || !found, "rejected module is reachable");
+                if found {
+                    let _ = top_level.call_function(num_args);
+                }
+            });
+            assert!(ran.is_ok(), "panicked on {description} with {policy:?}");
+        }
+    }
 }
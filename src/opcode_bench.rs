@@ -0,0 +1,286 @@
+//! A microbenchmark harness that times each opcode in isolation.
+//!
+//! Each benchmark runs a loop whose body exercises a single kind of
+//! instruction, along with the pushes and pops needed to leave the stack as
+//! it was. The time of a loop with an empty body is measured first and
+//! subtracted, so the report shows the cost of the body alone.
+//!
+//! The report is only meaningful for optimized builds:
+//!
+//! ```text
+//! cargo test --release opcode_report -- --ignored --nocapture
+//! ```
+//!
+//! Running it again with `--features soa-local-stack` compares the two local
+//! stack layouts. The `stack_*` benchmarks mix scalars with heap values, which
+//! the layouts store differently.
+
+use std::time::{Duration, Instant};
+
+use crate::{
+    binary::{
+        instructions::{CallInstruction, CompareOp, NumericKind, StackIndex, Truthiness},
+        modules::{ImportSource, ModuleId, ModuleMemberId},
+        ConstModule, FunctionBuilder, ModuleBuilder, ValueRef,
+    },
+    runtime::Runtime,
+};
+
+/// Values that benchmark bodies may refer to.
+struct Fixture {
+    /// A function that takes no arguments and returns nothing.
+    callee: ValueRef,
+    truth: ValueRef,
+}
+
+// The loop function keeps these values at the bottom of its stack.
+const COUNTER: StackIndex = StackIndex::FromBottom(0);
+const LIST: StackIndex = StackIndex::FromBottom(1);
+const CELL: StackIndex = StackIndex::FromBottom(2);
+const SCRATCH: StackIndex = StackIndex::FromBottom(3);
+
+struct OpcodeBench {
+    name: &'static str,
+    body: fn(&Fixture, &mut FunctionBuilder) -> anyhow::Result<()>,
+}
+
+macro_rules! bench {
+    ($name:literal, |$fixture:pat_param, $f:ident| $body:expr) => {
+        OpcodeBench {
+            name: $name,
+            body: |$fixture, $f| {
+                let _ = $body;
+                Ok(())
+            },
+        }
+    };
+}
+
+const BASELINE: OpcodeBench = bench!("baseline", |_, _f| ());
+
+const BENCHES: &[OpcodeBench] = &[
+    bench!("push_const", |_, f| f.push_int(1).pop(1)),
+    bench!("push_copy", |_, f| f.push_copy(SCRATCH).pop(1)),
+    bench!("stack_copy_heap", |_, f| f.push_copy(LIST).pop(1)),
+    bench!("stack_write_heap", |_, f| f
+        .push_copy(LIST)
+        .write_stack(SCRATCH)
+        .push_int(0)
+        .write_stack(SCRATCH)),
+    bench!("stack_check_mixed", |_, f| f
+        .push_copy(LIST)
+        .push_int(1)
+        .is_null()
+        .pop(1)
+        .is_null()
+        .pop(1)),
+    bench!("write_stack", |_, f| f.push_int(1).write_stack(SCRATCH)),
+    bench!("add", |_, f| f.push_int(1).push_int(2).add().pop(1)),
+    bench!("div", |_, f| f.push_int(1).push_int(3).div().pop(1)),
+    bench!("to_number", |_, f| f
+        .push_int(1)
+        .to_number(NumericKind::Float)
+        .pop(1)),
+    bench!("bool_and", |x, f| f
+        .push_value(&x.truth)?
+        .push_value(&x.truth)?
+        .bool_and()
+        .pop(1)),
+    bench!("bool_not", |x, f| f.push_value(&x.truth)?.bool_not().pop(1)),
+    bench!("cmp_ref_eq", |_, f| f
+        .push_int(1)
+        .push_int(1)
+        .compare(CompareOp::RefEq)
+        .pop(1)),
+    bench!("is_null", |_, f| f.push_int(1).is_null().pop(1)),
+    bench!("to_bool", |_, f| f
+        .push_int(1)
+        .to_bool(Truthiness::Empty)
+        .pop(1)),
+    bench!("identity_hash", |_, f| f
+        .push_copy(LIST)
+        .identity_hash()
+        .pop(1)),
+    bench!("list_new", |_, f| f.list_new().pop(1)),
+    bench!("list_append", |_, f| f.push_int(1).list_new().list_append()),
+    bench!("list_len", |_, f| f.push_copy(LIST).list_len().pop(1)),
+    bench!("list_get", |_, f| f
+        .push_int(1)
+        .push_copy(LIST)
+        .list_get()
+        .pop(1)),
+    bench!("list_set", |_, f| f
+        .push_int(7)
+        .push_int(1)
+        .push_copy(LIST)
+        .list_set()),
+    bench!("list_get_rel", |_, f| f
+        .push_int(-1)
+        .push_copy(LIST)
+        .list_get_rel()
+        .pop(1)),
+    bench!("list_slice", |_, f| f
+        .push_int(-1)
+        .push_int(1)
+        .push_copy(LIST)
+        .list_slice()
+        .pop(1)),
+    bench!("cell_new", |_, f| f.push_int(1).cell_new().pop(1)),
+    bench!("cell_get", |_, f| f.push_copy(CELL).cell_get().pop(1)),
+    bench!("cell_set", |_, f| f.push_int(1).push_copy(CELL).cell_set()),
+    bench!("weak_new", |_, f| f.push_copy(CELL).weak_new().pop(1)),
+    bench!("record_new", |_, f| f
+        .push_int(1)
+        .push_int(2)
+        .record_new(["x", "y"])
+        .pop(1)),
+    bench!("tag_new", |_, f| f.tag_new("point").pop(1)),
+    bench!("branch", |_, f| f
+        .branch("next")
+        .define_branch_target("next")),
+    bench!("branch_if", |_, f| f
+        .push_int(0)
+        .push_int(1)
+        .compare(CompareOp::RefEq)
+        .branch_if("next")
+        .define_branch_target("next")),
+    bench!("cmp_branch", |_, f| f
+        .push_int(0)
+        .push_int(1)
+        .cmp_branch(CompareOp::RefEq, "next")
+        .define_branch_target("next")),
+    bench!("call", |x, f| f.push_value(&x.callee)?.call(
+        CallInstruction {
+            num_args: 0,
+            num_returns: 0,
+        }
+    )),
+    bench!("call_dynamic", |x, f| f
+        .push_value(&x.callee)?
+        .push_int(0)
+        .call_dynamic()),
+    bench!("apply", |x, f| f.push_value(&x.callee)?.list_new().apply()),
+    bench!("call_protected", |x, f| f
+        .push_value(&x.callee)?
+        .call_protected(
+            CallInstruction {
+                num_args: 0,
+                num_returns: 0,
+            },
+            "caught"
+        )
+        .define_branch_target("caught")),
+    bench!("bind_front", |x, f| f
+        .push_value(&x.callee)?
+        .push_int(1)
+        .bind_front(1)
+        .pop(1)),
+];
+
+/// Builds a module exporting `run`, which takes an iteration count and runs
+/// the body of `bench` that many times.
+fn build_module(bench: &OpcodeBench) -> anyhow::Result<ConstModule> {
+    let builder = ModuleBuilder::new(ModuleId::new(["bench"]));
+    let (callee, mut callee_builder) = builder.new_function();
+    callee_builder.return_(0);
+    callee_builder.build()?;
+    let fixture = Fixture {
+        callee,
+        truth: builder.new_bool(true),
+    };
+
+    let (run, mut f) = builder.new_function();
+    f.list_new();
+    for i in 0..3 {
+        f.push_int(i).push_copy(LIST).list_append();
+    }
+    f.push_int(1).cell_new().push_int(0);
+    f.define_branch_target("loop")
+        .push_copy(COUNTER)
+        .push_int(0)
+        .compare(CompareOp::RefEq)
+        .branch_if("done");
+    (bench.body)(&fixture, &mut f)?;
+    f.push_copy(COUNTER)
+        .push_int(-1)
+        .add()
+        .write_stack(COUNTER)
+        .branch("loop")
+        .define_branch_target("done")
+        .return_(0);
+    f.build()?;
+    run.export(ModuleMemberId::new("run"))?;
+    Ok(builder.into_const_module()?)
+}
+
+/// Runs the loop for `bench` once, returning the elapsed time.
+fn time_bench(
+    runtime: &Runtime,
+    module: &ConstModule,
+    iterations: u32,
+) -> anyhow::Result<Duration> {
+    runtime.load_module(module)?;
+    let top_level = runtime.make_top_level();
+    {
+        let mut stack = top_level.stack();
+        stack.push_int(i64::from(iterations))?;
+        stack.push_import(&ImportSource::new(["bench"], "run"))?;
+    }
+    let start = Instant::now();
+    top_level.call_function(1)?;
+    Ok(start.elapsed())
+}
+
+/// Returns the best time of a few runs of `bench`.
+fn best_time(bench: &OpcodeBench, iterations: u32) -> anyhow::Result<Duration> {
+    let module = build_module(bench)?;
+    let mut best = Duration::MAX;
+    for _ in 0..3 {
+        best = best.min(time_bench(&Runtime::new(), &module, iterations)?);
+    }
+    Ok(best)
+}
+
+/// Times every benchmark, and returns a report with the cost of each body
+/// per iteration.
+fn report(iterations: u32) -> anyhow::Result<String> {
+    let baseline = best_time(&BASELINE, iterations)?;
+    let mut report = format!(
+        "{:<16} {:>10.2} ns/iter\n",
+        BASELINE.name,
+        baseline.as_nanos() as f64 / f64::from(iterations)
+    );
+    for bench in BENCHES {
+        let time = best_time(bench, iterations)?.saturating_sub(baseline);
+        report.push_str(&format!(
+            "{:<16} {:>10.2} ns/iter\n",
+            bench.name,
+            time.as_nanos() as f64 / f64::from(iterations)
+        ));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmark_bodies_are_stack_neutral() -> anyhow::Result<()> {
+        for bench in std::iter::once(&BASELINE).chain(BENCHES) {
+            let runtime = Runtime::new();
+            // A body that leaks values would overflow the limit.
+            runtime.set_max_frame_stack_size(Some(16));
+            time_bench(&runtime, &build_module(bench)?, 100)
+                .map_err(|e| anyhow::anyhow!("{}: {e}", bench.name))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "Only meaningful in release builds. Run with --ignored --nocapture."]
+    fn opcode_report() -> anyhow::Result<()> {
+        println!("{}", report(1_000_000)?);
+        Ok(())
+    }
+}
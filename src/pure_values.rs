@@ -1,8 +1,8 @@
 //! Values that can be shared between the binary and runtime.
 
-use std::rc::Rc;
+use std::{cmp::Ordering, rc::Rc};
 
-use num_traits::ToPrimitive;
+use num_traits::{FromPrimitive, Signed, ToPrimitive, Zero};
 
 #[derive(Clone, Debug)]
 enum IntegerInner {
@@ -33,6 +33,53 @@ impl Integer {
         }
     }
 
+    fn to_big_integer(&self) -> num_bigint::BigInt {
+        match &self.0 {
+            IntegerInner::Compact(i) => num_bigint::BigInt::from(*i),
+            IntegerInner::Big(i) => (**i).clone(),
+        }
+    }
+
+    /// Compares the integer with a float by their exact mathematical values.
+    ///
+    /// The integer is never rounded to a float, so integers that are not
+    /// exactly representable as an `f64` (such as `2^53 + 1`) still compare
+    /// unequal to the nearest float. Infinities are greater or less than every
+    /// integer, both zeros are equal to `0`, and NaN is unordered with
+    /// respect to every integer, so this returns `None` for it.
+    #[must_use]
+    pub fn cmp_float(&self, other: &Float) -> Option<Ordering> {
+        let f = other.value();
+        if f.is_nan() {
+            return None;
+        }
+        if f.is_infinite() {
+            return Some(if f > 0.0 {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            });
+        }
+        let whole = f.trunc();
+        // Every finite float with no fractional part is an integer that
+        // converts exactly.
+        let whole_ordering = match (self.to_compact_integer(), whole.to_i64()) {
+            (Some(i), Some(w)) => i.cmp(&w),
+            _ => self
+                .to_big_integer()
+                .cmp(&num_bigint::BigInt::from_f64(whole).expect("finite floats convert exactly")),
+        };
+        // If the whole parts are equal, the fractional part of the float
+        // decides.
+        Some(whole_ordering.then(if f > whole {
+            Ordering::Less
+        } else if f < whole {
+            Ordering::Greater
+        } else {
+            Ordering::Equal
+        }))
+    }
+
     #[must_use]
     pub fn add_owned(self, other: Self) -> Self {
         match (self.0, other.0) {
@@ -77,6 +124,45 @@ impl Integer {
             }
         }
     }
+
+    #[must_use]
+    pub fn sub_owned(self, other: Self) -> Self {
+        match (self.to_compact_integer(), other.to_compact_integer()) {
+            (Some(i1), Some(i2)) if i1.checked_sub(i2).is_some() => Integer::from(i1 - i2),
+            _ => Integer::from(self.to_big_integer() - other.to_big_integer()),
+        }
+    }
+
+    #[must_use]
+    pub fn mul_owned(self, other: Self) -> Self {
+        match (self.to_compact_integer(), other.to_compact_integer()) {
+            (Some(i1), Some(i2)) if i1.checked_mul(i2).is_some() => Integer::from(i1 * i2),
+            _ => Integer::from(self.to_big_integer() * other.to_big_integer()),
+        }
+    }
+
+    /// Returns the nearest float to the integer. Integers beyond the range
+    /// of floats become infinities.
+    #[must_use]
+    pub fn to_float(&self) -> Float {
+        match &self.0 {
+            IntegerInner::Compact(i) => Float(*i as f64),
+            IntegerInner::Big(_) => Rational::from(self.clone()).to_float(),
+        }
+    }
+
+    /// Returns the integer in little-endian two's complement, in as few
+    /// bytes as hold it.
+    #[must_use]
+    pub fn to_signed_bytes_le(&self) -> Vec<u8> {
+        self.to_big_integer().to_signed_bytes_le()
+    }
+
+    /// Reads an integer written by [`Integer::to_signed_bytes_le`].
+    #[must_use]
+    pub fn from_signed_bytes_le(bytes: &[u8]) -> Self {
+        Integer::from(num_bigint::BigInt::from_signed_bytes_le(bytes))
+    }
 }
 
 impl PartialEq for Integer {
@@ -91,6 +177,36 @@ impl PartialEq for Integer {
     }
 }
 
+impl Eq for Integer {}
+
+impl PartialOrd for Integer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Integer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        if let (Some(i1), Some(i2)) = (self.to_compact_integer(), other.to_compact_integer()) {
+            i1.cmp(&i2)
+        } else {
+            self.to_big_integer().cmp(&other.to_big_integer())
+        }
+    }
+}
+
+impl std::hash::Hash for Integer {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        // Hash compact values the same regardless of representation, to stay
+        // consistent with `PartialEq`.
+        if let Some(i) = self.to_compact_integer() {
+            i.hash(state);
+        } else if let IntegerInner::Big(i) = &self.0 {
+            i.hash(state);
+        }
+    }
+}
+
 impl From<i64> for Integer {
     fn from(i: i64) -> Self {
         Integer(IntegerInner::Compact(i))
@@ -107,6 +223,15 @@ impl From<num_bigint::BigInt> for Integer {
     }
 }
 
+impl std::fmt::Display for Integer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            IntegerInner::Compact(i) => write!(f, "{i}"),
+            IntegerInner::Big(i) => write!(f, "{i}"),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
 pub struct Float(f64);
 
@@ -121,10 +246,405 @@ impl Float {
         self.0
     }
 
+    #[must_use]
+    pub fn from_bits(bits: u64) -> Self {
+        Float(f64::from_bits(bits))
+    }
+
+    #[must_use]
+    pub fn to_bits(&self) -> u64 {
+        self.0.to_bits()
+    }
+
     #[must_use]
     pub fn add_owned(self, other: Self) -> Self {
         Float(self.0 + other.0)
     }
+
+    /// Parses a hexadecimal float literal, such as `0x1.8p3` or `-0x1p-1074`.
+    ///
+    /// The binary exponent is required. Values that are not exactly
+    /// representable are rounded to nearest, ties to even, so every finite
+    /// `f64` can be written exactly. Returns `None` if the text is not a hex
+    /// float literal.
+    #[must_use]
+    pub fn from_hex_literal(text: &str) -> Option<Self> {
+        parse_hex_float(text).map(Float)
+    }
+
+    /// Formats a finite value as a hexadecimal float literal that
+    /// [`Float::from_hex_literal`] parses back to the same bits. Returns `None`
+    /// for infinities and NaNs.
+    #[must_use]
+    pub fn to_hex_literal(&self) -> Option<String> {
+        format_hex_float(self.0)
+    }
+}
+
+/// An exact fraction of two integers, such as `1/3`, backed by
+/// [`num_rational::BigRational`].
+///
+/// Rationals are always kept in lowest terms with a positive denominator, so
+/// equal values have equal numerators and denominators. Integral values stay
+/// rationals, such as `2/1`, until they are explicitly converted.
+///
+/// There is no constant or lat literal for rationals, as one would need a new
+/// kind of constant in the module encoding. Code makes them by dividing
+/// integers, or with `to_number`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Rational(num_rational::BigRational);
+
+impl Rational {
+    /// Returns `numer / denom` in lowest terms, or `None` if `denom` is zero.
+    #[must_use]
+    pub fn new(numer: Integer, denom: Integer) -> Option<Self> {
+        let denom = denom.to_big_integer();
+        if denom.is_zero() {
+            return None;
+        }
+        Some(Rational(num_rational::BigRational::new(
+            numer.to_big_integer(),
+            denom,
+        )))
+    }
+
+    /// Returns the exact value of a finite float, or `None` for infinities
+    /// and NaN.
+    #[must_use]
+    pub fn from_float(value: &Float) -> Option<Self> {
+        num_rational::BigRational::from_float(value.value()).map(Rational)
+    }
+
+    #[must_use]
+    pub fn numer(&self) -> Integer {
+        Integer::from(self.0.numer().clone())
+    }
+
+    #[must_use]
+    pub fn denom(&self) -> Integer {
+        Integer::from(self.0.denom().clone())
+    }
+
+    #[must_use]
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// Returns the integer part of the value, rounding toward zero.
+    #[must_use]
+    pub fn trunc(&self) -> Integer {
+        Integer::from(self.0.trunc().to_integer())
+    }
+
+    /// Returns the nearest float to the value, rounding ties to even. Values
+    /// beyond the range of floats become infinities.
+    #[must_use]
+    pub fn to_float(&self) -> Float {
+        if self.0.is_zero() {
+            return Float(0.0);
+        }
+        let numer = self.0.numer().magnitude();
+        let denom = self.0.denom().magnitude();
+        // Scale the numerator so the quotient has 120 or 121 bits, which is
+        // enough to round correctly, and fits in a `u128`.
+        let shift = 120 - (numer.bits() as i64 - denom.bits() as i64);
+        let (numer, denom) = if shift >= 0 {
+            (numer << shift as usize, denom.clone())
+        } else {
+            (numer.clone(), denom << (-shift) as usize)
+        };
+        let quotient = (&numer / &denom)
+            .to_u128()
+            .expect("quotient has at most 121 bits");
+        let sticky = !(numer % denom).is_zero();
+        let value = compose_f64(quotient, sticky, -shift);
+        Float(if self.0.is_negative() { -value } else { value })
+    }
+
+    #[must_use]
+    pub fn add(&self, other: &Self) -> Self {
+        Rational(&self.0 + &other.0)
+    }
+
+    #[must_use]
+    pub fn sub(&self, other: &Self) -> Self {
+        Rational(&self.0 - &other.0)
+    }
+
+    #[must_use]
+    pub fn mul(&self, other: &Self) -> Self {
+        Rational(&self.0 * &other.0)
+    }
+
+    /// Returns `self / other`, or `None` if `other` is zero.
+    #[must_use]
+    pub fn checked_div(&self, other: &Self) -> Option<Self> {
+        if other.is_zero() {
+            return None;
+        }
+        Some(Rational(&self.0 / &other.0))
+    }
+
+    /// Compares the rational with a float by their exact mathematical values,
+    /// as with [`Integer::cmp_float`].
+    #[must_use]
+    pub fn cmp_float(&self, other: &Float) -> Option<Ordering> {
+        let f = other.value();
+        if f.is_nan() {
+            None
+        } else if f.is_infinite() {
+            Some(if f > 0.0 {
+                Ordering::Less
+            } else {
+                Ordering::Greater
+            })
+        } else {
+            Some(self.cmp(&Rational::from_float(other).expect("finite floats convert exactly")))
+        }
+    }
+}
+
+impl From<Integer> for Rational {
+    fn from(i: Integer) -> Self {
+        Rational(num_rational::BigRational::from_integer(i.to_big_integer()))
+    }
+}
+
+impl std::fmt::Display for Rational {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Written out in full, as `Ratio` writes integral values without a
+        // denominator.
+        write!(f, "{}/{}", self.0.numer(), self.0.denom())
+    }
+}
+
+/// A plain data value that can be passed into and out of the runtime without
+/// a reference to it, such as the bindings and result of
+/// [`eval_expression`](crate::eval_expression).
+///
+/// Unlike runtime values, lists are owned trees, so they cannot share items
+/// or contain themselves.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoonValue {
+    Null,
+    Bool(bool),
+    Integer(Integer),
+    Float(Float),
+    Rational(Rational),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<LoonValue>),
+}
+
+impl From<bool> for LoonValue {
+    fn from(b: bool) -> Self {
+        LoonValue::Bool(b)
+    }
+}
+
+impl From<i64> for LoonValue {
+    fn from(i: i64) -> Self {
+        LoonValue::Integer(Integer::from(i))
+    }
+}
+
+impl From<Integer> for LoonValue {
+    fn from(i: Integer) -> Self {
+        LoonValue::Integer(i)
+    }
+}
+
+impl From<f64> for LoonValue {
+    fn from(f: f64) -> Self {
+        LoonValue::Float(Float::new(f))
+    }
+}
+
+impl From<Float> for LoonValue {
+    fn from(f: Float) -> Self {
+        LoonValue::Float(f)
+    }
+}
+
+impl From<Rational> for LoonValue {
+    fn from(r: Rational) -> Self {
+        LoonValue::Rational(r)
+    }
+}
+
+impl From<&str> for LoonValue {
+    fn from(s: &str) -> Self {
+        LoonValue::String(s.to_string())
+    }
+}
+
+impl From<String> for LoonValue {
+    fn from(s: String) -> Self {
+        LoonValue::String(s)
+    }
+}
+
+impl From<Vec<LoonValue>> for LoonValue {
+    fn from(items: Vec<LoonValue>) -> Self {
+        LoonValue::List(items)
+    }
+}
+
+/// The maximum number of significant hex digits kept while parsing. Any
+/// further digits only contribute to rounding.
+const MAX_HEX_DIGITS: usize = 30;
+
+fn parse_hex_float(text: &str) -> Option<f64> {
+    let (negative, rest) = match text.as_bytes().first()? {
+        b'-' => (true, &text[1..]),
+        b'+' => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let rest = rest
+        .strip_prefix("0x")
+        .or_else(|| rest.strip_prefix("0X"))?;
+    let (mantissa, exponent) = rest.split_once(['p', 'P'])?;
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int_part.is_empty() && frac_part.is_empty() {
+        return None;
+    }
+
+    let exponent = parse_saturating_exponent(exponent)?;
+    let mut sig: u128 = 0;
+    let mut num_digits = 0;
+    let mut sticky = false;
+    let mut exp2: i64 = exponent;
+    for (c, is_frac) in int_part
+        .chars()
+        .map(|c| (c, false))
+        .chain(frac_part.chars().map(|c| (c, true)))
+    {
+        let digit = c.to_digit(16)?;
+        if num_digits < MAX_HEX_DIGITS {
+            if sig != 0 || digit != 0 {
+                sig = (sig << 4) | u128::from(digit);
+                num_digits += 1;
+            }
+            if is_frac {
+                exp2 -= 4;
+            }
+        } else {
+            sticky |= digit != 0;
+            if !is_frac {
+                exp2 += 4;
+            }
+        }
+    }
+    let value = compose_f64(sig, sticky, exp2);
+    Some(if negative { -value } else { value })
+}
+
+fn parse_saturating_exponent(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.as_bytes().first()? {
+        b'-' => (true, &text[1..]),
+        b'+' => (false, &text[1..]),
+        _ => (false, text),
+    };
+    if digits.is_empty() {
+        return None;
+    }
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = i64::from(c.to_digit(10)?);
+        // Anything this large already saturates to zero or infinity.
+        value = (value * 10 + digit).min(1 << 20);
+    }
+    Some(if negative { -value } else { value })
+}
+
+/// Computes `sig * 2^exp2` rounded to the nearest `f64`. `sticky` indicates
+/// that there are nonzero bits below the least significant bit of `sig`.
+fn compose_f64(sig: u128, sticky: bool, exp2: i64) -> f64 {
+    if sig == 0 {
+        return 0.0;
+    }
+    let bit_len = i64::from(128 - sig.leading_zeros());
+    // The value is in [2^exp, 2^(exp + 1)).
+    let exp = exp2 + bit_len - 1;
+    if exp > 1023 {
+        return f64::INFINITY;
+    }
+    // The number of significand bits available at this exponent.
+    let keep = if exp >= -1022 { 53 } else { exp + 1075 };
+    let shift = bit_len - keep;
+    let mut mantissa = if shift <= 0 {
+        sig << (-shift)
+    } else {
+        let (kept, half, rest) = if shift > 128 {
+            (0, false, true)
+        } else if shift == 128 {
+            (0, sig >> 127 == 1, sig & ((1 << 127) - 1) != 0)
+        } else {
+            (
+                sig >> shift,
+                (sig >> (shift - 1)) & 1 == 1,
+                sig & ((1 << (shift - 1)) - 1) != 0,
+            )
+        };
+        if half && (rest || sticky || kept & 1 == 1) {
+            kept + 1
+        } else {
+            kept
+        }
+    };
+
+    let bits = if exp >= -1022 {
+        let mut exp = exp;
+        if mantissa == 1 << 53 {
+            mantissa >>= 1;
+            exp += 1;
+        }
+        if exp > 1023 {
+            return f64::INFINITY;
+        }
+        (u64::try_from(exp + 1023).expect("exponent is in range") << 52)
+            | (u64::try_from(mantissa).expect("mantissa fits in 53 bits") & ((1 << 52) - 1))
+    } else {
+        // A subnormal. Rounding up to 2^52 produces the smallest normal,
+        // which has the same bit pattern.
+        u64::try_from(mantissa).expect("mantissa fits in 53 bits")
+    };
+    f64::from_bits(bits)
+}
+
+fn format_hex_float(value: f64) -> Option<String> {
+    if !value.is_finite() {
+        return None;
+    }
+    let sign = if value.is_sign_negative() { "-" } else { "" };
+    let bits = value.to_bits();
+    let biased_exp = (bits >> 52) & 0x7ff;
+    let fraction = bits & ((1 << 52) - 1);
+    let (lead, exp) = match (biased_exp, fraction) {
+        (0, 0) => return Some(format!("{sign}0x0p+0")),
+        (0, _) => (0, -1022),
+        _ => (
+            1,
+            i64::try_from(biased_exp).expect("exponent fits in i64") - 1023,
+        ),
+    };
+    let digits = format!("{fraction:013x}");
+    let digits = digits.trim_end_matches('0');
+    let exp_sign = if exp < 0 { "-" } else { "+" };
+    let exp = exp.abs();
+    Some(if digits.is_empty() {
+        format!("{sign}0x{lead}p{exp_sign}{exp}")
+    } else {
+        format!("{sign}0x{lead}.{digits}p{exp_sign}{exp}")
+    })
+}
+
+impl std::fmt::Display for Float {
+    /// Formats the float so that it is distinguishable from an integer, e.g.
+    /// `1.0` rather than `1`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
 }
 
 impl From<f64> for Float {
@@ -132,3 +652,199 @@ impl From<f64> for Float {
         Float(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integers_order_across_representations() {
+        let big = Integer::from(num_bigint::BigInt::from(i64::MAX) + 1);
+        let small = Integer::from(num_bigint::BigInt::from(i64::MIN) - 1);
+        assert!(Integer::from(i64::MAX) < big);
+        assert!(small < Integer::from(i64::MIN));
+        assert!(small < big);
+        assert_eq!(Integer::from(3).cmp(&Integer::from(3)), Ordering::Equal);
+    }
+
+    #[test]
+    fn integers_compare_exactly_with_floats() {
+        let cmp = |i: Integer, f: f64| i.cmp_float(&Float::new(f));
+        assert_eq!(cmp(Integer::from(1), 1.0), Some(Ordering::Equal));
+        assert_eq!(cmp(Integer::from(1), 1.5), Some(Ordering::Less));
+        assert_eq!(cmp(Integer::from(-1), -1.5), Some(Ordering::Greater));
+        assert_eq!(cmp(Integer::from(0), -0.0), Some(Ordering::Equal));
+        assert_eq!(cmp(Integer::from(0), -0.5), Some(Ordering::Greater));
+        assert_eq!(
+            cmp(Integer::from(i64::MAX), f64::INFINITY),
+            Some(Ordering::Less)
+        );
+        assert_eq!(
+            cmp(Integer::from(i64::MIN), f64::NEG_INFINITY),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(cmp(Integer::from(0), f64::NAN), None);
+
+        // 2^53 + 1 rounds to 2^53 as a float, but is still greater than it.
+        let two_53 = 9_007_199_254_740_992_i64;
+        assert_eq!(
+            cmp(Integer::from(two_53 + 1), two_53 as f64),
+            Some(Ordering::Greater)
+        );
+        // i64::MAX rounds up to 2^63 as a float.
+        assert_eq!(
+            cmp(Integer::from(i64::MAX), 2f64.powi(63)),
+            Some(Ordering::Less)
+        );
+        let big = Integer::from(num_bigint::BigInt::from(1) << 64);
+        assert_eq!(cmp(big.clone(), 2f64.powi(64)), Some(Ordering::Equal));
+        assert_eq!(cmp(big, 1e300), Some(Ordering::Less));
+    }
+
+    fn ratio(numer: i64, denom: i64) -> Rational {
+        Rational::new(numer.into(), denom.into()).unwrap()
+    }
+
+    #[test]
+    fn rationals_are_kept_in_lowest_terms() {
+        let r = ratio(6, -4);
+        assert_eq!(r.numer(), Integer::from(-3));
+        assert_eq!(r.denom(), Integer::from(2));
+        assert_eq!(r, ratio(-3, 2));
+        assert_eq!(ratio(0, -5), ratio(0, 1));
+        assert!(Rational::new(1.into(), 0.into()).is_none());
+        assert_eq!(r.to_string(), "-3/2");
+        assert_eq!(r.trunc(), Integer::from(-1));
+    }
+
+    #[test]
+    fn rational_arithmetic_is_exact() {
+        let third = ratio(1, 3);
+        let sixth = ratio(1, 6);
+        assert_eq!(third.add(&sixth), ratio(1, 2));
+        assert_eq!(third.sub(&sixth), sixth);
+        assert_eq!(third.mul(&sixth), ratio(1, 18));
+        assert_eq!(third.checked_div(&sixth), Some(ratio(2, 1)));
+        assert_eq!(third.checked_div(&ratio(0, 1)), None);
+        assert!(sixth < third);
+        assert!(ratio(-1, 2) < ratio(-1, 3));
+    }
+
+    #[test]
+    fn rationals_convert_exactly_from_floats() {
+        assert_eq!(Rational::from_float(&Float::new(0.75)), Some(ratio(3, 4)));
+        assert_eq!(Rational::from_float(&Float::new(-2.0)), Some(ratio(-2, 1)));
+        let tiny = Rational::from_float(&Float::new(f64::from_bits(1))).unwrap();
+        assert_eq!(
+            tiny.denom(),
+            Integer::from(num_bigint::BigInt::from(1) << 1074)
+        );
+        assert!(Rational::from_float(&Float::new(f64::NAN)).is_none());
+        assert!(Rational::from_float(&Float::new(f64::INFINITY)).is_none());
+
+        // 0.1 is slightly more than 1/10 as a float.
+        let tenth = ratio(1, 10);
+        assert_eq!(tenth.cmp_float(&Float::new(0.1)), Some(Ordering::Less));
+        assert_eq!(
+            ratio(1, 4).cmp_float(&Float::new(0.25)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(tenth.cmp_float(&Float::new(f64::NAN)), None);
+    }
+
+    #[test]
+    fn rationals_round_to_nearest_float() {
+        assert_eq!(ratio(1, 10).to_float().value(), 0.1);
+        assert_eq!(ratio(-1, 3).to_float().value(), -1.0 / 3.0);
+        assert_eq!(ratio(0, 1).to_float().value(), 0.0);
+        for value in [
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            f64::from_bits(1),
+            1e-300,
+            12345.678,
+        ] {
+            let exact = Rational::from_float(&Float::new(value)).unwrap();
+            assert_eq!(exact.to_float().value().to_bits(), value.to_bits());
+        }
+        // Halfway between 1 and the next float rounds to even.
+        let half_ulp = Rational::new(
+            Integer::from((num_bigint::BigInt::from(1) << 53) + 1),
+            Integer::from(num_bigint::BigInt::from(1) << 53),
+        )
+        .unwrap();
+        assert_eq!(half_ulp.to_float().value(), 1.0);
+        let huge = Rational::from(Integer::from(num_bigint::BigInt::from(1) << 2000));
+        assert_eq!(huge.to_float().value(), f64::INFINITY);
+        assert_eq!(
+            Integer::from(num_bigint::BigInt::from(1) << 64)
+                .to_float()
+                .value(),
+            2f64.powi(64)
+        );
+    }
+
+    fn round_trip(value: f64) {
+        let text = Float::new(value).to_hex_literal().unwrap();
+        let parsed = Float::from_hex_literal(&text).unwrap();
+        assert_eq!(parsed.to_bits(), value.to_bits(), "round trip of {text}");
+    }
+
+    #[test]
+    fn hex_literal_round_trips() {
+        for value in [
+            0.0,
+            -0.0,
+            1.0,
+            -1.5,
+            std::f64::consts::PI,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            f64::from_bits(1),
+            f64::from_bits((1 << 52) - 1),
+            -f64::from_bits(12345),
+            1e-310,
+        ] {
+            round_trip(value);
+        }
+    }
+
+    #[test]
+    fn hex_literal_parses_known_values() {
+        let parse = |text| Float::from_hex_literal(text).unwrap().value();
+        assert_eq!(parse("0x1.8p3"), 12.0);
+        assert_eq!(parse("-0x1p-1"), -0.5);
+        assert_eq!(parse("0x.8p1"), 1.0);
+        assert_eq!(parse("0x1p-1074").to_bits(), 1);
+        assert_eq!(parse("0x1p-1022"), f64::MIN_POSITIVE);
+        assert_eq!(parse("0x1.fffffffffffffp1023"), f64::MAX);
+    }
+
+    #[test]
+    fn hex_literal_rounds_to_nearest_even() {
+        let parse = |text| Float::from_hex_literal(text).unwrap().value();
+        // Halfway between 1 and the next float rounds to even (down).
+        assert_eq!(parse("0x1.00000000000008p0"), 1.0);
+        // Slightly above halfway rounds up.
+        assert_eq!(
+            parse("0x1.000000000000080000000000000000001p0"),
+            1.0 + f64::EPSILON
+        );
+        // Halfway below the smallest subnormal rounds to zero, and above it
+        // rounds to the smallest subnormal.
+        assert_eq!(parse("0x1p-1075"), 0.0);
+        assert_eq!(parse("0x1.8p-1075").to_bits(), 1);
+        // Overflow produces infinity.
+        assert_eq!(parse("0x1p1024"), f64::INFINITY);
+        assert_eq!(parse("0x1.fffffffffffff8p1023"), f64::INFINITY);
+    }
+
+    #[test]
+    fn hex_literal_rejects_other_text() {
+        for text in ["", "1.5", "0x", "0xp1", "0x1", "0x1p", "0x1gp1", "foo"] {
+            assert!(Float::from_hex_literal(text).is_none(), "{text}");
+        }
+        assert!(Float::new(f64::INFINITY).to_hex_literal().is_none());
+        assert!(Float::new(f64::NAN).to_hex_literal().is_none());
+    }
+}
@@ -0,0 +1,92 @@
+//! Capability tags that restrict which managed modules may import a native
+//! module.
+//!
+//! A native module may require capabilities, such as access to the file
+//! system. A managed module may only import from it if the embedder granted
+//! the module every capability the native module requires. Native modules
+//! without required capabilities can be imported by any module.
+
+use std::collections::HashSet;
+
+use crate::util::imm_string::ImmString;
+
+/// A tag naming something a native module can do, such as `"fs"`, `"net"`
+/// or `"time"`.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Capability(ImmString);
+
+impl Capability {
+    pub fn new(name: impl Into<ImmString>) -> Self {
+        Capability(name.into())
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl<T> From<T> for Capability
+where
+    T: Into<ImmString>,
+{
+    fn from(name: T) -> Self {
+        Capability::new(name)
+    }
+}
+
+/// A set of capabilities granted to a module.
+#[derive(Clone, Default, Debug)]
+pub struct CapabilitySet(HashSet<Capability>);
+
+impl CapabilitySet {
+    #[must_use]
+    pub fn new() -> Self {
+        CapabilitySet(HashSet::new())
+    }
+
+    /// Adds a capability to the set.
+    #[must_use]
+    pub fn with(mut self, capability: impl Into<Capability>) -> Self {
+        self.0.insert(capability.into());
+        self
+    }
+
+    #[must_use]
+    pub fn contains(&self, capability: &Capability) -> bool {
+        self.0.contains(capability)
+    }
+
+    /// Returns the first of `required` that is not in this set, if any.
+    pub(crate) fn first_missing<'a>(
+        &self,
+        required: impl IntoIterator<Item = &'a Capability>,
+    ) -> Option<&'a Capability> {
+        required.into_iter().find(|c| !self.contains(c))
+    }
+}
+
+impl<C> FromIterator<C> for CapabilitySet
+where
+    C: Into<Capability>,
+{
+    fn from_iter<I: IntoIterator<Item = C>>(iter: I) -> Self {
+        CapabilitySet(iter.into_iter().map(Into::into).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_capability_is_found() {
+        let granted: CapabilitySet = ["fs", "time"].into_iter().collect();
+        let required = [Capability::new("fs"), Capability::new("net")];
+        assert_eq!(
+            granted.first_missing(&required).map(Capability::name),
+            Some("net")
+        );
+        assert!(granted.first_missing(&required[..1]).is_none());
+    }
+}
@@ -3,7 +3,10 @@
 //! presence of a runtime, but they can be used to create Values.
 
 use crate::{
-    binary::const_table::ConstValue,
+    binary::{
+        const_table::{ConstIndex, ConstValue},
+        indexes::ModuleConstIndex,
+    },
     gc::{GcTraceable, PinnedGcRef},
 };
 
@@ -19,9 +22,11 @@ pub type ResolveFunc<'a> =
     Box<dyn FnOnce(&ModuleImportEnvironment, &[PinnedValue]) -> Result<()> + 'a>;
 
 pub trait ConstLoader {
+    /// Loads the constant at `index` in its table.
     fn load<'a>(
         &'a self,
         ctxt: &'a ConstResolutionContext,
+        index: ModuleConstIndex,
     ) -> Result<(PinnedValue, ResolveFunc<'a>)>;
 }
 
@@ -36,8 +41,10 @@ where
     let mut resolved_values = Vec::with_capacity(values.len());
     let mut resolvers: Vec<ResolveFunc<'a>> = Vec::with_capacity(values.len());
 
-    for value in values {
-        let (value, resolver) = value.load(ctxt)?;
+    for (index, value) in values.iter().enumerate() {
+        let index = ModuleConstIndex::from_usize(index)
+            .ok_or_else(|| RuntimeError::new_operation_precondition_error("Too many constants."))?;
+        let (value, resolver) = value.load(ctxt, index)?;
         resolved_values.push(value);
         resolvers.push(resolver);
     }
@@ -49,6 +56,72 @@ where
     Ok(resolved_values)
 }
 
+/// Returns an error if lists and records in the const table nest more than
+/// `max_depth` deep.
+///
+/// This walks the table with an explicit stack, so that deeply nested tables
+/// can be rejected without exhausting the native stack. Lists may refer to
+/// each other cyclically; a reference back to a list that is still being
+/// measured does not add to the depth.
+pub fn check_nesting_depth(const_table: &[ConstValue], max_depth: usize) -> Result<()> {
+    fn nth_item(value: &ConstValue, n: usize) -> Option<&ConstIndex> {
+        match value {
+            ConstValue::List(items) => items.get(n),
+            ConstValue::Record(record) => record.value(n),
+            _ => None,
+        }
+    }
+
+    let mut depths: Vec<Option<usize>> = vec![None; const_table.len()];
+    let mut on_stack = vec![false; const_table.len()];
+    // Entries are (table index, next item to visit, deepest item so far).
+    let mut stack: Vec<(usize, usize, usize)> = Vec::new();
+    for root in 0..const_table.len() {
+        if depths[root].is_some() {
+            continue;
+        }
+        on_stack[root] = true;
+        stack.push((root, 0, 0));
+        while let Some((index, next_item, max_item_depth)) = stack.last_mut() {
+            if let Some(item) = nth_item(&const_table[*index], *next_item) {
+                *next_item += 1;
+                let ConstIndex::ModuleConst(item) = item else {
+                    continue;
+                };
+                let item = item.as_usize();
+                match depths.get(item) {
+                    Some(Some(depth)) => *max_item_depth = (*max_item_depth).max(*depth),
+                    Some(None) if !on_stack[item] => {
+                        on_stack[item] = true;
+                        stack.push((item, 0, 0));
+                    }
+                    // Cyclic references and invalid indexes do not add depth.
+                    _ => {}
+                }
+            } else {
+                let depth = if matches!(
+                    const_table[*index],
+                    ConstValue::List(_) | ConstValue::Record(_)
+                ) {
+                    *max_item_depth + 1
+                } else {
+                    0
+                };
+                if depth > max_depth {
+                    return Err(RuntimeError::NestingTooDeep(max_depth));
+                }
+                depths[*index] = Some(depth);
+                on_stack[*index] = false;
+                stack.pop();
+                if let Some((_, _, parent_depth)) = stack.last_mut() {
+                    *parent_depth = (*parent_depth).max(depth);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 #[derive(Clone)]
 pub struct ValueTable(Vec<Value>);
 
@@ -65,6 +138,7 @@ impl ValueTable {
         const_table: &[ConstValue],
         ctxt: &ConstResolutionContext,
     ) -> Result<PinnedGcRef<Self>> {
+        check_nesting_depth(const_table, ctxt.env().max_nesting_depth())?;
         let values = resolve_constants(ctxt, ctxt.import_environment(), const_table)?;
         Ok(Self::from_values(ctxt.env(), values))
     }
@@ -77,11 +151,21 @@ impl ValueTable {
         })
     }
 
-    pub fn at(&self, index: u32) -> Result<PinnedValue> {
-        self.0
-            .get(usize::try_from(index).unwrap())
-            .map(Value::pin)
-            .ok_or_else(|| RuntimeError::new_internal_error("Index out of bounds."))
+    /// Returns the number of values in the table.
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = PinnedValue> + '_ {
+        self.0.iter().map(Value::pin)
+    }
+
+    /// Returns the value at `index`, which is a position in the module's
+    /// constant table or in a function's constants, depending on the table.
+    pub fn at(&self, index: usize) -> Result<PinnedValue> {
+        self.0.get(index).map(Value::pin).ok_or_else(|| {
+            RuntimeError::new_operation_precondition_error("Constant index out of range.")
+        })
     }
 }
 
@@ -100,7 +184,10 @@ impl GcTraceable for ValueTable {
 mod tests {
     use crate::runtime::global_env::GlobalEnv;
     use crate::{
-        binary::const_table::{ConstIndex, ConstValue},
+        binary::{
+            const_table::{ConstIndex, ConstValue},
+            indexes::ModuleConstIndex,
+        },
         pure_values::Float,
         runtime::modules::ModuleGlobals,
     };
@@ -138,9 +225,9 @@ mod tests {
         let values = vec![
             ConstValue::Integer(42.into()),
             ConstValue::List(vec![
-                ConstIndex::ModuleConst(0),
-                ConstIndex::ModuleConst(0),
-                ConstIndex::ModuleConst(0),
+                ConstIndex::ModuleConst(ModuleConstIndex::new(0)),
+                ConstIndex::ModuleConst(ModuleConstIndex::new(0)),
+                ConstIndex::ModuleConst(ModuleConstIndex::new(0)),
             ]),
         ];
 
@@ -161,4 +248,81 @@ mod tests {
 
         Ok(())
     }
+
+    fn nested_lists(depth: u32) -> Vec<ConstValue> {
+        let mut values = vec![ConstValue::Integer(0.into())];
+        for i in 0..depth {
+            values.push(ConstValue::List(vec![ConstIndex::ModuleConst(
+                ModuleConstIndex::new(i),
+            )]));
+        }
+        values
+    }
+
+    #[test]
+    fn nesting_depth_is_limited() {
+        assert!(check_nesting_depth(&nested_lists(5), 5).is_ok());
+        assert!(matches!(
+            check_nesting_depth(&nested_lists(6), 5),
+            Err(RuntimeError::NestingTooDeep(5))
+        ));
+    }
+
+    #[test]
+    fn very_deep_nesting_is_rejected_without_overflow() {
+        let values = nested_lists(100_000);
+        assert!(matches!(
+            check_nesting_depth(&values, 1000),
+            Err(RuntimeError::NestingTooDeep(1000))
+        ));
+    }
+
+    #[test]
+    fn cyclic_lists_have_finite_depth() {
+        let values = vec![
+            ConstValue::List(vec![ConstIndex::ModuleConst(ModuleConstIndex::new(1))]),
+            ConstValue::List(vec![ConstIndex::ModuleConst(ModuleConstIndex::new(0))]),
+            ConstValue::List(vec![ConstIndex::ModuleConst(ModuleConstIndex::new(2))]),
+        ];
+        assert!(check_nesting_depth(&values, 2).is_ok());
+        assert!(check_nesting_depth(&values, 1).is_err());
+    }
+
+    #[test]
+    fn from_binary_enforces_nesting_limit() {
+        let global_ctxt = GlobalEnv::new();
+        global_ctxt.set_max_nesting_depth(3);
+        let module_globals = ModuleGlobals::from_size_empty(&global_ctxt, 0);
+        let import_environment = ModuleImportEnvironment::new(&global_ctxt, vec![]);
+        let ctxt = ConstResolutionContext::new(&global_ctxt, &module_globals, &import_environment);
+
+        assert!(ValueTable::from_binary(&nested_lists(3), &ctxt).is_ok());
+        assert!(matches!(
+            ValueTable::from_binary(&nested_lists(4), &ctxt),
+            Err(RuntimeError::NestingTooDeep(3))
+        ));
+    }
+
+    #[test]
+    fn self_referential_list_contains_itself() -> anyhow::Result<()> {
+        let values = vec![
+            ConstValue::Integer(1.into()),
+            ConstValue::List(vec![
+                ConstIndex::ModuleConst(ModuleConstIndex::new(0)),
+                ConstIndex::ModuleConst(ModuleConstIndex::new(1)),
+            ]),
+        ];
+
+        let global_ctxt = GlobalEnv::new();
+        let module_globals = ModuleGlobals::from_size_empty(&global_ctxt, 0);
+        let import_environment = ModuleImportEnvironment::new(&global_ctxt, vec![]);
+        let ctxt = ConstResolutionContext::new(&global_ctxt, &module_globals, &import_environment);
+
+        let resolved_values = ValueTable::from_binary(&values, &ctxt)?;
+        let list_value = resolved_values.at(1)?;
+        let list = list_value.as_list()?;
+        assert_eq!(list.len(), 2);
+        assert!(list.at(1).ref_eq(&list_value));
+        Ok(())
+    }
 }
@@ -1,16 +1,22 @@
 //! Global contexts for the current state of a runtime environment.
 
-use crate::gc::PinnedGcRef;
+use crate::{
+    binary::{
+        indexes::{GlobalIndex, LocalConstIndex},
+        modules::ModuleId,
+    },
+    gc::PinnedGcRef,
+};
 
 use super::{
     constants::ValueTable, environment::ModuleImportEnvironment, error::Result,
-    global_env::GlobalEnv, modules::ModuleGlobals,
-    value::PinnedValue,
+    global_env::GlobalEnv, modules::ModuleGlobals, value::PinnedValue,
 };
 pub struct ConstResolutionContext<'a> {
     env: &'a GlobalEnv,
     module_globals: &'a PinnedGcRef<ModuleGlobals>,
     import_environment: &'a ModuleImportEnvironment,
+    module_id: Option<&'a ModuleId>,
 }
 
 impl<'a> ConstResolutionContext<'a> {
@@ -23,9 +29,16 @@ impl<'a> ConstResolutionContext<'a> {
             env,
             module_globals,
             import_environment,
+            module_id: None,
         }
     }
 
+    /// Sets the id of the module whose constants are being resolved.
+    pub fn with_module_id(mut self, module_id: &'a ModuleId) -> Self {
+        self.module_id = Some(module_id);
+        self
+    }
+
     pub fn env(&self) -> &GlobalEnv {
         self.env
     }
@@ -37,6 +50,10 @@ impl<'a> ConstResolutionContext<'a> {
     pub fn import_environment(&self) -> &ModuleImportEnvironment {
         self.import_environment
     }
+
+    pub fn module_id(&self) -> Option<&ModuleId> {
+        self.module_id
+    }
 }
 
 pub struct InstEvalContext<'a> {
@@ -62,15 +79,15 @@ impl<'a> InstEvalContext<'a> {
         self.global_context
     }
 
-    pub fn get_constant(&self, index: u32) -> Result<PinnedValue> {
-        self.local_constants.at(index)
+    pub fn get_constant(&self, index: LocalConstIndex) -> Result<PinnedValue> {
+        self.local_constants.at(index.as_usize())
     }
 
-    pub fn get_global(&self, index: u32) -> Result<PinnedValue> {
+    pub fn get_global(&self, index: GlobalIndex) -> Result<PinnedValue> {
         self.globals.at(index)
     }
 
-    pub fn set_global(&self, index: u32, value: PinnedValue) -> Result<()> {
+    pub fn set_global(&self, index: GlobalIndex, value: PinnedValue) -> Result<()> {
         self.globals.set(index, value)
     }
 }
@@ -1,6 +1,27 @@
-use crate::binary::{module_set::ModuleSet, ConstModule};
+use std::{rc::Rc, time::Duration};
 
-use super::{error::Result, global_env::GlobalEnv, TopLevelRuntime};
+use crate::{
+    binary::{
+        module_set::ModuleSet,
+        modules::{ImportSource, ModuleId},
+        ConstModule, InstructionPolicy, Program,
+    },
+    gc::{GcStats, GcTrigger},
+};
+
+use super::{
+    capabilities::CapabilitySet,
+    debug::{Breakpoint, DebugHandler},
+    error::{Result, RuntimeError},
+    global_env::GlobalEnv,
+    limits::CancelHandle,
+    module_tests::TestResult,
+    native_module::NativeModule,
+    profile::{FunctionOptimizer, FunctionProfile, StackSampler, TierUpPolicy},
+    stack_frame::StackShrinkPolicy,
+    stdlib::{self, io::IoBackendData},
+    IoBackend, TopLevelRuntime, ValueHandle,
+};
 
 pub struct Runtime {
     global_env: GlobalEnv,
@@ -18,26 +39,441 @@ impl Runtime {
         self.global_env.load_module(module)
     }
 
+    pub fn load_native_module(&self, module: &NativeModule) -> Result<()> {
+        self.global_env.load_native_module(module)
+    }
+
+    /// Loads the native modules of the standard library (`std.*`).
+    pub fn load_std_modules(&self) -> Result<()> {
+        for module in stdlib::modules() {
+            self.load_native_module(&module)?;
+        }
+        Ok(())
+    }
+
+    /// Loads all modules in the set, each after the modules it imports from.
+    ///
+    /// Every required dependency of the set's modules must either be in the
+    /// set or already be loaded, or this fails with
+    /// [`RuntimeError::DependencyNotLoaded`] before anything is loaded.
+    ///
+    /// Loading is transactional: if any module in the set fails to load, none
+    /// of the modules in the set remain loaded. If initializers are run on
+    /// load (see [`Self::set_init_modules_on_load`]), they run afterwards in
+    /// the same order. An initializer that fails stops the ones after it, and
+    /// its error is returned with all of the set's modules still loaded.
     pub fn load_module_set(&self, module_set: &ModuleSet) -> Result<()> {
-        if !module_set
-            .external_dependencies()
-            .all(|module_id| self.global_env.is_module_loaded(module_id))
-        {
-            panic!("Dependency not satisfied.");
+        let order = module_set.dependency_order();
+        for module in &order {
+            if let Some(dependency) = module.required_dependencies().find(|dependency| {
+                module_set.module(dependency).is_none()
+                    && !self.global_env.is_module_loaded(dependency)
+            }) {
+                return Err(RuntimeError::DependencyNotLoaded {
+                    module: module.id().to_string(),
+                    dependency: dependency.to_string(),
+                });
+            }
         }
 
-        // FIXME: This is a naive implementation that does not handle
-        // dependencies correctly.
-        for module in module_set.modules() {
-            self.load_module(module)?;
+        self.global_env.load_modules(order.iter().copied())?;
+        if self.init_modules_on_load() {
+            let top_level = self.make_top_level();
+            for module in &order {
+                top_level.init_module(module.id())?;
+            }
         }
         Ok(())
     }
 
+    /// Loads the modules of a program, as [`Self::load_module_set`] does.
+    /// The program is then run with [`TopLevelRuntime::run_program`].
+    pub fn load_program(&self, program: &Program) -> Result<()> {
+        self.load_module_set(program.modules())
+    }
+
+    /// Grants capabilities to the managed module with the given id, replacing
+    /// any previous grant.
+    ///
+    /// Modules may only import from native modules whose required
+    /// capabilities they were granted. Grants are checked when a module is
+    /// loaded, so they must be made before loading the module.
+    pub fn grant_capabilities(&self, module_id: &ModuleId, capabilities: CapabilitySet) {
+        self.global_env.grant_capabilities(module_id, capabilities);
+    }
+
+    /// Restricts the instructions the managed module with the given id may
+    /// use, replacing any previous policy. Modules without a policy may use
+    /// every instruction.
+    ///
+    /// The policy is checked when the module is loaded, which fails with
+    /// [`RuntimeError::Validation`] naming the first function and pc that
+    /// use a denied instruction. It must be set before loading the module.
+    pub fn set_instruction_policy(&self, module_id: &ModuleId, policy: InstructionPolicy) {
+        self.global_env.set_instruction_policy(module_id, policy);
+    }
+
+    /// Returns true if a module with the given id is loaded.
+    #[must_use]
+    pub fn is_module_loaded(&self, module_id: &ModuleId) -> bool {
+        self.global_env.is_module_loaded(module_id)
+    }
+
+    /// Returns the documentation string of the export that `source` names,
+    /// as given by a `(doc ...)` annotation when the module was built. Fails
+    /// if the module is not loaded.
+    pub fn export_doc(&self, source: &ImportSource) -> Result<Option<String>> {
+        self.global_env.get_export_doc(source)
+    }
+
     #[must_use]
     pub fn make_top_level(&self) -> TopLevelRuntime {
         TopLevelRuntime::new(self.global_env.clone())
     }
+
+    /// Runs the tests of a loaded module on a new top level. See
+    /// [`TopLevelRuntime::run_module_tests`].
+    pub fn run_module_tests(&self, module_id: &ModuleId) -> Result<Vec<TestResult>> {
+        self.make_top_level().run_module_tests(module_id)
+    }
+
+    /// Sets the number of instructions that may be executed before calls fail
+    /// with [`super::RuntimeError::OutOfFuel`]. `None` removes the limit.
+    ///
+    /// Fuel is charged at safe points, so a call may run a bounded number of
+    /// instructions past the limit before it is stopped.
+    pub fn set_fuel(&self, fuel: Option<u64>) {
+        self.global_env.set_fuel(fuel);
+    }
+
+    /// Returns the remaining fuel, or `None` if fuel is unlimited.
+    #[must_use]
+    pub fn fuel(&self) -> Option<u64> {
+        self.global_env.fuel()
+    }
+
+    /// Returns a handle that can be used to cancel execution in this runtime.
+    #[must_use]
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.global_env.cancel_handle()
+    }
+
+    /// Returns the call profiles of all managed functions in loaded modules.
+    #[must_use]
+    pub fn function_profiles(&self) -> Vec<FunctionProfile> {
+        self.global_env.function_profiles()
+    }
+
+    /// Returns the profiles of functions called at least `min_calls` times,
+    /// hottest first.
+    #[must_use]
+    pub fn hot_functions(&self, min_calls: u64) -> Vec<FunctionProfile> {
+        let mut profiles = self.function_profiles();
+        profiles.retain(|profile| profile.call_count() >= min_calls);
+        profiles.sort_by_key(|profile| std::cmp::Reverse(profile.call_count()));
+        profiles
+    }
+
+    /// Installs an optimizer that is given each managed function once it has
+    /// been called `threshold` times. See [`FunctionOptimizer`].
+    pub fn set_function_optimizer<O>(&self, threshold: u64, optimizer: O)
+    where
+        O: FunctionOptimizer + 'static,
+    {
+        self.global_env
+            .set_tier_up_policy(Some(TierUpPolicy::new(threshold, Box::new(optimizer))));
+    }
+
+    /// Removes any installed [`FunctionOptimizer`].
+    pub fn clear_function_optimizer(&self) {
+        self.global_env.set_tier_up_policy(None);
+    }
+
+    /// Starts sampling the managed call stack once every `interval`
+    /// instructions, discarding any earlier samples. See
+    /// [`Runtime::profiler_collapsed_stacks`].
+    pub fn start_stack_sampling(&self, interval: u64) {
+        self.global_env
+            .set_stack_sampler(Some(StackSampler::new(interval)));
+    }
+
+    /// Stops sampling and discards the samples.
+    pub fn stop_stack_sampling(&self) {
+        self.global_env.set_stack_sampler(None);
+    }
+
+    /// Returns the samples taken since sampling started, in the collapsed
+    /// stack format read by flamegraph tools such as `inferno-flamegraph`.
+    /// Each line holds the function ids of a sampled stack, outermost first
+    /// and separated by `;`, followed by a space and the number of samples.
+    /// Native functions are left out of the stacks.
+    ///
+    /// Returns an empty string if sampling is not running.
+    #[must_use]
+    pub fn profiler_collapsed_stacks(&self) -> String {
+        self.global_env
+            .stack_sampler()
+            .map(|sampler| sampler.collapsed_stacks())
+            .unwrap_or_default()
+    }
+
+    /// Installs a handler that is called before each managed instruction
+    /// runs and at breakpoints, replacing any installed before. See
+    /// [`DebugHandler`].
+    pub fn set_debug_handler<H>(&self, handler: H)
+    where
+        H: DebugHandler + 'static,
+    {
+        self.global_env.debug().set_handler(Some(Rc::new(handler)));
+    }
+
+    /// Removes any installed [`DebugHandler`]. Breakpoints stay registered.
+    pub fn clear_debug_handler(&self) {
+        self.global_env.debug().set_handler(None);
+    }
+
+    /// Registers a breakpoint, returning false if it was already registered.
+    /// Breakpoints only take effect while a [`DebugHandler`] is installed.
+    pub fn add_breakpoint(&self, breakpoint: Breakpoint) -> bool {
+        self.global_env.debug().add_breakpoint(breakpoint)
+    }
+
+    /// Removes a breakpoint, returning false if it was not registered.
+    pub fn remove_breakpoint(&self, breakpoint: &Breakpoint) -> bool {
+        self.global_env.debug().remove_breakpoint(breakpoint)
+    }
+
+    pub fn clear_breakpoints(&self) {
+        self.global_env.debug().clear_breakpoints();
+    }
+
+    /// Returns the registered breakpoints, in the order they were added.
+    #[must_use]
+    pub fn breakpoints(&self) -> Vec<Breakpoint> {
+        self.global_env.debug().breakpoints()
+    }
+
+    /// Releases a value retained with
+    /// [`StackContext::retain`](super::stack_frame::StackContext::retain), so
+    /// it can be collected once nothing else refers to it. Returns false if
+    /// the handle was created by a different runtime.
+    pub fn release(&self, handle: ValueHandle) -> bool {
+        self.global_env.release_handle(handle)
+    }
+
+    /// Returns the number of values retained through handles that have not
+    /// been released.
+    #[must_use]
+    pub fn num_retained(&self) -> usize {
+        self.global_env.num_handles()
+    }
+
+    /// Writes the runtime's heap as a Graphviz DOT graph, with each object
+    /// labeled by its type and the pinned roots highlighted. Useful for
+    /// finding out what keeps values from being collected.
+    pub fn dump_graph_dot(&self, w: impl std::io::Write) -> std::io::Result<()> {
+        self.global_env.dump_graph_dot(w)
+    }
+
+    /// Sets when garbage collections are triggered. By default the runtime
+    /// collects after every allocation, which finds values that are not kept
+    /// alive properly but is slow. [`GcTrigger::Adaptive`] sizes the heap
+    /// after each collection to the values that survived it.
+    /// [`GcTrigger::Generational`] mostly runs minor collections, which only
+    /// visit recently allocated values. [`GcTrigger::Manual`] only collects
+    /// when [`collect_garbage`](Self::collect_garbage) is called.
+    pub fn set_gc_trigger(&self, trigger: GcTrigger) {
+        self.global_env.set_gc_trigger(trigger);
+    }
+
+    #[must_use]
+    pub fn gc_trigger(&self) -> GcTrigger {
+        self.global_env.gc_trigger()
+    }
+
+    /// Returns the number of collections run and the time spent in them, the
+    /// objects that survived the last one, the heap size that triggers the
+    /// next, and the number of objects allocated so far.
+    #[must_use]
+    pub fn gc_stats(&self) -> GcStats {
+        self.global_env.gc_stats()
+    }
+
+    /// Runs a garbage collection now, whatever the trigger. Returns false
+    /// without collecting if a collection is already running or the heap is
+    /// locked.
+    pub fn collect_garbage(&self) -> bool {
+        self.global_env.collect_garbage()
+    }
+
+    /// Stores `value` as the host data of type `T`, returning the previous
+    /// value of that type if there was one. Native functions read it with
+    /// [`NativeFunctionContext::host_data`](super::value::NativeFunctionContext::host_data).
+    ///
+    /// Host data is not traced by the garbage collector, so it must not hold
+    /// values of the runtime.
+    pub fn set_host_data<T: 'static>(&self, value: T) -> Option<Rc<T>> {
+        self.global_env.set_host_data(value)
+    }
+
+    /// Removes and returns the host data of type `T`.
+    pub fn remove_host_data<T: 'static>(&self) -> Option<Rc<T>> {
+        self.global_env.remove_host_data()
+    }
+
+    /// Returns the host data of type `T`, if it has been set.
+    #[must_use]
+    pub fn host_data<T: 'static>(&self) -> Option<Rc<T>> {
+        self.global_env.host_data()
+    }
+
+    /// Sets the backend that the `std.io` module reads and writes through,
+    /// replacing any previous one. Until a backend is set, its functions
+    /// fail.
+    pub fn set_io_backend(&self, backend: impl IoBackend + 'static) {
+        self.set_host_data(IoBackendData(Box::new(backend)));
+    }
+
+    /// Enables or disables evaluating the side-effect-free prefix of module
+    /// initializers when modules are loaded. See
+    /// [`ConstModule::const_eval_initializer`].
+    ///
+    /// This only affects modules loaded after the call.
+    pub fn set_const_eval_initializers(&self, enabled: bool) {
+        self.global_env.set_const_eval_initializers(enabled);
+    }
+
+    /// Enables or disables the peephole rewrites of
+    /// [`ConstModule::peephole_optimize`] when modules are loaded. The
+    /// rewritten functions behave the same, but the program counters seen by
+    /// native functions refer to the rewritten instructions.
+    ///
+    /// This only affects modules loaded after the call.
+    pub fn set_peephole_optimize(&self, enabled: bool) {
+        self.global_env.set_peephole_optimize(enabled);
+    }
+
+    /// Allows loading a module under the id of a loaded module, replacing it.
+    /// By default this fails with [`RuntimeError::ModuleAlreadyLoaded`], so
+    /// that module sets cannot shadow each other's modules by accident.
+    ///
+    /// Modules that imported from a replaced module keep the values they
+    /// imported.
+    pub fn set_allow_module_replacement(&self, allowed: bool) {
+        self.global_env.set_allow_module_replacement(allowed);
+    }
+
+    #[must_use]
+    pub fn allow_module_replacement(&self) -> bool {
+        self.global_env.allow_module_replacement()
+    }
+
+    /// Runs the initializer of each module loaded by
+    /// [`Self::load_module_set`] once the whole set is loaded, so that they
+    /// need not be run with [`TopLevelRuntime::init_module`]. Off by default.
+    pub fn set_init_modules_on_load(&self, enabled: bool) {
+        self.global_env.set_init_modules_on_load(enabled);
+    }
+
+    #[must_use]
+    pub fn init_modules_on_load(&self) -> bool {
+        self.global_env.init_modules_on_load()
+    }
+
+    /// Records the managed frames that were active when a call fails. The
+    /// error is then wrapped in [`RuntimeError::WithStackTrace`], which
+    /// reports the same [`RuntimeError::kind`] as the error it wraps. Off by
+    /// default, so that errors can be matched directly.
+    pub fn set_capture_stack_traces(&self, enabled: bool) {
+        self.global_env.set_capture_stack_traces(enabled);
+    }
+
+    #[must_use]
+    pub fn capture_stack_traces(&self) -> bool {
+        self.global_env.capture_stack_traces()
+    }
+
+    /// Sets when operand stacks give back memory after growing. `None`
+    /// keeps their peak capacity for as long as they live. By default,
+    /// [`StackShrinkPolicy::default`] is used.
+    pub fn set_stack_shrink_policy(&self, policy: Option<StackShrinkPolicy>) {
+        self.global_env.set_stack_shrink_policy(policy);
+    }
+
+    #[must_use]
+    pub fn stack_shrink_policy(&self) -> Option<StackShrinkPolicy> {
+        self.global_env.stack_shrink_policy()
+    }
+
+    /// Sets how deeply constant lists in loaded modules may nest. Modules
+    /// that exceed it fail to load with [`RuntimeError::NestingTooDeep`], as
+    /// does `std.string.format` when given a value nested more deeply.
+    pub fn set_max_nesting_depth(&self, depth: usize) {
+        self.global_env.set_max_nesting_depth(depth);
+    }
+
+    #[must_use]
+    pub fn max_nesting_depth(&self) -> usize {
+        self.global_env.max_nesting_depth()
+    }
+
+    /// Sets how many values a single call frame, managed or native, may hold
+    /// on its operand stack. A function that grows its stack past the limit
+    /// fails with [`RuntimeError::StackLimitExceeded`], and pushes of many
+    /// values at once, such as `apply`, fail before they are made. The limit
+    /// applies to calls started after it is set. `None` removes the limit.
+    pub fn set_max_frame_stack_size(&self, size: Option<usize>) {
+        self.global_env.set_max_frame_stack_size(size);
+    }
+
+    #[must_use]
+    pub fn max_frame_stack_size(&self) -> Option<usize> {
+        self.global_env.max_frame_stack_size()
+    }
+
+    /// Sets how many calls may be active at once, counting managed and
+    /// native frames and the calls native functions make. A call past the
+    /// limit fails with [`RuntimeError::StackOverflow`], rather than growing
+    /// the call stack until memory runs out. Tail calls replace their frame,
+    /// so they do not add to the depth. `None` removes the limit; the default
+    /// is 4096.
+    pub fn set_max_call_depth(&self, depth: Option<usize>) {
+        self.global_env.set_max_call_depth(depth);
+    }
+
+    #[must_use]
+    pub fn max_call_depth(&self) -> Option<usize> {
+        self.global_env.max_call_depth()
+    }
+
+    /// Sets whether a panicking native function fails its call with
+    /// [`RuntimeError::NativePanic`], rather than unwinding through the
+    /// interpreter and the host's call. Enabled by default.
+    pub fn set_catch_native_panics(&self, catch: bool) {
+        self.global_env.set_catch_native_panics(catch);
+    }
+
+    #[must_use]
+    pub fn catch_native_panics(&self) -> bool {
+        self.global_env.catch_native_panics()
+    }
+
+    /// Sets how long a native function may run each time the interpreter
+    /// runs it, including any calls it makes back into the runtime. A
+    /// function that continues in a callback, such as with
+    /// `call_with_continuation` or `yield_to_host`, has each callback timed
+    /// on its own. `None` removes the limit.
+    ///
+    /// Native functions cannot be interrupted, so one that runs too long
+    /// fails with [`RuntimeError::NativeCallTimeout`] once it returns.
+    pub fn set_max_native_call_time(&self, time: Option<Duration>) {
+        self.global_env.set_max_native_call_time(time);
+    }
+
+    #[must_use]
+    pub fn max_native_call_time(&self) -> Option<Duration> {
+        self.global_env.max_native_call_time()
+    }
 }
 
 impl Default for Runtime {
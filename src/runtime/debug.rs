@@ -0,0 +1,219 @@
+//! Hooks for building debuggers on top of the interpreter.
+//!
+//! A host installs a [`DebugHandler`] on a [`super::Runtime`], which is then
+//! called before every managed instruction runs, and additionally when an
+//! instruction at a registered [`Breakpoint`] is reached. The handler sees
+//! the running function, its pc and a read-only view of its local stack, and
+//! can stop the call by returning an error.
+//!
+//! A stepping debugger can be built by blocking inside the callbacks while
+//! the user inspects the frame. Without a handler installed, the interpreter
+//! only pays for checking that none is.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    binary::{indexes::ModuleConstIndex, instructions::StackIndex, modules::ModuleId},
+    pure_values::LoonValue,
+};
+
+use super::{
+    error::Result, global_env::GlobalEnv, stack_frame::LocalStack, value::FunctionOrigin,
+    FunctionId,
+};
+
+/// Receives callbacks as managed code runs.
+///
+/// Callbacks run on the interpreter's thread, in the middle of a call. They
+/// may use the [`super::Runtime`] to change breakpoints, but must not start
+/// new calls.
+pub trait DebugHandler {
+    /// Called before each managed instruction runs. Returning an error stops
+    /// the running call with that error.
+    fn before_instruction(&self, _frame: &DebugFrame<'_>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before an instruction at a registered breakpoint runs, after
+    /// [`Self::before_instruction`]. Returning an error stops the running
+    /// call with that error.
+    fn on_breakpoint(&self, _frame: &DebugFrame<'_>, _breakpoint: &Breakpoint) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// An instruction of a managed function at which [`DebugHandler::on_breakpoint`]
+/// is called.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Breakpoint {
+    module_id: ModuleId,
+    const_index: ModuleConstIndex,
+    pc: usize,
+}
+
+impl Breakpoint {
+    /// A breakpoint at instruction `pc` of the function at `const_index` in
+    /// the constant table of the module `module_id`.
+    #[must_use]
+    pub fn new(module_id: ModuleId, const_index: ModuleConstIndex, pc: usize) -> Self {
+        Breakpoint {
+            module_id,
+            const_index,
+            pc,
+        }
+    }
+
+    #[must_use]
+    pub fn module_id(&self) -> &ModuleId {
+        &self.module_id
+    }
+
+    #[must_use]
+    pub fn const_index(&self) -> ModuleConstIndex {
+        self.const_index
+    }
+
+    #[must_use]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn is_at(&self, origin: &FunctionOrigin, pc: usize) -> bool {
+        self.pc == pc
+            && self.const_index == origin.const_index()
+            && origin.module_id() == Some(&self.module_id)
+    }
+}
+
+/// A managed frame that is about to run an instruction.
+pub struct DebugFrame<'a> {
+    env: &'a GlobalEnv,
+    origin: &'a FunctionOrigin,
+    pc: usize,
+    stack: &'a LocalStack,
+}
+
+impl DebugFrame<'_> {
+    /// The stable id of the running function.
+    #[must_use]
+    pub fn function_id(&self) -> FunctionId {
+        self.origin.function_id()
+    }
+
+    /// The module that defines the running function, if it was loaded from a
+    /// module.
+    #[must_use]
+    pub fn module_id(&self) -> Option<&ModuleId> {
+        self.origin.module_id()
+    }
+
+    /// The index of the running function in its module's constant table.
+    #[must_use]
+    pub fn const_index(&self) -> ModuleConstIndex {
+        self.origin.const_index()
+    }
+
+    /// The index of the instruction that is about to run.
+    #[must_use]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The number of values on the frame's local stack.
+    #[must_use]
+    pub fn stack_len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns a copy of the value at the given index of the local stack.
+    /// Lists are copied as deeply as the runtime's maximum nesting depth
+    /// allows.
+    pub fn get_loon_value(&self, index: StackIndex) -> Result<LoonValue> {
+        self.stack
+            .get_at_index(index)?
+            .to_loon_value(self.env.max_nesting_depth())
+    }
+
+    /// Returns the id of the function at the given index of the local stack.
+    pub fn get_function_id(&self, index: StackIndex) -> Result<FunctionId> {
+        self.stack.get_at_index(index)?.as_function()?.id()
+    }
+}
+
+/// The installed debug handler and the registered breakpoints.
+pub(crate) struct DebugState {
+    handler: RefCell<Option<Rc<dyn DebugHandler>>>,
+    breakpoints: RefCell<Vec<Breakpoint>>,
+}
+
+impl DebugState {
+    pub fn new() -> Self {
+        DebugState {
+            handler: RefCell::new(None),
+            breakpoints: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn set_handler(&self, handler: Option<Rc<dyn DebugHandler>>) {
+        *self.handler.borrow_mut() = handler;
+    }
+
+    /// Adds a breakpoint, returning false if it was already registered.
+    pub fn add_breakpoint(&self, breakpoint: Breakpoint) -> bool {
+        let mut breakpoints = self.breakpoints.borrow_mut();
+        if breakpoints.contains(&breakpoint) {
+            return false;
+        }
+        breakpoints.push(breakpoint);
+        true
+    }
+
+    /// Removes a breakpoint, returning false if it was not registered.
+    pub fn remove_breakpoint(&self, breakpoint: &Breakpoint) -> bool {
+        let mut breakpoints = self.breakpoints.borrow_mut();
+        let len = breakpoints.len();
+        breakpoints.retain(|registered| registered != breakpoint);
+        breakpoints.len() != len
+    }
+
+    pub fn clear_breakpoints(&self) {
+        self.breakpoints.borrow_mut().clear();
+    }
+
+    pub fn breakpoints(&self) -> Vec<Breakpoint> {
+        self.breakpoints.borrow().clone()
+    }
+
+    /// Calls the handler, if one is installed, before the instruction at `pc`
+    /// of the function `origin` runs.
+    pub fn before_instruction(
+        &self,
+        env: &GlobalEnv,
+        origin: &FunctionOrigin,
+        pc: usize,
+        stack: &LocalStack,
+    ) -> Result<()> {
+        // Nothing is borrowed while the handler runs, so that it can change
+        // breakpoints or replace itself.
+        let Some(handler) = self.handler.borrow().clone() else {
+            return Ok(());
+        };
+        let frame = DebugFrame {
+            env,
+            origin,
+            pc,
+            stack,
+        };
+        handler.before_instruction(&frame)?;
+        let hit = self
+            .breakpoints
+            .borrow()
+            .iter()
+            .find(|breakpoint| breakpoint.is_at(origin, pc))
+            .cloned();
+        if let Some(breakpoint) = hit {
+            handler.on_breakpoint(&frame, &breakpoint)?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
-use crate::gc::{GcTraceable, PinnedGcRef};
+use crate::{
+    binary::indexes::ImportIndex,
+    gc::{GcTraceable, PinnedGcRef},
+};
 
 use super::{
     error::{Result, RuntimeError},
@@ -31,10 +34,9 @@ impl ModuleImportEnvironment {
         })
     }
 
-    pub fn get_import(&self, index: u32) -> Result<PinnedValue> {
-        self.imports
-            .get(usize::try_from(index).unwrap())
-            .map(Value::pin)
-            .ok_or_else(|| RuntimeError::new_internal_error("Import index out of bounds."))
+    pub fn get_import(&self, index: ImportIndex) -> Result<PinnedValue> {
+        index.get(&self.imports).map(Value::pin).ok_or_else(|| {
+            RuntimeError::new_operation_precondition_error("Import index out of range.")
+        })
     }
 }
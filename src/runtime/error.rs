@@ -1,5 +1,7 @@
 use std::borrow::Cow;
 
+use crate::binary::{FunctionId, ValidationError};
+
 #[derive(Debug, thiserror::Error)]
 #[error("Type Error: {message}")]
 pub struct TypeError {
@@ -18,6 +20,60 @@ pub struct OperationPreconditionError {
     message: String,
 }
 
+/// The broad category of a [`RuntimeError`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// The script or the embedder used the runtime incorrectly, e.g. a type
+    /// mismatch or an out of range index.
+    UserError,
+    /// Execution hit a limit configured on the runtime.
+    ResourceLimit,
+    /// The runtime reached a state that should not be possible. This
+    /// indicates a bug in the VM.
+    Internal,
+    /// Execution was stopped by the embedder.
+    Cancelled,
+}
+
+/// A managed function that was running when an error was raised, and the
+/// instruction it was at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackTraceFrame {
+    function: String,
+    pc: usize,
+}
+
+impl StackTraceFrame {
+    pub(crate) fn new(function: &FunctionId, pc: usize) -> Self {
+        StackTraceFrame {
+            function: function.to_string(),
+            pc,
+        }
+    }
+
+    /// The function, as its [`FunctionId`] is displayed. It is kept as text,
+    /// like the names in other errors, so that errors can be sent between
+    /// threads.
+    #[must_use]
+    pub fn function(&self) -> &str {
+        &self.function
+    }
+
+    /// The index of the instruction that failed, or of the call the function
+    /// was waiting on.
+    #[must_use]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+}
+
+impl std::fmt::Display for StackTraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at pc {}", self.function, self.pc)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum RuntimeError {
     /// An error where the wrong type is used in an operation.
@@ -30,6 +86,104 @@ pub enum RuntimeError {
     OperationPrecondition(OperationPreconditionError),
     #[error("Internal error: {0}")]
     InternalError(String),
+    /// Execution used up the fuel allotted to the runtime.
+    #[error("Execution ran out of fuel.")]
+    OutOfFuel,
+    /// Execution ran past the wall-clock deadline of the call.
+    #[error("Execution exceeded its deadline.")]
+    Timeout,
+    /// Execution was cancelled through a [`super::CancelHandle`].
+    #[error("Execution was cancelled.")]
+    Cancelled,
+    /// A constant being loaded, or a value being formatted, is nested more
+    /// deeply than the runtime allows. Holds the limit.
+    #[error("Value nesting exceeds the maximum depth of {0}.")]
+    NestingTooDeep(usize),
+    /// A function grew its frame's operand stack past the configured limit.
+    /// Native functions are named `<native>`.
+    #[error("Function {function} exceeded the frame stack limit of {limit} values.")]
+    StackLimitExceeded { function: String, limit: usize },
+    /// A call would have made more calls active at once than the configured
+    /// limit allows. See [`super::Runtime::set_max_call_depth`].
+    #[error("Call depth exceeded the limit of {limit} calls.")]
+    StackOverflow { limit: usize },
+    /// A top level allocated more objects than its quota allows. See
+    /// [`super::TopLevelRuntime::set_allocation_quota`].
+    #[error("Top level allocated {allocated} objects, exceeding its quota of {quota}.")]
+    TenantQuotaExceeded { quota: u64, allocated: u64 },
+    /// `bind_front` captured more values than the function has arguments
+    /// left to bind, according to its declared arity.
+    #[error("Function {function} takes {arity} more arguments, but {bound} were bound.")]
+    TooManyBoundArguments {
+        function: String,
+        arity: u32,
+        bound: usize,
+    },
+    /// A native function panicked. Holds the panic message. Panics are only
+    /// caught while [`super::Runtime::set_catch_native_panics`] is enabled.
+    #[error("Native function panicked: {0}")]
+    NativePanic(String),
+    /// A native function ran for longer than
+    /// [`super::Runtime::set_max_native_call_time`] allows. Holds the limit.
+    #[error("Native function exceeded its time limit of {0:?}.")]
+    NativeCallTimeout(std::time::Duration),
+    /// A module imported from a native module that requires a capability
+    /// the importing module was not granted.
+    #[error("Module {module} was not granted the {capability:?} capability.")]
+    CapabilityNotGranted { module: String, capability: String },
+    /// A module was loaded under the id of a module that is already loaded,
+    /// and replacing modules was not allowed.
+    #[error("Module {module} is already loaded.")]
+    ModuleAlreadyLoaded { module: String },
+    /// A module in a module set requires a module that is neither in the set
+    /// nor already loaded.
+    #[error("Module {module} depends on {dependency}, which is not loaded.")]
+    DependencyNotLoaded { module: String, dependency: String },
+    /// A module failed validation when it was loaded, such as by using an
+    /// instruction its [`InstructionPolicy`](crate::binary::InstructionPolicy)
+    /// denies.
+    #[error("Module {module} failed validation: {error}")]
+    Validation {
+        module: String,
+        #[source]
+        error: ValidationError,
+    },
+    /// A module initializer ran out of fuel, exceeded its deadline or another
+    /// execution limit, or was cancelled. Holds the error that stopped it.
+    #[error("Initializer of module {module} was interrupted: {error}")]
+    InitializerInterrupted {
+        module: String,
+        #[source]
+        error: Box<RuntimeError>,
+    },
+    /// An import named a member its module does not export. Holds the
+    /// module's exports in sorted order, and the closest of them to `name`
+    /// if one is close enough to be a likely typo.
+    #[error(
+        "Module {module} has no export {name:?}.{}",
+        describe_exports(.available, .suggestion.as_deref())
+    )]
+    ExportNotFound {
+        module: String,
+        name: String,
+        available: Vec<String>,
+        suggestion: Option<String>,
+    },
+    /// Managed code raised an error with `Raise`. The kind names the error
+    /// for the code that catches it, e.g. `"error"` for a raised string.
+    #[error("Raised error ({kind}): {message}")]
+    Raised { kind: String, message: String },
+    /// The embedder's I/O backend reported an error.
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// An error raised while managed code was running, with the managed
+    /// frames that were active, outermost first. Errors are only wrapped like
+    /// this when [`super::Runtime::set_capture_stack_traces`] is enabled.
+    #[error("{error}")]
+    WithStackTrace {
+        error: Box<RuntimeError>,
+        trace: Vec<StackTraceFrame>,
+    },
 }
 
 impl RuntimeError {
@@ -54,6 +208,195 @@ impl RuntimeError {
     pub fn new_internal_error<'a>(message: impl Into<Cow<'a, str>>) -> Self {
         Self::InternalError(message.into().into_owned())
     }
+
+    /// Reports a frame stack that would grow past `limit`, in a native
+    /// function unless [`Self::with_frame_function`] names the function.
+    pub(crate) fn new_stack_limit_error(limit: usize) -> Self {
+        RuntimeError::StackLimitExceeded {
+            function: "<native>".to_string(),
+            limit,
+        }
+    }
+
+    /// Names `function` as the one whose frame stack grew past the limit.
+    /// Other errors are returned unchanged.
+    pub(crate) fn with_frame_function(self, function: &FunctionId) -> Self {
+        match self {
+            RuntimeError::StackLimitExceeded { limit, .. } => RuntimeError::StackLimitExceeded {
+                function: function.to_string(),
+                limit,
+            },
+            error => error,
+        }
+    }
+
+    /// Prefixes the message of a type or conversion error with `context`,
+    /// such as which value failed to convert. Other errors are returned
+    /// unchanged.
+    pub(crate) fn with_context(self, context: impl std::fmt::Display) -> Self {
+        match self {
+            RuntimeError::Type(TypeError { message }) => RuntimeError::Type(TypeError {
+                message: format!("{context}: {message}"),
+            }),
+            RuntimeError::Conversion(ConversionError { message }) => {
+                RuntimeError::Conversion(ConversionError {
+                    message: format!("{context}: {message}"),
+                })
+            }
+            error => error,
+        }
+    }
+
+    #[must_use]
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            RuntimeError::Type(_)
+            | RuntimeError::Conversion(_)
+            | RuntimeError::OperationPrecondition(_)
+            | RuntimeError::CapabilityNotGranted { .. }
+            | RuntimeError::ModuleAlreadyLoaded { .. }
+            | RuntimeError::DependencyNotLoaded { .. }
+            | RuntimeError::TooManyBoundArguments { .. }
+            | RuntimeError::Validation { .. }
+            | RuntimeError::ExportNotFound { .. }
+            | RuntimeError::NativePanic(_)
+            | RuntimeError::Raised { .. }
+            | RuntimeError::Io(_) => ErrorKind::UserError,
+            RuntimeError::OutOfFuel
+            | RuntimeError::Timeout
+            | RuntimeError::NativeCallTimeout(_)
+            | RuntimeError::NestingTooDeep(_)
+            | RuntimeError::StackLimitExceeded { .. }
+            | RuntimeError::StackOverflow { .. }
+            | RuntimeError::TenantQuotaExceeded { .. } => ErrorKind::ResourceLimit,
+            RuntimeError::InternalError(_) => ErrorKind::Internal,
+            RuntimeError::Cancelled => ErrorKind::Cancelled,
+            RuntimeError::InitializerInterrupted { error, .. }
+            | RuntimeError::WithStackTrace { error, .. } => error.kind(),
+        }
+    }
+
+    /// Returns the managed frames that were active when the error was raised,
+    /// outermost first. Empty unless stack traces are captured.
+    #[must_use]
+    pub fn stack_trace(&self) -> &[StackTraceFrame] {
+        match self {
+            RuntimeError::WithStackTrace { trace, .. } => trace,
+            _ => &[],
+        }
+    }
+
+    /// Returns the error without its stack trace, if it has one.
+    #[must_use]
+    pub fn without_stack_trace(self) -> RuntimeError {
+        match self {
+            RuntimeError::WithStackTrace { error, .. } => *error,
+            error => error,
+        }
+    }
+
+    /// Adds `frames`, which enclose those already in the trace, to the front
+    /// of the error's stack trace.
+    pub(crate) fn with_outer_frames(self, mut frames: Vec<StackTraceFrame>) -> RuntimeError {
+        match self {
+            RuntimeError::WithStackTrace { error, trace } => {
+                frames.extend(trace);
+                RuntimeError::WithStackTrace {
+                    error,
+                    trace: frames,
+                }
+            }
+            error => RuntimeError::WithStackTrace {
+                error: Box::new(error),
+                trace: frames,
+            },
+        }
+    }
+
+    /// Returns the kind and message of the error value a protected call
+    /// catches this error as, or `None` if it cannot be caught. Only user
+    /// errors can be caught, so that resource limits, cancellation and
+    /// internal errors always reach the embedder.
+    pub(crate) fn caught_kind_and_message(&self) -> Option<(String, String)> {
+        if self.kind() != ErrorKind::UserError {
+            return None;
+        }
+        Some(match self {
+            RuntimeError::Type(TypeError { message }) => ("type".to_string(), message.clone()),
+            RuntimeError::Conversion(ConversionError { message }) => {
+                ("conversion".to_string(), message.clone())
+            }
+            RuntimeError::OperationPrecondition(OperationPreconditionError { message }) => {
+                ("precondition".to_string(), message.clone())
+            }
+            RuntimeError::Raised { kind, message } => (kind.clone(), message.clone()),
+            RuntimeError::WithStackTrace { error, .. } => return error.caught_kind_and_message(),
+            error => ("runtime".to_string(), error.to_string()),
+        })
+    }
+
+    /// Returns true if the error indicates a bug in the VM rather than in the
+    /// script or the embedder.
+    #[must_use]
+    pub fn is_internal(&self) -> bool {
+        self.kind() == ErrorKind::Internal
+    }
+
+    /// Returns true if the same operation may succeed when retried, e.g.
+    /// after adding fuel, raising a limit, or clearing a cancellation.
+    #[must_use]
+    pub fn is_retriable(&self) -> bool {
+        matches!(self.kind(), ErrorKind::ResourceLimit | ErrorKind::Cancelled)
+    }
+}
+
+/// The number of exports listed in an [`RuntimeError::ExportNotFound`]
+/// message. Larger modules are cut short.
+const MAX_LISTED_EXPORTS: usize = 20;
+
+fn describe_exports(available: &[String], suggestion: Option<&str>) -> String {
+    let mut description = String::new();
+    if let Some(suggestion) = suggestion {
+        description.push_str(&format!(" Did you mean {suggestion:?}?"));
+    }
+    if available.is_empty() {
+        description.push_str(" It has no exports.");
+    } else {
+        let listed = available
+            .iter()
+            .take(MAX_LISTED_EXPORTS)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        description.push_str(&format!(" Available exports: {listed}"));
+        if available.len() > MAX_LISTED_EXPORTS {
+            let rest = available.len() - MAX_LISTED_EXPORTS;
+            description.push_str(&format!(", and {rest} more"));
+        }
+        description.push('.');
+    }
+    description
 }
 
 pub type Result<T> = std::result::Result<T, RuntimeError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_are_classified() {
+        let user = RuntimeError::new_type_error("bad type");
+        assert_eq!(user.kind(), ErrorKind::UserError);
+        assert!(!user.is_internal());
+        assert!(!user.is_retriable());
+
+        let internal = RuntimeError::new_internal_error("bug");
+        assert!(internal.is_internal());
+        assert!(!internal.is_retriable());
+
+        assert!(RuntimeError::OutOfFuel.is_retriable());
+        assert_eq!(RuntimeError::Timeout.kind(), ErrorKind::ResourceLimit);
+        assert_eq!(RuntimeError::Cancelled.kind(), ErrorKind::Cancelled);
+    }
+}
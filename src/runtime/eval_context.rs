@@ -6,112 +6,341 @@ use super::{
     error::Result,
     global_env::GlobalEnv,
     instructions::FrameChange,
-    stack_frame::{LocalStack, StackFrame},
-    value::Function,
-    RuntimeError,
+    stack_frame::{LocalStack, PinnedValueBuffer, StackFrame},
+    value::{ErrorValue, Function, NativeCallInfo, PinnedValue},
+    FunctionId, RuntimeError,
 };
 
-struct Inner {
-    call_stack: RefCell<Vec<GcRef<StackFrame>>>,
+/// The frames of a call that is being evaluated. It is kept apart from the
+/// [`EvalContext`] so that a paused call can be resumed later.
+pub(crate) struct CallStack {
+    frames: RefCell<Vec<GcRef<StackFrame>>>,
 }
 
-impl GcTraceable for Inner {
+impl CallStack {
+    pub fn new(global_context: &GlobalEnv) -> PinnedGcRef<Self> {
+        global_context.create_pinned_ref(CallStack {
+            frames: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Returns the functions of the managed frames, outermost first.
+    pub fn function_ids(&self) -> Vec<FunctionId> {
+        self.frames
+            .borrow()
+            .iter()
+            .filter_map(|frame| frame.borrow().function_id())
+            .collect()
+    }
+}
+
+impl GcTraceable for CallStack {
     fn trace<V>(&self, visitor: &mut V)
     where
         V: crate::gc::GcRefVisitor,
     {
-        for frame in self.call_stack.borrow().iter() {
+        for frame in self.frames.borrow().iter() {
             frame.trace(visitor);
         }
     }
 }
 
+/// How a bounded run of an [`EvalContext`] stopped.
+pub(crate) enum EvalOutcome {
+    /// The call returned this many values to the parent stack.
+    Returned(u32),
+    /// The step budget ran out.
+    Paused,
+    /// A native function yielded. The frames stay on the call stack, so
+    /// that the call can be resumed.
+    Yielded,
+}
+
 pub struct EvalContext<'a> {
     global_context: &'a GlobalEnv,
     parent_stack: &'a PinnedGcRef<LocalStack>,
-    inner: PinnedGcRef<Inner>,
+    call_stack: PinnedGcRef<CallStack>,
+    /// The number of frames in enclosing evaluations.
+    base_depth: usize,
 }
 
 impl<'a> EvalContext<'a> {
-    pub fn new(global_context: &'a GlobalEnv, parent_stack: &'a PinnedGcRef<LocalStack>) -> Self {
-        let inner = global_context.create_pinned_ref(Inner {
-            call_stack: RefCell::new(Vec::new()),
-        });
+    pub fn new(
+        global_context: &'a GlobalEnv,
+        parent_stack: &'a PinnedGcRef<LocalStack>,
+        base_depth: usize,
+    ) -> Self {
+        Self::with_call_stack(
+            global_context,
+            parent_stack,
+            CallStack::new(global_context),
+            base_depth,
+        )
+    }
 
+    /// Creates a context that continues evaluating the frames of
+    /// `call_stack`.
+    pub fn with_call_stack(
+        global_context: &'a GlobalEnv,
+        parent_stack: &'a PinnedGcRef<LocalStack>,
+        call_stack: PinnedGcRef<CallStack>,
+        base_depth: usize,
+    ) -> Self {
         EvalContext {
             global_context,
             parent_stack,
-            inner,
+            call_stack,
+            base_depth,
+        }
+    }
+
+    pub fn call_stack(&self) -> &PinnedGcRef<CallStack> {
+        &self.call_stack
+    }
+
+    /// Describes the call that created the frame on top of the call stack.
+    fn top_call_info(&self) -> NativeCallInfo {
+        let call_stack = self.call_stack.frames.borrow();
+        let caller = call_stack
+            .len()
+            .checked_sub(2)
+            .and_then(|i| call_stack[i].borrow().caller_info());
+        NativeCallInfo {
+            depth: self.base_depth + call_stack.len() - 1,
+            caller,
         }
     }
 
+    /// Runs the call of `function` with the top `num_args` values of the
+    /// parent stack until it returns, and returns the number of values it
+    /// pushed onto the parent stack.
+    ///
+    /// If a native function yields, the call is suspended instead. The parent
+    /// stack then receives a continuation that resumes it, followed by the
+    /// values the yielding function left on its stack.
     pub fn run(&mut self, function: &PinnedGcRef<Function>, num_args: u32) -> Result<u32> {
+        self.push_call(function, num_args)?;
+        loop {
+            let mut budget = u64::MAX;
+            match self.run_steps(&mut budget)? {
+                EvalOutcome::Returned(num_returns) => return Ok(num_returns),
+                EvalOutcome::Paused => {}
+                EvalOutcome::Yielded => return self.suspend(function),
+            }
+        }
+    }
+
+    /// Moves the frames of the call of `function` into a continuation, and
+    /// pushes it and the yielded values onto the parent stack.
+    fn suspend(&self, function: &PinnedGcRef<Function>) -> Result<u32> {
+        let function_id = function.id()?;
+        let frames = std::mem::take(&mut *self.call_stack.frames.borrow_mut())
+            .iter()
+            .map(GcRef::pin)
+            .collect::<Vec<_>>();
+        self.global_context.with_value_buffer(|buf| {
+            let yielding_frame = frames
+                .last()
+                .ok_or_else(|| RuntimeError::new_internal_error("Call stack is empty."))?;
+            yielding_frame.drain_top_n(yielding_frame.stack_len() as u32, buf)?;
+            let num_yielded = buf.len() as u32;
+            let continuation = Function::new_continuation(self.global_context, frames, function_id);
+            self.parent_stack.push(continuation.into())?;
+            self.parent_stack
+                .push_iter(self.global_context, buf.drain(..))?;
+            Ok(num_yielded + 1)
+        })
+    }
+
+    /// Starts a call of `function` with the top `num_args` values of the
+    /// parent stack, without running it.
+    pub fn push_call(&self, function: &PinnedGcRef<Function>, num_args: u32) -> Result<()> {
+        self.global_context.with_value_buffer(|buffer| {
+            self.parent_stack.drain_top_n(num_args, buffer)?;
+            self.enter(function, buffer)
+        })
+    }
+
+    /// Pushes the frames that run `function` with `args` onto the call stack.
+    /// A continuation pushes the frames of the call it suspended.
+    fn enter(&self, function: &PinnedGcRef<Function>, args: &mut PinnedValueBuffer) -> Result<()> {
+        if let Some(frames) =
+            function.resume_continuation(self.global_context, args, |num_frames| {
+                self.check_call_depth(num_frames)
+            })?
         {
-            let stack_frame = self.global_context.with_value_buffer(|buffer| {
-                self.parent_stack.drain_top_n(num_args, buffer)?;
-                function.make_stack_frame(self.global_context, buffer)
-            })?;
             self.global_context.with_lock(|lock| {
-                self.inner
-                    .call_stack
+                self.call_stack
+                    .frames
                     .borrow_mut()
-                    .push(stack_frame.into_ref(lock.guard()))
+                    .extend(frames.into_iter().map(|frame| frame.into_ref(lock.guard())))
             });
+            return Ok(());
+        }
+        self.check_call_depth(1)?;
+        let stack_frame = function.make_stack_frame(self.global_context, args)?;
+        self.global_context.with_lock(|lock| {
+            self.call_stack
+                .frames
+                .borrow_mut()
+                .push(stack_frame.into_ref(lock.guard()))
+        });
+        Ok(())
+    }
+
+    /// Fails if pushing `new_frames` more frames would make more calls active
+    /// than the runtime allows, counting those of enclosing contexts.
+    fn check_call_depth(&self, new_frames: usize) -> Result<()> {
+        if let Some(limit) = self.global_context.max_call_depth() {
+            let depth = self.base_depth + self.call_stack.frames.borrow().len() + new_frames;
+            if depth > limit {
+                return Err(RuntimeError::StackOverflow { limit });
+            }
         }
+        Ok(())
+    }
+
+    /// Runs the call for at most `budget` steps, taking the steps that ran
+    /// from `budget`.
+    ///
+    /// If stack traces are captured, errors carry the managed frames of this
+    /// context in front of any frames added by nested contexts.
+    pub fn run_steps(&mut self, budget: &mut u64) -> Result<EvalOutcome> {
+        self.run_steps_untraced(budget).map_err(|error| {
+            if !self.global_context.capture_stack_traces() {
+                return error;
+            }
+            let frames = self
+                .call_stack
+                .frames
+                .borrow()
+                .iter()
+                .filter_map(|frame| frame.borrow().stack_trace_frame())
+                .collect();
+            error.with_outer_frames(frames)
+        })
+    }
+
+    /// Runs the call like [`Self::run_frames`], catching errors in the
+    /// nearest frame that is waiting on a protected call.
+    fn run_steps_untraced(&mut self, budget: &mut u64) -> Result<EvalOutcome> {
         loop {
-            let frame = self.inner.call_stack.borrow().last().unwrap().pin();
-            match frame.run_to_frame_change(self.global_context)? {
+            match self.run_frames(budget) {
+                Err(error) if self.catch(&error)? => {}
+                outcome => return outcome,
+            }
+        }
+    }
+
+    /// Unwinds the call stack to the nearest frame waiting on a protected
+    /// call, which continues with the error as a value. Returns false, with
+    /// the call stack unchanged, if the error cannot be caught or no frame of
+    /// this context is waiting on a protected call.
+    fn catch(&self, error: &RuntimeError) -> Result<bool> {
+        let Some(error_value) = ErrorValue::caught(error) else {
+            return Ok(false);
+        };
+        let handler_frame = {
+            let mut frames = self.call_stack.frames.borrow_mut();
+            let Some(index) = frames
+                .iter()
+                .rposition(|frame| frame.borrow().has_handler())
+            else {
+                return Ok(false);
+            };
+            frames.truncate(index + 1);
+            frames[index].pin()
+        };
+        handler_frame.catch_error(PinnedValue::new_error(error_value))?;
+        Ok(true)
+    }
+
+    fn run_frames(&mut self, budget: &mut u64) -> Result<EvalOutcome> {
+        let sampler = self.global_context.stack_sampler();
+        let _active = sampler
+            .as_ref()
+            .map(|_| self.global_context.enter_call_stack(&self.call_stack));
+        loop {
+            let frame = self
+                .call_stack
+                .frames
+                .borrow()
+                .last()
+                .ok_or_else(|| RuntimeError::new_internal_error("Call stack is empty."))?
+                .pin();
+            // While sampling, frames are run only up to the next sample.
+            let mut slice = match &sampler {
+                Some(sampler) => (*budget).min(sampler.until_next()),
+                None => *budget,
+            };
+            let slice_len = slice;
+            let frame_change =
+                frame.run_to_frame_change(self.global_context, || self.top_call_info(), &mut slice);
+            let steps = slice_len - slice;
+            *budget -= steps;
+            if let Some(sampler) = &sampler {
+                if sampler.advance(steps) {
+                    self.global_context.record_stack_sample(sampler);
+                }
+            }
+            let Some(frame_change) = frame_change? else {
+                if *budget == 0 {
+                    return Ok(EvalOutcome::Paused);
+                }
+                continue;
+            };
+            match frame_change {
                 FrameChange::Return(num_returns) => {
                     let prev_frame = self
-                        .inner
                         .call_stack
+                        .frames
                         .borrow_mut()
                         .pop()
                         .expect("Call stack is empty.")
                         .pin();
-                    if let Some(frame) = self.inner.call_stack.borrow().last() {
+                    let shrink_policy = self.global_context.stack_shrink_policy();
+                    if let Some(frame) = self.call_stack.frames.borrow().last() {
                         self.global_context.with_value_buffer(|buf| {
                             prev_frame.drain_top_n(num_returns, buf)?;
-                            frame.borrow().push_iter(self.global_context, buf.drain(..));
-                            Ok::<_, RuntimeError>(())
+                            frame.borrow().push_iter(self.global_context, buf.drain(..))
                         })?;
+                        if let Some(policy) = shrink_policy {
+                            frame.borrow().shrink_stack_if_sparse(policy);
+                        }
                     } else {
-                        return self.global_context.with_value_buffer(|buf| {
+                        self.global_context.with_value_buffer(|buf| {
                             prev_frame.drain_top_n(num_returns, buf)?;
                             self.parent_stack
-                                .push_iter(self.global_context, buf.drain(..));
-                            Ok(num_returns)
-                        });
+                                .push_iter(self.global_context, buf.drain(..))
+                        })?;
+                        if let Some(policy) = shrink_policy {
+                            self.parent_stack.shrink_if_sparse(policy);
+                        }
+                        return Ok(EvalOutcome::Returned(num_returns));
                     }
                 }
                 FrameChange::Call(call) => {
-                    let stack_frame = self.global_context.with_value_buffer(|buf| {
+                    self.global_context.with_value_buffer(|buf| {
                         frame.drain_top_n(call.num_args, buf)?;
-                        let function = frame.pop()?.as_function()?.clone();
-                        let stack_frame = function.make_stack_frame(self.global_context, buf)?;
-                        Ok::<_, RuntimeError>(stack_frame)
+                        let function = match call.function {
+                            Some(function) => function,
+                            None => frame.pop()?.as_function()?.clone(),
+                        };
+                        self.enter(&function, buf)
                     })?;
-                    self.global_context.with_lock(|lock| {
-                        self.inner
-                            .call_stack
-                            .borrow_mut()
-                            .push(stack_frame.into_ref(lock.guard()))
-                    });
                 }
                 FrameChange::TailCall(call) => {
-                    let stack_frame = self.global_context.with_value_buffer(|buf| {
+                    self.global_context.with_value_buffer(|buf| {
                         frame.drain_top_n(call.num_args, buf)?;
-                        let function = frame.pop()?.as_function()?.clone();
-                        let stack_frame = function.make_stack_frame(self.global_context, buf)?;
-                        Ok::<_, RuntimeError>(stack_frame)
+                        let function = match call.function {
+                            Some(function) => function,
+                            None => frame.pop()?.as_function()?.clone(),
+                        };
+                        self.call_stack.frames.borrow_mut().pop();
+                        self.enter(&function, buf)
                     })?;
-                    let mut call_stack = self.inner.call_stack.borrow_mut();
-                    call_stack.pop();
-                    self.global_context.with_lock(|lock| {
-                        call_stack.push(stack_frame.into_ref(lock.guard()));
-                    });
                 }
-                FrameChange::YieldCall(_call) => todo!(),
+                FrameChange::YieldCall(_call) => return Ok(EvalOutcome::Yielded),
             }
         }
     }
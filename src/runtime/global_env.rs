@@ -1,30 +1,98 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    any::{Any, TypeId},
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    rc::{Rc, Weak},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 
 use super::{
+    capabilities::CapabilitySet,
+    debug::DebugState,
     error::{Result, RuntimeError},
+    eval_context::CallStack,
+    handle::ValueHandle,
     inst_set::{
-        Add, BindFront, BoolAnd, BoolNot, BoolOr, BoolXor, Branch, BranchIf, Call, CallDynamic,
-        Compare, ListAppend, ListGet, ListLen, ListNew, ListSet, Pop, PushConst, PushCopy,
-        PushGlobal, Return, ReturnDynamic, SetGlobal, TailCall, WriteStack,
+        Add, Apply, BindFront, BoolAnd, BoolNot, BoolOr, BoolXor, Branch, BranchIf, BranchIfTruthy,
+        Call, CallDynamic, CallProtected, CellGet, CellNew, CellSet, CmpBranch, Compare, Div,
+        IdentityHash, IsNull, ListAppend, ListGet, ListGetOrNull, ListGetRel, ListLen, ListNew,
+        ListSet, ListSetRel, ListSlice, Mul, Pop, PushConst, PushCopy, PushGlobal, Raise,
+        RecordGet, RecordNew, RecordSet, Return, ReturnDynamic, SetGlobal, StrLenBytes,
+        StrLenChars, StrSliceBytes, StrSliceChars, Sub, TagNew, TagOf, TagWrap, TailCall, ToBool,
+        ToNumber, Untag, WeakGet, WeakNew, WriteStack,
     },
     instructions::{InstEvalList, InstPtr},
+    limits::{CancelHandle, ExecutionLimits},
     modules::Module,
-    stack_frame::PinnedValueBuffer,
-    value::{Function, PinnedValue},
+    native_module::NativeModule,
+    profile::{FunctionProfile, StackSampler, TierUpPolicy},
+    quota::{ActiveAccounts, HeapAccount},
+    stack_frame::{PinnedValueBuffer, StackShrinkPolicy},
+    timers::{no_active_timers, TimerQueue},
+    value::{Function, PinnedValue, RecordShape},
+    FunctionId,
 };
 use crate::{
     binary::{
         self,
         instructions::{Instruction, InstructionList},
-        modules::{ImportSource, ModuleId},
+        modules::{ImportSource, ModuleId, ModuleMemberId},
+        InstructionPolicy,
     },
-    gc::{CollectGuard, GcEnv, GcRef, GcRefVisitor, GcTraceable, PinnedGcRef},
+    gc::{CollectGuard, GcEnv, GcRef, GcRefVisitor, GcStats, GcTraceable, GcTrigger, PinnedGcRef},
+    util::imm_string::ImmString,
 };
 
+const INITIAL_PRUNE_THRESHOLD: usize = 64;
+
+/// The id of the next runtime to be created. Ids are never reused within a
+/// process, unlike the addresses of runtimes that have been dropped.
+static NEXT_RUNTIME_ID: AtomicUsize = AtomicUsize::new(0);
+
 struct Inner {
     loaded_modules: RefCell<HashMap<ModuleId, GcRef<Module>>>,
-    // Precondition: All buffers are empty.
+    /// Canonical copies of the ids of modules that have been loaded.
+    module_ids: RefCell<HashSet<ModuleId>>,
+    /// Canonical copies of the names exported by loaded modules.
+    member_names: RefCell<HashSet<ModuleMemberId>>,
+    /// Buffers free for reuse by `with_value_buffer`. Every buffer here is
+    /// empty, which `ValueBufferGuard` maintains.
     value_buffers: RefCell<Vec<PinnedValueBuffer>>,
+    limits: ExecutionLimits,
+    tier_up_policy: RefCell<Option<Rc<TierUpPolicy>>>,
+    stack_sampler: RefCell<Option<Rc<StackSampler>>>,
+    /// The call stacks being run, outermost first, while stacks are being
+    /// sampled.
+    active_call_stacks: RefCell<Vec<PinnedGcRef<CallStack>>>,
+    /// The callback queues of the top levels running calls, outermost first.
+    active_timers: RefCell<Vec<Rc<TimerQueue>>>,
+    debug: DebugState,
+    heap_accounts: ActiveAccounts,
+    const_eval_initializers: Cell<bool>,
+    peephole_optimize: Cell<bool>,
+    allow_module_replacement: Cell<bool>,
+    init_modules_on_load: Cell<bool>,
+    capture_stack_traces: Cell<bool>,
+    stack_shrink_policy: Cell<Option<StackShrinkPolicy>>,
+    granted_capabilities: RefCell<HashMap<ModuleId, CapabilitySet>>,
+    instruction_policies: RefCell<HashMap<ModuleId, InstructionPolicy>>,
+    next_host_function_id: Cell<u64>,
+    /// Resolved instructions, shared between functions with identical
+    /// instruction lists.
+    resolved_instructions: RefCell<HashMap<InstructionList, Weak<InstEvalList>>>,
+    /// The cache size at which entries for dropped lists are removed.
+    resolved_prune_threshold: Cell<usize>,
+    /// The field names of records, shared by all records with the same
+    /// fields in the same order.
+    record_shapes: RefCell<HashMap<Vec<ImmString>, Rc<RecordShape>>>,
+    /// Embedder state for native functions, keyed by its type.
+    host_data: RefCell<HashMap<TypeId, Rc<dyn Any>>>,
+    /// Values retained by the embedder, keyed by handle id. Pinned values
+    /// are roots, so these stay alive until released.
+    handles: RefCell<HashMap<u64, PinnedValue>>,
+    next_handle_id: Cell<u64>,
+    runtime_id: usize,
 }
 
 impl Inner {
@@ -32,12 +100,37 @@ impl Inner {
         let loaded_modules = self.loaded_modules.borrow();
         loaded_modules
             .get(import_source.module_id())
-            .ok_or_else(|| RuntimeError::new_internal_error("Module not found in global context."))?
+            .ok_or_else(|| RuntimeError::new_operation_precondition_error("Module not loaded."))?
             .borrow()
-            .get_export(import_source.import_name())
+            .get_export(import_source)
+    }
+
+    /// Returns the resolved form of `inst_list`, reusing the result for an
+    /// identical list if one is still in use.
+    pub fn resolve_instructions_shared(
+        &self,
+        inst_list: &InstructionList,
+    ) -> Result<Rc<InstEvalList>> {
+        if let Some(resolved) = self
+            .resolved_instructions
+            .borrow()
+            .get(inst_list)
+            .and_then(Weak::upgrade)
+        {
+            return Ok(resolved);
+        }
+        let resolved = Rc::new(self.resolve_instructions(inst_list)?);
+        let mut cache = self.resolved_instructions.borrow_mut();
+        if cache.len() >= self.resolved_prune_threshold.get() {
+            cache.retain(|_, resolved| resolved.strong_count() > 0);
+            self.resolved_prune_threshold
+                .set((cache.len() * 2).max(INITIAL_PRUNE_THRESHOLD));
+        }
+        cache.insert(inst_list.clone(), Rc::downgrade(&resolved));
+        Ok(resolved)
     }
 
-    pub fn resolve_instructions(&self, inst_list: &InstructionList) -> Result<InstEvalList> {
+    fn resolve_instructions(&self, inst_list: &InstructionList) -> Result<InstEvalList> {
         let inst_slice = inst_list.instructions();
         let result = inst_slice
             .iter()
@@ -50,6 +143,9 @@ impl Inner {
                     Instruction::WriteStack(i) => InstPtr::new(WriteStack::new(*i)),
                     Instruction::Pop(i) => InstPtr::new(Pop::new(*i)),
                     Instruction::Add => InstPtr::new(Add),
+                    Instruction::Sub => InstPtr::new(Sub),
+                    Instruction::Mul => InstPtr::new(Mul),
+                    Instruction::Div => InstPtr::new(Div),
                     Instruction::BoolAnd => InstPtr::new(BoolAnd),
                     Instruction::BoolOr => InstPtr::new(BoolOr),
                     Instruction::BoolXor => InstPtr::new(BoolXor),
@@ -58,16 +154,51 @@ impl Inner {
                     Instruction::ListAppend => InstPtr::new(ListAppend),
                     Instruction::ListLen => InstPtr::new(ListLen),
                     Instruction::ListGet => InstPtr::new(ListGet),
+                    Instruction::ListGetOrNull => InstPtr::new(ListGetOrNull),
                     Instruction::ListSet => InstPtr::new(ListSet),
+                    Instruction::ListGetRel => InstPtr::new(ListGetRel),
+                    Instruction::ListSetRel => InstPtr::new(ListSetRel),
+                    Instruction::ListSlice => InstPtr::new(ListSlice),
+                    Instruction::CellNew => InstPtr::new(CellNew),
+                    Instruction::CellGet => InstPtr::new(CellGet),
+                    Instruction::CellSet => InstPtr::new(CellSet),
+                    Instruction::WeakNew => InstPtr::new(WeakNew),
+                    Instruction::WeakGet => InstPtr::new(WeakGet),
+                    Instruction::RecordNew(i) => InstPtr::new(RecordNew::new(*i)),
+                    Instruction::RecordGet(i) => InstPtr::new(RecordGet::new(*i)),
+                    Instruction::RecordSet(i) => InstPtr::new(RecordSet::new(*i)),
+                    Instruction::TagNew(i) => InstPtr::new(TagNew::new(*i)),
+                    Instruction::TagWrap => InstPtr::new(TagWrap),
+                    Instruction::TagOf => InstPtr::new(TagOf),
+                    Instruction::Untag => InstPtr::new(Untag),
+                    Instruction::StrLenBytes => InstPtr::new(StrLenBytes),
+                    Instruction::StrLenChars => InstPtr::new(StrLenChars),
+                    Instruction::StrSliceBytes => InstPtr::new(StrSliceBytes),
+                    Instruction::StrSliceChars => InstPtr::new(StrSliceChars),
                     Instruction::Compare(cmp_op) => InstPtr::new(Compare::new(*cmp_op)),
+                    Instruction::IsNull => InstPtr::new(IsNull),
+                    Instruction::IdentityHash => InstPtr::new(IdentityHash),
+                    Instruction::ToBool(truthiness) => InstPtr::new(ToBool::new(*truthiness)),
+                    Instruction::ToNumber(kind) => InstPtr::new(ToNumber::new(*kind)),
                     Instruction::Branch(target) => InstPtr::new(Branch::new(*target)),
                     Instruction::BranchIf(target) => InstPtr::new(BranchIf::new(*target)),
+                    Instruction::BranchIfTruthy(target, truthiness) => {
+                        InstPtr::new(BranchIfTruthy::new(*target, *truthiness))
+                    }
+                    Instruction::CmpBranch(cmp_op, target) => {
+                        InstPtr::new(CmpBranch::new(*cmp_op, *target))
+                    }
                     Instruction::Call(i) => InstPtr::new(Call::new(*i)),
                     Instruction::CallDynamic => InstPtr::new(CallDynamic),
+                    Instruction::Apply => InstPtr::new(Apply),
                     Instruction::Return(i) => InstPtr::new(Return::new(*i)),
                     Instruction::ReturnDynamic => InstPtr::new(ReturnDynamic),
                     Instruction::TailCall(i) => InstPtr::new(TailCall::new(*i)),
                     Instruction::BindFront(i) => InstPtr::new(BindFront::new(*i)),
+                    Instruction::CallProtected(i, handler) => {
+                        InstPtr::new(CallProtected::new(*i, *handler))
+                    }
+                    Instruction::Raise => InstPtr::new(Raise),
                 })
             })
             .collect::<Result<Vec<_>>>()?;
@@ -87,6 +218,11 @@ impl GcTraceable for Inner {
     }
 }
 
+fn downcast_host_data<T: 'static>(data: Rc<dyn Any>) -> Rc<T> {
+    data.downcast()
+        .unwrap_or_else(|_| unreachable!("Host data is keyed by its type."))
+}
+
 #[derive(Clone)]
 pub(crate) struct GlobalEnv {
     gc_env: GcEnv,
@@ -99,13 +235,62 @@ impl GlobalEnv {
         let gc_env = GcEnv::new(1);
         let inner = gc_env.create_pinned_ref(Inner {
             loaded_modules: RefCell::new(HashMap::new()),
+            module_ids: RefCell::new(HashSet::new()),
+            member_names: RefCell::new(HashSet::new()),
             value_buffers: RefCell::new(Vec::new()),
+            limits: ExecutionLimits::new(),
+            tier_up_policy: RefCell::new(None),
+            stack_sampler: RefCell::new(None),
+            active_call_stacks: RefCell::new(Vec::new()),
+            active_timers: RefCell::new(Vec::new()),
+            debug: DebugState::new(),
+            heap_accounts: ActiveAccounts::new(),
+            const_eval_initializers: Cell::new(false),
+            peephole_optimize: Cell::new(false),
+            allow_module_replacement: Cell::new(false),
+            init_modules_on_load: Cell::new(false),
+            capture_stack_traces: Cell::new(false),
+            stack_shrink_policy: Cell::new(Some(StackShrinkPolicy::default())),
+            granted_capabilities: RefCell::new(HashMap::new()),
+            instruction_policies: RefCell::new(HashMap::new()),
+            next_host_function_id: Cell::new(0),
+            resolved_instructions: RefCell::new(HashMap::new()),
+            resolved_prune_threshold: Cell::new(INITIAL_PRUNE_THRESHOLD),
+            record_shapes: RefCell::new(HashMap::new()),
+            host_data: RefCell::new(HashMap::new()),
+            handles: RefCell::new(HashMap::new()),
+            next_handle_id: Cell::new(0),
+            runtime_id: NEXT_RUNTIME_ID.fetch_add(1, Ordering::Relaxed),
         });
         GlobalEnv { gc_env, inner }
     }
 
-    pub fn resolve_instructions(&self, inst_list: &InstructionList) -> Result<InstEvalList> {
-        self.inner.resolve_instructions(inst_list)
+    /// Resolves an instruction list for evaluation. Identical lists share
+    /// the same resolved instructions, across functions and modules.
+    pub fn resolve_instructions(&self, inst_list: &InstructionList) -> Result<Rc<InstEvalList>> {
+        self.inner.resolve_instructions_shared(inst_list)
+    }
+
+    /// Returns the shape of records with the given fields, in order, failing
+    /// if a field is named more than once.
+    pub(crate) fn record_shape(&self, fields: Vec<ImmString>) -> Result<Rc<RecordShape>> {
+        if let Some(shape) = self.inner.record_shapes.borrow().get(&fields) {
+            return Ok(shape.clone());
+        }
+        let shape = Rc::new(RecordShape::new(fields.clone())?);
+        self.inner
+            .record_shapes
+            .borrow_mut()
+            .insert(fields, shape.clone());
+        Ok(shape)
+    }
+
+    /// Returns a new id for a native function created outside of a native
+    /// module.
+    pub fn next_host_function_id(&self) -> u64 {
+        let id = self.inner.next_host_function_id.get();
+        self.inner.next_host_function_id.set(id + 1);
+        id
     }
 
     pub fn with_lock<F, R>(&self, body: F) -> R
@@ -116,21 +301,25 @@ impl GlobalEnv {
             .with_lock(|guard| body(&GlobalEnvLock { gc_guard: guard }))
     }
 
+    /// Calls `body` with an empty buffer, reusing one from an earlier call if
+    /// possible. The buffer is cleared once `body` returns, and discarded if
+    /// it panics, so values left in it never outlive the call.
     pub fn with_value_buffer<F, R>(&self, body: F) -> R
     where
         F: FnOnce(&mut PinnedValueBuffer) -> R,
     {
-        let mut buffer = self
+        let buffer = self
             .inner
             .value_buffers
             .borrow_mut()
             .pop()
             .unwrap_or_default();
-
-        let result = body(&mut buffer);
-        buffer.clear();
-        self.inner.value_buffers.borrow_mut().push(buffer);
-        result
+        debug_assert!(buffer.is_empty(), "Pooled value buffer is not empty.");
+        let mut guard = ValueBufferGuard {
+            buffers: &self.inner.value_buffers,
+            buffer,
+        };
+        body(&mut guard.buffer)
     }
 
     pub fn create_pinned_ref<T>(&self, value: T) -> PinnedGcRef<T>
@@ -140,25 +329,557 @@ impl GlobalEnv {
         self.gc_env.create_pinned_ref(value)
     }
 
-    /// Loads a module into this global context.
+    pub fn dump_graph_dot(&self, w: impl std::io::Write) -> std::io::Result<()> {
+        self.gc_env.dump_graph_dot(w)
+    }
+
+    pub fn set_gc_trigger(&self, trigger: GcTrigger) {
+        self.gc_env.set_trigger(trigger);
+    }
+
+    pub fn gc_trigger(&self) -> GcTrigger {
+        self.gc_env.trigger()
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.gc_env.stats()
+    }
+
+    /// Collects garbage, then drops interned ids and names that are no
+    /// longer used.
+    pub fn collect_garbage(&self) -> bool {
+        let collected = self.gc_env.collect();
+        self.prune_interned();
+        collected
+    }
+
+    /// Marks a point in execution where all live values are reachable from
+    /// pinned roots. `steps` is the number of instructions executed since the
+    /// last safe point.
+    ///
+    /// Execution limits are checked here, and any pending garbage collection
+    /// is performed.
+    pub fn safe_point(&self, steps: u64) -> Result<()> {
+        self.inner.limits.check(steps)?;
+        self.inner
+            .heap_accounts
+            .check(self.gc_env.total_allocations())?;
+        self.gc_env.collect_if_pending();
+        Ok(())
+    }
+
+    pub fn set_fuel(&self, fuel: Option<u64>) {
+        self.inner.limits.set_fuel(fuel);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.inner.limits.fuel()
+    }
+
+    pub fn replace_deadline(&self, deadline: Option<Instant>) -> Option<Instant> {
+        self.inner.limits.replace_deadline(deadline)
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.inner.limits.deadline()
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.inner.limits.cancel_handle()
+    }
+
+    pub fn set_max_nesting_depth(&self, depth: usize) {
+        self.inner.limits.set_max_nesting_depth(depth);
+    }
+
+    pub fn max_nesting_depth(&self) -> usize {
+        self.inner.limits.max_nesting_depth()
+    }
+
+    pub fn set_max_frame_stack_size(&self, size: Option<usize>) {
+        self.inner.limits.set_max_frame_stack_size(size);
+    }
+
+    pub fn max_frame_stack_size(&self) -> Option<usize> {
+        self.inner.limits.max_frame_stack_size()
+    }
+
+    pub fn set_max_call_depth(&self, depth: Option<usize>) {
+        self.inner.limits.set_max_call_depth(depth);
+    }
+
+    pub fn max_call_depth(&self) -> Option<usize> {
+        self.inner.limits.max_call_depth()
+    }
+
+    pub fn set_catch_native_panics(&self, catch: bool) {
+        self.inner.limits.set_catch_native_panics(catch);
+    }
+
+    pub fn catch_native_panics(&self) -> bool {
+        self.inner.limits.catch_native_panics()
+    }
+
+    pub fn set_max_native_call_time(&self, time: Option<Duration>) {
+        self.inner.limits.set_max_native_call_time(time);
+    }
+
+    pub fn max_native_call_time(&self) -> Option<Duration> {
+        self.inner.limits.max_native_call_time()
+    }
+
+    /// Runs the body of a native function under the native call limits.
+    pub fn run_native<R>(&self, body: impl FnOnce() -> Result<R>) -> Result<R> {
+        self.inner.limits.run_native(body)
+    }
+
+    pub fn set_tier_up_policy(&self, policy: Option<TierUpPolicy>) {
+        *self.inner.tier_up_policy.borrow_mut() = policy.map(Rc::new);
+    }
+
+    pub fn tier_up_policy(&self) -> Option<Rc<TierUpPolicy>> {
+        self.inner.tier_up_policy.borrow().clone()
+    }
+
+    pub fn set_stack_sampler(&self, sampler: Option<StackSampler>) {
+        *self.inner.stack_sampler.borrow_mut() = sampler.map(Rc::new);
+    }
+
+    pub fn stack_sampler(&self) -> Option<Rc<StackSampler>> {
+        self.inner.stack_sampler.borrow().clone()
+    }
+
+    pub fn debug(&self) -> &DebugState {
+        &self.inner.debug
+    }
+
+    /// Charges objects allocated until the returned guard is dropped to
+    /// `account`, unless another account is entered in the meantime.
+    pub fn enter_heap_account(&self, account: &Rc<HeapAccount>) -> ActiveHeapAccount<'_> {
+        self.inner
+            .heap_accounts
+            .enter(account.clone(), self.gc_env.total_allocations());
+        ActiveHeapAccount { env: self }
+    }
+
+    /// Marks `call_stack` as running until the returned guard is dropped, so
+    /// that its frames are included in stack samples.
+    pub fn enter_call_stack(&self, call_stack: &PinnedGcRef<CallStack>) -> ActiveCallStack<'_> {
+        self.inner
+            .active_call_stacks
+            .borrow_mut()
+            .push(call_stack.clone());
+        ActiveCallStack { env: self }
+    }
+
+    /// Makes `timers` the queue that callbacks scheduled by native functions
+    /// go to until the returned guard is dropped.
+    pub(crate) fn enter_timer_queue(&self, timers: &Rc<TimerQueue>) -> ActiveTimerQueue<'_> {
+        self.inner.active_timers.borrow_mut().push(timers.clone());
+        ActiveTimerQueue { env: self }
+    }
+
+    /// Returns the callback queue of the innermost running top level.
+    pub(crate) fn active_timers(&self) -> Result<Rc<TimerQueue>> {
+        self.inner
+            .active_timers
+            .borrow()
+            .last()
+            .cloned()
+            .ok_or_else(no_active_timers)
+    }
+
+    /// Records the functions of every running call stack as one sample.
+    pub fn record_stack_sample(&self, sampler: &StackSampler) {
+        let stack = self
+            .inner
+            .active_call_stacks
+            .borrow()
+            .iter()
+            .flat_map(|call_stack| call_stack.function_ids())
+            .collect();
+        sampler.record(stack);
+    }
+
+    pub fn set_host_data<T: 'static>(&self, value: T) -> Option<Rc<T>> {
+        self.inner
+            .host_data
+            .borrow_mut()
+            .insert(TypeId::of::<T>(), Rc::new(value))
+            .map(downcast_host_data)
+    }
+
+    pub fn remove_host_data<T: 'static>(&self) -> Option<Rc<T>> {
+        self.inner
+            .host_data
+            .borrow_mut()
+            .remove(&TypeId::of::<T>())
+            .map(downcast_host_data)
+    }
+
+    pub fn host_data<T: 'static>(&self) -> Option<Rc<T>> {
+        self.inner
+            .host_data
+            .borrow()
+            .get(&TypeId::of::<T>())
+            .cloned()
+            .map(downcast_host_data)
+    }
+
+    /// Identifies this runtime, so that values taken from it are not used in
+    /// another. No two runtimes in a process share an id, even if one was
+    /// dropped before the other was created.
+    pub fn identity(&self) -> usize {
+        self.inner.runtime_id
+    }
+
+    pub fn retain_value(&self, value: PinnedValue) -> ValueHandle {
+        let id = self.inner.next_handle_id.get();
+        self.inner.next_handle_id.set(id + 1);
+        self.inner.handles.borrow_mut().insert(id, value);
+        ValueHandle::new(self.identity(), id)
+    }
+
+    pub fn resolve_handle(&self, handle: &ValueHandle) -> Result<PinnedValue> {
+        if handle.env_id() != self.identity() {
+            return Err(RuntimeError::new_operation_precondition_error(
+                "Handle belongs to a different runtime.",
+            ));
+        }
+        self.inner
+            .handles
+            .borrow()
+            .get(&handle.id())
+            .cloned()
+            .ok_or_else(|| {
+                RuntimeError::new_operation_precondition_error("Handle was already released.")
+            })
+    }
+
+    /// Releases the value held by `handle`. Returns false if the handle
+    /// belongs to a different runtime.
+    pub fn release_handle(&self, handle: ValueHandle) -> bool {
+        handle.env_id() == self.identity()
+            && self
+                .inner
+                .handles
+                .borrow_mut()
+                .remove(&handle.id())
+                .is_some()
+    }
+
+    pub fn num_handles(&self) -> usize {
+        self.inner.handles.borrow().len()
+    }
+
+    pub fn set_const_eval_initializers(&self, enabled: bool) {
+        self.inner.const_eval_initializers.set(enabled);
+    }
+
+    pub fn const_eval_initializers(&self) -> bool {
+        self.inner.const_eval_initializers.get()
+    }
+
+    pub fn set_peephole_optimize(&self, enabled: bool) {
+        self.inner.peephole_optimize.set(enabled);
+    }
+
+    pub fn peephole_optimize(&self) -> bool {
+        self.inner.peephole_optimize.get()
+    }
+
+    pub fn set_allow_module_replacement(&self, allowed: bool) {
+        self.inner.allow_module_replacement.set(allowed);
+    }
+
+    pub fn allow_module_replacement(&self) -> bool {
+        self.inner.allow_module_replacement.get()
+    }
+
+    pub fn set_init_modules_on_load(&self, enabled: bool) {
+        self.inner.init_modules_on_load.set(enabled);
+    }
+
+    pub fn init_modules_on_load(&self) -> bool {
+        self.inner.init_modules_on_load.get()
+    }
+
+    pub fn set_capture_stack_traces(&self, enabled: bool) {
+        self.inner.capture_stack_traces.set(enabled);
+    }
+
+    pub fn capture_stack_traces(&self) -> bool {
+        self.inner.capture_stack_traces.get()
+    }
+
+    /// Fails if a module with the id `module_id` is loaded, unless modules
+    /// may be replaced.
+    fn check_can_load(&self, module_id: &ModuleId) -> Result<()> {
+        if !self.allow_module_replacement() && self.is_module_loaded(module_id) {
+            return Err(RuntimeError::ModuleAlreadyLoaded {
+                module: module_id.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn set_stack_shrink_policy(&self, policy: Option<StackShrinkPolicy>) {
+        self.inner.stack_shrink_policy.set(policy);
+    }
+
+    pub fn stack_shrink_policy(&self) -> Option<StackShrinkPolicy> {
+        self.inner.stack_shrink_policy.get()
+    }
+
+    fn module_from_binary(
+        &self,
+        const_module: &binary::modules::ConstModule,
+    ) -> Result<PinnedGcRef<Module>> {
+        if let Some(policy) = self
+            .inner
+            .instruction_policies
+            .borrow()
+            .get(const_module.id())
+        {
+            const_module
+                .validate_instructions(policy)
+                .map_err(|error| RuntimeError::Validation {
+                    module: const_module.id().to_string(),
+                    error,
+                })?;
+        }
+        let folded = self
+            .const_eval_initializers()
+            .then(|| const_module.const_eval_initializer())
+            .flatten();
+        let const_module = folded.as_ref().unwrap_or(const_module);
+        let optimized = self
+            .peephole_optimize()
+            .then(|| const_module.peephole_optimize())
+            .flatten();
+        Module::from_binary(self, optimized.as_ref().unwrap_or(const_module))
+    }
+
+    /// Returns the call profiles of all managed functions defined by loaded
+    /// modules.
+    pub fn function_profiles(&self) -> Vec<FunctionProfile> {
+        let loaded_modules = self.inner.loaded_modules.borrow();
+        loaded_modules
+            .values()
+            .flat_map(|module| module.borrow().members())
+            .filter_map(|value| value.as_function().ok()?.profile())
+            .collect()
+    }
+
+    /// Loads a module into this global context. Fails if a module with the
+    /// same id is loaded, unless modules may be replaced.
     ///
     /// This does not initialize the module state, and has to be done at a
     /// later pass.
     pub fn load_module(&self, const_module: &binary::modules::ConstModule) -> Result<()> {
-        let module = Module::from_binary(self, const_module)?;
+        self.check_can_load(const_module.id())?;
+        let module = self.module_from_binary(const_module)?;
+        self.insert_module(const_module.id(), module);
+        Ok(())
+    }
+
+    /// Loads a group of modules into this global context as a single unit.
+    ///
+    /// Modules are loaded in order, so later modules may import from earlier
+    /// ones. If any module fails to load, every module loaded by this call is
+    /// removed again, and any module it replaced is restored.
+    ///
+    /// Modules already loaded are only replaced if that is allowed.
+    pub fn load_modules<'a>(
+        &self,
+        const_modules: impl IntoIterator<Item = &'a binary::modules::ConstModule>,
+    ) -> Result<()> {
+        let mut staged = Vec::new();
+        let result = const_modules.into_iter().try_for_each(|const_module| {
+            self.check_can_load(const_module.id())?;
+            let module = self.module_from_binary(const_module)?;
+            let replaced = self.insert_module(const_module.id(), module);
+            staged.push((const_module.id().clone(), replaced));
+            Ok(())
+        });
+        if result.is_err() {
+            self.with_lock(|lock| {
+                let mut loaded_modules = self.inner.loaded_modules.borrow_mut();
+                for (module_id, replaced) in staged.into_iter().rev() {
+                    match replaced {
+                        Some(module) => {
+                            loaded_modules.insert(module_id, module.into_ref(lock.guard()));
+                        }
+                        None => {
+                            loaded_modules.remove(&module_id);
+                        }
+                    }
+                }
+            });
+            self.prune_interned();
+        }
+        result
+    }
+
+    /// Loads a module of native functions into this global context.
+    pub fn load_native_module(&self, native_module: &NativeModule) -> Result<()> {
+        self.check_can_load(native_module.id())?;
+        let exports = native_module.functions().iter().map(|(name, func)| {
+            (
+                name.clone(),
+                PinnedValue::new_function(Function::from_native_ptr(
+                    self,
+                    func.clone(),
+                    FunctionId::Native {
+                        module_id: native_module.id().clone(),
+                        name: name.clone(),
+                    },
+                )),
+            )
+        });
+        let module = Module::from_exports(
+            self,
+            exports,
+            native_module.required_capabilities().to_vec(),
+        );
+        self.insert_module(native_module.id(), module);
+        Ok(())
+    }
+
+    /// Returns the canonical copy of `module_id`. Interned ids compare
+    /// equal by pointer, so lookups with them avoid comparing paths.
+    pub fn intern_module_id(&self, module_id: &ModuleId) -> ModuleId {
+        let mut module_ids = self.inner.module_ids.borrow_mut();
+        if let Some(interned) = module_ids.get(module_id) {
+            return interned.clone();
+        }
+        module_ids.insert(module_id.clone());
+        module_id.clone()
+    }
+
+    /// Returns the canonical copy of the member name `name`, as
+    /// [`Self::intern_module_id`] does for module ids.
+    pub fn intern_member_name(&self, name: &ModuleMemberId) -> ModuleMemberId {
+        let mut member_names = self.inner.member_names.borrow_mut();
+        if let Some(interned) = member_names.get(name) {
+            return interned.clone();
+        }
+        member_names.insert(name.clone());
+        name.clone()
+    }
+
+    /// Returns a copy of `source` whose module id and name are interned.
+    pub fn intern_import_source(&self, source: &ImportSource) -> ImportSource {
+        let module_id = self.intern_module_id(source.module_id());
+        let name = self.intern_member_name(source.import_name());
+        if source.is_optional() {
+            ImportSource::new_optional(module_id, name)
+        } else {
+            ImportSource::new(module_id, name)
+        }
+    }
+
+    /// Drops the interned ids and names that nothing else refers to, such as
+    /// those of modules whose load was rolled back or that were replaced and
+    /// since collected.
+    pub fn prune_interned(&self) {
+        self.inner
+            .module_ids
+            .borrow_mut()
+            .retain(|module_id| !module_id.is_unique());
+        self.inner
+            .member_names
+            .borrow_mut()
+            .retain(|name| !name.is_unique());
+    }
+
+    /// Registers a module, returning the module it replaced, if any.
+    fn insert_module(
+        &self,
+        module_id: &ModuleId,
+        module: PinnedGcRef<Module>,
+    ) -> Option<PinnedGcRef<Module>> {
         self.with_lock(|lock| {
             self.inner
                 .loaded_modules
                 .borrow_mut()
-                .insert(const_module.id().clone(), module.into_ref(lock.guard()))
-        });
-        Ok(())
+                .insert(
+                    self.intern_module_id(module_id),
+                    module.into_ref(lock.guard()),
+                )
+                .map(GcRef::into_pinned)
+        })
     }
 
     pub fn get_import(&self, import_source: &ImportSource) -> Result<PinnedValue> {
         self.inner.get_import(import_source)
     }
 
+    /// Resolves an import of the module `importer`, checking that it was
+    /// granted the capabilities the imported module requires.
+    ///
+    /// Optional imports from modules that are not loaded resolve to null.
+    pub fn get_import_for(
+        &self,
+        importer: &ModuleId,
+        import_source: &ImportSource,
+    ) -> Result<PinnedValue> {
+        let loaded_modules = self.inner.loaded_modules.borrow();
+        let module = match loaded_modules.get(import_source.module_id()) {
+            Some(module) => module.borrow(),
+            None if import_source.is_optional() => return Ok(PinnedValue::new_null()),
+            None => {
+                return Err(RuntimeError::new_operation_precondition_error(
+                    "Module not loaded.",
+                ))
+            }
+        };
+        let required = module.required_capabilities();
+        if !required.is_empty() {
+            let grants = self.inner.granted_capabilities.borrow();
+            let missing = match grants.get(importer) {
+                Some(granted) => granted.first_missing(required),
+                None => required.first(),
+            };
+            if let Some(missing) = missing {
+                return Err(RuntimeError::CapabilityNotGranted {
+                    module: importer.to_string(),
+                    capability: missing.name().to_string(),
+                });
+            }
+        }
+        module.get_export(import_source)
+    }
+
+    /// Grants capabilities to the module with the given id, replacing any
+    /// previous grant. This affects imports resolved after the call.
+    pub fn grant_capabilities(&self, module_id: &ModuleId, capabilities: CapabilitySet) {
+        self.inner
+            .granted_capabilities
+            .borrow_mut()
+            .insert(module_id.clone(), capabilities);
+    }
+
+    /// Restricts the instructions the module with the given id may use,
+    /// replacing any previous policy. This affects modules loaded after the
+    /// call.
+    pub fn set_instruction_policy(&self, module_id: &ModuleId, policy: InstructionPolicy) {
+        self.inner
+            .instruction_policies
+            .borrow_mut()
+            .insert(module_id.clone(), policy);
+    }
+
+    /// Returns the instruction policy of the module with the given id, if it
+    /// has one.
+    pub fn instruction_policy(&self, module_id: &ModuleId) -> Option<InstructionPolicy> {
+        self.inner
+            .instruction_policies
+            .borrow()
+            .get(module_id)
+            .copied()
+    }
+
     pub(super) fn get_init_function(
         &self,
         module_id: &ModuleId,
@@ -166,16 +887,37 @@ impl GlobalEnv {
         let loaded_modules = self.inner.loaded_modules.borrow();
         loaded_modules
             .get(module_id)
-            .ok_or_else(|| RuntimeError::new_internal_error("Module not found in global context."))?
+            .ok_or_else(|| RuntimeError::new_operation_precondition_error("Module not loaded."))?
             .borrow()
             .get_init_function()
     }
 
+    pub(super) fn get_export_doc(&self, source: &ImportSource) -> Result<Option<String>> {
+        let loaded_modules = self.inner.loaded_modules.borrow();
+        let module = loaded_modules
+            .get(source.module_id())
+            .ok_or_else(|| RuntimeError::new_operation_precondition_error("Module not loaded."))?
+            .borrow();
+        Ok(module.export_doc(source.import_name()).map(str::to_string))
+    }
+
+    pub(super) fn get_module_tests(
+        &self,
+        module_id: &ModuleId,
+    ) -> Result<Vec<(String, PinnedValue)>> {
+        let loaded_modules = self.inner.loaded_modules.borrow();
+        loaded_modules
+            .get(module_id)
+            .ok_or_else(|| RuntimeError::new_operation_precondition_error("Module not loaded."))?
+            .borrow()
+            .tests()
+    }
+
     pub(super) fn set_module_initialized(&self, module_id: &ModuleId) -> Result<()> {
         let loaded_modules = self.inner.loaded_modules.borrow();
         loaded_modules
             .get(module_id)
-            .ok_or_else(|| RuntimeError::new_internal_error("Module not found in global context."))?
+            .ok_or_else(|| RuntimeError::new_operation_precondition_error("Module not loaded."))?
             .borrow()
             .set_is_initialized();
         Ok(())
@@ -186,6 +928,63 @@ impl GlobalEnv {
     }
 }
 
+/// A call stack marked as running by [`GlobalEnv::enter_call_stack`].
+pub(crate) struct ActiveCallStack<'a> {
+    env: &'a GlobalEnv,
+}
+
+impl Drop for ActiveCallStack<'_> {
+    fn drop(&mut self) {
+        self.env.inner.active_call_stacks.borrow_mut().pop();
+    }
+}
+
+/// A callback queue entered by [`GlobalEnv::enter_timer_queue`].
+pub(crate) struct ActiveTimerQueue<'a> {
+    env: &'a GlobalEnv,
+}
+
+impl Drop for ActiveTimerQueue<'_> {
+    fn drop(&mut self) {
+        self.env.inner.active_timers.borrow_mut().pop();
+    }
+}
+
+pub(crate) struct ActiveHeapAccount<'a> {
+    env: &'a GlobalEnv,
+}
+
+impl Drop for ActiveHeapAccount<'_> {
+    fn drop(&mut self) {
+        self.env
+            .inner
+            .heap_accounts
+            .exit(self.env.gc_env.total_allocations());
+    }
+}
+
+/// A buffer taken from the pool for the length of a `with_value_buffer`
+/// call.
+struct ValueBufferGuard<'a> {
+    buffers: &'a RefCell<Vec<PinnedValueBuffer>>,
+    buffer: PinnedValueBuffer,
+}
+
+impl Drop for ValueBufferGuard<'_> {
+    fn drop(&mut self) {
+        // A buffer in use when a panic unwinds may hold values from a
+        // half-finished operation. It is dropped along with them rather than
+        // risk returning it to the pool.
+        if std::thread::panicking() {
+            return;
+        }
+        self.buffer.clear();
+        let mut buffers = self.buffers.borrow_mut();
+        buffers.push(std::mem::take(&mut self.buffer));
+        debug_assert!(buffers.iter().all(Vec::is_empty));
+    }
+}
+
 #[derive(Clone)]
 pub(crate) struct GlobalEnvLock<'a> {
     gc_guard: &'a CollectGuard<'a>,
@@ -196,3 +995,106 @@ impl<'a> GlobalEnvLock<'a> {
         self.gc_guard
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::indexes::LocalConstIndex;
+
+    fn list(instructions: Vec<Instruction>) -> InstructionList {
+        InstructionList::new(instructions)
+    }
+
+    #[test]
+    fn identical_instruction_lists_share_resolution() -> anyhow::Result<()> {
+        let env = GlobalEnv::new();
+        let insts = vec![
+            Instruction::PushConst(LocalConstIndex::new(0)),
+            Instruction::Return(1),
+        ];
+        let first = env.resolve_instructions(&list(insts.clone()))?;
+        let second = env.resolve_instructions(&list(insts))?;
+        assert!(Rc::ptr_eq(&first, &second));
+
+        let other = env.resolve_instructions(&list(vec![Instruction::Return(0)]))?;
+        assert!(!Rc::ptr_eq(&first, &other));
+        Ok(())
+    }
+
+    #[test]
+    fn value_buffers_are_discarded_on_panic() {
+        let env = GlobalEnv::new();
+        env.with_value_buffer(|buffer| {
+            buffer.push(PinnedValue::new_bool(true));
+            env.with_value_buffer(|nested| assert!(nested.is_empty()));
+        });
+        assert_eq!(env.inner.value_buffers.borrow().len(), 2);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            env.with_value_buffer(|buffer| {
+                buffer.push(PinnedValue::new_bool(true));
+                panic!("Interrupted mid-use.");
+            })
+        }));
+        assert!(result.is_err());
+        let buffers = env.inner.value_buffers.borrow();
+        assert_eq!(buffers.len(), 1);
+        assert!(buffers.iter().all(Vec::is_empty));
+    }
+
+    #[test]
+    fn dropped_resolutions_are_pruned() -> anyhow::Result<()> {
+        let env = GlobalEnv::new();
+        for i in 0..(INITIAL_PRUNE_THRESHOLD as u32 * 4) {
+            env.resolve_instructions(&list(vec![Instruction::Pop(i)]))?;
+        }
+        assert!(env.inner.resolved_instructions.borrow().len() <= INITIAL_PRUNE_THRESHOLD);
+        Ok(())
+    }
+
+    #[test]
+    fn handles_from_dropped_runtimes_are_rejected() {
+        let first = GlobalEnv::new();
+        let handle = first.retain_value(PinnedValue::new_bool(true));
+        let first_id = first.identity();
+        drop(first);
+
+        // The new runtime may reuse the memory of the dropped one, but not
+        // its id.
+        let second = GlobalEnv::new();
+        assert_ne!(second.identity(), first_id);
+        assert!(second.resolve_handle(&handle).is_err());
+        assert!(!second.release_handle(handle));
+    }
+
+    #[test]
+    fn interned_names_are_pruned_once_unused() -> anyhow::Result<()> {
+        let env = GlobalEnv::new();
+        let module_set = crate::lat::from_str(
+            r#"
+                (module-set
+                    ("a" (const value 1) (export value))
+                    ("b" (const other 2) (export other))
+                    ("c" (import value "missing" value)))
+            "#,
+        )?;
+        let module = |name| module_set.module(&ModuleId::new([name])).unwrap();
+        env.load_modules([module("a")])?;
+        assert!(env.load_modules([module("b"), module("c")]).is_err());
+
+        let first = env.intern_member_name(&ModuleMemberId::new("value"));
+        let second = env.intern_member_name(&ModuleMemberId::new("value"));
+        assert!(std::ptr::eq(first.as_str(), second.as_str()));
+        drop((first, second));
+
+        drop(module_set);
+        env.collect_garbage();
+        let names = env.inner.member_names.borrow();
+        assert!(names.contains(&ModuleMemberId::new("value")));
+        assert!(!names.contains(&ModuleMemberId::new("other")));
+        let module_ids = env.inner.module_ids.borrow();
+        assert!(module_ids.contains(&ModuleId::new(["a"])));
+        assert!(!module_ids.contains(&ModuleId::new(["b"])));
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+//! Handles that keep runtime values alive between calls.
+//!
+//! Values on a top-level stack are only kept alive while they stay on the
+//! stack. An embedder that wants to hold on to a value across calls, such as
+//! a callback registered by a script, retains it as a [`ValueHandle`]. The
+//! runtime roots the value until the handle is released.
+
+/// A rooted value, created with
+/// [`StackContext::retain`](super::stack_frame::StackContext::retain).
+///
+/// Dropping a handle does not release the value. It stays alive until the
+/// handle is passed to [`Runtime::release`](super::Runtime::release), or the
+/// runtime is dropped.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct ValueHandle {
+    /// The identity of the runtime that created the handle.
+    env_id: usize,
+    id: u64,
+}
+
+impl ValueHandle {
+    pub(crate) fn new(env_id: usize, id: u64) -> Self {
+        ValueHandle { env_id, id }
+    }
+
+    pub(crate) fn env_id(&self) -> usize {
+        self.env_id
+    }
+
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+}
@@ -0,0 +1,119 @@
+//! Runtime values held directly by the host.
+//!
+//! A [`HostValue`] is taken from a stack with
+//! [`StackContext::get_host_value`](super::stack_frame::StackContext::get_host_value),
+//! and keeps its value alive for as long as the host holds it. Unlike a
+//! [`ValueHandle`](super::ValueHandle), it needs no explicit release, and can
+//! be inspected without pushing it back onto a stack.
+
+use crate::pure_values::{Float, Integer, LoonValue};
+
+use super::{
+    error::{Result, RuntimeError},
+    value::PinnedValue,
+};
+
+/// The type of a runtime value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ValueKind {
+    Null,
+    Bool,
+    Integer,
+    Float,
+    Rational,
+    String,
+    Bytes,
+    List,
+    Function,
+    Map,
+    Cell,
+    WeakRef,
+    Record,
+    Tag,
+    Tagged,
+    Error,
+}
+
+/// A runtime value held by the host.
+///
+/// The value stays alive while any clone of the handle exists, and can be
+/// pushed onto a stack of the runtime that created it with
+/// [`StackContext::push_host_value`](super::stack_frame::StackContext::push_host_value).
+/// Lists are shared, not copied, so changes made to them by scripts are
+/// visible through the handle.
+#[derive(Clone)]
+pub struct HostValue {
+    /// The identity of the runtime that created the value.
+    env_id: usize,
+    value: PinnedValue,
+}
+
+impl HostValue {
+    pub(crate) fn new(env_id: usize, value: PinnedValue) -> Self {
+        HostValue { env_id, value }
+    }
+
+    /// Returns the value, if it belongs to the runtime `env_id`.
+    pub(crate) fn value_in(&self, env_id: usize) -> Result<&PinnedValue> {
+        if self.env_id != env_id {
+            return Err(RuntimeError::new_operation_precondition_error(
+                "Value belongs to a different runtime.",
+            ));
+        }
+        Ok(&self.value)
+    }
+
+    #[must_use]
+    pub fn kind(&self) -> ValueKind {
+        self.value.kind()
+    }
+
+    pub fn as_bool(&self) -> Result<bool> {
+        self.value.as_bool()
+    }
+
+    pub fn as_int(&self) -> Result<Integer> {
+        self.value.as_int().cloned()
+    }
+
+    pub fn as_float(&self) -> Result<Float> {
+        self.value.as_float().cloned()
+    }
+
+    pub fn as_str(&self) -> Result<&str> {
+        Ok(self.value.as_str()?.as_str())
+    }
+
+    pub fn as_bytes(&self) -> Result<&[u8]> {
+        Ok(self.value.as_bytes()?.as_bytes())
+    }
+
+    /// Returns the number of elements of a list.
+    pub fn list_len(&self) -> Result<usize> {
+        Ok(self.value.as_list()?.len())
+    }
+
+    /// Returns the element at `index` of a list, or `None` if the list is
+    /// shorter.
+    pub fn list_get(&self, index: usize) -> Result<Option<HostValue>> {
+        Ok(self
+            .value
+            .as_list()?
+            .get(index)
+            .map(|value| HostValue::new(self.env_id, value)))
+    }
+
+    /// Returns a copy of the value, copying lists nested at most `max_depth`
+    /// deep. Functions, maps and cells cannot be copied.
+    pub fn to_loon_value(&self, max_depth: usize) -> Result<LoonValue> {
+        self.value.to_loon_value(max_depth)
+    }
+}
+
+impl std::fmt::Debug for HostValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostValue")
+            .field("kind", &self.kind())
+            .finish_non_exhaustive()
+    }
+}
@@ -1,37 +1,70 @@
 mod add;
+mod apply;
+mod arith;
+mod bind_front;
 mod bool;
 mod branch;
 mod branch_if;
+mod branch_if_truthy;
 mod call;
 mod call_dynamic;
+mod call_protected;
+mod cell;
+mod cmp_branch;
 mod compare;
+mod identity_hash;
+mod is_null;
 mod list;
 mod pop;
 mod push_const;
 mod push_copy;
 mod push_global;
+mod raise;
+mod record;
 mod return_;
 mod return_dynamic;
 mod set_global;
+mod string;
+mod tag;
 mod tail_call;
+mod to_bool;
+mod to_number;
+mod weak;
 mod write_stack;
-mod bind_front;
 
 pub use add::Add;
+pub use apply::Apply;
+pub use arith::{Div, Mul, Sub};
+pub use bind_front::BindFront;
 pub use bool::{and::BoolAnd, not::BoolNot, or::BoolOr, xor::BoolXor};
 pub use branch::Branch;
 pub use branch_if::BranchIf;
+pub use branch_if_truthy::BranchIfTruthy;
 pub use call::Call;
 pub use call_dynamic::CallDynamic;
+pub use call_protected::CallProtected;
+pub use cell::{CellGet, CellNew, CellSet};
+pub use cmp_branch::CmpBranch;
 pub use compare::Compare;
-pub use list::{ListAppend, ListGet, ListLen, ListNew, ListSet};
+pub use identity_hash::IdentityHash;
+pub use is_null::IsNull;
+pub use list::{
+    ListAppend, ListGet, ListGetOrNull, ListGetRel, ListLen, ListNew, ListSet, ListSetRel,
+    ListSlice,
+};
 pub use pop::Pop;
 pub use push_const::PushConst;
 pub use push_copy::PushCopy;
 pub use push_global::PushGlobal;
+pub use raise::Raise;
+pub use record::{RecordGet, RecordNew, RecordSet};
 pub use return_::Return;
 pub use return_dynamic::ReturnDynamic;
 pub use set_global::SetGlobal;
+pub use string::{StrLenBytes, StrLenChars, StrSliceBytes, StrSliceChars};
+pub use tag::{TagNew, TagOf, TagWrap, Untag};
 pub use tail_call::TailCall;
+pub use to_bool::ToBool;
+pub use to_number::ToNumber;
+pub use weak::{WeakGet, WeakNew};
 pub use write_stack::WriteStack;
-pub use bind_front::BindFront;
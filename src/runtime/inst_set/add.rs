@@ -14,7 +14,7 @@ impl InstEval for Add {
         let b = stack.pop()?;
         // Right now, only implement integer addition.
         let result = a.add_owned(b)?;
-        stack.push(result);
+        stack.push(result)?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
@@ -0,0 +1,25 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::{Result, RuntimeError},
+    instructions::{FunctionCallResult, InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+};
+
+#[derive(Clone, Debug)]
+pub struct Apply;
+
+impl InstEval for Apply {
+    fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let list_value = stack.pop()?;
+        let list = list_value.as_list()?;
+        let num_args = u32::try_from(list.len()).map_err(|_| {
+            RuntimeError::new_operation_precondition_error("Number of arguments is too large.")
+        })?;
+        // The function is left below the arguments, as for a regular call.
+        stack.push_iter(ctxt.get_env(), (0..list.len()).map(|i| list.at(i)))?;
+        Ok(InstructionResult::Call(FunctionCallResult::new(
+            num_args,
+            InstructionTarget::Step,
+        )))
+    }
+}
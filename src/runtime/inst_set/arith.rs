@@ -0,0 +1,42 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+};
+
+#[derive(Clone, Debug)]
+pub struct Sub;
+
+impl InstEval for Sub {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let rhs = stack.pop()?;
+        let lhs = stack.pop()?;
+        stack.push(lhs.sub(&rhs)?)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Mul;
+
+impl InstEval for Mul {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let rhs = stack.pop()?;
+        let lhs = stack.pop()?;
+        stack.push(lhs.mul(&rhs)?)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Div;
+
+impl InstEval for Div {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let rhs = stack.pop()?;
+        let lhs = stack.pop()?;
+        stack.push(lhs.div(&rhs)?)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -19,8 +19,8 @@ impl InstEval for BindFront {
         ctxt.get_env().with_value_buffer(|buffer| {
             stack.drain_top_n(self.0, buffer)?;
             let func = stack.pop()?.as_function()?.clone();
-            let new_func = func.bind_front(ctxt.get_env(), &func, buffer);
-            stack.push(new_func.into());
+            let new_func = func.bind_front(ctxt.get_env(), &func, buffer)?;
+            stack.push(new_func.into())?;
             Ok(InstructionResult::Next(InstructionTarget::Step))
         })
     }
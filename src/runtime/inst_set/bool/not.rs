@@ -11,8 +11,8 @@ pub struct BoolNot;
 
 impl InstEval for BoolNot {
     fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
-        let b1 = stack.pop()?.as_bool()?;
-        stack.push(PinnedValue::new_bool(!b1));
+        let b1 = stack.pop_bool()?;
+        stack.push(PinnedValue::new_bool(!b1))?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
@@ -19,7 +19,7 @@ impl BranchIf {
 
 impl InstEval for BranchIf {
     fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
-        let cond = stack.pop()?.as_bool()?;
+        let cond = stack.pop_bool()?;
         Ok(if cond {
             InstructionResult::Next(InstructionTarget::Branch(self.0.target_index()))
         } else {
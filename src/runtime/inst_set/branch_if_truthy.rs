@@ -0,0 +1,29 @@
+use crate::{
+    binary::instructions::{BranchTarget, Truthiness},
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct BranchIfTruthy(BranchTarget, Truthiness);
+
+impl BranchIfTruthy {
+    pub fn new(index: BranchTarget, truthiness: Truthiness) -> Self {
+        BranchIfTruthy(index, truthiness)
+    }
+}
+
+impl InstEval for BranchIfTruthy {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let cond = stack.pop()?.to_bool(self.1)?;
+        Ok(if cond {
+            InstructionResult::Next(InstructionTarget::Branch(self.0.target_index()))
+        } else {
+            InstructionResult::Next(InstructionTarget::Step)
+        })
+    }
+}
@@ -0,0 +1,32 @@
+use crate::{
+    binary::instructions::{BranchTarget, CallInstruction},
+    runtime::{
+        context::InstEvalContext,
+        error::RuntimeError,
+        instructions::{FunctionCallResult, InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct CallProtected(CallInstruction, BranchTarget);
+
+impl CallProtected {
+    pub fn new(call_inst: CallInstruction, handler: BranchTarget) -> Self {
+        Self(call_inst, handler)
+    }
+}
+
+impl InstEval for CallProtected {
+    fn execute(
+        &self,
+        _ctxt: &InstEvalContext,
+        _stack: &LocalStack,
+    ) -> std::prelude::v1::Result<InstructionResult, RuntimeError> {
+        Ok(InstructionResult::Call(FunctionCallResult::new_protected(
+            self.0.num_args,
+            InstructionTarget::Step,
+            self.1.target_index(),
+        )))
+    }
+}
@@ -0,0 +1,17 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+};
+
+#[derive(Clone, Debug)]
+pub struct CellGet;
+
+impl InstEval for CellGet {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let cell_value = stack.pop()?;
+        stack.push(cell_value.as_cell()?.get())?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -0,0 +1,7 @@
+mod get;
+mod new;
+mod set;
+
+pub use get::CellGet;
+pub use new::CellNew;
+pub use set::CellSet;
@@ -0,0 +1,19 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+    value::{Cell, PinnedValue},
+};
+
+#[derive(Clone, Debug)]
+pub struct CellNew;
+
+impl InstEval for CellNew {
+    fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let value = stack.pop()?;
+        let cell = PinnedValue::new_cell(Cell::new(ctxt.get_env(), value));
+        stack.push(cell)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
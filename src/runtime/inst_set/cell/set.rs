@@ -0,0 +1,18 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+};
+
+#[derive(Clone, Debug)]
+pub struct CellSet;
+
+impl InstEval for CellSet {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let cell_value = stack.pop()?;
+        let cell = cell_value.as_cell()?;
+        cell.set(stack.pop()?);
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
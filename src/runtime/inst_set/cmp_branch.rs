@@ -0,0 +1,40 @@
+use crate::{
+    binary::instructions::{BranchTarget, CompareOp},
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+    },
+};
+
+use super::compare::{compare, compare_ints};
+
+#[derive(Clone, Debug)]
+pub struct CmpBranch(CompareOp, BranchTarget);
+
+impl CmpBranch {
+    pub fn new(cmp_op: CompareOp, index: BranchTarget) -> Self {
+        CmpBranch(cmp_op, index)
+    }
+}
+
+impl InstEval for CmpBranch {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        // Loop conditions mostly compare small integers, which can be done
+        // without pinning either value.
+        let cond = match stack.pop_int_pair() {
+            Some((left, right)) => compare_ints(self.0, left, right),
+            None => {
+                let right = stack.pop()?;
+                let left = stack.pop()?;
+                compare(self.0, &left, &right)?
+            }
+        };
+        Ok(if cond {
+            InstructionResult::Next(InstructionTarget::Branch(self.1.target_index()))
+        } else {
+            InstructionResult::Next(InstructionTarget::Step)
+        })
+    }
+}
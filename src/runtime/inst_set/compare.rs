@@ -1,3 +1,5 @@
+use std::cmp::Ordering;
+
 use crate::{
     binary::instructions::CompareOp,
     runtime::{
@@ -22,16 +24,39 @@ impl InstEval for Compare {
     fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
         let right = stack.pop()?;
         let left = stack.pop()?;
-        let result = match self.0 {
-            CompareOp::RefEq => left.ref_eq(&right),
-            CompareOp::Eq => todo!(),
-            CompareOp::Ne => todo!(),
-            CompareOp::Lt => todo!(),
-            CompareOp::Le => todo!(),
-            CompareOp::Gt => todo!(),
-            CompareOp::Ge => todo!(),
-        };
-        stack.push(PinnedValue::new_bool(result));
+        stack.push(PinnedValue::new_bool(compare(self.0, &left, &right)?))?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
+
+pub(super) fn compare(op: CompareOp, left: &PinnedValue, right: &PinnedValue) -> Result<bool> {
+    Ok(match op {
+        CompareOp::RefEq => left.ref_eq(right),
+        CompareOp::Eq => left.value_eq(right),
+        CompareOp::Ne => !left.value_eq(right),
+        // NaN is unordered, so every ordering comparison with it is false.
+        CompareOp::Lt => left.value_cmp(right)? == Some(Ordering::Less),
+        CompareOp::Le => matches!(
+            left.value_cmp(right)?,
+            Some(Ordering::Less | Ordering::Equal)
+        ),
+        CompareOp::Gt => left.value_cmp(right)? == Some(Ordering::Greater),
+        CompareOp::Ge => matches!(
+            left.value_cmp(right)?,
+            Some(Ordering::Greater | Ordering::Equal)
+        ),
+    })
+}
+
+/// Compares two integers that fit in an `i64`, which every operator treats
+/// the same way.
+pub(super) fn compare_ints(op: CompareOp, left: i64, right: i64) -> bool {
+    match op {
+        CompareOp::RefEq | CompareOp::Eq => left == right,
+        CompareOp::Ne => left != right,
+        CompareOp::Lt => left < right,
+        CompareOp::Le => left <= right,
+        CompareOp::Gt => left > right,
+        CompareOp::Ge => left >= right,
+    }
+}
@@ -0,0 +1,18 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+    value::PinnedValue,
+};
+
+#[derive(Clone, Debug)]
+pub struct IdentityHash;
+
+impl InstEval for IdentityHash {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let value = stack.pop()?;
+        stack.push(PinnedValue::new_integer(value.identity_hash().into()))?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -1,6 +1,6 @@
 use crate::runtime::{
     context::InstEvalContext,
-    error::Result,
+    error::{Result, RuntimeError},
     instructions::{InstEval, InstructionResult, InstructionTarget},
     stack_frame::LocalStack,
 };
@@ -13,8 +13,13 @@ impl InstEval for ListGet {
         let list_value = stack.pop()?;
         let list = list_value.as_list()?;
         let index = stack.pop()?.as_compact_integer()?;
-        let elem = list.at(index as usize);
-        stack.push(elem);
+        let elem = usize::try_from(index)
+            .ok()
+            .and_then(|index| list.get(index))
+            .ok_or_else(|| {
+                RuntimeError::new_operation_precondition_error("List index out of range.")
+            })?;
+        stack.push(elem)?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
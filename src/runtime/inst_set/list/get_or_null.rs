@@ -0,0 +1,24 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+    value::PinnedValue,
+};
+
+#[derive(Clone, Debug)]
+pub struct ListGetOrNull;
+
+impl InstEval for ListGetOrNull {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let list_value = stack.pop()?;
+        let list = list_value.as_list()?;
+        let index = stack.pop()?.as_compact_integer()?;
+        let elem = usize::try_from(index)
+            .ok()
+            .and_then(|index| list.get(index))
+            .unwrap_or_else(PinnedValue::new_null);
+        stack.push(elem)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
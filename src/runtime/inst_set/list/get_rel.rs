@@ -0,0 +1,22 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+};
+
+use super::resolve_rel_index;
+
+#[derive(Clone, Debug)]
+pub struct ListGetRel;
+
+impl InstEval for ListGetRel {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let list_value = stack.pop()?;
+        let list = list_value.as_list()?;
+        let index = stack.pop()?.as_compact_integer()?;
+        let elem = list.at(resolve_rel_index(index, list.len())?);
+        stack.push(elem)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -14,7 +14,7 @@ impl InstEval for ListLen {
         let list_value = stack.pop()?;
         let list = list_value.as_list()?;
         let len = list.len();
-        stack.push(PinnedValue::new_integer(i64::try_from(len).unwrap().into()));
+        stack.push(PinnedValue::new_integer(i64::try_from(len).unwrap().into()))?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
@@ -1,11 +1,65 @@
 mod append;
 mod get;
+mod get_or_null;
+mod get_rel;
 mod len;
 mod new;
 mod set;
+mod set_rel;
+mod slice;
 
 pub use append::ListAppend;
 pub use get::ListGet;
+pub use get_or_null::ListGetOrNull;
+pub use get_rel::ListGetRel;
 pub use len::ListLen;
 pub use new::ListNew;
 pub use set::ListSet;
+pub use set_rel::ListSetRel;
+pub use slice::ListSlice;
+
+use crate::runtime::error::{Result, RuntimeError};
+
+/// Resolves an index that counts from the end of the list when negative.
+fn resolve_rel_index(index: i64, len: usize) -> Result<usize> {
+    let len = i64::try_from(len).unwrap();
+    let index = if index < 0 { index + len } else { index };
+    if (0..len).contains(&index) {
+        Ok(usize::try_from(index).unwrap())
+    } else {
+        Err(RuntimeError::new_operation_precondition_error(
+            "List index out of range.",
+        ))
+    }
+}
+
+/// Resolves a slice bound, counting from the end when negative, and clamping
+/// it to `0..=len`.
+fn resolve_slice_bound(index: i64, len: usize) -> usize {
+    let len = i64::try_from(len).unwrap();
+    let index = if index < 0 { index + len } else { index };
+    usize::try_from(index.clamp(0, len)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_indexes_count_from_end() {
+        assert_eq!(resolve_rel_index(0, 3).unwrap(), 0);
+        assert_eq!(resolve_rel_index(-1, 3).unwrap(), 2);
+        assert_eq!(resolve_rel_index(-3, 3).unwrap(), 0);
+        assert!(resolve_rel_index(3, 3).is_err());
+        assert!(resolve_rel_index(-4, 3).is_err());
+        assert!(resolve_rel_index(0, 0).is_err());
+    }
+
+    #[test]
+    fn slice_bounds_are_clamped() {
+        assert_eq!(resolve_slice_bound(-1, 3), 2);
+        assert_eq!(resolve_slice_bound(-10, 3), 0);
+        assert_eq!(resolve_slice_bound(10, 3), 3);
+        assert_eq!(resolve_slice_bound(i64::MIN, 3), 0);
+    }
+}
@@ -12,7 +12,7 @@ pub struct ListNew;
 impl InstEval for ListNew {
     fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
         let list = PinnedValue::new_list(List::new(ctxt.get_env()));
-        stack.push(list);
+        stack.push(list)?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
@@ -1,6 +1,6 @@
 use crate::runtime::{
     context::InstEvalContext,
-    error::Result,
+    error::{Result, RuntimeError},
     instructions::{InstEval, InstructionResult, InstructionTarget},
     stack_frame::LocalStack,
 };
@@ -14,7 +14,10 @@ impl InstEval for ListSet {
         let list = list_value.as_list()?;
         let index = stack.pop()?.as_compact_integer()?;
         let elem = stack.pop()?;
-        list.set(index as u32, elem)?;
+        let index = u32::try_from(index).map_err(|_| {
+            RuntimeError::new_operation_precondition_error("List index out of range.")
+        })?;
+        list.set(index, elem)?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
@@ -0,0 +1,26 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::{Result, RuntimeError},
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+};
+
+use super::resolve_rel_index;
+
+#[derive(Clone, Debug)]
+pub struct ListSetRel;
+
+impl InstEval for ListSetRel {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let list_value = stack.pop()?;
+        let list = list_value.as_list()?;
+        let index = stack.pop()?.as_compact_integer()?;
+        let elem = stack.pop()?;
+        let index = resolve_rel_index(index, list.len())?;
+        let index = u32::try_from(index).map_err(|_| {
+            RuntimeError::new_operation_precondition_error("List index out of range.")
+        })?;
+        list.set(index, elem)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
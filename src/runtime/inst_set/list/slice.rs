@@ -0,0 +1,24 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+    value::{List, PinnedValue},
+};
+
+use super::resolve_slice_bound;
+
+#[derive(Clone, Debug)]
+pub struct ListSlice;
+
+impl InstEval for ListSlice {
+    fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let list_value = stack.pop()?;
+        let list = list_value.as_list()?;
+        let start = resolve_slice_bound(stack.pop()?.as_compact_integer()?, list.len());
+        let end = resolve_slice_bound(stack.pop()?.as_compact_integer()?, list.len());
+        let slice = List::from_iter(ctxt.get_env(), (start..end).map(|i| list.at(i)));
+        stack.push(PinnedValue::new_list(slice))?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -1,15 +1,18 @@
-use crate::runtime::{
-    context::InstEvalContext,
-    error::Result,
-    instructions::{InstEval, InstructionResult, InstructionTarget},
-    stack_frame::LocalStack,
+use crate::{
+    binary::indexes::LocalConstIndex,
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+    },
 };
 
 #[derive(Clone, Debug)]
-pub struct PushConst(u32);
+pub struct PushConst(LocalConstIndex);
 
 impl PushConst {
-    pub fn new(index: u32) -> Self {
+    pub fn new(index: LocalConstIndex) -> Self {
         PushConst(index)
     }
 }
@@ -17,7 +20,7 @@ impl PushConst {
 impl InstEval for PushConst {
     fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
         let value = ctxt.get_constant(self.0)?;
-        stack.push(value);
+        stack.push(value)?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
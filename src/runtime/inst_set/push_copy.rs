@@ -20,7 +20,7 @@ impl PushCopy {
 impl InstEval for PushCopy {
     fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
         let value = stack.get_at_index(self.0)?;
-        stack.push(value.clone());
+        stack.push(value.clone())?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
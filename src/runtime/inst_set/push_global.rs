@@ -1,15 +1,18 @@
-use crate::runtime::{
-    context::InstEvalContext,
-    error::Result,
-    instructions::{InstEval, InstructionResult, InstructionTarget},
-    stack_frame::LocalStack,
+use crate::{
+    binary::indexes::GlobalIndex,
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+    },
 };
 
 #[derive(Clone, Debug)]
-pub struct PushGlobal(u32);
+pub struct PushGlobal(GlobalIndex);
 
 impl PushGlobal {
-    pub fn new(index: u32) -> Self {
+    pub fn new(index: GlobalIndex) -> Self {
         PushGlobal(index)
     }
 }
@@ -17,7 +20,7 @@ impl PushGlobal {
 impl InstEval for PushGlobal {
     fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
         let value = ctxt.get_global(self.0)?;
-        stack.push(value);
+        stack.push(value)?;
         Ok(InstructionResult::Next(InstructionTarget::Step))
     }
 }
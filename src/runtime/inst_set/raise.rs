@@ -0,0 +1,23 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::{Result, RuntimeError},
+    instructions::{InstEval, InstructionResult},
+    stack_frame::LocalStack,
+    value::ErrorValue,
+};
+
+#[derive(Clone, Debug)]
+pub struct Raise;
+
+impl InstEval for Raise {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let value = stack.pop()?;
+        if let Ok(message) = value.as_str() {
+            return Err(ErrorValue::new("error".into(), message.clone()).to_runtime_error());
+        }
+        Err(value
+            .as_error()
+            .map_err(|_| RuntimeError::new_type_error("Only errors and strings can be raised."))?
+            .to_runtime_error())
+    }
+}
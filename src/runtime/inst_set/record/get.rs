@@ -0,0 +1,28 @@
+use crate::{
+    binary::indexes::LocalConstIndex,
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct RecordGet(LocalConstIndex);
+
+impl RecordGet {
+    pub fn new(field: LocalConstIndex) -> Self {
+        RecordGet(field)
+    }
+}
+
+impl InstEval for RecordGet {
+    fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let field = ctxt.get_constant(self.0)?;
+        let record_value = stack.pop()?;
+        let value = record_value.as_record()?.get(field.as_str()?)?;
+        stack.push(value)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
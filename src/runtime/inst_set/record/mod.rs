@@ -0,0 +1,7 @@
+mod get;
+mod new;
+mod set;
+
+pub use get::RecordGet;
+pub use new::RecordNew;
+pub use set::RecordSet;
@@ -0,0 +1,39 @@
+use crate::{
+    binary::indexes::LocalConstIndex,
+    runtime::{
+        context::InstEvalContext,
+        error::{Result, RuntimeError},
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+        value::{PinnedValue, Record},
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct RecordNew(LocalConstIndex);
+
+impl RecordNew {
+    pub fn new(fields: LocalConstIndex) -> Self {
+        RecordNew(fields)
+    }
+}
+
+impl InstEval for RecordNew {
+    fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let fields_value = ctxt.get_constant(self.0)?;
+        let fields = fields_value.as_list()?;
+        let names = (0..fields.len())
+            .map(|i| Ok(fields.at(i).as_str()?.clone()))
+            .collect::<Result<Vec<_>>>()?;
+        let num_fields = u32::try_from(names.len()).map_err(|_| {
+            RuntimeError::new_operation_precondition_error("Too many record fields.")
+        })?;
+        let shape = ctxt.get_env().record_shape(names)?;
+        ctxt.get_env().with_value_buffer(|buffer| {
+            stack.drain_top_n(num_fields, buffer)?;
+            let record = Record::new(ctxt.get_env(), shape, buffer.drain(..));
+            stack.push(PinnedValue::new_record(record))?;
+            Ok(InstructionResult::Next(InstructionTarget::Step))
+        })
+    }
+}
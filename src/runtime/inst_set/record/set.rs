@@ -0,0 +1,28 @@
+use crate::{
+    binary::indexes::LocalConstIndex,
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct RecordSet(LocalConstIndex);
+
+impl RecordSet {
+    pub fn new(field: LocalConstIndex) -> Self {
+        RecordSet(field)
+    }
+}
+
+impl InstEval for RecordSet {
+    fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let field = ctxt.get_constant(self.0)?;
+        let record_value = stack.pop()?;
+        let record = record_value.as_record()?;
+        record.set(field.as_str()?, stack.pop()?)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -1,15 +1,18 @@
-use crate::runtime::{
-    context::InstEvalContext,
-    error::Result,
-    instructions::{InstEval, InstructionResult, InstructionTarget},
-    stack_frame::LocalStack,
+use crate::{
+    binary::indexes::GlobalIndex,
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+    },
 };
 
 #[derive(Clone, Debug)]
-pub struct SetGlobal(u32);
+pub struct SetGlobal(GlobalIndex);
 
 impl SetGlobal {
-    pub fn new(index: u32) -> Self {
+    pub fn new(index: GlobalIndex) -> Self {
         SetGlobal(index)
     }
 }
@@ -0,0 +1,29 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+    value::PinnedValue,
+};
+
+#[derive(Clone, Debug)]
+pub struct StrLenBytes;
+
+impl InstEval for StrLenBytes {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let len = stack.pop()?.as_str()?.as_str().len();
+        stack.push(PinnedValue::new_integer(i64::try_from(len).unwrap().into()))?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StrLenChars;
+
+impl InstEval for StrLenChars {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let len = stack.pop()?.as_str()?.as_str().chars().count();
+        stack.push(PinnedValue::new_integer(i64::try_from(len).unwrap().into()))?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -0,0 +1,109 @@
+mod len;
+mod slice;
+
+pub use len::{StrLenBytes, StrLenChars};
+pub use slice::{StrSliceBytes, StrSliceChars};
+
+use std::ops::Range;
+
+use crate::runtime::error::{Result, RuntimeError};
+
+/// Resolves a slice bound counted in `unit`s, counting from the end when
+/// negative. The result must be within `0..=len`.
+fn resolve_bound(index: i64, len: usize, unit: &str) -> Result<usize> {
+    let signed_len = i64::try_from(len).unwrap();
+    let resolved = if index < 0 {
+        index.saturating_add(signed_len)
+    } else {
+        index
+    };
+    if (0..=signed_len).contains(&resolved) {
+        Ok(usize::try_from(resolved).unwrap())
+    } else {
+        Err(RuntimeError::new_operation_precondition_error(format!(
+            "Index {index} is out of range for a string of {len} {unit}s."
+        )))
+    }
+}
+
+/// Resolves the `start` and `end` indexes of a slice of a string that is
+/// `len` `unit`s long.
+fn resolve_range(start: i64, end: i64, len: usize, unit: &str) -> Result<Range<usize>> {
+    let range = resolve_bound(start, len, unit)?..resolve_bound(end, len, unit)?;
+    if range.start > range.end {
+        return Err(RuntimeError::new_operation_precondition_error(format!(
+            "Slice start {start} is after its end {end}."
+        )));
+    }
+    Ok(range)
+}
+
+/// Checks that the byte offset `offset` of `s`, given as `index`, is on a
+/// char boundary. The error names the boundaries on either side.
+fn check_char_boundary(s: &str, index: i64, offset: usize) -> Result<()> {
+    if s.is_char_boundary(offset) {
+        return Ok(());
+    }
+    let before = (0..offset).rev().find(|&i| s.is_char_boundary(i)).unwrap();
+    let after = (offset..=s.len()).find(|&i| s.is_char_boundary(i)).unwrap();
+    Err(RuntimeError::new_operation_precondition_error(format!(
+        "Byte index {index} is inside a char. The nearest char boundaries are {before} and {after}."
+    )))
+}
+
+/// Returns the byte range of `s` for the byte indexes `start` and `end`.
+fn byte_range(s: &str, start: i64, end: i64) -> Result<Range<usize>> {
+    let range = resolve_range(start, end, s.len(), "byte")?;
+    check_char_boundary(s, start, range.start)?;
+    check_char_boundary(s, end, range.end)?;
+    Ok(range)
+}
+
+/// Returns the byte range of `s` for the char indexes `start` and `end`.
+fn char_range(s: &str, start: i64, end: i64) -> Result<Range<usize>> {
+    let range = resolve_range(start, end, s.chars().count(), "char")?;
+    let mut offsets = s
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(s.len()));
+    let start_offset = offsets.nth(range.start).unwrap();
+    let end_offset = match range.len() {
+        0 => start_offset,
+        len => offsets.nth(len - 1).unwrap(),
+    };
+    Ok(start_offset..end_offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(result: Result<Range<usize>>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn byte_ranges_stay_on_char_boundaries() {
+        let s = "h\u{e9}llo";
+        assert_eq!(byte_range(s, 0, 3).unwrap(), 0..3);
+        assert_eq!(byte_range(s, -3, -1).unwrap(), 3..5);
+        assert_eq!(byte_range(s, 6, 6).unwrap(), 6..6);
+        assert!(message(byte_range(s, 2, 4)).contains("Byte index 2 is inside a char"));
+        assert!(message(byte_range(s, 0, 2)).contains("boundaries are 1 and 3"));
+        assert!(message(byte_range(s, 0, 7))
+            .contains("Index 7 is out of range for a string of 6 bytes"));
+        assert!(message(byte_range(s, 4, 3)).contains("Slice start 4 is after its end 3"));
+    }
+
+    #[test]
+    fn char_ranges_map_to_bytes() {
+        let s = "h\u{e9}llo";
+        assert_eq!(char_range(s, 0, 2).unwrap(), 0..3);
+        assert_eq!(char_range(s, 1, 1).unwrap(), 1..1);
+        assert_eq!(char_range(s, -2, 5).unwrap(), 4..6);
+        assert_eq!(char_range("", 0, 0).unwrap(), 0..0);
+        assert!(message(char_range(s, 0, 6))
+            .contains("Index 6 is out of range for a string of 5 chars"));
+        assert!(message(char_range(s, i64::MIN, 1)).contains("out of range"));
+    }
+}
@@ -0,0 +1,44 @@
+use std::ops::Range;
+
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+    value::PinnedValue,
+};
+
+use super::{byte_range, char_range};
+
+/// Pops a string and its slice bounds, and pushes the part of the string in
+/// the byte range that `range` computes from them.
+fn slice(
+    stack: &LocalStack,
+    range: fn(&str, i64, i64) -> Result<Range<usize>>,
+) -> Result<InstructionResult> {
+    let string_value = stack.pop()?;
+    let s = string_value.as_str()?.as_str();
+    let start = stack.pop()?.as_compact_integer()?;
+    let end = stack.pop()?.as_compact_integer()?;
+    let slice = &s[range(s, start, end)?];
+    stack.push(PinnedValue::new_string(slice.into()))?;
+    Ok(InstructionResult::Next(InstructionTarget::Step))
+}
+
+#[derive(Clone, Debug)]
+pub struct StrSliceBytes;
+
+impl InstEval for StrSliceBytes {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        slice(stack, byte_range)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StrSliceChars;
+
+impl InstEval for StrSliceChars {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        slice(stack, char_range)
+    }
+}
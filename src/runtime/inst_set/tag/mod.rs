@@ -0,0 +1,9 @@
+mod new;
+mod of;
+mod untag;
+mod wrap;
+
+pub use new::TagNew;
+pub use of::TagOf;
+pub use untag::Untag;
+pub use wrap::TagWrap;
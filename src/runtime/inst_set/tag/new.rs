@@ -0,0 +1,27 @@
+use crate::{
+    binary::indexes::LocalConstIndex,
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+        value::{PinnedValue, Tag},
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct TagNew(LocalConstIndex);
+
+impl TagNew {
+    pub fn new(name: LocalConstIndex) -> Self {
+        TagNew(name)
+    }
+}
+
+impl InstEval for TagNew {
+    fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let name = ctxt.get_constant(self.0)?.as_str()?.clone();
+        stack.push(PinnedValue::new_tag(Tag::new(ctxt.get_env(), name)))?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
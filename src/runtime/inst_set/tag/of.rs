@@ -0,0 +1,22 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+    value::PinnedValue,
+};
+
+#[derive(Clone, Debug)]
+pub struct TagOf;
+
+impl InstEval for TagOf {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let value = stack.pop()?;
+        let tag = match value.as_tagged() {
+            Ok(tagged) => PinnedValue::new_tag(tagged.tag()),
+            Err(_) => PinnedValue::new_null(),
+        };
+        stack.push(tag)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -0,0 +1,25 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::{Result, RuntimeError},
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+};
+
+#[derive(Clone, Debug)]
+pub struct Untag;
+
+impl InstEval for Untag {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let tag_value = stack.pop()?;
+        let tag = tag_value.as_tag()?;
+        let tagged_value = stack.pop()?;
+        let value = tagged_value.as_tagged()?.untag(tag).ok_or_else(|| {
+            RuntimeError::new_type_error(format!(
+                "Value is not tagged with {}.",
+                tag.name().as_str()
+            ))
+        })?;
+        stack.push(value)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
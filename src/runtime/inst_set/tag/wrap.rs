@@ -0,0 +1,21 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+    value::{PinnedValue, Tagged},
+};
+
+#[derive(Clone, Debug)]
+pub struct TagWrap;
+
+impl InstEval for TagWrap {
+    fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let tag_value = stack.pop()?;
+        let tag = tag_value.as_tag()?.clone();
+        let value = stack.pop()?;
+        let tagged = Tagged::new(ctxt.get_env(), tag, value);
+        stack.push(PinnedValue::new_tagged(tagged))?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -0,0 +1,27 @@
+use crate::{
+    binary::instructions::Truthiness,
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+        value::PinnedValue,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct ToBool(Truthiness);
+
+impl ToBool {
+    pub fn new(truthiness: Truthiness) -> Self {
+        ToBool(truthiness)
+    }
+}
+
+impl InstEval for ToBool {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let value = stack.pop()?;
+        stack.push(PinnedValue::new_bool(value.to_bool(self.0)?))?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
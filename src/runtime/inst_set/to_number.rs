@@ -0,0 +1,26 @@
+use crate::{
+    binary::instructions::NumericKind,
+    runtime::{
+        context::InstEvalContext,
+        error::Result,
+        instructions::{InstEval, InstructionResult, InstructionTarget},
+        stack_frame::LocalStack,
+    },
+};
+
+#[derive(Clone, Debug)]
+pub struct ToNumber(NumericKind);
+
+impl ToNumber {
+    pub fn new(kind: NumericKind) -> Self {
+        ToNumber(kind)
+    }
+}
+
+impl InstEval for ToNumber {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let value = stack.pop()?;
+        stack.push(value.to_number(self.0)?)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -0,0 +1,17 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+};
+
+#[derive(Clone, Debug)]
+pub struct WeakGet;
+
+impl InstEval for WeakGet {
+    fn execute(&self, _ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let weak_value = stack.pop()?;
+        stack.push(weak_value.as_weak_ref()?.get())?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -0,0 +1,5 @@
+mod get;
+mod new;
+
+pub use get::WeakGet;
+pub use new::WeakNew;
@@ -0,0 +1,19 @@
+use crate::runtime::{
+    context::InstEvalContext,
+    error::Result,
+    instructions::{InstEval, InstructionResult, InstructionTarget},
+    stack_frame::LocalStack,
+    value::{PinnedValue, WeakRef},
+};
+
+#[derive(Clone, Debug)]
+pub struct WeakNew;
+
+impl InstEval for WeakNew {
+    fn execute(&self, ctxt: &InstEvalContext, stack: &LocalStack) -> Result<InstructionResult> {
+        let target = stack.pop()?;
+        let weak_ref = PinnedValue::new_weak_ref(WeakRef::new(ctxt.get_env(), &target)?);
+        stack.push(weak_ref)?;
+        Ok(InstructionResult::Next(InstructionTarget::Step))
+    }
+}
@@ -1,8 +1,10 @@
 use std::rc::Rc;
 
-use crate::gc::{GcRefVisitor, GcTraceable};
+use crate::gc::{GcRefVisitor, GcTraceable, PinnedGcRef};
 
-use super::{context::InstEvalContext, error::RuntimeError, stack_frame::LocalStack};
+use super::{
+    context::InstEvalContext, error::RuntimeError, stack_frame::LocalStack, value::Function,
+};
 
 #[derive(Clone, Copy, Debug)]
 pub enum InstructionTarget {
@@ -13,6 +15,9 @@ pub enum InstructionTarget {
 pub struct FunctionCallResult {
     num_args: u32,
     return_target: InstructionTarget,
+    /// Where to continue with the caught error if the call fails, for
+    /// protected calls.
+    handler: Option<u32>,
 }
 
 impl FunctionCallResult {
@@ -20,6 +25,17 @@ impl FunctionCallResult {
         FunctionCallResult {
             num_args,
             return_target,
+            handler: None,
+        }
+    }
+
+    /// Creates a call that continues at `handler` if it fails with an error
+    /// that can be caught.
+    pub fn new_protected(num_args: u32, return_target: InstructionTarget, handler: u32) -> Self {
+        FunctionCallResult {
+            num_args,
+            return_target,
+            handler: Some(handler),
         }
     }
 
@@ -30,6 +46,10 @@ impl FunctionCallResult {
     pub fn return_target(&self) -> InstructionTarget {
         self.return_target
     }
+
+    pub fn handler(&self) -> Option<u32> {
+        self.handler
+    }
 }
 
 pub enum InstructionResult {
@@ -112,6 +132,9 @@ impl GcTraceable for InstEvalList {
 
 pub struct CallStepResult {
     pub num_args: u32,
+    /// The function to call, if the frame has already taken it off its
+    /// stack. Otherwise it is found below the arguments.
+    pub function: Option<PinnedGcRef<Function>>,
 }
 
 pub struct YieldStepResult;
@@ -0,0 +1,186 @@
+//! Execution limits that are enforced at interpreter safe points.
+//!
+//! Safe points are placed at loop back-edges (branches to an earlier
+//! instruction) and at frame changes. Straight-line code between safe points
+//! only pays for a local step counter, which is charged against the fuel
+//! budget in bulk at the next safe point.
+//!
+//! Native functions cannot be interrupted, so their limits are checked when
+//! they return.
+
+use std::{
+    any::Any,
+    cell::Cell,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use super::error::{Result, RuntimeError};
+
+/// A handle that can be used to cancel execution in a [`super::Runtime`],
+/// possibly from another thread.
+///
+/// Cancellation is observed at the next safe point, at which time the running
+/// call fails with [`RuntimeError::Cancelled`] and the request is cleared.
+#[derive(Clone, Debug)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    /// Requests that the currently running (or next) call be cancelled.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// The default limit on how deeply constant lists may nest.
+pub(crate) const DEFAULT_MAX_NESTING_DEPTH: usize = 1024;
+
+/// The default limit on how many calls may be active at once.
+pub(crate) const DEFAULT_MAX_CALL_DEPTH: usize = 4096;
+
+pub(crate) struct ExecutionLimits {
+    fuel: Cell<Option<u64>>,
+    deadline: Cell<Option<Instant>>,
+    cancel_requested: Arc<AtomicBool>,
+    max_nesting_depth: Cell<usize>,
+    max_frame_stack_size: Cell<Option<usize>>,
+    max_call_depth: Cell<Option<usize>>,
+    catch_native_panics: Cell<bool>,
+    max_native_call_time: Cell<Option<Duration>>,
+}
+
+/// Returns the message of a panic payload, if it has one.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<dyn Any>".to_string()
+    }
+}
+
+impl ExecutionLimits {
+    pub fn new() -> Self {
+        ExecutionLimits {
+            fuel: Cell::new(None),
+            deadline: Cell::new(None),
+            cancel_requested: Arc::new(AtomicBool::new(false)),
+            max_nesting_depth: Cell::new(DEFAULT_MAX_NESTING_DEPTH),
+            max_frame_stack_size: Cell::new(None),
+            max_call_depth: Cell::new(Some(DEFAULT_MAX_CALL_DEPTH)),
+            catch_native_panics: Cell::new(true),
+            max_native_call_time: Cell::new(None),
+        }
+    }
+
+    pub fn set_catch_native_panics(&self, catch: bool) {
+        self.catch_native_panics.set(catch);
+    }
+
+    pub fn catch_native_panics(&self) -> bool {
+        self.catch_native_panics.get()
+    }
+
+    pub fn set_max_native_call_time(&self, time: Option<Duration>) {
+        self.max_native_call_time.set(time);
+    }
+
+    pub fn max_native_call_time(&self) -> Option<Duration> {
+        self.max_native_call_time.get()
+    }
+
+    /// Runs the body of a native function, or one of its continuations,
+    /// turning a panic into [`RuntimeError::NativePanic`] if panics are
+    /// caught, and failing if it ran for longer than a native call may. Each
+    /// call of this is timed on its own, so time spent in earlier callbacks
+    /// of the same native call is not counted.
+    pub fn run_native<R>(&self, body: impl FnOnce() -> Result<R>) -> Result<R> {
+        let started = Instant::now();
+        let result = if self.catch_native_panics.get() {
+            panic::catch_unwind(AssertUnwindSafe(body))
+                .unwrap_or_else(|payload| Err(RuntimeError::NativePanic(panic_message(&*payload))))
+        } else {
+            body()
+        };
+        let value = result?;
+        if let Some(limit) = self.max_native_call_time.get() {
+            if started.elapsed() > limit {
+                return Err(RuntimeError::NativeCallTimeout(limit));
+            }
+        }
+        Ok(value)
+    }
+
+    pub fn set_max_nesting_depth(&self, depth: usize) {
+        self.max_nesting_depth.set(depth);
+    }
+
+    pub fn max_nesting_depth(&self) -> usize {
+        self.max_nesting_depth.get()
+    }
+
+    pub fn set_max_frame_stack_size(&self, size: Option<usize>) {
+        self.max_frame_stack_size.set(size);
+    }
+
+    pub fn max_frame_stack_size(&self) -> Option<usize> {
+        self.max_frame_stack_size.get()
+    }
+
+    pub fn set_max_call_depth(&self, depth: Option<usize>) {
+        self.max_call_depth.set(depth);
+    }
+
+    pub fn max_call_depth(&self) -> Option<usize> {
+        self.max_call_depth.get()
+    }
+
+    pub fn set_fuel(&self, fuel: Option<u64>) {
+        self.fuel.set(fuel);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel.get()
+    }
+
+    /// Sets the wall-clock deadline for execution, returning the previous one.
+    pub fn replace_deadline(&self, deadline: Option<Instant>) -> Option<Instant> {
+        self.deadline.replace(deadline)
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline.get()
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle(self.cancel_requested.clone())
+    }
+
+    /// Charges `steps` executed instructions against the limits, returning an
+    /// error if any limit has been exceeded.
+    pub fn check(&self, steps: u64) -> Result<()> {
+        if self.cancel_requested.swap(false, Ordering::AcqRel) {
+            return Err(RuntimeError::Cancelled);
+        }
+        if let Some(deadline) = self.deadline.get() {
+            if Instant::now() >= deadline {
+                return Err(RuntimeError::Timeout);
+            }
+        }
+        if let Some(fuel) = self.fuel.get() {
+            match fuel.checked_sub(steps) {
+                Some(remaining) => self.fuel.set(Some(remaining)),
+                None => {
+                    self.fuel.set(Some(0));
+                    return Err(RuntimeError::OutOfFuel);
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,18 +1,47 @@
+mod capabilities;
 mod constants;
 mod context;
 mod core;
+mod debug;
 mod environment;
 mod error;
 mod eval_context;
 mod global_env;
+mod handle;
+mod host_value;
 mod inst_set;
 mod instructions;
+mod limits;
+mod module_tests;
 mod modules;
+mod native_module;
+mod profile;
+mod quota;
+mod records;
 mod stack;
 mod stack_frame;
+mod stdlib;
+mod thunk;
+mod timers;
 mod top_level;
 mod value;
 
+pub use crate::binary::FunctionId;
+pub use crate::gc::{GcStats, GcTrigger};
+pub use capabilities::{Capability, CapabilitySet};
 pub use core::Runtime;
-pub use error::{Result, RuntimeError};
-pub use top_level::TopLevelRuntime;
+pub use debug::{Breakpoint, DebugFrame, DebugHandler};
+pub use error::{ErrorKind, Result, RuntimeError, StackTraceFrame};
+pub use handle::ValueHandle;
+pub use host_value::{HostValue, ValueKind};
+pub use limits::CancelHandle;
+pub use module_tests::{TestOutcome, TestResult};
+pub use native_module::NativeModule;
+pub use profile::{FunctionOptimizer, FunctionProfile};
+pub use records::{FromLoonRecord, IntoLoonRecord, RecordFields};
+pub use stack_frame::StackShrinkPolicy;
+pub use stdlib::io::{IoBackend, MemoryIoBackend, OpenMode};
+pub use thunk::{FromStack, IntoStack, ThunkArgs, ThunkReturn};
+pub use timers::TimerId;
+pub use top_level::{StepOutcome, TopLevelRuntime};
+pub use value::{CallerInfo, PinnedValue};
@@ -0,0 +1,105 @@
+//! Running the unit tests that modules ship with.
+//!
+//! Tests are declared in lat with `(test "name" <function>)` items. They are
+//! not exported, so they can only be run through
+//! [`TopLevelRuntime::run_module_tests`] or
+//! [`Runtime::run_module_tests`](super::Runtime::run_module_tests).
+
+use crate::binary::{instructions::Truthiness, modules::ModuleId};
+
+use super::{
+    error::{Result, RuntimeError},
+    top_level::TopLevelRuntime,
+    value::PinnedValue,
+};
+
+/// How a module test finished.
+#[derive(Debug)]
+pub enum TestOutcome {
+    Passed,
+    /// The test returned `false` or null.
+    ReturnedFalsy,
+    /// The test failed with an error.
+    Failed(RuntimeError),
+}
+
+/// The outcome of one test of a module.
+#[derive(Debug)]
+pub struct TestResult {
+    name: String,
+    outcome: TestOutcome,
+}
+
+impl TestResult {
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn outcome(&self) -> &TestOutcome {
+        &self.outcome
+    }
+
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, TestOutcome::Passed)
+    }
+}
+
+impl std::fmt::Display for TestResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.outcome {
+            TestOutcome::Passed => write!(f, "{}: ok", self.name),
+            TestOutcome::ReturnedFalsy => write!(f, "{}: returned a falsy value", self.name),
+            TestOutcome::Failed(error) => write!(f, "{}: {error}", self.name),
+        }
+    }
+}
+
+impl TopLevelRuntime {
+    /// Runs the tests of a loaded module in the order they were declared,
+    /// initializing the module first if needed.
+    ///
+    /// Each test is called with no arguments. It passes unless it fails with
+    /// an error, or the first value it returns is `false` or null. The
+    /// outcomes of the tests are returned, and this only fails if the module
+    /// is not loaded or cannot be initialized.
+    pub fn run_module_tests(&self, module_id: &ModuleId) -> Result<Vec<TestResult>> {
+        self.init_module(module_id)?;
+        self.global_context()
+            .get_module_tests(module_id)?
+            .into_iter()
+            .map(|(name, test)| {
+                Ok(TestResult {
+                    name,
+                    outcome: self.run_test(test)?,
+                })
+            })
+            .collect()
+    }
+
+    fn run_test(&self, test: PinnedValue) -> Result<TestOutcome> {
+        let mut stack = self.stack();
+        let base = stack.len();
+        stack.push_value(test)?;
+        drop(stack);
+        let outcome = match self.call_function(0) {
+            Ok(0) => TestOutcome::Passed,
+            Ok(num_returns) => {
+                let returns = self.stack().peek_n(num_returns as usize)?;
+                if returns[0].to_bool(Truthiness::NullAndFalse)? {
+                    TestOutcome::Passed
+                } else {
+                    TestOutcome::ReturnedFalsy
+                }
+            }
+            Err(error) => TestOutcome::Failed(error),
+        };
+        // Leave the stack as it was, whatever the test left on it.
+        let mut stack = self.stack();
+        let extra = stack.len().saturating_sub(base);
+        stack.pop_n(extra)?;
+        Ok(outcome)
+    }
+}
@@ -4,6 +4,7 @@ use std::{
 };
 
 use super::{
+    capabilities::Capability,
     constants::ValueTable,
     context::ConstResolutionContext,
     environment::ModuleImportEnvironment,
@@ -12,8 +13,13 @@ use super::{
     value::{Function, PinnedValue, Value},
 };
 use crate::{
-    binary::{modules::ModuleMemberId, ConstModule},
+    binary::{
+        indexes::{GlobalIndex, ModuleConstIndex},
+        modules::{ImportSource, ModuleMemberId},
+        ConstModule,
+    },
     gc::{GcRef, GcTraceable, PinnedGcRef},
+    util::suggest::closest_match,
 };
 
 pub struct ModuleGlobals {
@@ -29,28 +35,31 @@ impl ModuleGlobals {
         global_env.create_pinned_ref(ModuleGlobals { values: globals })
     }
 
-    pub fn at(&self, index: u32) -> Result<PinnedValue> {
-        let cell = self
-            .values
-            .get(usize::try_from(index).unwrap())
-            .ok_or_else(|| RuntimeError::new_internal_error("Index out of bounds."))?;
-        let result = cell
-            .borrow()
-            .as_ref()
-            .map(Value::pin)
-            .ok_or_else(|| RuntimeError::new_internal_error("Global not set."))?;
+    /// Returns the number of globals.
+    pub fn size(&self) -> u32 {
+        u32::try_from(self.values.len()).expect("Global tables are indexed by u32.")
+    }
+
+    pub fn at(&self, index: GlobalIndex) -> Result<PinnedValue> {
+        let cell = index.get(&self.values).ok_or_else(|| {
+            RuntimeError::new_operation_precondition_error("Global index out of range.")
+        })?;
+        let result = cell.borrow().as_ref().map(Value::pin).ok_or_else(|| {
+            RuntimeError::new_operation_precondition_error("Global read before it was set.")
+        })?;
         Ok(result)
     }
 
     pub fn set(
         &self,
-        index: u32,
+        index: GlobalIndex,
         value: PinnedValue,
     ) -> std::prelude::v1::Result<(), RuntimeError> {
-        let mut cell = self
-            .values
-            .get(usize::try_from(index).unwrap())
-            .ok_or_else(|| RuntimeError::new_internal_error("Index out of bounds."))?
+        let mut cell = index
+            .get(&self.values)
+            .ok_or_else(|| {
+                RuntimeError::new_operation_precondition_error("Global index out of range.")
+            })?
             .borrow_mut();
         cell.replace(value.to_value());
         Ok(())
@@ -73,9 +82,15 @@ impl GcTraceable for ModuleGlobals {
 pub struct Module {
     members: GcRef<ValueTable>,
     module_globals: GcRef<ModuleGlobals>,
-    exports: HashMap<ModuleMemberId, u32>,
-    initializer: Option<u32>,
+    exports: HashMap<ModuleMemberId, ModuleConstIndex>,
+    /// The documentation strings of exports that have them.
+    export_docs: HashMap<ModuleMemberId, String>,
+    initializer: Option<ModuleConstIndex>,
+    /// The module's unit tests, by name.
+    tests: Vec<(String, ModuleConstIndex)>,
     is_initialized: Cell<bool>,
+    /// Capabilities a module must be granted to import from this module.
+    required_capabilities: Vec<Capability>,
 }
 
 impl Module {
@@ -84,12 +99,14 @@ impl Module {
         let import_values = module
             .imports()
             .iter()
-            .map(|id| ctxt.get_import(id))
+            .map(|id| ctxt.get_import_for(module.id(), id))
             .collect::<Result<Vec<_>>>()?;
         let module_globals = ModuleGlobals::from_size_empty(ctxt, module.global_table_size());
         let import_env = ModuleImportEnvironment::new(ctxt, import_values);
+        let module_id = ctxt.intern_module_id(module.id());
         let members = {
-            let const_ctxt = ConstResolutionContext::new(ctxt, &module_globals, &import_env);
+            let const_ctxt = ConstResolutionContext::new(ctxt, &module_globals, &import_env)
+                .with_module_id(&module_id);
             ValueTable::from_binary(module.const_table(), &const_ctxt)?
         };
         // The module is already initialized if there is no initializer to run.
@@ -98,19 +115,102 @@ impl Module {
             Ok(ctxt.create_pinned_ref(Module {
                 members: members.into_ref(lock.guard()),
                 module_globals: module_globals.into_ref(lock.guard()),
-                exports: module.exports().clone(),
+                exports: module
+                    .exports()
+                    .iter()
+                    .map(|(name, index)| (ctxt.intern_member_name(name), *index))
+                    .collect(),
+                export_docs: module.export_docs().clone(),
                 initializer: module.initializer(),
+                tests: module.tests().to_vec(),
                 is_initialized: Cell::new(is_initialized),
+                required_capabilities: Vec::new(),
             }))
         })
     }
 
-    pub fn get_export(&self, name: &ModuleMemberId) -> Result<PinnedValue> {
-        let index = self
-            .exports
-            .get(name)
-            .ok_or_else(|| RuntimeError::new_internal_error("Export not found."))?;
-        self.members.borrow().at(*index)
+    /// Creates an already initialized module that exports the given values.
+    pub fn from_exports(
+        ctxt: &GlobalEnv,
+        exports: impl IntoIterator<Item = (ModuleMemberId, PinnedValue)>,
+        required_capabilities: Vec<Capability>,
+    ) -> PinnedGcRef<Self> {
+        let (names, values): (Vec<_>, Vec<_>) = exports.into_iter().unzip();
+        let members = ValueTable::from_values(ctxt, values);
+        let module_globals = ModuleGlobals::from_size_empty(ctxt, 0);
+        let exports = names
+            .into_iter()
+            .enumerate()
+            .map(|(i, name)| {
+                let index = ModuleConstIndex::from_usize(i).expect("Too many exports.");
+                (ctxt.intern_member_name(&name), index)
+            })
+            .collect();
+        ctxt.with_lock(|lock| {
+            ctxt.create_pinned_ref(Module {
+                members: members.into_ref(lock.guard()),
+                module_globals: module_globals.into_ref(lock.guard()),
+                exports,
+                export_docs: HashMap::new(),
+                initializer: None,
+                tests: Vec::new(),
+                is_initialized: Cell::new(true),
+                required_capabilities,
+            })
+        })
+    }
+
+    pub fn required_capabilities(&self) -> &[Capability] {
+        &self.required_capabilities
+    }
+
+    /// Returns all values in the module's constant table.
+    pub fn members(&self) -> Vec<PinnedValue> {
+        self.members.borrow().values().collect()
+    }
+
+    /// Returns the names this module exports, in sorted order.
+    pub fn export_names(&self) -> Vec<&ModuleMemberId> {
+        let mut names = self.exports.keys().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Returns the documentation string of the named export, if it has one.
+    pub fn export_doc(&self, name: &ModuleMemberId) -> Option<&str> {
+        self.export_docs.get(name).map(String::as_str)
+    }
+
+    /// Returns the export that `source` imports. If there is none, the error
+    /// lists the exports there are, and suggests one that is a close match.
+    pub fn get_export(&self, source: &ImportSource) -> Result<PinnedValue> {
+        let name = source.import_name();
+        let Some(index) = self.exports.get(name) else {
+            let available = self
+                .export_names()
+                .into_iter()
+                .map(|name| name.as_str().to_string())
+                .collect::<Vec<_>>();
+            let suggestion = closest_match(name.as_str(), available.iter().map(String::as_str))
+                .map(str::to_string);
+            return Err(RuntimeError::ExportNotFound {
+                module: source.module_id().to_string(),
+                name: name.as_str().to_string(),
+                available,
+                suggestion,
+            });
+        };
+        self.members.borrow().at(index.as_usize())
+    }
+
+    /// Returns the module's unit tests, by name, in the order they were
+    /// declared.
+    pub fn tests(&self) -> Result<Vec<(String, PinnedValue)>> {
+        let members = self.members.borrow();
+        self.tests
+            .iter()
+            .map(|(name, index)| Ok((name.clone(), members.at(index.as_usize())?)))
+            .collect()
     }
 
     pub fn get_init_function(&self) -> Result<Option<PinnedGcRef<Function>>> {
@@ -121,7 +221,11 @@ impl Module {
             .initializer
             .expect("Can only be uninitialized if there is an initializer.");
         Ok(Some(
-            self.members.borrow().at(index)?.as_function()?.clone(),
+            self.members
+                .borrow()
+                .at(index.as_usize())?
+                .as_function()?
+                .clone(),
         ))
     }
 
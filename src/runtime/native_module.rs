@@ -0,0 +1,59 @@
+//! Modules whose members are implemented by the host.
+
+use crate::binary::modules::{ModuleId, ModuleMemberId};
+
+use super::{
+    capabilities::Capability,
+    error::Result,
+    value::{NativeFunctionContext, NativeFunctionPtr, NativeFunctionResult},
+};
+
+/// A module of native functions that can be loaded into a [`super::Runtime`].
+///
+/// Once loaded, managed modules can import its members like those of any
+/// other module, as long as they were granted the capabilities the module
+/// requires.
+pub struct NativeModule {
+    id: ModuleId,
+    functions: Vec<(ModuleMemberId, NativeFunctionPtr)>,
+    required_capabilities: Vec<Capability>,
+}
+
+impl NativeModule {
+    pub fn new(id: impl Into<ModuleId>) -> Self {
+        NativeModule {
+            id: id.into(),
+            functions: Vec::new(),
+            required_capabilities: Vec::new(),
+        }
+    }
+
+    pub fn id(&self) -> &ModuleId {
+        &self.id
+    }
+
+    /// Adds a native function exported under the given name.
+    pub fn add_function<F>(&mut self, name: impl Into<ModuleMemberId>, function: F) -> &mut Self
+    where
+        F: Fn(NativeFunctionContext) -> Result<NativeFunctionResult> + 'static,
+    {
+        self.functions
+            .push((name.into(), NativeFunctionPtr::new(function)));
+        self
+    }
+
+    /// Requires managed modules to be granted `capability` before they can
+    /// import from this module. See [`super::Runtime::grant_capabilities`].
+    pub fn require_capability(&mut self, capability: impl Into<Capability>) -> &mut Self {
+        self.required_capabilities.push(capability.into());
+        self
+    }
+
+    pub fn required_capabilities(&self) -> &[Capability] {
+        &self.required_capabilities
+    }
+
+    pub(crate) fn functions(&self) -> &[(ModuleMemberId, NativeFunctionPtr)] {
+        &self.functions
+    }
+}
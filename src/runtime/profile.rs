@@ -0,0 +1,175 @@
+//! Call-count profiling of managed functions, sampling of call stacks, and
+//! the hook for swapping in optimized code for hot functions.
+//!
+//! Every managed function counts its calls. Embedders can read the counts to
+//! find hot functions, and can install a [`FunctionOptimizer`] that is given
+//! the chance to replace a function's instructions once it has been called
+//! often enough. This is the groundwork for a tiered execution engine.
+//!
+//! Call counts do not show where time goes, so the runtime can also sample
+//! the managed call stack every so many instructions, and report the samples
+//! in the collapsed stack format read by flamegraph tools.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    fmt::Write,
+};
+
+use crate::binary::{indexes::ModuleConstIndex, instructions::InstructionList, modules::ModuleId};
+
+use super::FunctionId;
+
+/// The call count of a managed function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FunctionProfile {
+    module_id: Option<ModuleId>,
+    const_index: ModuleConstIndex,
+    call_count: u64,
+}
+
+impl FunctionProfile {
+    pub(crate) fn new(
+        module_id: Option<ModuleId>,
+        const_index: ModuleConstIndex,
+        call_count: u64,
+    ) -> Self {
+        FunctionProfile {
+            module_id,
+            const_index,
+            call_count,
+        }
+    }
+
+    /// The module that defines the function, if it was loaded from a module.
+    #[must_use]
+    pub fn module_id(&self) -> Option<&ModuleId> {
+        self.module_id.as_ref()
+    }
+
+    /// The index of the function in its module's constant table.
+    #[must_use]
+    pub fn const_index(&self) -> ModuleConstIndex {
+        self.const_index
+    }
+
+    /// The stable id of the function.
+    #[must_use]
+    pub fn function_id(&self) -> FunctionId {
+        FunctionId::Managed {
+            module_id: self.module_id.clone(),
+            const_index: self.const_index,
+        }
+    }
+
+    /// The number of times the function has been called.
+    #[must_use]
+    pub fn call_count(&self) -> u64 {
+        self.call_count
+    }
+}
+
+/// Produces optimized variants of hot functions.
+pub trait FunctionOptimizer {
+    /// Called once when a function reaches the call threshold, with the
+    /// function's original instructions. Returning a new instruction list
+    /// makes later calls run it instead. Calls already in progress finish with
+    /// the instructions they started with.
+    ///
+    /// The new instructions refer to the same constants and globals as the
+    /// original ones. Instructions that fail the checks the module was loaded
+    /// with (operand bounds and the module's instruction policy) are ignored,
+    /// and the function keeps running its original instructions.
+    fn optimize(
+        &self,
+        profile: &FunctionProfile,
+        instructions: &InstructionList,
+    ) -> Option<InstructionList>;
+}
+
+pub(crate) struct TierUpPolicy {
+    threshold: u64,
+    optimizer: Box<dyn FunctionOptimizer>,
+}
+
+impl TierUpPolicy {
+    pub fn new(threshold: u64, optimizer: Box<dyn FunctionOptimizer>) -> Self {
+        TierUpPolicy {
+            threshold,
+            optimizer,
+        }
+    }
+
+    pub fn threshold(&self) -> u64 {
+        self.threshold
+    }
+
+    pub fn optimizer(&self) -> &dyn FunctionOptimizer {
+        &*self.optimizer
+    }
+}
+
+/// Records the managed call stack every `interval` instructions.
+pub(crate) struct StackSampler {
+    interval: u64,
+    /// Instructions left to run before the next sample.
+    until_next: Cell<u64>,
+    /// The number of samples of each stack, outermost function first.
+    samples: RefCell<HashMap<Vec<FunctionId>, u64>>,
+}
+
+impl StackSampler {
+    pub fn new(interval: u64) -> Self {
+        let interval = interval.max(1);
+        StackSampler {
+            interval,
+            until_next: Cell::new(interval),
+            samples: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The number of instructions that may run before the next sample.
+    pub fn until_next(&self) -> u64 {
+        self.until_next.get()
+    }
+
+    /// Counts `steps` executed instructions, returning true if a sample is
+    /// due.
+    pub fn advance(&self, steps: u64) -> bool {
+        let remaining = self.until_next.get().saturating_sub(steps);
+        if remaining == 0 {
+            self.until_next.set(self.interval);
+            true
+        } else {
+            self.until_next.set(remaining);
+            false
+        }
+    }
+
+    pub fn record(&self, stack: Vec<FunctionId>) {
+        if !stack.is_empty() {
+            *self.samples.borrow_mut().entry(stack).or_default() += 1;
+        }
+    }
+
+    /// Returns one line per sampled stack, of the function ids from the
+    /// outermost call in, separated by `;`, and the number of samples. Lines
+    /// are sorted, so equal samples give equal output.
+    pub fn collapsed_stacks(&self) -> String {
+        let mut lines = self
+            .samples
+            .borrow()
+            .iter()
+            .map(|(stack, count)| {
+                let frames = stack.iter().map(ToString::to_string).collect::<Vec<_>>();
+                (frames.join(";"), *count)
+            })
+            .collect::<Vec<_>>();
+        lines.sort();
+        let mut out = String::new();
+        for (stack, count) in lines {
+            writeln!(out, "{stack} {count}").expect("Writing to a string cannot fail.");
+        }
+        out
+    }
+}
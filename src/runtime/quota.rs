@@ -0,0 +1,123 @@
+//! Accounting of heap allocations per top level.
+//!
+//! All top levels of a runtime share one heap, so in multi-tenant embeddings
+//! one tenant could allocate enough to starve the others. Every object
+//! allocated while a top level's call runs is charged to that top level, and
+//! a top level may be given a quota on the number of objects it allocates.
+//!
+//! Quotas are checked at safe points, like fuel, so a call may allocate a
+//! bounded number of objects past its quota before it is stopped.
+
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use super::error::{Result, RuntimeError};
+
+/// The allocations charged to one top level, and its quota.
+pub(crate) struct HeapAccount {
+    allocated: Cell<u64>,
+    quota: Cell<Option<u64>>,
+}
+
+impl HeapAccount {
+    pub fn new() -> Self {
+        HeapAccount {
+            allocated: Cell::new(0),
+            quota: Cell::new(None),
+        }
+    }
+
+    pub fn allocated(&self) -> u64 {
+        self.allocated.get()
+    }
+
+    pub fn reset_allocated(&self) {
+        self.allocated.set(0);
+    }
+
+    pub fn set_quota(&self, quota: Option<u64>) {
+        self.quota.set(quota);
+    }
+
+    pub fn quota(&self) -> Option<u64> {
+        self.quota.get()
+    }
+
+    fn charge(&self, allocations: u64) {
+        self.allocated
+            .set(self.allocated.get().saturating_add(allocations));
+    }
+}
+
+struct ActiveAccount {
+    account: Rc<HeapAccount>,
+    /// The heap's allocation count when the account was last charged.
+    charged_at: Cell<u64>,
+}
+
+impl ActiveAccount {
+    fn settle(&self, total_allocations: u64) {
+        self.account
+            .charge(total_allocations.saturating_sub(self.charged_at.get()));
+        self.charged_at.set(total_allocations);
+    }
+}
+
+/// The accounts of the top levels whose calls are running, outermost first.
+/// Allocations are charged to the innermost one.
+pub(crate) struct ActiveAccounts {
+    accounts: RefCell<Vec<ActiveAccount>>,
+}
+
+impl ActiveAccounts {
+    pub fn new() -> Self {
+        ActiveAccounts {
+            accounts: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Starts charging allocations to `account`, given the heap's current
+    /// allocation count.
+    pub fn enter(&self, account: Rc<HeapAccount>, total_allocations: u64) {
+        let mut accounts = self.accounts.borrow_mut();
+        if let Some(outer) = accounts.last() {
+            outer.settle(total_allocations);
+        }
+        accounts.push(ActiveAccount {
+            account,
+            charged_at: Cell::new(total_allocations),
+        });
+    }
+
+    /// Stops charging allocations to the innermost account, and resumes
+    /// charging the one it interrupted.
+    pub fn exit(&self, total_allocations: u64) {
+        let mut accounts = self.accounts.borrow_mut();
+        if let Some(inner) = accounts.pop() {
+            inner.settle(total_allocations);
+        }
+        if let Some(outer) = accounts.last() {
+            outer.charged_at.set(total_allocations);
+        }
+    }
+
+    /// Charges the innermost account, failing if it is over its quota.
+    pub fn check(&self, total_allocations: u64) -> Result<()> {
+        let accounts = self.accounts.borrow();
+        let Some(active) = accounts.last() else {
+            return Ok(());
+        };
+        active.settle(total_allocations);
+        match active.account.quota() {
+            Some(quota) if active.account.allocated() > quota => {
+                Err(RuntimeError::TenantQuotaExceeded {
+                    quota,
+                    allocated: active.account.allocated(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
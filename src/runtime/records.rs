@@ -0,0 +1,137 @@
+//! Adapters between lists of records and lists of Rust structs.
+//!
+//! Natives that process structured collections, such as rows or events, can
+//! read a list of records into a `Vec<T>` with `StackContext::get_records`,
+//! and push one back with `StackContext::push_records`, instead of
+//! extracting each field by hand. The [`loon_record!`](crate::loon_record)
+//! macro implements the conversions for a struct, mapping each named field to
+//! the record field of the same name.
+//!
+//! The conversions are implemented with a declarative macro rather than a
+//! `#[derive(FromLoonRecord)]`, which would need a separate proc-macro crate.
+//! Field values are converted through [`LoonValue`], which has no record
+//! variant, so a field cannot hold a nested record: reading one fails, and
+//! a struct implemented with the macro cannot be the type of another
+//! struct's field. Such fields have to be read by hand.
+//!
+//! Errors name the item and field that could not be converted, e.g.
+//! `Item 2: Field "score": Value is not an integer.`
+
+use crate::{pure_values::LoonValue, util::imm_string::ImmString};
+
+use super::{
+    error::{Result, RuntimeError},
+    global_env::GlobalEnv,
+    thunk::FromStack,
+    value::{List, PinnedValue, Record},
+};
+
+/// A Rust struct that can be read from a record. Usually implemented with
+/// [`loon_record!`](crate::loon_record).
+pub trait FromLoonRecord: Sized {
+    fn from_record(record: &RecordFields) -> Result<Self>;
+}
+
+/// A Rust struct that can be stored as a record. Usually implemented with
+/// [`loon_record!`](crate::loon_record).
+pub trait IntoLoonRecord {
+    /// The names of the record's fields, in order.
+    const FIELDS: &'static [&'static str];
+
+    /// Returns the values of the fields, in the order of [`Self::FIELDS`].
+    fn into_field_values(self) -> Vec<LoonValue>;
+}
+
+/// The fields of a record being read by [`FromLoonRecord::from_record`].
+pub struct RecordFields<'a> {
+    env: &'a GlobalEnv,
+    record: &'a Record,
+}
+
+impl<'a> RecordFields<'a> {
+    pub(crate) fn new(env: &'a GlobalEnv, record: &'a Record) -> Self {
+        RecordFields { env, record }
+    }
+
+    /// Reads the named field, failing if the record has no such field or its
+    /// value cannot be converted.
+    pub fn field<T: FromStack>(&self, name: &str) -> Result<T> {
+        self.record
+            .get(name)
+            .and_then(|value| value.to_loon_value(self.env.max_nesting_depth()))
+            .and_then(T::from_loon_value)
+            .map_err(|error| error.with_context(format!("Field {name:?}")))
+    }
+}
+
+/// Reads the record `item` of a list into a `T`.
+pub(crate) fn read_record<T: FromLoonRecord>(env: &GlobalEnv, item: &PinnedValue) -> Result<T> {
+    T::from_record(&RecordFields::new(env, item.as_record()?))
+}
+
+/// Creates a list with one record for each of `items`, all of the same shape.
+pub(crate) fn records_to_list<T: IntoLoonRecord>(
+    env: &GlobalEnv,
+    items: impl IntoIterator<Item = T>,
+) -> Result<PinnedValue> {
+    let shape = env.record_shape(T::FIELDS.iter().copied().map(ImmString::from_str).collect())?;
+    let mut records = Vec::new();
+    for item in items {
+        let values = item.into_field_values();
+        if values.len() != T::FIELDS.len() {
+            return Err(RuntimeError::new_operation_precondition_error(format!(
+                "Record has {} fields but {} values were given.",
+                T::FIELDS.len(),
+                values.len()
+            )));
+        }
+        let values = values
+            .iter()
+            .map(|value| PinnedValue::from_loon_value(env, value));
+        records.push(PinnedValue::new_record(Record::new(
+            env,
+            shape.clone(),
+            values,
+        )));
+    }
+    Ok(PinnedValue::new_list(List::from_iter(env, records)))
+}
+
+/// Implements [`FromLoonRecord`] and [`IntoLoonRecord`] for a struct, with
+/// one record field for each of the listed struct fields, which must be all
+/// of them. The field types must implement
+/// [`FromStack`](crate::runtime::FromStack) and
+/// [`IntoStack`](crate::runtime::IntoStack), as the parameter and return
+/// types of thunks do.
+///
+/// ```ignore
+/// struct Row {
+///     name: String,
+///     score: i64,
+/// }
+///
+/// loon::loon_record!(Row { name, score });
+/// ```
+#[macro_export]
+macro_rules! loon_record {
+    ($ty:ident { $($field:ident),* $(,)? }) => {
+        impl $crate::runtime::FromLoonRecord for $ty {
+            fn from_record(
+                record: &$crate::runtime::RecordFields,
+            ) -> $crate::runtime::Result<Self> {
+                Ok($ty {
+                    $($field: record.field(stringify!($field))?,)*
+                })
+            }
+        }
+
+        impl $crate::runtime::IntoLoonRecord for $ty {
+            const FIELDS: &'static [&'static str] = &[$(stringify!($field)),*];
+
+            fn into_field_values(self) -> Vec<$crate::LoonValue> {
+                let $ty { $($field),* } = self;
+                vec![$($crate::runtime::IntoStack::into_loon_value($field)),*]
+            }
+        }
+    };
+}
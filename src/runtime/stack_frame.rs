@@ -3,42 +3,61 @@ use std::{cell::RefCell, rc::Rc};
 use crate::{
     binary::{instructions::StackIndex, modules::ImportSource},
     gc::{GcRef, GcRefVisitor, GcTraceable, PinnedGcRef},
-    pure_values::{Float, Integer},
+    pure_values::{Float, Integer, LoonValue},
     runtime::value::NativeFunctionResult,
-    util::imm_string::ImmString,
+    util::imm_string::{ImmBytes, ImmString},
 };
 
 use super::{
     constants::ValueTable,
     context::InstEvalContext,
-    error::{Result, RuntimeError},
+    error::{Result, RuntimeError, StackTraceFrame},
     global_env::GlobalEnv,
+    handle::ValueHandle,
+    host_value::HostValue,
     instructions::{
-        CallStepResult, FrameChange, InstEval, InstEvalList, InstructionResult, InstructionTarget,
+        CallStepResult, FrameChange, InstEvalList, InstructionResult, InstructionTarget,
         YieldStepResult,
     },
     modules::ModuleGlobals,
+    records::{self, FromLoonRecord, IntoLoonRecord},
     value::{
-        Function, List, NativeFunctionContext, NativeFunctionPtr, NativeFunctionResultInner,
-        PinnedValue, Value,
+        CallerInfo, Function, FunctionOrigin, List, NativeCallInfo, NativeFunctionContext,
+        NativeFunctionPtr, NativeFunctionResultInner, PinnedValue, Value,
     },
+    FunctionId,
 };
 
+mod storage;
+
+use self::storage::ValueStorage;
+
 struct InstState {
     pc: usize,
+    /// The pc of the most recent call instruction.
+    call_pc: usize,
+    /// Set while the frame waits for the call at `call_pc` to return.
+    in_call: bool,
+    /// Where to continue if the call at `call_pc` fails, if it is a protected
+    /// call.
+    handler: Option<u32>,
     inst_list: Rc<InstEvalList>,
 }
 
 impl InstState {
     pub fn new(inst_list: Rc<InstEvalList>) -> Self {
-        InstState { pc: 0, inst_list }
-    }
-
-    pub fn curr_inst(&self) -> &dyn InstEval {
-        self.inst_list.inst_at(self.pc).unwrap()
+        InstState {
+            pc: 0,
+            call_pc: 0,
+            in_call: false,
+            handler: None,
+            inst_list,
+        }
     }
 
-    pub fn update_pc(&mut self, pc: InstructionTarget) -> Result<()> {
+    /// Moves to the next instruction. Returns true if the move was a
+    /// back-edge (a branch to the current or an earlier instruction).
+    pub fn update_pc(&mut self, pc: InstructionTarget) -> Result<bool> {
         let next_pc = match pc {
             InstructionTarget::Step => self.pc + 1,
             InstructionTarget::Branch(i) => usize::try_from(i).unwrap(),
@@ -48,8 +67,9 @@ impl InstState {
                 "Instruction stepped out of bounds.",
             ));
         }
+        let is_back_edge = next_pc <= self.pc;
         self.pc = next_pc;
-        Ok(())
+        Ok(is_back_edge)
     }
 }
 
@@ -64,19 +84,73 @@ impl GcTraceable for InstState {
 
 pub(crate) type PinnedValueBuffer = Vec<PinnedValue>;
 
+/// When operand stacks give memory back after growing, such as while
+/// passing many values between calls. Stacks are checked when a frame
+/// returns to them, and have their capacity halved if they are mostly empty.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct StackShrinkPolicy {
+    /// A stack is shrunk when it uses less than this percentage of its
+    /// capacity.
+    pub min_utilization_percent: u8,
+    /// Stacks are never shrunk below this capacity, so that small stacks
+    /// are not reallocated on every call.
+    pub min_capacity: usize,
+}
+
+impl Default for StackShrinkPolicy {
+    fn default() -> Self {
+        StackShrinkPolicy {
+            min_utilization_percent: 25,
+            min_capacity: 64,
+        }
+    }
+}
+
 pub(crate) struct LocalStack {
-    stack: RefCell<Vec<Value>>,
+    stack: RefCell<ValueStorage>,
+    /// The most values the stack may hold. Pushes check it before pushing,
+    /// and managed frames again after each instruction. Only the stacks of
+    /// call frames are limited.
+    max_len: Option<usize>,
 }
 
 impl LocalStack {
     pub fn new(env: &GlobalEnv) -> PinnedGcRef<Self> {
         env.create_pinned_ref(LocalStack {
-            stack: RefCell::new(Vec::new()),
+            stack: RefCell::new(ValueStorage::new()),
+            max_len: None,
+        })
+    }
+
+    /// Creates the stack of a call frame, limited by the runtime's frame
+    /// stack size.
+    pub fn new_frame(env: &GlobalEnv) -> PinnedGcRef<Self> {
+        env.create_pinned_ref(LocalStack {
+            stack: RefCell::new(ValueStorage::new()),
+            max_len: env.max_frame_stack_size(),
         })
     }
 
-    pub fn push(&self, value: PinnedValue) {
+    /// Fails if pushing `additional` more values would grow the stack past
+    /// its limit. Managed frames name their function in the error.
+    fn ensure_room(&self, additional: usize) -> Result<()> {
+        match self.max_len {
+            Some(limit) if self.len().saturating_add(additional) > limit => {
+                Err(RuntimeError::new_stack_limit_error(limit))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Pushes `value`, failing if the stack is already at its limit.
+    pub fn push(&self, value: PinnedValue) -> Result<()> {
+        self.ensure_room(1)?;
         self.stack.borrow_mut().push(value.to_value());
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.stack.borrow().len()
     }
 
     pub fn pop(&self) -> Result<PinnedValue> {
@@ -87,6 +161,18 @@ impl LocalStack {
             .ok_or_else(|| RuntimeError::new_operation_precondition_error("Local stack is empty."))
     }
 
+    /// Pops the top value, which must be a boolean.
+    pub fn pop_bool(&self) -> Result<bool> {
+        self.stack.borrow_mut().pop_bool()
+    }
+
+    /// Pops the top two values if both are integers that fit in an `i64`,
+    /// returning them in the order they were pushed. Otherwise leaves the
+    /// stack unchanged.
+    pub fn pop_int_pair(&self) -> Option<(i64, i64)> {
+        self.stack.borrow_mut().pop_int_pair()
+    }
+
     pub fn pop_n(&self, n: usize) -> Result<()> {
         let mut stack = self.stack.borrow_mut();
         let trunc_len = stack.len().checked_sub(n).ok_or_else(|| {
@@ -103,14 +189,14 @@ impl LocalStack {
                 .borrow()
                 .len()
                 .checked_sub((i as usize) + 1)
-                .ok_or_else(|| RuntimeError::new_internal_error("Stack index out of range"))?,
+                .ok_or_else(|| {
+                    RuntimeError::new_operation_precondition_error("Stack index out of range.")
+                })?,
             StackIndex::FromBottom(i) => i as usize,
         };
-        self.stack
-            .borrow()
-            .get(index)
-            .ok_or_else(|| RuntimeError::new_internal_error("Stack index out of range."))
-            .map(Value::pin)
+        self.stack.borrow().get(index).ok_or_else(|| {
+            RuntimeError::new_operation_precondition_error("Stack index out of range.")
+        })
     }
 
     pub fn set_at_index(&self, index: StackIndex, value: PinnedValue) -> Result<()> {
@@ -120,10 +206,16 @@ impl LocalStack {
                 .borrow()
                 .len()
                 .checked_sub((i as usize) + 1)
-                .ok_or_else(|| RuntimeError::new_internal_error("Stack index out of range"))?,
+                .ok_or_else(|| {
+                    RuntimeError::new_operation_precondition_error("Stack index out of range.")
+                })?,
             StackIndex::FromBottom(i) => i as usize,
         };
-        self.stack.borrow_mut()[index] = value.to_value();
+        if !self.stack.borrow_mut().set(index, value.to_value()) {
+            return Err(RuntimeError::new_operation_precondition_error(
+                "Stack index out of range.",
+            ));
+        }
         Ok(())
     }
 
@@ -133,17 +225,58 @@ impl LocalStack {
         let start = src_stack.len().checked_sub(len).ok_or_else(|| {
             RuntimeError::new_operation_precondition_error("Local stack is too small.")
         })?;
-        buffer.extend(src_stack[start..].iter().map(Value::pin));
+        buffer.extend(src_stack.pin_from(start));
         src_stack.truncate(start);
         Ok(())
     }
 
-    pub fn push_iter(&self, env: &GlobalEnv, iter: impl Iterator<Item = PinnedValue>) {
+    /// Copies the top `len` values onto the end of `buffer`, bottom-most
+    /// first, without removing them from the stack.
+    pub fn peek_top_n(&self, len: usize, buffer: &mut PinnedValueBuffer) -> Result<()> {
+        let src_stack = self.stack.borrow();
+        let start = src_stack.len().checked_sub(len).ok_or_else(|| {
+            RuntimeError::new_operation_precondition_error("Local stack is too small.")
+        })?;
+        buffer.extend(src_stack.pin_from(start));
+        Ok(())
+    }
+
+    /// Halves the capacity of the stack if `policy` calls for it.
+    pub fn shrink_if_sparse(&self, policy: StackShrinkPolicy) {
+        let mut stack = self.stack.borrow_mut();
+        let capacity = stack.capacity();
+        let utilization = stack.len() * 100;
+        if capacity > policy.min_capacity
+            && utilization < capacity * usize::from(policy.min_utilization_percent)
+        {
+            stack.shrink_to((capacity / 2).max(policy.min_capacity));
+        }
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.stack.borrow().capacity()
+    }
+
+    /// Pushes `values` in order, so the last ends up on top. This is the one
+    /// way values are pushed in bulk, by calls, returns and natives alike.
+    ///
+    /// Fails without pushing anything if the values are known up front not
+    /// to fit under the stack's limit, so that spreading a large list does
+    /// not allocate the stack for it first.
+    pub fn push_iter(
+        &self,
+        env: &GlobalEnv,
+        values: impl IntoIterator<Item = PinnedValue>,
+    ) -> Result<()> {
+        let values = values.into_iter();
+        self.ensure_room(values.size_hint().0)?;
         env.with_lock(|l| {
             self.stack
                 .borrow_mut()
-                .extend(iter.map(|v| v.into_value(l)))
-        })
+                .extend(values.map(|v| v.into_value(l)))
+        });
+        self.ensure_room(0)
     }
 }
 
@@ -152,12 +285,32 @@ impl GcTraceable for LocalStack {
     where
         V: GcRefVisitor,
     {
-        for value in self.stack.borrow().iter() {
-            value.trace(visitor);
-        }
+        self.stack.borrow().trace(visitor);
     }
 }
 
+/// Access to a stack of values, from the host or from a native function.
+///
+/// Values that move in bulk keep the order they were pushed in: the value
+/// pushed first is the deepest, and comes first in any list or argument
+/// sequence made from them. Code generators can rely on this:
+///
+/// - A function called with `n` arguments receives them in the order they
+///   were pushed, the last on top of its stack. Values bound with
+///   `bind_front` come before the arguments of the call, in the order they
+///   were bound, and `apply` passes the elements of a list in list order.
+/// - A function that returns `n` values leaves them in the order it pushed
+///   them, the last on top of its caller's stack.
+/// - [`Self::make_list`] and the slice pushes list values in the order they
+///   were pushed.
+///
+/// Calls made from the host and from native functions take the function from
+/// the top of the stack, above its arguments, while the `call` instruction
+/// takes it from below them.
+///
+/// The stack of a native function's frame is limited by the runtime's frame
+/// stack size, and pushes past it fail with
+/// [`RuntimeError::StackLimitExceeded`]. The host's stack is not limited.
 pub struct StackContext<'a> {
     env: &'a GlobalEnv,
     stack: PinnedGcRef<LocalStack>,
@@ -169,48 +322,83 @@ impl<'a> StackContext<'a> {
     }
     pub fn push_import(&mut self, source: &ImportSource) -> Result<()> {
         let value = self.env.get_import(source)?;
-        self.stack.push(value);
-        Ok(())
+        self.stack.push(value)
     }
 
-    pub fn push_bool(&mut self, value: bool) {
-        self.stack.push(PinnedValue::new_bool(value));
+    pub fn push_bool(&mut self, value: bool) -> Result<()> {
+        self.stack.push(PinnedValue::new_bool(value))
     }
 
-    pub fn push_int(&mut self, value: impl Into<Integer>) {
-        self.stack.push(PinnedValue::new_integer(value.into()));
+    pub fn push_int(&mut self, value: impl Into<Integer>) -> Result<()> {
+        self.stack.push(PinnedValue::new_integer(value.into()))
     }
 
-    pub fn push_float(&mut self, value: f64) {
-        self.stack.push(PinnedValue::new_float(value.into()));
+    pub fn push_float(&mut self, value: f64) -> Result<()> {
+        self.stack.push(PinnedValue::new_float(value.into()))
+    }
+
+    pub fn push_string(&mut self, value: impl AsRef<str>) -> Result<()> {
+        self.stack
+            .push(PinnedValue::new_string(ImmString::from_str(value.as_ref())))
     }
 
-    pub fn push_string(&mut self, value: impl AsRef<str>) {
+    pub fn push_bytes(&mut self, value: impl AsRef<[u8]>) -> Result<()> {
         self.stack
-            .push(PinnedValue::new_string(ImmString::from_str(value.as_ref())));
+            .push(PinnedValue::new_bytes(ImmBytes::from(value.as_ref())))
     }
 
+    pub fn push_loon_value(&mut self, value: &LoonValue) -> Result<()> {
+        self.stack
+            .push(PinnedValue::from_loon_value(self.env, value))
+    }
+
+    /// Pushes a list of the integers in `values`, in order.
+    ///
+    /// The list is built in one batch with its final capacity, which is much
+    /// cheaper than pushing each value and calling [`Self::make_list`].
+    pub fn push_int_slice(&mut self, values: &[i64]) -> Result<()> {
+        let mut items = Vec::with_capacity(values.len());
+        items.extend(values.iter().map(|&i| Value::new_integer(i.into())));
+        self.stack
+            .push(PinnedValue::new_list(List::from_values(self.env, items)))
+    }
+
+    /// Pushes a list of the floats in `values`, in order. See
+    /// [`Self::push_int_slice`].
+    pub fn push_float_slice(&mut self, values: &[f64]) -> Result<()> {
+        let mut items = Vec::with_capacity(values.len());
+        items.extend(values.iter().map(|&f| Value::new_float(f.into())));
+        self.stack
+            .push(PinnedValue::new_list(List::from_values(self.env, items)))
+    }
+
+    /// Pushes a list with one record for each of `items`, e.g. structs
+    /// implemented with [`loon_record!`](crate::loon_record).
+    pub fn push_records<T: IntoLoonRecord>(
+        &mut self,
+        items: impl IntoIterator<Item = T>,
+    ) -> Result<()> {
+        let list = records::records_to_list(self.env, items)?;
+        self.stack.push(list)
+    }
+
+    /// Pops the top `size` values and pushes a list of them. The deepest of
+    /// them becomes the first element, so they are listed in the order they
+    /// were pushed. The stack is left unchanged if it holds fewer values.
     pub fn make_list(&mut self, size: usize) -> Result<()> {
-        let mut list = Vec::with_capacity(size);
-        for _ in 0..size {
-            list.push(self.stack.pop()?);
-        }
-        // FIXME: Which direction should the list be from the stack?
-        // Current here is from first push to last.
-        list.reverse();
+        let items = self.drain_args(size)?;
         self.stack
-            .push(PinnedValue::new_list(List::from_iter(self.env, list)));
-        Ok(())
+            .push(PinnedValue::new_list(List::from_iter(self.env, items)))
     }
 
-    pub fn push_native_function<F>(&mut self, function: F)
+    pub fn push_native_function<F>(&mut self, function: F) -> Result<()>
     where
         F: Fn(NativeFunctionContext) -> Result<NativeFunctionResult> + 'static,
     {
         self.stack
             .push(PinnedValue::new_function(Function::new_native(
                 self.env, function,
-            )));
+            )))
     }
 
     pub fn get_int(&self, index: StackIndex) -> Result<Integer> {
@@ -225,6 +413,49 @@ impl<'a> StackContext<'a> {
         self.stack.get_at_index(index)?.as_bool()
     }
 
+    /// Returns a copy of the value at the given index. Lists are copied as
+    /// deeply as the runtime's maximum nesting depth allows.
+    pub fn get_loon_value(&self, index: StackIndex) -> Result<LoonValue> {
+        self.stack
+            .get_at_index(index)?
+            .to_loon_value(self.env.max_nesting_depth())
+    }
+
+    /// Returns a handle to the value at the given index, which keeps it alive
+    /// for as long as the host holds it.
+    pub fn get_host_value(&self, index: StackIndex) -> Result<HostValue> {
+        Ok(HostValue::new(
+            self.env.identity(),
+            self.stack.get_at_index(index)?,
+        ))
+    }
+
+    /// Pushes a value taken with [`Self::get_host_value`]. Fails if it was
+    /// taken from a different runtime.
+    pub fn push_host_value(&mut self, value: &HostValue) -> Result<()> {
+        let value = value.value_in(self.env.identity())?.clone();
+        self.stack.push(value)
+    }
+
+    /// Retains the value at the given index, keeping it alive after it is
+    /// removed from the stack until the handle is released with
+    /// [`Runtime::release`](crate::runtime::Runtime::release).
+    pub fn retain(&self, index: StackIndex) -> Result<ValueHandle> {
+        Ok(self.env.retain_value(self.stack.get_at_index(index)?))
+    }
+
+    /// Pushes the value held by `handle`. Fails if the handle was created by
+    /// a different runtime.
+    pub fn push_handle(&mut self, handle: &ValueHandle) -> Result<()> {
+        let value = self.env.resolve_handle(handle)?;
+        self.stack.push(value)
+    }
+
+    /// Returns the id of the function at the given index.
+    pub fn get_function_id(&self, index: StackIndex) -> Result<FunctionId> {
+        self.stack.get_at_index(index)?.as_function()?.id()
+    }
+
     pub fn get_string<F, R>(&self, index: StackIndex, body: F) -> Result<R>
     where
         F: FnOnce(&str) -> Result<R>,
@@ -232,44 +463,179 @@ impl<'a> StackContext<'a> {
         body(self.stack.get_at_index(index)?.as_str()?)
     }
 
+    pub fn get_bytes<F, R>(&self, index: StackIndex, body: F) -> Result<R>
+    where
+        F: FnOnce(&[u8]) -> Result<R>,
+    {
+        body(self.stack.get_at_index(index)?.as_bytes()?)
+    }
+
+    /// Reads the list of records at the given index, converting each record.
+    /// Errors name the item that could not be converted.
+    pub fn get_records<T: FromLoonRecord>(&self, index: StackIndex) -> Result<Vec<T>> {
+        let value = self.stack.get_at_index(index)?;
+        let list = value.as_list()?;
+        (0..list.len())
+            .map(|i| {
+                records::read_record(self.env, &list.at(i))
+                    .map_err(|error| error.with_context(format!("Item {i}")))
+            })
+            .collect()
+    }
+
     pub fn pop_n(&mut self, n: usize) -> Result<()> {
         self.stack.pop_n(n)
     }
+
+    /// Returns the number of values on the stack.
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn push_value(&mut self, value: PinnedValue) -> Result<()> {
+        self.stack.push(value)
+    }
+
+    pub(crate) fn pop_value(&mut self) -> Result<PinnedValue> {
+        self.stack.pop()
+    }
+
+    /// Pops the top `n` values off of the stack, returning them bottom-most
+    /// first.
+    pub fn drain_args(&mut self, n: usize) -> Result<Vec<PinnedValue>> {
+        let n = u32::try_from(n).map_err(|_| {
+            RuntimeError::new_operation_precondition_error("Local stack is too small.")
+        })?;
+        let mut values = Vec::with_capacity(n as usize);
+        self.stack.drain_top_n(n, &mut values)?;
+        Ok(values)
+    }
+
+    /// Returns copies of the top `n` values on the stack, bottom-most first.
+    pub fn peek_n(&self, n: usize) -> Result<Vec<PinnedValue>> {
+        let mut values = Vec::with_capacity(n);
+        self.stack.peek_top_n(n, &mut values)?;
+        Ok(values)
+    }
+
+    /// Pushes all values in order, so the last value ends up on top. Fails
+    /// if the values do not fit under the frame stack limit.
+    pub fn push_all(&mut self, values: impl IntoIterator<Item = PinnedValue>) -> Result<()> {
+        self.stack.push_iter(self.env, values)
+    }
+}
+
+enum StepResult {
+    Next,
+    BackEdge,
+    FrameChange(FrameChange),
 }
 
 struct ManagedFrameState {
     inst_state: RefCell<InstState>,
+    origin: Rc<FunctionOrigin>,
     local_consts: GcRef<ValueTable>,
     module_globals: GcRef<ModuleGlobals>,
 }
 
 impl ManagedFrameState {
+    pub fn caller_info(&self) -> CallerInfo {
+        CallerInfo::new(
+            self.origin.module_id().cloned(),
+            self.origin.const_index(),
+            self.inst_state.borrow().call_pc,
+        )
+    }
+
+    /// Describes this frame in a stack trace, at the instruction it is
+    /// running or the call it is waiting on.
+    pub fn stack_trace_frame(&self) -> StackTraceFrame {
+        let inst_state = self.inst_state.borrow();
+        let pc = if inst_state.in_call {
+            inst_state.call_pc
+        } else {
+            inst_state.pc
+        };
+        StackTraceFrame::new(&self.origin.function_id(), pc)
+    }
+
+    /// Returns true if the frame is waiting on a protected call.
+    fn has_handler(&self) -> bool {
+        let inst_state = self.inst_state.borrow();
+        inst_state.in_call && inst_state.handler.is_some()
+    }
+
+    fn catch_error(&self, local_stack: &LocalStack, error: PinnedValue) -> Result<()> {
+        let mut inst_state = self.inst_state.borrow_mut();
+        let handler = inst_state.handler.take().ok_or_else(|| {
+            RuntimeError::new_internal_error("Frame is not waiting on a protected call.")
+        })?;
+        inst_state.in_call = false;
+        inst_state.update_pc(InstructionTarget::Branch(handler))?;
+        local_stack.push(error)?;
+        Ok(())
+    }
+
     pub fn step(
         &self,
         ctxt: &GlobalEnv,
         local_stack: &PinnedGcRef<LocalStack>,
-    ) -> Result<Option<FrameChange>> {
+    ) -> Result<StepResult> {
         let local_consts = self.local_consts.pin();
         let globals = self.module_globals.pin();
         let inst_eval_ctxt = InstEvalContext::new(ctxt, &local_consts, &globals);
+        // The instruction state is not borrowed while the instruction runs,
+        // as instructions that allocate may trigger a collection that traces
+        // this frame.
+        let (inst_list, pc) = {
+            let inst_state = self.inst_state.borrow();
+            (inst_state.inst_list.clone(), inst_state.pc)
+        };
+        ctxt.debug()
+            .before_instruction(ctxt, &self.origin, pc, local_stack)?;
+        // Only the first instruction can be missing, as later ones are
+        // checked when the pc is updated.
+        let inst_result = inst_list
+            .inst_at(pc)
+            .ok_or_else(|| {
+                RuntimeError::new_operation_precondition_error("Function has no instructions.")
+            })?
+            .execute(&inst_eval_ctxt, local_stack)
+            .and_then(|result| local_stack.ensure_room(0).map(|()| result))
+            .map_err(|error| error.with_frame_function(&self.origin.function_id()))?;
         let mut inst_state = self.inst_state.borrow_mut();
-        let inst = inst_state.curr_inst();
-        let result = match inst.execute(&inst_eval_ctxt, local_stack)? {
+        let result = match inst_result {
             InstructionResult::Next(target) => {
-                inst_state.update_pc(target)?;
-                None
+                if inst_state.update_pc(target)? {
+                    StepResult::BackEdge
+                } else {
+                    StepResult::Next
+                }
+            }
+            InstructionResult::Return(num_values) => {
+                StepResult::FrameChange(FrameChange::Return(num_values))
             }
-            InstructionResult::Return(num_values) => Some(FrameChange::Return(num_values)),
             InstructionResult::Call(func_call) => {
+                inst_state.call_pc = inst_state.pc;
+                inst_state.in_call = true;
+                inst_state.handler = func_call.handler();
                 inst_state.update_pc(func_call.return_target())?;
                 let call = CallStepResult {
                     num_args: func_call.num_args(),
+                    function: None,
                 };
-                Some(FrameChange::Call(call))
+                StepResult::FrameChange(FrameChange::Call(call))
+            }
+            InstructionResult::TailCall(func_call) => {
+                StepResult::FrameChange(FrameChange::TailCall(CallStepResult {
+                    num_args: func_call.num_args(),
+                    function: None,
+                }))
             }
-            InstructionResult::TailCall(func_call) => Some(FrameChange::TailCall(CallStepResult {
-                num_args: func_call.num_args(),
-            })),
         };
         Ok(result)
     }
@@ -278,12 +644,32 @@ impl ManagedFrameState {
         &self,
         ctxt: &GlobalEnv,
         local_stack: &PinnedGcRef<LocalStack>,
-    ) -> Result<FrameChange> {
-        loop {
-            if let Some(result) = self.step(ctxt, local_stack)? {
-                return Ok(result);
+        budget: &mut u64,
+    ) -> Result<Option<FrameChange>> {
+        self.inst_state.borrow_mut().in_call = false;
+        // Instructions executed since the last safe point. Keeping this local
+        // means straight-line code never touches the shared limit state.
+        let mut steps: u64 = 0;
+        let frame_change = loop {
+            if *budget == 0 {
+                ctxt.safe_point(steps)?;
+                return Ok(None);
             }
-        }
+            *budget -= 1;
+            steps += 1;
+            match self.step(ctxt, local_stack)? {
+                StepResult::Next => {}
+                StepResult::BackEdge => {
+                    ctxt.safe_point(steps)?;
+                    steps = 0;
+                }
+                StepResult::FrameChange(frame_change) => break frame_change,
+            }
+        };
+        // Frame changes are also safe points, so that recursion without loops
+        // is still bounded by the execution limits.
+        ctxt.safe_point(steps)?;
+        Ok(Some(frame_change))
     }
 }
 
@@ -307,24 +693,31 @@ impl NativeFrameState {
         &self,
         env: &GlobalEnv,
         local_stack: &PinnedGcRef<LocalStack>,
+        call_info: NativeCallInfo,
     ) -> Result<FrameChange> {
-        let ctxt = NativeFunctionContext::new(env, local_stack);
-        match self.native_func.borrow().call(ctxt)?.0 {
+        let ctxt = NativeFunctionContext::new(env, local_stack, call_info);
+        // The function is not borrowed while matching, as a continuation
+        // replaces it.
+        let result = env.run_native(|| self.native_func.borrow().call(ctxt))?;
+        match result.0 {
             NativeFunctionResultInner::ReturnValue(num_values) => {
                 Ok(FrameChange::Return(num_values))
             }
             NativeFunctionResultInner::TailCall(tail_call) => {
                 Ok(FrameChange::TailCall(CallStepResult {
                     num_args: tail_call.num_args,
+                    function: Some(tail_call.function),
                 }))
             }
             NativeFunctionResultInner::CallWithContinuation(call) => {
                 *self.native_func.borrow_mut() = call.continuation().clone();
                 Ok(FrameChange::Call(CallStepResult {
                     num_args: call.num_args(),
+                    function: Some(call.function().clone()),
                 }))
             }
-            NativeFunctionResultInner::YieldCall(_call) => {
+            NativeFunctionResultInner::YieldCall(call) => {
+                *self.native_func.borrow_mut() = call.continuation;
                 Ok(FrameChange::YieldCall(YieldStepResult))
             }
         }
@@ -366,6 +759,7 @@ impl StackFrame {
     pub fn new_managed(
         env: &GlobalEnv,
         inst_list: Rc<InstEvalList>,
+        origin: Rc<FunctionOrigin>,
         local_consts: PinnedGcRef<ValueTable>,
         module_globals: PinnedGcRef<ModuleGlobals>,
         local_stack: PinnedGcRef<LocalStack>,
@@ -374,6 +768,7 @@ impl StackFrame {
             env.create_pinned_ref(StackFrame {
                 frame_state: FrameState::Managed(ManagedFrameState {
                     inst_state: RefCell::new(InstState::new(inst_list)),
+                    origin,
                     local_consts: local_consts.into_ref(lock.guard()),
                     module_globals: module_globals.into_ref(lock.guard()),
                 }),
@@ -397,11 +792,72 @@ impl StackFrame {
         })
     }
 
-    pub fn run_to_frame_change(&self, ctxt: &GlobalEnv) -> Result<FrameChange> {
+    /// Runs the frame until it calls or returns. `call_info` describes the
+    /// call that created this frame, and is only computed for native frames.
+    ///
+    /// Each instruction, and each call of a native function, takes one step
+    /// from `budget`. Returns `None` if the budget runs out first.
+    pub fn run_to_frame_change(
+        &self,
+        ctxt: &GlobalEnv,
+        call_info: impl FnOnce() -> NativeCallInfo,
+        budget: &mut u64,
+    ) -> Result<Option<FrameChange>> {
         let local_stack = self.local_stack.pin();
         match &self.frame_state {
-            FrameState::Managed(state) => state.run_to_frame_change(ctxt, &local_stack),
-            FrameState::Native(state) => state.run_to_frame_change(ctxt, &local_stack),
+            FrameState::Managed(state) => state.run_to_frame_change(ctxt, &local_stack, budget),
+            FrameState::Native(_) if *budget == 0 => Ok(None),
+            FrameState::Native(state) => {
+                *budget -= 1;
+                state
+                    .run_to_frame_change(ctxt, &local_stack, call_info())
+                    .map(Some)
+            }
+        }
+    }
+
+    /// Returns the function this frame runs, if it is a managed frame.
+    pub fn function_id(&self) -> Option<FunctionId> {
+        match &self.frame_state {
+            FrameState::Managed(state) => Some(state.origin.function_id()),
+            FrameState::Native(_) => None,
+        }
+    }
+
+    /// Describes this frame in a stack trace, if it is a managed frame.
+    pub fn stack_trace_frame(&self) -> Option<StackTraceFrame> {
+        match &self.frame_state {
+            FrameState::Managed(state) => Some(state.stack_trace_frame()),
+            FrameState::Native(_) => None,
+        }
+    }
+
+    /// Describes this frame as the caller of the frame above it, if it is a
+    /// managed frame.
+    pub fn caller_info(&self) -> Option<CallerInfo> {
+        match &self.frame_state {
+            FrameState::Managed(state) => Some(state.caller_info()),
+            FrameState::Native(_) => None,
+        }
+    }
+
+    /// Returns true if the frame is waiting on a protected call, and so can
+    /// catch an error raised above it.
+    pub fn has_handler(&self) -> bool {
+        match &self.frame_state {
+            FrameState::Managed(state) => state.has_handler(),
+            FrameState::Native(_) => false,
+        }
+    }
+
+    /// Catches an error raised by the protected call the frame is waiting
+    /// on: the frame continues at the call's handler, with `error` pushed.
+    pub fn catch_error(&self, error: PinnedValue) -> Result<()> {
+        match &self.frame_state {
+            FrameState::Managed(state) => state.catch_error(&self.local_stack.borrow(), error),
+            FrameState::Native(_) => Err(RuntimeError::new_internal_error(
+                "Native frames cannot catch errors.",
+            )),
         }
     }
 
@@ -409,8 +865,26 @@ impl StackFrame {
         self.local_stack.borrow().pop()
     }
 
-    pub fn push_iter(&self, env: &GlobalEnv, iter: impl Iterator<Item = PinnedValue>) {
-        self.local_stack.borrow().push_iter(env, iter);
+    pub fn push_iter(
+        &self,
+        env: &GlobalEnv,
+        values: impl IntoIterator<Item = PinnedValue>,
+    ) -> Result<()> {
+        self.local_stack
+            .borrow()
+            .push_iter(env, values)
+            .map_err(|error| match self.function_id() {
+                Some(function) => error.with_frame_function(&function),
+                None => error,
+            })
+    }
+
+    pub fn shrink_stack_if_sparse(&self, policy: StackShrinkPolicy) {
+        self.local_stack.borrow().shrink_if_sparse(policy);
+    }
+
+    pub fn stack_len(&self) -> usize {
+        self.local_stack.borrow().len()
     }
 
     pub fn drain_top_n(&self, len: u32, buffer: &mut PinnedValueBuffer) -> Result<()> {
@@ -428,3 +902,69 @@ impl GcTraceable for StackFrame {
         self.local_stack.trace(visitor);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulk_operations_preserve_order() -> anyhow::Result<()> {
+        let env = GlobalEnv::new();
+        let mut stack = StackContext::new(&env, LocalStack::new(&env));
+        stack.push_all((1..=4).map(|i| PinnedValue::new_integer(i.into())))?;
+        assert_eq!(stack.len(), 4);
+
+        let peeked = stack.peek_n(2)?;
+        assert_eq!(peeked[0].as_int()?, &3.into());
+        assert_eq!(peeked[1].as_int()?, &4.into());
+        assert_eq!(stack.len(), 4);
+
+        let drained = stack.drain_args(3)?;
+        assert_eq!(drained[0].as_int()?, &2.into());
+        assert_eq!(drained[2].as_int()?, &4.into());
+        assert_eq!(stack.len(), 1);
+
+        assert!(stack.drain_args(2).is_err());
+        assert!(stack.peek_n(2).is_err());
+
+        // Collections are pushed directly, and reversed iterators push their
+        // last item first.
+        stack.push_all(drained)?;
+        stack.push_all(peeked.into_iter().rev())?;
+        let all = stack.drain_args(6)?;
+        let all = all
+            .iter()
+            .map(|v| v.as_int().cloned())
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(all, [1, 2, 3, 4, 4, 3].map(Integer::from));
+        Ok(())
+    }
+
+    #[test]
+    fn sparse_stacks_shrink_by_half() -> anyhow::Result<()> {
+        let env = GlobalEnv::new();
+        let local_stack = LocalStack::new(&env);
+        let policy = StackShrinkPolicy {
+            min_utilization_percent: 25,
+            min_capacity: 16,
+        };
+        local_stack.push_iter(&env, (0..1000).map(|_| PinnedValue::new_null()))?;
+        let peak = local_stack.capacity();
+        local_stack.shrink_if_sparse(policy);
+        assert_eq!(local_stack.capacity(), peak);
+
+        local_stack.pop_n(990)?;
+        let mut capacities = vec![local_stack.capacity()];
+        for _ in 0..10 {
+            local_stack.shrink_if_sparse(policy);
+            capacities.push(local_stack.capacity());
+        }
+        assert!(capacities[1] <= peak / 2);
+        assert!(capacities.windows(2).all(|w| w[1] <= w[0]));
+        // Shrinking stops once about a quarter of the capacity is in use.
+        let last = *capacities.last().unwrap();
+        assert!((16..=64).contains(&last));
+        assert_eq!(local_stack.len(), 10);
+        Ok(())
+    }
+}
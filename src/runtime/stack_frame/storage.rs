@@ -0,0 +1,383 @@
+//! Storage for the values of a [`super::LocalStack`].
+//!
+//! By default this is a plain `Vec<Value>`. With the `soa-local-stack`
+//! feature, values are split into parallel arrays of type tags and scalar
+//! payloads, with other values in a side table, so that type checks and
+//! scalar operations only touch the small arrays. Both layouts have the same
+//! interface, so the two can be compared with the opcode benchmarks in
+//! `opcode_bench.rs`, run once with the feature and once without.
+
+use crate::{
+    gc::{GcRefVisitor, GcTraceable},
+    runtime::{
+        error::{Result, RuntimeError},
+        value::{PinnedValue, Value},
+    },
+};
+
+#[cfg(feature = "soa-local-stack")]
+use crate::runtime::value::ScalarKind;
+
+#[cfg(not(feature = "soa-local-stack"))]
+pub(super) struct ValueStorage(Vec<Value>);
+
+#[cfg(not(feature = "soa-local-stack"))]
+impl ValueStorage {
+    pub fn new() -> Self {
+        ValueStorage(Vec::new())
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn push(&mut self, value: Value) {
+        self.0.push(value);
+    }
+
+    pub fn pop(&mut self) -> Option<Value> {
+        self.0.pop()
+    }
+
+    pub fn pop_bool(&mut self) -> Result<bool> {
+        self.0
+            .pop()
+            .ok_or_else(|| RuntimeError::new_operation_precondition_error("Local stack is empty."))?
+            .pin()
+            .as_bool()
+    }
+
+    pub fn pop_int_pair(&mut self) -> Option<(i64, i64)> {
+        let start = self.0.len().checked_sub(2)?;
+        let left = self.0[start].as_small_integer()?;
+        let right = self.0[start + 1].as_small_integer()?;
+        self.0.truncate(start);
+        Some((left, right))
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
+    /// Lowers the capacity to at most `capacity`, keeping room for the
+    /// current values.
+    pub fn shrink_to(&mut self, capacity: usize) {
+        self.0.shrink_to(capacity);
+    }
+
+    pub fn get(&self, index: usize) -> Option<PinnedValue> {
+        self.0.get(index).map(Value::pin)
+    }
+
+    /// Replaces the value at `index`, returning false if it is out of range.
+    pub fn set(&mut self, index: usize, value: Value) -> bool {
+        match self.0.get_mut(index) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn pin_from(&self, start: usize) -> impl Iterator<Item = PinnedValue> + '_ {
+        self.0[start..].iter().map(Value::pin)
+    }
+
+    /// Pushes `values` in order, so the last ends up on top.
+    pub fn extend(&mut self, values: impl Iterator<Item = Value>) {
+        self.0.extend(values);
+    }
+}
+
+#[cfg(not(feature = "soa-local-stack"))]
+impl GcTraceable for ValueStorage {
+    fn trace<V>(&self, visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        for value in &self.0 {
+            value.trace(visitor);
+        }
+    }
+}
+
+#[cfg(feature = "soa-local-stack")]
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tag {
+    Scalar(ScalarKind),
+    Boxed,
+}
+
+#[cfg(feature = "soa-local-stack")]
+fn boxed_slot(word: u64) -> usize {
+    usize::try_from(word).expect("Boxed slots are stored from a usize.")
+}
+
+/// Values split into parallel arrays. Scalars are stored entirely in `tags`
+/// and `words`. Other values are kept in the `boxed` side table, and their
+/// word holds their slot in it, so slots holding scalars cost no more than a
+/// tag and a word.
+#[cfg(feature = "soa-local-stack")]
+pub(super) struct ValueStorage {
+    tags: Vec<Tag>,
+    words: Vec<u64>,
+    boxed: Vec<Option<Value>>,
+    /// Slots of `boxed` that hold no value, to be reused.
+    free_boxed: Vec<usize>,
+}
+
+#[cfg(feature = "soa-local-stack")]
+impl ValueStorage {
+    pub fn new() -> Self {
+        ValueStorage {
+            tags: Vec::new(),
+            words: Vec::new(),
+            boxed: Vec::new(),
+            free_boxed: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    /// Stores a non-scalar value in the side table, returning its slot.
+    fn store_boxed(&mut self, value: Value) -> u64 {
+        let slot = match self.free_boxed.pop() {
+            Some(slot) => {
+                self.boxed[slot] = Some(value);
+                slot
+            }
+            None => {
+                self.boxed.push(Some(value));
+                self.boxed.len() - 1
+            }
+        };
+        slot as u64
+    }
+
+    /// Removes the value in the given slot of the side table.
+    fn take_boxed(&mut self, word: u64) -> Value {
+        let slot = boxed_slot(word);
+        let value = self.boxed[slot].take().expect("boxed slot is occupied");
+        if slot + 1 == self.boxed.len() {
+            self.boxed.pop();
+        } else {
+            self.free_boxed.push(slot);
+        }
+        if self.free_boxed.len() == self.boxed.len() {
+            self.boxed.clear();
+            self.free_boxed.clear();
+        }
+        value
+    }
+
+    fn encode(&mut self, value: Value) -> (Tag, u64) {
+        match value.to_scalar() {
+            Some((kind, word)) => (Tag::Scalar(kind), word),
+            None => (Tag::Boxed, self.store_boxed(value)),
+        }
+    }
+
+    pub fn push(&mut self, value: Value) {
+        let (tag, word) = self.encode(value);
+        self.tags.push(tag);
+        self.words.push(word);
+    }
+
+    fn value_at(&self, index: usize) -> Option<Value> {
+        let word = *self.words.get(index)?;
+        match self.tags[index] {
+            Tag::Scalar(kind) => Some(Value::from_scalar(kind, word)),
+            Tag::Boxed => self.boxed[boxed_slot(word)].clone(),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Value> {
+        let tag = self.tags.pop()?;
+        let word = self.words.pop().expect("arrays have the same length");
+        Some(match tag {
+            Tag::Scalar(kind) => Value::from_scalar(kind, word),
+            Tag::Boxed => self.take_boxed(word),
+        })
+    }
+
+    pub fn pop_bool(&mut self) -> Result<bool> {
+        let tag = *self.tags.last().ok_or_else(|| {
+            RuntimeError::new_operation_precondition_error("Local stack is empty.")
+        })?;
+        if tag != Tag::Scalar(ScalarKind::Bool) {
+            self.pop();
+            return Err(RuntimeError::new_type_error("Value is not a boolean."));
+        }
+        self.tags.pop();
+        Ok(self.words.pop().expect("arrays have the same length") != 0)
+    }
+
+    pub fn pop_int_pair(&mut self) -> Option<(i64, i64)> {
+        let start = self.tags.len().checked_sub(2)?;
+        let int = Tag::Scalar(ScalarKind::Integer);
+        if self.tags[start] != int || self.tags[start + 1] != int {
+            return None;
+        }
+        let pair = (self.words[start] as i64, self.words[start + 1] as i64);
+        self.truncate(start);
+        Some(pair)
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        for index in len..self.len() {
+            if self.tags[index] == Tag::Boxed {
+                self.take_boxed(self.words[index]);
+            }
+        }
+        self.tags.truncate(len);
+        self.words.truncate(len);
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.tags.capacity().min(self.words.capacity())
+    }
+
+    /// Lowers the capacity to at most `capacity`, keeping room for the
+    /// current values.
+    pub fn shrink_to(&mut self, capacity: usize) {
+        self.tags.shrink_to(capacity);
+        self.words.shrink_to(capacity);
+        self.boxed.shrink_to(capacity);
+        self.free_boxed.shrink_to(capacity);
+    }
+
+    pub fn get(&self, index: usize) -> Option<PinnedValue> {
+        self.value_at(index).map(Value::into_pinned)
+    }
+
+    /// Replaces the value at `index`, returning false if it is out of range.
+    pub fn set(&mut self, index: usize, value: Value) -> bool {
+        if index >= self.len() {
+            return false;
+        }
+        if self.tags[index] == Tag::Boxed {
+            self.take_boxed(self.words[index]);
+        }
+        let (tag, word) = self.encode(value);
+        self.tags[index] = tag;
+        self.words[index] = word;
+        true
+    }
+
+    pub fn pin_from(&self, start: usize) -> impl Iterator<Item = PinnedValue> + '_ {
+        (start..self.len()).map(|index| {
+            self.value_at(index)
+                .expect("index is in range")
+                .into_pinned()
+        })
+    }
+
+    /// Pushes `values` in order, so the last ends up on top.
+    pub fn extend(&mut self, values: impl Iterator<Item = Value>) {
+        let (additional, _) = values.size_hint();
+        self.tags.reserve(additional);
+        self.words.reserve(additional);
+        for value in values {
+            self.push(value);
+        }
+    }
+}
+
+#[cfg(feature = "soa-local-stack")]
+impl GcTraceable for ValueStorage {
+    fn trace<V>(&self, visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        for value in self.boxed.iter().flatten() {
+            value.trace(visitor);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        pure_values::{Float, Integer},
+        util::imm_string::ImmString,
+    };
+
+    fn int(i: i64) -> Value {
+        PinnedValue::new_integer(Integer::from(i)).to_value()
+    }
+
+    #[test]
+    fn push_pop_round_trips_values() -> anyhow::Result<()> {
+        let mut storage = ValueStorage::new();
+        storage.push(PinnedValue::new_bool(true).to_value());
+        storage.push(int(-5));
+        storage.push(PinnedValue::new_float(Float::new(-0.0)).to_value());
+        storage.push(PinnedValue::new_string(ImmString::from_str("hi")).to_value());
+        assert_eq!(storage.len(), 4);
+
+        assert_eq!(storage.pop().unwrap().pin().as_str()?.as_str(), "hi");
+        assert_eq!(
+            storage.pop().unwrap().pin().as_float()?.value().to_bits(),
+            (-0.0f64).to_bits()
+        );
+        assert_eq!(storage.pop().unwrap().pin().as_int()?, &Integer::from(-5));
+        assert!(storage.pop_bool()?);
+        assert!(storage.pop().is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn set_get_and_truncate() -> anyhow::Result<()> {
+        let mut storage = ValueStorage::new();
+        storage.extend((0..4).map(int));
+        assert!(storage.set(1, PinnedValue::new_bool(false).to_value()));
+        assert!(!storage.set(4, int(0)));
+        assert!(!storage.get(1).unwrap().as_bool()?);
+        assert_eq!(storage.get(2).unwrap().as_int()?, &Integer::from(2));
+        assert!(storage.get(4).is_none());
+
+        let tail = storage
+            .pin_from(2)
+            .map(|v| v.as_int().cloned())
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(tail, vec![Integer::from(2), Integer::from(3)]);
+
+        storage.truncate(1);
+        assert_eq!(storage.len(), 1);
+        assert!(storage.pop_bool().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn boxed_values_survive_replacement() -> anyhow::Result<()> {
+        let string = |s: &str| PinnedValue::new_string(ImmString::from_str(s)).to_value();
+        let mut storage = ValueStorage::new();
+        storage.push(string("a"));
+        storage.push(int(1));
+        storage.push(string("b"));
+        storage.push(string("c"));
+        assert!(storage.set(0, int(0)));
+        assert!(storage.set(1, string("d")));
+        assert!(storage.set(2, string("e")));
+        let values = storage
+            .pin_from(0)
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(values, ["0", "d", "e", "c"]);
+
+        storage.truncate(2);
+        storage.push(string("f"));
+        assert_eq!(storage.pop().unwrap().pin().as_str()?.as_str(), "f");
+        assert_eq!(storage.pop().unwrap().pin().as_str()?.as_str(), "d");
+        assert_eq!(storage.get(0).unwrap().as_int()?, &Integer::from(0));
+        Ok(())
+    }
+}
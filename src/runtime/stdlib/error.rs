@@ -0,0 +1,47 @@
+//! The `std.error` module, for creating and inspecting the error values that
+//! protected calls catch.
+
+use crate::runtime::{
+    error::Result,
+    native_module::NativeModule,
+    value::{ErrorValue, NativeFunctionContext, NativeFunctionResult, PinnedValue},
+};
+
+use super::check_arg_count;
+
+pub(super) fn module() -> NativeModule {
+    let mut module = NativeModule::new(["std", "error"]);
+    module
+        .add_function("new", new)
+        .add_function("kind", kind)
+        .add_function("message", message);
+    module
+}
+
+/// `new(kind, message)`: Returns an error value to be raised with `Raise`.
+fn new(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    check_arg_count(&mut ctxt, 2)?;
+    let args = ctxt.stack().drain_args(2)?;
+    let error = ErrorValue::new(args[0].as_str()?.clone(), args[1].as_str()?.clone());
+    ctxt.stack().push_value(PinnedValue::new_error(error))?;
+    Ok(ctxt.return_with(1))
+}
+
+/// `kind(error)`: Returns the kind of an error, e.g. `"type"` for type
+/// errors, or the kind it was created with.
+fn kind(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    check_arg_count(&mut ctxt, 1)?;
+    let error = ctxt.stack().pop_value()?;
+    let kind = error.as_error()?.kind().clone();
+    ctxt.stack().push_value(PinnedValue::new_string(kind))?;
+    Ok(ctxt.return_with(1))
+}
+
+/// `message(error)`: Returns the message of an error.
+fn message(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    check_arg_count(&mut ctxt, 1)?;
+    let error = ctxt.stack().pop_value()?;
+    let message = error.as_error()?.message().clone();
+    ctxt.stack().push_value(PinnedValue::new_string(message))?;
+    Ok(ctxt.return_with(1))
+}
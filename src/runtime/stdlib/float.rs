@@ -0,0 +1,149 @@
+//! The `std.float` module, with locale-independent conversions between
+//! floats and text.
+
+use crate::{
+    pure_values::Float,
+    runtime::{
+        error::{Result, RuntimeError},
+        native_module::NativeModule,
+        value::{NativeFunctionContext, NativeFunctionResult, PinnedValue},
+    },
+};
+
+/// The largest precision accepted by `to_string`. Every finite float has an
+/// exact decimal expansion with at most this many fractional digits.
+const MAX_PRECISION: usize = 1074;
+
+pub(super) fn module() -> NativeModule {
+    let mut module = NativeModule::new(["std", "float"]);
+    module
+        .add_function("parse", parse)
+        .add_function("to_string", to_string);
+    module
+}
+
+/// `parse(str)`: Returns the float written in `str`, or null if it is not a
+/// float literal.
+///
+/// Accepts decimal notation with an optional sign, fraction and exponent,
+/// such as `-1.5e3`, `.5` or `2`, and `inf`, `infinity` and `nan` in any
+/// case. Surrounding whitespace is not allowed. Values are rounded to the
+/// nearest float.
+fn parse(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    let mut stack = ctxt.stack();
+    if stack.len() != 1 {
+        return Err(RuntimeError::new_operation_precondition_error(format!(
+            "Expected 1 argument, got {}.",
+            stack.len()
+        )));
+    }
+    let text = stack.pop_value()?;
+    stack.push_value(match parse_float(text.as_str()?.as_str()) {
+        Some(value) => PinnedValue::new_float(Float::new(value)),
+        None => PinnedValue::new_null(),
+    })?;
+    Ok(ctxt.return_with(1))
+}
+
+/// `to_string(f, precision)`: Returns `f` formatted as text.
+///
+/// If `precision` is null, this is the shortest text that parses back to
+/// exactly `f`, such as `0.1` or `1e300`. Otherwise it is an integer number of
+/// digits to write after the decimal point, rounding to nearest. Infinities
+/// and NaN are written as `inf`, `-inf` and `NaN` in either case.
+fn to_string(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    let mut stack = ctxt.stack();
+    if stack.len() != 2 {
+        return Err(RuntimeError::new_operation_precondition_error(format!(
+            "Expected 2 arguments, got {}.",
+            stack.len()
+        )));
+    }
+    let precision = stack.pop_value()?;
+    let value = stack.pop_value()?;
+    let precision = if precision.is_null() {
+        None
+    } else {
+        let precision = usize::try_from(precision.as_compact_integer()?)
+            .ok()
+            .filter(|p| *p <= MAX_PRECISION)
+            .ok_or_else(|| {
+                RuntimeError::new_operation_precondition_error(format!(
+                    "Precision must be between 0 and {MAX_PRECISION}."
+                ))
+            })?;
+        Some(precision)
+    };
+    let text = format_float(value.as_float()?.value(), precision);
+    stack.push_value(PinnedValue::new_string(text.as_str().into()))?;
+    Ok(ctxt.return_with(1))
+}
+
+fn parse_float(text: &str) -> Option<f64> {
+    text.parse().ok()
+}
+
+fn format_float(value: f64, precision: Option<usize>) -> String {
+    match precision {
+        // The debug format is the shortest round-trip representation, and
+        // keeps a `.0` on integral values.
+        None => format!("{value:?}"),
+        Some(precision) => format!("{value:.precision$}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortest_format_round_trips() {
+        for value in [
+            0.1,
+            -0.0,
+            1.0,
+            1e300,
+            1e-7,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            f64::from_bits(1),
+            std::f64::consts::PI,
+        ] {
+            let text = format_float(value, None);
+            assert_eq!(
+                parse_float(&text).map(f64::to_bits),
+                Some(value.to_bits()),
+                "{text}"
+            );
+        }
+        assert_eq!(format_float(0.1, None), "0.1");
+        assert_eq!(format_float(1.0, None), "1.0");
+        assert_eq!(format_float(1e300, None), "1e300");
+    }
+
+    #[test]
+    fn fixed_precision_rounds() {
+        assert_eq!(format_float(2.5, Some(0)), "2");
+        assert_eq!(format_float(1.005, Some(2)), "1.00");
+        assert_eq!(format_float(-1.25, Some(3)), "-1.250");
+        assert_eq!(format_float(f64::NEG_INFINITY, Some(2)), "-inf");
+        assert_eq!(format_float(f64::NAN, None), "NaN");
+        // The smallest subnormal is 2^-1074, whose expansion needs every
+        // digit of the maximum precision and ends in a 5.
+        let text = format_float(f64::from_bits(1), Some(MAX_PRECISION));
+        assert_eq!(text.len(), "0.".len() + MAX_PRECISION);
+        assert!(text.ends_with('5'));
+    }
+
+    #[test]
+    fn parse_accepts_only_literals() {
+        assert_eq!(parse_float("-1.5e3"), Some(-1500.0));
+        assert_eq!(parse_float(".5"), Some(0.5));
+        assert_eq!(parse_float("2"), Some(2.0));
+        assert_eq!(parse_float("Infinity"), Some(f64::INFINITY));
+        assert!(parse_float("nan").is_some_and(f64::is_nan));
+        for text in ["", " 1", "1 ", "1,5", "0x10", "1e", "--1"] {
+            assert_eq!(parse_float(text), None, "{text}");
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! The `std.fn` module, with combinators over function values.
+
+use crate::runtime::{
+    error::{Result, RuntimeError},
+    native_module::NativeModule,
+    value::{
+        Function, List, Map, MapKey, NativeFunctionContext, NativeFunctionResult, PinnedValue,
+    },
+};
+
+type NativeFn = fn(NativeFunctionContext) -> Result<NativeFunctionResult>;
+
+pub(super) fn module() -> NativeModule {
+    let mut module = NativeModule::new(["std", "fn"]);
+    module
+        .add_function("memoize", memoize)
+        .add_function("memoize_by", memoize_by);
+    module
+}
+
+/// `memoize(f)`: Returns a function that calls `f`, caching its return values
+/// keyed on the arguments.
+///
+/// Scalar arguments are compared by value, and references by identity.
+fn memoize(ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    make_memoized(ctxt, 1, memoized_call)
+}
+
+/// `memoize_by(f, key_fn)`: Like `memoize`, but the cache key is the single
+/// value returned by calling `key_fn` with the arguments.
+fn memoize_by(ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    make_memoized(ctxt, 2, memoized_call_by)
+}
+
+fn to_u32(value: usize) -> Result<u32> {
+    u32::try_from(value)
+        .map_err(|_| RuntimeError::new_conversion_error("Value count does not fit in u32."))
+}
+
+/// Collects and pops all values on the native function's stack.
+fn take_stack(ctxt: &mut NativeFunctionContext) -> Result<Vec<PinnedValue>> {
+    let mut stack = ctxt.stack();
+    stack.drain_args(stack.len())
+}
+
+fn make_memoized(
+    mut ctxt: NativeFunctionContext,
+    num_args: usize,
+    call: NativeFn,
+) -> Result<NativeFunctionResult> {
+    let env = ctxt.env();
+    let mut args = take_stack(&mut ctxt)?;
+    if args.len() != num_args {
+        return Err(RuntimeError::new_operation_precondition_error(format!(
+            "Expected {num_args} arguments, got {}.",
+            args.len()
+        )));
+    }
+    for arg in &args {
+        arg.as_function()?;
+    }
+    // The closure captures the wrapped function, the cache, and the key
+    // function if there is one, in that order.
+    args.insert(1, PinnedValue::new_map(Map::new(env)));
+    let closure = Function::new_closure(env, Function::new_native(env, call), args.into_iter());
+    ctxt.stack()
+        .push_value(PinnedValue::new_function(closure))?;
+    Ok(ctxt.return_with(1))
+}
+
+fn memoized_call(ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    call_memoized(ctxt, false)
+}
+
+fn memoized_call_by(ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    call_memoized(ctxt, true)
+}
+
+fn call_memoized(
+    mut ctxt: NativeFunctionContext,
+    has_key_fn: bool,
+) -> Result<NativeFunctionResult> {
+    let env = ctxt.env();
+    let mut captured = take_stack(&mut ctxt)?;
+    let num_captured = if has_key_fn { 3 } else { 2 };
+    if captured.len() < num_captured {
+        return Err(RuntimeError::new_internal_error(
+            "Memoized function is missing captured values.",
+        ));
+    }
+    let args = captured.split_off(num_captured);
+    let function = captured[0].clone();
+    let cache = captured[1].as_map()?.clone();
+
+    let key = if has_key_fn {
+        ctxt.stack()
+            .push_all(args.iter().chain([&captured[2]]).cloned())?;
+        let num_returns = ctxt.call(to_u32(args.len())?)?;
+        if num_returns != 1 {
+            return Err(RuntimeError::new_operation_precondition_error(format!(
+                "Key function must return 1 value, returned {num_returns}."
+            )));
+        }
+        MapKey::from_value(&ctxt.stack().pop_value()?)
+    } else {
+        MapKey::from_values(&args)
+    };
+
+    if let Some(results) = cache.get(&key) {
+        let results = results.as_list()?;
+        ctxt.stack()
+            .push_all((0..results.len()).map(|i| results.at(i)))?;
+        return Ok(ctxt.return_with(to_u32(results.len())?));
+    }
+
+    ctxt.stack()
+        .push_all(args.iter().cloned().chain([function]))?;
+    let num_returns = ctxt.call(to_u32(args.len())?)?;
+    let results = ctxt.stack().peek_n(num_returns as usize)?;
+    cache.insert(key, PinnedValue::new_list(List::from_iter(env, results)));
+    Ok(ctxt.return_with(num_returns))
+}
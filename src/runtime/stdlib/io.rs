@@ -0,0 +1,318 @@
+//! The `std.io` module, with streams over handles opened by path.
+//!
+//! The runtime does not access the file system itself. Streams are provided
+//! by the [`IoBackend`] the embedder sets with
+//! [`Runtime::set_io_backend`](crate::runtime::Runtime::set_io_backend), so
+//! the same scripts can run against real files, network resources or memory.
+//! Importing the module requires the `"io"` capability.
+
+use std::{cell::RefCell, collections::HashMap, io, rc::Rc};
+
+use crate::{
+    runtime::{
+        error::{Result, RuntimeError},
+        native_module::NativeModule,
+        value::{NativeFunctionContext, NativeFunctionResult, PinnedValue},
+    },
+    util::imm_string::ImmBytes,
+};
+
+/// How a stream is opened.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum OpenMode {
+    /// Reads from the start of an existing stream.
+    Read,
+    /// Writes to a stream, replacing its previous contents.
+    Write,
+    /// Writes to the end of a stream, keeping its previous contents.
+    Append,
+}
+
+impl OpenMode {
+    fn parse(mode: &str) -> Option<Self> {
+        match mode {
+            "r" => Some(OpenMode::Read),
+            "w" => Some(OpenMode::Write),
+            "a" => Some(OpenMode::Append),
+            _ => None,
+        }
+    }
+}
+
+/// The streams available to scripts through `std.io`.
+///
+/// Handles are chosen by the backend, and only need to be unique among the
+/// streams that are open at the same time.
+pub trait IoBackend {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<u64>;
+
+    /// Reads at most `max_len` bytes. Returns no bytes at the end of the
+    /// stream.
+    fn read(&self, handle: u64, max_len: usize) -> io::Result<Vec<u8>>;
+
+    /// Writes some prefix of `data`, returning its length.
+    fn write(&self, handle: u64, data: &[u8]) -> io::Result<usize>;
+
+    fn close(&self, handle: u64) -> io::Result<()>;
+}
+
+/// The backend set on the runtime, kept in its host data.
+pub(crate) struct IoBackendData(pub(crate) Box<dyn IoBackend>);
+
+struct OpenStream {
+    path: String,
+    mode: OpenMode,
+    position: usize,
+}
+
+#[derive(Default)]
+struct MemoryFiles {
+    files: HashMap<String, Vec<u8>>,
+    streams: HashMap<u64, OpenStream>,
+    next_handle: u64,
+}
+
+/// An [`IoBackend`] whose streams are byte buffers in memory, keyed by path.
+///
+/// Clones share the same buffers, so the embedder can keep a clone to
+/// inspect what scripts wrote.
+#[derive(Clone, Default)]
+pub struct MemoryIoBackend(Rc<RefCell<MemoryFiles>>);
+
+impl MemoryIoBackend {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the contents of the buffer at `path`, creating it if needed.
+    pub fn insert_file(&self, path: impl Into<String>, contents: impl Into<Vec<u8>>) {
+        self.0
+            .borrow_mut()
+            .files
+            .insert(path.into(), contents.into());
+    }
+
+    /// Returns a copy of the contents of the buffer at `path`.
+    #[must_use]
+    pub fn file(&self, path: &str) -> Option<Vec<u8>> {
+        self.0.borrow().files.get(path).cloned()
+    }
+
+    /// Returns the number of streams that have not been closed.
+    #[must_use]
+    pub fn num_open(&self) -> usize {
+        self.0.borrow().streams.len()
+    }
+}
+
+fn unknown_handle(handle: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Handle {handle} is not open."),
+    )
+}
+
+impl IoBackend for MemoryIoBackend {
+    fn open(&self, path: &str, mode: OpenMode) -> io::Result<u64> {
+        let mut inner = self.0.borrow_mut();
+        let position = match mode {
+            OpenMode::Read => {
+                if !inner.files.contains_key(path) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("{path:?} does not exist."),
+                    ));
+                }
+                0
+            }
+            OpenMode::Write => {
+                inner.files.insert(path.to_string(), Vec::new());
+                0
+            }
+            OpenMode::Append => inner.files.entry(path.to_string()).or_default().len(),
+        };
+        let handle = inner.next_handle;
+        inner.next_handle += 1;
+        inner.streams.insert(
+            handle,
+            OpenStream {
+                path: path.to_string(),
+                mode,
+                position,
+            },
+        );
+        Ok(handle)
+    }
+
+    fn read(&self, handle: u64, max_len: usize) -> io::Result<Vec<u8>> {
+        let mut inner = self.0.borrow_mut();
+        let MemoryFiles { files, streams, .. } = &mut *inner;
+        let stream = streams
+            .get_mut(&handle)
+            .ok_or_else(|| unknown_handle(handle))?;
+        if stream.mode != OpenMode::Read {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Stream is not open for reading.",
+            ));
+        }
+        let contents = files.get(&stream.path).map_or(&[][..], Vec::as_slice);
+        let start = stream.position.min(contents.len());
+        let end = start.saturating_add(max_len).min(contents.len());
+        stream.position = end;
+        Ok(contents[start..end].to_vec())
+    }
+
+    fn write(&self, handle: u64, data: &[u8]) -> io::Result<usize> {
+        let mut inner = self.0.borrow_mut();
+        let MemoryFiles { files, streams, .. } = &mut *inner;
+        let stream = streams
+            .get_mut(&handle)
+            .ok_or_else(|| unknown_handle(handle))?;
+        if stream.mode == OpenMode::Read {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Stream is not open for writing.",
+            ));
+        }
+        let contents = files.entry(stream.path.clone()).or_default();
+        let end = stream.position + data.len();
+        if contents.len() < end {
+            contents.resize(end, 0);
+        }
+        contents[stream.position..end].copy_from_slice(data);
+        stream.position = end;
+        Ok(data.len())
+    }
+
+    fn close(&self, handle: u64) -> io::Result<()> {
+        self.0
+            .borrow_mut()
+            .streams
+            .remove(&handle)
+            .map(|_| ())
+            .ok_or_else(|| unknown_handle(handle))
+    }
+}
+
+pub(super) fn module() -> NativeModule {
+    let mut module = NativeModule::new(["std", "io"]);
+    module
+        .require_capability("io")
+        .add_function("open", open)
+        .add_function("read", read)
+        .add_function("write", write)
+        .add_function("close", close);
+    module
+}
+
+/// Pops the native function's arguments, of which there must be exactly
+/// `N`.
+fn take_args<const N: usize>(ctxt: &mut NativeFunctionContext) -> Result<[PinnedValue; N]> {
+    let mut stack = ctxt.stack();
+    if stack.len() != N {
+        return Err(RuntimeError::new_operation_precondition_error(format!(
+            "Expected {N} arguments, got {}.",
+            stack.len()
+        )));
+    }
+    let args = stack.drain_args(N)?;
+    Ok(args
+        .try_into()
+        .unwrap_or_else(|_| unreachable!("Exactly {N} arguments were drained.")))
+}
+
+fn backend(ctxt: &NativeFunctionContext) -> Result<Rc<IoBackendData>> {
+    ctxt.host_data::<IoBackendData>()
+        .ok_or_else(|| RuntimeError::new_operation_precondition_error("No I/O backend is set."))
+}
+
+fn as_handle(value: &PinnedValue) -> Result<u64> {
+    u64::try_from(value.as_compact_integer()?)
+        .map_err(|_| RuntimeError::new_conversion_error("Handles are non-negative."))
+}
+
+/// `open(path, mode)`: Opens the stream at `path`, returning its handle.
+/// `mode` is `"r"` to read, `"w"` to write or `"a"` to append.
+fn open(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    let [path, mode] = take_args(&mut ctxt)?;
+    let mode = OpenMode::parse(mode.as_str()?.as_str()).ok_or_else(|| {
+        RuntimeError::new_operation_precondition_error("Mode must be \"r\", \"w\" or \"a\".")
+    })?;
+    let handle = backend(&ctxt)?.0.open(path.as_str()?.as_str(), mode)?;
+    let handle = i64::try_from(handle)
+        .map_err(|_| RuntimeError::new_conversion_error("Handle is too large."))?;
+    ctxt.stack().push_int(handle)?;
+    Ok(ctxt.return_with(1))
+}
+
+/// `read(handle, max_len)`: Reads at most `max_len` bytes from the stream.
+/// Returns empty bytes at the end of the stream.
+fn read(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    let [handle, max_len] = take_args(&mut ctxt)?;
+    let max_len = usize::try_from(max_len.as_compact_integer()?)
+        .map_err(|_| RuntimeError::new_conversion_error("Length must be non-negative."))?;
+    let data = backend(&ctxt)?.0.read(as_handle(&handle)?, max_len)?;
+    ctxt.stack()
+        .push_value(PinnedValue::new_bytes(ImmBytes::from(&data[..])))?;
+    Ok(ctxt.return_with(1))
+}
+
+/// `write(handle, data)`: Writes a string or bytes to the stream, returning
+/// the number of bytes written.
+fn write(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    let [handle, data] = take_args(&mut ctxt)?;
+    let backend = backend(&ctxt)?;
+    let handle = as_handle(&handle)?;
+    let written = match data.as_str() {
+        Ok(data) => backend.0.write(handle, data.as_str().as_bytes())?,
+        Err(_) => backend.0.write(handle, &data.as_bytes()?[..])?,
+    };
+    let written = i64::try_from(written)
+        .map_err(|_| RuntimeError::new_conversion_error("Written length is too large."))?;
+    ctxt.stack().push_int(written)?;
+    Ok(ctxt.return_with(1))
+}
+
+/// `close(handle)`: Closes the stream. The handle may be reused afterwards.
+fn close(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    let [handle] = take_args(&mut ctxt)?;
+    backend(&ctxt)?.0.close(as_handle(&handle)?)?;
+    Ok(ctxt.return_with(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_streams_read_write_and_append() -> anyhow::Result<()> {
+        let backend = MemoryIoBackend::new();
+        backend.insert_file("in", "hello");
+        let handle = backend.open("in", OpenMode::Read)?;
+        assert_eq!(backend.read(handle, 3)?, b"hel");
+        assert_eq!(backend.read(handle, 10)?, b"lo");
+        assert!(backend.read(handle, 10)?.is_empty());
+        assert!(backend.write(handle, b"x").is_err());
+        backend.close(handle)?;
+        assert!(backend.close(handle).is_err());
+
+        let handle = backend.open("in", OpenMode::Append)?;
+        backend.write(handle, b", world")?;
+        backend.close(handle)?;
+        assert_eq!(backend.file("in").unwrap(), b"hello, world");
+
+        let handle = backend.open("in", OpenMode::Write)?;
+        backend.write(handle, b"bye")?;
+        backend.close(handle)?;
+        assert_eq!(backend.file("in").unwrap(), b"bye");
+
+        assert_eq!(
+            backend.open("missing", OpenMode::Read).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(backend.num_open(), 0);
+        Ok(())
+    }
+}
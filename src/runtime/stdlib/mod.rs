@@ -0,0 +1,38 @@
+//! Native modules that make up the Loon standard library.
+
+mod error;
+mod float;
+mod function;
+pub(crate) mod io;
+mod string;
+mod timer;
+
+use super::{
+    error::{Result, RuntimeError},
+    native_module::NativeModule,
+    value::NativeFunctionContext,
+};
+
+/// Returns all modules of the standard library.
+pub(crate) fn modules() -> Vec<NativeModule> {
+    vec![
+        error::module(),
+        float::module(),
+        function::module(),
+        io::module(),
+        string::module(),
+        timer::module(),
+    ]
+}
+
+/// Fails unless the native function was called with exactly `expected`
+/// arguments.
+fn check_arg_count(ctxt: &mut NativeFunctionContext, expected: usize) -> Result<()> {
+    let len = ctxt.stack().len();
+    if len != expected {
+        return Err(RuntimeError::new_operation_precondition_error(format!(
+            "Expected {expected} argument(s), got {len}."
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,199 @@
+//! The `std.string` module, with operations over string values.
+
+use crate::runtime::{
+    error::{Result, RuntimeError},
+    native_module::NativeModule,
+    value::{NativeFunctionContext, NativeFunctionResult, PinnedValue},
+};
+
+pub(super) fn module() -> NativeModule {
+    let mut module = NativeModule::new(["std", "string"]);
+    module.add_function("format", format);
+    module
+}
+
+/// `format(template, args)`: Returns `template` with each `{N}` placeholder
+/// replaced by the formatted value at index `N` of the list `args`.
+///
+/// `{{` and `}}` are written as literal braces.
+fn format(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    let max_depth = ctxt.env().max_nesting_depth();
+    let mut stack = ctxt.stack();
+    if stack.len() != 2 {
+        return Err(RuntimeError::new_operation_precondition_error(format!(
+            "Expected 2 arguments, got {}.",
+            stack.len()
+        )));
+    }
+    let args = stack.pop_value()?;
+    let template = stack.pop_value()?;
+    let args = args.as_list()?;
+    let args = (0..args.len()).map(|i| args.at(i)).collect::<Vec<_>>();
+    let result = format_template(template.as_str()?.as_str(), &args, max_depth)?;
+    stack.push_value(PinnedValue::new_string(result.as_str().into()))?;
+    Ok(ctxt.return_with(1))
+}
+
+fn format_template(template: &str, args: &[PinnedValue], max_depth: usize) -> Result<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                let rest = chars.as_str();
+                if let Some(rest) = rest.strip_prefix('{') {
+                    result.push('{');
+                    chars = rest.chars();
+                    continue;
+                }
+                let (index, rest) = rest.split_once('}').ok_or_else(|| {
+                    RuntimeError::new_operation_precondition_error("Unclosed '{' in template.")
+                })?;
+                let arg = index
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| args.get(index))
+                    .ok_or_else(|| {
+                        RuntimeError::new_operation_precondition_error(format!(
+                            "Invalid placeholder {{{index}}} for {} arguments.",
+                            args.len()
+                        ))
+                    })?;
+                result.push_str(&arg.to_display_string(max_depth)?);
+                chars = rest.chars();
+            }
+            '}' => {
+                let rest = chars.as_str().strip_prefix('}').ok_or_else(|| {
+                    RuntimeError::new_operation_precondition_error("Unmatched '}' in template.")
+                })?;
+                result.push('}');
+                chars = rest.chars();
+            }
+            c => result.push(c),
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gc::GcTrigger,
+        pure_values::{Float, Integer},
+        runtime::{global_env::GlobalEnv, value::List},
+    };
+
+    #[test]
+    fn placeholders_are_replaced_by_position() -> anyhow::Result<()> {
+        let args = [
+            PinnedValue::new_string("world".into()),
+            PinnedValue::new_integer(Integer::from(42)),
+        ];
+        assert_eq!(
+            format_template("hello {0}, {1} and {0}", &args, 8)?,
+            "hello world, 42 and world"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn escaped_braces_are_literal() -> anyhow::Result<()> {
+        let args = [PinnedValue::new_bool(true)];
+        assert_eq!(format_template("{{{0}}}", &args, 8)?, "{true}");
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_placeholders_are_errors() {
+        let args = [PinnedValue::new_float(Float::new(1.0))];
+        assert!(format_template("{1}", &args, 8).is_err());
+        assert!(format_template("{x}", &args, 8).is_err());
+        assert!(format_template("{0", &args, 8).is_err());
+        assert!(format_template("0}", &args, 8).is_err());
+    }
+
+    #[test]
+    fn lists_are_formatted_recursively() -> anyhow::Result<()> {
+        let env = GlobalEnv::new();
+        let list = List::from_iter(
+            &env,
+            [
+                PinnedValue::new_float(Float::new(1.0)),
+                PinnedValue::new_string("a".into()),
+            ],
+        );
+        list.append(PinnedValue::new_list(list.clone()));
+        let args = [PinnedValue::new_list(list)];
+        assert_eq!(format_template("{0}", &args, 8)?, r#"[1.0, "a", [...]]"#);
+        Ok(())
+    }
+
+    #[test]
+    fn lists_nested_past_the_limit_are_errors() -> anyhow::Result<()> {
+        let env = GlobalEnv::new();
+        let mut value = PinnedValue::new_integer(Integer::from(1));
+        for _ in 0..3 {
+            value = PinnedValue::new_list(List::from_iter(&env, [value]));
+        }
+        let args = [value];
+        assert_eq!(format_template("{0}", &args, 3)?, "[[[1]]]");
+        assert!(matches!(
+            format_template("{0}", &args, 2),
+            Err(RuntimeError::NestingTooDeep(2))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn deeply_nested_values_are_formatted_without_recursion() -> anyhow::Result<()> {
+        // Deep enough to overflow the stack of a recursive formatter, but
+        // short of the number of values written before the rest are elided.
+        const DEPTH: usize = 9_000;
+        let env = GlobalEnv::new();
+        // Collecting on every allocation would make building the value slow.
+        env.set_gc_trigger(GcTrigger::Manual);
+        let mut value = PinnedValue::new_integer(Integer::from(1));
+        for _ in 0..DEPTH {
+            value = PinnedValue::new_list(List::from_iter(&env, [value]));
+        }
+        let written = format_template("{0}", &[value], DEPTH)?;
+        assert_eq!(
+            written,
+            format!("{}1{}", "[".repeat(DEPTH), "]".repeat(DEPTH))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn shared_lists_are_written_in_full() -> anyhow::Result<()> {
+        let env = GlobalEnv::new();
+        let mut value = PinnedValue::new_list(List::from_iter(
+            &env,
+            [PinnedValue::new_integer(Integer::from(1))],
+        ));
+        let args = [PinnedValue::new_list(List::from_iter(
+            &env,
+            [value.clone(), value.clone()],
+        ))];
+        assert_eq!(format_template("{0}", &args, 8)?, "[[1], [1]]");
+
+        // Each level holds the one below twice, so writing every path would
+        // take 2^64 values.
+        for _ in 0..64 {
+            value = PinnedValue::new_list(List::from_iter(&env, [value.clone(), value]));
+        }
+        let written = format_template("{0}", &[value], 100)?;
+        assert!(written.starts_with("[[[["));
+        assert!(written.trim_end_matches(']').ends_with("..."));
+        assert!(written.len() < 100_000);
+
+        let long = List::from_iter(
+            &env,
+            (0..20_000i64).map(|i| PinnedValue::new_integer(Integer::from(i))),
+        );
+        let written = format_template("{0}", &[PinnedValue::new_list(long)], 8)?;
+        assert!(written.ends_with(", 9998, ...]"));
+        Ok(())
+    }
+}
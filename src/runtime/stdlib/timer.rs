@@ -0,0 +1,62 @@
+//! The `std.timer` module, for scheduling callbacks on the clock of the
+//! running top level.
+
+use crate::runtime::{
+    error::{Result, RuntimeError},
+    native_module::NativeModule,
+    value::{NativeFunctionContext, NativeFunctionResult, PinnedValue},
+    TimerId,
+};
+
+use super::check_arg_count;
+
+pub(super) fn module() -> NativeModule {
+    let mut module = NativeModule::new(["std", "timer"]);
+    module
+        .add_function("schedule", schedule)
+        .add_function("cancel", cancel)
+        .add_function("now", now);
+    module
+}
+
+/// Reads a timer id or tick count, which must be a non-negative integer.
+fn to_ticks(value: &PinnedValue) -> Result<u64> {
+    u64::try_from(value.as_compact_integer()?).map_err(|_| {
+        RuntimeError::new_operation_precondition_error("Tick counts cannot be negative.")
+    })
+}
+
+/// `schedule(delay, f)`: Schedules `f` to be called with no arguments once
+/// `delay` more ticks have passed, and returns an id for `cancel`.
+fn schedule(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    check_arg_count(&mut ctxt, 2)?;
+    let mut args = ctxt.stack().drain_args(2)?;
+    let delay = to_ticks(&args[0])?;
+    ctxt.stack().push_value(args.remove(1))?;
+    let TimerId(id) = ctxt.schedule_callback(delay)?;
+    let id = i64::try_from(id).map_err(|_| {
+        RuntimeError::new_operation_precondition_error("Too many callbacks have been scheduled.")
+    })?;
+    ctxt.stack()
+        .push_value(PinnedValue::new_integer(id.into()))?;
+    Ok(ctxt.return_with(1))
+}
+
+/// `cancel(id)`: Cancels a scheduled callback. Returns true if it had not yet
+/// run.
+fn cancel(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    check_arg_count(&mut ctxt, 1)?;
+    let id = to_ticks(&ctxt.stack().pop_value()?)?;
+    let cancelled = ctxt.cancel_callback(TimerId(id))?;
+    ctxt.stack().push_value(PinnedValue::new_bool(cancelled))?;
+    Ok(ctxt.return_with(1))
+}
+
+/// `now()`: Returns the current tick of the clock.
+fn now(mut ctxt: NativeFunctionContext) -> Result<NativeFunctionResult> {
+    check_arg_count(&mut ctxt, 0)?;
+    let tick = i64::try_from(ctxt.current_tick()?).unwrap_or(i64::MAX);
+    ctxt.stack()
+        .push_value(PinnedValue::new_integer(tick.into()))?;
+    Ok(ctxt.return_with(1))
+}
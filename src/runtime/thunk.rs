@@ -0,0 +1,410 @@
+//! Typed wrappers for calling exported functions from Rust.
+//!
+//! A thunk pairs an export with the Rust types of its parameters and return
+//! values, so that callers pass and receive plain Rust values instead of
+//! arranging the stack themselves. [`TopLevelRuntime::call`] does the same
+//! for a single call.
+//!
+//! Return values of the wrong type fail with an error that names their
+//! position, e.g. `Return value 2 of 3: Value is not a string.`
+
+use crate::{
+    binary::{instructions::StackIndex, modules::ImportSource},
+    pure_values::{Integer, LoonValue},
+};
+
+use super::{
+    error::{Result, RuntimeError},
+    stack_frame::StackContext,
+    top_level::TopLevelRuntime,
+};
+
+/// A Rust value that can be passed as an argument to a Loon function, or
+/// stored in a record field by [`loon_record!`](crate::loon_record).
+pub trait IntoStack: Sized {
+    /// Converts the value for places other than the stack, such as record
+    /// fields.
+    fn into_loon_value(self) -> LoonValue;
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_loon_value(&self.into_loon_value())
+    }
+}
+
+/// A Rust value that can be read from a Loon function's return values, or
+/// from a record field by [`loon_record!`](crate::loon_record).
+pub trait FromStack: Sized {
+    /// Converts a value read from places other than the stack, such as
+    /// record fields.
+    fn from_loon_value(value: LoonValue) -> Result<Self>;
+
+    fn read_from(stack: &StackContext, index: StackIndex) -> Result<Self> {
+        Self::from_loon_value(stack.get_loon_value(index)?)
+    }
+}
+
+fn type_error(expected: &str) -> RuntimeError {
+    RuntimeError::new_type_error(format!("Value is not {expected}."))
+}
+
+impl IntoStack for bool {
+    fn into_loon_value(self) -> LoonValue {
+        LoonValue::Bool(self)
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_bool(self)
+    }
+}
+
+impl IntoStack for i64 {
+    fn into_loon_value(self) -> LoonValue {
+        LoonValue::from(self)
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_int(self)
+    }
+}
+
+impl IntoStack for Integer {
+    fn into_loon_value(self) -> LoonValue {
+        LoonValue::Integer(self)
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_int(self)
+    }
+}
+
+impl IntoStack for f64 {
+    fn into_loon_value(self) -> LoonValue {
+        LoonValue::from(self)
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_float(self)
+    }
+}
+
+impl IntoStack for &str {
+    fn into_loon_value(self) -> LoonValue {
+        LoonValue::String(self.to_string())
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_string(self)
+    }
+}
+
+impl IntoStack for String {
+    fn into_loon_value(self) -> LoonValue {
+        LoonValue::String(self)
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_string(self)
+    }
+}
+
+impl IntoStack for &[u8] {
+    fn into_loon_value(self) -> LoonValue {
+        LoonValue::Bytes(self.to_vec())
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_bytes(self)
+    }
+}
+
+impl IntoStack for Vec<u8> {
+    fn into_loon_value(self) -> LoonValue {
+        LoonValue::Bytes(self)
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_bytes(self)
+    }
+}
+
+impl IntoStack for &LoonValue {
+    fn into_loon_value(self) -> LoonValue {
+        self.clone()
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_loon_value(self)
+    }
+}
+
+impl IntoStack for LoonValue {
+    fn into_loon_value(self) -> LoonValue {
+        self
+    }
+
+    fn push_onto(self, stack: &mut StackContext) -> Result<()> {
+        stack.push_loon_value(&self)
+    }
+}
+
+/// `None` is passed as null.
+impl<T: IntoStack> IntoStack for Option<T> {
+    fn into_loon_value(self) -> LoonValue {
+        self.map_or(LoonValue::Null, T::into_loon_value)
+    }
+}
+
+impl FromStack for bool {
+    fn from_loon_value(value: LoonValue) -> Result<Self> {
+        match value {
+            LoonValue::Bool(b) => Ok(b),
+            _ => Err(type_error("a boolean")),
+        }
+    }
+
+    fn read_from(stack: &StackContext, index: StackIndex) -> Result<Self> {
+        stack.get_bool(index)
+    }
+}
+
+/// Fails if the integer does not fit in an `i64`.
+fn compact_integer(value: Integer) -> Result<i64> {
+    value
+        .to_compact_integer()
+        .ok_or_else(|| RuntimeError::new_conversion_error("Integer does not fit in an i64."))
+}
+
+impl FromStack for i64 {
+    fn from_loon_value(value: LoonValue) -> Result<Self> {
+        compact_integer(Integer::from_loon_value(value)?)
+    }
+
+    fn read_from(stack: &StackContext, index: StackIndex) -> Result<Self> {
+        compact_integer(stack.get_int(index)?)
+    }
+}
+
+impl FromStack for Integer {
+    fn from_loon_value(value: LoonValue) -> Result<Self> {
+        match value {
+            LoonValue::Integer(i) => Ok(i),
+            _ => Err(type_error("an integer")),
+        }
+    }
+
+    fn read_from(stack: &StackContext, index: StackIndex) -> Result<Self> {
+        stack.get_int(index)
+    }
+}
+
+impl FromStack for f64 {
+    fn from_loon_value(value: LoonValue) -> Result<Self> {
+        match value {
+            LoonValue::Float(f) => Ok(f.value()),
+            _ => Err(type_error("a float")),
+        }
+    }
+
+    fn read_from(stack: &StackContext, index: StackIndex) -> Result<Self> {
+        Ok(stack.get_float(index)?.value())
+    }
+}
+
+impl FromStack for String {
+    fn from_loon_value(value: LoonValue) -> Result<Self> {
+        match value {
+            LoonValue::String(s) => Ok(s),
+            _ => Err(type_error("a string")),
+        }
+    }
+
+    fn read_from(stack: &StackContext, index: StackIndex) -> Result<Self> {
+        stack.get_string(index, |s| Ok(s.to_string()))
+    }
+}
+
+impl FromStack for Vec<u8> {
+    fn from_loon_value(value: LoonValue) -> Result<Self> {
+        match value {
+            LoonValue::Bytes(b) => Ok(b),
+            _ => Err(type_error("a byte string")),
+        }
+    }
+
+    fn read_from(stack: &StackContext, index: StackIndex) -> Result<Self> {
+        stack.get_bytes(index, |b| Ok(b.to_vec()))
+    }
+}
+
+impl FromStack for LoonValue {
+    fn from_loon_value(value: LoonValue) -> Result<Self> {
+        Ok(value)
+    }
+
+    fn read_from(stack: &StackContext, index: StackIndex) -> Result<Self> {
+        stack.get_loon_value(index)
+    }
+}
+
+/// Null reads as `None`.
+impl<T: FromStack> FromStack for Option<T> {
+    fn from_loon_value(value: LoonValue) -> Result<Self> {
+        match value {
+            LoonValue::Null => Ok(None),
+            value => T::from_loon_value(value).map(Some),
+        }
+    }
+}
+
+/// The parameters of a thunk, as a tuple of [`IntoStack`] values.
+pub trait ThunkArgs {
+    const COUNT: u32;
+
+    fn push_all(self, stack: &mut StackContext) -> Result<()>;
+}
+
+/// The return values of a thunk: `()`, a single [`FromStack`] value, or a
+/// tuple of them.
+pub trait ThunkReturn: Sized {
+    const COUNT: u32;
+
+    /// Reads the values from the top `COUNT` values of the stack, the first
+    /// being the deepest.
+    fn read_all(stack: &StackContext) -> Result<Self>;
+}
+
+/// Reads the return value `depth` values from the top of the stack, naming
+/// its position among the `count` return values if it cannot be read.
+fn read_return<T: FromStack>(stack: &StackContext, count: u32, depth: u32) -> Result<T> {
+    T::read_from(stack, StackIndex::FromTop(depth))
+        .map_err(|error| error.with_context(format!("Return value {} of {count}", count - depth)))
+}
+
+macro_rules! tuple_impls {
+    ($($name:ident)*) => {
+        impl<$($name: IntoStack),*> ThunkArgs for ($($name,)*) {
+            const COUNT: u32 = <[&str]>::len(&[$(stringify!($name)),*]) as u32;
+
+            #[allow(non_snake_case, unused_variables)]
+            fn push_all(self, stack: &mut StackContext) -> Result<()> {
+                let ($($name,)*) = self;
+                $($name.push_onto(stack)?;)*
+                Ok(())
+            }
+        }
+
+        impl<$($name: FromStack),*> ThunkReturn for ($($name,)*) {
+            const COUNT: u32 = <[&str]>::len(&[$(stringify!($name)),*]) as u32;
+
+            #[allow(unused_variables, unused_mut, unused_assignments)]
+            fn read_all(stack: &StackContext) -> Result<Self> {
+                let count = <Self as ThunkReturn>::COUNT;
+                let mut depth = count;
+                Ok(($({
+                    depth -= 1;
+                    read_return::<$name>(stack, count, depth)?
+                },)*))
+            }
+        }
+    };
+}
+
+tuple_impls!();
+tuple_impls!(A);
+tuple_impls!(A B);
+tuple_impls!(A B C);
+tuple_impls!(A B C D);
+tuple_impls!(A B C D E);
+tuple_impls!(A B C D E F);
+
+macro_rules! single_return_impls {
+    ($($ty:ty),*) => {
+        $(impl ThunkReturn for $ty {
+            const COUNT: u32 = 1;
+
+            fn read_all(stack: &StackContext) -> Result<Self> {
+                read_return::<$ty>(stack, 1, 0)
+            }
+        })*
+    };
+}
+
+single_return_impls!(bool, i64, Integer, f64, String, Vec<u8>, LoonValue);
+
+impl TopLevelRuntime {
+    /// Returns a closure that calls the function exported as `source`, with
+    /// the parameter types `A` and return types `R` declared by the caller.
+    ///
+    /// ```ignore
+    /// let add = top_level.thunk::<(i64, i64), i64>(&ImportSource::new(["math"], "add"))?;
+    /// assert_eq!(add((1, 2))?, 3);
+    /// ```
+    ///
+    /// The export is checked to be a function when the thunk is made. Each
+    /// call fails if the function returns a different number of values than
+    /// `R` declares, or values of other types, and leaves the stack as it was.
+    pub fn thunk<A, R>(&self, source: &ImportSource) -> Result<impl Fn(A) -> Result<R> + '_>
+    where
+        A: ThunkArgs,
+        R: ThunkReturn,
+    {
+        {
+            let mut stack = self.stack();
+            stack.push_import(source)?;
+            let is_function = stack.get_function_id(StackIndex::FromTop(0));
+            stack.pop_n(1)?;
+            is_function?;
+        }
+        let source = self.global_context().intern_import_source(source);
+        Ok(move |args: A| self.call(&source, args))
+    }
+
+    /// Calls the function exported as `source` once, with the Rust values in
+    /// the tuple `args`, and reads its return values as the types `R`.
+    ///
+    /// ```ignore
+    /// let (quotient, remainder) = top_level
+    ///     .call::<_, (i64, i64)>(&ImportSource::new(["math"], "div_rem"), (7, 2))?;
+    /// ```
+    ///
+    /// Like a call of a [`Self::thunk`], this fails if the function returns a
+    /// different number of values than `R` declares, or values of other
+    /// types, and leaves the stack as it was either way.
+    pub fn call<A, R>(&self, source: &ImportSource, args: A) -> Result<R>
+    where
+        A: ThunkArgs,
+        R: ThunkReturn,
+    {
+        let base = self.stack().len();
+        let result = self.call_thunk(source, args);
+        self.truncate_stack(base)?;
+        result
+    }
+
+    fn call_thunk<A, R>(&self, source: &ImportSource, args: A) -> Result<R>
+    where
+        A: ThunkArgs,
+        R: ThunkReturn,
+    {
+        {
+            let mut stack = self.stack();
+            args.push_all(&mut stack)?;
+            stack.push_import(source)?;
+        }
+        let num_returns = self.call_function(A::COUNT)?;
+        if num_returns != R::COUNT {
+            return Err(RuntimeError::new_operation_precondition_error(format!(
+                "Expected {} return values, got {num_returns}.",
+                R::COUNT
+            )));
+        }
+        R::read_all(&self.stack())
+    }
+
+    /// Pops values until `len` remain.
+    fn truncate_stack(&self, len: usize) -> Result<()> {
+        let mut stack = self.stack();
+        let excess = stack.len().saturating_sub(len);
+        stack.pop_n(excess)
+    }
+}
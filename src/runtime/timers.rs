@@ -0,0 +1,184 @@
+//! Callbacks scheduled to run at a later tick of a top level's clock.
+//!
+//! Each [`TopLevelRuntime`] has a logical clock, which advances by one tick
+//! for each instruction run with [`TopLevelRuntime::run_steps`], and by as
+//! many ticks as the host passes to [`TopLevelRuntime::advance_ticks`].
+//! Function values can be scheduled to run once the clock reaches a given
+//! tick, by the host, by native functions, or by managed code through the
+//! `std.timer` module. Due callbacks run between the instructions of a
+//! pending call, or when the host calls
+//! [`TopLevelRuntime::run_due_callbacks`], which gives cooperative embedders
+//! such as games an event loop without threads.
+
+use std::{
+    cell::{Cell, RefCell},
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use super::{
+    error::{Result, RuntimeError},
+    top_level::TopLevelRuntime,
+    value::PinnedValue,
+};
+
+/// Identifies a scheduled callback, e.g. to cancel it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TimerId(pub(crate) u64);
+
+/// The scheduled callbacks of a top level, and its clock.
+pub(crate) struct TimerQueue {
+    now: Cell<u64>,
+    next_id: Cell<u64>,
+    /// The due ticks and ids of scheduled callbacks, earliest first. Entries
+    /// of cancelled callbacks are dropped when they reach the front.
+    schedule: RefCell<BinaryHeap<Reverse<(u64, u64)>>>,
+    /// The callbacks that have been neither run nor cancelled. They are
+    /// pinned, so they stay alive until then.
+    callbacks: RefCell<HashMap<u64, PinnedValue>>,
+}
+
+impl TimerQueue {
+    pub fn new() -> Self {
+        TimerQueue {
+            now: Cell::new(0),
+            next_id: Cell::new(0),
+            schedule: RefCell::new(BinaryHeap::new()),
+            callbacks: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now.get()
+    }
+
+    pub fn advance(&self, ticks: u64) {
+        self.now.set(self.now.get().saturating_add(ticks));
+    }
+
+    /// Returns how many ticks from now the earliest scheduled callback is
+    /// due, or zero if it already is.
+    pub fn ticks_until_due(&self) -> Option<u64> {
+        let schedule = self.schedule.borrow();
+        let &Reverse((tick, _)) = schedule.peek()?;
+        Some(tick.saturating_sub(self.now()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.callbacks.borrow().len()
+    }
+
+    /// Schedules `callback` to run `delay` ticks from now. Callbacks due at
+    /// the same tick run in the order they were scheduled.
+    pub fn schedule(&self, delay: u64, callback: PinnedValue) -> Result<TimerId> {
+        self.schedule_at(self.now().saturating_add(delay), callback)
+    }
+
+    pub fn schedule_at(&self, tick: u64, callback: PinnedValue) -> Result<TimerId> {
+        callback.as_function()?;
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        self.schedule.borrow_mut().push(Reverse((tick, id)));
+        self.callbacks.borrow_mut().insert(id, callback);
+        Ok(TimerId(id))
+    }
+
+    /// Cancels a callback, returning true if it had not yet run.
+    pub fn cancel(&self, id: TimerId) -> bool {
+        self.callbacks.borrow_mut().remove(&id.0).is_some()
+    }
+
+    /// Returns the id the next scheduled callback will get.
+    fn next_id(&self) -> TimerId {
+        TimerId(self.next_id.get())
+    }
+
+    /// Removes and returns the earliest due callback scheduled before
+    /// `before`, if there is one.
+    fn pop_due(&self, before: TimerId) -> Option<(TimerId, PinnedValue)> {
+        let mut schedule = self.schedule.borrow_mut();
+        while let Some(&Reverse((tick, id))) = schedule.peek() {
+            // Callbacks scheduled while dispatching are due no earlier than
+            // now, so they sort after every callback that was already due.
+            if tick > self.now() || id >= before.0 {
+                return None;
+            }
+            schedule.pop();
+            if let Some(callback) = self.callbacks.borrow_mut().remove(&id) {
+                return Some((TimerId(id), callback));
+            }
+        }
+        None
+    }
+}
+
+impl TopLevelRuntime {
+    /// Schedules the function on top of the stack to be called with no
+    /// arguments once `delay` more ticks have passed. The function is popped.
+    pub fn schedule_callback(&self, delay: u64) -> Result<TimerId> {
+        let callback = self.stack().drain_args(1)?.remove(0);
+        self.timers().schedule(delay, callback)
+    }
+
+    /// Schedules the function on top of the stack like
+    /// [`Self::schedule_callback`], to run once the clock reaches `tick`.
+    pub fn schedule_callback_at(&self, tick: u64) -> Result<TimerId> {
+        let callback = self.stack().drain_args(1)?.remove(0);
+        self.timers().schedule_at(tick, callback)
+    }
+
+    /// Cancels a scheduled callback, returning true if it had not yet run.
+    pub fn cancel_callback(&self, id: TimerId) -> bool {
+        self.timers().cancel(id)
+    }
+
+    /// Returns the current tick of the clock.
+    #[must_use]
+    pub fn current_tick(&self) -> u64 {
+        self.timers().now()
+    }
+
+    /// Advances the clock, e.g. once per frame of a game. Callbacks that
+    /// become due run on the next call of [`Self::run_due_callbacks`] or
+    /// [`Self::run_steps`].
+    pub fn advance_ticks(&self, ticks: u64) {
+        self.timers().advance(ticks);
+    }
+
+    /// Returns the number of callbacks that have been neither run nor
+    /// cancelled.
+    #[must_use]
+    pub fn pending_callbacks(&self) -> usize {
+        self.timers().len()
+    }
+
+    /// Runs the callbacks that are due, earliest first, and returns how many
+    /// ran. Their return values are discarded.
+    ///
+    /// Callbacks scheduled while this runs wait for the next call, even if
+    /// they are already due. If a callback fails, the error is returned, and
+    /// the remaining due callbacks stay scheduled.
+    pub fn run_due_callbacks(&self) -> Result<usize> {
+        let timers = self.timers();
+        let before = timers.next_id();
+        let mut count = 0;
+        while let Some((_, callback)) = timers.pop_due(before) {
+            let base = self.stack().len();
+            self.stack().push_value(callback)?;
+            let result = self.call_function(0);
+            let mut stack = self.stack();
+            let extra = stack.len().saturating_sub(base);
+            stack.pop_n(extra)?;
+            result?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Fails because no top level is running to schedule a callback on.
+pub(crate) fn no_active_timers() -> RuntimeError {
+    RuntimeError::new_operation_precondition_error(
+        "Callbacks can only be scheduled while a top level's call is running.",
+    )
+}
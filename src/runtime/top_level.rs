@@ -1,14 +1,18 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Instant};
+
 use crate::{
-    binary::modules::ModuleId,
+    binary::{instructions::StackIndex, modules::ModuleId, Program},
     gc::{GcRef, GcTraceable, PinnedGcRef},
 };
 
 use super::{
-    error::Result,
-    eval_context::EvalContext,
+    error::{Result, RuntimeError},
+    eval_context::{CallStack, EvalContext, EvalOutcome},
     global_env::GlobalEnv,
+    quota::HeapAccount,
     stack_frame::{LocalStack, StackContext},
-    value::PinnedValue,
+    timers::TimerQueue,
+    value::{PinnedValue, Value},
 };
 
 pub struct Stack<'a> {
@@ -29,8 +33,37 @@ impl<'a> std::ops::DerefMut for Stack<'a> {
     }
 }
 
+/// How a call run with [`TopLevelRuntime::run_steps`] stopped.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum StepOutcome {
+    /// The call returned. Its return values, of which there are this many,
+    /// are on top of the stack.
+    Completed(u32),
+
+    /// The instruction budget ran out. Calling `run_steps` again continues
+    /// the call.
+    Paused,
+
+    /// A native function yielded to the host. Calling `run_steps` again
+    /// resumes the call.
+    Yielded,
+
+    /// The call failed, and has been abandoned.
+    Error(RuntimeError),
+
+    /// A scheduled callback that was due failed before the call continued.
+    /// The call is still pending, and calling `run_steps` again continues it.
+    CallbackFailed(RuntimeError),
+}
+
 struct Inner {
     stack: GcRef<LocalStack>,
+    /// The frames of the call started by [`TopLevelRuntime::start_call`], if
+    /// it has not finished yet.
+    pending_call: RefCell<Option<GcRef<CallStack>>>,
+    /// Values kept by name across calls, see [`TopLevelRuntime::set_slot`].
+    slots: RefCell<HashMap<String, Value>>,
 }
 
 impl GcTraceable for Inner {
@@ -39,12 +72,20 @@ impl GcTraceable for Inner {
         V: crate::gc::GcRefVisitor,
     {
         self.stack.trace(visitor);
+        if let Some(call_stack) = self.pending_call.borrow().as_ref() {
+            call_stack.trace(visitor);
+        }
+        for value in self.slots.borrow().values() {
+            value.trace(visitor);
+        }
     }
 }
 
 pub struct TopLevelRuntime {
     global_context: GlobalEnv,
     inner: PinnedGcRef<Inner>,
+    heap_account: Rc<HeapAccount>,
+    timers: Rc<TimerQueue>,
 }
 
 impl TopLevelRuntime {
@@ -52,14 +93,26 @@ impl TopLevelRuntime {
         let inner = global_context.with_lock(|lock| {
             global_context.create_pinned_ref(Inner {
                 stack: LocalStack::new(&global_context).into_ref(lock.guard()),
+                pending_call: RefCell::new(None),
+                slots: RefCell::new(HashMap::new()),
             })
         });
         TopLevelRuntime {
             global_context,
             inner,
+            heap_account: Rc::new(HeapAccount::new()),
+            timers: Rc::new(TimerQueue::new()),
         }
     }
 
+    pub(super) fn global_context(&self) -> &GlobalEnv {
+        &self.global_context
+    }
+
+    pub(super) fn timers(&self) -> &TimerQueue {
+        &self.timers
+    }
+
     #[must_use]
     pub fn stack(&self) -> Stack {
         Stack {
@@ -67,22 +120,288 @@ impl TopLevelRuntime {
         }
     }
 
+    /// Stores the value at `index` of the stack in the slot `name`, replacing
+    /// any value it held before. The stack is left unchanged.
+    ///
+    /// Slots keep their values alive across calls until they are removed, so
+    /// that values can be kept without tracking their stack positions.
+    pub fn set_slot(&self, name: impl Into<String>, index: StackIndex) -> Result<()> {
+        let value = self.inner.stack.borrow().get_at_index(index)?;
+        self.inner
+            .slots
+            .borrow_mut()
+            .insert(name.into(), value.to_value());
+        Ok(())
+    }
+
+    /// Pushes the value of the slot `name` onto the stack.
+    pub fn get_slot(&self, name: &str) -> Result<()> {
+        let value = self
+            .inner
+            .slots
+            .borrow()
+            .get(name)
+            .map(Value::pin)
+            .ok_or_else(|| {
+                RuntimeError::new_operation_precondition_error(format!("Slot {name:?} is not set."))
+            })?;
+        self.inner.stack.borrow().push(value)?;
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn has_slot(&self, name: &str) -> bool {
+        self.inner.slots.borrow().contains_key(name)
+    }
+
+    /// Removes the slot `name`, returning true if it was set.
+    pub fn remove_slot(&self, name: &str) -> bool {
+        self.inner.slots.borrow_mut().remove(name).is_some()
+    }
+
+    /// Returns the names of the slots that are set, in sorted order.
+    #[must_use]
+    pub fn slot_names(&self) -> Vec<String> {
+        let mut names = self
+            .inner
+            .slots
+            .borrow()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Calls the function on top of the stack with the `num_args` values
+    /// below it, and returns the number of values it returned.
+    ///
+    /// If a native function yields, the call returns a continuation followed
+    /// by the yielded values instead. Calling the continuation resumes the
+    /// call.
     pub fn call_function(&self, num_args: u32) -> Result<u32> {
+        let _account = self.global_context.enter_heap_account(&self.heap_account);
+        let _timers = self.global_context.enter_timer_queue(&self.timers);
         let function = self.inner.stack.borrow().pop()?.as_function()?.clone();
         let local_stack = self.inner.stack.pin();
-        let mut eval_context = EvalContext::new(&self.global_context, &local_stack);
+        let mut eval_context = EvalContext::new(&self.global_context, &local_stack, 0);
         eval_context.run(&function, num_args)
     }
 
+    /// Starts a call like [`Self::call_function`], but without running it.
+    /// The call is then run in slices with [`Self::run_steps`], so that the
+    /// host can do other work in between, e.g. render a frame.
+    ///
+    /// Only one such call may be in progress at a time.
+    pub fn start_call(&self, num_args: u32) -> Result<()> {
+        if self.has_pending_call() {
+            return Err(RuntimeError::new_operation_precondition_error(
+                "A call is already in progress.",
+            ));
+        }
+        let _account = self.global_context.enter_heap_account(&self.heap_account);
+        let _timers = self.global_context.enter_timer_queue(&self.timers);
+        let function = self.inner.stack.borrow().pop()?.as_function()?.clone();
+        let local_stack = self.inner.stack.pin();
+        let eval_context = EvalContext::new(&self.global_context, &local_stack, 0);
+        eval_context.push_call(&function, num_args)?;
+        *self.inner.pending_call.borrow_mut() = Some(eval_context.call_stack().to_ref());
+        Ok(())
+    }
+
+    /// Returns true if a call started with [`Self::start_call`] has not yet
+    /// completed or failed.
+    #[must_use]
+    pub fn has_pending_call(&self) -> bool {
+        self.inner.pending_call.borrow().is_some()
+    }
+
+    /// Continues the call started with [`Self::start_call`] for at most
+    /// `max_instructions` instructions. Each call of a native function counts
+    /// as one instruction.
+    ///
+    /// The execution limits still apply, and a call that fails or completes
+    /// is no longer pending.
+    ///
+    /// Each instruction is one tick of the clock of scheduled callbacks.
+    /// Callbacks that are due run first, as by [`Self::run_due_callbacks`],
+    /// and the slice stops to run the others once they become due. Callbacks
+    /// that the call itself schedules are timed from where the slice, or the
+    /// last such stop, began.
+    pub fn run_steps(&self, max_instructions: u64) -> StepOutcome {
+        if !self.has_pending_call() {
+            return StepOutcome::Error(RuntimeError::new_operation_precondition_error(
+                "No call is in progress.",
+            ));
+        }
+        let mut budget = max_instructions;
+        loop {
+            if let Err(error) = self.run_due_callbacks() {
+                return StepOutcome::CallbackFailed(error);
+            }
+            let mut slice = match self.timers.ticks_until_due() {
+                Some(ticks) => budget.min(ticks.max(1)),
+                None => budget,
+            };
+            let slice_len = slice;
+            let outcome = self.run_pending_call(&mut slice);
+            let steps = slice_len - slice;
+            self.timers.advance(steps);
+            budget -= steps;
+            match outcome {
+                StepOutcome::Paused if budget > 0 => {}
+                outcome => return outcome,
+            }
+        }
+    }
+
+    /// Continues the pending call for at most `budget` instructions, taking
+    /// the instructions that ran from `budget`.
+    fn run_pending_call(&self, budget: &mut u64) -> StepOutcome {
+        // Callbacks run as calls of their own, so the pending call is only
+        // pinned once they are done.
+        let Some(call_stack) = self.inner.pending_call.borrow().as_ref().map(GcRef::pin) else {
+            return StepOutcome::Error(RuntimeError::new_operation_precondition_error(
+                "No call is in progress.",
+            ));
+        };
+        let _account = self.global_context.enter_heap_account(&self.heap_account);
+        let _timers = self.global_context.enter_timer_queue(&self.timers);
+        let local_stack = self.inner.stack.pin();
+        let mut eval_context =
+            EvalContext::with_call_stack(&self.global_context, &local_stack, call_stack, 0);
+        let outcome = match eval_context.run_steps(budget) {
+            Ok(EvalOutcome::Paused) => return StepOutcome::Paused,
+            Ok(EvalOutcome::Yielded) => return StepOutcome::Yielded,
+            Ok(EvalOutcome::Returned(num_returns)) => StepOutcome::Completed(num_returns),
+            Err(e) => StepOutcome::Error(e),
+        };
+        *self.inner.pending_call.borrow_mut() = None;
+        outcome
+    }
+
+    /// Limits the number of objects this top level's calls may allocate, as
+    /// counted by [`Self::allocations`]. A call that goes over it fails with
+    /// [`RuntimeError::TenantQuotaExceeded`]. `None` removes the quota.
+    ///
+    /// All top levels of a runtime share one heap, so quotas keep one tenant
+    /// from starving the others. The quota is checked at safe points, so a
+    /// call may allocate a bounded number of objects past it.
+    pub fn set_allocation_quota(&self, quota: Option<u64>) {
+        self.heap_account.set_quota(quota);
+    }
+
+    #[must_use]
+    pub fn allocation_quota(&self) -> Option<u64> {
+        self.heap_account.quota()
+    }
+
+    /// Returns the number of objects allocated while this top level's calls
+    /// ran, including those that have since been collected. Allocations made
+    /// while a call of another top level runs within one of them are charged
+    /// to the other top level.
+    #[must_use]
+    pub fn allocations(&self) -> u64 {
+        self.heap_account.allocated()
+    }
+
+    /// Starts counting allocations from zero again, e.g. at the start of a
+    /// new accounting period.
+    pub fn reset_allocations(&self) {
+        self.heap_account.reset_allocated();
+    }
+
+    /// Calls a function like [`Self::call_function`], failing with
+    /// [`RuntimeError::Timeout`] if it is still running at `deadline`.
+    ///
+    /// The clock is checked at safe points, so the call may overrun the
+    /// deadline by the time it takes to reach the next one. A deadline set by
+    /// an enclosing call still applies if it is earlier.
+    pub fn call_function_with_deadline(&self, num_args: u32, deadline: Instant) -> Result<u32> {
+        self.with_deadline(deadline, || self.call_function(num_args))
+    }
+
+    fn with_deadline<R>(&self, deadline: Instant, body: impl FnOnce() -> R) -> R {
+        let previous = self.global_context.deadline();
+        let effective = previous.map_or(deadline, |previous| previous.min(deadline));
+        self.global_context.replace_deadline(Some(effective));
+        let result = body();
+        self.global_context.replace_deadline(previous);
+        result
+    }
+
     pub fn init_module(&self, module_id: &ModuleId) -> Result<()> {
-        if let Some(init_func) = self.global_context.get_init_function(module_id)? {
-            self.inner
-                .stack
-                .borrow()
-                .push(PinnedValue::new_function(init_func));
-            self.call_function(0)?;
-            self.global_context.set_module_initialized(module_id)?;
+        self.init_module_with_args(module_id, 0)
+    }
+
+    /// Runs the module's initializer with the top `num_args` values of the
+    /// stack as its arguments, e.g. to pass configuration to the module.
+    ///
+    /// The arguments are consumed. It is an error to pass arguments to a
+    /// module without an initializer.
+    ///
+    /// The runtime's fuel and cancellation apply as they do to any call. An
+    /// initializer stopped by them fails with
+    /// [`RuntimeError::InitializerInterrupted`], and the module stays
+    /// uninitialized.
+    pub fn init_module_with_args(&self, module_id: &ModuleId, num_args: u32) -> Result<()> {
+        match self.global_context.get_init_function(module_id)? {
+            Some(init_func) => {
+                self.inner
+                    .stack
+                    .borrow()
+                    .push(PinnedValue::new_function(init_func))?;
+                self.call_function(num_args)
+                    .map_err(|error| initializer_error(module_id, error))?;
+                self.global_context.set_module_initialized(module_id)?;
+            }
+            None if num_args > 0 => {
+                return Err(RuntimeError::new_operation_precondition_error(
+                    "Module has no initializer to receive arguments.",
+                ));
+            }
+            None => {}
         }
         Ok(())
     }
+
+    /// Initializes a module like [`Self::init_module_with_args`], failing with
+    /// [`RuntimeError::InitializerInterrupted`] if the initializer is still
+    /// running at `deadline`.
+    pub fn init_module_with_deadline(
+        &self,
+        module_id: &ModuleId,
+        num_args: u32,
+        deadline: Instant,
+    ) -> Result<()> {
+        self.with_deadline(deadline, || self.init_module_with_args(module_id, num_args))
+    }
+
+    /// Runs a program loaded with
+    /// [`Runtime::load_program`](super::Runtime::load_program): initializes
+    /// its modules in order, skipping those already initialized, then calls
+    /// its entry point with the top `num_args` values of the stack, and
+    /// returns the number of values it returned, as
+    /// [`Self::call_function`] does.
+    pub fn run_program(&self, program: &Program, num_args: u32) -> Result<u32> {
+        for module_id in program.init_order() {
+            self.init_module(module_id)?;
+        }
+        let entry = self.global_context.get_import(program.entry())?;
+        self.inner.stack.borrow().push(entry)?;
+        self.call_function(num_args)
+    }
+}
+
+/// Names the module in errors that stopped its initializer before it could
+/// complete. Other errors are passed through.
+fn initializer_error(module_id: &ModuleId, error: RuntimeError) -> RuntimeError {
+    if error.is_retriable() {
+        RuntimeError::InitializerInterrupted {
+            module: module_id.to_string(),
+            error: Box::new(error),
+        }
+    } else {
+        error
+    }
 }
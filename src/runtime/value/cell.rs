@@ -0,0 +1,43 @@
+use std::cell::RefCell;
+
+use crate::{
+    gc::{GcRefVisitor, GcTraceable, PinnedGcRef},
+    runtime::{global_env::GlobalEnv, value::Value},
+};
+
+use super::core::PinnedValue;
+
+/// A mutable box holding a single value.
+///
+/// Cells let several closures share a mutable variable without the overhead
+/// of a one-element list.
+pub struct Cell {
+    value: RefCell<Value>,
+}
+
+impl Cell {
+    pub(crate) fn new(env: &GlobalEnv, value: PinnedValue) -> PinnedGcRef<Self> {
+        env.with_lock(|lock| {
+            env.create_pinned_ref(Cell {
+                value: RefCell::new(value.into_value(lock)),
+            })
+        })
+    }
+
+    pub fn get(&self) -> PinnedValue {
+        self.value.borrow().pin()
+    }
+
+    pub fn set(&self, value: PinnedValue) {
+        *self.value.borrow_mut() = value.to_value();
+    }
+}
+
+impl GcTraceable for Cell {
+    fn trace<V>(&self, visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        self.value.borrow().trace(visitor);
+    }
+}
@@ -1,54 +1,149 @@
-use std::rc::Rc;
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::DefaultHasher, HashSet},
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
 
 use crate::{
-    binary::{ConstIndex, ConstValue},
+    binary::{
+        instructions::{NumericKind, Truthiness},
+        ConstIndex, ConstValue, ModuleConstIndex,
+    },
     gc::{GcRef, GcRefVisitor, GcTraceable, PinnedGcRef},
-    pure_values::{Float, Integer},
+    pure_values::{Float, Integer, LoonValue, Rational},
     runtime::{
         constants::{ConstLoader, ResolveFunc, ValueTable},
         context::ConstResolutionContext,
         environment::ModuleImportEnvironment,
-        global_env::GlobalEnvLock,
-        RuntimeError,
+        global_env::{GlobalEnv, GlobalEnvLock},
+        limits::DEFAULT_MAX_NESTING_DEPTH,
+        RuntimeError, ValueKind,
     },
-    util::imm_string::ImmString,
+    util::imm_string::{ImmBytes, ImmString},
 };
 
-use super::{Function, List};
+use super::{
+    map::MapKey, Cell, ErrorValue, Function, FunctionOrigin, List, Map, Record, Tag, Tagged,
+    WeakRef,
+};
 
 #[derive(Clone)]
 enum ValueInner {
+    Null,
     Integer(Integer),
     Float(Float),
+    Rational(Rational),
     Bool(bool),
     String(ImmString),
+    Bytes(ImmBytes),
     List(GcRef<List>),
     Function(GcRef<Function>),
+    Map(GcRef<Map>),
+    Cell(GcRef<Cell>),
+    WeakRef(GcRef<WeakRef>),
+    Record(GcRef<Record>),
+    Tag(GcRef<Tag>),
+    Tagged(GcRef<Tagged>),
+    Error(Rc<ErrorValue>),
 }
 
 #[derive(Clone)]
 pub(crate) struct Value(ValueInner);
 
 impl Value {
+    /// Creates an integer value. Scalars refer to no heap objects, so they
+    /// need no pinning.
+    pub fn new_integer(i: Integer) -> Self {
+        Value(ValueInner::Integer(i))
+    }
+
+    pub fn new_float(f: Float) -> Self {
+        Value(ValueInner::Float(f))
+    }
+
     pub fn into_pinned(self) -> PinnedValue {
         PinnedValue(match self.0 {
+            ValueInner::Null => PinnedValueInner::Null,
             ValueInner::Integer(i) => PinnedValueInner::Integer(i),
             ValueInner::Float(f) => PinnedValueInner::Float(f),
+            ValueInner::Rational(r) => PinnedValueInner::Rational(r),
             ValueInner::Bool(b) => PinnedValueInner::Bool(b),
             ValueInner::String(s) => PinnedValueInner::String(s),
+            ValueInner::Bytes(b) => PinnedValueInner::Bytes(b),
             ValueInner::List(l) => PinnedValueInner::List(l.into_pinned()),
             ValueInner::Function(f) => PinnedValueInner::Function(f.into_pinned()),
+            ValueInner::Map(m) => PinnedValueInner::Map(m.into_pinned()),
+            ValueInner::Cell(c) => PinnedValueInner::Cell(c.into_pinned()),
+            ValueInner::WeakRef(w) => PinnedValueInner::WeakRef(w.into_pinned()),
+            ValueInner::Record(r) => PinnedValueInner::Record(r.into_pinned()),
+            ValueInner::Tag(t) => PinnedValueInner::Tag(t.into_pinned()),
+            ValueInner::Tagged(t) => PinnedValueInner::Tagged(t.into_pinned()),
+            ValueInner::Error(e) => PinnedValueInner::Error(e),
         })
     }
 
+    /// Returns the value if it is an integer that fits in an `i64`.
+    #[cfg(not(feature = "soa-local-stack"))]
+    pub fn as_small_integer(&self) -> Option<i64> {
+        match &self.0 {
+            ValueInner::Integer(i) => i.to_compact_integer(),
+            _ => None,
+        }
+    }
+
     pub fn pin(&self) -> PinnedValue {
         PinnedValue(match &self.0 {
+            ValueInner::Null => PinnedValueInner::Null,
             ValueInner::Integer(i) => PinnedValueInner::Integer(i.clone()),
             ValueInner::Float(f) => PinnedValueInner::Float(f.clone()),
+            ValueInner::Rational(r) => PinnedValueInner::Rational(r.clone()),
             ValueInner::Bool(b) => PinnedValueInner::Bool(*b),
             ValueInner::String(s) => PinnedValueInner::String(s.clone()),
+            ValueInner::Bytes(b) => PinnedValueInner::Bytes(b.clone()),
             ValueInner::List(l) => PinnedValueInner::List(l.pin()),
             ValueInner::Function(f) => PinnedValueInner::Function(f.pin()),
+            ValueInner::Map(m) => PinnedValueInner::Map(m.pin()),
+            ValueInner::Cell(c) => PinnedValueInner::Cell(c.pin()),
+            ValueInner::WeakRef(w) => PinnedValueInner::WeakRef(w.pin()),
+            ValueInner::Record(r) => PinnedValueInner::Record(r.pin()),
+            ValueInner::Tag(t) => PinnedValueInner::Tag(t.pin()),
+            ValueInner::Tagged(t) => PinnedValueInner::Tagged(t.pin()),
+            ValueInner::Error(e) => PinnedValueInner::Error(e.clone()),
+        })
+    }
+}
+
+/// The kinds of values that fit entirely in a 64-bit word.
+#[cfg(feature = "soa-local-stack")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum ScalarKind {
+    Bool,
+    Integer,
+    Float,
+}
+
+#[cfg(feature = "soa-local-stack")]
+impl Value {
+    /// Splits a scalar value into its kind and payload word. Returns `None`
+    /// for values that need more than a word.
+    pub fn to_scalar(&self) -> Option<(ScalarKind, u64)> {
+        match &self.0 {
+            ValueInner::Bool(b) => Some((ScalarKind::Bool, u64::from(*b))),
+            ValueInner::Integer(i) => i
+                .to_compact_integer()
+                .map(|i| (ScalarKind::Integer, i as u64)),
+            ValueInner::Float(f) => Some((ScalarKind::Float, f.to_bits())),
+            _ => None,
+        }
+    }
+
+    /// Reassembles a value split by [`Value::to_scalar`].
+    pub fn from_scalar(kind: ScalarKind, word: u64) -> Self {
+        Value(match kind {
+            ScalarKind::Bool => ValueInner::Bool(word != 0),
+            ScalarKind::Integer => ValueInner::Integer(Integer::from(word as i64)),
+            ScalarKind::Float => ValueInner::Float(Float::from_bits(word)),
         })
     }
 }
@@ -59,12 +154,22 @@ impl GcTraceable for Value {
         V: GcRefVisitor,
     {
         match &self.0 {
-            ValueInner::Integer(_)
+            ValueInner::Null
+            | ValueInner::Integer(_)
             | ValueInner::Float(_)
+            | ValueInner::Rational(_)
             | ValueInner::String(_)
-            | ValueInner::Bool(_) => {}
+            | ValueInner::Bytes(_)
+            | ValueInner::Bool(_)
+            | ValueInner::Error(_) => {}
             ValueInner::List(l) => l.trace(visitor),
             ValueInner::Function(f) => f.trace(visitor),
+            ValueInner::Map(m) => m.trace(visitor),
+            ValueInner::Cell(c) => c.trace(visitor),
+            ValueInner::WeakRef(w) => w.trace(visitor),
+            ValueInner::Record(r) => r.trace(visitor),
+            ValueInner::Tag(t) => t.trace(visitor),
+            ValueInner::Tagged(t) => t.trace(visitor),
         }
     }
 }
@@ -75,10 +180,9 @@ fn resolve_index(
     consts: &[PinnedValue],
 ) -> Result<PinnedValue, RuntimeError> {
     match const_index {
-        ConstIndex::ModuleConst(index) => consts
-            .get(usize::try_from(*index).unwrap())
-            .cloned()
-            .ok_or_else(|| RuntimeError::new_internal_error("Invalid index.")),
+        ConstIndex::ModuleConst(index) => index.get(consts).cloned().ok_or_else(|| {
+            RuntimeError::new_operation_precondition_error("Invalid constant index.")
+        }),
         ConstIndex::ModuleImport(index) => imports.get_import(*index),
     }
 }
@@ -87,12 +191,15 @@ impl ConstLoader for ConstValue {
     fn load<'a>(
         &'a self,
         ctxt: &'a ConstResolutionContext,
+        index: ModuleConstIndex,
     ) -> Result<(PinnedValue, ResolveFunc<'a>), RuntimeError> {
         let (value, resolver) = match self {
+            ConstValue::Null => (PinnedValueInner::Null, None),
             ConstValue::Bool(b) => (PinnedValueInner::Bool(*b), None),
             ConstValue::Integer(i) => (PinnedValueInner::Integer(i.clone()), None),
             ConstValue::Float(f) => (PinnedValueInner::Float(f.clone()), None),
             ConstValue::String(s) => (PinnedValueInner::String(s.clone()), None),
+            ConstValue::Bytes(b) => (PinnedValueInner::Bytes(b.clone()), None),
             ConstValue::List(list) => {
                 let list_value = List::new(ctxt.env());
                 let resolver: ResolveFunc = {
@@ -108,12 +215,41 @@ impl ConstLoader for ConstValue {
 
                 (PinnedValueInner::List(list_value), Some(resolver))
             }
+            ConstValue::Record(record) => {
+                let shape = ctxt.env().record_shape(
+                    record
+                        .fields()
+                        .iter()
+                        .map(|(name, _)| name.clone())
+                        .collect(),
+                )?;
+                // The values may refer to constants that are not loaded yet,
+                // so the fields start out null.
+                let record_value = Record::new(
+                    ctxt.env(),
+                    shape,
+                    record.fields().iter().map(|_| PinnedValue::new_null()),
+                );
+                let resolver: ResolveFunc = {
+                    let record_value = record_value.clone();
+                    Box::new(move |imports, vs| {
+                        for (i, (_, index)) in record.fields().iter().enumerate() {
+                            record_value.set_at(i, resolve_index(index, imports, vs)?);
+                        }
+                        Ok(())
+                    })
+                };
+
+                (PinnedValueInner::Record(record_value), Some(resolver))
+            }
             ConstValue::Function(const_func) => {
                 let (deferred, resolve_fn) = Function::new_managed_deferred(
                     ctxt.env(),
                     ctxt.module_globals().clone(),
-                    Rc::new(ctxt.env().resolve_instructions(const_func.instructions())?),
-                );
+                    const_func.instructions().clone(),
+                    Rc::new(FunctionOrigin::new(ctxt.module_id().cloned(), index)),
+                    const_func.arity(),
+                )?;
                 let resolver: ResolveFunc = Box::new(move |imports, vs| {
                     let module_constants = const_func.module_constants();
                     let mut resolved_func_consts =
@@ -135,10 +271,16 @@ impl ConstLoader for ConstValue {
     }
 }
 
+/// A runtime value that stays alive while it is held, such as the arguments
+/// a native function takes off of its stack.
 #[derive(Clone)]
-pub(crate) struct PinnedValue(PinnedValueInner);
+pub struct PinnedValue(PinnedValueInner);
 
 impl PinnedValue {
+    pub fn new_null() -> Self {
+        PinnedValue(PinnedValueInner::Null)
+    }
+
     pub fn new_integer(i: Integer) -> Self {
         PinnedValue(PinnedValueInner::Integer(i))
     }
@@ -147,6 +289,10 @@ impl PinnedValue {
         PinnedValue(PinnedValueInner::Float(f))
     }
 
+    pub fn new_rational(r: Rational) -> Self {
+        PinnedValue(PinnedValueInner::Rational(r))
+    }
+
     pub fn new_bool(b: bool) -> Self {
         PinnedValue(PinnedValueInner::Bool(b))
     }
@@ -155,14 +301,71 @@ impl PinnedValue {
         PinnedValue(PinnedValueInner::String(s))
     }
 
+    pub fn new_bytes(b: ImmBytes) -> Self {
+        PinnedValue(PinnedValueInner::Bytes(b))
+    }
+
     pub fn new_list(l: PinnedGcRef<List>) -> Self {
         PinnedValue(PinnedValueInner::List(l))
     }
 
-    pub fn new_function(f: PinnedGcRef<Function>) -> Self {
+    pub(crate) fn new_function(f: PinnedGcRef<Function>) -> Self {
         PinnedValue(PinnedValueInner::Function(f))
     }
 
+    pub fn new_map(m: PinnedGcRef<Map>) -> Self {
+        PinnedValue(PinnedValueInner::Map(m))
+    }
+
+    pub fn new_cell(c: PinnedGcRef<Cell>) -> Self {
+        PinnedValue(PinnedValueInner::Cell(c))
+    }
+
+    pub fn new_weak_ref(w: PinnedGcRef<WeakRef>) -> Self {
+        PinnedValue(PinnedValueInner::WeakRef(w))
+    }
+
+    pub fn new_record(r: PinnedGcRef<Record>) -> Self {
+        PinnedValue(PinnedValueInner::Record(r))
+    }
+
+    pub fn new_tag(t: PinnedGcRef<Tag>) -> Self {
+        PinnedValue(PinnedValueInner::Tag(t))
+    }
+
+    pub fn new_tagged(t: PinnedGcRef<Tagged>) -> Self {
+        PinnedValue(PinnedValueInner::Tagged(t))
+    }
+
+    pub fn new_error(e: ErrorValue) -> Self {
+        PinnedValue(PinnedValueInner::Error(Rc::new(e)))
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self.0, PinnedValueInner::Null)
+    }
+
+    pub fn kind(&self) -> ValueKind {
+        match &self.0 {
+            PinnedValueInner::Null => ValueKind::Null,
+            PinnedValueInner::Bool(_) => ValueKind::Bool,
+            PinnedValueInner::Integer(_) => ValueKind::Integer,
+            PinnedValueInner::Float(_) => ValueKind::Float,
+            PinnedValueInner::Rational(_) => ValueKind::Rational,
+            PinnedValueInner::String(_) => ValueKind::String,
+            PinnedValueInner::Bytes(_) => ValueKind::Bytes,
+            PinnedValueInner::List(_) => ValueKind::List,
+            PinnedValueInner::Function(_) => ValueKind::Function,
+            PinnedValueInner::Map(_) => ValueKind::Map,
+            PinnedValueInner::Cell(_) => ValueKind::Cell,
+            PinnedValueInner::WeakRef(_) => ValueKind::WeakRef,
+            PinnedValueInner::Record(_) => ValueKind::Record,
+            PinnedValueInner::Tag(_) => ValueKind::Tag,
+            PinnedValueInner::Tagged(_) => ValueKind::Tagged,
+            PinnedValueInner::Error(_) => ValueKind::Error,
+        }
+    }
+
     pub fn as_compact_integer(&self) -> Result<i64, RuntimeError> {
         match &self.0 {
             PinnedValueInner::Integer(i) => i
@@ -172,6 +375,26 @@ impl PinnedValue {
         }
     }
 
+    /// Converts the value to a boolean with the given truthiness rules.
+    pub fn to_bool(&self, truthiness: Truthiness) -> Result<bool, RuntimeError> {
+        Ok(match (truthiness, &self.0) {
+            (_, PinnedValueInner::Bool(b)) => *b,
+            (Truthiness::Strict, _) => return self.as_bool(),
+            (_, PinnedValueInner::Null) => false,
+            (Truthiness::NullAndFalse, _) => true,
+            (Truthiness::Empty, value) => match value {
+                PinnedValueInner::Integer(i) => i.to_compact_integer() != Some(0),
+                PinnedValueInner::Float(f) => f.value() != 0.0 && !f.value().is_nan(),
+                PinnedValueInner::Rational(r) => !r.is_zero(),
+                PinnedValueInner::String(s) => !s.as_str().is_empty(),
+                PinnedValueInner::Bytes(b) => !b.as_bytes().is_empty(),
+                PinnedValueInner::List(l) => l.len() != 0,
+                PinnedValueInner::Map(m) => m.len() != 0,
+                _ => true,
+            },
+        })
+    }
+
     pub fn as_bool(&self) -> Result<bool, RuntimeError> {
         match &self.0 {
             PinnedValueInner::Bool(b) => Ok(*b),
@@ -193,7 +416,7 @@ impl PinnedValue {
         }
     }
 
-    pub fn as_function(&self) -> Result<&PinnedGcRef<Function>, RuntimeError> {
+    pub(crate) fn as_function(&self) -> Result<&PinnedGcRef<Function>, RuntimeError> {
         match &self.0 {
             PinnedValueInner::Function(f) => Ok(f),
             _ => Err(RuntimeError::new_type_error("Value is not a function.")),
@@ -207,6 +430,57 @@ impl PinnedValue {
         }
     }
 
+    pub fn as_map(&self) -> Result<&PinnedGcRef<Map>, RuntimeError> {
+        match &self.0 {
+            PinnedValueInner::Map(m) => Ok(m),
+            _ => Err(RuntimeError::new_type_error("Value is not a map.")),
+        }
+    }
+
+    pub fn as_cell(&self) -> Result<&PinnedGcRef<Cell>, RuntimeError> {
+        match &self.0 {
+            PinnedValueInner::Cell(c) => Ok(c),
+            _ => Err(RuntimeError::new_type_error("Value is not a cell.")),
+        }
+    }
+
+    pub fn as_weak_ref(&self) -> Result<&PinnedGcRef<WeakRef>, RuntimeError> {
+        match &self.0 {
+            PinnedValueInner::WeakRef(w) => Ok(w),
+            _ => Err(RuntimeError::new_type_error(
+                "Value is not a weak reference.",
+            )),
+        }
+    }
+
+    pub fn as_record(&self) -> Result<&PinnedGcRef<Record>, RuntimeError> {
+        match &self.0 {
+            PinnedValueInner::Record(r) => Ok(r),
+            _ => Err(RuntimeError::new_type_error("Value is not a record.")),
+        }
+    }
+
+    pub fn as_tag(&self) -> Result<&PinnedGcRef<Tag>, RuntimeError> {
+        match &self.0 {
+            PinnedValueInner::Tag(t) => Ok(t),
+            _ => Err(RuntimeError::new_type_error("Value is not a tag.")),
+        }
+    }
+
+    pub fn as_tagged(&self) -> Result<&PinnedGcRef<Tagged>, RuntimeError> {
+        match &self.0 {
+            PinnedValueInner::Tagged(t) => Ok(t),
+            _ => Err(RuntimeError::new_type_error("Value is not a tagged value.")),
+        }
+    }
+
+    pub fn as_error(&self) -> Result<&ErrorValue, RuntimeError> {
+        match &self.0 {
+            PinnedValueInner::Error(e) => Ok(e),
+            _ => Err(RuntimeError::new_type_error("Value is not an error.")),
+        }
+    }
+
     pub fn as_str(&self) -> Result<&ImmString, RuntimeError> {
         match &self.0 {
             PinnedValueInner::String(s) => Ok(s),
@@ -214,22 +488,273 @@ impl PinnedValue {
         }
     }
 
+    pub fn as_bytes(&self) -> Result<&ImmBytes, RuntimeError> {
+        match &self.0 {
+            PinnedValueInner::Bytes(b) => Ok(b),
+            _ => Err(RuntimeError::new_type_error("Value is not a byte string.")),
+        }
+    }
+
     /// Returns true if the two values are the same concrete value, or are the same
     /// reference.
     pub fn ref_eq(&self, other: &Self) -> bool {
         match (&self.0, &other.0) {
+            (PinnedValueInner::Null, PinnedValueInner::Null) => true,
             (PinnedValueInner::Bool(b1), PinnedValueInner::Bool(b2)) => b1 == b2,
             (PinnedValueInner::Integer(i1), PinnedValueInner::Integer(i2)) => i1 == i2,
             (PinnedValueInner::Float(f1), PinnedValueInner::Float(f2)) => f1 == f2,
+            (PinnedValueInner::Rational(r1), PinnedValueInner::Rational(r2)) => r1 == r2,
             (PinnedValueInner::String(s1), PinnedValueInner::String(s2)) => s1 == s2,
+            (PinnedValueInner::Bytes(b1), PinnedValueInner::Bytes(b2)) => b1 == b2,
             (PinnedValueInner::List(l1), PinnedValueInner::List(l2)) => PinnedGcRef::ref_eq(l1, l2),
             (PinnedValueInner::Function(f1), PinnedValueInner::Function(f2)) => {
                 PinnedGcRef::ref_eq(f1, f2)
             }
+            (PinnedValueInner::Map(m1), PinnedValueInner::Map(m2)) => PinnedGcRef::ref_eq(m1, m2),
+            (PinnedValueInner::Cell(c1), PinnedValueInner::Cell(c2)) => PinnedGcRef::ref_eq(c1, c2),
+            (PinnedValueInner::WeakRef(w1), PinnedValueInner::WeakRef(w2)) => {
+                PinnedGcRef::ref_eq(w1, w2)
+            }
+            (PinnedValueInner::Record(r1), PinnedValueInner::Record(r2)) => {
+                PinnedGcRef::ref_eq(r1, r2)
+            }
+            (PinnedValueInner::Tag(t1), PinnedValueInner::Tag(t2)) => PinnedGcRef::ref_eq(t1, t2),
+            (PinnedValueInner::Tagged(t1), PinnedValueInner::Tagged(t2)) => {
+                PinnedGcRef::ref_eq(t1, t2)
+            }
+            (PinnedValueInner::Error(e1), PinnedValueInner::Error(e2)) => Rc::ptr_eq(e1, e2),
             _ => false,
         }
     }
 
+    /// Returns a hash of the value that is consistent with [`Self::ref_eq`].
+    ///
+    /// Scalars, strings and bytes are hashed by value. Reference values are
+    /// hashed by identity, so their hash is stable for the lifetime of the
+    /// object, but may be reused once it is collected.
+    pub fn identity_hash(&self) -> i64 {
+        let key = match &self.0 {
+            // Both zeros are equal, so they must hash the same.
+            PinnedValueInner::Float(f) if f.value() == 0.0 => MapKey::Float(0),
+            _ => self.to_map_key(),
+        };
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        hasher.finish() as i64
+    }
+
+    /// Returns true if the two values are equal by value, as used by
+    /// `CompareOp::Eq`.
+    ///
+    /// Numbers of any kind are equal if they have the same mathematical value
+    /// (see [`Integer::cmp_float`]), so NaN is not equal to anything,
+    /// including itself. All other values are compared as by
+    /// [`Self::ref_eq`].
+    pub fn value_eq(&self, other: &Self) -> bool {
+        match self.numeric_cmp(other) {
+            Some(ordering) => ordering == Some(Ordering::Equal),
+            None => self.ref_eq(other),
+        }
+    }
+
+    /// Orders two values, as used by the ordering variants of `CompareOp`.
+    ///
+    /// Numbers are ordered by their mathematical values, and may be mixed.
+    /// Strings and byte strings are ordered lexicographically by their bytes.
+    /// Returns `Ok(None)` if either value is NaN, and a type error for any
+    /// other combination of values.
+    pub fn value_cmp(&self, other: &Self) -> Result<Option<Ordering>, RuntimeError> {
+        if let Some(ordering) = self.numeric_cmp(other) {
+            return Ok(ordering);
+        }
+        Ok(match (&self.0, &other.0) {
+            (PinnedValueInner::String(s1), PinnedValueInner::String(s2)) => {
+                Some(s1.as_str().cmp(s2.as_str()))
+            }
+            (PinnedValueInner::Bytes(b1), PinnedValueInner::Bytes(b2)) => {
+                Some(b1.as_bytes().cmp(b2.as_bytes()))
+            }
+            _ => {
+                return Err(RuntimeError::new_type_error(
+                    "Only numbers, strings and byte strings can be ordered, and only with \
+                     values of the same kind.",
+                ))
+            }
+        })
+    }
+
+    /// Orders two numbers exactly by their mathematical values. Returns
+    /// `None` if either value is not a number, and `Some(None)` if either is
+    /// NaN.
+    fn numeric_cmp(&self, other: &Self) -> Option<Option<Ordering>> {
+        Some(match (&self.0, &other.0) {
+            (PinnedValueInner::Integer(i1), PinnedValueInner::Integer(i2)) => Some(i1.cmp(i2)),
+            (PinnedValueInner::Float(f1), PinnedValueInner::Float(f2)) => f1.partial_cmp(f2),
+            (PinnedValueInner::Rational(r1), PinnedValueInner::Rational(r2)) => Some(r1.cmp(r2)),
+            (PinnedValueInner::Integer(i), PinnedValueInner::Float(f)) => i.cmp_float(f),
+            (PinnedValueInner::Rational(r), PinnedValueInner::Float(f)) => r.cmp_float(f),
+            (PinnedValueInner::Integer(i), PinnedValueInner::Rational(r)) => {
+                Some(Rational::from(i.clone()).cmp(r))
+            }
+            (
+                PinnedValueInner::Float(_) | PinnedValueInner::Rational(_),
+                PinnedValueInner::Integer(_),
+            )
+            | (PinnedValueInner::Float(_), PinnedValueInner::Rational(_)) => {
+                other.numeric_cmp(self)?.map(Ordering::reverse)
+            }
+            _ => return None,
+        })
+    }
+
+    /// Returns the kind of number this value is, or `None` if it is not a
+    /// number.
+    pub fn numeric_kind(&self) -> Option<NumericKind> {
+        match &self.0 {
+            PinnedValueInner::Integer(_) => Some(NumericKind::Integer),
+            PinnedValueInner::Rational(_) => Some(NumericKind::Rational),
+            PinnedValueInner::Float(_) => Some(NumericKind::Float),
+            _ => None,
+        }
+    }
+
+    /// Converts a number to the given kind, as used by `ToNumber`.
+    ///
+    /// Conversions to integers round toward zero, and conversions to floats
+    /// round to nearest. Infinities and NaN are a conversion error unless
+    /// converted to a float.
+    pub fn to_number(&self, kind: NumericKind) -> Result<PinnedValue, RuntimeError> {
+        let not_finite = || {
+            RuntimeError::new_conversion_error(format!(
+                "Cannot convert {self} to {}.",
+                match kind {
+                    NumericKind::Integer => "an integer",
+                    NumericKind::Rational => "a rational",
+                    NumericKind::Float => "a float",
+                }
+            ))
+        };
+        Ok(PinnedValue(match (&self.0, kind) {
+            (PinnedValueInner::Integer(i), NumericKind::Integer) => {
+                PinnedValueInner::Integer(i.clone())
+            }
+            (PinnedValueInner::Integer(i), NumericKind::Rational) => {
+                PinnedValueInner::Rational(Rational::from(i.clone()))
+            }
+            (PinnedValueInner::Integer(i), NumericKind::Float) => {
+                PinnedValueInner::Float(i.to_float())
+            }
+            (PinnedValueInner::Rational(r), NumericKind::Integer) => {
+                PinnedValueInner::Integer(r.trunc())
+            }
+            (PinnedValueInner::Rational(r), NumericKind::Rational) => {
+                PinnedValueInner::Rational(r.clone())
+            }
+            (PinnedValueInner::Rational(r), NumericKind::Float) => {
+                PinnedValueInner::Float(r.to_float())
+            }
+            (PinnedValueInner::Float(f), NumericKind::Integer) => {
+                PinnedValueInner::Integer(Rational::from_float(f).ok_or_else(not_finite)?.trunc())
+            }
+            (PinnedValueInner::Float(f), NumericKind::Rational) => {
+                PinnedValueInner::Rational(Rational::from_float(f).ok_or_else(not_finite)?)
+            }
+            (PinnedValueInner::Float(f), NumericKind::Float) => PinnedValueInner::Float(f.clone()),
+            _ => return Err(RuntimeError::new_type_error("Value is not a number.")),
+        }))
+    }
+
+    pub(super) fn to_map_key(&self) -> MapKey {
+        match &self.0 {
+            PinnedValueInner::Null => MapKey::Null,
+            PinnedValueInner::Bool(b) => MapKey::Bool(*b),
+            PinnedValueInner::Integer(i) => MapKey::Integer(i.clone()),
+            PinnedValueInner::Float(f) => MapKey::Float(f.value().to_bits()),
+            PinnedValueInner::Rational(r) => MapKey::Rational(r.clone()),
+            PinnedValueInner::String(s) => MapKey::String(s.clone()),
+            PinnedValueInner::Bytes(b) => MapKey::Bytes(b.clone()),
+            PinnedValueInner::List(l) => MapKey::Ref(l.identity(), self.to_value()),
+            PinnedValueInner::Function(f) => MapKey::Ref(f.identity(), self.to_value()),
+            PinnedValueInner::Map(m) => MapKey::Ref(m.identity(), self.to_value()),
+            PinnedValueInner::Cell(c) => MapKey::Ref(c.identity(), self.to_value()),
+            PinnedValueInner::WeakRef(w) => MapKey::Ref(w.identity(), self.to_value()),
+            PinnedValueInner::Record(r) => MapKey::Ref(r.identity(), self.to_value()),
+            PinnedValueInner::Tag(t) => MapKey::Ref(t.identity(), self.to_value()),
+            PinnedValueInner::Tagged(t) => MapKey::Ref(t.identity(), self.to_value()),
+            PinnedValueInner::Error(e) => {
+                MapKey::Ref(Rc::as_ptr(e) as *const () as usize, self.to_value())
+            }
+        }
+    }
+
+    /// Creates a value from a [`LoonValue`], allocating lists in `env`.
+    pub(crate) fn from_loon_value(env: &GlobalEnv, value: &LoonValue) -> Self {
+        match value {
+            LoonValue::Null => PinnedValue::new_null(),
+            LoonValue::Bool(b) => PinnedValue::new_bool(*b),
+            LoonValue::Integer(i) => PinnedValue::new_integer(i.clone()),
+            LoonValue::Float(f) => PinnedValue::new_float(f.clone()),
+            LoonValue::Rational(r) => PinnedValue::new_rational(r.clone()),
+            LoonValue::String(s) => PinnedValue::new_string(s.as_str().into()),
+            LoonValue::Bytes(b) => PinnedValue::new_bytes(b.as_slice().into()),
+            LoonValue::List(items) => PinnedValue::new_list(List::from_iter(
+                env,
+                items
+                    .iter()
+                    .map(|item| PinnedValue::from_loon_value(env, item)),
+            )),
+        }
+    }
+
+    /// Converts the value to a [`LoonValue`], copying lists nested at most
+    /// `max_depth` deep.
+    ///
+    /// Functions, maps, cells, weak references, records, tags, tagged values
+    /// and errors have no `LoonValue` form, and are a type error. A list that
+    /// contains itself exceeds any depth limit.
+    pub fn to_loon_value(&self, max_depth: usize) -> Result<LoonValue, RuntimeError> {
+        self.to_loon_value_within(max_depth, max_depth)
+    }
+
+    fn to_loon_value_within(
+        &self,
+        remaining_depth: usize,
+        max_depth: usize,
+    ) -> Result<LoonValue, RuntimeError> {
+        Ok(match &self.0 {
+            PinnedValueInner::Null => LoonValue::Null,
+            PinnedValueInner::Bool(b) => LoonValue::Bool(*b),
+            PinnedValueInner::Integer(i) => LoonValue::Integer(i.clone()),
+            PinnedValueInner::Float(f) => LoonValue::Float(f.clone()),
+            PinnedValueInner::Rational(r) => LoonValue::Rational(r.clone()),
+            PinnedValueInner::String(s) => LoonValue::String(s.as_str().to_string()),
+            PinnedValueInner::Bytes(b) => LoonValue::Bytes(b.as_bytes().to_vec()),
+            PinnedValueInner::List(l) => {
+                let remaining_depth = remaining_depth
+                    .checked_sub(1)
+                    .ok_or(RuntimeError::NestingTooDeep(max_depth))?;
+                LoonValue::List(
+                    (0..l.len())
+                        .map(|i| l.at(i).to_loon_value_within(remaining_depth, max_depth))
+                        .collect::<Result<_, _>>()?,
+                )
+            }
+            PinnedValueInner::Function(_)
+            | PinnedValueInner::Map(_)
+            | PinnedValueInner::Cell(_)
+            | PinnedValueInner::WeakRef(_)
+            | PinnedValueInner::Record(_)
+            | PinnedValueInner::Tag(_)
+            | PinnedValueInner::Tagged(_)
+            | PinnedValueInner::Error(_) => {
+                return Err(RuntimeError::new_type_error(
+                    "Functions, maps, cells, weak references, records, tags and errors cannot \
+                     be converted to plain values.",
+                ))
+            }
+        })
+    }
+
     pub fn add_owned(self, other: Self) -> Result<Self, RuntimeError> {
         match (self.0, other.0) {
             (PinnedValueInner::Integer(i1), PinnedValueInner::Integer(i2)) => {
@@ -238,43 +763,307 @@ impl PinnedValue {
             (PinnedValueInner::Float(f1), PinnedValueInner::Float(f2)) => {
                 Ok(PinnedValue(PinnedValueInner::Float(f1.add_owned(f2))))
             }
-            _ => Err(RuntimeError::new_type_error(
-                "Addition is only supported for integers and floats.",
-            )),
+            (v1, v2) => PinnedValue(v1).arith(&PinnedValue(v2), ArithOp::Add),
         }
     }
 
-    pub fn to_value(&self) -> Value {
+    /// Returns `self - other`.
+    pub fn sub(&self, other: &Self) -> Result<Self, RuntimeError> {
+        self.arith(other, ArithOp::Sub)
+    }
+
+    pub fn mul(&self, other: &Self) -> Result<Self, RuntimeError> {
+        self.arith(other, ArithOp::Mul)
+    }
+
+    /// Returns `self / other`.
+    pub fn div(&self, other: &Self) -> Result<Self, RuntimeError> {
+        self.arith(other, ArithOp::Div)
+    }
+
+    /// Applies an arithmetic operation to two numbers, after converting both
+    /// to the wider of their kinds.
+    fn arith(&self, other: &Self, op: ArithOp) -> Result<Self, RuntimeError> {
+        let (Some(kind1), Some(kind2)) = (self.numeric_kind(), other.numeric_kind()) else {
+            return Err(RuntimeError::new_type_error(
+                "Arithmetic is only supported for numbers.",
+            ));
+        };
+        let kind = kind1.max(kind2);
+        let (lhs, rhs) = (self.to_number(kind)?, other.to_number(kind)?);
+        let division_by_zero =
+            || RuntimeError::new_operation_precondition_error("Division by zero.");
+        Ok(PinnedValue(match (lhs.0, rhs.0) {
+            (PinnedValueInner::Integer(i1), PinnedValueInner::Integer(i2)) => match op {
+                ArithOp::Add => PinnedValueInner::Integer(i1.add_owned(i2)),
+                ArithOp::Sub => PinnedValueInner::Integer(i1.sub_owned(i2)),
+                ArithOp::Mul => PinnedValueInner::Integer(i1.mul_owned(i2)),
+                ArithOp::Div => {
+                    PinnedValueInner::Rational(Rational::new(i1, i2).ok_or_else(division_by_zero)?)
+                }
+            },
+            (PinnedValueInner::Rational(r1), PinnedValueInner::Rational(r2)) => {
+                PinnedValueInner::Rational(match op {
+                    ArithOp::Add => r1.add(&r2),
+                    ArithOp::Sub => r1.sub(&r2),
+                    ArithOp::Mul => r1.mul(&r2),
+                    ArithOp::Div => r1.checked_div(&r2).ok_or_else(division_by_zero)?,
+                })
+            }
+            (PinnedValueInner::Float(f1), PinnedValueInner::Float(f2)) => {
+                let (f1, f2) = (f1.value(), f2.value());
+                PinnedValueInner::Float(Float::new(match op {
+                    ArithOp::Add => f1 + f2,
+                    ArithOp::Sub => f1 - f2,
+                    ArithOp::Mul => f1 * f2,
+                    ArithOp::Div => f1 / f2,
+                }))
+            }
+            _ => unreachable!("both operands were converted to the same kind"),
+        }))
+    }
+
+    pub(crate) fn to_value(&self) -> Value {
         Value(match &self.0 {
+            PinnedValueInner::Null => ValueInner::Null,
             PinnedValueInner::Integer(i) => ValueInner::Integer(i.clone()),
             PinnedValueInner::Float(f) => ValueInner::Float(f.clone()),
+            PinnedValueInner::Rational(r) => ValueInner::Rational(r.clone()),
             PinnedValueInner::Bool(b) => ValueInner::Bool(*b),
             PinnedValueInner::String(s) => ValueInner::String(s.clone()),
+            PinnedValueInner::Bytes(b) => ValueInner::Bytes(b.clone()),
             PinnedValueInner::List(l) => ValueInner::List(l.to_ref()),
             PinnedValueInner::Function(f) => ValueInner::Function(f.to_ref()),
+            PinnedValueInner::Map(m) => ValueInner::Map(m.to_ref()),
+            PinnedValueInner::Cell(c) => ValueInner::Cell(c.to_ref()),
+            PinnedValueInner::WeakRef(w) => ValueInner::WeakRef(w.to_ref()),
+            PinnedValueInner::Record(r) => ValueInner::Record(r.to_ref()),
+            PinnedValueInner::Tag(t) => ValueInner::Tag(t.to_ref()),
+            PinnedValueInner::Tagged(t) => ValueInner::Tagged(t.to_ref()),
+            PinnedValueInner::Error(e) => ValueInner::Error(e.clone()),
         })
     }
 
-    pub fn into_value(self, env_lock: &GlobalEnvLock) -> Value {
+    pub(crate) fn into_value(self, env_lock: &GlobalEnvLock) -> Value {
         Value(match self.0 {
+            PinnedValueInner::Null => ValueInner::Null,
             PinnedValueInner::Integer(i) => ValueInner::Integer(i),
             PinnedValueInner::Float(f) => ValueInner::Float(f),
+            PinnedValueInner::Rational(r) => ValueInner::Rational(r),
             PinnedValueInner::Bool(b) => ValueInner::Bool(b),
             PinnedValueInner::String(s) => ValueInner::String(s),
+            PinnedValueInner::Bytes(b) => ValueInner::Bytes(b),
             PinnedValueInner::List(l) => ValueInner::List(l.into_ref(env_lock.guard())),
             PinnedValueInner::Function(f) => ValueInner::Function(f.into_ref(env_lock.guard())),
+            PinnedValueInner::Map(m) => ValueInner::Map(m.into_ref(env_lock.guard())),
+            PinnedValueInner::Cell(c) => ValueInner::Cell(c.into_ref(env_lock.guard())),
+            PinnedValueInner::WeakRef(w) => ValueInner::WeakRef(w.into_ref(env_lock.guard())),
+            PinnedValueInner::Record(r) => ValueInner::Record(r.into_ref(env_lock.guard())),
+            PinnedValueInner::Tag(t) => ValueInner::Tag(t.into_ref(env_lock.guard())),
+            PinnedValueInner::Tagged(t) => ValueInner::Tagged(t.into_ref(env_lock.guard())),
+            PinnedValueInner::Error(e) => ValueInner::Error(e),
         })
     }
 }
 
+#[derive(Copy, Clone)]
+enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
 #[derive(Clone)]
 enum PinnedValueInner {
+    Null,
     Integer(Integer),
     Float(Float),
+    Rational(Rational),
     Bool(bool),
     String(ImmString),
+    Bytes(ImmBytes),
     List(PinnedGcRef<List>),
     Function(PinnedGcRef<Function>),
+    Map(PinnedGcRef<Map>),
+    Cell(PinnedGcRef<Cell>),
+    WeakRef(PinnedGcRef<WeakRef>),
+    Record(PinnedGcRef<Record>),
+    Tag(PinnedGcRef<Tag>),
+    Tagged(PinnedGcRef<Tagged>),
+    Error(Rc<ErrorValue>),
+}
+
+/// At most this many values are written when formatting a value. The rest
+/// are elided as `...`.
+const MAX_DISPLAY_VALUES: usize = 10_000;
+
+/// The state of formatting one value.
+struct FormatState {
+    /// How many lists enclose the value being written.
+    depth: usize,
+    /// The identities of the lists that enclose the value being written.
+    ancestors: HashSet<usize>,
+    /// How many values have been written.
+    written: usize,
+    max_depth: usize,
+    too_deep: bool,
+}
+
+impl FormatState {
+    fn new(max_depth: usize) -> Self {
+        FormatState {
+            depth: 0,
+            ancestors: HashSet::new(),
+            written: 0,
+            max_depth,
+            too_deep: false,
+        }
+    }
+}
+
+/// A value that is partly written, waiting for its items to be written.
+enum FormatFrame {
+    /// A list, with the index of the next item to write.
+    List {
+        list: PinnedGcRef<List>,
+        next: usize,
+    },
+    /// A tagged value, with its value if that is still to be written.
+    Tagged(Option<PinnedValue>),
+}
+
+impl PinnedValue {
+    /// Writes the value with an explicit stack of the lists and tagged values
+    /// being written, so that deeply nested values do not overflow the native
+    /// stack.
+    fn fmt_nested(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        state: &mut FormatState,
+    ) -> std::fmt::Result {
+        let mut frames = Vec::new();
+        self.fmt_start(f, state, &mut frames)?;
+        while let Some(frame) = frames.last_mut() {
+            let item = match frame {
+                FormatFrame::List { list, next }
+                    if *next < list.len() && state.written <= MAX_DISPLAY_VALUES =>
+                {
+                    let item = list.at(*next);
+                    *next += 1;
+                    Some((item, *next > 1))
+                }
+                FormatFrame::List { .. } => None,
+                FormatFrame::Tagged(value) => value.take().map(|value| (value, false)),
+            };
+            match item {
+                Some((item, needs_separator)) => {
+                    if needs_separator {
+                        f.write_str(", ")?;
+                    }
+                    item.fmt_start(f, state, &mut frames)?;
+                }
+                None => match frames.pop() {
+                    Some(FormatFrame::List { list, .. }) => {
+                        state.depth -= 1;
+                        state.ancestors.remove(&list.identity());
+                        f.write_str("]")?;
+                    }
+                    _ => f.write_str(")")?,
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the value if it contains no other values. Otherwise writes
+    /// its opening and pushes a frame for the rest onto `frames`.
+    fn fmt_start(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        state: &mut FormatState,
+        frames: &mut Vec<FormatFrame>,
+    ) -> std::fmt::Result {
+        if state.written >= MAX_DISPLAY_VALUES {
+            if state.written == MAX_DISPLAY_VALUES {
+                state.written += 1;
+                f.write_str("...")?;
+            }
+            return Ok(());
+        }
+        state.written += 1;
+        match &self.0 {
+            PinnedValueInner::Null => f.write_str("null"),
+            PinnedValueInner::Integer(i) => write!(f, "{i}"),
+            PinnedValueInner::Float(fl) => write!(f, "{fl}"),
+            PinnedValueInner::Rational(r) => write!(f, "{r}"),
+            PinnedValueInner::Bool(b) => write!(f, "{b}"),
+            PinnedValueInner::String(s) if state.depth == 0 => f.write_str(s.as_str()),
+            PinnedValueInner::String(s) => write!(f, "{:?}", s.as_str()),
+            PinnedValueInner::Bytes(b) => write!(f, "b\"{}\"", b.as_bytes().escape_ascii()),
+            PinnedValueInner::List(l) => {
+                if state.depth >= state.max_depth {
+                    state.too_deep = true;
+                    return f.write_str("[...]");
+                }
+                // A list that encloses itself is elided, so that cyclic
+                // lists are not written forever.
+                if !state.ancestors.insert(l.identity()) {
+                    return f.write_str("[...]");
+                }
+                state.depth += 1;
+                frames.push(FormatFrame::List {
+                    list: l.clone(),
+                    next: 0,
+                });
+                f.write_str("[")
+            }
+            PinnedValueInner::Function(_) => f.write_str("<function>"),
+            PinnedValueInner::Map(_) => f.write_str("<map>"),
+            PinnedValueInner::Cell(_) => f.write_str("<cell>"),
+            PinnedValueInner::WeakRef(_) => f.write_str("<weak>"),
+            PinnedValueInner::Record(_) => f.write_str("<record>"),
+            PinnedValueInner::Tag(t) => write!(f, "<tag {}>", t.name().as_str()),
+            PinnedValueInner::Tagged(t) => {
+                frames.push(FormatFrame::Tagged(Some(t.value())));
+                write!(f, "{}(", t.tag().name().as_str())
+            }
+            PinnedValueInner::Error(e) => {
+                write!(f, "<error {}: {}>", e.kind().as_str(), e.message().as_str())
+            }
+        }
+    }
+
+    /// Formats the value as [`Display`](std::fmt::Display) does, failing
+    /// with [`RuntimeError::NestingTooDeep`] if lists are nested more than
+    /// `max_depth` deep, instead of eliding them.
+    pub fn to_display_string(&self, max_depth: usize) -> Result<String, RuntimeError> {
+        struct Limited<'a>(&'a PinnedValue, std::cell::RefCell<FormatState>);
+
+        impl std::fmt::Display for Limited<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt_nested(f, &mut self.1.borrow_mut())
+            }
+        }
+
+        let limited = Limited(self, std::cell::RefCell::new(FormatState::new(max_depth)));
+        let result = limited.to_string();
+        if limited.1.borrow().too_deep {
+            return Err(RuntimeError::NestingTooDeep(max_depth));
+        }
+        Ok(result)
+    }
+}
+
+impl std::fmt::Display for PinnedValue {
+    /// Formats the value for human-readable output.
+    ///
+    /// Strings are written as-is, except inside lists where they are quoted.
+    /// A list that contains itself is elided as `[...]` where it recurs, as
+    /// are lists nested more than the default maximum nesting depth. Values past the first few thousand are elided as `...`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.fmt_nested(f, &mut FormatState::new(DEFAULT_MAX_NESTING_DEPTH))
+    }
 }
 
 impl From<Integer> for PinnedValue {
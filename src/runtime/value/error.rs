@@ -0,0 +1,42 @@
+use crate::{runtime::RuntimeError, util::imm_string::ImmString};
+
+/// An error as managed code sees it: a kind, such as `"type"` for type
+/// errors, and a message.
+///
+/// Error values are created when a protected call catches an error, and can
+/// be raised again with `Raise`. They are immutable, and hold no references
+/// to other values.
+#[derive(Debug)]
+pub struct ErrorValue {
+    kind: ImmString,
+    message: ImmString,
+}
+
+impl ErrorValue {
+    pub fn new(kind: ImmString, message: ImmString) -> Self {
+        ErrorValue { kind, message }
+    }
+
+    /// Returns the value a protected call catches `error` as, or `None` if
+    /// the error cannot be caught.
+    pub fn caught(error: &RuntimeError) -> Option<Self> {
+        let (kind, message) = error.caught_kind_and_message()?;
+        Some(ErrorValue::new(kind.into(), message.into()))
+    }
+
+    pub fn kind(&self) -> &ImmString {
+        &self.kind
+    }
+
+    pub fn message(&self) -> &ImmString {
+        &self.message
+    }
+
+    /// Returns the error that raising this value fails with.
+    pub fn to_runtime_error(&self) -> RuntimeError {
+        RuntimeError::Raised {
+            kind: self.kind.as_str().to_string(),
+            message: self.message.as_str().to_string(),
+        }
+    }
+}
@@ -1,19 +1,21 @@
-use std::rc::Rc;
+use std::{cell::RefCell, rc::Rc};
 
 use crate::{
+    binary::instructions::InstructionList,
     gc::{GcRef, GcRefVisitor, GcTraceable, PinnedGcRef},
     runtime::{
         constants::ValueTable,
         error::{Result, RuntimeError},
         global_env::GlobalEnv,
-        instructions::InstEvalList,
         modules::ModuleGlobals,
+        profile::FunctionProfile,
         stack_frame::{LocalStack, PinnedValueBuffer, StackFrame},
         value::Value,
+        FunctionId,
     },
 };
 
-use self::managed::ManagedFunction;
+use self::managed::{FunctionOrigin, ManagedFunction};
 use self::native::NativeFunctionPtr;
 
 use super::PinnedValue;
@@ -38,35 +40,101 @@ impl GcTraceable for Closure {
     }
 }
 
+/// The rest of a call that was suspended when a native function yielded.
+/// Calling it resumes the call, which can only be done once.
+pub struct Continuation {
+    /// The frames of the suspended call, outermost first. They are taken
+    /// when the continuation is resumed.
+    frames: RefCell<Option<Vec<GcRef<StackFrame>>>>,
+    /// The function whose call was suspended.
+    function_id: FunctionId,
+}
+
+impl Continuation {
+    /// Takes the suspended frames, passing `values` to the frame that
+    /// yielded. `check_depth` is called with the number of frames before they
+    /// are taken, so the continuation stays resumable if it fails.
+    fn resume(
+        &self,
+        env: &GlobalEnv,
+        values: impl IntoIterator<Item = PinnedValue>,
+        check_depth: impl FnOnce(usize) -> Result<()>,
+    ) -> Result<Vec<PinnedGcRef<StackFrame>>> {
+        let mut frames = self.frames.borrow_mut();
+        let num_frames = frames
+            .as_ref()
+            .ok_or_else(|| {
+                RuntimeError::new_operation_precondition_error("Continuation was already resumed.")
+            })?
+            .len();
+        check_depth(num_frames)?;
+        let frames = frames
+            .take()
+            .expect("frames were checked above")
+            .iter()
+            .map(GcRef::pin)
+            .collect::<Vec<_>>();
+        frames
+            .last()
+            .ok_or_else(|| RuntimeError::new_internal_error("Continuation has no frames."))?
+            .push_iter(env, values)?;
+        Ok(frames)
+    }
+}
+
+impl GcTraceable for Continuation {
+    fn trace<V>(&self, visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        for frame in self.frames.borrow().iter().flatten() {
+            visitor.visit(frame);
+        }
+    }
+}
+
 pub(crate) enum Function {
     Managed(ManagedFunction),
-    Native(NativeFunctionPtr),
+    Native(NativeFunctionPtr, FunctionId),
     Closure(Closure),
+    Continuation(Continuation),
 }
 
 impl Function {
     pub fn new_managed_deferred(
         global_env: &GlobalEnv,
         global: PinnedGcRef<ModuleGlobals>,
-        inst_list: Rc<InstEvalList>,
-    ) -> (PinnedGcRef<Self>, impl FnOnce(PinnedGcRef<ValueTable>)) {
+        source: InstructionList,
+        origin: Rc<FunctionOrigin>,
+        arity: Option<u32>,
+    ) -> Result<(PinnedGcRef<Self>, impl FnOnce(PinnedGcRef<ValueTable>))> {
+        let inst_list = global_env.resolve_instructions(&source)?;
         let base_func_value = global_env.create_pinned_ref(Function::Managed(
-            ManagedFunction::new_deferred(global, inst_list),
+            ManagedFunction::new_deferred(global, source, inst_list, origin, arity),
         ));
 
-        (base_func_value.clone(), move |value_table| {
+        Ok((base_func_value.clone(), move |value_table| {
             let Function::Managed(managed_func) = &*base_func_value else {
                 unreachable!()
             };
             managed_func.resolve_constants(value_table);
-        })
+        }))
     }
 
     pub fn new_native<T>(global_env: &GlobalEnv, native_func: T) -> PinnedGcRef<Self>
     where
         T: native::NativeFunction + 'static,
     {
-        global_env.create_pinned_ref(Function::Native(NativeFunctionPtr::new(native_func)))
+        let id = FunctionId::Host(global_env.next_host_function_id());
+        Self::from_native_ptr(global_env, NativeFunctionPtr::new(native_func), id)
+    }
+
+    pub fn from_native_ptr(
+        global_env: &GlobalEnv,
+        native_func: NativeFunctionPtr,
+        id: FunctionId,
+    ) -> PinnedGcRef<Self> {
+        global_env.create_pinned_ref(Function::Native(native_func, id))
     }
 
     pub fn new_closure(
@@ -82,14 +150,84 @@ impl Function {
         })
     }
 
+    /// Creates a continuation that resumes `frames`, the suspended frames of
+    /// a call of the function `function_id`.
+    pub fn new_continuation(
+        global_env: &GlobalEnv,
+        frames: Vec<PinnedGcRef<StackFrame>>,
+        function_id: FunctionId,
+    ) -> PinnedGcRef<Self> {
+        global_env.with_lock(|lock| {
+            global_env.create_pinned_ref(Function::Continuation(Continuation {
+                frames: RefCell::new(Some(
+                    frames
+                        .into_iter()
+                        .map(|frame| frame.into_ref(lock.guard()))
+                        .collect(),
+                )),
+                function_id,
+            }))
+        })
+    }
+
+    /// If this function is a continuation, or a closure over one, resumes it
+    /// with `args` and returns the frames to continue running. Other
+    /// functions return `None`, and are called with
+    /// [`Self::make_stack_frame`]. `check_depth` is called with the number
+    /// of frames before the continuation gives them up.
+    pub fn resume_continuation(
+        &self,
+        env: &GlobalEnv,
+        args: &mut PinnedValueBuffer,
+        check_depth: impl FnOnce(usize) -> Result<()>,
+    ) -> Result<Option<Vec<PinnedGcRef<StackFrame>>>> {
+        match self {
+            Function::Continuation(continuation) => continuation
+                .resume(env, args.drain(..), check_depth)
+                .map(Some),
+            Function::Closure(closure) => {
+                let function = closure.function.try_borrow().ok_or_else(|| {
+                    RuntimeError::new_internal_error("Function is not available.")
+                })?;
+                let Function::Continuation(continuation) = &*function else {
+                    return Ok(None);
+                };
+                continuation
+                    .resume(
+                        env,
+                        closure
+                            .captured_values
+                            .iter()
+                            .map(Value::pin)
+                            .chain(args.drain(..)),
+                        check_depth,
+                    )
+                    .map(Some)
+            }
+            Function::Managed(_) | Function::Native(..) => Ok(None),
+        }
+    }
+
+    /// Returns a closure that calls this function with `captured_values`
+    /// before its arguments. Fails if the function declares an arity and
+    /// fewer arguments than the captured values are left to bind.
     pub fn bind_front(
         &self,
         global_env: &GlobalEnv,
         self_ref: &PinnedGcRef<Function>,
         captured_values: &mut PinnedValueBuffer,
-    ) -> PinnedGcRef<Self> {
-        match self {
-            Function::Managed(_) | Function::Native(_) => {
+    ) -> Result<PinnedGcRef<Self>> {
+        if let Some(arity) = self.arity()? {
+            if captured_values.len() > arity as usize {
+                return Err(RuntimeError::TooManyBoundArguments {
+                    function: self.id()?.to_string(),
+                    arity,
+                    bound: captured_values.len(),
+                });
+            }
+        }
+        Ok(match self {
+            Function::Managed(_) | Function::Native(..) | Function::Continuation(_) => {
                 Function::new_closure(global_env, self_ref.clone(), captured_values.drain(..))
             }
             Function::Closure(closure) => Function::new_closure(
@@ -101,6 +239,44 @@ impl Function {
                     .map(Value::pin)
                     .chain(captured_values.drain(..)),
             ),
+        })
+    }
+
+    /// Returns the number of arguments the function has left to bind, if
+    /// its arity was declared. Closures subtract the values they captured.
+    pub fn arity(&self) -> Result<Option<u32>> {
+        match self {
+            Function::Managed(managed) => Ok(managed.arity()),
+            Function::Native(..) | Function::Continuation(_) => Ok(None),
+            Function::Closure(closure) => Ok(closure
+                .function
+                .try_borrow()
+                .ok_or_else(|| RuntimeError::new_internal_error("Function is not available."))?
+                .arity()?
+                .map(|arity| arity.saturating_sub(closure.captured_values.len() as u32))),
+        }
+    }
+
+    /// Returns the call profile of a managed function.
+    pub fn profile(&self) -> Option<FunctionProfile> {
+        match self {
+            Function::Managed(managed) => Some(managed.profile()),
+            Function::Native(..) | Function::Closure(_) | Function::Continuation(_) => None,
+        }
+    }
+
+    /// Returns the id of the function. Closures report the id of the
+    /// function they wrap.
+    pub fn id(&self) -> Result<FunctionId> {
+        match self {
+            Function::Managed(managed) => Ok(managed.function_id()),
+            Function::Native(_, id) => Ok(id.clone()),
+            Function::Continuation(continuation) => Ok(continuation.function_id.clone()),
+            Function::Closure(closure) => closure
+                .function
+                .try_borrow()
+                .ok_or_else(|| RuntimeError::new_internal_error("Function is not available."))?
+                .id(),
         }
     }
 
@@ -109,7 +285,7 @@ impl Function {
         env: &GlobalEnv,
         args: &mut PinnedValueBuffer,
     ) -> Result<PinnedGcRef<StackFrame>> {
-        self.make_stack_frame_inner(env, args, LocalStack::new(env))
+        self.make_stack_frame_inner(env, args, LocalStack::new_frame(env))
     }
 
     fn make_stack_frame_inner(
@@ -120,9 +296,9 @@ impl Function {
     ) -> Result<PinnedGcRef<StackFrame>> {
         match self {
             Function::Managed(managed) => managed.make_stack_frame(env, args, local_stack),
-            Function::Native(native) => native.make_stack_frame(env, args, local_stack),
+            Function::Native(native, _) => native.make_stack_frame(env, args, local_stack),
             Function::Closure(closure) => {
-                local_stack.push_iter(env, closure.captured_values.iter().map(Value::pin));
+                local_stack.push_iter(env, closure.captured_values.iter().map(Value::pin))?;
                 let stack_frame = closure
                     .function
                     .try_borrow()
@@ -130,6 +306,9 @@ impl Function {
                     .make_stack_frame_inner(env, args, local_stack)?;
                 Ok(stack_frame)
             }
+            Function::Continuation(_) => Err(RuntimeError::new_internal_error(
+                "Continuations are resumed, not given a new frame.",
+            )),
         }
     }
 }
@@ -141,8 +320,9 @@ impl GcTraceable for Function {
     {
         match self {
             Function::Managed(managed) => managed.trace(visitor),
-            Function::Native(native) => native.trace(visitor),
+            Function::Native(native, _) => native.trace(visitor),
             Function::Closure(closure) => closure.trace(visitor),
+            Function::Continuation(continuation) => continuation.trace(visitor),
         }
     }
 }
@@ -1,33 +1,151 @@
 //! A managed function, representing code within the Loon runtime to evaluate.
 
-use std::{cell::OnceCell, rc::Rc};
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    rc::Rc,
+};
 
 use crate::{
+    binary::{
+        indexes::ModuleConstIndex,
+        instructions::InstructionList,
+        modules::{validate_replacement_instructions, ModuleId},
+        ValidationError,
+    },
     gc::{GcRef, GcRefVisitor, GcTraceable, PinnedGcRef},
     runtime::{
         constants::ValueTable,
+        error::RuntimeError,
         global_env::GlobalEnv,
         instructions::InstEvalList,
         modules::ModuleGlobals,
+        profile::FunctionProfile,
         stack_frame::{LocalStack, PinnedValueBuffer, StackFrame},
-        Result,
+        FunctionId, Result,
     },
 };
 
+/// Where a managed function was defined.
+pub(crate) struct FunctionOrigin {
+    module_id: Option<ModuleId>,
+    const_index: ModuleConstIndex,
+}
+
+impl FunctionOrigin {
+    pub fn new(module_id: Option<ModuleId>, const_index: ModuleConstIndex) -> Self {
+        FunctionOrigin {
+            module_id,
+            const_index,
+        }
+    }
+
+    pub fn module_id(&self) -> Option<&ModuleId> {
+        self.module_id.as_ref()
+    }
+
+    pub fn const_index(&self) -> ModuleConstIndex {
+        self.const_index
+    }
+
+    pub fn function_id(&self) -> FunctionId {
+        FunctionId::Managed {
+            module_id: self.module_id.clone(),
+            const_index: self.const_index,
+        }
+    }
+}
+
 /// A managed function, representing code within the Loon runtime to evaluate.
 pub(crate) struct ManagedFunction {
     globals: GcRef<ModuleGlobals>,
     constants: OnceCell<GcRef<ValueTable>>,
-    inst_list: Rc<InstEvalList>,
+    inst_list: RefCell<Rc<InstEvalList>>,
+    /// The instructions the function was loaded from, kept so that an
+    /// optimized variant can be produced when the function becomes hot.
+    source: InstructionList,
+    origin: Rc<FunctionOrigin>,
+    /// The number of arguments the function declares, if known.
+    arity: Option<u32>,
+    call_count: Cell<u64>,
 }
 
 impl ManagedFunction {
-    pub fn new_deferred(globals: PinnedGcRef<ModuleGlobals>, inst_list: Rc<InstEvalList>) -> Self {
+    pub fn new_deferred(
+        globals: PinnedGcRef<ModuleGlobals>,
+        source: InstructionList,
+        inst_list: Rc<InstEvalList>,
+        origin: Rc<FunctionOrigin>,
+        arity: Option<u32>,
+    ) -> Self {
         ManagedFunction {
             globals: globals.to_ref(),
             constants: OnceCell::new(),
-            inst_list,
+            inst_list: RefCell::new(inst_list),
+            source,
+            origin,
+            arity,
+            call_count: Cell::new(0),
+        }
+    }
+
+    pub fn function_id(&self) -> FunctionId {
+        self.origin.function_id()
+    }
+
+    pub fn arity(&self) -> Option<u32> {
+        self.arity
+    }
+
+    pub fn profile(&self) -> FunctionProfile {
+        FunctionProfile::new(
+            self.origin.module_id().cloned(),
+            self.origin.const_index(),
+            self.call_count.get(),
+        )
+    }
+
+    /// Counts a call, and swaps in optimized instructions if the call count
+    /// has reached the runtime's tier-up threshold. Optimized instructions
+    /// that would not pass the checks the module was loaded with are
+    /// dropped, and the function keeps its original instructions.
+    fn record_call(&self, env: &GlobalEnv) -> Result<()> {
+        let call_count = self.call_count.get() + 1;
+        self.call_count.set(call_count);
+        if let Some(policy) = env.tier_up_policy() {
+            if call_count == policy.threshold() {
+                if let Some(optimized) = policy.optimizer().optimize(&self.profile(), &self.source)
+                {
+                    if self.validate_optimized(env, &optimized).is_ok() {
+                        *self.inst_list.borrow_mut() = env.resolve_instructions(&optimized)?;
+                    }
+                }
+            }
         }
+        Ok(())
+    }
+
+    /// Checks optimized instructions against the function's constants, its
+    /// module's globals and its module's instruction policy.
+    fn validate_optimized(
+        &self,
+        env: &GlobalEnv,
+        optimized: &InstructionList,
+    ) -> std::result::Result<(), ValidationError> {
+        let policy = self
+            .origin
+            .module_id()
+            .and_then(|module_id| env.instruction_policy(module_id));
+        let num_constants = self
+            .constants
+            .get()
+            .map_or(0, |constants| constants.pin().size());
+        validate_replacement_instructions(
+            self.origin.const_index(),
+            optimized,
+            num_constants,
+            self.globals.pin().size(),
+            policy.as_ref(),
+        )
     }
 
     pub fn make_stack_frame(
@@ -36,18 +154,24 @@ impl ManagedFunction {
         args: &mut PinnedValueBuffer,
         local_stack: PinnedGcRef<LocalStack>,
     ) -> Result<PinnedGcRef<StackFrame>> {
-        local_stack.push_iter(env, args.drain(..));
+        self.record_call(env)?;
+        local_stack
+            .push_iter(env, args.drain(..))
+            .map_err(|error| error.with_frame_function(&self.origin.function_id()))?;
         Ok(StackFrame::new_managed(
             env,
-            self.inst_list.clone(),
-            self.constants().pin(),
+            self.inst_list.borrow().clone(),
+            self.origin.clone(),
+            self.constants()?.pin(),
             self.globals.pin(),
             local_stack,
         ))
     }
 
-    pub fn constants(&self) -> &GcRef<ValueTable> {
-        self.constants.get().expect("Constants not resolved.")
+    pub fn constants(&self) -> Result<&GcRef<ValueTable>> {
+        self.constants
+            .get()
+            .ok_or_else(|| RuntimeError::new_internal_error("Constants not resolved."))
     }
 
     pub fn resolve_constants(&self, constants: PinnedGcRef<ValueTable>) {
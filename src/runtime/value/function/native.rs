@@ -3,12 +3,14 @@
 use std::rc::Rc;
 
 use crate::{
+    binary::{indexes::ModuleConstIndex, modules::ModuleId},
     gc::{GcTraceable, PinnedGcRef},
     runtime::{
         error::Result,
         eval_context::EvalContext,
         global_env::GlobalEnv,
         stack_frame::{LocalStack, PinnedValueBuffer, StackContext, StackFrame},
+        FunctionId, TimerId,
     },
 };
 
@@ -40,8 +42,8 @@ impl CallWithContinuation {
 }
 
 pub(crate) struct YieldCall {
-    /// The function that will be called with the continuation as an argument.
-    pub function: PinnedGcRef<Function>,
+    /// The function that replaces the yielding one when the host resumes.
+    pub continuation: NativeFunctionPtr,
 }
 
 pub struct NativeFunctionResult(pub(crate) NativeFunctionResultInner);
@@ -60,37 +62,150 @@ pub enum NativeFunctionResultInner {
     /// receive the return values of the provided function as arguments.
     CallWithContinuation(CallWithContinuation),
 
-    /// Yield to the closest enclosing continuation scope, or the top-level
-    /// if that does not exist.
+    /// Yield to the host, which resumes the frame by calling the continuation
+    /// with the same stack.
     YieldCall(YieldCall),
 }
 
+/// Identifies the managed function, and the instruction within it, that
+/// called a native function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CallerInfo {
+    module_id: Option<ModuleId>,
+    const_index: ModuleConstIndex,
+    pc: usize,
+}
+
+impl CallerInfo {
+    pub(crate) fn new(
+        module_id: Option<ModuleId>,
+        const_index: ModuleConstIndex,
+        pc: usize,
+    ) -> Self {
+        CallerInfo {
+            module_id,
+            const_index,
+            pc,
+        }
+    }
+
+    /// The module that defines the calling function, if it was loaded from a
+    /// module.
+    #[must_use]
+    pub fn module_id(&self) -> Option<&ModuleId> {
+        self.module_id.as_ref()
+    }
+
+    /// The index of the calling function in its module's constant table.
+    #[must_use]
+    pub fn const_index(&self) -> ModuleConstIndex {
+        self.const_index
+    }
+
+    /// The stable id of the calling function.
+    #[must_use]
+    pub fn function_id(&self) -> FunctionId {
+        FunctionId::Managed {
+            module_id: self.module_id.clone(),
+            const_index: self.const_index,
+        }
+    }
+
+    /// The index of the call instruction within the calling function.
+    #[must_use]
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+}
+
+/// Information about the call that created a native frame.
+pub(crate) struct NativeCallInfo {
+    pub depth: usize,
+    pub caller: Option<CallerInfo>,
+}
+
 pub struct NativeFunctionContext<'a> {
     global_context: &'a GlobalEnv,
     local_stack: &'a PinnedGcRef<LocalStack>,
+    call_info: NativeCallInfo,
 }
 
 impl<'a> NativeFunctionContext<'a> {
     pub(crate) fn new(
         global_context: &'a GlobalEnv,
         local_stack: &'a PinnedGcRef<LocalStack>,
+        call_info: NativeCallInfo,
     ) -> Self {
         NativeFunctionContext {
             global_context,
             local_stack,
+            call_info,
         }
     }
 
+    pub(crate) fn env(&self) -> &'a GlobalEnv {
+        self.global_context
+    }
+
+    /// Returns the managed function that called this function, or `None` if
+    /// it was called by the host or by another native function.
+    #[must_use]
+    pub fn caller(&self) -> Option<&CallerInfo> {
+        self.call_info.caller.as_ref()
+    }
+
+    /// Returns the number of frames below this one on the call stack,
+    /// including frames of enclosing calls made through
+    /// [`NativeFunctionContext::call`]. A function called directly by the host
+    /// has depth 0.
+    #[must_use]
+    pub fn call_depth(&self) -> usize {
+        self.call_info.depth
+    }
+
+    /// Returns the host data of type `T` set with
+    /// [`Runtime::set_host_data`](crate::runtime::Runtime::set_host_data).
+    #[must_use]
+    pub fn host_data<T: 'static>(&self) -> Option<Rc<T>> {
+        self.global_context.host_data()
+    }
+
     pub fn stack(&mut self) -> StackContext {
         StackContext::new(self.global_context, self.local_stack.clone())
     }
 
     pub fn call(&mut self, num_args: u32) -> Result<u32> {
         let function = self.local_stack.pop()?.as_function()?.clone();
-        let mut eval_context = EvalContext::new(self.global_context, self.local_stack);
+        let mut eval_context = EvalContext::new(
+            self.global_context,
+            self.local_stack,
+            self.call_info.depth + 1,
+        );
         eval_context.run(&function, num_args)
     }
 
+    /// Schedules the function on top of the stack to be called with no
+    /// arguments once `delay` more ticks of the running top level's clock
+    /// have passed. The function is popped.
+    ///
+    /// See [`TopLevelRuntime::schedule_callback`](crate::runtime::TopLevelRuntime::schedule_callback).
+    pub fn schedule_callback(&mut self, delay: u64) -> Result<TimerId> {
+        let timers = self.global_context.active_timers()?;
+        let callback = self.local_stack.pop()?;
+        timers.schedule(delay, callback)
+    }
+
+    /// Cancels a callback scheduled on the running top level, returning true
+    /// if it had not yet run.
+    pub fn cancel_callback(&mut self, id: TimerId) -> Result<bool> {
+        Ok(self.global_context.active_timers()?.cancel(id))
+    }
+
+    /// Returns the current tick of the running top level's clock.
+    pub fn current_tick(&self) -> Result<u64> {
+        Ok(self.global_context.active_timers()?.now())
+    }
+
     pub fn return_with(self, num_args: u32) -> NativeFunctionResult {
         NativeFunctionResult(NativeFunctionResultInner::ReturnValue(num_args))
     }
@@ -102,17 +217,49 @@ impl<'a> NativeFunctionContext<'a> {
         )))
     }
 
-    pub fn call_with_continuation(
+    /// Suspends evaluation, to be resumed later by calling `continuation` in
+    /// place of this function.
+    ///
+    /// In calls run with
+    /// [`TopLevelRuntime::run_steps`](crate::runtime::TopLevelRuntime::run_steps),
+    /// the host sees [`StepOutcome::Yielded`](crate::runtime::StepOutcome::Yielded),
+    /// and resuming calls `continuation` with the values this function left
+    /// on its stack.
+    ///
+    /// Elsewhere, the innermost call made by the host or by
+    /// [`NativeFunctionContext::call`] returns early. Its return values are a
+    /// continuation function, followed by the values this function left on
+    /// its stack. Calling the continuation, from managed code or the host,
+    /// resumes the suspended frames: `continuation` is called with the
+    /// continuation's arguments, and the values it returns are those of the
+    /// original call. A continuation can only be called once.
+    pub fn yield_to_host<F>(self, continuation: F) -> NativeFunctionResult
+    where
+        F: Fn(NativeFunctionContext) -> Result<NativeFunctionResult> + 'static,
+    {
+        NativeFunctionResult(NativeFunctionResultInner::YieldCall(YieldCall {
+            continuation: NativeFunctionPtr::new(continuation),
+        }))
+    }
+
+    /// Calls the function on top of the stack with the `num_args` values
+    /// below it, like [`Self::call`], but without nesting the call on the
+    /// Rust stack. `continuation` is then called in place of this function,
+    /// with the return values pushed onto this function's stack.
+    pub fn call_with_continuation<F>(
         self,
         num_args: u32,
-        continuation: NativeFunctionPtr,
-    ) -> Result<NativeFunctionResult> {
+        continuation: F,
+    ) -> Result<NativeFunctionResult>
+    where
+        F: Fn(NativeFunctionContext) -> Result<NativeFunctionResult> + 'static,
+    {
         let function = self.local_stack.pop()?.as_function()?.clone();
         Ok(NativeFunctionResult(
             NativeFunctionResultInner::CallWithContinuation(CallWithContinuation {
                 function,
                 num_args,
-                continuation,
+                continuation: NativeFunctionPtr::new(continuation),
             }),
         ))
     }
@@ -152,7 +299,7 @@ impl NativeFunctionPtr {
         args: &mut PinnedValueBuffer,
         local_stack: PinnedGcRef<LocalStack>,
     ) -> Result<PinnedGcRef<StackFrame>> {
-        local_stack.push_iter(env, args.drain(..));
+        local_stack.push_iter(env, args.drain(..))?;
         Ok(StackFrame::new_native(env, self.clone(), local_stack))
     }
 }
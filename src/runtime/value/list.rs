@@ -17,13 +17,13 @@ pub struct List {
 }
 
 impl List {
-    pub fn new(env: &GlobalEnv) -> PinnedGcRef<Self> {
+    pub(crate) fn new(env: &GlobalEnv) -> PinnedGcRef<Self> {
         env.create_pinned_ref(List {
             items: RefCell::new(Vec::new()),
         })
     }
 
-    pub fn from_iter(
+    pub(crate) fn from_iter(
         env: &GlobalEnv,
         iter: impl IntoIterator<Item = PinnedValue>,
     ) -> PinnedGcRef<Self> {
@@ -34,6 +34,14 @@ impl List {
         })
     }
 
+    /// Creates a list that holds `items`, which must not refer to objects
+    /// that may be collected before the list is reachable, such as scalars.
+    pub(crate) fn from_values(env: &GlobalEnv, items: Vec<Value>) -> PinnedGcRef<Self> {
+        env.create_pinned_ref(List {
+            items: RefCell::new(items),
+        })
+    }
+
     pub fn len(&self) -> usize {
         self.items.borrow().len()
     }
@@ -52,10 +60,9 @@ impl List {
 
     pub fn set(&self, index: u32, value: PinnedValue) -> Result<()> {
         let mut items = self.items.borrow_mut();
-        *items
-            .get_mut(index as usize)
-            .ok_or_else(|| RuntimeError::new_internal_error("Index out of bounds."))? =
-            value.to_value();
+        *items.get_mut(index as usize).ok_or_else(|| {
+            RuntimeError::new_operation_precondition_error("List index out of range.")
+        })? = value.to_value();
         Ok(())
     }
 }
@@ -0,0 +1,137 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use crate::{
+    gc::{GcRefVisitor, GcTraceable, PinnedGcRef},
+    pure_values::{Integer, Rational},
+    runtime::{global_env::GlobalEnv, value::Value},
+    util::imm_string::{ImmBytes, ImmString},
+};
+
+use super::core::PinnedValue;
+
+/// A key into a [`Map`].
+///
+/// Scalar values are compared by value, and reference values are compared by
+/// identity, matching the semantics of `CompareOp::RefEq`. Floats are compared
+/// by their bit pattern, so `NaN` keys can be found again.
+#[derive(Clone)]
+pub(crate) enum MapKey {
+    Null,
+    Bool(bool),
+    Integer(Integer),
+    Float(u64),
+    Rational(Rational),
+    String(ImmString),
+    Bytes(ImmBytes),
+    /// A reference value, with its identity. The value is kept so that it is
+    /// traced, which also keeps the identity from being reused.
+    Ref(usize, Value),
+    /// A composite key made of several values.
+    Tuple(Vec<MapKey>),
+}
+
+impl MapKey {
+    pub fn from_value(value: &PinnedValue) -> Self {
+        value.to_map_key()
+    }
+
+    pub fn from_values<'a>(values: impl IntoIterator<Item = &'a PinnedValue>) -> Self {
+        MapKey::Tuple(values.into_iter().map(MapKey::from_value).collect())
+    }
+}
+
+impl PartialEq for MapKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (MapKey::Null, MapKey::Null) => true,
+            (MapKey::Bool(b1), MapKey::Bool(b2)) => b1 == b2,
+            (MapKey::Integer(i1), MapKey::Integer(i2)) => i1 == i2,
+            (MapKey::Float(f1), MapKey::Float(f2)) => f1 == f2,
+            (MapKey::Rational(r1), MapKey::Rational(r2)) => r1 == r2,
+            (MapKey::String(s1), MapKey::String(s2)) => s1 == s2,
+            (MapKey::Bytes(b1), MapKey::Bytes(b2)) => b1 == b2,
+            (MapKey::Ref(r1, _), MapKey::Ref(r2, _)) => r1 == r2,
+            (MapKey::Tuple(t1), MapKey::Tuple(t2)) => t1 == t2,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for MapKey {}
+
+impl std::hash::Hash for MapKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            MapKey::Null => {}
+            MapKey::Bool(b) => b.hash(state),
+            MapKey::Integer(i) => i.hash(state),
+            MapKey::Float(f) => f.hash(state),
+            MapKey::Rational(r) => r.hash(state),
+            MapKey::String(s) => s.hash(state),
+            MapKey::Bytes(b) => b.hash(state),
+            MapKey::Ref(r, _) => r.hash(state),
+            MapKey::Tuple(t) => t.hash(state),
+        }
+    }
+}
+
+impl GcTraceable for MapKey {
+    fn trace<V>(&self, visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        match self {
+            MapKey::Ref(_, value) => value.trace(visitor),
+            MapKey::Tuple(keys) => {
+                for key in keys {
+                    key.trace(visitor);
+                }
+            }
+            MapKey::Null
+            | MapKey::Bool(_)
+            | MapKey::Integer(_)
+            | MapKey::Float(_)
+            | MapKey::Rational(_)
+            | MapKey::String(_)
+            | MapKey::Bytes(_) => {}
+        }
+    }
+}
+
+/// A mutable hash map from keys to values.
+pub struct Map {
+    items: RefCell<HashMap<MapKey, Value>>,
+}
+
+impl Map {
+    pub(crate) fn new(env: &GlobalEnv) -> PinnedGcRef<Self> {
+        env.create_pinned_ref(Map {
+            items: RefCell::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) fn get(&self, key: &MapKey) -> Option<PinnedValue> {
+        self.items.borrow().get(key).map(Value::pin)
+    }
+
+    pub(crate) fn insert(&self, key: MapKey, value: PinnedValue) {
+        self.items.borrow_mut().insert(key, value.to_value());
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.borrow().len()
+    }
+}
+
+impl GcTraceable for Map {
+    fn trace<V>(&self, visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        for (key, value) in self.items.borrow().iter() {
+            key.trace(visitor);
+            value.trace(visitor);
+        }
+    }
+}
@@ -1,10 +1,25 @@
+mod cell;
 mod core;
+mod error;
 mod function;
 mod list;
-pub use self::function::native::NativeFunctionResult;
-pub(crate) use core::{PinnedValue, Value};
+mod map;
+mod record;
+mod tag;
+mod weak;
+pub use self::function::native::{CallerInfo, NativeFunctionResult};
+pub(crate) use cell::Cell;
+pub use core::PinnedValue;
+#[cfg(feature = "soa-local-stack")]
+pub(crate) use core::ScalarKind;
+pub(crate) use core::Value;
+pub(crate) use error::ErrorValue;
 pub(crate) use function::native::{
-    NativeFunctionContext, NativeFunctionPtr, NativeFunctionResultInner,
+    NativeCallInfo, NativeFunctionContext, NativeFunctionPtr, NativeFunctionResultInner,
 };
-pub(crate) use function::Function;
+pub(crate) use function::{managed::FunctionOrigin, Function};
 pub(crate) use list::List;
+pub(crate) use map::{Map, MapKey};
+pub(crate) use record::{Record, RecordShape};
+pub(crate) use tag::{Tag, Tagged};
+pub(crate) use weak::WeakRef;
@@ -0,0 +1,106 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    gc::{GcRefVisitor, GcTraceable, PinnedGcRef},
+    runtime::{
+        error::{Result, RuntimeError},
+        global_env::GlobalEnv,
+        value::Value,
+    },
+    util::imm_string::ImmString,
+};
+
+use super::core::PinnedValue;
+
+/// The field names of a record, in order.
+///
+/// Shapes are interned by [`GlobalEnv::record_shape`], so records with the
+/// same fields share one table of names.
+pub(crate) struct RecordShape {
+    fields: Vec<ImmString>,
+}
+
+impl RecordShape {
+    /// Creates a shape, failing if a field is named more than once.
+    pub fn new(fields: Vec<ImmString>) -> Result<Self> {
+        for (i, name) in fields.iter().enumerate() {
+            if fields[..i].contains(name) {
+                return Err(RuntimeError::new_operation_precondition_error(format!(
+                    "Record field {:?} is given more than once.",
+                    name.as_str()
+                )));
+            }
+        }
+        Ok(RecordShape { fields })
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    fn field_index(&self, name: &str) -> Result<usize> {
+        self.fields
+            .iter()
+            .position(|field| field.as_str() == name)
+            .ok_or_else(|| {
+                RuntimeError::new_operation_precondition_error(format!(
+                    "Record has no field {name:?}."
+                ))
+            })
+    }
+}
+
+/// A value with a fixed set of named fields.
+pub struct Record {
+    shape: Rc<RecordShape>,
+    values: RefCell<Vec<Value>>,
+}
+
+impl Record {
+    /// Creates a record with one value for each field of `shape`, in order.
+    pub(crate) fn new(
+        env: &GlobalEnv,
+        shape: Rc<RecordShape>,
+        values: impl IntoIterator<Item = PinnedValue>,
+    ) -> PinnedGcRef<Self> {
+        env.with_lock(|lock| {
+            let values = values
+                .into_iter()
+                .map(|v| v.into_value(lock))
+                .collect::<Vec<_>>();
+            debug_assert_eq!(values.len(), shape.len());
+            env.create_pinned_ref(Record {
+                shape,
+                values: RefCell::new(values),
+            })
+        })
+    }
+
+    pub fn get(&self, field: &str) -> Result<PinnedValue> {
+        let index = self.shape.field_index(field)?;
+        Ok(self.values.borrow()[index].pin())
+    }
+
+    pub fn set(&self, field: &str, value: PinnedValue) -> Result<()> {
+        let index = self.shape.field_index(field)?;
+        self.set_at(index, value);
+        Ok(())
+    }
+
+    /// Sets the value of the field at `index` in the record's shape.
+    pub(crate) fn set_at(&self, index: usize, value: PinnedValue) {
+        self.values.borrow_mut()[index] = value.to_value();
+    }
+}
+
+impl GcTraceable for Record {
+    fn trace<V>(&self, visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        let values = self.values.borrow();
+        for value in &values[..] {
+            value.trace(visitor);
+        }
+    }
+}
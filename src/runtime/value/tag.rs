@@ -0,0 +1,82 @@
+use crate::{
+    gc::{GcRef, GcRefVisitor, GcTraceable, PinnedGcRef},
+    runtime::{global_env::GlobalEnv, value::Value},
+    util::imm_string::ImmString,
+};
+
+use super::core::PinnedValue;
+
+/// A unique marker for the values of a user-defined type.
+///
+/// Tags are compared by identity, so two tags with the same name are still
+/// distinct. The name is only used when displaying values.
+pub struct Tag {
+    name: ImmString,
+}
+
+impl Tag {
+    pub(crate) fn new(env: &GlobalEnv, name: ImmString) -> PinnedGcRef<Self> {
+        env.create_pinned_ref(Tag { name })
+    }
+
+    pub fn name(&self) -> &ImmString {
+        &self.name
+    }
+}
+
+impl GcTraceable for Tag {
+    fn trace<V>(&self, _visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        // A tag holds no references.
+    }
+}
+
+/// A value wrapped with a [`Tag`].
+///
+/// Tagged values are immutable. The wrapped value can only be taken out by
+/// code that holds the tag.
+pub struct Tagged {
+    tag: GcRef<Tag>,
+    value: Value,
+}
+
+impl Tagged {
+    pub(crate) fn new(
+        env: &GlobalEnv,
+        tag: PinnedGcRef<Tag>,
+        value: PinnedValue,
+    ) -> PinnedGcRef<Self> {
+        env.with_lock(|lock| {
+            env.create_pinned_ref(Tagged {
+                tag: tag.into_ref(lock.guard()),
+                value: value.into_value(lock),
+            })
+        })
+    }
+
+    pub fn tag(&self) -> PinnedGcRef<Tag> {
+        self.tag.pin()
+    }
+
+    /// Returns the wrapped value, whatever its tag, e.g. to display it.
+    pub(crate) fn value(&self) -> PinnedValue {
+        self.value.pin()
+    }
+
+    /// Returns the wrapped value if this is tagged with `tag`.
+    pub fn untag(&self, tag: &PinnedGcRef<Tag>) -> Option<PinnedValue> {
+        PinnedGcRef::ref_eq(&self.tag.pin(), tag).then(|| self.value.pin())
+    }
+}
+
+impl GcTraceable for Tagged {
+    fn trace<V>(&self, visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        self.tag.trace(visitor);
+        self.value.trace(visitor);
+    }
+}
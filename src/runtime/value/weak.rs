@@ -0,0 +1,75 @@
+use crate::{
+    gc::{GcRefVisitor, GcTraceable, PinnedGcRef, WeakGcRef},
+    runtime::{global_env::GlobalEnv, RuntimeError, ValueKind},
+};
+
+use super::{core::PinnedValue, Cell, Function, List, Map, Record, Tag, Tagged};
+
+enum WeakTarget {
+    List(WeakGcRef<List>),
+    Function(WeakGcRef<Function>),
+    Map(WeakGcRef<Map>),
+    Cell(WeakGcRef<Cell>),
+    WeakRef(WeakGcRef<WeakRef>),
+    Record(WeakGcRef<Record>),
+    Tag(WeakGcRef<Tag>),
+    Tagged(WeakGcRef<Tagged>),
+}
+
+/// A reference to a heap value that does not keep the value alive.
+///
+/// Once the value is collected, the reference reads as null. Scalars,
+/// strings and byte strings are never collected, so they cannot be weakly
+/// referenced.
+pub struct WeakRef {
+    target: WeakTarget,
+}
+
+impl WeakRef {
+    pub(crate) fn new(
+        env: &GlobalEnv,
+        value: &PinnedValue,
+    ) -> Result<PinnedGcRef<Self>, RuntimeError> {
+        let target = match value.kind() {
+            ValueKind::List => WeakTarget::List(value.as_list()?.downgrade()),
+            ValueKind::Function => WeakTarget::Function(value.as_function()?.downgrade()),
+            ValueKind::Map => WeakTarget::Map(value.as_map()?.downgrade()),
+            ValueKind::Cell => WeakTarget::Cell(value.as_cell()?.downgrade()),
+            ValueKind::WeakRef => WeakTarget::WeakRef(value.as_weak_ref()?.downgrade()),
+            ValueKind::Record => WeakTarget::Record(value.as_record()?.downgrade()),
+            ValueKind::Tag => WeakTarget::Tag(value.as_tag()?.downgrade()),
+            ValueKind::Tagged => WeakTarget::Tagged(value.as_tagged()?.downgrade()),
+            _ => {
+                return Err(RuntimeError::new_type_error(
+                    "Only lists, functions, maps, cells, weak references, records, tags and \
+                     tagged values can be weakly referenced.",
+                ))
+            }
+        };
+        Ok(env.create_pinned_ref(WeakRef { target }))
+    }
+
+    /// Returns the referenced value, or null if it has been collected.
+    pub fn get(&self) -> PinnedValue {
+        let value = match &self.target {
+            WeakTarget::List(l) => l.upgrade().map(PinnedValue::new_list),
+            WeakTarget::Function(f) => f.upgrade().map(PinnedValue::new_function),
+            WeakTarget::Map(m) => m.upgrade().map(PinnedValue::new_map),
+            WeakTarget::Cell(c) => c.upgrade().map(PinnedValue::new_cell),
+            WeakTarget::WeakRef(w) => w.upgrade().map(PinnedValue::new_weak_ref),
+            WeakTarget::Record(r) => r.upgrade().map(PinnedValue::new_record),
+            WeakTarget::Tag(t) => t.upgrade().map(PinnedValue::new_tag),
+            WeakTarget::Tagged(t) => t.upgrade().map(PinnedValue::new_tagged),
+        };
+        value.unwrap_or_else(PinnedValue::new_null)
+    }
+}
+
+impl GcTraceable for WeakRef {
+    fn trace<V>(&self, _visitor: &mut V)
+    where
+        V: GcRefVisitor,
+    {
+        // The target is deliberately not traced.
+    }
+}
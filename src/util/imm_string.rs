@@ -1,12 +1,28 @@
+//! Immutable, reference counted strings and byte strings.
+//!
+//! By default the contents are stored in a single allocation behind their
+//! reference count. With the `forbid-unsafe` feature they are stored in
+//! `Arc`s instead, which costs an extra indirection but needs no unsafe code.
+
+#[cfg(feature = "forbid-unsafe")]
+use std::sync::Arc;
+#[cfg(not(feature = "forbid-unsafe"))]
 use std::{alloc::Layout, mem::MaybeUninit, sync::atomic::AtomicUsize};
 
+#[cfg(not(feature = "forbid-unsafe"))]
 struct StringHeader {
     ref_count: AtomicUsize,
     len: usize,
 }
 
+#[cfg(not(feature = "forbid-unsafe"))]
 pub struct ImmBytes(RawData);
 
+#[cfg(feature = "forbid-unsafe")]
+#[derive(Clone)]
+pub struct ImmBytes(Arc<[u8]>);
+
+#[cfg(not(feature = "forbid-unsafe"))]
 impl ImmBytes {
     pub fn from_bytes<I>(iter: I) -> Self
     where
@@ -22,6 +38,29 @@ impl ImmBytes {
     pub fn as_bytes(&self) -> &[u8] {
         self.0.data()
     }
+
+    /// Returns true if no other copy of this byte string exists.
+    pub fn is_unique(&self) -> bool {
+        self.0
+            .header()
+            .ref_count
+            .load(std::sync::atomic::Ordering::Acquire)
+            == 1
+    }
+}
+
+#[cfg(feature = "forbid-unsafe")]
+impl ImmBytes {
+    pub fn from_bytes<I>(iter: I) -> Self
+    where
+        I: ExactSizeIterator<Item = u8>,
+    {
+        Self(iter.collect())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 impl std::fmt::Debug for ImmBytes {
@@ -38,6 +77,7 @@ impl std::ops::Deref for ImmBytes {
     }
 }
 
+#[cfg(not(feature = "forbid-unsafe"))]
 impl Clone for ImmBytes {
     fn clone(&self) -> Self {
         self.0
@@ -48,6 +88,7 @@ impl Clone for ImmBytes {
     }
 }
 
+#[cfg(not(feature = "forbid-unsafe"))]
 impl Drop for ImmBytes {
     fn drop(&mut self) {
         let header = self.0.header();
@@ -64,7 +105,7 @@ impl Drop for ImmBytes {
 
 impl PartialEq for ImmBytes {
     fn eq(&self, other: &Self) -> bool {
-        self.as_bytes() == other.as_bytes()
+        std::ptr::eq(self.as_bytes(), other.as_bytes()) || self.as_bytes() == other.as_bytes()
     }
 }
 
@@ -82,15 +123,33 @@ impl Ord for ImmBytes {
     }
 }
 
+impl From<&[u8]> for ImmBytes {
+    fn from(bytes: &[u8]) -> Self {
+        Self::from_bytes(bytes.iter().copied())
+    }
+}
+
+impl From<Vec<u8>> for ImmBytes {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self::from(&bytes[..])
+    }
+}
+
 impl std::hash::Hash for ImmBytes {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.as_bytes().hash(state);
     }
 }
 
-#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg(not(feature = "forbid-unsafe"))]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ImmString(ImmBytes);
 
+#[cfg(feature = "forbid-unsafe")]
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ImmString(Arc<str>);
+
+#[cfg(not(feature = "forbid-unsafe"))]
 impl ImmString {
     pub fn from_str(s: &str) -> Self {
         // Safety: The type of the input validates it as a valid string.
@@ -119,6 +178,43 @@ impl ImmString {
         // Safety: The data was validated during construction.
         unsafe { std::str::from_utf8_unchecked(&self.0[..]) }
     }
+
+    /// Returns true if no other copy of this string exists.
+    pub fn is_unique(&self) -> bool {
+        self.0.is_unique()
+    }
+}
+
+#[cfg(feature = "forbid-unsafe")]
+impl ImmString {
+    pub fn from_str(s: &str) -> Self {
+        Self(Arc::from(s))
+    }
+
+    pub fn try_from_bytes<I>(iter: I) -> Result<Self, std::str::Utf8Error>
+    where
+        I: ExactSizeIterator<Item = u8>,
+    {
+        let string = String::from_utf8(iter.collect()).map_err(|e| e.utf8_error())?;
+        Ok(Self(Arc::from(string)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns true if no other copy of this string exists.
+    pub fn is_unique(&self) -> bool {
+        Arc::strong_count(&self.0) == 1
+    }
+}
+
+// Hashes as a `str`, so that lookups through `Borrow<str>` find the same
+// entries.
+impl std::hash::Hash for ImmString {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
 }
 
 impl std::fmt::Debug for ImmString {
@@ -153,9 +249,11 @@ impl std::borrow::Borrow<str> for ImmString {
     }
 }
 
+#[cfg(not(feature = "forbid-unsafe"))]
 #[derive(Copy, Clone)]
 struct RawData(*const u8);
 
+#[cfg(not(feature = "forbid-unsafe"))]
 fn make_data_layout(len: usize) -> (Layout, usize) {
     const HEADER_LAYOUT: Layout = Layout::new::<StringHeader>();
     let data_layout = Layout::array::<u8>(len).expect("Failed to create layout for data.");
@@ -168,6 +266,7 @@ fn make_data_layout(len: usize) -> (Layout, usize) {
     (layout.pad_to_align(), offset)
 }
 
+#[cfg(not(feature = "forbid-unsafe"))]
 impl RawData {
     pub fn from_bytes<I>(data: I) -> Self
     where
@@ -1,7 +1,20 @@
-use std::{borrow::Borrow, hash::Hash};
+use std::{borrow::Borrow, cell::RefCell, hash::Hash, rc::Rc};
+
+/// Counts of the work done by an intern set.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct InternStats {
+    /// The number of distinct values in the set.
+    pub unique: usize,
+    /// The number of values interned, including repeats.
+    pub lookups: u64,
+    /// The number of lookups that found an existing value.
+    pub hits: u64,
+}
 
 pub struct InternSet<T> {
     map: std::collections::HashSet<T>,
+    lookups: u64,
+    hits: u64,
 }
 
 impl<T> InternSet<T>
@@ -11,6 +24,8 @@ where
     pub fn new() -> Self {
         InternSet {
             map: std::collections::HashSet::new(),
+            lookups: 0,
+            hits: 0,
         }
     }
 
@@ -20,7 +35,9 @@ where
         V: Hash + Eq + ?Sized,
         for<'a> &'a V: Into<T>,
     {
+        self.lookups += 1;
         if let Some(interned) = self.map.get(value) {
+            self.hits += 1;
             interned.clone()
         } else {
             let value: T = value.into();
@@ -28,4 +45,68 @@ where
             value
         }
     }
+
+    pub fn stats(&self) -> InternStats {
+        InternStats {
+            unique: self.map.len(),
+            lookups: self.lookups,
+            hits: self.hits,
+        }
+    }
+}
+
+/// An intern set shared by several builders, e.g. all of the functions of a
+/// module.
+pub struct SharedInternSet<T>(Rc<RefCell<InternSet<T>>>);
+
+impl<T> SharedInternSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    pub fn new() -> Self {
+        SharedInternSet(Rc::new(RefCell::new(InternSet::new())))
+    }
+
+    pub fn intern<V>(&self, value: &V) -> T
+    where
+        T: Borrow<V>,
+        V: Hash + Eq + ?Sized,
+        for<'a> &'a V: Into<T>,
+    {
+        self.0.borrow_mut().intern(value)
+    }
+
+    pub fn stats(&self) -> InternStats {
+        RefCell::borrow(&self.0).stats()
+    }
+}
+
+impl<T> Clone for SharedInternSet<T> {
+    fn clone(&self) -> Self {
+        SharedInternSet(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::imm_string::ImmString;
+
+    #[test]
+    fn shared_sets_count_hits_across_clones() {
+        let set = SharedInternSet::<ImmString>::new();
+        let other = set.clone();
+        let a = set.intern("loop");
+        let b = other.intern("loop");
+        other.intern("end");
+        assert_eq!(a, b);
+        assert_eq!(
+            set.stats(),
+            InternStats {
+                unique: 2,
+                lookups: 3,
+                hits: 1,
+            }
+        );
+    }
 }
@@ -1,2 +1,4 @@
 pub mod imm_string;
 pub mod intern;
+#[cfg(feature = "runtime")]
+pub mod suggest;
@@ -0,0 +1,537 @@
+//! A frontend for a small subset of the WebAssembly text format.
+//!
+//! A module may contain functions, function imports and function exports.
+//! Function bodies are straight-line code in flat or folded form, using
+//! `local.get`, `local.set`, `local.tee`, `call`, `drop`, `return`, `nop`,
+//! and the `const`, `add`, `sub` and `mul` instructions of `i32`, `i64`,
+//! `f32` and `f64`, along with `f32.div` and `f64.div`.
+//!
+//! Integer types become Loon integers, which do not wrap on overflow, and
+//! float types become Loon floats. Parameters and locals are the bottom slots
+//! of the function's stack, so operands are pushed above them.
+
+use std::{cell::Cell, collections::HashMap};
+
+use crate::binary::{
+    error::BuilderError,
+    instructions::{CallInstruction, StackIndex},
+    modules::{ImportSource, ModuleId, ModuleMemberId},
+    ConstModule, DeferredValue, FunctionBuilder, ModuleBuilder, ValueRef,
+};
+
+#[non_exhaustive]
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Lexpr(#[from] lexpr::parse::Error),
+
+    #[error(transparent)]
+    Builder(#[from] BuilderError),
+
+    #[error("Malformed {0}")]
+    Malformed(&'static str),
+
+    #[error("Unsupported construct: {0}")]
+    Unsupported(String),
+
+    #[error("Unknown {0}: {1}")]
+    UnknownName(&'static str, String),
+
+    #[error("Name defined more than once: {0}")]
+    DuplicateName(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ValType {
+    Int,
+    Float,
+}
+
+impl ValType {
+    fn parse(expr: &lexpr::Value) -> Result<Self> {
+        match expr.as_symbol() {
+            Some("i32" | "i64") => Ok(ValType::Int),
+            Some("f32" | "f64") => Ok(ValType::Float),
+            Some(other) => Err(Error::Unsupported(other.to_string())),
+            None => Err(Error::Malformed("value type")),
+        }
+    }
+}
+
+/// Splits a list into its head symbol and the rest of its items.
+fn split_head(expr: &lexpr::Value) -> Option<(&str, Vec<&lexpr::Value>)> {
+    let mut items = expr.list_iter()?;
+    let head = items.next()?.as_symbol()?;
+    Some((head, items.collect()))
+}
+
+/// Converts the position of a local or a number of locals or results.
+fn local_count(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| BuilderError::IndexOverflow.into())
+}
+
+/// Returns the name of an identifier such as `$x`, if `expr` is one.
+fn identifier(expr: &lexpr::Value) -> Option<&str> {
+    expr.as_symbol().filter(|name| name.starts_with('$'))
+}
+
+/// The signature and local names of a function, read from the fields that
+/// precede its body.
+#[derive(Default)]
+struct FuncHeader<'a> {
+    name: Option<&'a str>,
+    exports: Vec<&'a str>,
+    /// The names of the parameters, then the locals, in slot order.
+    local_names: Vec<Option<&'a str>>,
+    local_types: Vec<ValType>,
+    num_params: u32,
+    num_results: u32,
+    body: Vec<&'a lexpr::Value>,
+}
+
+impl<'a> FuncHeader<'a> {
+    /// Parses the items of a `func` field, after the `func` symbol. Only
+    /// definitions have locals and a body.
+    fn parse(items: &[&'a lexpr::Value], is_definition: bool) -> Result<Self> {
+        let mut header = FuncHeader::default();
+        let mut items = items.iter().copied().peekable();
+        if let Some(name) = items.peek().copied().and_then(identifier) {
+            header.name = Some(name);
+            items.next();
+        }
+        while let Some(item) = items.peek().copied() {
+            let Some((head, rest)) = split_head(item) else {
+                break;
+            };
+            match head {
+                "export" if is_definition => {
+                    let [name] = rest[..] else {
+                        return Err(Error::Malformed("export"));
+                    };
+                    header
+                        .exports
+                        .push(name.as_str().ok_or(Error::Malformed("export"))?);
+                }
+                "param" | "local" => {
+                    if head == "param"
+                        && local_count(header.local_types.len())? != header.num_params
+                    {
+                        return Err(Error::Malformed("param after local"));
+                    }
+                    if head == "local" && !is_definition {
+                        return Err(Error::Malformed("import"));
+                    }
+                    match rest[..] {
+                        [name, ty] if identifier(name).is_some() => {
+                            header.local_names.push(identifier(name));
+                            header.local_types.push(ValType::parse(ty)?);
+                        }
+                        _ => {
+                            for ty in rest {
+                                header.local_names.push(None);
+                                header.local_types.push(ValType::parse(ty)?);
+                            }
+                        }
+                    }
+                    if head == "param" {
+                        header.num_params = local_count(header.local_types.len())?;
+                    }
+                }
+                "result" => {
+                    for ty in &rest {
+                        ValType::parse(ty)?;
+                    }
+                    header.num_results = header
+                        .num_results
+                        .checked_add(local_count(rest.len())?)
+                        .ok_or(BuilderError::IndexOverflow)?;
+                }
+                _ => break,
+            }
+            items.next();
+        }
+        header.body = items.collect();
+        if !is_definition && !header.body.is_empty() {
+            return Err(Error::Malformed("import"));
+        }
+        Ok(header)
+    }
+}
+
+struct Func {
+    value: ValueRef,
+    num_params: u32,
+    num_results: u32,
+    /// The function to build, for functions defined in the module.
+    definition: Cell<Option<DeferredValue>>,
+}
+
+/// Finds the function named by a `$name` or an index.
+fn func_index(
+    funcs: &[&Func],
+    func_names: &HashMap<&str, usize>,
+    expr: &lexpr::Value,
+) -> Result<usize> {
+    match identifier(expr) {
+        Some(name) => func_names
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::UnknownName("function", name.to_string())),
+        None => expr
+            .as_u64()
+            .map(|index| index as usize)
+            .filter(|index| *index < funcs.len())
+            .ok_or_else(|| Error::UnknownName("function", expr.to_string())),
+    }
+}
+
+/// Moves the top value of the stack below the `count` values under it.
+fn rotate_below(fn_builder: &mut FunctionBuilder, count: u32) {
+    if count == 0 {
+        return;
+    }
+    // Keep a copy of the top value, shift the others up by one slot, then
+    // write the copy into the lowest slot.
+    fn_builder.push_copy(StackIndex::FromTop(0));
+    for slot in (1..=count).rev() {
+        fn_builder
+            .push_copy(StackIndex::FromTop(count + 2 - slot))
+            .write_stack(StackIndex::FromTop(count + 1 - slot));
+    }
+    fn_builder.write_stack(StackIndex::FromTop(count));
+}
+
+/// The operand of an instruction, read before its folded operands.
+enum Immediate {
+    None,
+    Local(u32),
+    Func(usize),
+    Int(i64),
+    Float(f64),
+}
+
+struct Lowering<'m, 'a> {
+    builder: &'m ModuleBuilder,
+    funcs: &'m [&'m Func],
+    func_names: &'m HashMap<&'a str, usize>,
+    header: &'m FuncHeader<'a>,
+}
+
+impl Lowering<'_, '_> {
+    fn local(&self, expr: &lexpr::Value) -> Result<u32> {
+        let index = match identifier(expr) {
+            Some(name) => self
+                .header
+                .local_names
+                .iter()
+                .position(|local| *local == Some(name))
+                .ok_or_else(|| Error::UnknownName("local", name.to_string()))?,
+            None => expr
+                .as_u64()
+                .map(|index| index as usize)
+                .filter(|index| *index < self.header.local_types.len())
+                .ok_or_else(|| Error::UnknownName("local", expr.to_string()))?,
+        };
+        local_count(index)
+    }
+
+    fn read_immediate<'e>(
+        &self,
+        op: &str,
+        items: &mut impl Iterator<Item = &'e lexpr::Value>,
+    ) -> Result<Immediate> {
+        let mut operand = || items.next().ok_or(Error::Malformed("instruction"));
+        Ok(match op {
+            "local.get" | "local.set" | "local.tee" => Immediate::Local(self.local(operand()?)?),
+            "call" => Immediate::Func(func_index(self.funcs, self.func_names, operand()?)?),
+            "i32.const" | "i64.const" => {
+                Immediate::Int(operand()?.as_i64().ok_or(Error::Malformed("integer"))?)
+            }
+            "f32.const" | "f64.const" => {
+                Immediate::Float(operand()?.as_f64().ok_or(Error::Malformed("float"))?)
+            }
+            _ => Immediate::None,
+        })
+    }
+
+    fn emit(&self, fn_builder: &mut FunctionBuilder, op: &str, immediate: Immediate) -> Result<()> {
+        match (op, immediate) {
+            ("local.get", Immediate::Local(index)) => {
+                fn_builder.push_copy(StackIndex::FromBottom(index));
+            }
+            ("local.set", Immediate::Local(index)) => {
+                fn_builder.write_stack(StackIndex::FromBottom(index));
+            }
+            ("local.tee", Immediate::Local(index)) => {
+                fn_builder
+                    .push_copy(StackIndex::FromTop(0))
+                    .write_stack(StackIndex::FromBottom(index));
+            }
+            ("call", Immediate::Func(index)) => {
+                // Calls take the function below their arguments, which are
+                // already on the stack here.
+                let func = self.funcs[index];
+                fn_builder.push_value(&func.value)?;
+                rotate_below(fn_builder, func.num_params);
+                self.emit_call(fn_builder, func);
+            }
+            (_, Immediate::Int(value)) => {
+                fn_builder.push_int(value);
+            }
+            (_, Immediate::Float(value)) => {
+                fn_builder.push_value(&self.builder.new_float(value))?;
+            }
+            ("i32.add" | "i64.add" | "f32.add" | "f64.add", _) => {
+                fn_builder.add();
+            }
+            ("i32.sub" | "i64.sub" | "f32.sub" | "f64.sub", _) => {
+                fn_builder.sub();
+            }
+            ("i32.mul" | "i64.mul" | "f32.mul" | "f64.mul", _) => {
+                fn_builder.mul();
+            }
+            ("f32.div" | "f64.div", _) => {
+                fn_builder.div();
+            }
+            ("drop", _) => {
+                fn_builder.pop(1);
+            }
+            ("return", _) => {
+                fn_builder.return_(self.header.num_results);
+            }
+            ("nop", _) => {}
+            (op, _) => return Err(Error::Unsupported(op.to_string())),
+        }
+        Ok(())
+    }
+
+    fn emit_call(&self, fn_builder: &mut FunctionBuilder, func: &Func) {
+        fn_builder.call(CallInstruction {
+            num_args: func.num_params,
+            num_returns: func.num_results,
+        });
+    }
+
+    fn lower_instrs<'e>(
+        &self,
+        fn_builder: &mut FunctionBuilder,
+        items: impl IntoIterator<Item = &'e lexpr::Value>,
+    ) -> Result<()> {
+        let mut items = items.into_iter();
+        while let Some(item) = items.next() {
+            if let Some(op) = item.as_symbol() {
+                let immediate = self.read_immediate(op, &mut items)?;
+                self.emit(fn_builder, op, immediate)?;
+            } else {
+                // A folded instruction lowers its operands first.
+                let (op, rest) = split_head(item).ok_or(Error::Malformed("instruction"))?;
+                let mut rest = rest.into_iter();
+                match self.read_immediate(op, &mut rest)? {
+                    Immediate::Func(index) if op == "call" => {
+                        let func = self.funcs[index];
+                        fn_builder.push_value(&func.value)?;
+                        self.lower_instrs(fn_builder, rest)?;
+                        self.emit_call(fn_builder, func);
+                    }
+                    immediate => {
+                        self.lower_instrs(fn_builder, rest)?;
+                        self.emit(fn_builder, op, immediate)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn lower(&self, mut fn_builder: FunctionBuilder) -> Result<()> {
+        for ty in &self.header.local_types[self.header.num_params as usize..] {
+            match ty {
+                ValType::Int => fn_builder.push_int(0),
+                ValType::Float => fn_builder.push_value(&self.builder.new_float(0.0))?,
+            };
+        }
+        self.lower_instrs(&mut fn_builder, self.header.body.iter().copied())?;
+        fn_builder.return_(self.header.num_results);
+        fn_builder.build()?;
+        Ok(())
+    }
+}
+
+/// Lowers the text of a WAT `(module ...)` to a module with the given id.
+///
+/// Each exported function is exported under its WAT export name. An import
+/// `(import "a.b" "f" (func ...))` imports the member `f` of the Loon module
+/// `a.b`.
+pub fn module_from_str(module_id: ModuleId, text: &str) -> Result<ConstModule> {
+    let expr = lexpr::from_str(text)?;
+    let (head, fields) = split_head(&expr).ok_or(Error::Malformed("module"))?;
+    if head != "module" {
+        return Err(Error::Malformed("module"));
+    }
+    let fields = match fields.split_first() {
+        Some((name, rest)) if identifier(name).is_some() => rest,
+        _ => &fields[..],
+    };
+
+    let builder = ModuleBuilder::new(module_id);
+    let mut funcs = Vec::new();
+    let mut func_names = HashMap::new();
+    let mut exports = Vec::new();
+    // Exports given as module fields, which may name functions defined later.
+    let mut export_fields = Vec::new();
+    for field in fields {
+        let (head, items) = split_head(field).ok_or(Error::Malformed("module field"))?;
+        let (header, func) = match head {
+            "func" => {
+                let header = FuncHeader::parse(&items, true)?;
+                let (value, deferred) = builder.new_deferred();
+                exports.extend(header.exports.iter().map(|name| (*name, funcs.len())));
+                let func = Func {
+                    value,
+                    num_params: header.num_params,
+                    num_results: header.num_results,
+                    definition: Cell::new(Some(deferred)),
+                };
+                (header, func)
+            }
+            "import" => {
+                let [module, member, desc] = items[..] else {
+                    return Err(Error::Malformed("import"));
+                };
+                let (Some(module), Some(member)) = (module.as_str(), member.as_str()) else {
+                    return Err(Error::Malformed("import"));
+                };
+                let (desc_head, desc_items) = split_head(desc).ok_or(Error::Malformed("import"))?;
+                if desc_head != "func" {
+                    return Err(Error::Unsupported(format!("{desc_head} import")));
+                }
+                let header = FuncHeader::parse(&desc_items, false)?;
+                let source = ImportSource::new(
+                    ModuleId::new(module.split('.')),
+                    ModuleMemberId::new(member),
+                );
+                let func = Func {
+                    value: builder.add_import(source),
+                    num_params: header.num_params,
+                    num_results: header.num_results,
+                    definition: Cell::new(None),
+                };
+                (header, func)
+            }
+            "export" => {
+                let [name, desc] = items[..] else {
+                    return Err(Error::Malformed("export"));
+                };
+                let name = name.as_str().ok_or(Error::Malformed("export"))?;
+                match split_head(desc) {
+                    Some(("func", target)) if target.len() == 1 => {
+                        export_fields.push((name, target[0]));
+                    }
+                    _ => return Err(Error::Unsupported(format!("export {desc}"))),
+                }
+                continue;
+            }
+            other => return Err(Error::Unsupported(other.to_string())),
+        };
+        if let Some(name) = header.name {
+            if func_names.insert(name, funcs.len()).is_some() {
+                return Err(Error::DuplicateName(name.to_string()));
+            }
+        }
+        funcs.push((header, func));
+    }
+
+    let funcs_only = funcs.iter().map(|(_, func)| func).collect::<Vec<_>>();
+    for (name, target) in export_fields {
+        exports.push((name, func_index(&funcs_only, &func_names, target)?));
+    }
+    for (header, func) in &funcs {
+        let Some(deferred) = func.definition.take() else {
+            continue;
+        };
+        let lowering = Lowering {
+            builder: &builder,
+            funcs: &funcs_only,
+            func_names: &func_names,
+            header,
+        };
+        lowering.lower(deferred.into_function_builder())?;
+    }
+    for (name, index) in exports {
+        funcs_only[index].value.export(ModuleMemberId::new(name))?;
+    }
+    Ok(builder.into_const_module()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{instructions::Instruction, ConstValue};
+
+    fn function_insts(module: &ConstModule, export: &str) -> Vec<Instruction> {
+        let index = module.exports()[&ModuleMemberId::new(export)];
+        let ConstValue::Function(function) = &module.const_table()[index.as_usize()] else {
+            panic!("Expected a function.");
+        };
+        function.instructions().instructions().to_vec()
+    }
+
+    #[test]
+    fn locals_are_stack_slots() -> anyhow::Result<()> {
+        let module = module_from_str(
+            ModuleId::new(["wat"]),
+            r#"
+                (module $m
+                    (func $scale (export "scale") (param $x i64) (param i64) (result i64)
+                        (local $tmp i64)
+                        (local.set $tmp (i64.mul (local.get $x) (local.get 1)))
+                        local.get $tmp
+                        i64.const 1
+                        i64.add))
+            "#,
+        )?;
+        assert_eq!(
+            function_insts(&module, "scale")[..6],
+            [
+                Instruction::PushConst(crate::binary::LocalConstIndex::new(0)),
+                Instruction::PushCopy(StackIndex::FromBottom(0)),
+                Instruction::PushCopy(StackIndex::FromBottom(1)),
+                Instruction::Mul,
+                Instruction::WriteStack(StackIndex::FromBottom(2)),
+                Instruction::PushCopy(StackIndex::FromBottom(2)),
+            ]
+        );
+        assert_eq!(
+            function_insts(&module, "scale").last(),
+            Some(&Instruction::Return(1))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unsupported_and_unknown_constructs_fail() {
+        let lower = |text: &str| module_from_str(ModuleId::new(["wat"]), text);
+        assert!(matches!(
+            lower("(module (memory 1))"),
+            Err(Error::Unsupported(name)) if name == "memory"
+        ));
+        assert!(matches!(
+            lower("(module (func (block)))"),
+            Err(Error::Unsupported(name)) if name == "block"
+        ));
+        assert!(matches!(
+            lower("(module (func call $missing))"),
+            Err(Error::UnknownName("function", _))
+        ));
+        assert!(matches!(
+            lower("(module (func (local.get 0)))"),
+            Err(Error::UnknownName("local", _))
+        ));
+        assert!(matches!(
+            lower("(module (func $f) (func $f))"),
+            Err(Error::DuplicateName(name)) if name == "$f"
+        ));
+    }
+}
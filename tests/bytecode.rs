@@ -0,0 +1,125 @@
+//! Tests of the bytecode layer through the public API alone. These do not use
+//! the interpreter, and also run without the `runtime` feature:
+//!
+//! ```text
+//! cargo test --no-default-features --test bytecode
+//! ```
+
+use std::collections::HashSet;
+
+use loon::binary::{
+    CallInstruction, ConstValue, DecodeError, FunctionId, FunctionReference, ImportSource,
+    InstructionFamily, InstructionList, InstructionPolicy, ModuleBuilder, ModuleId, ModuleMemberId,
+    ModuleSet, ValidationError,
+};
+
+fn build_module() -> anyhow::Result<ModuleSet> {
+    let builder = ModuleBuilder::new(ModuleId::new(["app"]));
+    let print = builder.add_import(ImportSource::new(ModuleId::new(["std", "io"]), "print"));
+
+    let (sum, mut f) = builder.new_function();
+    f.push_int(1).push_int(2).add().return_(1);
+    f.build()?;
+
+    let (main, mut f) = builder.new_function();
+    f.push_value(&print)?
+        .push_value(&sum)?
+        .call(CallInstruction {
+            num_args: 0,
+            num_returns: 1,
+        });
+    f.call(CallInstruction {
+        num_args: 1,
+        num_returns: 0,
+    })
+    .return_(0);
+    f.build()?;
+    main.export(ModuleMemberId::new("main"))?;
+
+    let (_unused, mut f) = builder.new_function();
+    f.return_(0);
+    f.build()?;
+
+    Ok(ModuleSet::new([builder.into_const_module()?]))
+}
+
+fn export_id(module_set: &ModuleSet, name: &str) -> FunctionId {
+    let module_id = ModuleId::new(["app"]);
+    let const_index = module_set.module(&module_id).unwrap().exports()[&ModuleMemberId::new(name)];
+    FunctionId::Managed {
+        module_id: Some(module_id),
+        const_index,
+    }
+}
+
+#[test]
+fn built_functions_round_trip_through_the_encoding() -> anyhow::Result<()> {
+    let module_set = build_module()?;
+    let module = module_set.module(&ModuleId::new(["app"])).unwrap();
+    let mut functions = 0;
+    for value in module.const_table() {
+        if let ConstValue::Function(function) = value {
+            let encoded = function.instructions().encode();
+            assert_eq!(&InstructionList::decode(&encoded)?, function.instructions());
+            functions += 1;
+        }
+    }
+    assert_eq!(functions, 3);
+    assert!(matches!(
+        InstructionList::decode(&[0xff]),
+        Err(DecodeError::UnknownOpcode(0xff, _))
+    ));
+    Ok(())
+}
+
+#[test]
+fn policies_are_checked_without_loading() -> anyhow::Result<()> {
+    let module_set = build_module()?;
+    let module = module_set.module(&ModuleId::new(["app"])).unwrap();
+    module.validate_instructions(&InstructionPolicy::allow_all())?;
+    let denied = module
+        .validate_instructions(&InstructionPolicy::allow_all().deny(InstructionFamily::Arithmetic))
+        .unwrap_err();
+    assert!(matches!(
+        denied,
+        ValidationError::DeniedInstruction {
+            family: InstructionFamily::Arithmetic,
+            ..
+        }
+    ));
+    Ok(())
+}
+
+#[test]
+fn call_graph_is_available_without_the_runtime() -> anyhow::Result<()> {
+    let module_set = build_module()?;
+    let main = export_id(&module_set, "main");
+    let graph = module_set.call_graph();
+    assert_eq!(graph.entry_points(), std::slice::from_ref(&main));
+    assert_eq!(
+        graph.references(&main)[0],
+        FunctionReference::Import(ImportSource::new(ModuleId::new(["std", "io"]), "print"))
+    );
+    assert_eq!(graph.reachable_from([&main]).len(), 2);
+    assert_eq!(graph.unreachable().len(), 1);
+    Ok(())
+}
+
+#[test]
+fn lat_modules_parse_without_the_runtime() -> anyhow::Result<()> {
+    let module_set = loon::lat::from_str(
+        r#"
+            (module-set
+                ("app"
+                    (const helper (fn (return 0)))
+                    (const main (fn (push helper) (return 1)))
+                    (export main)))
+        "#,
+    )?;
+    let main = export_id(&module_set, "main");
+    let graph = module_set.call_graph();
+    let reached = graph.reachable_from([&main]);
+    assert_eq!(reached.len(), 2);
+    assert_eq!(graph.functions().collect::<HashSet<_>>().len(), 2);
+    Ok(())
+}